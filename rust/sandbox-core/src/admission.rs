@@ -0,0 +1,273 @@
+// Admission control for executions.
+//
+// At most MAX_CONCURRENT_EXECUTIONS executions run at a time. Up to
+// EXECUTION_QUEUE_DEPTH more of each priority wait for a slot, each for at most
+// QUEUE_WAIT_TIMEOUT_MS; anything beyond that is turned away, so a burst of
+// requests can't create an unbounded number of runtimes.
+//
+// A freed slot goes to the oldest waiter of a priority picked by weighted round
+// robin among the priorities that have waiters, so interactive executions get most
+// slots while others queue, and batch executions still get some. The last
+// INTERACTIVE_RESERVED_SLOTS free slots are only taken by interactive executions.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify};
+
+use crate::config::Config;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Interactive,
+    #[default]
+    Normal,
+    Batch,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Normal, Priority::Batch];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Normal => "normal",
+            Priority::Batch => "batch",
+        }
+    }
+
+    // Share of the freed slots while every priority has waiters
+    fn weight(self) -> i64 {
+        match self {
+            Priority::Interactive => 6,
+            Priority::Normal => 3,
+            Priority::Batch => 1,
+        }
+    }
+}
+
+pub struct Admission {
+    queue: Mutex<Queue>,
+    max_concurrent: usize,
+    reserved: usize,
+    queue_depth: usize,
+    wait: Duration,
+    // Signalled whenever a slot is freed, for `drained`
+    released: Notify,
+}
+
+pub enum Saturation {
+    QueueFull,
+    WaitTimedOut,
+}
+
+struct Queue {
+    free: usize,
+    // Oldest first, by priority
+    waiting: [VecDeque<Waiter>; 3],
+    // Credit of each priority in the round robin
+    credit: [i64; 3],
+    next_waiter: u64,
+    // Waiters from this one on aren't served, once draining started
+    drain_from: Option<u64>,
+}
+
+struct Waiter {
+    id: u64,
+    admitted: oneshot::Sender<()>,
+}
+
+// A slot for one execution, freed when dropped
+pub struct Slot {
+    admission: Arc<Admission>,
+    // How long the execution waited for it
+    pub waited: Duration,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.admission.release();
+    }
+}
+
+// Leaves the queue when the wait ends without a slot, e.g. because the execution
+// was dropped, and frees the slot it was handed if that happened in the meantime
+struct Waiting<'a> {
+    admission: &'a Admission,
+    priority: Priority,
+    id: u64,
+    admitted: Option<oneshot::Receiver<()>>,
+}
+
+impl Waiting<'_> {
+    // Whether the slot was handed over after all
+    fn leave(&mut self) -> bool {
+        let mut queue = self.admission.queue.lock().unwrap();
+        let waiting = &mut queue.waiting[self.priority as usize];
+        if let Some(position) = waiting.iter().position(|waiter| waiter.id == self.id) {
+            waiting.remove(position);
+            self.admitted = None;
+            // `drained` may have been waiting for it
+            self.admission.released.notify_one();
+            return false;
+        }
+        drop(queue);
+        self.admitted.take().is_some_and(|mut admitted| admitted.try_recv().is_ok())
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.admitted.is_some() && self.leave() {
+            self.admission.release();
+        }
+    }
+}
+
+impl Admission {
+    pub fn from_config(config: &Config) -> Self {
+        let max_concurrent = config.max_concurrent_executions.max(1);
+        Admission {
+            queue: Mutex::new(Queue {
+                free: max_concurrent,
+                waiting: Default::default(),
+                credit: [0; 3],
+                next_waiter: 0,
+                drain_from: None,
+            }),
+            max_concurrent,
+            // At least one slot is left for the other priorities
+            reserved: config.interactive_reserved_slots.min(max_concurrent - 1),
+            queue_depth: config.execution_queue_depth,
+            wait: Duration::from_millis(config.queue_wait_timeout_ms),
+            released: Notify::new(),
+        }
+    }
+
+    // A slot for one execution, held until it is dropped
+    pub async fn admit(self: &Arc<Self>, priority: Priority) -> Result<Slot, Saturation> {
+        let started = Instant::now();
+        let slot = |waited| Slot { admission: self.clone(), waited };
+        let (id, admitted) = {
+            let mut queue = self.queue.lock().unwrap();
+            let id = queue.next_waiter;
+            queue.next_waiter += 1;
+            let (sender, mut admitted) = oneshot::channel();
+            queue.waiting[priority as usize].push_back(Waiter { id, admitted: sender });
+            self.hand_over(&mut queue);
+            if admitted.try_recv().is_ok() {
+                return Ok(slot(Duration::ZERO));
+            }
+            // Still the last of its queue, as waiters are served from the front
+            if queue.waiting[priority as usize].len() > self.queue_depth {
+                queue.waiting[priority as usize].pop_back();
+                return Err(Saturation::QueueFull);
+            }
+            (id, admitted)
+        };
+        let mut waiting = Waiting { admission: self, priority, id, admitted: Some(admitted) };
+        let admitted = waiting.admitted.as_mut().unwrap();
+        match tokio::time::timeout(self.wait, admitted).await {
+            Ok(Ok(())) => {
+                waiting.admitted = None;
+                Ok(slot(started.elapsed()))
+            }
+            // Handed a slot just as the wait timed out
+            _ if waiting.leave() => Ok(slot(started.elapsed())),
+            _ => Err(Saturation::WaitTimedOut),
+        }
+    }
+
+    fn may_take(&self, queue: &Queue, priority: Priority) -> bool {
+        match priority {
+            Priority::Interactive => queue.free > 0,
+            _ => queue.free > self.reserved,
+        }
+    }
+
+    fn release(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.free += 1;
+        self.hand_over(&mut queue);
+        drop(queue);
+        self.released.notify_one();
+    }
+
+    // Gives free slots to waiters, by weighted round robin over the priorities
+    // that have one that may take a slot
+    fn hand_over(&self, queue: &mut Queue) {
+        loop {
+            let drain_from = queue.drain_from.unwrap_or(u64::MAX);
+            let eligible: Vec<Priority> = Priority::ALL
+                .into_iter()
+                .filter(|priority| {
+                    queue.waiting[*priority as usize].front().is_some_and(|waiter| waiter.id < drain_from)
+                        && self.may_take(queue, *priority)
+                })
+                .collect();
+            for priority in Priority::ALL.into_iter().filter(|priority| queue.waiting[*priority as usize].is_empty()) {
+                queue.credit[priority as usize] = 0;
+            }
+            let Some(total) = eligible.iter().map(|priority| priority.weight()).reduce(|a, b| a + b) else {
+                return;
+            };
+            for priority in &eligible {
+                queue.credit[*priority as usize] += priority.weight();
+            }
+            let next = *eligible.iter().max_by_key(|priority| (queue.credit[**priority as usize], -(**priority as i64))).unwrap();
+            queue.credit[next as usize] -= total;
+            let waiter = queue.waiting[next as usize].pop_front().unwrap();
+            // A waiter that is gone leaves the slot to the next one
+            if waiter.admitted.send(()).is_ok() {
+                queue.free -= 1;
+            }
+        }
+    }
+
+    // Resolves once every slot is free, i.e. every execution admitted so far or
+    // waiting in the queue has finished. Takes the slots with it, so executions
+    // arriving in the meantime queue behind it.
+    pub async fn drained(&self) {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                let next_waiter = queue.next_waiter;
+                let drain_from = *queue.drain_from.get_or_insert(next_waiter);
+                let earlier = queue.waiting.iter().flatten().any(|waiter| waiter.id < drain_from);
+                if queue.free == self.max_concurrent && !earlier {
+                    queue.free = 0;
+                    return;
+                }
+            }
+            self.released.notified().await;
+        }
+    }
+
+    pub fn message(&self, saturation: Saturation) -> String {
+        match saturation {
+            Saturation::QueueFull => format!(
+                "All {} execution slots are busy and {} executions of this priority are already waiting",
+                self.max_concurrent, self.queue_depth
+            ),
+            Saturation::WaitTimedOut => format!(
+                "All {} execution slots stayed busy for {} ms",
+                self.max_concurrent,
+                self.wait.as_millis()
+            ),
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.queue.lock().unwrap().free
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().waiting.iter().map(VecDeque::len).sum()
+    }
+}
@@ -0,0 +1,508 @@
+// Static analysis without execution, for POST /analyze.
+//
+// The code is compiled as /validate compiles it, then scanned token by token for
+// the globals it refers to: identifiers that aren't properties, object keys or
+// names declared anywhere in the code. Declarations aren't scoped, so a local
+// `const fetch` hides the global everywhere. Globals of the sandbox are reported in
+// `uses`, with the member for namespaces like `crypto.sha256`; standard JavaScript
+// globals are left out, and anything else is an unknown global. Computed access
+// such as `globalThis['fe' + 'tch']` can't be followed; the objects accessed that
+// way are listed in `computedAccess`.
+
+use crate::surface::{self, standard_globals};
+use crate::validate::{compile_errors, ValidationError};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+
+// Reserved words and names that only have a meaning inside functions
+const KEYWORDS: &[&str] = &[
+    "arguments", "as", "async", "await", "break", "case", "catch", "class", "const", "continue",
+    "debugger", "default", "delete", "do", "else", "enum", "export", "extends", "false", "finally",
+    "for", "from", "function", "get", "if", "import", "in", "instanceof", "let", "new", "null",
+    "of", "return", "set", "static", "super", "switch", "this", "throw", "true", "try", "typeof",
+    "var", "void", "while", "with", "yield",
+];
+
+// Keywords after which a `/` starts a regular expression rather than a division
+pub(crate) const REGEX_KEYWORDS: &[&str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void", "throw", "case", "do",
+    "else", "yield", "await",
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Analysis {
+    pub syntax_valid: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ValidationError>,
+    pub uses: BTreeSet<String>,
+    pub unknown_globals: BTreeSet<String>,
+    pub computed_access: BTreeSet<String>,
+}
+
+pub fn analyze(code: &str, module: bool, max_stack_bytes: usize) -> Result<Analysis, String> {
+    let errors = compile_errors(code, module, max_stack_bytes)?;
+    let standard = standard_globals()?;
+    let tokens = tokenize(code);
+    let references = References::find(&tokens);
+
+    let mut analysis = Analysis {
+        syntax_valid: errors.is_empty(),
+        errors,
+        uses: BTreeSet::new(),
+        unknown_globals: BTreeSet::new(),
+        computed_access: BTreeSet::new(),
+    };
+    for &i in &references.free {
+        let mut i = i;
+        // `globalThis.fetch` is a use of `fetch`
+        if tokens[i].text == "globalThis" {
+            match (punct_at(&tokens, i + 1), tokens.get(i + 2)) {
+                (Some("." | "?."), Some(next))
+                    if next.kind == Kind::Identifier
+                        && !references.declared.contains(next.text) =>
+                {
+                    i += 2
+                }
+                (Some("["), _) => {
+                    analysis.computed_access.insert("globalThis".to_string());
+                    continue;
+                }
+                _ => continue,
+            }
+        }
+        let name = tokens[i].text;
+        if let Some(global) = surface::find(name) {
+            let member = tokens
+                .get(i + 2)
+                .filter(|member| member.kind == Kind::Identifier);
+            match (punct_at(&tokens, i + 1), member) {
+                (Some("." | "?."), Some(member)) if global.kind == surface::Kind::Namespace => {
+                    analysis.uses.insert(format!("{}.{}", name, member.text));
+                }
+                (Some("["), _) => {
+                    analysis.computed_access.insert(name.to_string());
+                    analysis.uses.insert(name.to_string());
+                }
+                _ => {
+                    analysis.uses.insert(name.to_string());
+                }
+            }
+        } else if !standard.contains(name) {
+            analysis.unknown_globals.insert(name.to_string());
+        }
+    }
+    Ok(analysis)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Identifier,
+    Punct,
+    // Numbers, strings, template text and regular expressions
+    Literal,
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+}
+
+fn punct_at<'a>(tokens: &[Token<'a>], i: usize) -> Option<&'a str> {
+    tokens
+        .get(i)
+        .filter(|token| token.kind == Kind::Punct)
+        .map(|token| token.text)
+}
+
+pub(crate) fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte >= 0x80
+}
+
+// Splits the code into identifiers, punctuators and literals, dropping comments.
+// Template literals are followed into their `${}` substitutions.
+fn tokenize(code: &str) -> Vec<Token<'_>> {
+    let bytes = code.as_bytes();
+    let mut tokens: Vec<Token<'_>> = Vec::new();
+    // Brace depths at which a `}` continues a template literal
+    let mut templates: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let byte = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        let kind = match byte {
+            b' ' | b'\t' | b'\n' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'/' if next == Some(b'/') => {
+                i = find(bytes, i, b"\n").unwrap_or(bytes.len());
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = find(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+                continue;
+            }
+            b'\'' | b'"' => {
+                i = skip_string(bytes, i + 1, byte);
+                Kind::Literal
+            }
+            b'`' | b'}' if byte == b'`' || templates.last() == Some(&depth) => {
+                if byte == b'}' {
+                    templates.pop();
+                    depth -= 1;
+                }
+                let (end, substitution) = skip_template(bytes, i + 1);
+                i = end;
+                if substitution {
+                    depth += 1;
+                    templates.push(depth);
+                }
+                Kind::Literal
+            }
+            b'/' if regex_allowed(tokens.last()) => {
+                i = skip_regex(bytes, i + 1);
+                Kind::Literal
+            }
+            b'0'..=b'9' => {
+                i = skip_number(bytes, i);
+                Kind::Literal
+            }
+            b'.' if next.is_some_and(|next| next.is_ascii_digit()) => {
+                i = skip_number(bytes, i);
+                Kind::Literal
+            }
+            // Private names, which are never globals
+            b'#' => {
+                i += 1;
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Literal
+            }
+            _ if is_identifier_byte(byte) => {
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Identifier
+            }
+            _ => {
+                let rest = &bytes[i..];
+                i += if rest.starts_with(b"...") {
+                    3
+                } else if rest.starts_with(b"=>")
+                    || (rest.starts_with(b"?.") && !rest.get(2).is_some_and(u8::is_ascii_digit))
+                {
+                    2
+                } else {
+                    1
+                };
+                match byte {
+                    b'{' => depth += 1,
+                    b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                Kind::Punct
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: &code[start..i],
+        });
+    }
+    tokens
+}
+
+pub(crate) fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|at| from + at)
+}
+
+// The end of a string whose opening quote is right before `i`
+pub(crate) fn skip_string(bytes: &[u8], mut i: usize, quote: u8) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            byte if byte == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+// The end of the template text starting at `i`, and whether it ends at a `${`
+// rather than the closing backtick
+pub(crate) fn skip_template(bytes: &[u8], mut i: usize) -> (usize, bool) {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => return (i + 1, false),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => return (i + 2, true),
+            _ => i += 1,
+        }
+    }
+    (bytes.len(), false)
+}
+
+pub(crate) fn skip_regex(bytes: &[u8], mut i: usize) -> usize {
+    let mut class = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\n' => return i,
+            b'[' => class = true,
+            b']' => class = false,
+            b'/' if !class => {
+                i += 1;
+                // Flags
+                while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                return i;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+pub(crate) fn skip_number(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'.' | b'_' => i += 1,
+            // Exponents like `1e-7`
+            b'+' | b'-' if matches!(bytes[i - 1], b'e' | b'E') => i += 1,
+            byte if byte.is_ascii_alphanumeric() => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+fn regex_allowed(previous: Option<&Token<'_>>) -> bool {
+    match previous {
+        None => true,
+        Some(token) => match token.kind {
+            Kind::Punct => !matches!(token.text, ")" | "]" | "}"),
+            Kind::Identifier => REGEX_KEYWORDS.contains(&token.text),
+            Kind::Literal => false,
+        },
+    }
+}
+
+fn is_name(token: &Token<'_>) -> bool {
+    token.kind == Kind::Identifier && !KEYWORDS.contains(&token.text)
+}
+
+struct References<'a> {
+    // Names bound anywhere in the code
+    declared: HashSet<&'a str>,
+    // Indices of the identifiers referring to globals
+    free: Vec<usize>,
+}
+
+impl<'a> References<'a> {
+    fn find(tokens: &[Token<'a>]) -> Self {
+        let mut bound = vec![false; tokens.len()];
+        let mut declared = HashSet::new();
+        let mut bind = |i: usize, bound: &mut Vec<bool>| {
+            bound[i] = true;
+            declared.insert(tokens[i].text);
+        };
+
+        for i in 0..tokens.len() {
+            let token = &tokens[i];
+            // The start of the code counts as the start of a statement
+            let previous = if i == 0 {
+                Some(";")
+            } else {
+                punct_at(tokens, i - 1)
+            };
+            match (token.kind, token.text) {
+                (Kind::Identifier, "var" | "let" | "const") => {
+                    for binding in declaration_bindings(tokens, i + 1) {
+                        bind(binding, &mut bound);
+                    }
+                }
+                (Kind::Identifier, "function" | "class") => {
+                    let name = if punct_at(tokens, i + 1) == Some("*") {
+                        i + 2
+                    } else {
+                        i + 1
+                    };
+                    if tokens.get(name).is_some_and(is_name) {
+                        bind(name, &mut bound);
+                    }
+                    let open = if tokens.get(name).is_some_and(is_name) {
+                        name + 1
+                    } else {
+                        name
+                    };
+                    if token.text == "function" && punct_at(tokens, open) == Some("(") {
+                        for binding in pattern_bindings(tokens, open, matching(tokens, open)) {
+                            bind(binding, &mut bound);
+                        }
+                    }
+                }
+                (Kind::Identifier, "catch") if punct_at(tokens, i + 1) == Some("(") => {
+                    for binding in pattern_bindings(tokens, i + 1, matching(tokens, i + 1)) {
+                        bind(binding, &mut bound);
+                    }
+                }
+                (Kind::Identifier, "import")
+                    if !matches!(punct_at(tokens, i + 1), Some("(" | ".")) =>
+                {
+                    let mut j = i + 1;
+                    while j < tokens.len() && tokens[j].kind != Kind::Literal {
+                        if is_name(&tokens[j]) {
+                            bind(j, &mut bound);
+                        }
+                        j += 1;
+                    }
+                }
+                (Kind::Identifier, _) if matches!(previous, Some("." | "?.")) => bound[i] = true,
+                // Labels of `break label`
+                (Kind::Identifier, _)
+                    if i > 0 && matches!(tokens[i - 1].text, "break" | "continue") =>
+                {
+                    bound[i] = true
+                }
+                // Object keys and labels
+                (Kind::Identifier, _)
+                    if punct_at(tokens, i + 1) == Some(":")
+                        && matches!(previous, Some("{" | "," | ";" | "}")) =>
+                {
+                    bound[i] = true
+                }
+                // Methods, `name(params) { ... }`
+                (Kind::Identifier, _) if is_name(token) && punct_at(tokens, i + 1) == Some("(") => {
+                    let close = matching(tokens, i + 1);
+                    if punct_at(tokens, close + 1) == Some("{") {
+                        bound[i] = true;
+                        for binding in pattern_bindings(tokens, i + 1, close) {
+                            bind(binding, &mut bound);
+                        }
+                    }
+                }
+                (Kind::Punct, "=>") => {
+                    if i > 0 && is_name(&tokens[i - 1]) {
+                        bind(i - 1, &mut bound);
+                    } else if previous == Some(")") {
+                        let open = matching_back(tokens, i - 1);
+                        for binding in pattern_bindings(tokens, open, i - 1) {
+                            bind(binding, &mut bound);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let free = (0..tokens.len())
+            .filter(|&i| is_name(&tokens[i]) && !bound[i] && !declared.contains(tokens[i].text))
+            .collect();
+        References { declared, free }
+    }
+}
+
+// The bindings of `var`, `let` and `const` declarations starting at `i`
+fn declaration_bindings(tokens: &[Token<'_>], mut i: usize) -> Vec<usize> {
+    let mut bindings = Vec::new();
+    loop {
+        match tokens.get(i) {
+            Some(token) if is_name(token) => {
+                bindings.push(i);
+                i += 1;
+            }
+            Some(token) if token.kind == Kind::Punct && matches!(token.text, "{" | "[") => {
+                let close = matching(tokens, i);
+                bindings.extend(pattern_bindings(tokens, i, close));
+                i = close + 1;
+            }
+            _ => return bindings,
+        }
+        if punct_at(tokens, i) == Some("=") {
+            // Skip the initializer, up to the next declaration of the list
+            let mut depth = 0usize;
+            i += 1;
+            while let Some(token) = tokens.get(i) {
+                if token.kind == Kind::Punct {
+                    match token.text {
+                        "(" | "[" | "{" => depth += 1,
+                        ")" | "]" | "}" if depth == 0 => return bindings,
+                        ")" | "]" | "}" => depth -= 1,
+                        "," | ";" if depth == 0 => break,
+                        _ => {}
+                    }
+                }
+                i += 1;
+            }
+        }
+        if punct_at(tokens, i) != Some(",") {
+            return bindings;
+        }
+        i += 1;
+    }
+}
+
+// The names bound by a parameter list or destructuring pattern between the
+// brackets at `open` and `close`. Defaults are references, not bindings.
+fn pattern_bindings(tokens: &[Token<'_>], open: usize, close: usize) -> Vec<usize> {
+    (open + 1..close.min(tokens.len()))
+        .filter(|&i| {
+            is_name(&tokens[i])
+                && matches!(
+                    punct_at(tokens, i - 1),
+                    Some("(" | "," | "{" | "[" | "..." | ":")
+                )
+                && matches!(punct_at(tokens, i + 1), Some("," | ")" | "=" | "}" | "]"))
+        })
+        .collect()
+}
+
+// The index of the bracket closing the one at `open`, or the end of the tokens
+fn matching(tokens: &[Token<'_>], open: usize) -> usize {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.kind == Kind::Punct {
+            match token.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens.len()
+}
+
+// The index of the bracket opening the one at `close`
+fn matching_back(tokens: &[Token<'_>], close: usize) -> usize {
+    let mut depth = 0usize;
+    for i in (0..=close).rev() {
+        if tokens[i].kind == Kind::Punct {
+            match tokens[i].text {
+                ")" | "]" | "}" => depth += 1,
+                "(" | "[" | "{" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    0
+}
@@ -0,0 +1,173 @@
+// Cross-execution cache for GET responses, opted into per call with
+// `options.cache = { ttlSeconds }`.
+//
+// Entries are keyed by the SHA-256 of method, final URL, the explicitly set
+// request headers and the cookies of the execution's jar, so credentials sent in
+// headers or cookies aren't kept in plaintext, with the tenant of the execution
+// in front so tenants don't share entries. They are
+// bounded both by count (FETCH_CACHE_MAX_ENTRIES) and by the approximate
+// size of the cached results (FETCH_CACHE_MAX_BYTES), evicting least recently
+// used entries first.
+//
+// Entries past their TTL are dropped unless the response had an `ETag` or
+// `Last-Modified`; those are kept to be revalidated with a conditional request,
+// and a 304 makes them fresh again.
+
+use crate::config::Config;
+use crate::fetch::HttpResult;
+use lru::LruCache;
+use reqwest::header::HeaderValue;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Longer `ttlSeconds` are cut down to this
+pub const MAX_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+struct Entry {
+    result: HttpResult,
+    size: usize,
+    expires_at: Instant,
+    validators: Validators,
+}
+
+// What a stale entry is revalidated with
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn of(result: &HttpResult) -> Self {
+        Validators {
+            etag: result.headers.get("etag").cloned(),
+            last_modified: result.headers.get("last-modified").cloned(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+pub enum Cached {
+    Fresh(HttpResult),
+    // Past its TTL, to be served again only if the upstream says it didn't change
+    Stale(HttpResult, Validators),
+}
+
+struct Entries {
+    lru: LruCache<String, Entry>,
+    bytes: usize,
+}
+
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn from_config(config: &Config) -> Self {
+        ResponseCache {
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_entries: config.fetch_cache_max_entries,
+            max_bytes: config.fetch_cache_max_bytes,
+        }
+    }
+    
+    // Derived from the built request rather than from the options, so equivalent
+    // calls (options in another order, `query` or a literal query string) share an
+    // entry. Header names are already lowercased by `HeaderName`, and sorted here.
+    // `per_execution` names the headers the service sets anew for every execution,
+    // like the request id, which would keep executions from ever sharing an entry.
+    // `cookies` are those the execution's jar adds to the request.
+    pub fn key(request: &reqwest::Request, per_execution: &[String], cookies: Option<&HeaderValue>) -> String {
+        let mut headers: Vec<(&str, &[u8])> = request
+            .headers()
+            .iter()
+            .filter(|(name, _)| !per_execution.iter().any(|skipped| skipped == name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_bytes()))
+            .collect();
+        headers.sort();
+        
+        let mut key = format!("{} {}", request.method(), request.url());
+        for (name, value) in headers {
+            key.push('\n');
+            key.push_str(name);
+            key.push_str(": ");
+            key.push_str(&String::from_utf8_lossy(value));
+        }
+        if let Some(cookies) = cookies {
+            // The jar lists them in no particular order
+            let cookies = String::from_utf8_lossy(cookies.as_bytes());
+            let mut cookies: Vec<&str> = cookies.split("; ").collect();
+            cookies.sort();
+            key.push_str("\ncookie jar: ");
+            key.push_str(&cookies.join("; "));
+        }
+        Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+    
+    pub fn get(&self, key: &str) -> Option<Cached> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.lru.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => return Some(Cached::Fresh(entry.result.clone())),
+            Some(entry) if !entry.validators.is_empty() => {
+                return Some(Cached::Stale(entry.result.clone(), entry.validators.clone()))
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            if let Some(entry) = entries.lru.pop(key) {
+                entries.bytes -= entry.size;
+            }
+        }
+        None
+    }
+    
+    pub fn insert(&self, key: String, result: &HttpResult, ttl: Duration) {
+        // Approximated by the size the result has when handed to user code
+        let size = key.len() + serde_json::to_vec(result).map(|v| v.len()).unwrap_or(0);
+        let Some(expires_at) = Instant::now().checked_add(ttl.min(MAX_TTL)) else {
+            return;
+        };
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Entry {
+            result: result.clone(),
+            size,
+            expires_at,
+            validators: Validators::of(result),
+        };
+        if let Some(old) = entries.lru.put(key, entry) {
+            entries.bytes -= old.size;
+        }
+        entries.bytes += size;
+        
+        while entries.lru.len() > self.max_entries || entries.bytes > self.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.bytes -= evicted.size,
+                None => break,
+            }
+        }
+    }
+    
+    // The upstream answered a revalidation with 304: the entry is fresh for another `ttl`
+    pub fn refresh(&self, key: &str, ttl: Duration) {
+        let Some(expires_at) = Instant::now().checked_add(ttl.min(MAX_TTL)) else {
+            return;
+        };
+        if let Some(entry) = self.entries.lock().unwrap().lru.get_mut(key) {
+            entry.expires_at = expires_at;
+        }
+    }
+}
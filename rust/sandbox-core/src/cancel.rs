@@ -0,0 +1,160 @@
+// Cooperative cancellation of running executions.
+//
+// Dropping the future of an execution doesn't stop a busy loop in JavaScript, the
+// evaluation never yields. QuickJS instead polls an interrupt handler while it runs
+// code, which aborts the script with an uncatchable exception once the execution
+// timed out, used up its CPU budget or was cancelled, e.g. because the client went
+// away, or when shutdown gave up waiting for it. Each call of the handler is a
+// checkpoint, where the execution's heap high-water mark is sampled too. Code that
+// is waiting, e.g. on a timer in a loop, never reaches one, so executions also
+// watch for `interrupted` while they wait.
+
+use rquickjs::runtime::InterruptHandler;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::cpu::CpuTime;
+use crate::memory::HeapMeter;
+
+// Set once shutdown interrupts every execution still running
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+// How often `interrupted` looks at an execution that is waiting
+const WAITING_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn interrupt_all() {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+}
+
+pub struct Cancellation {
+    cancelled: AtomicBool,
+    deadline: Instant,
+    cpu: Option<Arc<CpuTime>>,
+    // Calls of the interrupt handler so far
+    interrupts: AtomicU64,
+    // The heap of the runtime the execution runs on, and its high-water mark as of
+    // the last sample
+    heap: OnceLock<Arc<HeapMeter>>,
+    heap_peak: AtomicUsize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interruption {
+    TimedOut,
+    CpuBudget,
+    Cancelled,
+    ShuttingDown,
+}
+
+impl Cancellation {
+    pub fn new(timeout: Duration) -> Arc<Self> {
+        Arc::new(Cancellation {
+            cancelled: AtomicBool::new(false),
+            deadline: Instant::now() + timeout,
+            cpu: None,
+            interrupts: AtomicU64::new(0),
+            heap: OnceLock::new(),
+            heap_peak: AtomicUsize::new(0),
+        })
+    }
+
+    // Also stops the execution once `cpu` is over its budget
+    pub fn with_cpu_budget(timeout: Duration, cpu: Arc<CpuTime>) -> Arc<Self> {
+        Arc::new(Cancellation {
+            cancelled: AtomicBool::new(false),
+            deadline: Instant::now() + timeout,
+            cpu: Some(cpu),
+            interrupts: AtomicU64::new(0),
+            heap: OnceLock::new(),
+            heap_peak: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // Why the execution should stop, if it should
+    pub fn interruption(&self) -> Option<Interruption> {
+        if SHUTTING_DOWN.load(Ordering::Relaxed) {
+            Some(Interruption::ShuttingDown)
+        } else if self.cancelled.load(Ordering::Relaxed) {
+            Some(Interruption::Cancelled)
+        } else if self.cpu.as_ref().is_some_and(|cpu| cpu.exceeded()) {
+            Some(Interruption::CpuBudget)
+        } else if Instant::now() >= self.deadline {
+            Some(Interruption::TimedOut)
+        } else {
+            None
+        }
+    }
+
+    // Resolves once the execution should stop
+    pub async fn interrupted(&self) -> Interruption {
+        let mut checks = tokio::time::interval(WAITING_CHECK_INTERVAL);
+        loop {
+            checks.tick().await;
+            if let Some(interruption) = self.interruption() {
+                return interruption;
+            }
+        }
+    }
+
+    // For `AsyncRuntime::set_interrupt_handler`, returning true aborts the script
+    pub fn interrupt_handler(self: &Arc<Self>) -> InterruptHandler {
+        let cancellation = self.clone();
+        Box::new(move || {
+            cancellation.interrupts.fetch_add(1, Ordering::Relaxed);
+            cancellation.sample_heap();
+            cancellation.interruption().is_some()
+        })
+    }
+
+    pub fn interrupt_count(&self) -> u64 {
+        self.interrupts.load(Ordering::Relaxed)
+    }
+
+    // Samples the heap of the runtime the execution got from here on, starting its
+    // high-water mark afresh
+    pub fn watch_heap(&self, heap: Arc<HeapMeter>) {
+        heap.reset_peak();
+        if self.heap.set(heap).is_ok() {
+            self.sample_heap();
+        }
+    }
+
+    // At checkpoints and once the evaluation is over
+    pub fn sample_heap(&self) {
+        if let Some(heap) = self.heap.get() {
+            self.heap_peak.fetch_max(heap.peak(), Ordering::Relaxed);
+        }
+    }
+
+    // The most heap the execution's runtime used at once, as of the last sample
+    pub fn heap_peak(&self) -> usize {
+        self.heap_peak.load(Ordering::Relaxed)
+    }
+
+    // Cancels the execution when dropped before `disarm`, i.e. when the request
+    // handler is dropped because the client disconnected
+    pub fn guard(self: &Arc<Self>) -> CancelOnDrop {
+        CancelOnDrop(Some(self.clone()))
+    }
+}
+
+pub struct CancelOnDrop(Option<Arc<Cancellation>>);
+
+impl CancelOnDrop {
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = &self.0 {
+            cancellation.cancel();
+        }
+    }
+}
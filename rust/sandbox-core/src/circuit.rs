@@ -0,0 +1,296 @@
+// Per-host circuit breakers for outbound requests.
+//
+// CIRCUIT_BREAKER_FAILURES consecutive failures of a host (no connection, a timeout,
+// a broken transfer or a 5xx status), each within CIRCUIT_BREAKER_WINDOW_MS of the
+// one before, open its circuit: for CIRCUIT_BREAKER_COOLDOWN_MS its requests fail
+// at once with `circuit_open` instead of waiting out their timeouts. Then the circuit
+// is half-open and lets one probe through, refusing the others while it is under
+// way; a probe that succeeds closes the circuit and one that fails opens it again.
+// Hosts are told apart by host and port.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::fetch::{ErrorCode, HttpResult};
+
+// The breakers' time, which tests move forward instead of waiting out cooldowns
+#[derive(Default)]
+pub struct CircuitClock {
+    offset: Mutex<Duration>,
+}
+
+impl CircuitClock {
+    fn now(&self) -> Instant {
+        Instant::now() + *self.offset.lock().unwrap()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    // Whether the probe is under way
+    HalfOpen { probing: bool },
+}
+
+struct Circuit {
+    state: State,
+    // Of the current run; reset by a success or a gap longer than the window
+    failures: u32,
+    last_failure: Option<Instant>,
+    opened: u64,
+    rejected: u64,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Circuit {
+            state: State::Closed,
+            failures: 0,
+            last_failure: None,
+            opened: 0,
+            rejected: 0,
+        }
+    }
+}
+
+pub struct CircuitBreakers {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    // 0 when the breakers are off
+    failure_threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    // Only hosts that failed since their last success or ever opened their circuit
+    circuits: Mutex<BTreeMap<String, Circuit>>,
+    clock: CircuitClock,
+}
+
+// A request the breaker let through, to be told how it went. Dropped without an
+// outcome, e.g. when the script aborted it, a probe leaves the circuit half-open
+// for the next one.
+pub struct Admitted {
+    shared: Arc<Shared>,
+    host: String,
+    probe: bool,
+}
+
+// A circuit as GET /admin/circuits lists it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitStatus {
+    pub host: String,
+    // "closed", "open" or "half_open"
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    // Until the circuit is half-open, for open ones
+    pub retry_after_ms: Option<u64>,
+    // Times the circuit opened
+    pub opened: u64,
+    // Requests refused while it was open or probing
+    pub rejected: u64,
+}
+
+// Empty and disabled by default, for backends without breakers such as mocks
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitsReport {
+    pub enabled: bool,
+    pub failure_threshold: u32,
+    pub window_ms: u64,
+    pub cooldown_ms: u64,
+    pub circuits: Vec<CircuitStatus>,
+}
+
+impl CircuitBreakers {
+    pub fn from_config(config: &Config) -> Self {
+        CircuitBreakers {
+            shared: Arc::new(Shared {
+                failure_threshold: config.circuit_breaker_failures,
+                window: Duration::from_millis(config.circuit_breaker_window_ms),
+                cooldown: Duration::from_millis(config.circuit_breaker_cooldown_ms),
+                circuits: Mutex::default(),
+                clock: CircuitClock::default(),
+            }),
+        }
+    }
+
+    pub fn clock(&self) -> &CircuitClock {
+        &self.shared.clock
+    }
+
+    // Lets a request to `host` through, or refuses it with `circuit_open`
+    pub fn admit(&self, host: &str, method: &str, url: &str) -> Result<Option<Admitted>, Box<HttpResult>> {
+        if self.shared.failure_threshold == 0 {
+            return Ok(None);
+        }
+        let now = self.shared.clock.now();
+        let mut circuits = self.shared.circuits.lock().unwrap();
+        let mut probe = false;
+        if let Some(circuit) = circuits.get_mut(host) {
+            let retry_after = match circuit.state {
+                State::Open { until } if until > now => Some(until - now),
+                State::Open { .. } | State::HalfOpen { probing: false } => {
+                    circuit.state = State::HalfOpen { probing: true };
+                    probe = true;
+                    None
+                }
+                State::HalfOpen { probing: true } => Some(Duration::ZERO),
+                State::Closed => None,
+            };
+            if let Some(retry_after) = retry_after {
+                circuit.rejected += 1;
+                let mut refused = HttpResult::failure(
+                    ErrorCode::CircuitOpen,
+                    "Circuit Open",
+                    match circuit.state {
+                        State::HalfOpen { .. } => format!(
+                            "{} {} wasn't sent: the circuit for {} is half-open and a probe request is under way",
+                            method, url, host
+                        ),
+                        _ => format!(
+                            "{} {} wasn't sent: the circuit for {} is open after {} consecutive failures, for another {} ms",
+                            method,
+                            url,
+                            host,
+                            circuit.failures,
+                            retry_after.as_millis()
+                        ),
+                    },
+                );
+                refused.retry_after_ms = Some(retry_after.as_millis() as u64);
+                return Err(Box::new(refused));
+            }
+        }
+        Ok(Some(Admitted {
+            shared: self.shared.clone(),
+            host: host.to_string(),
+            probe,
+        }))
+    }
+
+    pub fn report(&self) -> CircuitsReport {
+        let shared = &self.shared;
+        let now = shared.clock.now();
+        let circuits = shared.circuits.lock().unwrap();
+        CircuitsReport {
+            enabled: shared.failure_threshold > 0,
+            failure_threshold: shared.failure_threshold,
+            window_ms: shared.window.as_millis() as u64,
+            cooldown_ms: shared.cooldown.as_millis() as u64,
+            circuits: circuits
+                .iter()
+                .map(|(host, circuit)| CircuitStatus {
+                    host: host.clone(),
+                    state: match circuit.state {
+                        State::Closed => "closed",
+                        State::Open { until } if until > now => "open",
+                        State::Open { .. } | State::HalfOpen { .. } => "half_open",
+                    },
+                    consecutive_failures: circuit.failures,
+                    retry_after_ms: match circuit.state {
+                        State::Open { until } if until > now => Some((until - now).as_millis() as u64),
+                        _ => None,
+                    },
+                    opened: circuit.opened,
+                    rejected: circuit.rejected,
+                })
+                .collect(),
+        }
+    }
+
+    // Prometheus text for the hosts whose circuit ever opened
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let report = self.report();
+        let tripped: Vec<&CircuitStatus> = report.circuits.iter().filter(|circuit| circuit.opened > 0).collect();
+        if tripped.is_empty() {
+            return out;
+        }
+        let mut metric = |name: &str, kind: &str, help: &str, value: fn(&CircuitStatus) -> u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for circuit in &tripped {
+                let host = circuit.host.replace('\\', "\\\\").replace('"', "\\\"");
+                let _ = writeln!(out, "{}{{host=\"{}\"}} {}", name, host, value(circuit));
+            }
+        };
+        metric(
+            "jsexec_circuit_state",
+            "gauge",
+            "Circuit of each host that tripped: 0 closed, 1 half-open, 2 open.",
+            |circuit| match circuit.state {
+                "open" => 2,
+                "half_open" => 1,
+                _ => 0,
+            },
+        );
+        metric("jsexec_circuit_opened_total", "counter", "Times the circuit of each host opened.", |circuit| circuit.opened);
+        metric(
+            "jsexec_circuit_rejected_total",
+            "counter",
+            "Requests refused with circuit_open, by host.",
+            |circuit| circuit.rejected,
+        );
+        out
+    }
+}
+
+impl Admitted {
+    // Counts the result of the request towards its host's circuit. Results that
+    // nothing was sent for, like cached or blocked ones, say nothing about the host.
+    pub fn record(mut self, result: &HttpResult) {
+        if result.attempts == 0 {
+            return;
+        }
+        let failed = match result.error_code {
+            Some(code) => matches!(code, ErrorCode::Connect | ErrorCode::Timeout | ErrorCode::Network),
+            None => result.status >= 500,
+        };
+        let shared = self.shared.clone();
+        let now = shared.clock.now();
+        let mut circuits = shared.circuits.lock().unwrap();
+        self.probe = false;
+        if !failed {
+            if let Some(circuit) = circuits.get_mut(&self.host) {
+                circuit.state = State::Closed;
+                circuit.failures = 0;
+                circuit.last_failure = None;
+                if circuit.opened == 0 {
+                    circuits.remove(&self.host);
+                }
+            }
+            return;
+        }
+        let circuit = circuits.entry(self.host.clone()).or_insert_with(Circuit::new);
+        let in_window = circuit.last_failure.is_some_and(|last| now.duration_since(last) <= shared.window);
+        circuit.failures = if in_window { circuit.failures + 1 } else { 1 };
+        circuit.last_failure = Some(now);
+        let reopen = matches!(circuit.state, State::HalfOpen { .. });
+        if reopen || (circuit.state == State::Closed && circuit.failures >= shared.failure_threshold) {
+            circuit.state = State::Open { until: now + shared.cooldown };
+            circuit.opened += 1;
+            tracing::warn!(host = %self.host, failures = circuit.failures, "Opened the circuit of an outbound host");
+        }
+    }
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        if self.probe {
+            if let Some(circuit) = self.shared.circuits.lock().unwrap().get_mut(&self.host) {
+                circuit.state = State::HalfOpen { probing: false };
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// Execution start time, exposed to user code as `EXECUTION_TIME`.
+//
+// With `freeze_time` the clock stands still at that instant: `Date.now()` and
+// `new Date()` return the start time and `performance.now()` returns 0 for the
+// whole execution, so values derived from them are reproducible.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct ExecutionClock {
+    epoch_ms: u64,
+    frozen: bool,
+}
+
+impl ExecutionClock {
+    pub fn start(frozen: bool) -> Self {
+        let epoch_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        ExecutionClock { epoch_ms, frozen }
+    }
+
+    // Module installing `EXECUTION_TIME` and, when frozen, the fixed clock
+    pub fn js(&self) -> String {
+        format!(
+            r#"
+            const epochMs = {epoch_ms};
+            globalThis.EXECUTION_TIME = Object.freeze({{ iso: new Date(epochMs).toISOString(), epochMs }});
+
+            if ({frozen}) {{
+                const RealDate = Date;
+                // `Date()` without `new` returns a string, so this has to be a function
+                function FrozenDate(...args) {{
+                    if (!new.target) {{
+                        return new RealDate(epochMs).toString();
+                    }}
+                    return Reflect.construct(RealDate, args.length ? args : [epochMs], new.target);
+                }}
+                Object.defineProperty(FrozenDate, "name", {{ value: "Date" }});
+                Object.setPrototypeOf(FrozenDate, RealDate);
+                FrozenDate.prototype = RealDate.prototype;
+                FrozenDate.now = function now() {{
+                    return epochMs;
+                }};
+                Object.defineProperty(RealDate.prototype, "constructor", {{ value: FrozenDate }});
+                globalThis.Date = FrozenDate;
+
+                // `performance.now` can't be redefined, so `performance` is shadowed instead
+                const frozenPerformance = Object.create(performance, {{
+                    now: {{ value: function now() {{ return 0; }} }},
+                }});
+                Object.defineProperty(globalThis, "performance", {{ value: frozenPerformance }});
+            }}
+            "#,
+            epoch_ms = self.epoch_ms,
+            frozen = self.frozen,
+        )
+    }
+}
@@ -0,0 +1,207 @@
+// `structuredClone(value)`, a deep copy in the style of the browser's.
+//
+// Primitives, plain objects, arrays, Dates, RegExps, Maps, Sets, Errors,
+// ArrayBuffers, typed arrays and DataViews are copied, cycles and shared
+// references included: a value reachable twice is copied once. Other objects are
+// copied as plain objects of their own enumerable properties. Functions, symbols,
+// promises and weak collections can't be cloned and throw a DataCloneError that
+// names where in the value they are.
+
+use rquickjs::function::{Constructor, Func, This};
+use rquickjs::{Array, Ctx, Exception, Function, Object, Result, Value};
+use std::collections::HashMap;
+
+// Deeper values throw a RangeError instead of exhausting the stack
+const MAX_DEPTH: usize = 1_000;
+
+fn data_clone_error(ctx: &Ctx<'_>, message: &str) -> rquickjs::Error {
+    match Exception::from_message(ctx.clone(), message) {
+        Ok(exception) => {
+            if let Err(e) = exception.as_object().set("name", "DataCloneError") {
+                return e;
+            }
+            exception.throw()
+        }
+        Err(e) => e,
+    }
+}
+
+struct Cloner<'js> {
+    ctx: Ctx<'js>,
+    // Originals and their copies
+    copies: HashMap<Value<'js>, Value<'js>>,
+    // Where the cloner is, as property keys below the value
+    path: Vec<String>,
+}
+
+impl<'js> Cloner<'js> {
+    fn constructor(&self, name: &str) -> Result<Constructor<'js>> {
+        self.ctx.globals().get(name)
+    }
+
+    fn instance_of(&self, object: &Object<'js>, name: &str) -> Result<bool> {
+        Ok(object.is_instance_of(self.constructor(name)?))
+    }
+
+    fn location(&self) -> String {
+        match self.path.is_empty() {
+            true => "the value itself".to_string(),
+            false => format!("value{}", self.path.concat()),
+        }
+    }
+
+    fn uncloneable(&self, what: &str) -> rquickjs::Error {
+        data_clone_error(&self.ctx, &format!("structuredClone can't clone {} (at {})", what, self.location()))
+    }
+
+    // A method of the object called without arguments, e.g. `date.getTime()`
+    fn call(&self, object: &Object<'js>, method: &str) -> Result<Value<'js>> {
+        let function: Function = object.get(method)?;
+        function.call((This(object.clone()),))
+    }
+
+    fn child(&mut self, key: String, value: Value<'js>) -> Result<Value<'js>> {
+        self.path.push(key);
+        let copy = self.clone_value(value)?;
+        self.path.pop();
+        Ok(copy)
+    }
+
+    fn clone_value(&mut self, value: Value<'js>) -> Result<Value<'js>> {
+        if value.is_symbol() {
+            return Err(self.uncloneable("a symbol"));
+        }
+        if value.is_function() {
+            return Err(self.uncloneable("a function"));
+        }
+        let Some(object) = value.as_object().cloned() else {
+            return Ok(value);
+        };
+        if let Some(copy) = self.copies.get(&value) {
+            return Ok(copy.clone());
+        }
+        if self.path.len() >= MAX_DEPTH {
+            return Err(Exception::throw_range(
+                &self.ctx,
+                &format!("structuredClone: value is nested more than {} levels deep", MAX_DEPTH),
+            ));
+        }
+        if value.is_promise() {
+            return Err(self.uncloneable("a Promise"));
+        }
+        for name in ["WeakMap", "WeakSet", "WeakRef"] {
+            if self.ctx.globals().contains_key(name)? && self.instance_of(&object, name)? {
+                return Err(self.uncloneable(&format!("a {}", name)));
+            }
+        }
+
+        if let Some(array) = object.as_array() {
+            let copy = Array::new(self.ctx.clone())?;
+            self.copies.insert(value.clone(), copy.clone().into_value());
+            for index in 0..array.len() {
+                let item = self.child(format!("[{}]", index), array.get(index)?)?;
+                copy.set(index, item)?;
+            }
+            return Ok(copy.into_value());
+        }
+        if self.instance_of(&object, "Date")? {
+            let time: Value = self.call(&object, "getTime")?;
+            let copy: Value = self.constructor("Date")?.construct((time,))?;
+            self.copies.insert(value, copy.clone());
+            return Ok(copy);
+        }
+        if self.instance_of(&object, "RegExp")? {
+            let source: Value = object.get("source")?;
+            let flags: Value = object.get("flags")?;
+            let copy: Value = self.constructor("RegExp")?.construct((source, flags))?;
+            self.copies.insert(value, copy.clone());
+            return Ok(copy);
+        }
+        if self.instance_of(&object, "ArrayBuffer")? {
+            let copy: Value = self.call(&object, "slice")?;
+            self.copies.insert(value, copy.clone());
+            return Ok(copy);
+        }
+        if self.instance_of(&object, "DataView")? {
+            let buffer: Object = object.get("buffer")?;
+            let buffer = self.clone_value(buffer.into_value())?;
+            let copy: Value = self
+                .constructor("DataView")?
+                .construct((buffer, object.get::<_, Value>("byteOffset")?, object.get::<_, Value>("byteLength")?))?;
+            self.copies.insert(value, copy.clone());
+            return Ok(copy);
+        }
+        let is_view: Function = self.ctx.globals().get::<_, Object>("ArrayBuffer")?.get("isView")?;
+        if is_view.call::<_, bool>((value.clone(),))? {
+            // A typed array, copied with its own buffer
+            let copy: Value = self.call(&object, "slice")?;
+            self.copies.insert(value, copy.clone());
+            return Ok(copy);
+        }
+        if self.instance_of(&object, "Map")? {
+            let copy: Object = self.constructor("Map")?.construct(())?;
+            self.copies.insert(value.clone(), copy.clone().into_value());
+            let entries: Array = self.entries(&object)?;
+            let set: Function = copy.get("set")?;
+            for (index, entry) in entries.iter::<Array>().enumerate() {
+                let entry = entry?;
+                let key = self.child(format!(".keys()[{}]", index), entry.get(0)?)?;
+                let item = self.child(format!(".values()[{}]", index), entry.get(1)?)?;
+                set.call::<_, Value>((This(copy.clone()), key, item))?;
+            }
+            return Ok(copy.into_value());
+        }
+        if self.instance_of(&object, "Set")? {
+            let copy: Object = self.constructor("Set")?.construct(())?;
+            self.copies.insert(value.clone(), copy.clone().into_value());
+            let items: Array = self.entries(&object)?;
+            let add: Function = copy.get("add")?;
+            for (index, item) in items.iter::<Value>().enumerate() {
+                let item = self.child(format!(".values()[{}]", index), item?)?;
+                add.call::<_, Value>((This(copy.clone()), item))?;
+            }
+            return Ok(copy.into_value());
+        }
+        if value.is_error() {
+            let message: Value = object.get("message")?;
+            let copy: Object = self.constructor("Error")?.construct((message,))?;
+            self.copies.insert(value.clone(), copy.clone().into_value());
+            copy.set("name", object.get::<_, Value>("name")?)?;
+            copy.set("stack", object.get::<_, Value>("stack")?)?;
+            if object.contains_key("cause")? {
+                let cause = self.child(".cause".to_string(), object.get("cause")?)?;
+                copy.set("cause", cause)?;
+            }
+            copy.set_prototype(object.get_prototype().as_ref())?;
+            return Ok(copy.into_value());
+        }
+
+        let copy = Object::new(self.ctx.clone())?;
+        self.copies.insert(value, copy.clone().into_value());
+        for key in object.keys::<String>() {
+            let key = key?;
+            let item = self.child(format!(".{}", key), object.get(key.as_str())?)?;
+            copy.set(key.as_str(), item)?;
+        }
+        Ok(copy.into_value())
+    }
+
+    // `Array.from(collection)`: [key, value] pairs of a Map, the items of a Set
+    fn entries(&self, collection: &Object<'js>) -> Result<Array<'js>> {
+        let from: Function = self.ctx.globals().get::<_, Object>("Array")?.get("from")?;
+        from.call((collection.clone(),))
+    }
+}
+
+fn structured_clone<'js>(ctx: Ctx<'js>, value: Value<'js>) -> Result<Value<'js>> {
+    Cloner {
+        ctx,
+        copies: HashMap::new(),
+        path: Vec::new(),
+    }
+    .clone_value(value)
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    ctx.globals().set("structuredClone", Func::from(structured_clone))
+}
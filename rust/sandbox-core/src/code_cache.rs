@@ -0,0 +1,256 @@
+// Cache of compiled user code, keyed by the SHA-256 of the source.
+//
+// Agents tend to run the same snippet many times with different inputs. Instead of
+// parsing and compiling it for every execution, the QuickJS bytecode of the first
+// compilation is kept and read into the fresh context of later executions. Entries
+// that fail to load are dropped and the code is compiled from source again, so
+// results never depend on the cache. CODE_CACHE_MAX_ENTRIES bounds the number of
+// entries (0 disables the cache), evicting least recently used ones first.
+
+use lru::LruCache;
+use rquickjs::module::Declared;
+use rquickjs::{qjs, Ctx, Error, Module, Promise, Result, Value, WriteOptions};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+
+// The name `Ctx::eval` gives scripts, which stack traces are rewritten from
+const SCRIPT_FILENAME: &CStr = c"eval_script";
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Script = 0,
+    Module = 1,
+}
+
+type Key = [u8; 32];
+
+pub struct CodeCache {
+    entries: Option<Mutex<LruCache<Key, Arc<[u8]>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CodeCache {
+    pub fn from_config(config: &Config) -> Self {
+        CodeCache {
+            entries: NonZeroUsize::new(config.code_cache_max_entries).map(|max| Mutex::new(LruCache::new(max))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // Hits and misses since the server started
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    // The code compiled as an async script, ready for `run_script`, and whether it
+    // came from the cache
+    pub fn script<'js>(&self, ctx: &Ctx<'js>, code: &str) -> Result<(Value<'js>, bool)> {
+        self.lookup(
+            ctx,
+            Kind::Script,
+            code,
+            |bytes| read_script(ctx, bytes),
+            || compile_script(ctx, code),
+            |function| write_script(ctx, function),
+        )
+    }
+
+    // The code declared as a module under `name`, and whether it came from the cache
+    pub fn module<'js>(&self, ctx: &Ctx<'js>, name: &str, code: &str) -> Result<(Module<'js, Declared>, bool)> {
+        self.lookup(
+            ctx,
+            Kind::Module,
+            code,
+            // Only ever given bytecode written by `Module::write` below
+            |bytes| unsafe { Module::load(ctx.clone(), bytes) },
+            || Module::declare(ctx.clone(), name, code),
+            |module| module.write(WriteOptions::default()),
+        )
+    }
+
+    fn lookup<'js, T>(
+        &self,
+        ctx: &Ctx<'js>,
+        kind: Kind,
+        code: &str,
+        load: impl FnOnce(&[u8]) -> Result<T>,
+        compile: impl FnOnce() -> Result<T>,
+        write: impl FnOnce(&T) -> Result<Vec<u8>>,
+    ) -> Result<(T, bool)> {
+        let Some(entries) = &self.entries else {
+            return compile().map(|compiled| (compiled, false));
+        };
+        let key = key(kind, code);
+
+        let cached = entries.lock().unwrap().get(&key).cloned();
+        if let Some(bytes) = cached {
+            match load(&bytes) {
+                Ok(loaded) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok((loaded, true));
+                }
+                Err(e) => {
+                    let _ = ctx.catch();
+                    tracing::warn!("Dropping cached bytecode that failed to load: {}", e);
+                    entries.lock().unwrap().pop(&key);
+                }
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // Code that doesn't compile isn't cached, its error is reported as usual
+        let compiled = compile()?;
+        match write(&compiled) {
+            Ok(bytes) => {
+                entries.lock().unwrap().put(key, bytes.into());
+            }
+            Err(e) => {
+                let _ = ctx.catch();
+                tracing::debug!("Compiled code can't be cached: {}", e);
+            }
+        }
+        Ok((compiled, false))
+    }
+}
+
+// Bytecode of the sandbox's own modules by name, written the first time each is
+// declared and loaded into every later context instead of parsing the source again.
+// Unlike user code, these are a handful of fixed sources, so they are kept for the
+// life of the process whatever CODE_CACHE_MAX_ENTRIES says.
+static PRELUDE: Mutex<BTreeMap<&'static str, Arc<[u8]>>> = Mutex::new(BTreeMap::new());
+
+// Declares one of the sandbox's modules, from its bytecode once there is some
+pub fn prelude<'js>(ctx: &Ctx<'js>, name: &'static str, source: &'static str) -> Result<Module<'js, Declared>> {
+    let cached = PRELUDE.lock().unwrap().get(name).cloned();
+    if let Some(bytes) = cached {
+        // Only ever given bytecode written by `Module::write` below
+        match unsafe { Module::load(ctx.clone(), &bytes) } {
+            Ok(module) => return Ok(module),
+            Err(e) => {
+                let _ = ctx.catch();
+                tracing::warn!("Compiling {} again, its bytecode failed to load: {}", name, e);
+            }
+        }
+    }
+    let module = Module::declare(ctx.clone(), name, source)?;
+    match module.write(WriteOptions::default()) {
+        Ok(bytes) => {
+            PRELUDE.lock().unwrap().insert(name, bytes.into());
+        }
+        Err(e) => {
+            let _ = ctx.catch();
+            tracing::debug!("{} can't be kept as bytecode: {}", name, e);
+        }
+    }
+    Ok(module)
+}
+
+// Evaluates one of the sandbox's modules like `Module::evaluate`
+pub fn evaluate_prelude<'js>(ctx: &Ctx<'js>, name: &'static str, source: &'static str) -> Result<Promise<'js>> {
+    let (_, promise) = prelude(ctx, name, source)?.eval()?;
+    Ok(promise)
+}
+
+fn key(kind: Kind, code: &str) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update([kind as u8]);
+    hasher.update(code.as_bytes());
+    hasher.finalize().into()
+}
+
+// rquickjs only compiles modules without running them, so scripts are compiled,
+// written and read through the QuickJS API directly. The flags match those of
+// `Ctx::eval_promise`.
+pub fn compile_script<'js>(ctx: &Ctx<'js>, code: &str) -> Result<Value<'js>> {
+    compile(ctx, SCRIPT_FILENAME, code, qjs::JS_EVAL_FLAG_ASYNC)
+}
+
+// A strict script under its own file name, whose `run_script_bytecode` runs it
+// synchronously. For the prelude scripts, which can't await at the top level.
+pub fn compile_named_script<'js>(ctx: &Ctx<'js>, filename: &str, code: &str) -> Result<Value<'js>> {
+    compile(ctx, &CString::new(filename)?, code, 0)
+}
+
+fn compile<'js>(ctx: &Ctx<'js>, filename: &CStr, code: &str, flags: u32) -> Result<Value<'js>> {
+    let source = CString::new(code)?;
+    let flags = qjs::JS_EVAL_TYPE_GLOBAL | qjs::JS_EVAL_FLAG_STRICT | qjs::JS_EVAL_FLAG_COMPILE_ONLY | flags;
+    unsafe {
+        let function = qjs::JS_Eval(
+            ctx.as_raw().as_ptr(),
+            source.as_ptr(),
+            code.len() as _,
+            filename.as_ptr(),
+            flags as i32,
+        );
+        owned(ctx, function)
+    }
+}
+
+pub fn write_script(ctx: &Ctx<'_>, function: &Value<'_>) -> Result<Vec<u8>> {
+    unsafe {
+        let mut len = 0;
+        let buf = qjs::JS_WriteObject(
+            ctx.as_raw().as_ptr(),
+            &mut len,
+            function.as_raw(),
+            qjs::JS_WRITE_OBJ_BYTECODE as i32,
+        );
+        if buf.is_null() {
+            return Err(Error::Exception);
+        }
+        let bytes = std::slice::from_raw_parts(buf, len as usize).to_vec();
+        qjs::js_free(ctx.as_raw().as_ptr(), buf.cast());
+        Ok(bytes)
+    }
+}
+
+fn read_script<'js>(ctx: &Ctx<'js>, bytes: &[u8]) -> Result<Value<'js>> {
+    unsafe {
+        let function = qjs::JS_ReadObject(
+            ctx.as_raw().as_ptr(),
+            bytes.as_ptr(),
+            bytes.len() as _,
+            qjs::JS_READ_OBJ_BYTECODE as i32,
+        );
+        owned(ctx, function)
+    }
+}
+
+// Runs a script returned by `CodeCache::script`. Like `Ctx::eval_promise`, the
+// result is a promise of `{ value }`.
+pub fn run_script<'js>(ctx: &Ctx<'js>, function: Value<'js>) -> Result<Promise<'js>> {
+    unsafe {
+        // JS_EvalFunction takes over the reference it is given
+        let raw = qjs::JS_DupValue(ctx.as_raw().as_ptr(), function.as_raw());
+        let result = owned(ctx, qjs::JS_EvalFunction(ctx.as_raw().as_ptr(), raw))?;
+        result.into_promise().ok_or(Error::Unknown)
+    }
+}
+
+// Reads a script written by `write_script` and runs it, returning its completion value
+pub fn run_script_bytecode<'js>(ctx: &Ctx<'js>, bytes: &[u8]) -> Result<Value<'js>> {
+    let function = read_script(ctx, bytes)?;
+    unsafe {
+        let raw = qjs::JS_DupValue(ctx.as_raw().as_ptr(), function.as_raw());
+        owned(ctx, qjs::JS_EvalFunction(ctx.as_raw().as_ptr(), raw))
+    }
+}
+
+unsafe fn owned<'js>(ctx: &Ctx<'js>, value: qjs::JSValue) -> Result<Value<'js>> {
+    if qjs::JS_IsException(value) {
+        return Err(Error::Exception);
+    }
+    Ok(Value::from_raw(ctx.clone(), value))
+}
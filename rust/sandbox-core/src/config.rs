@@ -0,0 +1,543 @@
+// Server configuration, read once at startup.
+//
+// Settings come from a TOML file given with `--config path` or CONFIG_PATH, and from
+// environment variables, which take precedence over the file; anything set in
+// neither keeps its default. Every key is named after its variable, lowercased
+// (`execution_timeout_ms` for EXECUTION_TIMEOUT_MS). Lists are TOML arrays in the
+// file and comma-separated in the environment, and an empty variable counts as
+// unset. Values of the wrong type fail startup with an error naming the key, and
+// so do values out of range, such as a rate limit that isn't positive or a
+// timeout of 0.
+// The `[sandbox_env]` and `[signing_secrets]` tables are the exception: every
+// SANDBOX_ENV_<NAME> or SIGNING_SECRET_<NAME> variable adds the entry NAME to them,
+// or replaces the file's. `[tenants.<id>]` tables are
+// only read from the file.
+// RUST_LOG and the OTEL_* exporter variables other than the endpoint and service
+// name are read by their libraries directly.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use toml::{Table, Value};
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Listeners
+    pub port: u16,
+    pub listen: Vec<String>,
+    pub listen_socket_mode: String,
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
+    pub tls_client_ca_path: String,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    pub cors_max_age: u64,
+    pub cors_allow_credentials: bool,
+    pub rate_limit_enabled: bool,
+    pub rate_limit_api_key_per_second: f64,
+    pub rate_limit_api_key_burst: f64,
+    pub rate_limit_ip_per_second: f64,
+    pub rate_limit_ip_burst: f64,
+    pub trust_proxy: bool,
+    pub max_body_bytes: usize,
+    // Each file of a multipart /execute body
+    pub max_file_bytes: usize,
+
+    // Executions
+    pub max_code_bytes: usize,
+    pub max_inputs_bytes: usize,
+    // Inputs with more bytes of JSON than this aren't evaluated into INPUTS up front
+    // but read as the code touches them; 0 always evaluates them
+    pub lazy_inputs_bytes: usize,
+    pub execution_timeout_ms: u64,
+    pub max_exec_timeout_ms: u64,
+    // CPU time an execution may use, not counting the time it waits
+    pub exec_cpu_ms: u64,
+    pub max_requests_per_execution: u32,
+    pub max_result_bytes: usize,
+    // Results of /execute at least this large are streamed as NDJSON; 0 only streams
+    // those that ask for it
+    pub stream_result_bytes: usize,
+    pub js_max_stack_bytes: usize,
+    pub js_max_memory_bytes: usize,
+    pub disable_dynamic_eval: bool,
+    // Total time an execution may wait in sleep() and setTimeout
+    pub sleep_budget_ms: u64,
+    // Injected into every execution as the frozen ENV
+    pub sandbox_env: BTreeMap<String, String>,
+    // Scripts evaluated into every context before user code: the .js files of
+    // PRELUDE_DIR by name, then PRELUDE_FILES in the order given
+    pub prelude_dir: String,
+    pub prelude_files: Vec<String>,
+    pub state_path: String,
+    pub state_max_namespace_bytes: usize,
+    pub max_concurrent_executions: usize,
+    // Of each priority
+    pub execution_queue_depth: usize,
+    pub queue_wait_timeout_ms: u64,
+    // Slots only interactive executions may take
+    pub interactive_reserved_slots: usize,
+    // API keys that may ask for priority "interactive"; any caller may when empty
+    pub interactive_api_keys: Vec<String>,
+    // API keys that may read the usage of every key from GET /usage
+    pub admin_api_keys: Vec<String>,
+    // Budgets of every API key per UTC day and per calendar month; 0 for none
+    pub quota_daily_executions: u64,
+    pub quota_daily_cpu_ms: u64,
+    pub quota_daily_fetched_bytes: u64,
+    pub quota_monthly_executions: u64,
+    pub quota_monthly_cpu_ms: u64,
+    pub quota_monthly_fetched_bytes: u64,
+    // The JSON file the usage is saved to, so that restarts don't reset it
+    pub quota_path: String,
+    // "in-process", or "subprocess" to run every execution in a worker process
+    pub isolation_mode: String,
+    // Worker processes started ahead of executions
+    pub isolation_workers: usize,
+    // Executions a worker process runs before it is replaced; 1 gives each its own
+    pub isolation_worker_max_executions: u32,
+    // The address space limit (RLIMIT_AS) of worker processes
+    pub isolation_worker_memory_bytes: u64,
+    // The binary started with --worker, the server's own when empty
+    pub isolation_worker_binary: String,
+    pub js_runtime_pool_size: usize,
+    pub js_runtime_max_uses: u32,
+    pub js_runtime_max_heap_bytes: usize,
+    pub js_runtime_pool_wait_ms: u64,
+    pub code_cache_max_entries: usize,
+    // Results of requests with `cache`, by count and approximate size
+    pub result_cache_max_entries: usize,
+    pub result_cache_max_bytes: usize,
+    // The longest `ttlSeconds` a request may ask for
+    pub result_cache_max_ttl_seconds: u64,
+    pub max_batch_jobs: usize,
+    pub batch_parallelism: usize,
+    pub max_input_sets: usize,
+    pub max_sessions: usize,
+    pub session_idle_timeout_ms: u64,
+    pub max_contexts: usize,
+    pub context_idle_timeout_ms: u64,
+    pub jobs_concurrency: usize,
+    pub jobs_max_stored: usize,
+    pub job_result_ttl_ms: u64,
+    // Signs the callbacks of jobs with a callback_url, which are refused without it
+    pub job_callback_secret: String,
+    pub job_callback_max_attempts: u32,
+    // Before the first retry, doubling for each further one
+    pub job_callback_retry_ms: u64,
+    pub job_callback_timeout_ms: u64,
+
+    // Outbound requests
+    pub fetch_timeout_ms: u64,
+    pub fetch_max_attempts: u32,
+    pub fetch_max_body_bytes: usize,
+    // Request and response bodies of all the httpRequest calls of one execution
+    pub max_fetch_total_bytes: usize,
+    pub fetch_cache_max_entries: usize,
+    pub fetch_cache_max_bytes: usize,
+    // Requests of one execution in flight at once, in total and to any one host; 0
+    // for no limit
+    pub fetch_concurrency: usize,
+    pub fetch_concurrency_per_host: usize,
+    pub fetch_allowlist: Vec<String>,
+    pub fetch_denylist: Vec<String>,
+    // Methods httpRequest may use, e.g. GET and HEAD for read-only deployments; any
+    // method when empty
+    pub outbound_allowed_methods: Vec<String>,
+    // Headers masked in dry run reports and recorded HTTP traces
+    pub masked_headers: Vec<String>,
+    // Headers only sent to URLs matching the allowlist, when there is one
+    pub sensitive_headers: Vec<String>,
+    pub header_forward_allowlist: Vec<String>,
+    // Secrets `options.sign` may reference by name, which scripts never see
+    pub signing_secrets: BTreeMap<String, String>,
+    pub allow_private_networks: bool,
+    // Blocks cloud metadata endpoints, even with private networks allowed
+    pub block_cloud_metadata: bool,
+    // `host=ip` entries resolving those hosts in place of DNS
+    pub dns_overrides: Vec<String>,
+    // Blocks every outbound request; answering them with http_mocks still works
+    pub network_disabled: bool,
+    pub allow_insecure_tls: bool,
+    pub outbound_ca_bundle: String,
+    pub outbound_http_proxy: String,
+    pub outbound_https_proxy: String,
+    pub outbound_no_proxy: Vec<String>,
+    pub outbound_request_id: bool,
+    // Sent unless the script sets a User-Agent header; none while empty, and the
+    // embedding service's name by default after `Config::name_service`
+    pub outbound_user_agent: String,
+    // Appends the X-Request-Id of the execution to that User-Agent
+    pub ua_include_request_id: bool,
+    // Consecutive failures of one host, each within the window of the one before,
+    // that open its circuit; 0 turns the circuit breakers off
+    pub circuit_breaker_failures: u32,
+    pub circuit_breaker_window_ms: u64,
+    // How long an open circuit refuses requests before it lets a probe through
+    pub circuit_breaker_cooldown_ms: u64,
+
+    // Operations
+    pub health_check_timeout_ms: u64,
+    pub readiness_saturation_window_ms: u64,
+    // Time the startup warm-up may take before the server stays not ready
+    pub warm_up_timeout_ms: u64,
+    pub shutdown_grace_seconds: u64,
+    pub log_format: String,
+    pub log_code: bool,
+    pub metrics_host_label: bool,
+    // Serves Swagger UI at /docs, for the document at /openapi.json
+    pub openapi_docs: bool,
+    pub otel_exporter_otlp_endpoint: String,
+    pub otel_service_name: String,
+    // One record per execution, appended to a file, posted to a collector or both
+    pub audit_file: String,
+    // Rotated before it would grow beyond this, keeping this many older files
+    pub audit_file_max_bytes: u64,
+    pub audit_file_keep: u32,
+    pub audit_url: String,
+    pub audit_http_max_attempts: u32,
+    // Before the first retry, doubling for each further one
+    pub audit_http_retry_ms: u64,
+    pub audit_http_timeout_ms: u64,
+    // Records waiting for each sink; more are lost
+    pub audit_queue_records: usize,
+
+    // By tenant id; only set in the config file, as `[tenants.<id>]` tables
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+// Guardrails of one tenant, on top of the server-wide ones. 0 means no limit of
+// the tenant's own.
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantConfig {
+    // X-API-Key values whose requests run for the tenant
+    pub api_keys: Vec<String>,
+    // Executions of the tenant running or waiting for a slot at once
+    pub max_concurrent_executions: usize,
+    // Executions per second, with bursts of up to `rate_limit_burst`
+    pub rate_limit_per_second: f64,
+    pub rate_limit_burst: f64,
+    // URLs the tenant's executions may call, as FETCH_ALLOWLIST patterns, which
+    // still apply
+    pub fetch_allowlist: Vec<String>,
+    // Budgets per calendar month (UTC)
+    pub monthly_executions: u64,
+    pub monthly_cpu_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: 3000,
+            listen: Vec::new(),
+            listen_socket_mode: String::new(),
+            tls_cert_path: String::new(),
+            tls_key_path: String::new(),
+            tls_client_ca_path: String::new(),
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_headers: ["content-type", "x-api-key", "x-request-id"].map(String::from).to_vec(),
+            cors_max_age: 600,
+            cors_allow_credentials: false,
+            rate_limit_enabled: false,
+            rate_limit_api_key_per_second: 20.0,
+            rate_limit_api_key_burst: 40.0,
+            rate_limit_ip_per_second: 5.0,
+            rate_limit_ip_burst: 10.0,
+            trust_proxy: false,
+            max_body_bytes: 10 * 1024 * 1024,
+            max_file_bytes: 5 * 1024 * 1024,
+
+            max_code_bytes: 256 * 1024,
+            max_inputs_bytes: 5 * 1024 * 1024,
+            lazy_inputs_bytes: 4 * 1024 * 1024,
+            execution_timeout_ms: 30_000,
+            max_exec_timeout_ms: 120_000,
+            exec_cpu_ms: 30_000,
+            max_requests_per_execution: 25,
+            max_result_bytes: 5 * 1024 * 1024,
+            stream_result_bytes: 0,
+            js_max_stack_bytes: 512 * 1024,
+            js_max_memory_bytes: 256 * 1024 * 1024,
+            disable_dynamic_eval: false,
+            sleep_budget_ms: 10_000,
+            sandbox_env: BTreeMap::new(),
+            prelude_dir: String::new(),
+            prelude_files: Vec::new(),
+            state_path: String::new(),
+            state_max_namespace_bytes: 1024 * 1024,
+            max_concurrent_executions: 32,
+            execution_queue_depth: 64,
+            queue_wait_timeout_ms: 5_000,
+            interactive_reserved_slots: 0,
+            interactive_api_keys: Vec::new(),
+            admin_api_keys: Vec::new(),
+            quota_daily_executions: 0,
+            quota_daily_cpu_ms: 0,
+            quota_daily_fetched_bytes: 0,
+            quota_monthly_executions: 0,
+            quota_monthly_cpu_ms: 0,
+            quota_monthly_fetched_bytes: 0,
+            quota_path: String::new(),
+            isolation_mode: "in-process".to_string(),
+            isolation_workers: 4,
+            isolation_worker_max_executions: 1,
+            isolation_worker_memory_bytes: 1024 * 1024 * 1024,
+            isolation_worker_binary: String::new(),
+            js_runtime_pool_size: 0,
+            js_runtime_max_uses: 100,
+            js_runtime_max_heap_bytes: 64 * 1024 * 1024,
+            js_runtime_pool_wait_ms: 50,
+            code_cache_max_entries: 256,
+            result_cache_max_entries: 1024,
+            result_cache_max_bytes: 64 * 1024 * 1024,
+            result_cache_max_ttl_seconds: 86400,
+            max_batch_jobs: 50,
+            batch_parallelism: 4,
+            max_input_sets: 1000,
+            max_sessions: 16,
+            session_idle_timeout_ms: 300_000,
+            max_contexts: 16,
+            context_idle_timeout_ms: 300_000,
+            jobs_concurrency: 4,
+            jobs_max_stored: 1000,
+            job_result_ttl_ms: 10 * 60 * 1000,
+            job_callback_secret: String::new(),
+            job_callback_max_attempts: 5,
+            job_callback_retry_ms: 1000,
+            job_callback_timeout_ms: 10_000,
+
+            fetch_timeout_ms: 10_000,
+            fetch_max_attempts: 5,
+            fetch_max_body_bytes: 10 * 1024 * 1024,
+            max_fetch_total_bytes: 50 * 1024 * 1024,
+            fetch_cache_max_entries: 1000,
+            fetch_cache_max_bytes: 50 * 1024 * 1024,
+            fetch_concurrency: 8,
+            fetch_concurrency_per_host: 0,
+            fetch_allowlist: Vec::new(),
+            fetch_denylist: Vec::new(),
+            outbound_allowed_methods: Vec::new(),
+            masked_headers: ["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key"]
+                .map(String::from)
+                .to_vec(),
+            sensitive_headers: ["authorization", "cookie", "x-api-key"].map(String::from).to_vec(),
+            header_forward_allowlist: Vec::new(),
+            signing_secrets: BTreeMap::new(),
+            allow_private_networks: false,
+            block_cloud_metadata: true,
+            dns_overrides: Vec::new(),
+            network_disabled: false,
+            allow_insecure_tls: false,
+            outbound_ca_bundle: String::new(),
+            outbound_http_proxy: String::new(),
+            outbound_https_proxy: String::new(),
+            outbound_no_proxy: Vec::new(),
+            outbound_request_id: true,
+            outbound_user_agent: String::new(),
+            ua_include_request_id: false,
+            circuit_breaker_failures: 0,
+            circuit_breaker_window_ms: 60_000,
+            circuit_breaker_cooldown_ms: 30_000,
+
+            health_check_timeout_ms: 2_000,
+            readiness_saturation_window_ms: 10_000,
+            warm_up_timeout_ms: 30_000,
+            shutdown_grace_seconds: 30,
+            log_format: "text".to_string(),
+            log_code: false,
+            metrics_host_label: false,
+            openapi_docs: false,
+            otel_exporter_otlp_endpoint: String::new(),
+            otel_service_name: "js-execution-service".to_string(),
+            audit_file: String::new(),
+            audit_file_max_bytes: 100 * 1024 * 1024,
+            audit_file_keep: 5,
+            audit_url: String::new(),
+            audit_http_max_attempts: 5,
+            audit_http_retry_ms: 1000,
+            audit_http_timeout_ms: 10_000,
+            audit_queue_records: 10_000,
+
+            tenants: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let mut table = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read the config file {}: {}", path.display(), e))?;
+                // Parsed as a Config first, so errors point at the key and line
+                toml::from_str::<Config>(&text)
+                    .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+                toml::from_str::<Table>(&text).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?
+            }
+            None => Table::new(),
+        };
+
+        // The defaults tell which type every key has
+        let defaults = Table::try_from(Config::default()).map_err(|e| e.to_string())?;
+        for (key, default) in &defaults {
+            if key == "tenants" || PREFIXED_TABLES.iter().any(|(table, _)| table == key) {
+                continue;
+            }
+            let var = key.to_ascii_uppercase();
+            match std::env::var(&var) {
+                Ok(raw) if !raw.trim().is_empty() => {
+                    let value = from_env(&var, &raw, default)?;
+                    // On its own, so e.g. an out of range number is reported with its variable
+                    Config::deserialize(Table::from_iter([(key.clone(), value.clone())]))
+                        .map_err(|e| format!("Invalid {}: {}", var, e))?;
+                    table.insert(key.clone(), value);
+                }
+                _ => {}
+            }
+        }
+        for (key, prefix) in PREFIXED_TABLES {
+            let mut entries = match table.remove(key) {
+                Some(Value::Table(entries)) => entries,
+                _ => Table::new(),
+            };
+            for (var, value) in std::env::vars() {
+                match var.strip_prefix(prefix) {
+                    Some(name) if !name.is_empty() && !value.trim().is_empty() => {
+                        entries.insert(name.to_string(), Value::String(value.trim().to_string()));
+                    }
+                    _ => {}
+                }
+            }
+            table.insert(key.to_string(), Value::Table(entries));
+        }
+        let config = Config::deserialize(table).map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The service embedding the sandbox, which names itself in the User-Agent of
+    // outbound requests unless OUTBOUND_USER_AGENT is set
+    pub fn name_service(&mut self, name: &str, version: &str) {
+        if self.outbound_user_agent.is_empty() {
+            self.outbound_user_agent = format!("{}/{}", name, version);
+        }
+    }
+
+    // Values of the right type that still make no sense
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = [
+            ("RATE_LIMIT_API_KEY_PER_SECOND", self.rate_limit_api_key_per_second),
+            ("RATE_LIMIT_API_KEY_BURST", self.rate_limit_api_key_burst),
+            ("RATE_LIMIT_IP_PER_SECOND", self.rate_limit_ip_per_second),
+            ("RATE_LIMIT_IP_BURST", self.rate_limit_ip_burst),
+        ];
+        for (var, rate) in rates {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(format!("Invalid {}: expected a positive number, got {}", var, rate));
+            }
+        }
+        // Limits without a meaning for 0, where it would fail every execution
+        let limits = [
+            ("EXECUTION_TIMEOUT_MS", self.execution_timeout_ms),
+            ("EXEC_CPU_MS", self.exec_cpu_ms),
+            ("MAX_BODY_BYTES", self.max_body_bytes as u64),
+            ("MAX_CODE_BYTES", self.max_code_bytes as u64),
+            ("MAX_INPUTS_BYTES", self.max_inputs_bytes as u64),
+            ("MAX_RESULT_BYTES", self.max_result_bytes as u64),
+            ("JS_MAX_STACK_BYTES", self.js_max_stack_bytes as u64),
+            ("JS_MAX_MEMORY_BYTES", self.js_max_memory_bytes as u64),
+            ("MAX_CONCURRENT_EXECUTIONS", self.max_concurrent_executions as u64),
+            ("MAX_BATCH_JOBS", self.max_batch_jobs as u64),
+            ("BATCH_PARALLELISM", self.batch_parallelism as u64),
+            ("MAX_INPUT_SETS", self.max_input_sets as u64),
+            ("RESULT_CACHE_MAX_TTL_SECONDS", self.result_cache_max_ttl_seconds),
+            ("JOBS_CONCURRENCY", self.jobs_concurrency as u64),
+            ("FETCH_TIMEOUT_MS", self.fetch_timeout_ms),
+            ("FETCH_MAX_ATTEMPTS", self.fetch_max_attempts.into()),
+            ("FETCH_MAX_BODY_BYTES", self.fetch_max_body_bytes as u64),
+        ];
+        for (var, limit) in limits {
+            if limit == 0 {
+                return Err(format!("Invalid {}: expected a number above 0", var));
+            }
+        }
+        // 0 turns a tenant's rate limit off
+        for (id, tenant) in &self.tenants {
+            for (key, rate) in [("rate_limit_per_second", tenant.rate_limit_per_second), ("rate_limit_burst", tenant.rate_limit_burst)] {
+                if !(rate.is_finite() && rate >= 0.0) {
+                    return Err(format!("Invalid {} of tenant {}: expected 0 or a positive number, got {}", key, id, rate));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // For the startup log, with secrets, API keys and credentials in URLs (e.g.
+    // proxies) masked
+    pub fn redacted(&self) -> String {
+        let mut config = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = config.as_object_mut() {
+            for (key, value) in fields.iter_mut() {
+                match value {
+                    serde_json::Value::String(s) if key.ends_with("_secret") && !s.is_empty() => *s = "redacted".to_string(),
+                    serde_json::Value::String(s) => redact(s),
+                    serde_json::Value::Array(items) if key.ends_with("_api_keys") => {
+                        items.iter_mut().for_each(|item| *item = "redacted".into())
+                    }
+                    serde_json::Value::Object(secrets) if key == "signing_secrets" => {
+                        secrets.values_mut().for_each(|value| *value = "redacted".into())
+                    }
+                    serde_json::Value::Object(tenants) if key == "tenants" => tenants
+                        .values_mut()
+                        .filter_map(|tenant| tenant.get_mut("api_keys")?.as_array_mut())
+                        .flatten()
+                        .for_each(|item| *item = "redacted".into()),
+                    serde_json::Value::Array(items) => items
+                        .iter_mut()
+                        .filter_map(|item| match item {
+                            serde_json::Value::String(s) => Some(s),
+                            _ => None,
+                        })
+                        .for_each(redact),
+                    _ => {}
+                }
+            }
+        }
+        config.to_string()
+    }
+}
+
+// Tables whose entries are also set with one variable each, by prefix
+const PREFIXED_TABLES: [(&str, &str); 2] = [("sandbox_env", "SANDBOX_ENV_"), ("signing_secrets", "SIGNING_SECRET_")];
+
+fn from_env(var: &str, raw: &str, default: &Value) -> Result<Value, String> {
+    let raw = raw.trim();
+    let invalid = |expected: &str| format!("Invalid {}: expected {}, got {:?}", var, expected, raw);
+    Ok(match default {
+        Value::Boolean(_) => match raw.to_ascii_lowercase().as_str() {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ => return Err(invalid("true or false")),
+        },
+        Value::Integer(_) => Value::Integer(raw.parse().map_err(|_| invalid("an integer"))?),
+        Value::Float(_) => Value::Float(raw.parse().map_err(|_| invalid("a number"))?),
+        Value::Array(_) => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => Value::String(raw.to_string()),
+    })
+}
+
+fn redact(value: &mut String) {
+    if let Ok(mut url) = url::Url::parse(value) {
+        if url.password().is_some() && url.set_password(Some("redacted")).is_ok() {
+            *value = url.to_string();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// CPU time spent by one execution, for EXEC_CPU_MS.
+//
+// An execution's future runs on whichever Tokio worker polls it, and awaits in
+// between, so the thread's CPU clock is read around every poll and only those
+// slices are added up. Waiting on requests and timers takes no CPU and isn't
+// counted; work done by host functions called from JavaScript, such as parsing a
+// response, is. The interrupt handler, which QuickJS calls while it runs code,
+// also counts the slice in progress, so a busy loop is stopped inside its poll.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+
+pub struct CpuTime {
+    budget_ns: u64,
+    // Of the polls that finished
+    used_ns: AtomicU64,
+    // The thread's clock when the poll in progress started, 0 between polls
+    slice_started_ns: AtomicU64,
+}
+
+impl CpuTime {
+    pub fn new(budget: Duration) -> Arc<Self> {
+        Arc::new(CpuTime {
+            budget_ns: budget.as_nanos() as u64,
+            used_ns: AtomicU64::new(0),
+            slice_started_ns: AtomicU64::new(0),
+        })
+    }
+
+    pub fn used(&self) -> Duration {
+        Duration::from_nanos(self.used_ns.load(Ordering::Relaxed))
+    }
+
+    // Called on the thread running the execution, from the interrupt handler
+    pub fn exceeded(&self) -> bool {
+        let mut used = self.used_ns.load(Ordering::Relaxed);
+        let started = self.slice_started_ns.load(Ordering::Relaxed);
+        if started > 0 {
+            used += thread_cpu_ns().saturating_sub(started);
+        }
+        used > self.budget_ns
+    }
+
+    // `future`, with the CPU time of its polls added up
+    pub fn measure<F: Future>(self: &Arc<Self>, future: F) -> impl Future<Output = F::Output> {
+        let cpu = self.clone();
+        let mut future = Box::pin(future);
+        std::future::poll_fn(move |cx| {
+            let started = thread_cpu_ns().max(1);
+            cpu.slice_started_ns.store(started, Ordering::Relaxed);
+            let poll: Poll<F::Output> = future.as_mut().poll(cx);
+            cpu.slice_started_ns.store(0, Ordering::Relaxed);
+            cpu.used_ns.fetch_add(thread_cpu_ns().saturating_sub(started), Ordering::Relaxed);
+            poll
+        })
+    }
+}
+
+// CPU time of the calling thread
+#[cfg(unix)]
+fn thread_cpu_ns() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: clock_gettime only writes to the timespec it is given
+    let status = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut now) };
+    if status != 0 {
+        return 0;
+    }
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+// Elsewhere the wall-clock time of the polls stands in for their CPU time
+#[cfg(not(unix))]
+fn thread_cpu_ns() -> u64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64 + 1
+}
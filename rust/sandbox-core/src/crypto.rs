@@ -0,0 +1,115 @@
+// Native hashing helpers installed as the `crypto` global.
+//
+// Payloads are UTF-8 strings, or base64 with `{ inputEncoding: "base64" }`, and HMAC
+// keys likewise with `keyEncoding`, which defaults to the `inputEncoding`. Digests
+// are lowercase hex, or base64 with `{ encoding: "base64" }`.
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use rquickjs::function::{Func, Opt};
+use rquickjs::{Ctx, Exception, Function, Object, Result};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+struct Encodings {
+    input_base64: bool,
+    key_base64: bool,
+    output_base64: bool,
+}
+
+impl Encodings {
+    fn from_options(ctx: &Ctx<'_>, options: &Opt<Object<'_>>) -> Result<Self> {
+        let field = |name: &str| -> Result<Option<String>> {
+            match &options.0 {
+                Some(options) => options.get(name),
+                None => Ok(None),
+            }
+        };
+
+        let base64 = |name: &str, default: bool| -> Result<bool> {
+            match field(name)?.as_deref() {
+                None => Ok(default),
+                Some("utf8") => Ok(false),
+                Some("base64") => Ok(true),
+                Some(other) => Err(Exception::throw_type(
+                    ctx,
+                    &format!("Unsupported {} '{}', expected \"utf8\" or \"base64\"", name, other),
+                )),
+            }
+        };
+        let input_base64 = base64("inputEncoding", false)?;
+        let key_base64 = base64("keyEncoding", input_base64)?;
+        let output_base64 = match field("encoding")?.as_deref() {
+            None | Some("hex") => false,
+            Some("base64") => true,
+            Some(other) => {
+                return Err(Exception::throw_type(
+                    ctx,
+                    &format!("Unsupported encoding '{}', expected \"hex\" or \"base64\"", other),
+                ))
+            }
+        };
+
+        Ok(Encodings {
+            input_base64,
+            key_base64,
+            output_base64,
+        })
+    }
+
+    fn decode(&self, ctx: &Ctx<'_>, data: &str) -> Result<Vec<u8>> {
+        decode(ctx, data, self.input_base64, "input")
+    }
+
+    fn decode_key(&self, ctx: &Ctx<'_>, key: &str) -> Result<Vec<u8>> {
+        decode(ctx, key, self.key_base64, "key")
+    }
+
+    fn encode(&self, digest: &[u8]) -> String {
+        if self.output_base64 {
+            BASE64_STANDARD.encode(digest)
+        } else {
+            digest.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+    }
+}
+
+fn decode(ctx: &Ctx<'_>, data: &str, base64: bool, what: &str) -> Result<Vec<u8>> {
+    if !base64 {
+        return Ok(data.as_bytes().to_vec());
+    }
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(|e| Exception::throw_type(ctx, &format!("Invalid base64 {}: {}", what, e)))
+}
+
+fn digest<D: Digest>(ctx: Ctx<'_>, data: String, options: Opt<Object<'_>>) -> Result<String> {
+    let encodings = Encodings::from_options(&ctx, &options)?;
+    let data = encodings.decode(&ctx, &data)?;
+    Ok(encodings.encode(&D::digest(data)))
+}
+
+fn hmac_sha256(ctx: Ctx<'_>, key: String, data: String, options: Opt<Object<'_>>) -> Result<String> {
+    let encodings = Encodings::from_options(&ctx, &options)?;
+    let key = encodings.decode_key(&ctx, &key)?;
+    let data = encodings.decode(&ctx, &data)?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts keys of any length");
+    mac.update(&data);
+    Ok(encodings.encode(&mac.finalize().into_bytes()))
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    let crypto = Object::new(ctx.clone())?;
+    crypto.set("sha256", Func::from(digest::<Sha256>))?;
+    crypto.set("sha1", Func::from(digest::<Sha1>))?;
+    crypto.set("md5", Func::from(digest::<Md5>))?;
+    crypto.set("hmacSha256", Func::from(hmac_sha256))?;
+    crypto.set("randomUUID", Func::from(|| uuid::Uuid::new_v4().to_string()))?;
+
+    let freeze: Function = ctx.globals().get::<_, Object>("Object")?.get("freeze")?;
+    freeze.call::<_, ()>((crypto.clone(),))?;
+    ctx.globals().set("crypto", crypto)?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+// Dry runs, which show what code would send without it touching the network.
+//
+// The code runs as usual, except that httpRequest calls are recorded instead of
+// sent, and each is answered with a placeholder: status 200, no headers and `{}` as
+// data. Headers named in MASKED_HEADERS are reported as MASKED. A request
+// made after the script received a placeholder may have been built from it, e.g.
+// from an id in an earlier response, and is marked `dependent`: its URL, headers
+// and body are only what the placeholders made them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::fetch::{request_url, FromCache, HttpResult, Timing};
+use crate::secrets::Secrets;
+
+pub const MASKED: &str = "***MASKED***";
+
+// Bytes of a request body that are reported
+const BODY_PREVIEW_BYTES: usize = 1024;
+
+// One httpRequest call of a dry run
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedRequest {
+    pub url: String,
+    pub method: String,
+    pub headers: BTreeMap<String, String>,
+    // Start of the body, null without one
+    pub body_preview: Option<String>,
+    pub dependent: bool,
+}
+
+impl PlannedRequest {
+    pub fn redact(&mut self, secrets: &Secrets) {
+        secrets.redact_in_place(&mut self.url);
+        for value in self.headers.values_mut() {
+            secrets.redact_in_place(value);
+        }
+        if let Some(body) = &mut self.body_preview {
+            secrets.redact_in_place(body);
+        }
+    }
+}
+
+pub struct DryRun {
+    // Lowercase
+    masked_headers: Vec<String>,
+    requests: Mutex<Vec<PlannedRequest>>,
+}
+
+impl DryRun {
+    pub fn new(masked_headers: &[String]) -> Self {
+        DryRun {
+            masked_headers: masked_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Records the request and answers it with the placeholder
+    pub fn plan(&self, url: String, options: Option<&HashMap<String, Value>>, dependent: bool) -> HttpResult {
+        let option = |name: &str| options.and_then(|options| options.get(name));
+        let url = request_url(url, options);
+        let method = option("method").and_then(Value::as_str).unwrap_or("GET").to_ascii_uppercase();
+        let headers = masked_headers(options, &self.masked_headers);
+        let body_preview = match option("body").or_else(|| option("multipart")) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(body)) => Some(preview(body)),
+            Some(body) => Some(preview(&body.to_string())),
+        };
+        self.requests.lock().unwrap().push(PlannedRequest {
+            url: url.clone(),
+            method,
+            headers,
+            body_preview,
+            dependent,
+        });
+
+        HttpResult {
+            ok: true,
+            status: 200,
+            status_text: "OK".to_string(),
+            headers: BTreeMap::new(),
+            raw_headers: Vec::new(),
+            data: Value::Object(Default::default()),
+            text: "{}".to_string(),
+            json_parse_error: None,
+            content_type: None,
+            content_length: None,
+            final_url: Some(url),
+            timing: Timing::default(),
+            redirects: Vec::new(),
+            attempts: 0,
+            set_cookies: Vec::new(),
+            from_cache: FromCache::No,
+            error_code: None,
+            stripped_headers: Vec::new(),
+            retry_after_ms: None,
+        }
+    }
+
+    // In the order they were made
+    pub fn requests(&self) -> Vec<PlannedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// The headers the script set, with those in `masked` (lowercase) masked
+pub(crate) fn masked_headers(options: Option<&HashMap<String, Value>>, masked: &[String]) -> BTreeMap<String, String> {
+    let option = |name: &str| options.and_then(|options| options.get(name));
+    let mut headers: BTreeMap<String, String> = option("headers")
+        .and_then(Value::as_object)
+        .map(|headers| {
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        _ if masked.contains(&name.to_ascii_lowercase()) => MASKED.to_string(),
+                        Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    (name.clone(), value)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // `auth` turns into an Authorization header, unless the script set one
+    if option("auth").is_some() && !headers.keys().any(|name| name.eq_ignore_ascii_case("authorization")) {
+        headers.insert("authorization".to_string(), MASKED.to_string());
+    }
+    headers
+}
+
+fn preview(body: &str) -> String {
+    let mut end = BODY_PREVIEW_BYTES.min(body.len());
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    body[..end].to_string()
+}
@@ -0,0 +1,284 @@
+// Native binary encoding helpers: the `btoa`/`atob` globals, the `base64` object and
+// the `TextEncoder`/`TextDecoder` classes.
+//
+// `btoa`/`atob` follow browser semantics and work on latin-1 strings. The `base64`
+// helpers are binary safe: they accept strings (encoded as UTF-8), Uint8Arrays,
+// ArrayBuffers or arrays of byte values.
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine as _;
+use rquickjs::class::Trace;
+use rquickjs::function::{Func, Opt, This};
+use rquickjs::{Array, Class, Coerced, Ctx, Exception, Function, JsLifetime, Object, Result, TypedArray, Value};
+
+// Standard alphabet, padding optional when decoding (forgiving-base64)
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+fn invalid_character(ctx: &Ctx<'_>, message: &str) -> rquickjs::Error {
+    match Exception::from_message(ctx.clone(), message) {
+        Ok(exception) => {
+            if let Err(e) = exception.as_object().set("name", "InvalidCharacterError") {
+                return e;
+            }
+            exception.throw()
+        }
+        Err(e) => e,
+    }
+}
+
+// Rust string of a JavaScript string. Lone surrogates can't be represented in UTF-8
+// and are replaced with U+FFFD, as `TextEncoder` does
+fn utf8_string<'js>(ctx: &Ctx<'js>, string: &rquickjs::String<'js>) -> Result<String> {
+    match string.to_string() {
+        Err(rquickjs::Error::Utf8(_)) => {
+            let prototype: Object = ctx.globals().get::<_, Object>("String")?.get("prototype")?;
+            let to_well_formed: Function = prototype.get("toWellFormed")?;
+            let well_formed: rquickjs::String = to_well_formed.call((This(string.clone()),))?;
+            well_formed.to_string()
+        }
+        result => result,
+    }
+}
+
+// Bytes of a string (as UTF-8), Uint8Array, ArrayBuffer or array of byte values
+pub fn bytes_from_value<'js>(ctx: &Ctx<'js>, value: &Value<'js>) -> Result<Vec<u8>> {
+    match value.as_string() {
+        Some(string) => Ok(utf8_string(ctx, string)?.into_bytes()),
+        None => binary_from_value(ctx, value),
+    }
+}
+
+// Bytes of a Uint8Array, ArrayBuffer or array of byte values
+pub fn binary_from_value(ctx: &Ctx<'_>, value: &Value<'_>) -> Result<Vec<u8>> {
+    if let Some(object) = value.as_object() {
+        if let Some(bytes) = object.as_typed_array::<u8>() {
+            return Ok(AsRef::<[u8]>::as_ref(&bytes).to_vec());
+        }
+        if let Some(buffer) = object.as_array_buffer() {
+            return Ok(buffer.as_bytes().unwrap_or_default().to_vec());
+        }
+        if let Some(array) = object.as_array() {
+            return array
+                .iter::<Value>()
+                .map(|item| {
+                    item?
+                        .as_number()
+                        .filter(|n| n.fract() == 0.0 && (0.0..=255.0).contains(n))
+                        .map(|n| n as u8)
+                        .ok_or_else(|| Exception::throw_type(ctx, "Byte arrays may only contain integers from 0 to 255"))
+                })
+                .collect();
+        }
+    }
+    Err(Exception::throw_type(
+        ctx,
+        if value.is_string() {
+            "Expected a Uint8Array, ArrayBuffer or array of bytes, not a string"
+        } else {
+            "Expected a string, Uint8Array, ArrayBuffer or array of bytes"
+        },
+    ))
+}
+
+fn btoa(ctx: Ctx<'_>, data: Coerced<String>) -> Result<String> {
+    let bytes = data
+        .0
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid_character(&ctx, "btoa: the string contains characters outside of the Latin1 range"))?;
+    Ok(BASE64.encode(bytes))
+}
+
+fn atob(ctx: Ctx<'_>, data: Coerced<String>) -> Result<String> {
+    let compact: String = data.0.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let bytes = BASE64
+        .decode(compact)
+        .map_err(|_| invalid_character(&ctx, "atob: the string to be decoded is not correctly encoded"))?;
+    Ok(bytes.into_iter().map(char::from).collect())
+}
+
+fn base64_encode<'js>(ctx: Ctx<'js>, data: Value<'js>) -> Result<String> {
+    Ok(BASE64.encode(bytes_from_value(&ctx, &data)?))
+}
+
+// `base64.decode(text, { output: "bytes" })` returns an array of byte values,
+// otherwise the bytes are decoded as UTF-8, and must be valid UTF-8
+fn base64_decode<'js>(ctx: Ctx<'js>, data: Coerced<String>, options: Opt<Object<'js>>) -> Result<Value<'js>> {
+    let compact: String = data.0.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let bytes = BASE64
+        .decode(compact)
+        .map_err(|e| Exception::throw_type(&ctx, &format!("Invalid base64 input: {}", e)))?;
+
+    let output: Option<String> = match &options.0 {
+        Some(options) => options.get("output")?,
+        None => None,
+    };
+    match output.as_deref() {
+        None | Some("string") => {
+            let text = String::from_utf8(bytes).map_err(|_| {
+                Exception::throw_type(&ctx, "base64.decode: the data is not valid UTF-8, use { output: \"bytes\" } for binary data")
+            })?;
+            Ok(rquickjs::String::from_str(ctx, &text)?.into_value())
+        }
+        Some("bytes") => {
+            let array = Array::new(ctx)?;
+            for (index, byte) in bytes.into_iter().enumerate() {
+                array.set(index, byte)?;
+            }
+            Ok(array.into_value())
+        }
+        Some(other) => Err(Exception::throw_type(
+            &ctx,
+            &format!("Unsupported output '{}', expected \"string\" or \"bytes\"", other),
+        )),
+    }
+}
+
+#[derive(Trace, JsLifetime)]
+#[rquickjs::class]
+pub struct TextEncoder {}
+
+#[rquickjs::methods]
+impl TextEncoder {
+    #[qjs(constructor)]
+    pub fn new() -> Self {
+        TextEncoder {}
+    }
+
+    #[qjs(get)]
+    pub fn encoding(&self) -> &'static str {
+        "utf-8"
+    }
+
+    pub fn encode<'js>(&self, ctx: Ctx<'js>, input: Opt<Coerced<rquickjs::String<'js>>>) -> Result<TypedArray<'js, u8>> {
+        let text = match &input.0 {
+            Some(input) => utf8_string(&ctx, &input.0)?,
+            None => String::new(),
+        };
+        TypedArray::new(ctx, text.into_bytes())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TextEncoding {
+    Utf8,
+    // Bytes map to U+0000 - U+00FF one to one
+    Latin1,
+}
+
+impl TextEncoding {
+    fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" => Some(TextEncoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "l1" | "ascii" | "us-ascii" => {
+                Some(TextEncoding::Latin1)
+            }
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf-8",
+            TextEncoding::Latin1 => "iso-8859-1",
+        }
+    }
+}
+
+#[derive(Trace, JsLifetime)]
+#[rquickjs::class]
+pub struct TextDecoder {
+    #[qjs(skip_trace)]
+    encoding: TextEncoding,
+    fatal: bool,
+    ignore_bom: bool,
+}
+
+#[rquickjs::methods]
+impl TextDecoder {
+    // `new TextDecoder(label = "utf-8", { fatal = false, ignoreBOM = false })`
+    #[qjs(constructor)]
+    pub fn new<'js>(ctx: Ctx<'js>, label: Opt<Coerced<String>>, options: Opt<Object<'js>>) -> Result<Self> {
+        let encoding = match &label.0 {
+            Some(label) => TextEncoding::from_label(&label.0).ok_or_else(|| {
+                Exception::throw_range(
+                    &ctx,
+                    &format!("Unsupported encoding '{}', expected \"utf-8\" or \"latin1\"", label.0),
+                )
+            })?,
+            None => TextEncoding::Utf8,
+        };
+        let flag = |key: &str| -> Result<bool> {
+            match &options.0 {
+                Some(options) => Ok(options.get::<_, Option<bool>>(key)?.unwrap_or(false)),
+                None => Ok(false),
+            }
+        };
+        Ok(TextDecoder {
+            encoding,
+            fatal: flag("fatal")?,
+            ignore_bom: flag("ignoreBOM")?,
+        })
+    }
+
+    #[qjs(get)]
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    #[qjs(get)]
+    pub fn fatal(&self) -> bool {
+        self.fatal
+    }
+
+    #[qjs(get, rename = "ignoreBOM")]
+    pub fn ignore_bom(&self) -> bool {
+        self.ignore_bom
+    }
+
+    pub fn decode<'js>(&self, ctx: Ctx<'js>, input: Opt<Value<'js>>) -> Result<String> {
+        let bytes = match &input.0 {
+            Some(input) if !input.is_undefined() => binary_from_value(&ctx, input)?,
+            _ => Vec::new(),
+        };
+        match self.encoding {
+            TextEncoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            TextEncoding::Utf8 => {
+                let bytes = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+                    Some(rest) if !self.ignore_bom => rest,
+                    _ => &bytes[..],
+                };
+                if self.fatal {
+                    std::str::from_utf8(bytes)
+                        .map(str::to_string)
+                        .map_err(|_| Exception::throw_type(&ctx, "The encoded data is not valid utf-8"))
+                } else {
+                    Ok(String::from_utf8_lossy(bytes).into_owned())
+                }
+            }
+        }
+    }
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    let globals = ctx.globals();
+    globals.set("btoa", Func::from(btoa))?;
+    globals.set("atob", Func::from(atob))?;
+
+    let base64 = Object::new(ctx.clone())?;
+    base64.set("encode", Func::from(base64_encode))?;
+    base64.set("decode", Func::from(base64_decode))?;
+    let freeze: Function = globals.get::<_, Object>("Object")?.get("freeze")?;
+    freeze.call::<_, ()>((base64.clone(),))?;
+    globals.set("base64", base64)?;
+
+    Class::<TextEncoder>::define(&globals)?;
+    Class::<TextDecoder>::define(&globals)?;
+    Ok(())
+}
+
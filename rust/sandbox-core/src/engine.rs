@@ -0,0 +1,906 @@
+// Evaluation of user code in a QuickJS context.
+//
+// A context gets INPUTS, ENV, SECRETS, FILES and the sandbox globals (httpRequest, fetch,
+// `console`, the clock, timers, crypto, encoding, URL, structuredClone, jsonpath,
+// parseXml, parseYaml and the utils library) before the code runs as an async script or an
+// ES module. The result is serialized to JSON inside the context, so values that
+// can't be represented are reported as errors instead of silently dropped.
+
+use rquickjs::{AsyncContext, AsyncRuntime, Ctx, Module, TypedArray, async_with, function::{Args, Func, Async, Opt, This}};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::cancel::Cancellation;
+use crate::clock::ExecutionClock;
+use crate::code_cache::{self, CodeCache};
+use crate::error::{ErrorKind, ExecError};
+use crate::executor::Limit;
+use crate::fetch::{error_codes_js, Chunk, FetchSession, HttpBackend};
+use crate::files::{self, Files};
+use crate::inputs;
+use crate::js_error::{self, USER_CODE_FILENAME};
+use crate::metrics::METRICS;
+use crate::modules::{ModuleMap, SandboxModules};
+use crate::prelude_scripts::PreludeScripts;
+use crate::secrets::Secrets;
+use crate::serialize::{self, BigIntMode};
+use crate::state::{self, StateSession};
+use crate::timers::{self, SleepBudget};
+use crate::surface;
+use crate::{clone, crypto, encoding, jsonpath, random, urls, xml, yaml};
+
+// How much of an oversized result is echoed back with `debug`
+const RESULT_PREVIEW_BYTES: usize = 1024;
+
+// Promise rejections nobody handled, e.g. a failing `httpRequest(...).then(...)` chain
+// that was never awaited. Keyed by promise identity so rejections that get a
+// handler later are dropped again.
+#[derive(Clone, Default)]
+pub struct RejectionLog {
+    pending: Arc<Mutex<Vec<(u64, String)>>>,
+}
+
+impl RejectionLog {
+    pub fn tracker(&self) -> rquickjs::runtime::RejectionTracker {
+        let pending = self.pending.clone();
+        Box::new(move |_ctx, promise, reason, is_handled| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            promise.hash(&mut hasher);
+            let id = hasher.finish();
+            
+            let mut pending = pending.lock().unwrap();
+            if is_handled {
+                pending.retain(|(pending_id, _)| *pending_id != id);
+            } else {
+                pending.push((id, describe_rejection(&reason)));
+            }
+        })
+    }
+    
+    pub fn messages(&self) -> Vec<String> {
+        self.pending.lock().unwrap().iter().map(|(_, message)| message.clone()).collect()
+    }
+}
+
+// Error objects are described by their stack when available, anything else by its string value
+fn describe_rejection(reason: &rquickjs::Value) -> String {
+    let text = reason
+        .get::<rquickjs::Coerced<String>>()
+        .map(|s| s.0)
+        .unwrap_or_else(|_| "<unprintable rejection>".to_string());
+    let stack = reason
+        .as_object()
+        .and_then(|obj| obj.get::<_, Option<String>>("stack").ok().flatten());
+    match stack {
+        Some(stack) if !stack.is_empty() => format!("{}\n{}", text, js_error::rewrite_locations(stack.trim_end()).0),
+        _ => text,
+    }
+}
+
+// How the code is evaluated
+pub struct ExecutionOptions {
+    pub module: bool,
+    pub bigint_mode: BigIntMode,
+    // The function whose return value is the result, instead of the code's value
+    pub entrypoint: Option<Entrypoint>,
+    // Modules the code can import, the code being the entry module
+    pub modules: Option<Arc<ModuleMap>>,
+    pub max_result_bytes: Limit<usize>,
+    // Inputs with more bytes of JSON are read lazily; 0 always materializes them
+    pub lazy_inputs_bytes: usize,
+    pub cancellation: Arc<Cancellation>,
+    pub disable_dynamic_eval: bool,
+    // httpRequest, fetch and graphql throw NetworkDisabledError, and the host
+    // function behind them isn't installed
+    pub disable_network: bool,
+    pub clock: ExecutionClock,
+    pub random_seed: u32,
+    pub code_cache: Arc<CodeCache>,
+    // Set once the code was loaded from the code cache
+    pub code_cache_hit: Arc<AtomicBool>,
+    // Receives `console` output
+    pub console: ConsoleSink,
+    pub secrets: Arc<Secrets>,
+    pub files: Arc<Files>,
+    // SANDBOX_ENV, the same for every execution
+    pub env: Arc<BTreeMap<String, String>>,
+    // The `state` global, left out when None
+    pub state: Option<Arc<StateSession>>,
+    // What is left of SLEEP_BUDGET_MS for sleep() and setTimeout
+    pub sleep_budget: Arc<SleepBudget>,
+    // Evaluated before user code, left out when None
+    pub prelude_scripts: Option<Arc<PreludeScripts>>,
+}
+
+// A function for the code to define: a global of a script, or an export of a module
+#[derive(Clone, Debug)]
+pub struct Entrypoint {
+    pub name: String,
+    // Given to the function as JSON values, `[INPUTS]` when None
+    pub args: Option<Vec<Value>>,
+}
+
+// Called with the level (`log`, `warn`, ...) and the formatted message of every
+// `console` call
+pub type ConsoleSink = Arc<dyn Fn(String, String) + Send + Sync>;
+
+// Execute JavaScript code with QuickJS - true single pass with async HTTP execution
+pub async fn execute_js_with_quickjs(
+    runtime: &AsyncRuntime,
+    code: &str,
+    inputs: &Value,
+    http: Arc<dyn HttpBackend>,
+    session: Arc<FetchSession>,
+    rejections: &RejectionLog,
+    options: &ExecutionOptions,
+) -> std::result::Result<Value, ExecError> {
+    let json = execute_js_to_json(runtime, code, inputs, http, session, rejections, options).await?;
+    serde_json::from_str(&json).map_err(|e| e.to_string().into())
+}
+
+// The same, with the result as the JSON text QuickJS produced
+pub async fn execute_js_to_json(
+    runtime: &AsyncRuntime,
+    code: &str,
+    inputs: &Value,
+    http: Arc<dyn HttpBackend>,
+    session: Arc<FetchSession>,
+    rejections: &RejectionLog,
+    options: &ExecutionOptions,
+) -> std::result::Result<String, ExecError> {
+    runtime.set_host_promise_rejection_tracker(Some(rejections.tracker())).await;
+    runtime.set_interrupt_handler(Some(options.cancellation.interrupt_handler())).await;
+    let modules = SandboxModules::new(options.modules.clone());
+    runtime.set_loader(modules.clone(), modules.clone()).await;
+    let context = create_context(runtime, inputs, http, session, options).await?;
+    evaluate_to_json(&context, code, &modules, options).await
+}
+
+// A context with INPUTS and the sandbox globals installed
+pub async fn create_context(
+    runtime: &AsyncRuntime,
+    inputs: &Value,
+    http: Arc<dyn HttpBackend>,
+    session: Arc<FetchSession>,
+    options: &ExecutionOptions,
+) -> std::result::Result<AsyncContext, ExecError> {
+    let context = AsyncContext::full(runtime).await.map_err(|e| format!("Context error: {}", e))?;
+    
+    // Inject INPUTS, whatever JSON value it is, with getInput and friends
+    context.with(|ctx| {
+        inputs::install(&ctx, inputs, options.lazy_inputs_bytes)
+            .map_err(|e| format!("INPUTS injection error: {}", e))
+    }).await?;
+    
+    // ENV and SECRETS can be neither replaced nor changed
+    let env_json = serde_json::to_string(&*options.env).map_err(|e| e.to_string())?;
+    let frozen_js = format!(
+        "Object.defineProperty(globalThis, 'ENV', {{ value: Object.freeze({}) }});\
+         Object.defineProperty(globalThis, 'SECRETS', {{ value: Object.freeze({}) }});",
+        env_json,
+        options.secrets.to_json()
+    );
+    context.with(|ctx| {
+        ctx.eval::<(), _>(frozen_js)
+            .map_err(|_| "ENV and SECRETS injection error".to_string())
+    }).await?;
+    
+    // Register async httpRequest function using Func::from(Async(...))
+    async_with!(context => |ctx| {
+        if !options.disable_network {
+            install_streams(&ctx, http.clone(), session.clone())
+                .map_err(|e| format!("Failed to set httpGetStream: {:?}", e))?;
+        }
+        
+        // Requests started with an AbortSignal, by the id the prelude gave them
+        let aborts: Arc<Mutex<HashMap<u32, Arc<Notify>>>> = Arc::default();
+        
+        // Called from JavaScript with the options already serialized, so the closure
+        // only deals in owned strings and can hold on to the shared HTTP clients
+        let pending = aborts.clone();
+        let http_request_impl = move |url: String, options_json: String, abort_id: Opt<u32>| {
+            // Parse options from JSON string
+            let opts: Option<HashMap<String, Value>> = serde_json::from_str(&options_json).ok();
+            let abort = abort_id.0.map(|id| pending.lock().unwrap().entry(id).or_default().clone());
+            let request = session.request(http.clone(), url, opts, abort);
+            let pending = pending.clone();
+            async move {
+                // Perform the HTTP request
+                let result = request.await;
+                if let Some(id) = abort_id.0 {
+                    pending.lock().unwrap().remove(&id);
+                }
+                
+                // Return the result as JSON string
+                Ok::<String, rquickjs::Error>(serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string()))
+            }
+        };
+        
+        // Register the async function using Func::from(Async(...))
+        if !options.disable_network {
+            ctx.globals().set("__httpRequestAsync", Func::from(Async(http_request_impl)))
+                .map_err(|e| format!("Failed to set httpRequest: {:?}", e))?;
+        }
+        // Drops a request that hasn't finished yet
+        ctx.globals().set("__httpAbort", Func::from(move |id: u32| {
+            if let Some(abort) = aborts.lock().unwrap().get(&id) {
+                abort.notify_one();
+            }
+        }))
+            .map_err(|e| format!("Failed to set httpRequest: {:?}", e))?;
+        
+        let console = options.console.clone();
+        ctx.globals().set("__console", Func::from(move |level: String, message: String| console(level, message)))
+            .map_err(|e| format!("Failed to set console: {:?}", e))?;
+        code_cache::evaluate_prelude(&ctx, "console.js", include_str!("js/console.js"))
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create console: {:?}", e))?;
+        
+        // Error code taxonomy for HttpResult.errorCode
+        Module::evaluate(ctx.clone(), "http_constants.js", error_codes_js())
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create Http constants: {:?}", e))?;
+        
+        // Create a JavaScript wrapper that parses the JSON result. Loaded as a module so
+        // its stack frames are never mistaken for user code.
+        code_cache::evaluate_prelude(&ctx, "prelude.js", r#"
+            const httpAbort = globalThis.__httpAbort;
+            delete globalThis.__httpAbort;
+            let nextAbortId = 1;
+            
+            // What a request aborted by `signal` rejects with: the signal's own
+            // AbortError or TimeoutError, or an AbortError carrying its `reason`
+            const abortError = (signal) => {
+                const reason = signal.reason;
+                if (reason instanceof DOMException && (reason.name === "AbortError" || reason.name === "TimeoutError")) {
+                    return reason;
+                }
+                const error = new DOMException(`The request was aborted: ${String(reason)}`, "AbortError");
+                error.reason = reason;
+                return error;
+            };
+            
+            // Runs `send` with an abort id, rejecting as soon as `signal` aborts, which
+            // also has the host drop the request
+            const abortable = (signal, send) => {
+                if (signal.aborted) {
+                    return Promise.reject(abortError(signal));
+                }
+                const id = nextAbortId++;
+                return new Promise((resolve, reject) => {
+                    const onAbort = () => {
+                        httpAbort(id);
+                        reject(abortError(signal));
+                    };
+                    signal.addEventListener("abort", onAbort, { once: true });
+                    send(id).then(resolve, reject).finally(() => signal.removeEventListener("abort", onAbort));
+                });
+            };
+            
+            // Thrown for requests the script can't carry on from, and with
+            // `throwOnError` for every failed request
+            globalThis.HttpError = class HttpError extends Error {
+                constructor(message, url, options, result) {
+                    super(message);
+                    this.name = "HttpError";
+                    this.url = url;
+                    this.options = options;
+                    // The errorCode, or "http_status" for a response that isn't 2xx
+                    this.reason = result.errorCode ?? "http_status";
+                    this.status = result.status;
+                    this.statusText = result.statusText;
+                    this.response = result;
+                }
+            };
+            
+            globalThis.httpRequest = async function httpRequest(url, options) {
+                // Also takes a URL instance
+                url = String(url);
+                const { signal, ...rest } = options || {};
+                const resultJson = signal === undefined || signal === null
+                    ? await __httpRequestAsync(url, JSON.stringify(rest))
+                    : await abortable(signal, (id) => __httpRequestAsync(url, JSON.stringify(rest), id));
+                const result = JSON.parse(resultJson);
+                // Stop the script instead of letting it keep calling past the limit, or
+                // carry on without the response it expected
+                if (result.errorCode === Http.ErrorCode.REQUEST_LIMIT_EXCEEDED
+                    || result.errorCode === Http.ErrorCode.UNMATCHED_REQUEST) {
+                    throw new HttpError(result.data, url, options, result);
+                }
+                if (options?.throwOnError && !result.ok) {
+                    const method = String(options.method ?? "GET").toUpperCase();
+                    const message = result.errorCode
+                        ? `${method} ${url} failed: ${result.data}`
+                        : `${method} ${url} returned ${result.status} ${result.statusText}`;
+                    throw new HttpError(message, url, options, result);
+                }
+                return result;
+            };
+            
+            // Thrown by the request functions of executions without network access
+            globalThis.NetworkDisabledError = class NetworkDisabledError extends Error {
+                constructor(message) {
+                    super(message);
+                    this.name = "NetworkDisabledError";
+                }
+            };
+            
+            // Thrown by `graphql` for a response with a non-empty `errors` array
+            globalThis.GraphQLError = class GraphQLError extends HttpError {
+                constructor(message, url, options, result) {
+                    super(message, url, options, result);
+                    this.name = "GraphQLError";
+                    this.reason = "graphql";
+                    this.errors = result.data.errors;
+                    // Whatever the server resolved despite the errors
+                    this.data = result.data.data ?? null;
+                }
+            };
+            
+            // A GraphQL POST, with the response's `data` on success
+            globalThis.graphql = async function graphql(url, query, variables, options) {
+                const { operationName, ...rest } = options || {};
+                const body = { query, variables: variables ?? {} };
+                if (operationName !== undefined) body.operationName = operationName;
+                // Headers the script set, in any case, win over the defaults
+                const headers = { ...rest.headers };
+                const names = Object.keys(headers).map((name) => name.toLowerCase());
+                for (const name of ["Content-Type", "Accept"]) {
+                    if (!names.includes(name.toLowerCase())) headers[name] = "application/json";
+                }
+                const request = {
+                    ...rest,
+                    method: "POST",
+                    headers,
+                    body: JSON.stringify(body),
+                    throwOnError: false,
+                };
+                const result = await httpRequest(url, request);
+                const errors = result.data?.errors;
+                if (Array.isArray(errors) && errors.length > 0) {
+                    const messages = errors.map((error) => Array.isArray(error?.path)
+                        ? `${error.message} (at ${error.path.join(".")})`
+                        : String(error?.message ?? error));
+                    throw new GraphQLError(`GraphQL request to ${url} failed: ${messages.join("; ")}`, url, request, result);
+                }
+                if (!result.ok) {
+                    const message = result.errorCode
+                        ? `POST ${url} failed: ${result.data}`
+                        : `POST ${url} returned ${result.status} ${result.statusText}`;
+                    throw new HttpError(message, url, request, result);
+                }
+                return { data: result.data?.data ?? null, response: result };
+            };
+            
+            const streamNext = globalThis.__httpStreamNext;
+            const streamClose = globalThis.__httpStreamClose;
+            delete globalThis.__httpStreamNext;
+            delete globalThis.__httpStreamClose;
+            
+            // Hands the body to `onChunk` a chunk at a time, awaiting it before the
+            // next one is downloaded, and resolves to the response without a body
+            globalThis.httpGetStream = async function httpGetStream(url, options, onChunk) {
+                if (typeof options === "function") {
+                    [options, onChunk] = [undefined, options];
+                }
+                if (typeof onChunk !== "function") {
+                    throw new TypeError("httpGetStream: onChunk must be a function");
+                }
+                url = String(url);
+                const { signal, ...rest } = options || {};
+                signal?.throwIfAborted();
+                const { result, stream } = JSON.parse(await __httpStreamAsync(url, JSON.stringify(rest)));
+                const { data, text, jsonParseError, ...response } = result;
+                if (response.errorCode) {
+                    return { ...response, bytesRead: 0, error: data };
+                }
+                let bytesRead, failure;
+                try {
+                    let chunk;
+                    while ((chunk = await streamNext(stream)) !== null) {
+                        signal?.throwIfAborted();
+                        await onChunk(chunk);
+                    }
+                } finally {
+                    [bytesRead, failure] = JSON.parse(streamClose(stream));
+                }
+                if (failure) {
+                    return { ...response, ok: false, errorCode: failure.errorCode, error: failure.data, bytesRead };
+                }
+                return { ...response, bytesRead };
+            };
+            
+            // All values of a response header, case-insensitive
+            globalThis.headersGet = function headersGet(result, name) {
+                const wanted = String(name).toLowerCase();
+                return (result.rawHeaders || [])
+                    .filter(([key]) => key.toLowerCase() === wanted)
+                    .map(([, value]) => value);
+            };
+        "#)
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create httpRequest wrapper: {:?}", e))?;
+        
+        // fetch() and Headers over httpRequest
+        code_cache::evaluate_prelude(&ctx, "fetch.js", include_str!("js/fetch.js"))
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create fetch: {:?}", e))?;
+        
+        // Fail before anything is sent, in place of the functions that would send it
+        if options.disable_network {
+            code_cache::evaluate_prelude(&ctx, "network_disabled.js", r#"
+                for (const name of ["httpRequest", "httpGetStream", "fetch", "graphql"]) {
+                    const stub = async function () {
+                        throw new NetworkDisabledError(`${name} is disabled: this execution has no network access`);
+                    };
+                    globalThis[name] = Object.defineProperty(stub, "name", { value: name });
+                }
+            "#)
+                .and_then(|promise| promise.finish::<()>())
+                .map_err(|e| format!("Failed to disable the network: {:?}", e))?;
+        }
+        
+        // EXECUTION_TIME, and the frozen clock with freeze_time
+        Module::evaluate(ctx.clone(), "clock.js", options.clock.js())
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create clock: {:?}", e))?;
+        random::install(&ctx, options.random_seed)
+            .map_err(|e| format!("Failed to seed Math.random: {:?}", e))?;
+        
+        // Uploaded files, as the frozen FILES
+        files::install(&ctx, &options.files).map_err(|e| format!("FILES injection error: {:?}", e))?;
+        
+        // Hashing helpers, available as the `crypto` global
+        crypto::install(&ctx).map_err(|e| format!("Failed to create crypto: {:?}", e))?;
+        
+        // btoa/atob and the binary safe `base64` helpers
+        encoding::install(&ctx).map_err(|e| format!("Failed to create base64 helpers: {:?}", e))?;
+        
+        // sleep(), setTimeout, clearTimeout and AbortController
+        timers::install(&ctx, options.sleep_budget.clone()).map_err(|e| format!("Failed to create timers: {:?}", e))?;
+        
+        // Deep copies, as the `structuredClone` global
+        clone::install(&ctx).map_err(|e| format!("Failed to create structuredClone: {:?}", e))?;
+        
+        // The `URL` and `URLSearchParams` classes
+        urls::install(&ctx).map_err(|e| format!("Failed to create URL: {:?}", e))?;
+        
+        // JSONPath queries, as the `jsonpath` and `jsonpathFirst` globals
+        jsonpath::install(&ctx).map_err(|e| format!("Failed to create jsonpath: {:?}", e))?;
+        
+        // XML to plain objects, as the `parseXml` global
+        xml::install(&ctx).map_err(|e| format!("Failed to create parseXml: {:?}", e))?;
+        
+        // YAML to plain values and back, as the `parseYaml`, `parseYamlAll` and `toYaml` globals
+        yaml::install(&ctx).map_err(|e| format!("Failed to create parseYaml: {:?}", e))?;
+        
+        if let Some(session) = &options.state {
+            state::install(&ctx, session.clone()).map_err(|e| format!("Failed to create state: {:?}", e))?;
+        }
+        
+        // Utility library, available as the `utils` global
+        code_cache::evaluate_prelude(&ctx, "utils.js", include_str!("js/utils.js"))
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create utils: {:?}", e))?;
+        
+        // Named outputs, as the `RESULTS` object and `setResult`
+        code_cache::evaluate_prelude(&ctx, "results.js", include_str!("js/results.js"))
+            .and_then(|promise| promise.finish::<()>())
+            .map_err(|e| format!("Failed to create RESULTS: {:?}", e))?;
+        
+        // Every global is listed by GET /functions
+        if cfg!(debug_assertions) {
+            surface::check(&ctx, options.state.is_some())?;
+        }
+        
+        // Installed last, once the helpers are set up. The properties are neither
+        // writable nor configurable, so user code can't put the originals back.
+        if options.disable_dynamic_eval {
+            code_cache::evaluate_prelude(&ctx, "dynamic_eval.js", r#"
+                const blocked = (name) => {
+                    const stub = function () {
+                        throw new EvalError(`${name} is disabled: dynamic code evaluation is not allowed on this server`);
+                    };
+                    return Object.defineProperty(stub, "name", { value: name });
+                };
+                const lock = (target, key, value) =>
+                    Object.defineProperty(target, key, { value, writable: false, configurable: false });
+                
+                const FunctionStub = blocked("Function");
+                FunctionStub.prototype = Function.prototype;
+                lock(Function.prototype, "constructor", FunctionStub);
+                // Reachable through the prototypes of functions of each kind
+                for (const fn of [async function () {}, function* () {}, async function* () {}]) {
+                    const prototype = Object.getPrototypeOf(fn);
+                    lock(prototype, "constructor", blocked(prototype.constructor.name));
+                }
+                lock(globalThis, "Function", FunctionStub);
+                lock(globalThis, "eval", blocked("eval"));
+            "#)
+                .and_then(|promise| promise.finish::<()>())
+                .map_err(|e| format!("Failed to disable dynamic evaluation: {:?}", e))?;
+        }
+        
+        // PRELUDE_DIR, on top of the sandbox's globals
+        if let Some(scripts) = &options.prelude_scripts {
+            scripts.evaluate(&ctx)?;
+        }
+        
+        Ok::<(), String>(())
+    }).await?;
+    
+    Ok(context)
+}
+
+// The host functions behind httpGetStream: opening the request, reading the next
+// chunk of its body (null at the end) and closing it
+fn install_streams<'js>(ctx: &Ctx<'js>, http: Arc<dyn HttpBackend>, session: Arc<FetchSession>) -> rquickjs::Result<()> {
+    let opening = session.clone();
+    ctx.globals().set("__httpStreamAsync", Func::from(Async(move |url: String, options_json: String| {
+        let opts: Option<HashMap<String, Value>> = serde_json::from_str(&options_json).ok();
+        let open = opening.open_stream(http.clone(), url, opts);
+        async move {
+            let (result, stream) = open.await;
+            Ok::<String, rquickjs::Error>(serde_json::json!({ "result": result, "stream": stream }).to_string())
+        }
+    })))?;
+    let reading = session.clone();
+    ctx.globals().set("__httpStreamNext", Func::from(Async(move |ctx: Ctx<'js>, id: u32| {
+        let next = reading.next_chunk(id);
+        async move {
+            match next.await {
+                Some(Chunk::Text(text)) => rquickjs::String::from_str(ctx, &text).map(|text| text.into_value()),
+                Some(Chunk::Bytes(bytes)) => TypedArray::<u8>::new(ctx, bytes).map(|bytes| bytes.into_value()),
+                None => Ok(rquickjs::Value::new_null(ctx)),
+            }
+        }
+    })))?;
+    ctx.globals().set("__httpStreamClose", Func::from(move |id: u32| {
+        let (bytes_read, failure) = session.close_stream(id);
+        serde_json::json!([bytes_read, failure]).to_string()
+    }))?;
+    Ok(())
+}
+
+// Runs the code in the context and returns its serialized result
+pub async fn evaluate(
+    context: &AsyncContext,
+    code: &str,
+    modules: &SandboxModules,
+    options: &ExecutionOptions,
+) -> std::result::Result<Value, ExecError> {
+    let json = evaluate_to_json(context, code, modules, options).await?;
+    serde_json::from_str(&json).map_err(|e| e.to_string().into())
+}
+
+// The result as JSON text, without parsing it again
+pub async fn evaluate_to_json(
+    context: &AsyncContext,
+    code: &str,
+    modules: &SandboxModules,
+    options: &ExecutionOptions,
+) -> std::result::Result<String, ExecError> {
+    // Execute the user code - evaluate directly as async code (like Node.js does)
+    // The user's code should contain 'await' keywords where needed
+    let code_owned = code.to_string();
+    let result_json = async_with!(context => |ctx| {
+        // What an earlier evaluation in the same context set doesn't count
+        let results: rquickjs::Object = ctx.globals().get("RESULTS")
+            .map_err(|e| format!("RESULTS lookup error: {:?}", e))?;
+        for name in results.keys::<String>().collect::<Vec<_>>() {
+            results.remove(name.map_err(|e| format!("RESULTS lookup error: {:?}", e))?)
+                .map_err(|e| format!("RESULTS lookup error: {:?}", e))?;
+        }
+        
+        let result = if options.module {
+            evaluate_module(&ctx, &code_owned, modules, options).await?
+        } else {
+            evaluate_script(&ctx, &code_owned, options).await?
+        };
+        let result = match &options.entrypoint {
+            Some(entrypoint) => call_entrypoint(&ctx, entrypoint, result, options.module)?,
+            None => result,
+        };
+        let result = settle(&ctx, result).await?;
+        
+        let replacer = serialize::replacer(&ctx, options.bigint_mode)
+            .map_err(|e| format!("Failed to create result replacer: {:?}", e))?;
+        // Named outputs replace the code's value, each serialized on its own
+        let names = results.keys::<String>().collect::<rquickjs::Result<Vec<_>>>()
+            .map_err(|e| format!("RESULTS lookup error: {:?}", e))?;
+        let json_str = if names.is_empty() {
+            stringify(&ctx, result, &replacer, "result")?
+        } else {
+            let mut json = String::from("{");
+            for (index, name) in names.iter().enumerate() {
+                let value = results.get(name.as_str()).map_err(|e| format!("RESULTS lookup error: {:?}", e))?;
+                if index > 0 {
+                    json.push(',');
+                }
+                json.push_str(&Value::String(name.clone()).to_string());
+                json.push(':');
+                json.push_str(&stringify(&ctx, value, &replacer, &format!("RESULTS[{}]", Value::String(name.clone())))?);
+            }
+            json.push('}');
+            json
+        };
+        
+        // Checked before the result is parsed again
+        if json_str.len() > options.max_result_bytes.value {
+            let mut error = ExecError::new(
+                ErrorKind::ResultTooLarge,
+                format!(
+                    "Result is {} bytes, exceeding the {} limit of {} bytes",
+                    json_str.len(),
+                    options.max_result_bytes.name,
+                    options.max_result_bytes.value
+                ),
+            );
+            let mut end = RESULT_PREVIEW_BYTES.min(json_str.len());
+            while !json_str.is_char_boundary(end) {
+                end -= 1;
+            }
+            error.preview = Some(json_str[..end].to_string());
+            return Err(error);
+        }
+        
+        METRICS.result_bytes(json_str.len());
+        Ok::<String, ExecError>(json_str)
+    }).await.map_err(|mut error| {
+        // What was thrown is returned like a result would be
+        if let Some(js_error) = &mut error.js_error {
+            js_error.cap(options.max_result_bytes.value);
+        }
+        error
+    })?;
+    
+    Ok(result_json)
+}
+
+// The value as JSON, with errors naming it as `what`, e.g. "result"
+fn stringify<'js>(
+    ctx: &Ctx<'js>,
+    value: rquickjs::Value<'js>,
+    replacer: &rquickjs::Function<'js>,
+    what: &str,
+) -> Result<String, ExecError> {
+    // Functions and symbols would silently stringify to nothing
+    if value.is_function() || value.is_symbol() {
+        return Err(ExecError::new(
+            ErrorKind::Unserializable,
+            format!("{} is not serializable: {} values can't be represented as JSON", what, value.type_name()),
+        ));
+    }
+    match ctx.json_stringify_replacer(value, replacer.clone()) {
+        Ok(Some(json)) => Ok(json.to_string().map_err(|e| format!("JSON stringify error: {:?}", e))?),
+        // `undefined`, e.g. when the last statement has no value
+        Ok(None) => Ok("null".to_string()),
+        Err(e) => {
+            let error = ExecError::from_js(ctx, e, ErrorKind::Runtime, "JSON stringify error");
+            let Some(js_error) = error.js_error.as_deref() else {
+                return Err(error);
+            };
+            let message = match (js_error.name.as_deref(), js_error.message.as_str()) {
+                (Some("TypeError"), "circular reference") => format!("{} contains circular references", what),
+                (Some("TypeError"), "BigInt are forbidden in JSON.stringify") => {
+                    format!("{} is not serializable: it contains BigInt values and bigint_mode is \"error\"", what)
+                }
+                // Thrown by the replacer
+                (Some("RangeError"), message) if message.starts_with("result is nested") => message.replacen("result", what, 1),
+                _ => return Err(error),
+            };
+            Err(ExecError::new(ErrorKind::Unserializable, message))
+        }
+    }
+}
+
+// Evaluated as an async script, so top-level await works and the result is the
+// completion value of the last statement, like a REPL or eval() would produce
+async fn evaluate_script<'js>(
+    ctx: &Ctx<'js>,
+    code: &str,
+    options: &ExecutionOptions,
+) -> Result<rquickjs::Value<'js>, ExecError> {
+    // The whole script is compiled before any of it runs and runtime exceptions reject
+    // the promise, so an exception here is always a syntax error
+    let (function, hit) = options.code_cache.script(ctx, code)
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Syntax, "Evaluation error"))?;
+    options.code_cache_hit.store(hit, Ordering::Relaxed);
+    stop_if_interrupted(options)?;
+    let promise = code_cache::run_script(ctx, function)
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Syntax, "Evaluation error"))?;
+    mark_handled(&promise)?;
+    
+    // Await the promise to get the result, which QuickJS wraps as `{ value }`
+    let settled = promise.into_future::<rquickjs::Object>().await
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Promise resolution error"))?;
+    let result = settled.get("value")
+        .map_err(|e| format!("Promise resolution error: {:?}", e))?;
+    Ok(result)
+}
+
+// Evaluated as an ES module. The result is the default export, called with INPUTS
+// if it is a function, or the module namespace when there is no default export.
+async fn evaluate_module<'js>(
+    ctx: &Ctx<'js>,
+    code: &str,
+    modules: &SandboxModules,
+    options: &ExecutionOptions,
+) -> Result<rquickjs::Value<'js>, ExecError> {
+    // Imports are resolved while the module is declared and linked
+    let unresolved = |e: rquickjs::Error, kind: ErrorKind, context: &str| {
+        let Some(message) = modules.unresolved_message() else {
+            return ExecError::from_js(ctx, e, kind, context);
+        };
+        // Clear the pending exception raised for the failed import
+        let _ = ctx.catch();
+        ExecError::new(ErrorKind::Import, message)
+    };
+    
+    // The entry module is compiled under its own name, which relative imports resolve
+    // against, so it doesn't share bytecode with other code
+    let compiled = match &options.modules {
+        Some(map) => Module::declare(ctx.clone(), map.entry.as_str(), code).map(|declared| (declared, false)),
+        None => options.code_cache.module(ctx, USER_CODE_FILENAME, code),
+    };
+    let (declared, hit) = compiled
+        .map_err(|e| unresolved(e, ErrorKind::Syntax, "Evaluation error"))?;
+    options.code_cache_hit.store(hit, Ordering::Relaxed);
+    stop_if_interrupted(options)?;
+    let (evaluated, promise) = declared.eval()
+        .map_err(|e| unresolved(e, ErrorKind::Runtime, "Evaluation error"))?;
+    mark_handled(&promise)?;
+    promise.into_future::<()>().await
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Promise resolution error"))?;
+    
+    let namespace = evaluated.namespace()
+        .map_err(|e| format!("Module namespace error: {:?}", e))?;
+    // The entrypoint is looked up among the exports
+    if options.entrypoint.is_some() {
+        return Ok(namespace.into_value());
+    }
+    let default: rquickjs::Value = namespace.get("default")
+        .map_err(|e| format!("Module namespace error: {:?}", e))?;
+    
+    if default.is_undefined() {
+        return Ok(namespace.into_value());
+    }
+    let Some(function) = default.as_function() else {
+        return Ok(default);
+    };
+    
+    let inputs: rquickjs::Value = ctx.globals().get("INPUTS")
+        .map_err(|e| format!("INPUTS lookup error: {:?}", e))?;
+    let returned: rquickjs::Value = function.call((inputs,))
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Default export error"))?;
+    let Some(promise) = returned.as_promise().cloned() else {
+        return Ok(returned);
+    };
+    mark_handled(&promise)?;
+    let result = promise.into_future().await
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Promise resolution error"))?;
+    Ok(result)
+}
+
+// The parser never calls the interrupt handler, so a compile that ran past the
+// timeout or CPU budget is only noticed once it is over. Deep nesting can't keep it
+// busy, that fails as soon as it reaches JS_MAX_STACK_BYTES.
+fn stop_if_interrupted(options: &ExecutionOptions) -> Result<(), ExecError> {
+    match options.cancellation.interruption() {
+        Some(_) => Err(ExecError::new(ErrorKind::Interrupted, "Interrupted while compiling".to_string())),
+        None => Ok(()),
+    }
+}
+
+// Calls the entrypoint once the code was evaluated. A script's functions are
+// looked up like a later script would see them, so top-level `const` and `let`
+// bindings count too; a module's among its exports, in `namespace`.
+fn call_entrypoint<'js>(
+    ctx: &Ctx<'js>,
+    entrypoint: &Entrypoint,
+    namespace: rquickjs::Value<'js>,
+    module: bool,
+) -> Result<rquickjs::Value<'js>, ExecError> {
+    let name = &entrypoint.name;
+    let invalid = |message: String| ExecError::new(ErrorKind::Entrypoint, message);
+    let found: rquickjs::Value = if module {
+        let namespace = namespace.as_object().cloned()
+            .ok_or_else(|| format!("Module namespace error: {} has no exports", USER_CODE_FILENAME))?;
+        let exports: Vec<String> = namespace.keys::<String>().flatten().collect();
+        if !exports.contains(name) {
+            let exported = match exports.is_empty() {
+                true => "nothing".to_string(),
+                false => exports.join(", "),
+            };
+            return Err(invalid(format!("Entrypoint {:?} isn't exported by the module, which exports {}", name, exported)));
+        }
+        namespace.get(name.as_str()).map_err(|e| format!("Module namespace error: {:?}", e))?
+    } else {
+        let identifier = name.chars().enumerate().all(|(i, c)| {
+            c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+        });
+        if name.is_empty() || !identifier {
+            return Err(invalid(format!("Entrypoint {:?} isn't a valid identifier", name)));
+        }
+        ctx.eval::<rquickjs::Value, _>(format!("typeof {0} === 'undefined' ? undefined : {0}", name))
+            .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Entrypoint, "Entrypoint lookup error"))?
+    };
+    let Some(function) = found.as_function() else {
+        let what = match found.type_name() {
+            "undefined" if !module => "isn't defined by the code".to_string(),
+            type_name @ ("undefined" | "null") => format!("is {}, not a function", type_name),
+            type_name => format!("is {} {}, not a function", article(type_name), type_name),
+        };
+        return Err(invalid(format!("Entrypoint {:?} {}", name, what)));
+    };
+
+    let args: Vec<rquickjs::Value> = match &entrypoint.args {
+        Some(args) => args.iter()
+            .map(|arg| ctx.json_parse(arg.to_string()))
+            .collect::<rquickjs::Result<_>>()
+            .map_err(|e| format!("Entrypoint args error: {:?}", e))?,
+        None => vec![ctx.globals().get("INPUTS").map_err(|e| format!("INPUTS lookup error: {:?}", e))?],
+    };
+    let mut call = Args::new(ctx.clone(), args.len());
+    call.push_args(args).map_err(|e| format!("Entrypoint args error: {:?}", e))?;
+    function.call_arg(call)
+        .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Entrypoint error"))
+}
+
+fn article(word: &str) -> &'static str {
+    match word.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        true => "an",
+        false => "a",
+    }
+}
+
+// A promise or other thenable as the result, e.g. `Promise.resolve(42)` as the last
+// expression, is awaited like `await` would, until the value is neither. The job
+// queue runs meanwhile, so the execution timeout still applies. A rejection fails
+// the execution with the reason, like an exception thrown by the code.
+async fn settle<'js>(ctx: &Ctx<'js>, mut value: rquickjs::Value<'js>) -> Result<rquickjs::Value<'js>, ExecError> {
+    loop {
+        let promise = match value.as_promise() {
+            Some(promise) => promise.clone(),
+            None if is_thenable(ctx, &value) => {
+                // `Promise.resolve` adopts the thenable's state
+                let constructor: rquickjs::Object = ctx.globals().get("Promise")
+                    .map_err(|e| format!("Promise lookup error: {:?}", e))?;
+                let resolve: rquickjs::Function = constructor.get("resolve")
+                    .map_err(|e| format!("Promise lookup error: {:?}", e))?;
+                resolve.call((This(constructor), value))
+                    .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Promise resolution error"))?
+            }
+            None => return Ok(value),
+        };
+        mark_handled(&promise)?;
+        value = promise.into_future().await
+            .map_err(|e| ExecError::from_js(ctx, e, ErrorKind::Runtime, "Promise resolution error"))?;
+    }
+}
+
+fn is_thenable<'js>(ctx: &Ctx<'js>, value: &rquickjs::Value<'js>) -> bool {
+    let Some(obj) = value.as_object() else {
+        return false;
+    };
+    match obj.get::<_, rquickjs::Value>("then") {
+        Ok(then) => then.is_function(),
+        // A `then` getter that throws makes it no thenable here
+        Err(_) => {
+            let _ = ctx.catch();
+            false
+        }
+    }
+}
+
+// The rejection of the execution itself is reported as the error, so mark it as
+// handled to keep it out of the unhandled rejections
+fn mark_handled(promise: &rquickjs::Promise<'_>) -> Result<(), ExecError> {
+    promise.catch()
+        .and_then(|catch| catch.call::<_, ()>((This(promise.clone()), Func::from(|| ()))))
+        .map_err(|e| format!("Evaluation error: {:?}", e).into())
+}
@@ -0,0 +1,161 @@
+// Why an execution failed.
+//
+// Every failure has a kind, which the server maps to the HTTP status and the `error`
+// title of its response, and a message. Exceptions thrown by user code also come
+// with the JavaScript error itself.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::cancel::Interruption;
+use crate::executor::Report;
+use crate::js_error::JsError;
+use crate::secrets::Secrets;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    // The code doesn't parse
+    Syntax,
+    // The code threw or produced a result that can't be returned
+    Runtime,
+    // A module import names an unknown module
+    Import,
+    // The requested entrypoint isn't a function of the code
+    Entrypoint,
+    // The execution timed out or was cancelled
+    Interrupted,
+    // The result can't be represented as JSON
+    Unserializable,
+    // The serialized result exceeds the result size limit
+    ResultTooLarge,
+    // The code made more outbound requests than allowed, even if it caught the error
+    RequestLimit,
+    // The code allocated more memory than the runtime is allowed
+    MemoryLimit,
+    // The code ran on the CPU for longer than its budget
+    CpuLimit,
+    // A request had no mocked or replayed response, even if the code caught the error
+    UnmatchedRequest,
+    // No execution slot became free in time
+    Busy,
+    // The worker process running the execution died, with ISOLATION_MODE=subprocess
+    WorkerCrashed,
+    // The sandbox itself failed
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn error(self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "SyntaxError",
+            ErrorKind::Import => "ModuleResolutionError",
+            ErrorKind::Entrypoint => "Invalid entrypoint",
+            ErrorKind::Unserializable => "UnserializableResult",
+            ErrorKind::Runtime => "RuntimeError",
+            ErrorKind::Interrupted => "Execution interrupted",
+            ErrorKind::ResultTooLarge => "Result too large",
+            ErrorKind::RequestLimit => "Request limit exceeded",
+            ErrorKind::MemoryLimit => "Memory limit exceeded",
+            ErrorKind::CpuLimit => "CPU budget exceeded",
+            ErrorKind::UnmatchedRequest => "Unmatched request",
+            ErrorKind::Busy => "Server busy",
+            ErrorKind::WorkerCrashed => "Worker crashed",
+            ErrorKind::Internal => "Execution failed",
+        }
+    }
+}
+
+// Failure of an execution, with the JavaScript exception when user code threw
+#[derive(Debug)]
+pub struct ExecError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub js_error: Option<Box<JsError>>,
+    // Start of the serialized result, when it was too large to return
+    pub preview: Option<String>,
+    // What the execution did before it failed, unless it never started
+    pub report: Option<Box<Report>>,
+}
+
+impl ExecError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        ExecError {
+            kind,
+            message,
+            js_error: None,
+            preview: None,
+            report: None,
+        }
+    }
+
+    // JavaScript exceptions are attributed to `exception_kind`, anything else is internal
+    pub fn from_js(ctx: &rquickjs::Ctx<'_>, error: rquickjs::Error, exception_kind: ErrorKind, context: &str) -> Self {
+        match error {
+            rquickjs::Error::Exception => {
+                let js_error = JsError::catch(ctx);
+                ExecError {
+                    message: format!("{}: {}", context, js_error.describe()),
+                    js_error: Some(Box::new(js_error)),
+                    ..ExecError::new(exception_kind, String::new())
+                }
+            }
+            e => format!("{}: {:?}", context, e).into(),
+        }
+    }
+
+    pub fn interrupted(interruption: Interruption, timeout: Duration) -> Self {
+        match interruption {
+            Interruption::TimedOut => ExecError::new(
+                ErrorKind::Interrupted,
+                format!("Execution exceeded the timeout of {} ms", timeout.as_millis()),
+            ),
+            Interruption::CpuBudget => ExecError::new(ErrorKind::CpuLimit, "Execution exceeded its CPU budget".to_string()),
+            Interruption::Cancelled => ExecError::new(ErrorKind::Interrupted, "Execution was cancelled".to_string()),
+            Interruption::ShuttingDown => ExecError::new(
+                ErrorKind::Interrupted,
+                "Execution was interrupted because the server is shutting down".to_string(),
+            ),
+        }
+    }
+
+    // QuickJS throws an InternalError when an allocation fails, which user code can
+    // catch, but it fails again whenever the code allocates after that
+    pub fn is_out_of_memory(&self) -> bool {
+        self.js_error
+            .as_ref()
+            .is_some_and(|e| e.name.as_deref() == Some("InternalError") && e.message == "out of memory")
+    }
+
+    pub fn redact(&mut self, secrets: &Secrets) {
+        secrets.redact_in_place(&mut self.message);
+        if let Some(js_error) = &mut self.js_error {
+            secrets.redact_in_place(&mut js_error.message);
+            if let Some(stack) = &mut js_error.stack {
+                secrets.redact_in_place(stack);
+            }
+            for value in [&mut js_error.properties, &mut js_error.thrown].into_iter().flatten() {
+                secrets.redact_value(value);
+            }
+        }
+        if let Some(preview) = &mut self.preview {
+            secrets.redact_in_place(preview);
+        }
+        if let Some(report) = &mut self.report {
+            report.redact(secrets);
+        }
+    }
+}
+
+impl From<String> for ExecError {
+    fn from(message: String) -> Self {
+        ExecError::new(ErrorKind::Internal, message)
+    }
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind.error(), self.message)
+    }
+}
+
+impl std::error::Error for ExecError {}
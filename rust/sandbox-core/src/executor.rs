@@ -0,0 +1,794 @@
+// Runs executions, the way POST /execute does but without any server.
+//
+// An Executor holds what executions share: the runtime pool, the code cache, the
+// admission control and the backend httpRequest calls go to. `execute` runs code
+// with the configured limits, `run` with per-execution options that can only
+// tighten them. The code runs on a task of its own, so the timeout and a dropped
+// `run` future (e.g. a client that went away) stop even a busy script. Secrets are
+// redacted from everything `run` returns. With ISOLATION_MODE=subprocess, `run`
+// hands the code to a worker process instead (see `isolation`).
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::admission::{Admission, Priority};
+use crate::cancel::{Cancellation, Interruption};
+use crate::circuit::CircuitBreakers;
+use crate::clock::ExecutionClock;
+use crate::code_cache::CodeCache;
+use crate::result_cache::ResultCache;
+use crate::config::Config;
+use crate::cpu::CpuTime;
+use crate::dry_run::{DryRun, PlannedRequest};
+use crate::engine::{execute_js_to_json, execute_js_with_quickjs, ConsoleSink, Entrypoint, ExecutionOptions, RejectionLog};
+use crate::error::{ErrorKind, ExecError};
+use crate::fetch::{FetchSession, HttpBackend, HttpClients, HttpTrace};
+use crate::isolation::{self, Job, WorkerPool};
+use crate::metrics::METRICS;
+use crate::mocks::HttpMocks;
+use crate::modules::ModuleMap;
+use crate::recording::{self, DebugRequest, Recorder, Recording, Replay, RequestLog};
+use crate::policy::{OutboundPolicy, UrlPatterns};
+use crate::pool::RuntimePool;
+use crate::prelude_scripts::PreludeScripts;
+use crate::proxy::ProxyConfig;
+use crate::files::Files;
+use crate::secrets::Secrets;
+use crate::state::{StateSession, StateStore};
+use crate::timers::SleepBudget;
+use crate::serialize::BigIntMode;
+use crate::typescript::{self, Language};
+
+// Console lines kept per execution; later ones are dropped
+const MAX_LOG_LINES: usize = 1000;
+
+// The configured limits, which apply to every execution
+pub struct Limits {
+    // EXECUTION_TIMEOUT_MS, unless an execution asks for another timeout
+    pub execution_timeout: Duration,
+    // MAX_EXEC_TIMEOUT_MS, the longest timeout an execution may ask for; never
+    // shorter than the default
+    pub max_execution_timeout: Duration,
+    // EXEC_CPU_MS
+    pub cpu_budget: Duration,
+    // MAX_REQUESTS_PER_EXECUTION
+    pub max_requests: u32,
+    // MAX_RESULT_BYTES
+    pub max_result_bytes: usize,
+    // LAZY_INPUTS_BYTES
+    pub lazy_inputs_bytes: usize,
+    // JS_MAX_MEMORY_BYTES, the heap limit of the runtime
+    pub memory_bytes: usize,
+    // FETCH_MAX_BODY_BYTES, per httpRequest response
+    pub max_fetch_body_bytes: usize,
+    // MAX_FETCH_TOTAL_BYTES, across the httpRequest calls of an execution
+    pub max_fetch_total_bytes: usize,
+    // FETCH_CONCURRENCY and FETCH_CONCURRENCY_PER_HOST
+    pub fetch_concurrency: usize,
+    pub fetch_concurrency_per_host: usize,
+    // DISABLE_DYNAMIC_EVAL
+    pub disable_dynamic_eval: bool,
+    // NETWORK_DISABLED
+    pub network_disabled: bool,
+    // SLEEP_BUDGET_MS
+    pub sleep_budget: Duration,
+    // JS_MAX_STACK_BYTES, also for syntax checks
+    pub js_max_stack_bytes: usize,
+}
+
+// Settings of one execution
+#[derive(Clone, Default)]
+pub struct Options {
+    // Evaluate the code as an ES module instead of a script
+    pub module: bool,
+    pub bigint_mode: BigIntMode,
+    // Stop the clock at the execution start time
+    pub freeze_time: bool,
+    // Seed for Math.random, generated when absent
+    pub random_seed: Option<u32>,
+    // In place of the default timeout, up to the longest allowed
+    pub timeout: Option<Duration>,
+    // Each of these can only tighten the executor's limit
+    pub cpu_budget: Option<Duration>,
+    pub max_requests: Option<u32>,
+    pub max_result_bytes: Option<usize>,
+    pub memory_bytes: Option<usize>,
+    pub max_fetch_body_bytes: Option<usize>,
+    pub disable_dynamic_eval: bool,
+    // Run without network access: httpRequest, fetch and graphql fail without sending
+    // anything, unless http_mocks or replay_http answer them
+    pub disable_network: bool,
+    // Sent on outbound requests, generated when absent
+    pub request_id: Option<String>,
+    // Available to the code as SECRETS
+    pub secrets: Arc<Secrets>,
+    // Available to the code as FILES
+    pub files: Arc<Files>,
+    // Whose `state` the code gets, "default" when absent
+    pub state_namespace: Option<String>,
+    // Record httpRequest calls instead of sending them, and keep no state changes
+    pub dry_run: bool,
+    // Answer httpRequest calls instead of the network
+    pub http_mocks: Option<Arc<HttpMocks>>,
+    // Answer httpRequest calls from an earlier execution's recording
+    pub replay_http: Option<Arc<Replay>>,
+    // Keep the responses httpRequest calls got, in Report::recorded_http
+    pub record_http: bool,
+    // Serve the result from the result cache, or keep it there for this long
+    pub cache_ttl: Option<Duration>,
+    // Call this function of the code and return what it returns
+    pub entrypoint: Option<Entrypoint>,
+    // TypeScript is stripped of its types before anything else
+    pub language: Language,
+    // Modules the code can import, the code being the source of the entry module
+    pub modules: Option<Arc<ModuleMap>>,
+    // Which queue the execution waits in for a slot
+    pub priority: Priority,
+    // The tenant the execution runs for, whose cached results and responses are
+    // kept apart from other tenants'
+    pub tenant: Option<String>,
+    // The tenant's allowlist, which outbound requests must match on top of the policy
+    pub fetch_allowlist: Option<Arc<UrlPatterns>>,
+    // Keep the result as the JSON text the engine produced, in Execution::result_json,
+    // instead of parsing it
+    pub raw_result: bool,
+    // Run without the PRELUDE_DIR scripts
+    pub skip_prelude: bool,
+    // Keep every httpRequest call under its replay_http key, in Report::debug_requests
+    pub debug: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Execution {
+    // Null when the result is kept as `result_json`
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_json: Option<String>,
+    pub report: Report,
+}
+
+impl Execution {
+    // The result in the form `Options.raw_result` asks for, whichever form it was
+    // kept in, e.g. by a worker or the result cache
+    fn with_raw_result(mut self, raw: bool) -> Result<Self, ExecError> {
+        match (raw, self.result_json.take()) {
+            (true, None) => {
+                self.result_json = Some(serde_json::to_string(&self.result).map_err(|e| e.to_string())?);
+                self.result = Value::Null;
+            }
+            (true, json) => self.result_json = json,
+            (false, Some(json)) => self.result = serde_json::from_str(&json).map_err(|e| e.to_string())?,
+            (false, None) => {}
+        }
+        Ok(self)
+    }
+}
+
+// What an execution did, whether it succeeded or not. Serialized for the way back
+// from a worker process.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Report {
+    pub logs: Vec<LogLine>,
+    // Promise rejections no handler was attached to
+    pub unhandled_rejections: Vec<String>,
+    // Every httpRequest call, in the order they finished
+    pub http: Vec<HttpTrace>,
+    // The httpRequest calls of a dry run, in the order they were made
+    pub planned_requests: Vec<PlannedRequest>,
+    // The httpRequest calls with `debug`, in the order they were made
+    pub debug_requests: Vec<DebugRequest>,
+    // The responses of the httpRequest calls, with `record_http`, which worker
+    // processes don't run with
+    #[serde(skip)]
+    pub recorded_http: Option<Recording>,
+    pub code_bytes: usize,
+    // From the call to `run`, including the wait for a slot
+    pub duration: Duration,
+    // The wait for a slot alone
+    pub queue_wait: Duration,
+    // Time with at least one outbound request in flight
+    pub fetch_duration: Duration,
+    pub usage: Usage,
+    pub http_request_count: u32,
+    pub max_requests: u32,
+    // The timeout and limits the execution ran with
+    pub timeout: Duration,
+    pub cpu_budget: Duration,
+    pub max_result_bytes: usize,
+    pub memory_bytes: usize,
+    pub max_fetch_body_bytes: usize,
+    pub max_fetch_total_bytes: usize,
+    pub random_seed: u32,
+    // False when the execution ran without network access
+    pub network_allowed: bool,
+    // Whether the code was loaded from the code cache
+    pub code_cache_hit: bool,
+    // Whether the result came from the result cache, without running the code
+    pub result_cache_hit: bool,
+    // Whether the code set or deleted state keys
+    pub state_changed: bool,
+    pub request_id: String,
+}
+
+impl Report {
+    pub fn redact(&mut self, secrets: &Secrets) {
+        for line in &mut self.logs {
+            secrets.redact_in_place(&mut line.message);
+        }
+        for rejection in &mut self.unhandled_rejections {
+            secrets.redact_in_place(rejection);
+        }
+        for request in &mut self.http {
+            secrets.redact_in_place(&mut request.url);
+        }
+        for request in &mut self.planned_requests {
+            request.redact(secrets);
+        }
+        for request in &mut self.debug_requests {
+            request.redact(secrets);
+        }
+        if let Some(recorded) = &mut self.recorded_http {
+            recording::redact(recorded, secrets);
+        }
+    }
+}
+
+// What an execution consumed, also recorded in the usage histograms
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Usage {
+    // The most heap the runtime used at once, sampled at interrupt checkpoints and
+    // after the evaluation
+    pub heap_peak_bytes: usize,
+    // Time on the CPU running the code, waits not included
+    pub cpu_time: Duration,
+    // Calls of the interrupt handler
+    pub interrupts: u64,
+    // Response bodies received from upstreams, cached responses not included
+    pub fetched_bytes: u64,
+    // Request bodies sent, once for every attempt
+    pub sent_bytes: u64,
+    // Size of the serialized result, 0 on errors
+    pub result_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogLine {
+    pub level: String,
+    pub message: String,
+}
+
+pub struct Executor {
+    http: Arc<dyn HttpBackend>,
+    runtimes: Arc<RuntimePool>,
+    code_cache: Arc<CodeCache>,
+    result_cache: Arc<ResultCache>,
+    admission: Arc<Admission>,
+    limits: Limits,
+    env: Arc<BTreeMap<String, String>>,
+    state: Arc<StateStore>,
+    // MASKED_HEADERS
+    masked_headers: Vec<String>,
+    // With ISOLATION_MODE=subprocess
+    workers: Option<WorkerPool>,
+    prelude_scripts: Arc<PreludeScripts>,
+}
+
+impl Executor {
+    // Outbound requests go over the network, as the fetch policy and proxy allow
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let proxy = ProxyConfig::from_config(config)?;
+        let http = HttpClients::new(config, OutboundPolicy::from_config(config)?, proxy)?;
+        Executor::with_http_backend(config, Arc::new(http))
+    }
+
+    pub fn with_http_backend(config: &Config, http: Arc<dyn HttpBackend>) -> Result<Self, String> {
+        Ok(Executor {
+            http,
+            runtimes: Arc::new(RuntimePool::from_config(config)),
+            code_cache: Arc::new(CodeCache::from_config(config)),
+            result_cache: Arc::new(ResultCache::from_config(config)),
+            admission: Arc::new(Admission::from_config(config)),
+            limits: Limits {
+                execution_timeout: Duration::from_millis(config.execution_timeout_ms),
+                max_execution_timeout: Duration::from_millis(config.max_exec_timeout_ms.max(config.execution_timeout_ms)),
+                cpu_budget: Duration::from_millis(config.exec_cpu_ms),
+                max_requests: config.max_requests_per_execution,
+                max_result_bytes: config.max_result_bytes,
+                lazy_inputs_bytes: config.lazy_inputs_bytes,
+                memory_bytes: config.js_max_memory_bytes,
+                max_fetch_body_bytes: config.fetch_max_body_bytes,
+                max_fetch_total_bytes: config.max_fetch_total_bytes,
+                fetch_concurrency: config.fetch_concurrency,
+                fetch_concurrency_per_host: config.fetch_concurrency_per_host,
+                disable_dynamic_eval: config.disable_dynamic_eval,
+                network_disabled: config.network_disabled,
+                sleep_budget: Duration::from_millis(config.sleep_budget_ms),
+                js_max_stack_bytes: config.js_max_stack_bytes,
+            },
+            env: Arc::new(config.sandbox_env.clone()),
+            state: Arc::new(StateStore::from_config(config)?),
+            masked_headers: config.masked_headers.clone(),
+            workers: WorkerPool::from_config(config)?,
+            prelude_scripts: Arc::new(PreludeScripts::from_config(config)?),
+        })
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn http(&self) -> Arc<dyn HttpBackend> {
+        self.http.clone()
+    }
+
+    pub fn runtimes(&self) -> &Arc<RuntimePool> {
+        &self.runtimes
+    }
+
+    pub fn code_cache(&self) -> &CodeCache {
+        &self.code_cache
+    }
+
+    pub fn admission(&self) -> &Arc<Admission> {
+        &self.admission
+    }
+
+    pub fn state(&self) -> &Arc<StateStore> {
+        &self.state
+    }
+
+    // The per-host circuit breakers, when requests go over the network
+    pub fn circuits(&self) -> Option<&CircuitBreakers> {
+        self.http.circuits()
+    }
+
+    // The PRELUDE_DIR scripts every context gets
+    pub fn prelude_scripts(&self) -> &PreludeScripts {
+        &self.prelude_scripts
+    }
+
+    // The worker processes executions run in, with ISOLATION_MODE=subprocess
+    pub fn workers(&self) -> Option<&WorkerPool> {
+        self.workers.as_ref()
+    }
+
+    // Settings for evaluations that don't go through `run`, e.g. sessions. Console
+    // output is discarded.
+    pub fn options(&self, cancellation: Arc<Cancellation>) -> ExecutionOptions {
+        ExecutionOptions {
+            module: false,
+            bigint_mode: BigIntMode::default(),
+            entrypoint: None,
+            modules: None,
+            max_result_bytes: Limit::tightened("MAX_RESULT_BYTES", self.limits.max_result_bytes, "max_result_bytes", None),
+            // Sessions replace INPUTS with a plain value on every evaluation
+            lazy_inputs_bytes: 0,
+            cancellation,
+            disable_dynamic_eval: self.limits.disable_dynamic_eval,
+            disable_network: self.limits.network_disabled,
+            clock: ExecutionClock::start(false),
+            random_seed: rand::random(),
+            code_cache: self.code_cache.clone(),
+            code_cache_hit: Arc::new(AtomicBool::new(false)),
+            console: Arc::new(|_, _| {}),
+            secrets: Arc::default(),
+            files: Arc::default(),
+            env: self.env.clone(),
+            state: None,
+            sleep_budget: Arc::new(SleepBudget::new(self.limits.sleep_budget)),
+            prelude_scripts: Some(self.prelude_scripts.clone()),
+        }
+    }
+
+    // The ENV every execution gets
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    pub async fn execute(&self, code: &str, inputs: &Value) -> Result<Execution, ExecError> {
+        self.run(code, inputs, Options::default()).await
+    }
+
+    // Fills the runtime pool and evaluates a trivial script on one of its runtimes,
+    // which compiles the prelude modules to bytecode for every later context. Takes
+    // no execution slot and isn't counted as an execution.
+    pub async fn warm_up(&self) -> Result<(), String> {
+        if let Some(workers) = &self.workers {
+            workers.warm_up().await?;
+        }
+        self.runtimes.warm_up().await?;
+        self.evaluate_nothing().await
+    }
+
+    // Evaluates the prelude scripts in a fresh context, where one that throws at its
+    // top level would fail every execution. For startup, which it stops.
+    pub async fn check_prelude_scripts(&self) -> Result<(), String> {
+        if self.prelude_scripts.is_empty() {
+            return Ok(());
+        }
+        self.evaluate_nothing().await
+    }
+
+    async fn evaluate_nothing(&self) -> Result<(), String> {
+        let lease = self.runtimes.acquire().await?;
+        let session = Arc::new(FetchSession::new(0, "warm-up".to_string()));
+        let options = self.options(Cancellation::new(self.limits.execution_timeout));
+        let inputs = Value::Object(Default::default());
+        let outcome = execute_js_with_quickjs(
+            lease.runtime(),
+            "undefined",
+            &inputs,
+            self.http(),
+            session,
+            &RejectionLog::default(),
+            &options,
+        )
+        .await;
+        lease.release().await;
+        outcome.map(|_| ()).map_err(|e| e.message)
+    }
+
+    // Waits for an execution slot first, and fails as Busy if none becomes free
+    pub async fn run(&self, code: &str, inputs: &Value, mut options: Options) -> Result<Execution, ExecError> {
+        let transpiled;
+        let code = match options.language {
+            Language::JavaScript => code,
+            Language::TypeScript => {
+                if let Some(map) = &options.modules {
+                    options.modules = Some(Arc::new(typescript::strip_module_types(map)?));
+                }
+                transpiled = typescript::strip_types(code)?;
+                transpiled.as_str()
+            }
+        };
+        let raw_result = options.raw_result;
+        let cache = options.cache_ttl.and_then(|ttl| Some((ResultCache::key(code, inputs, &options)?, ttl)));
+        if let Some((key, _)) = &cache {
+            let request_id = options.request_id.clone().unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+            if let Some(execution) = self.result_cache.get(key, request_id, Instant::now()) {
+                return execution.with_raw_result(raw_result);
+            }
+        }
+        let secrets = options.secrets.clone();
+        let outcome = match &self.workers {
+            Some(workers) => self.run_in_worker(workers, code, inputs, options).await,
+            None => self.run_unredacted(code, inputs, options).await,
+        };
+        let outcome = outcome.and_then(|execution| execution.with_raw_result(raw_result));
+        if let (Some((key, ttl)), Ok(execution)) = (cache, &outcome) {
+            self.result_cache.insert(key, execution, ttl);
+        }
+        if secrets.is_empty() {
+            return outcome;
+        }
+        match outcome {
+            Ok(mut execution) => {
+                secrets.redact_value(&mut execution.result);
+                if let Some(json) = &mut execution.result_json {
+                    secrets.redact_json(json);
+                }
+                execution.report.redact(&secrets);
+                Ok(execution)
+            }
+            Err(mut e) => {
+                e.redact(&secrets);
+                Err(e)
+            }
+        }
+    }
+
+    // Takes the slot here and sends the code to a worker process, along with the
+    // state of its namespace, whose writes are applied here if it succeeded
+    async fn run_in_worker(
+        &self,
+        workers: &WorkerPool,
+        code: &str,
+        inputs: &Value,
+        options: Options,
+    ) -> Result<Execution, ExecError> {
+        let started = Instant::now();
+        if let Some(option) = isolation::unsupported(&options) {
+            return Err(format!("{} isn't available with ISOLATION_MODE=subprocess", option).into());
+        }
+        let limits = &self.limits;
+        let timeout = options.timeout.map_or(limits.execution_timeout, |timeout| timeout.min(limits.max_execution_timeout));
+        let namespace = options.state_namespace.clone().unwrap_or_else(|| "default".to_string());
+        let job = Job::new(code, inputs, &options, timeout, &namespace, self.state.export(&namespace));
+        let permit = self
+            .admission
+            .admit(options.priority)
+            .await
+            .map_err(|saturation| ExecError::new(ErrorKind::Busy, self.admission.message(saturation)))?;
+        let queue_wait = permit.waited;
+        METRICS.queue_wait(options.priority, queue_wait);
+        let (outcome, state_writes) = workers.run(&job).await?;
+        drop(permit);
+
+        let mut outcome = match outcome {
+            Ok(execution) => match self.state.apply(&namespace, state_writes) {
+                Ok(()) => Ok(execution),
+                Err(message) => Err(ExecError {
+                    report: Some(Box::new(execution.report)),
+                    ..ExecError::new(ErrorKind::Internal, message)
+                }),
+            },
+            Err(e) => Err(e),
+        };
+        let report = match &mut outcome {
+            Ok(execution) => Some(&mut execution.report),
+            Err(e) => e.report.as_deref_mut(),
+        };
+        if let Some(report) = report {
+            report.duration = started.elapsed();
+            report.queue_wait = queue_wait;
+            METRICS.execution_usage(&report.usage);
+        }
+        outcome
+    }
+
+    async fn run_unredacted(&self, code: &str, inputs: &Value, options: Options) -> Result<Execution, ExecError> {
+        let started = Instant::now();
+        let limits = &self.limits;
+        let timeout = options.timeout.map_or(limits.execution_timeout, |timeout| timeout.min(limits.max_execution_timeout));
+        let max_requests = Limit::tightened(
+            "MAX_REQUESTS_PER_EXECUTION",
+            limits.max_requests,
+            "max_requests",
+            options.max_requests,
+        );
+        let max_result_bytes =
+            Limit::tightened("MAX_RESULT_BYTES", limits.max_result_bytes, "max_result_bytes", options.max_result_bytes);
+        let memory_bytes = Limit::tightened("JS_MAX_MEMORY_BYTES", limits.memory_bytes, "memory_bytes", options.memory_bytes);
+        let max_fetch_body_bytes = Limit::tightened(
+            "FETCH_MAX_BODY_BYTES",
+            limits.max_fetch_body_bytes,
+            "max_fetch_body_bytes",
+            options.max_fetch_body_bytes,
+        );
+        let cpu_budget = Limit::tightened("EXEC_CPU_MS", limits.cpu_budget, "cpu_ms", options.cpu_budget);
+        let cpu = CpuTime::new(cpu_budget.value);
+        let cancellation = Cancellation::with_cpu_budget(timeout, cpu.clone());
+        let random_seed = options.random_seed.unwrap_or_else(rand::random);
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        // Mocks and recordings answer from within the process, so they still work offline
+        let network_allowed = !(limits.network_disabled || options.disable_network);
+        let answered_locally = options.http_mocks.is_some() || options.replay_http.is_some();
+        let namespace = options.state_namespace.unwrap_or_else(|| "default".to_string());
+        let state = Arc::new(StateSession::new(self.state.clone(), namespace));
+        let secrets = options.secrets;
+        let execution_options = ExecutionOptions {
+            module: options.module,
+            bigint_mode: options.bigint_mode,
+            entrypoint: options.entrypoint.clone(),
+            modules: options.modules.clone(),
+            max_result_bytes,
+            lazy_inputs_bytes: limits.lazy_inputs_bytes,
+            cancellation: cancellation.clone(),
+            disable_dynamic_eval: limits.disable_dynamic_eval || options.disable_dynamic_eval,
+            disable_network: !network_allowed && !answered_locally,
+            clock: ExecutionClock::start(options.freeze_time),
+            random_seed,
+            code_cache: self.code_cache.clone(),
+            code_cache_hit: Arc::new(AtomicBool::new(false)),
+            console: collect(logs.clone()),
+            secrets: secrets.clone(),
+            files: options.files,
+            env: self.env.clone(),
+            state: Some(state.clone()),
+            sleep_budget: Arc::new(SleepBudget::new(limits.sleep_budget)),
+            prelude_scripts: (!options.skip_prelude).then(|| self.prelude_scripts.clone()),
+        };
+        let code_cache_hit = execution_options.code_cache_hit.clone();
+
+        // Cookies set by one request are sent on later requests of this execution only
+        let request_id = options.request_id.unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+        let dry_run = options.dry_run.then(|| Arc::new(DryRun::new(&self.masked_headers)));
+        let mut session = FetchSession::new(max_requests.value, request_id.clone())
+            .with_max_body_bytes(max_fetch_body_bytes)
+            .with_max_total_bytes(limits.max_fetch_total_bytes as u64)
+            .with_concurrency(limits.fetch_concurrency, limits.fetch_concurrency_per_host)
+            .with_console(collect(logs.clone()))
+            .with_secrets(secrets);
+        if let Some(dry_run) = &dry_run {
+            session = session.with_dry_run(dry_run.clone());
+        }
+        if let Some(tenant) = &options.tenant {
+            session = session.with_tenant(tenant.clone(), options.fetch_allowlist.clone());
+        }
+        let session = Arc::new(session);
+        let rejections = RejectionLog::default();
+        let mut http: Arc<dyn HttpBackend> = match (&options.http_mocks, &options.replay_http) {
+            (Some(mocks), _) => mocks.clone(),
+            (None, Some(replay)) => replay.clone(),
+            (None, None) => self.http.clone(),
+        };
+        let recorder = options.record_http.then(|| Arc::new(Recorder::new(http.clone(), &self.masked_headers)));
+        if let Some(recorder) = &recorder {
+            http = recorder.clone();
+        }
+        let request_log = options.debug.then(|| Arc::new(RequestLog::new(http.clone(), &self.masked_headers)));
+        if let Some(request_log) = &request_log {
+            http = request_log.clone();
+        }
+
+        let permit = self
+            .admission
+            .admit(options.priority)
+            .await
+            .map_err(|saturation| ExecError::new(ErrorKind::Busy, self.admission.message(saturation)))?;
+        let queue_wait = permit.waited;
+        METRICS.queue_wait(options.priority, queue_wait);
+
+        // The slot is held until the script has actually stopped
+        let execution = tokio::spawn({
+            let code = code.to_string();
+            let inputs = inputs.clone();
+            let http = http.clone();
+            let session = session.clone();
+            let rejections = rejections.clone();
+            let runtimes = self.runtimes.clone();
+            let cpu = cpu.clone();
+            let cancellation = cancellation.clone();
+            async move {
+                let _permit = permit;
+                let lease = runtimes.acquire().await?;
+                cancellation.watch_heap(lease.heap().clone());
+                lease.runtime().set_memory_limit(memory_bytes.value).await;
+                let execution =
+                    execute_js_to_json(lease.runtime(), &code, &inputs, http, session, &rejections, &execution_options);
+                // Code stopped while it was waiting leaves its runtime mid-execution,
+                // and the runtime is dropped with the lease
+                let outcome = tokio::select! {
+                    outcome = cpu.measure(execution) => outcome,
+                    _ = cancellation.interrupted() => return Err("Interrupted while waiting".to_string().into()),
+                };
+                cancellation.sample_heap();
+                lease.release().await;
+                outcome
+            }
+            .in_current_span()
+        });
+        let abort = execution.abort_handle();
+        let guard = cancellation.guard();
+        let outcome = match tokio::time::timeout(timeout, execution).await {
+            // Scripts stopped by the interrupt handler fail with an uncatchable exception
+            Ok(Ok(outcome)) => match (outcome, cancellation.interruption()) {
+                (Err(_), Some(Interruption::CpuBudget)) => Err(ExecError::new(
+                    ErrorKind::CpuLimit,
+                    format!(
+                        "Execution exceeded the {} limit of {} ms of CPU time",
+                        cpu_budget.name,
+                        cpu_budget.value.as_millis()
+                    ),
+                )),
+                (Err(_), Some(interruption)) => Err(ExecError::interrupted(interruption, timeout)),
+                (outcome, _) => outcome,
+            },
+            Ok(Err(e)) => Err(format!("Execution task failed: {}", e).into()),
+            // Still waiting, e.g. on an outbound request
+            Err(_) => {
+                abort.abort();
+                Err(ExecError::interrupted(Interruption::TimedOut, timeout))
+            }
+        };
+        guard.disarm();
+        let outcome = outcome.and_then(|json| match options.raw_result {
+            true => Ok((Value::Null, Some(json))),
+            false => Ok((serde_json::from_str(&json).map_err(|e| e.to_string())?, None)),
+        });
+
+        let usage = Usage {
+            heap_peak_bytes: cancellation.heap_peak(),
+            cpu_time: cpu.used(),
+            interrupts: cancellation.interrupt_count(),
+            fetched_bytes: session.fetched_bytes(),
+            sent_bytes: session.sent_bytes(),
+            result_bytes: match &outcome {
+                Ok((_, Some(json))) => json.len(),
+                Ok((result, None)) => serde_json::to_vec(result).map_or(0, |bytes| bytes.len()),
+                Err(_) => 0,
+            },
+        };
+        METRICS.execution_usage(&usage);
+        let report = Report {
+            logs: std::mem::take(&mut *logs.lock().unwrap()),
+            unhandled_rejections: rejections.messages(),
+            http: session.trace(),
+            planned_requests: dry_run.as_ref().map_or_else(Vec::new, |dry_run| dry_run.requests()),
+            debug_requests: request_log.map_or_else(Vec::new, |request_log| request_log.requests()),
+            recorded_http: recorder.map(|recorder| recorder.recording()),
+            code_bytes: code.len(),
+            duration: started.elapsed(),
+            queue_wait,
+            fetch_duration: session.fetch_duration(),
+            usage,
+            http_request_count: session.request_count(),
+            max_requests: max_requests.value,
+            timeout,
+            cpu_budget: cpu_budget.value,
+            max_result_bytes: max_result_bytes.value,
+            memory_bytes: memory_bytes.value,
+            max_fetch_body_bytes: max_fetch_body_bytes.value,
+            max_fetch_total_bytes: limits.max_fetch_total_bytes,
+            random_seed,
+            network_allowed,
+            code_cache_hit: code_cache_hit.load(Ordering::Relaxed),
+            result_cache_hit: false,
+            state_changed: state.changed(),
+            request_id,
+        };
+
+        // Exceeding the request limit fails the execution even if the script caught the error
+        if session.limit_exceeded() {
+            let message = format!(
+                "Execution attempted {} outbound requests, exceeding the {} limit of {}",
+                report.http_request_count, max_requests.name, max_requests.value
+            );
+            return Err(ExecError {
+                report: Some(Box::new(report)),
+                ..ExecError::new(ErrorKind::RequestLimit, message)
+            });
+        }
+        let unmatched = options.http_mocks.as_ref().and_then(|mocks| mocks.unmatched());
+        if let Some(message) = unmatched.or_else(|| options.replay_http.as_ref().and_then(|replay| replay.unmatched())) {
+            return Err(ExecError {
+                report: Some(Box::new(report)),
+                ..ExecError::new(ErrorKind::UnmatchedRequest, message)
+            });
+        }
+        match outcome {
+            // State changes are only kept when the execution succeeded, and not in dry runs
+            Ok((result, result_json)) if options.dry_run => Ok(Execution { result, result_json, report }),
+            Ok((result, result_json)) => match state.commit() {
+                Ok(()) => Ok(Execution { result, result_json, report }),
+                Err(message) => Err(ExecError {
+                    report: Some(Box::new(report)),
+                    ..ExecError::new(ErrorKind::Internal, message)
+                }),
+            },
+            Err(e) if e.is_out_of_memory() => Err(ExecError {
+                report: Some(Box::new(report)),
+                ..ExecError::new(
+                    ErrorKind::MemoryLimit,
+                    format!("Execution exceeded the {} limit of {} bytes", memory_bytes.name, memory_bytes.value),
+                )
+            }),
+            Err(e) => Err(ExecError {
+                report: Some(Box::new(report)),
+                ..e
+            }),
+        }
+    }
+}
+
+// A limit in effect for an execution, with the option or setting it comes from so
+// that errors can say which one was exceeded
+#[derive(Clone, Copy, Debug)]
+pub struct Limit<T> {
+    pub value: T,
+    pub name: &'static str,
+}
+
+impl<T: Ord + Copy> Limit<T> {
+    // The configured limit, or the option where it is stricter
+    pub fn tightened(setting: &'static str, limit: T, option: &'static str, requested: Option<T>) -> Self {
+        match requested {
+            Some(value) if value < limit => Limit { value, name: option },
+            _ => Limit { value: limit, name: setting },
+        }
+    }
+}
+
+fn collect(logs: Arc<Mutex<Vec<LogLine>>>) -> ConsoleSink {
+    Arc::new(move |level, message| {
+        let mut logs = logs.lock().unwrap();
+        if logs.len() < MAX_LOG_LINES {
+            logs.push(LogLine { level, message });
+        }
+    })
+}
@@ -0,0 +1,2072 @@
+// Outbound HTTP for the httpRequest function exposed to user code
+
+use crate::cache::{self, Cached, ResponseCache};
+use crate::circuit::CircuitBreakers;
+use crate::config::Config;
+use crate::executor::Limit;
+use crate::dry_run::DryRun;
+use crate::engine::ConsoleSink;
+use crate::policy::{self, OutboundPolicy, UrlPatterns};
+use crate::proxy::ProxyConfig;
+use crate::metrics::METRICS;
+use crate::secrets::Secrets;
+use crate::signing::Signing;
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use opentelemetry::global;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderValue, CONTENT_LENGTH, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// Same limit reqwest applies with its default redirect policy
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+// Upper bound for `maxRedirects`, which also bounds the number of cached client variants
+const MAX_REDIRECTS_LIMIT: usize = 20;
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const POOL_MAX_IDLE_PER_HOST: usize = 32;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 200;
+const DEFAULT_RETRY_ON: [u16; 3] = [502, 503, 504];
+const MAX_RETRY_BACKOFF_MS: u64 = 10_000;
+
+
+// Machine-readable reason a fetch produced no usable response. Absent for
+// completed HTTP exchanges, including 4xx/5xx statuses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Dns,
+    Connect,
+    Tls,
+    Timeout,
+    TooManyRedirects,
+    BodyTooLarge,
+    BlockedByPolicy,
+    InvalidRequest,
+    RequestLimitExceeded,
+    // No http_mocks entry or replayed response for the request
+    UnmatchedRequest,
+    // The script aborted the request with an AbortSignal
+    Aborted,
+    Network,
+    // The method isn't in OUTBOUND_ALLOWED_METHODS
+    MethodNotAllowed,
+    // The execution moved MAX_FETCH_TOTAL_BYTES already
+    BandwidthQuotaExceeded,
+    // The host failed too often lately, see circuit.rs
+    CircuitOpen,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 15] = [
+        ErrorCode::Dns,
+        ErrorCode::Connect,
+        ErrorCode::Tls,
+        ErrorCode::Timeout,
+        ErrorCode::TooManyRedirects,
+        ErrorCode::BodyTooLarge,
+        ErrorCode::BlockedByPolicy,
+        ErrorCode::InvalidRequest,
+        ErrorCode::RequestLimitExceeded,
+        ErrorCode::UnmatchedRequest,
+        ErrorCode::Aborted,
+        ErrorCode::Network,
+        ErrorCode::MethodNotAllowed,
+        ErrorCode::BandwidthQuotaExceeded,
+        ErrorCode::CircuitOpen,
+    ];
+    
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Dns => "dns",
+            ErrorCode::Connect => "connect",
+            ErrorCode::Tls => "tls",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::TooManyRedirects => "too_many_redirects",
+            ErrorCode::BodyTooLarge => "body_too_large",
+            ErrorCode::BlockedByPolicy => "blocked_by_policy",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::RequestLimitExceeded => "request_limit_exceeded",
+            ErrorCode::UnmatchedRequest => "unmatched_request",
+            ErrorCode::Aborted => "aborted",
+            ErrorCode::Network => "network",
+            ErrorCode::MethodNotAllowed => "method_not_allowed",
+            ErrorCode::BandwidthQuotaExceeded => "bandwidth_quota_exceeded",
+            ErrorCode::CircuitOpen => "circuit_open",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        ErrorCode::ALL
+            .into_iter()
+            .find(|known| known.as_str() == code)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown error code '{}'", code)))
+    }
+}
+
+// `Http.ErrorCode` constants injected into the JS context, e.g. `Http.ErrorCode.TIMEOUT`
+pub fn error_codes_js() -> String {
+    let entries: Vec<String> = ErrorCode::ALL
+        .iter()
+        .map(|code| format!("{}: \"{}\"", code.as_str().to_ascii_uppercase(), code.as_str()))
+        .collect();
+    format!(
+        "globalThis.Http = Object.freeze({{ ErrorCode: Object.freeze({{ {} }}) }});",
+        entries.join(", ")
+    )
+}
+
+// Read back from recorded HTTP traces, where everything but `status` may be left out
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpResult {
+    #[serde(default)]
+    pub ok: bool,
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    // Lowercase name -> value, repeated headers joined with ", "
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // Every header as a [name, value] pair, in the order received
+    #[serde(default)]
+    pub raw_headers: Vec<(String, String)>,
+    // The body parsed as JSON, "" when it isn't JSON
+    #[serde(default)]
+    pub data: Value,
+    // The body as it came, "" for requests without a response
+    #[serde(default)]
+    pub text: String,
+    // Why a body that isn't empty couldn't be parsed as JSON
+    #[serde(default)]
+    pub json_parse_error: Option<String>,
+    // The Content-Type header, lowercase
+    #[serde(default)]
+    pub content_type: Option<String>,
+    // The declared Content-Length
+    #[serde(default)]
+    pub content_length: Option<u64>,
+    // The URL that answered, after any redirects
+    #[serde(default)]
+    pub final_url: Option<String>,
+    #[serde(default)]
+    pub timing: Timing,
+    // The redirects followed on the way to `final_url`
+    #[serde(default)]
+    pub redirects: Vec<RedirectHop>,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub set_cookies: Vec<String>,
+    #[serde(default)]
+    pub from_cache: FromCache,
+    #[serde(default)]
+    pub error_code: Option<ErrorCode>,
+    // Sensitive headers left out because HEADER_FORWARD_ALLOWLIST doesn't allow
+    // the host they were headed for
+    #[serde(default)]
+    pub stripped_headers: Vec<String>,
+    // How long until the host's open circuit lets a request through again
+    #[serde(default)]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl HttpResult {
+    // Result for requests that never produced an HTTP response
+    pub fn failure(error_code: ErrorCode, status_text: &str, message: String) -> Self {
+        HttpResult {
+            ok: false,
+            status: 0,
+            status_text: status_text.to_string(),
+            headers: BTreeMap::new(),
+            raw_headers: Vec::new(),
+            data: Value::String(message),
+            text: String::new(),
+            json_parse_error: None,
+            content_type: None,
+            content_length: None,
+            final_url: None,
+            timing: Timing::default(),
+            redirects: Vec::new(),
+            attempts: 0,
+            set_cookies: Vec::new(),
+            from_cache: FromCache::No,
+            error_code: Some(error_code),
+            stripped_headers: Vec::new(),
+            retry_after_ms: None,
+        }
+    }
+}
+
+// Whether a result came from the response cache: `false`, `true`, or "revalidated"
+// for a stale entry the upstream confirmed with a 304
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FromCache {
+    #[default]
+    No,
+    Yes,
+    Revalidated,
+}
+
+impl Serialize for FromCache {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FromCache::No => serializer.serialize_bool(false),
+            FromCache::Yes => serializer.serialize_bool(true),
+            FromCache::Revalidated => serializer.serialize_str("revalidated"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FromCache {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Bool(false) => Ok(FromCache::No),
+            Value::Bool(true) => Ok(FromCache::Yes),
+            Value::String(value) if value == "revalidated" => Ok(FromCache::Revalidated),
+            other => Err(serde::de::Error::custom(format!("invalid fromCache {}", other))),
+        }
+    }
+}
+
+// How long a request took, across all its attempts
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Timing {
+    // From the call until the result was ready, including the wait for a slot
+    pub total_ms: u64,
+    // From the first attempt until the response headers of the last one, for
+    // requests that got a response from the network
+    pub ttfb_ms: Option<u64>,
+}
+
+// A response that redirected the request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+    // What changed about the request on the way to the next URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Serialize for HttpResult {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("HttpResult", 19)?;
+        state.serialize_field("ok", &self.ok)?;
+        state.serialize_field("status", &self.status)?;
+        state.serialize_field("statusText", &self.status_text)?;
+        state.serialize_field("headers", &self.headers)?;
+        state.serialize_field("rawHeaders", &self.raw_headers)?;
+        state.serialize_field("data", &self.data)?;
+        state.serialize_field("text", &self.text)?;
+        state.serialize_field("jsonParseError", &self.json_parse_error)?;
+        state.serialize_field("contentType", &self.content_type)?;
+        state.serialize_field("contentLength", &self.content_length)?;
+        state.serialize_field("finalUrl", &self.final_url)?;
+        state.serialize_field("timing", &self.timing)?;
+        state.serialize_field("redirects", &self.redirects)?;
+        state.serialize_field("attempts", &self.attempts)?;
+        state.serialize_field("setCookies", &self.set_cookies)?;
+        state.serialize_field("fromCache", &self.from_cache)?;
+        state.serialize_field("errorCode", &self.error_code)?;
+        if self.stripped_headers.is_empty() {
+            state.skip_field("strippedHeaders")?;
+        } else {
+            state.serialize_field("strippedHeaders", &self.stripped_headers)?;
+        }
+        match self.retry_after_ms {
+            Some(retry_after_ms) => state.serialize_field("retryAfterMs", &retry_after_ms)?,
+            None => state.skip_field("retryAfterMs")?,
+        }
+        state.end()
+    }
+}
+
+// Per-execution state shared by every httpRequest call of one /execute request
+pub struct FetchSession {
+    cookies: Arc<Jar>,
+    max_requests: u32,
+    // Every httpRequest call, including the ones refused for exceeding `max_requests`
+    requests: AtomicU32,
+    // Size limit for response bodies, FETCH_MAX_BODY_BYTES unless set
+    max_body_bytes: Option<Limit<usize>>,
+    fetch_time: Mutex<FetchTime>,
+    // Id of the request this execution belongs to
+    request_id: String,
+    trace: Mutex<Vec<HttpTrace>>,
+    // Responses handed back to the script so far
+    responses: AtomicU32,
+    // Body bytes of the responses that came from upstreams, i.e. not from the cache
+    fetched_bytes: AtomicU64,
+    // Body bytes of the requests sent, once for every attempt
+    sent_bytes: AtomicU64,
+    // MAX_FETCH_TOTAL_BYTES, for fetched and sent bytes together
+    max_total_bytes: Option<u64>,
+    // Records the requests instead, in a dry run
+    dry_run: Option<Arc<DryRun>>,
+    concurrency: FetchConcurrency,
+    // Where warnings about the requests go in the execution's logs
+    console: Option<ConsoleSink>,
+    // The tenant the execution runs for, whose responses are cached apart from
+    // other tenants'
+    tenant: Option<String>,
+    // The tenant's allowlist, on top of the outbound policy
+    allowlist: Option<Arc<UrlPatterns>>,
+    // Bodies httpGetStream is reading, by the id handed to the script
+    streams: Mutex<HashMap<u32, BodyStream>>,
+    next_stream: AtomicU32,
+    // The execution's SECRETS, which `options.sign` references
+    secrets: Arc<Secrets>,
+}
+
+// A streamed body and what has been read of it
+struct BodyStream {
+    body: ResponseBody,
+    method: String,
+    url: String,
+    // Chunks are handed over as strings rather than bytes
+    text: bool,
+    // The start of a UTF-8 sequence the last chunk cut off
+    partial: Vec<u8>,
+    bytes_read: u64,
+    // Why reading stopped before the end
+    failure: Option<HttpResult>,
+}
+
+// One chunk of a streamed body
+pub enum Chunk {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+// Caps the requests of one execution in flight at once, in total and per host. A
+// request waits for its host's slot before it takes one of the total, so requests
+// queued for a busy host don't hold up the others.
+struct FetchConcurrency {
+    total: Arc<Semaphore>,
+    // No limit per host when 0
+    per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl FetchConcurrency {
+    fn new(total: usize, per_host: usize) -> Self {
+        FetchConcurrency {
+            total: Arc::new(Semaphore::new(match total {
+                0 => Semaphore::MAX_PERMITS,
+                total => total,
+            })),
+            per_host,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn acquire(&self, url: &str) -> (Option<OwnedSemaphorePermit>, OwnedSemaphorePermit) {
+        let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+        let host_slots = match (self.per_host, host) {
+            (0, _) | (_, None) => None,
+            (per_host, Some(host)) => {
+                Some(self.hosts.lock().unwrap().entry(host).or_insert_with(|| Arc::new(Semaphore::new(per_host))).clone())
+            }
+        };
+        // Never closed
+        let host_permit = match host_slots {
+            Some(slots) => Some(slots.acquire_owned().await.unwrap()),
+            None => None,
+        };
+        (host_permit, self.total.clone().acquire_owned().await.unwrap())
+    }
+}
+
+// One httpRequest call as it is reported in the execution's trace
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpTrace {
+    pub method: String,
+    pub url: String,
+    // None for requests that got no response
+    pub status: Option<u16>,
+    pub error_code: Option<ErrorCode>,
+    pub from_cache: bool,
+    pub attempts: u32,
+    pub duration_ms: u64,
+}
+
+// Wall-clock time during which at least one request was in flight, so concurrent
+// requests aren't counted twice
+#[derive(Default)]
+struct FetchTime {
+    in_flight: u32,
+    busy_since: Option<Instant>,
+    total: Duration,
+}
+
+// One request in flight, for as long as it lives: an aborted request is dropped
+// without finishing
+struct InFlight<'a>(&'a Mutex<FetchTime>);
+
+impl<'a> InFlight<'a> {
+    fn start(time: &'a Mutex<FetchTime>) -> Self {
+        let mut guard = time.lock().unwrap();
+        if guard.in_flight == 0 {
+            guard.busy_since = Some(Instant::now());
+        }
+        guard.in_flight += 1;
+        InFlight(time)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut time = self.0.lock().unwrap();
+        time.in_flight -= 1;
+        if time.in_flight == 0 {
+            if let Some(since) = time.busy_since.take() {
+                time.total += since.elapsed();
+            }
+        }
+    }
+}
+
+impl FetchSession {
+    pub fn new(max_requests: u32, request_id: String) -> Self {
+        FetchSession {
+            cookies: Arc::new(Jar::default()),
+            max_requests,
+            requests: AtomicU32::new(0),
+            max_body_bytes: None,
+            fetch_time: Mutex::new(FetchTime::default()),
+            request_id,
+            trace: Mutex::new(Vec::new()),
+            responses: AtomicU32::new(0),
+            fetched_bytes: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            max_total_bytes: None,
+            dry_run: None,
+            concurrency: FetchConcurrency::new(0, 0),
+            console: None,
+            tenant: None,
+            allowlist: None,
+            streams: Mutex::new(HashMap::new()),
+            next_stream: AtomicU32::new(1),
+            secrets: Arc::new(Secrets::default()),
+        }
+    }
+
+    // At most `total` requests in flight at once, and `per_host` to any one host; 0
+    // for no limit
+    pub fn with_concurrency(self, total: usize, per_host: usize) -> Self {
+        FetchSession {
+            concurrency: FetchConcurrency::new(total, per_host),
+            ..self
+        }
+    }
+
+    pub fn with_max_total_bytes(self, max_total_bytes: u64) -> Self {
+        FetchSession {
+            max_total_bytes: Some(max_total_bytes),
+            ..self
+        }
+    }
+
+    pub fn with_console(self, console: ConsoleSink) -> Self {
+        FetchSession {
+            console: Some(console),
+            ..self
+        }
+    }
+
+    pub fn with_tenant(self, tenant: String, allowlist: Option<Arc<UrlPatterns>>) -> Self {
+        FetchSession {
+            tenant: Some(tenant),
+            allowlist,
+            ..self
+        }
+    }
+
+    pub fn with_secrets(self, secrets: Arc<Secrets>) -> Self {
+        FetchSession {
+            secrets,
+            ..self
+        }
+    }
+
+    pub fn with_dry_run(self, dry_run: Arc<DryRun>) -> Self {
+        FetchSession {
+            dry_run: Some(dry_run),
+            ..self
+        }
+    }
+
+    // One httpRequest call, made when the script calls it rather than when the
+    // request starts, so that a dry run knows which responses the script had seen.
+    // Notifying `abort` drops the request wherever it is, waiting for a slot or
+    // halfway through the body, and ends it as `aborted`.
+    pub fn request(
+        self: &Arc<Self>,
+        backend: Arc<dyn HttpBackend>,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+        abort: Option<Arc<Notify>>,
+    ) -> impl Future<Output = HttpResult> + Send + 'static {
+        let session = self.clone();
+        let dependent = self.responses.load(Ordering::Relaxed) > 0;
+        async move { session.fetch(&*backend, url, options, dependent, abort).await }
+    }
+    
+    pub fn with_max_body_bytes(self, max_body_bytes: Limit<usize>) -> Self {
+        FetchSession {
+            max_body_bytes: Some(max_body_bytes),
+            ..self
+        }
+    }
+    
+    // Counted towards `max_requests` and recorded in the trace
+    async fn fetch(
+        &self,
+        backend: &dyn HttpBackend,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+        dependent: bool,
+        abort: Option<Arc<Notify>>,
+    ) -> HttpResult {
+        let called = Instant::now();
+        if let Some(refused) = self.count_request() {
+            return refused;
+        }
+        
+        let method = request_method(options.as_ref());
+        let exchange = async {
+            if let Some(refused) = self.over_quota(&method, &url) {
+                return (refused, Instant::now());
+            }
+            // Not counted in the request's duration
+            let _permits = match &self.dry_run {
+                Some(_) => None,
+                None => Some(self.concurrency.acquire(&url).await),
+            };
+            let started = Instant::now();
+            let result = match &self.dry_run {
+                Some(dry_run) => dry_run.plan(url.clone(), options.as_ref(), dependent),
+                None => self.timed(backend.fetch(self, url.clone(), options)).await,
+            };
+            (result, started)
+        };
+        let (mut result, started) = match abort {
+            Some(abort) => tokio::select! {
+                done = exchange => done,
+                _ = abort.notified() => {
+                    let message = format!("{} {} was aborted", method, url);
+                    (HttpResult::failure(ErrorCode::Aborted, "Aborted", message), called)
+                }
+            },
+            None => exchange.await,
+        };
+        result.timing.total_ms = called.elapsed().as_millis() as u64;
+        if result.error_code.is_none() && result.from_cache == FromCache::No && self.dry_run.is_none() {
+            self.fetched_bytes.fetch_add(result.text.len() as u64, Ordering::Relaxed);
+        }
+        self.finish(method, url, started, &result);
+        result
+    }
+    
+    // One httpGetStream call: the response with an empty body, and the id its body
+    // is read by when there is one. The request counts like any other, and the
+    // chunks count towards MAX_FETCH_TOTAL_BYTES as they are read; FETCH_MAX_BODY_BYTES
+    // doesn't apply, since the body is never held whole.
+    pub fn open_stream(
+        self: &Arc<Self>,
+        backend: Arc<dyn HttpBackend>,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> impl Future<Output = (HttpResult, Option<u32>)> + Send + 'static {
+        let session = self.clone();
+        let dependent = self.responses.load(Ordering::Relaxed) > 0;
+        async move {
+            let called = Instant::now();
+            if let Some(refused) = session.count_request() {
+                return (refused, None);
+            }
+            let method = request_method(options.as_ref());
+            if let Some(refused) = session.over_quota(&method, &url) {
+                return (refused, None);
+            }
+            // Only held until the response arrives, so the callbacks can make
+            // requests of their own to the same host
+            let permits = match &session.dry_run {
+                Some(_) => None,
+                None => Some(session.concurrency.acquire(&url).await),
+            };
+            let started = Instant::now();
+            let (mut result, body) = match &session.dry_run {
+                Some(dry_run) => {
+                    let mut result = dry_run.plan(url.clone(), options.as_ref(), dependent);
+                    let body = ResponseBody::Buffered(Some(std::mem::take(&mut result.text).into_bytes()));
+                    (result, body)
+                }
+                None => session.timed(backend.stream(&session, url.clone(), options)).await,
+            };
+            drop(permits);
+            result.timing.total_ms = called.elapsed().as_millis() as u64;
+            session.finish(method.clone(), url.clone(), started, &result);
+            if result.error_code.is_some() {
+                return (result, None);
+            }
+            let id = session.next_stream.fetch_add(1, Ordering::Relaxed);
+            let text = result.content_type.as_deref().is_some_and(is_text);
+            let stream = BodyStream { body, method, url, text, partial: Vec::new(), bytes_read: 0, failure: None };
+            session.streams.lock().unwrap().insert(id, stream);
+            (result, Some(id))
+        }
+    }
+    
+    // The next chunk of a streamed body, None once it ended or failed. Nothing more
+    // is downloaded until the script asks for the next one.
+    pub fn next_chunk(self: &Arc<Self>, id: u32) -> impl Future<Output = Option<Chunk>> + Send + 'static {
+        let session = self.clone();
+        async move {
+            let mut stream = session.streams.lock().unwrap().remove(&id)?;
+            let chunk = session.read_chunk(&mut stream).await;
+            session.streams.lock().unwrap().insert(id, stream);
+            chunk
+        }
+    }
+    
+    async fn read_chunk(&self, stream: &mut BodyStream) -> Option<Chunk> {
+        if stream.failure.is_some() {
+            return None;
+        }
+        loop {
+            let bytes = match self.timed(stream.body.chunk()).await {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) if stream.partial.is_empty() => return None,
+                // A sequence cut off by the end of the body
+                Ok(None) => return Some(Chunk::Text(String::from_utf8_lossy(&std::mem::take(&mut stream.partial)).into_owned())),
+                Err(e) => {
+                    let message = format!("Fetch failed: {} {} broke off after {} bytes: {}", stream.method, stream.url, stream.bytes_read, e);
+                    stream.failure = Some(HttpResult::failure(transport_error_code(&e), "Error", message));
+                    return None;
+                }
+            };
+            stream.bytes_read += bytes.len() as u64;
+            if self.dry_run.is_none() {
+                self.fetched_bytes.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            }
+            let moved = self.fetched_bytes() + self.sent_bytes();
+            if let Some(max_total_bytes) = self.max_total_bytes.filter(|max| moved > *max) {
+                stream.failure = Some(HttpResult::failure(
+                    ErrorCode::BandwidthQuotaExceeded,
+                    "Bandwidth Quota Exceeded",
+                    format!(
+                        "{} {} was cut off after {} bytes: the execution moved {} bytes, MAX_FETCH_TOTAL_BYTES is {}",
+                        stream.method, stream.url, stream.bytes_read, moved, max_total_bytes
+                    ),
+                ));
+                return None;
+            }
+            if !stream.text {
+                return Some(Chunk::Bytes(bytes));
+            }
+            let mut bytes = std::mem::take(&mut stream.partial).into_iter().chain(bytes).collect::<Vec<u8>>();
+            let complete = match std::str::from_utf8(&bytes) {
+                Ok(_) => bytes.len(),
+                // An incomplete sequence at the end waits for the next chunk
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => bytes.len(),
+            };
+            stream.partial = bytes.split_off(complete);
+            if !bytes.is_empty() {
+                return Some(Chunk::Text(String::from_utf8_lossy(&bytes).into_owned()));
+            }
+        }
+    }
+    
+    // Stops reading a streamed body: how many bytes were read, and why it ended
+    // early if it did
+    pub fn close_stream(&self, id: u32) -> (u64, Option<HttpResult>) {
+        match self.streams.lock().unwrap().remove(&id) {
+            Some(stream) => (stream.bytes_read, stream.failure),
+            None => (0, None),
+        }
+    }
+    
+    // Refuses the request past `max_requests`
+    fn count_request(&self) -> Option<HttpResult> {
+        let request_number = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        (request_number > self.max_requests).then(|| {
+            HttpResult::failure(
+                ErrorCode::RequestLimitExceeded,
+                "Request Limit Exceeded",
+                format!(
+                    "Execution exceeded the limit of {} outbound requests",
+                    self.max_requests
+                ),
+            )
+        })
+    }
+    
+    // Counts the response handed back and records it in the trace
+    fn finish(&self, method: String, url: String, started: Instant, result: &HttpResult) {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        if let (Some(console), false) = (&self.console, result.stripped_headers.is_empty()) {
+            console(
+                "warn".to_string(),
+                format!(
+                    "{} left out of {} {}: only sent to hosts in HEADER_FORWARD_ALLOWLIST",
+                    result.stripped_headers.join(", "),
+                    method,
+                    url
+                ),
+            );
+        }
+        self.trace.lock().unwrap().push(HttpTrace {
+            method,
+            url,
+            status: result.error_code.is_none().then_some(result.status),
+            error_code: result.error_code,
+            from_cache: result.from_cache != FromCache::No,
+            attempts: result.attempts,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+    
+    // Requests already in flight when the quota runs out finish; only later ones
+    // are refused
+    fn over_quota(&self, method: &str, url: &str) -> Option<HttpResult> {
+        let moved = self.fetched_bytes() + self.sent_bytes();
+        let max_total_bytes = self.max_total_bytes.filter(|max| moved >= *max)?;
+        Some(HttpResult::failure(
+            ErrorCode::BandwidthQuotaExceeded,
+            "Bandwidth Quota Exceeded",
+            format!(
+                "{} {} wasn't sent: the execution already moved {} bytes, MAX_FETCH_TOTAL_BYTES is {}",
+                method, url, moved, max_total_bytes
+            ),
+        ))
+    }
+    
+    // Runs a fetch while accounting its duration towards `fetch_duration`
+    async fn timed<F: Future>(&self, fetch: F) -> F::Output {
+        let _in_flight = InFlight::start(&self.fetch_time);
+        fetch.await
+    }
+    
+    pub fn fetch_duration(&self) -> Duration {
+        let time = self.fetch_time.lock().unwrap();
+        time.total + time.busy_since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+    
+    pub fn fetched_bytes(&self) -> u64 {
+        self.fetched_bytes.load(Ordering::Relaxed)
+    }
+    
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+    
+    pub fn max_requests(&self) -> u32 {
+        self.max_requests
+    }
+    
+    pub fn request_count(&self) -> u32 {
+        self.requests.load(Ordering::Relaxed)
+    }
+    
+    pub fn limit_exceeded(&self) -> bool {
+        self.request_count() > self.max_requests
+    }
+    
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+    
+    pub fn trace(&self) -> Vec<HttpTrace> {
+        self.trace.lock().unwrap().clone()
+    }
+}
+
+fn request_method(options: Option<&HashMap<String, Value>>) -> String {
+    options
+        .and_then(|o| o.get("method"))
+        .and_then(|m| m.as_str())
+        .unwrap_or("GET")
+        .to_ascii_uppercase()
+}
+
+// Content types whose streamed chunks are strings
+fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || essence.ends_with("json")
+        || essence.ends_with("xml")
+        || essence.ends_with("javascript")
+        || essence == "application/x-www-form-urlencoded"
+}
+
+// Where httpRequest calls are sent. HttpClients sends them over the network; other
+// implementations can answer them in-process, e.g. with canned responses in tests.
+pub trait HttpBackend: Send + Sync {
+    fn fetch<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>>;
+
+    // A request whose body httpGetStream reads in chunks, answered with an empty
+    // body until then. Backends that only have whole responses hand the body over
+    // as a single chunk.
+    fn stream<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = (HttpResult, ResponseBody)> + Send + 'a>> {
+        Box::pin(async move {
+            let mut result = self.fetch(session, url, options).await;
+            let body = ResponseBody::Buffered(Some(std::mem::take(&mut result.text).into_bytes()));
+            result.data = Value::String(String::new());
+            (result, body)
+        })
+    }
+
+    // The breakers of the hosts, for backends that send requests over the network
+    fn circuits(&self) -> Option<&CircuitBreakers> {
+        None
+    }
+}
+
+impl HttpBackend for HttpClients {
+    fn fetch<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        Box::pin(perform_fetch(self, session, url, options, None))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = (HttpResult, ResponseBody)> + Send + 'a>> {
+        Box::pin(async move {
+            let mut response = None;
+            let result = perform_fetch(self, session, url, options, Some(&mut response)).await;
+            let body = match response {
+                Some(response) => ResponseBody::Network(response),
+                None => ResponseBody::Buffered(None),
+            };
+            (result, body)
+        })
+    }
+
+    fn circuits(&self) -> Option<&CircuitBreakers> {
+        Some(&self.circuits)
+    }
+}
+
+// The body of a response to httpGetStream, read as the script asks for it
+pub enum ResponseBody {
+    Network(reqwest::Response),
+    Buffered(Option<Vec<u8>>),
+}
+
+impl ResponseBody {
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, reqwest::Error> {
+        match self {
+            ResponseBody::Network(response) => Ok(response.chunk().await?.map(|chunk| chunk.to_vec())),
+            ResponseBody::Buffered(body) => Ok(body.take().filter(|body| !body.is_empty())),
+        }
+    }
+}
+
+tokio::task_local! {
+    static SESSION_COOKIES: Arc<Jar>;
+    static REDIRECTS: Arc<RedirectChain>;
+}
+
+// The redirects of the request currently being sent, collected by the redirect
+// policy of the shared clients like cookies are
+struct RedirectChain {
+    // Whether the request carries an Authorization header, which reqwest drops on
+    // redirects to another host
+    authorization: bool,
+    // The sensitive headers of the request that reqwest keeps on redirects to
+    // another host, as set by the script
+    sensitive: Mutex<Vec<String>>,
+    hops: Mutex<Vec<RedirectHop>>,
+    // Sensitive headers left out on the way, for hosts outside HEADER_FORWARD_ALLOWLIST
+    stripped: Mutex<Vec<String>>,
+    // A redirect reqwest must not follow because it would take along headers that
+    // have to be left out: where it leads, and which of them
+    resume: Mutex<Option<(reqwest::Url, Vec<String>)>>,
+    // The tenant's allowlist, which redirect targets must match too
+    allowlist: Option<Arc<UrlPatterns>>,
+}
+
+// What reqwest removes by itself on redirects to another host
+const REMOVED_ACROSS_ORIGINS: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+fn removed_across_origins(header: &str) -> bool {
+    REMOVED_ACROSS_ORIGINS.iter().any(|name| name.eq_ignore_ascii_case(header))
+}
+
+impl RedirectChain {
+    fn new(authorization: bool, sensitive: Vec<String>, allowlist: Option<Arc<UrlPatterns>>) -> Self {
+        RedirectChain {
+            authorization,
+            sensitive: Mutex::new(sensitive),
+            hops: Mutex::new(Vec::new()),
+            stripped: Mutex::new(Vec::new()),
+            resume: Mutex::new(None),
+            allowlist,
+        }
+    }
+
+    fn check_allowlist(url: &reqwest::Url) -> Result<(), policy::BlockedError> {
+        REDIRECTS
+            .try_with(|chain| chain.allowlist.as_ref().map_or(Ok(()), |allowlist| allowlist.check(url)))
+            .unwrap_or(Ok(()))
+    }
+
+    // Records the hop, returning the sensitive headers that must be left out on the
+    // way to its target
+    fn record(attempt: &reqwest::redirect::Attempt<'_>, policy: &OutboundPolicy) -> Vec<String> {
+        let Some(from) = attempt.previous().last() else {
+            return Vec::new();
+        };
+        REDIRECTS
+            .try_with(|chain| {
+                let cross_origin = from.host_str() != attempt.url().host_str()
+                    || from.port_or_known_default() != attempt.url().port_or_known_default();
+                let mut notes = Vec::new();
+                if cross_origin && chain.authorization {
+                    notes.push("Authorization header removed for the redirect to another origin".to_string());
+                }
+                let mut blocked = Vec::new();
+                if cross_origin {
+                    let mut sensitive = chain.sensitive.lock().unwrap();
+                    let (kept, left_out) =
+                        sensitive.drain(..).partition(|name| policy.forwards_header(name, attempt.url()));
+                    *sensitive = kept;
+                    blocked = left_out;
+                }
+                if !blocked.is_empty() {
+                    notes.push(format!(
+                        "{} removed for the redirect to a host outside HEADER_FORWARD_ALLOWLIST",
+                        blocked.join(", ")
+                    ));
+                    chain.stripped.lock().unwrap().extend(blocked.iter().cloned());
+                }
+                chain.hops.lock().unwrap().push(RedirectHop {
+                    url: from.to_string(),
+                    status: attempt.status().as_u16(),
+                    note: (!notes.is_empty()).then(|| notes.join("; ")),
+                });
+                blocked
+            })
+            .unwrap_or_default()
+    }
+
+    fn hop_count() -> Option<usize> {
+        REDIRECTS.try_with(|chain| chain.hops.lock().unwrap().len()).ok()
+    }
+
+    fn resume_at(url: &reqwest::Url, stripped: Vec<String>) {
+        let _ = REDIRECTS.try_with(|chain| *chain.resume.lock().unwrap() = Some((url.clone(), stripped)));
+    }
+}
+
+// The request a redirect leads to, as reqwest would send it, minus `stripped`.
+// None when its body can't be sent again.
+fn redirected(
+    request: &reqwest::Request,
+    status: reqwest::StatusCode,
+    url: reqwest::Url,
+    stripped: &[String],
+) -> Option<reqwest::Request> {
+    let mut next = request.try_clone()?;
+    let method = next.method().clone();
+    let to_get = (status == reqwest::StatusCode::SEE_OTHER && method != reqwest::Method::HEAD)
+        || (matches!(status, reqwest::StatusCode::MOVED_PERMANENTLY | reqwest::StatusCode::FOUND)
+            && method == reqwest::Method::POST);
+    if to_get {
+        *next.method_mut() = reqwest::Method::GET;
+        *next.body_mut() = None;
+        for name in ["content-type", "content-length", "content-encoding", "transfer-encoding"] {
+            next.headers_mut().remove(name);
+        }
+    }
+    // Only resumed for redirects to another host
+    for name in REMOVED_ACROSS_ORIGINS.iter().copied().chain(stripped.iter().map(String::as_str)) {
+        next.headers_mut().remove(name);
+    }
+    *next.url_mut() = url;
+    Some(next)
+}
+
+// Sends one request of a chain, within the execution's cookie jar unless `cookies`
+// is None
+async fn send(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    chain: &Arc<RedirectChain>,
+    cookies: Option<&Arc<Jar>>,
+) -> reqwest::Result<reqwest::Response> {
+    // reqwest looks up the cookies when `execute` is called, not when it is polled,
+    // so the call has to happen within the scopes
+    let send = REDIRECTS.scope(chain.clone(), async move { client.execute(request).await });
+    match cookies {
+        Some(jar) => SESSION_COOKIES.scope(jar.clone(), send).await,
+        None => send.await,
+    }
+}
+
+// Cookie provider installed on the shared clients. It forwards to the jar of the
+// execution currently sending a request (scoped as a task-local around the send),
+// so the clients stay shared while cookies never leak between executions.
+struct SessionCookieStore;
+
+impl CookieStore for SessionCookieStore {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &reqwest::Url) {
+        let _ = SESSION_COOKIES.try_with(|jar| jar.set_cookies(cookie_headers, url));
+    }
+    
+    fn cookies(&self, url: &reqwest::Url) -> Option<HeaderValue> {
+        SESSION_COOKIES.try_with(|jar| jar.cookies(url)).ok().flatten()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum RedirectMode {
+    Follow(usize),
+    Manual,
+}
+
+// Per-call options that reqwest only supports at the client level
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ClientVariant {
+    redirect: RedirectMode,
+    insecure_tls: bool,
+}
+
+// Outbound clients shared by all executions, so connections and TLS sessions are
+// pooled across requests. Settings reqwest only supports per client (redirect
+// policy, TLS verification) get their own lazily built variant, cached for reuse.
+pub struct HttpClients {
+    policy: OutboundPolicy,
+    proxy: ProxyConfig,
+    // Extra roots from OUTBOUND_CA_BUNDLE
+    ca_certificates: Vec<reqwest::Certificate>,
+    // Whether `options.insecureSkipTlsVerify` may be honored (ALLOW_INSECURE_TLS)
+    allow_insecure_tls: bool,
+    // OUTBOUND_ALLOWED_METHODS in uppercase, every method when empty
+    allowed_methods: Vec<String>,
+    default_timeout_ms: u64,
+    // Server-side cap for `options.retry.attempts`
+    max_attempts: u32,
+    max_body_bytes: usize,
+    // Whether outbound requests carry the X-Request-Id of their execution
+    send_request_id: bool,
+    user_agent: String,
+    // Whether that User-Agent carries the request id (UA_INCLUDE_REQUEST_ID)
+    ua_include_request_id: bool,
+    // The `[signing_secrets]`, for `options.sign`
+    signing_secrets: BTreeMap<String, String>,
+    cache: ResponseCache,
+    circuits: CircuitBreakers,
+    variants: Mutex<HashMap<ClientVariant, reqwest::Client>>,
+}
+
+impl HttpClients {
+    pub fn new(config: &Config, policy: OutboundPolicy, proxy: ProxyConfig) -> Result<Self, String> {
+        let clients = HttpClients {
+            policy: policy.with_trusted_hosts(proxy.hosts()),
+            proxy,
+            ca_certificates: load_ca_bundle(&config.outbound_ca_bundle)?,
+            allow_insecure_tls: config.allow_insecure_tls,
+            allowed_methods: allowed_methods(&config.outbound_allowed_methods)?,
+            default_timeout_ms: config.fetch_timeout_ms,
+            max_attempts: config.fetch_max_attempts,
+            max_body_bytes: config.fetch_max_body_bytes,
+            send_request_id: config.outbound_request_id,
+            user_agent: config.outbound_user_agent.clone(),
+            ua_include_request_id: config.ua_include_request_id,
+            signing_secrets: config.signing_secrets.clone(),
+            cache: ResponseCache::from_config(config),
+            circuits: CircuitBreakers::from_config(config),
+            variants: Mutex::new(HashMap::new()),
+        };
+        
+        // Build the default client up front so configuration errors surface at startup
+        clients
+            .client(ClientVariant {
+                redirect: RedirectMode::Follow(DEFAULT_MAX_REDIRECTS),
+                insecure_tls: false,
+            })
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+        Ok(clients)
+    }
+    
+    fn client(&self, variant: ClientVariant) -> reqwest::Result<reqwest::Client> {
+        let mut variants = self.variants.lock().unwrap();
+        if let Some(client) = variants.get(&variant) {
+            return Ok(client.clone());
+        }
+        
+        let client = self.build_client(variant)?;
+        variants.insert(variant, client.clone());
+        Ok(client)
+    }
+    
+    fn build_client(&self, variant: ClientVariant) -> reqwest::Result<reqwest::Client> {
+        let redirect_policy = match variant.redirect {
+            RedirectMode::Manual => reqwest::redirect::Policy::none(),
+            RedirectMode::Follow(max_redirects) => {
+                let policy = self.policy.clone();
+                reqwest::redirect::Policy::custom(move |attempt| {
+                    let stripped = RedirectChain::record(&attempt, &policy);
+                    // Redirect targets go through the same outbound policy as the initial URL
+                    if let Err(e) = policy.check_url(attempt.url()).and_then(|()| RedirectChain::check_allowlist(attempt.url())) {
+                        return attempt.error(e);
+                    }
+                    // Counted across the requests a resumed chain is made of
+                    if RedirectChain::hop_count().unwrap_or(attempt.previous().len()) > max_redirects {
+                        attempt.error("too many redirects")
+                    } else if !stripped.is_empty() {
+                        // fetch() follows it without those headers
+                        RedirectChain::resume_at(attempt.url(), stripped);
+                        attempt.stop()
+                    } else {
+                        attempt.follow()
+                    }
+                })
+            }
+        };
+        
+        let mut builder = reqwest::Client::builder()
+            .redirect(redirect_policy)
+            .timeout(Duration::from_millis(self.default_timeout_ms))
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+            .cookie_provider(Arc::new(SessionCookieStore));
+        if !self.user_agent.is_empty() {
+            builder = builder.user_agent(self.user_agent.as_str());
+        }
+        if let Some(resolver) = self.policy.resolver() {
+            builder = builder.dns_resolver(resolver);
+        }
+        for certificate in &self.ca_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if variant.insecure_tls {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        self.proxy.apply(builder).build()
+    }
+}
+
+// Loads OUTBOUND_CA_BUNDLE; a configured but unreadable bundle is a startup error
+fn load_ca_bundle(path: &str) -> Result<Vec<reqwest::Certificate>, String> {
+    if path.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    
+    let pem = std::fs::read(path)
+        .map_err(|e| format!("Failed to read OUTBOUND_CA_BUNDLE {}: {}", path, e))?;
+    let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+        .map_err(|e| format!("Failed to parse OUTBOUND_CA_BUNDLE {}: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("OUTBOUND_CA_BUNDLE {} contains no certificates", path));
+    }
+    
+    tracing::info!("Loaded {} CA certificate(s) from {}", certificates.len(), path);
+    Ok(certificates)
+}
+
+fn allowed_methods(methods: &[String]) -> Result<Vec<String>, String> {
+    methods
+        .iter()
+        .map(|method| match reqwest::Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes()) {
+            Ok(method) => Ok(method.to_string()),
+            Err(_) => Err(format!("Invalid OUTBOUND_ALLOWED_METHODS entry '{}'", method)),
+        })
+        .collect()
+}
+
+// Logged as a `fetch` span per request. Only the host is recorded, since paths and
+// query strings may carry credentials.
+// With `stream`, the response is left in it with its body unread
+pub async fn perform_fetch(
+    clients: &HttpClients,
+    session: &FetchSession,
+    url: String,
+    options: Option<HashMap<String, Value>>,
+    stream: Option<&mut Option<reqwest::Response>>,
+) -> HttpResult {
+    let started = Instant::now();
+    let host = reqwest::Url::parse(&url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let method = options
+        .as_ref()
+        .and_then(|o| o.get("method"))
+        .and_then(|m| m.as_str())
+        .unwrap_or("GET")
+        .to_ascii_uppercase();
+    // The circuit tells hosts on different ports apart
+    let authority = reqwest::Url::parse(&url)
+        .ok()
+        .and_then(|url| Some(format!("{}:{}", url.host_str()?, url.port_or_known_default()?)))
+        .unwrap_or_default();
+    let admitted = match clients.circuits.admit(&authority, &method, &url) {
+        Ok(admitted) => admitted,
+        Err(refused) => return *refused,
+    };
+    let span = tracing::info_span!("fetch", host = %host, method = %method, status = tracing::field::Empty);
+    let result = fetch(clients, session, url, options, stream).instrument(span.clone()).await;
+    if let Some(admitted) = admitted {
+        admitted.record(&result);
+    }
+    let status = result.error_code.is_none().then_some(result.status);
+    if let Some(status) = status {
+        span.record("status", status);
+    }
+    METRICS.outbound_request(&host, status, started.elapsed());
+    tracing::debug!(
+        parent: &span,
+        error_code = result.error_code.map(ErrorCode::as_str),
+        duration_ms = started.elapsed().as_millis() as u64,
+        "Outbound request finished"
+    );
+    result
+}
+
+async fn fetch(
+    clients: &HttpClients,
+    session: &FetchSession,
+    url: String,
+    options: Option<HashMap<String, Value>>,
+    mut stream: Option<&mut Option<reqwest::Response>>,
+) -> HttpResult {
+    let policy = &clients.policy;
+    
+    let parsed_url = match reqwest::Url::parse(&url) {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", format!("Invalid URL {}: {}", url, e)),
+    };
+    let allowed = policy
+        .check_url(&parsed_url)
+        .and_then(|()| session.allowlist.as_ref().map_or(Ok(()), |allowlist| allowlist.check(&parsed_url)));
+    if let Err(e) = allowed {
+        return HttpResult::failure(ErrorCode::BlockedByPolicy, e.status_text, e.message);
+    }
+    let method = options
+        .as_ref()
+        .and_then(|o| o.get("method"))
+        .and_then(|m| m.as_str())
+        .unwrap_or("GET");
+    if !clients.allowed_methods.is_empty() && !clients.allowed_methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(method)) {
+        return HttpResult::failure(
+            ErrorCode::MethodNotAllowed,
+            "Forbidden by policy",
+            format!(
+                "Request blocked: {} isn't allowed, OUTBOUND_ALLOWED_METHODS is {}",
+                method.to_ascii_uppercase(),
+                clients.allowed_methods.join(", ")
+            ),
+        );
+    }
+    let host = parsed_url.host_str().unwrap_or("");
+    match clients.proxy.proxy_for(&parsed_url) {
+        Some(proxy) => {
+            tracing::debug!("Routing request to {} through proxy {}", host, proxy);
+            if let Err(e) = policy.check_host_addresses(&parsed_url).await {
+                return HttpResult::failure(ErrorCode::BlockedByPolicy, e.status_text, e.message);
+            }
+        }
+        None if clients.proxy.is_enabled() => {
+            tracing::debug!("Request to {} bypasses the proxy (OUTBOUND_NO_PROXY)", host);
+        }
+        None => {}
+    }
+    
+    // Redirect handling: "follow" (default) or "manual" to return the 3xx response as-is
+    let redirect_mode = options
+        .as_ref()
+        .and_then(|o| o.get("redirect"))
+        .and_then(|r| r.as_str())
+        .unwrap_or("follow");
+    
+    let max_redirects = options
+        .as_ref()
+        .and_then(|o| o.get("maxRedirects"))
+        .and_then(|m| m.as_u64())
+        .map(|m| (m as usize).min(MAX_REDIRECTS_LIMIT))
+        .unwrap_or(DEFAULT_MAX_REDIRECTS);
+    
+    let insecure_tls = options
+        .as_ref()
+        .and_then(|o| o.get("insecureSkipTlsVerify"))
+        .and_then(|i| i.as_bool())
+        .unwrap_or(false);
+    if insecure_tls && !clients.allow_insecure_tls {
+        return HttpResult::failure(
+            ErrorCode::BlockedByPolicy,
+            "Forbidden by policy",
+            "insecureSkipTlsVerify requires the server to be started with ALLOW_INSECURE_TLS=true".to_string(),
+        );
+    }
+    
+    let variant = ClientVariant {
+        redirect: match redirect_mode {
+            "manual" => RedirectMode::Manual,
+            _ if max_redirects == 0 => RedirectMode::Manual,
+            _ => RedirectMode::Follow(max_redirects),
+        },
+        insecure_tls,
+    };
+    
+    let client = match clients.client(variant) {
+        Ok(client) => client,
+        Err(e) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", format!("Failed to build HTTP client: {}", e)),
+    };
+    
+    let timeout_ms = options
+        .as_ref()
+        .and_then(|o| o.get("timeoutMs"))
+        .and_then(|t| t.as_u64())
+        .unwrap_or(clients.default_timeout_ms);
+    
+    let mut headers_map: HashMap<String, String> = options
+        .as_ref()
+        .and_then(|o| o.get("headers"))
+        .and_then(|h| serde_json::from_value(h.clone()).ok())
+        .unwrap_or_default();
+    // `options.ifNoneMatch` for scripts that keep their own validators and want the
+    // 304 as it comes; an If-None-Match header wins
+    if let Some(etag) = options.as_ref().and_then(|o| o.get("ifNoneMatch")).and_then(|e| e.as_str()) {
+        if !headers_map.keys().any(|name| name.eq_ignore_ascii_case("if-none-match")) {
+            headers_map.insert("If-None-Match".to_string(), etag.to_string());
+        }
+    }
+    
+    // Sensitive headers only go to the hosts HEADER_FORWARD_ALLOWLIST allows
+    let mut stripped_headers = Vec::new();
+    headers_map.retain(|name, _| {
+        let forwarded = policy.forwards_header(name, &parsed_url);
+        if !forwarded {
+            stripped_headers.push(name.clone());
+        }
+        forwarded
+    });
+    let auth = match options.as_ref().and_then(|o| o.get("auth")) {
+        Some(_) if !policy.forwards_header("authorization", &parsed_url) => {
+            if !stripped_headers.iter().any(|name| name.eq_ignore_ascii_case("authorization")) {
+                stripped_headers.push("Authorization".to_string());
+            }
+            None
+        }
+        auth => auth,
+    };
+    // The signature goes where its header may go, like Authorization
+    let signing = match options.as_ref().and_then(|o| o.get("sign")).map(Signing::from_options) {
+        Some(Err(message)) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", message),
+        Some(Ok(signing)) if !policy.forwards_header(signing.header(), &parsed_url) => {
+            if !stripped_headers.iter().any(|name| name.eq_ignore_ascii_case(signing.header())) {
+                stripped_headers.push(signing.header().to_string());
+            }
+            None
+        }
+        Some(Ok(signing)) => Some(signing),
+        None => None,
+    };
+    stripped_headers.sort();
+    let authorization = auth.is_some() || headers_map.keys().any(|name| name.eq_ignore_ascii_case("authorization"));
+    // Kept by reqwest on redirects to another host, so checked on every hop
+    let sensitive: Vec<String> = headers_map
+        .keys()
+        .filter(|name| policy.is_sensitive(name) && !removed_across_origins(name))
+        .cloned()
+        .collect();
+    
+    let mut body = options
+        .as_ref()
+        .and_then(|o| o.get("body").cloned());
+    
+    let multipart = match options.as_ref().and_then(|o| o.get("multipart")) {
+        Some(parts) => match multipart_form(parts) {
+            Ok(form) => Some(form),
+            Err(message) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", message),
+        },
+        None => None,
+    };
+    
+    // The multipart boundary must come from the generated Content-Type header
+    if multipart.is_some() {
+        let before = headers_map.len();
+        headers_map.retain(|key, _| !key.eq_ignore_ascii_case("content-type"));
+        if headers_map.len() != before {
+            tracing::warn!("Ignoring Content-Type header for multipart request to {}", host);
+        }
+        if body.take().is_some() {
+            tracing::warn!("Ignoring body for multipart request to {}", host);
+        }
+    }
+    
+    // Standard and custom methods alike, in uppercase
+    let method = match reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes()) {
+        Ok(method) => method,
+        Err(_) => {
+            return HttpResult::failure(ErrorCode::InvalidRequest, "Error", format!("Invalid HTTP method '{}'", method))
+        }
+    };
+    let head = method == reqwest::Method::HEAD;
+    let mut request = client.request(method, &url);
+    
+    request = request.timeout(Duration::from_millis(timeout_ms));
+    
+    // Appended to any query string already present in the URL
+    if let Some(query) = options.as_ref().and_then(|o| o.get("query")) {
+        request = request.query(&query_pairs(query));
+    }
+    
+    for (key, value) in &headers_map {
+        request = request.header(key, value);
+    }
+    
+    // A request id, User-Agent or trace context set by the script wins
+    let set_by_script = |name: &str| headers_map.keys().any(|k| k.eq_ignore_ascii_case(name));
+    // Headers that differ from one execution to the next, left out of the cache key
+    let mut per_execution = Vec::new();
+    if clients.send_request_id && !set_by_script("x-request-id") {
+        request = request.header("x-request-id", session.request_id());
+        per_execution.push("x-request-id".to_string());
+    }
+    // The client can't decompress bodies, so it asks for them as they are
+    if !set_by_script("accept-encoding") {
+        request = request.header("accept-encoding", "identity");
+    }
+    if clients.ua_include_request_id && !clients.user_agent.is_empty() && !set_by_script("user-agent") {
+        request = request.header("user-agent", format!("{} (request-id {})", clients.user_agent, session.request_id()));
+        per_execution.push("user-agent".to_string());
+    }
+    for (name, value) in trace_headers() {
+        if !set_by_script(&name) {
+            per_execution.push(name.to_ascii_lowercase());
+            request = request.header(name, value);
+        }
+    }
+    
+    if let Some(auth) = auth {
+        let explicit_header = headers_map
+            .keys()
+            .any(|k| k.eq_ignore_ascii_case("authorization"));
+        
+        if explicit_header {
+            // The explicit header wins; never log the credentials themselves
+            let warning = format!(
+                "Ignoring options.auth for request to {}: an explicit Authorization header was provided",
+                host
+            );
+            tracing::warn!("{}", warning);
+            if let Some(console) = &session.console {
+                console("warn".to_string(), warning);
+            }
+        } else {
+            request = match apply_auth(request, auth) {
+                Ok(request) => request,
+                Err(message) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", message),
+            };
+        }
+    }
+    
+    if let Some(b) = body {
+        if let Some(body_str) = b.as_str() {
+            request = request.body(body_str.to_string());
+        }
+    }
+    
+    if let Some(form) = multipart {
+        request = request.multipart(form);
+    }
+    
+    let mut request = match request.build() {
+        Ok(request) => request,
+        Err(e) => return HttpResult::failure(ErrorCode::InvalidRequest, "Error", format!("Invalid request: {}", e)),
+    };
+    
+    // Over the request as it is sent, the script's secrets taking precedence
+    if let Some(signing) = signing {
+        let secret = |name: &str| {
+            let secret = session.secrets.values().get(name).or_else(|| clients.signing_secrets.get(name));
+            secret.cloned()
+        };
+        let script_headers: Vec<String> = headers_map.keys().cloned().collect();
+        if let Err(message) = signing.sign(&mut request, &script_headers, &secret) {
+            return HttpResult::failure(ErrorCode::InvalidRequest, "Error", message);
+        }
+    }
+    
+    // `options.cache = { ttlSeconds }` serves repeated GETs from the cross-execution
+    // cache. Streamed bodies are never held whole, so they aren't cached.
+    let cache_ttl = options
+        .as_ref()
+        .and_then(|o| o.get("cache"))
+        .and_then(|c| c.get("ttlSeconds"))
+        .and_then(|t| t.as_f64())
+        .filter(|ttl| *ttl > 0.0 && stream.is_none())
+        .map(|ttl| Duration::try_from_secs_f64(ttl).map_or(cache::MAX_TTL, |ttl| ttl.min(cache::MAX_TTL)));
+    // `options.cookies: false` opts a single call out of the execution's cookie jar
+    let use_cookies = options
+        .as_ref()
+        .and_then(|o| o.get("cookies"))
+        .and_then(|c| c.as_bool())
+        .unwrap_or(true);
+    let cache_key = match cache_ttl {
+        Some(_) if request.method() == reqwest::Method::GET => {
+            // The jar's cookies are only added inside reqwest, after the key is made
+            let jar = use_cookies.then(|| session.cookies.cookies(request.url())).flatten();
+            let key = ResponseCache::key(&request, &per_execution, jar.as_ref());
+            Some(match &session.tenant {
+                Some(tenant) => format!("tenant:{}\n{}", tenant, key),
+                None => key,
+            })
+        }
+        Some(_) => {
+            tracing::debug!("Not caching {} request to {}: only GET responses are cached", request.method(), host);
+            None
+        }
+        None => None,
+    };
+    // A stale entry is asked for with its validators, unless the script sent its own
+    let stale = match cache_key.as_deref().and_then(|key| clients.cache.get(key)) {
+        Some(Cached::Fresh(mut cached)) => {
+            cached.from_cache = FromCache::Yes;
+            cached.attempts = 0;
+            cached.timing = Timing::default();
+            return cached;
+        }
+        Some(Cached::Stale(cached, validators)) if !set_by_script("if-none-match") && !set_by_script("if-modified-since") => {
+            let headers = request.headers_mut();
+            let validators = [(IF_NONE_MATCH, validators.etag), (IF_MODIFIED_SINCE, validators.last_modified)];
+            for (name, value) in validators {
+                if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+                    headers.insert(name, value);
+                }
+            }
+            Some(cached)
+        }
+        _ => None,
+    };
+    
+    let retry = RetryOptions::from_options(options.as_ref(), clients.max_attempts);
+    let context = FetchContext {
+        url: &url,
+        head,
+        timeout_ms,
+        max_redirects,
+        max_body_bytes: session
+            .max_body_bytes
+            .unwrap_or(Limit { value: clients.max_body_bytes, name: "FETCH_MAX_BODY_BYTES" }),
+    };
+    
+    // Multipart bodies are streamed, but reqwest gives them a Content-Length
+    let body_bytes = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| bytes.len() as u64)
+        .or_else(|| request.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
+    
+    let mut pending = Some(request);
+    let mut attempt = 1;
+    let first_attempt = Instant::now();
+    let mut result = loop {
+        let request = pending.take().expect("a request is pending for every attempt");
+        
+        // Keep a copy while another attempt is allowed. Multipart bodies can't be
+        // cloned, so those requests are sent exactly once.
+        let (to_send, retained) = if attempt < retry.attempts {
+            match request.try_clone() {
+                Some(copy) => (copy, Some(request)),
+                None => (request, None),
+            }
+        } else {
+            (request, None)
+        };
+        
+        let chain = Arc::new(RedirectChain::new(authorization, sensitive.clone(), session.allowlist.clone()));
+        let cookies = use_cookies.then_some(&session.cookies);
+        // The request the next one of a resumed redirect is made from
+        let mut previous = if sensitive.is_empty() { None } else { to_send.try_clone() };
+        let mut outcome = send(&client, to_send, &chain, cookies).await;
+        session.sent_bytes.fetch_add(body_bytes, Ordering::Relaxed);
+        loop {
+            let Some((url, stripped)) = chain.resume.lock().unwrap().take() else {
+                break;
+            };
+            let next = match (&previous, &outcome) {
+                (Some(request), Ok(response)) => redirected(request, response.status(), url, &stripped),
+                _ => None,
+            };
+            let Some(next) = next else {
+                break;
+            };
+            previous = next.try_clone();
+            outcome = send(&client, next, &chain, cookies).await;
+        }
+        let redirects = std::mem::take(&mut *chain.hops.lock().unwrap());
+        let mut stripped = stripped_headers.clone();
+        stripped.extend(chain.stripped.lock().unwrap().drain(..));
+        
+        if let Some(request) = retained {
+            if retry.should_retry(&outcome) {
+                tokio::time::sleep(retry.backoff(attempt)).await;
+                attempt += 1;
+                pending = Some(request);
+                continue;
+            }
+        }
+        
+        let mut result = match outcome {
+            Ok(response) => {
+                let ttfb = first_attempt.elapsed();
+                let mut result = match stream.as_deref_mut() {
+                    Some(slot) => {
+                        let head = response_head(&response);
+                        *slot = Some(response);
+                        head
+                    }
+                    None => response_result(response, &context).await,
+                };
+                result.timing.ttfb_ms = Some(ttfb.as_millis() as u64);
+                result
+            }
+            Err(e) => error_result(e, &context),
+        };
+        result.redirects = redirects;
+        result.stripped_headers = stripped;
+        break result;
+    };
+    
+    result.attempts = attempt;
+    
+    // Responses setting cookies belong to this execution's session and aren't shared
+    if let (Some(key), Some(ttl)) = (cache_key, cache_ttl) {
+        match stale {
+            Some(cached) if result.status == 304 && result.error_code.is_none() => {
+                clients.cache.refresh(&key, ttl);
+                return HttpResult {
+                    from_cache: FromCache::Revalidated,
+                    attempts: result.attempts,
+                    timing: result.timing,
+                    redirects: result.redirects,
+                    stripped_headers: result.stripped_headers,
+                    ..cached
+                };
+            }
+            _ if result.ok && result.set_cookies.is_empty() => clients.cache.insert(key, &result, ttl),
+            _ => {}
+        }
+    }
+    result
+}
+
+// `options.auth` is `{type: "bearer", token}` or `{type: "basic", username, password}`.
+// Error messages must not include the credentials.
+fn apply_auth(request: reqwest::RequestBuilder, auth: &Value) -> Result<reqwest::RequestBuilder, String> {
+    let field = |name: &str| auth.get(name).and_then(|v| v.as_str());
+    
+    match field("type") {
+        Some("bearer") => match field("token") {
+            Some(token) => Ok(request.bearer_auth(token)),
+            None => Err("Invalid auth option: bearer auth requires a token".to_string()),
+        },
+        Some("basic") => match field("username") {
+            Some(username) => Ok(request.basic_auth(username, field("password"))),
+            None => Err("Invalid auth option: basic auth requires a username".to_string()),
+        },
+        _ => Err("Invalid auth option: type must be \"bearer\" or \"basic\"".to_string()),
+    }
+}
+
+// `options.multipart` is an array of `{name, value}` text parts and
+// `{name, filename, contentBase64, contentType}` file parts
+fn multipart_form(parts: &Value) -> Result<reqwest::multipart::Form, String> {
+    let parts = parts
+        .as_array()
+        .ok_or_else(|| "Invalid multipart option: expected an array of parts".to_string())?;
+    
+    // RFC 5987 encoding keeps non-ASCII field names intact; file names are sent
+    // as UTF-8, as RFC 7578 has them
+    let mut form = reqwest::multipart::Form::new().percent_encode_attr_chars();
+    for (index, part) in parts.iter().enumerate() {
+        let field = |name: &str| part.get(name).and_then(|v| v.as_str());
+        let name = field("name")
+            .ok_or_else(|| format!("Invalid multipart part {}: missing name", index))?
+            .to_string();
+        
+        if let Some(content) = field("contentBase64") {
+            let bytes = BASE64_STANDARD
+                .decode(content)
+                .map_err(|e| format!("Invalid multipart part {}: contentBase64 is not valid base64: {}", index, e))?;
+            let mut file = reqwest::multipart::Part::bytes(bytes);
+            if let Some(filename) = field("filename") {
+                file = file.file_name(filename.to_string());
+            }
+            if let Some(content_type) = field("contentType") {
+                file = file
+                    .mime_str(content_type)
+                    .map_err(|e| format!("Invalid multipart part {}: {}", index, e))?;
+            }
+            form = form.part(name, file);
+        } else {
+            let value = match part.get("value") {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(other) => other.to_string(),
+            };
+            form = form.text(name, value);
+        }
+    }
+    Ok(form)
+}
+
+// `options.query` is either an object (array values become repeated keys)
+// or an array of `[key, value]` pairs
+// The URL with `options.query` appended, as it is sent
+pub(crate) fn request_url(url: String, options: Option<&HashMap<String, Value>>) -> String {
+    match (options.and_then(|options| options.get("query")), reqwest::Url::parse(&url)) {
+        (Some(query), Ok(mut parsed)) => {
+            parsed.query_pairs_mut().extend_pairs(query_pairs(query));
+            parsed.to_string()
+        }
+        _ => url,
+    }
+}
+
+fn query_pairs(query: &Value) -> Vec<(String, String)> {
+    fn param_value(value: &Value) -> String {
+        match value {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            other => other.to_string(),
+        }
+    }
+    
+    let mut pairs = Vec::new();
+    match query {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Array(values) => {
+                        pairs.extend(values.iter().map(|v| (key.clone(), param_value(v))))
+                    }
+                    value => pairs.push((key.clone(), param_value(value))),
+                }
+            }
+        }
+        Value::Array(entries) => {
+            for entry in entries {
+                if let Some([key, value]) = entry.as_array().map(Vec::as_slice) {
+                    pairs.push((param_value(key), param_value(value)));
+                }
+            }
+        }
+        _ => {}
+    }
+    pairs
+}
+
+// What result construction needs to know about the fetch in progress
+struct FetchContext<'a> {
+    url: &'a str,
+    // HEAD responses have no body, whatever their Content-Length says
+    head: bool,
+    timeout_ms: u64,
+    max_redirects: usize,
+    max_body_bytes: Limit<usize>,
+}
+
+// The response with an empty body
+fn response_head(response: &reqwest::Response) -> HttpResult {
+    let status = response.status().as_u16();
+    let status_text = response.status().canonical_reason().unwrap_or("").to_string();
+    let ok = response.status().is_success();
+    let final_url = response.url().to_string();
+    let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok());
+    let content_type = header(reqwest::header::CONTENT_TYPE).map(str::to_ascii_lowercase);
+    let content_length = header(reqwest::header::CONTENT_LENGTH).and_then(|length| length.trim().parse().ok());
+    
+    let set_cookies = response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .map(|v| v.to_str().unwrap_or("").to_string())
+        .collect();
+    
+    // Header names arrive lowercased from hyper
+    let raw_headers: Vec<(String, String)> = response
+        .headers()
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_str().unwrap_or("").to_string()))
+        .collect();
+    
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    for (key, value) in &raw_headers {
+        headers
+            .entry(key.clone())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.clone());
+    }
+    
+    HttpResult {
+        ok,
+        status,
+        status_text,
+        headers,
+        raw_headers,
+        data: Value::String(String::new()),
+        text: String::new(),
+        json_parse_error: None,
+        content_type,
+        content_length,
+        final_url: Some(final_url),
+        timing: Timing::default(),
+        redirects: Vec::new(),
+        attempts: 1,
+        set_cookies,
+        from_cache: FromCache::No,
+        error_code: None,
+        stripped_headers: Vec::new(),
+        retry_after_ms: None,
+    }
+}
+
+async fn response_result(mut response: reqwest::Response, context: &FetchContext<'_>) -> HttpResult {
+    let head = response_head(&response);
+    // Reject oversized bodies from the declared length when possible, otherwise
+    // stop reading as soon as the limit is crossed
+    let too_large = || {
+        HttpResult::failure(
+            ErrorCode::BodyTooLarge,
+            "Body Too Large",
+            format!(
+                "Fetch failed: response body from {} exceeds the {} limit of {} bytes",
+                context.url, context.max_body_bytes.name, context.max_body_bytes.value
+            ),
+        )
+    };
+    if !context.head && response.content_length().unwrap_or(0) > context.max_body_bytes.value as u64 {
+        return too_large();
+    }
+    
+    let mut body = Vec::new();
+    if !context.head {
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    if body.len() + chunk.len() > context.max_body_bytes.value {
+                        return too_large();
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                Ok(None) => break,
+                Err(e) => return error_result(e, context),
+            }
+        }
+    }
+    
+    let content_encoding = head
+        .headers
+        .get("content-encoding")
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity");
+    let (data, json_parse_error) = match serde_json::from_slice::<Value>(&body) {
+        Err(_) if !body.is_empty() && content_encoding.is_some() => (
+            Value::String(String::new()),
+            Some(format!("body is {}-encoded, which can't be decompressed", content_encoding.unwrap_or_default())),
+        ),
+        Ok(json) => (json, None),
+        Err(_) if body.is_empty() => (Value::String(String::new()), None),
+        Err(e) => (Value::String(String::new()), Some(e.to_string())),
+    };
+    let text = String::from_utf8_lossy(&body).into_owned();
+    
+    HttpResult {
+        data,
+        text,
+        json_parse_error,
+        ..head
+    }
+}
+
+fn error_result(e: reqwest::Error, context: &FetchContext<'_>) -> HttpResult {
+    if let Some(blocked) = policy::blocked_error(&e) {
+        return HttpResult::failure(ErrorCode::BlockedByPolicy, blocked.status_text, blocked.message.clone());
+    }
+    
+    if e.is_timeout() {
+        return HttpResult::failure(
+            ErrorCode::Timeout,
+            "Timeout",
+            format!("Fetch failed: no response from {} within {} ms", context.url, context.timeout_ms),
+        );
+    }
+    
+    if e.is_redirect() {
+        return HttpResult::failure(
+            ErrorCode::TooManyRedirects,
+            "Too Many Redirects",
+            format!(
+                "Fetch failed: exceeded maxRedirects ({}) for {}",
+                context.max_redirects, context.url
+            ),
+        );
+    }
+    
+    HttpResult::failure(transport_error_code(&e), "Error", format!("Fetch failed: {}", e))
+}
+
+// reqwest doesn't expose why a connection failed, so classify connect errors by
+// the messages hyper and the TLS backend put in the source chain
+fn transport_error_code(e: &reqwest::Error) -> ErrorCode {
+    if e.is_builder() {
+        return ErrorCode::InvalidRequest;
+    }
+    if !e.is_connect() {
+        return ErrorCode::Network;
+    }
+    
+    let mut messages = Vec::new();
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        messages.push(err.to_string().to_ascii_lowercase());
+        source = err.source();
+    }
+    
+    if messages.iter().any(|m| m.contains("dns error")) {
+        ErrorCode::Dns
+    } else if messages
+        .iter()
+        .any(|m| m.contains("certificate") || m.contains("ssl") || m.contains("tls") || m.contains("handshake"))
+    {
+        ErrorCode::Tls
+    } else {
+        ErrorCode::Connect
+    }
+}
+
+// `options.retry = { attempts, backoffMs, retryOn }`
+struct RetryOptions {
+    attempts: u32,
+    backoff_ms: u64,
+    retry_on: Vec<u16>,
+}
+
+impl RetryOptions {
+    fn from_options(options: Option<&HashMap<String, Value>>, max_attempts: u32) -> Self {
+        let retry = match options.and_then(|o| o.get("retry")) {
+            Some(Value::Object(retry)) => retry,
+            _ => {
+                return RetryOptions {
+                    attempts: 1,
+                    backoff_ms: 0,
+                    retry_on: Vec::new(),
+                }
+            }
+        };
+        
+        let attempts = retry
+            .get("attempts")
+            .and_then(|a| a.as_u64())
+            .map(|a| a as u32)
+            .unwrap_or(DEFAULT_RETRY_ATTEMPTS);
+        
+        let backoff_ms = retry
+            .get("backoffMs")
+            .and_then(|b| b.as_u64())
+            .unwrap_or(DEFAULT_RETRY_BACKOFF_MS);
+        
+        let retry_on = retry
+            .get("retryOn")
+            .and_then(|r| r.as_array())
+            .map(|codes| {
+                codes
+                    .iter()
+                    .filter_map(|c| c.as_u64())
+                    .map(|c| c as u16)
+                    .collect()
+            })
+            .unwrap_or_else(|| DEFAULT_RETRY_ON.to_vec());
+        
+        RetryOptions {
+            attempts: attempts.clamp(1, max_attempts.max(1)),
+            backoff_ms,
+            retry_on,
+        }
+    }
+    
+    // Network errors and listed status codes are retried; policy rejections and
+    // redirect limit errors would fail identically on every attempt
+    fn should_retry(&self, outcome: &reqwest::Result<reqwest::Response>) -> bool {
+        match outcome {
+            Ok(response) => self.retry_on.contains(&response.status().as_u16()),
+            Err(e) => policy::blocked_error(e).is_none() && !e.is_redirect() && !e.is_builder(),
+        }
+    }
+    
+    // Exponential backoff with "equal jitter": half the delay is fixed, half random
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff_ms
+            .saturating_mul(1u64 << (attempt - 1).min(16))
+            .min(MAX_RETRY_BACKOFF_MS);
+        let jitter = if delay > 1 {
+            rand::thread_rng().gen_range(0..=delay / 2)
+        } else {
+            0
+        };
+        Duration::from_millis(delay - delay / 2 + jitter)
+    }
+}
+
+// Headers carrying the current span's trace context (see the server's telemetry),
+// empty unless a propagator was installed
+fn trace_headers() -> HashMap<String, String> {
+    let context = Span::current().context();
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+    headers
+}
@@ -0,0 +1,46 @@
+// Files uploaded with a multipart request, available to the code as the frozen FILES.
+//
+// FILES maps the field name of each file to `{ name, size, contentType, bytes() }`.
+// The content stays on the host until the code calls `bytes()`, which copies it into
+// a new Uint8Array every time, so code that only looks at the names and sizes never
+// pays for the copy.
+
+use rquickjs::{Ctx, Function, Object, Result, TypedArray, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct File {
+    // The file name the client sent, if any
+    pub name: Option<String>,
+    pub content_type: Option<String>,
+    pub bytes: Arc<[u8]>,
+}
+
+// By field name
+pub type Files = BTreeMap<String, File>;
+
+pub fn install<'js>(ctx: &Ctx<'js>, files: &Files) -> Result<()> {
+    let entries = Object::new(ctx.clone())?;
+    for (field, file) in files {
+        let entry = Object::new(ctx.clone())?;
+        entry.set("name", file.name.as_deref())?;
+        entry.set("size", file.bytes.len())?;
+        entry.set("contentType", file.content_type.as_deref())?;
+        let bytes = file.bytes.clone();
+        entry.set(
+            "bytes",
+            Function::new(ctx.clone(), move |ctx: Ctx<'js>| TypedArray::<u8>::new(ctx, bytes.to_vec()))?.with_name("bytes")?,
+        )?;
+        entries.set(field.as_str(), entry)?;
+    }
+
+    let define: Function = ctx.eval(
+        "(files) => {
+            Object.values(files).forEach(Object.freeze);
+            Object.defineProperty(globalThis, 'FILES', { value: Object.freeze(files) });
+        }",
+    )?;
+    define.call::<_, Value>((entries,))?;
+    Ok(())
+}
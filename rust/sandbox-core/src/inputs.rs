@@ -0,0 +1,83 @@
+// The INPUTS global, and `getInput`, `inputKeys` and `inputSize` for reading it.
+//
+// Inputs up to LAZY_INPUTS_BYTES of JSON are evaluated into INPUTS as they are.
+// Larger ones stay on the Rust side: INPUTS is then a read-only proxy that converts
+// only the parts the code touches, and `getInput(path)` converts a subtree at once.
+// The lookups go through the `__inputs` helpers installed here, by JSON pointer;
+// js/inputs.js turns paths into pointers and builds the proxy.
+
+use rquickjs::function::Func;
+use rquickjs::{Ctx, Object, Result};
+use serde_json::Value;
+use std::io;
+use std::sync::Arc;
+
+use crate::code_cache;
+
+// The length of the value's JSON text, without writing it out
+pub fn json_len(value: &Value) -> usize {
+    struct Counter(usize);
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+// Lazy above `lazy_above` bytes of JSON; 0 never is
+pub fn install(ctx: &Ctx<'_>, inputs: &Value, lazy_above: usize) -> Result<()> {
+    let lazy = lazy_above > 0 && json_len(inputs) > lazy_above;
+    if !lazy {
+        let inputs_json = serde_json::to_string(inputs).unwrap_or_else(|_| "null".to_string());
+        ctx.eval::<(), _>(format!("var INPUTS = {};", inputs_json))?;
+    } else {
+        let inputs = Arc::new(inputs.clone());
+        let helpers = Object::new(ctx.clone())?;
+        let value = inputs.clone();
+        helpers.set(
+            "kind",
+            Func::from(move |pointer: String| {
+                value.pointer(&pointer).map(|value| match value {
+                    Value::Object(_) => "object",
+                    Value::Array(_) => "array",
+                    _ => "value",
+                })
+            }),
+        )?;
+        let value = inputs.clone();
+        helpers.set(
+            "length",
+            Func::from(move |pointer: String| value.pointer(&pointer).and_then(Value::as_array).map(Vec::len)),
+        )?;
+        // As JSON, parsed by the prelude
+        let value = inputs.clone();
+        helpers.set(
+            "get",
+            Func::from(move |pointer: String| value.pointer(&pointer).map(|value| value.to_string())),
+        )?;
+        let value = inputs.clone();
+        helpers.set(
+            "keys",
+            Func::from(move |pointer: String| {
+                value.pointer(&pointer).and_then(|value| match value {
+                    Value::Object(map) => Some(map.keys().cloned().collect::<Vec<_>>()),
+                    Value::Array(items) => Some((0..items.len()).map(|index| index.to_string()).collect()),
+                    _ => None,
+                })
+            }),
+        )?;
+        helpers.set(
+            "size",
+            Func::from(move |pointer: String| inputs.pointer(&pointer).map(json_len)),
+        )?;
+        ctx.globals().set("__inputs", helpers)?;
+    }
+    code_cache::evaluate_prelude(ctx, "inputs.js", include_str!("js/inputs.js"))?.finish::<()>()
+}
@@ -0,0 +1,539 @@
+// Subprocess isolation: with ISOLATION_MODE=subprocess, executions run in worker
+// processes instead of the server's own, so a crash or an escape from the sandbox
+// doesn't reach the server or the memory of other executions.
+//
+// Workers are the server binary started with the hidden `--worker` flag. The server
+// talks to a worker over its stdin and stdout in frames of a 4-byte big-endian length
+// followed by that much JSON: first the configuration, then a `Job` for each
+// execution, which the worker answers with a `Done`. A worker runs one execution at
+// a time, on an executor of its own, under an RLIMIT_AS of
+// ISOLATION_WORKER_MEMORY_BYTES, and is replaced after
+// ISOLATION_WORKER_MAX_EXECUTIONS executions. ISOLATION_WORKERS of them are started
+// ahead of time; more are started while all of those are busy.
+//
+// The server keeps the admission control, the result cache and the state: a job
+// carries the entries of the execution's namespace, and the worker answers with the
+// writes of a successful execution. A worker that doesn't answer in time is killed,
+// and its execution fails as interrupted; one that dies fails its execution with
+// `WorkerCrashed`. Either way a new worker takes its place.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{ExitCode, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::cancel::Interruption;
+use crate::config::Config;
+use crate::engine::Entrypoint;
+use crate::error::{ErrorKind, ExecError};
+use crate::executor::{Execution, Executor, Options, Report};
+use crate::files::{File, Files};
+use crate::js_error::JsError;
+use crate::metrics::METRICS;
+use crate::modules::ModuleMap;
+use crate::policy::UrlPatterns;
+use crate::secrets::Secrets;
+use crate::serialize::BigIntMode;
+
+// How long past its timeout an execution's worker has to answer before it is killed
+const KILL_GRACE: Duration = Duration::from_millis(500);
+// As for the server's worker threads: room for the Rust frames below the interpreter
+const STACK_HEADROOM_BYTES: usize = 1024 * 1024;
+const MIN_STACK_BYTES: usize = 2 * 1024 * 1024;
+
+// One execution, as a worker gets it
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    code: String,
+    inputs: Value,
+    module: bool,
+    bigint_mode: BigIntMode,
+    freeze_time: bool,
+    random_seed: Option<u32>,
+    timeout: Duration,
+    cpu_budget: Option<Duration>,
+    max_requests: Option<u32>,
+    max_result_bytes: Option<usize>,
+    memory_bytes: Option<usize>,
+    max_fetch_body_bytes: Option<usize>,
+    disable_dynamic_eval: bool,
+    disable_network: bool,
+    skip_prelude: bool,
+    debug: bool,
+    request_id: Option<String>,
+    secrets: BTreeMap<String, String>,
+    files: BTreeMap<String, WireFile>,
+    entrypoint: Option<(String, Option<Vec<Value>>)>,
+    // The entry module and the sources
+    modules: Option<(String, BTreeMap<String, String>)>,
+    tenant: Option<String>,
+    state_namespace: String,
+    // StateStore::export of the namespace
+    state: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireFile {
+    name: Option<String>,
+    content_type: Option<String>,
+    // Base64
+    bytes: String,
+}
+
+impl Job {
+    // With the timeout resolved, and TypeScript already stripped of its types
+    pub fn new(code: &str, inputs: &Value, options: &Options, timeout: Duration, namespace: &str, state: Value) -> Self {
+        Job {
+            code: code.to_string(),
+            inputs: inputs.clone(),
+            module: options.module,
+            bigint_mode: options.bigint_mode,
+            freeze_time: options.freeze_time,
+            random_seed: options.random_seed,
+            timeout,
+            cpu_budget: options.cpu_budget,
+            max_requests: options.max_requests,
+            max_result_bytes: options.max_result_bytes,
+            memory_bytes: options.memory_bytes,
+            max_fetch_body_bytes: options.max_fetch_body_bytes,
+            disable_dynamic_eval: options.disable_dynamic_eval,
+            disable_network: options.disable_network,
+            skip_prelude: options.skip_prelude,
+            debug: options.debug,
+            request_id: options.request_id.clone(),
+            secrets: options.secrets.values().clone(),
+            files: options
+                .files
+                .iter()
+                .map(|(field, file)| {
+                    let file = WireFile {
+                        name: file.name.clone(),
+                        content_type: file.content_type.clone(),
+                        bytes: BASE64.encode(&file.bytes),
+                    };
+                    (field.clone(), file)
+                })
+                .collect(),
+            entrypoint: options.entrypoint.as_ref().map(|entrypoint| (entrypoint.name.clone(), entrypoint.args.clone())),
+            modules: options.modules.as_ref().map(|map| (map.entry.clone(), map.sources.clone())),
+            tenant: options.tenant.clone(),
+            state_namespace: namespace.to_string(),
+            state,
+        }
+    }
+
+    fn options(self, allowlists: &HashMap<String, Arc<UrlPatterns>>) -> Result<(String, Value, Options), String> {
+        let mut files = Files::new();
+        for (field, file) in self.files {
+            let bytes = BASE64.decode(&file.bytes).map_err(|e| format!("Invalid file {}: {}", field, e))?;
+            let file = File {
+                name: file.name,
+                content_type: file.content_type,
+                bytes: bytes.into(),
+            };
+            files.insert(field, file);
+        }
+        let fetch_allowlist = self.tenant.as_ref().and_then(|tenant| allowlists.get(tenant).cloned());
+        let options = Options {
+            module: self.module,
+            bigint_mode: self.bigint_mode,
+            freeze_time: self.freeze_time,
+            random_seed: self.random_seed,
+            timeout: Some(self.timeout),
+            cpu_budget: self.cpu_budget,
+            max_requests: self.max_requests,
+            max_result_bytes: self.max_result_bytes,
+            memory_bytes: self.memory_bytes,
+            max_fetch_body_bytes: self.max_fetch_body_bytes,
+            disable_dynamic_eval: self.disable_dynamic_eval,
+            disable_network: self.disable_network,
+            skip_prelude: self.skip_prelude,
+            debug: self.debug,
+            request_id: self.request_id,
+            secrets: Arc::new(Secrets::new(self.secrets)),
+            files: Arc::new(files),
+            state_namespace: Some(self.state_namespace),
+            entrypoint: self.entrypoint.map(|(name, args)| Entrypoint { name, args }),
+            modules: self.modules.map(|(entry, sources)| Arc::new(ModuleMap { entry, sources })),
+            tenant: self.tenant,
+            fetch_allowlist,
+            ..Options::default()
+        };
+        Ok((self.code, self.inputs, options))
+    }
+}
+
+// What a worker answers a job with
+#[derive(Serialize, Deserialize)]
+struct Done {
+    outcome: Result<Execution, Failure>,
+    // StateStore::writes_since the job's entries
+    state_writes: Value,
+}
+
+// An ExecError, with the thrown value that JsError leaves out of its JSON
+#[derive(Serialize, Deserialize)]
+struct Failure {
+    kind: ErrorKind,
+    message: String,
+    js_error: Option<JsError>,
+    thrown: Option<Value>,
+    preview: Option<String>,
+    report: Option<Report>,
+}
+
+impl From<ExecError> for Failure {
+    fn from(e: ExecError) -> Self {
+        let thrown = e.js_error.as_ref().and_then(|js_error| js_error.thrown.clone());
+        Failure {
+            kind: e.kind,
+            message: e.message,
+            js_error: e.js_error.map(|js_error| *js_error),
+            thrown,
+            preview: e.preview,
+            report: e.report.map(|report| *report),
+        }
+    }
+}
+
+impl From<Failure> for ExecError {
+    fn from(failure: Failure) -> Self {
+        let js_error = failure.js_error.map(|js_error| JsError {
+            thrown: failure.thrown,
+            ..js_error
+        });
+        ExecError {
+            js_error: js_error.map(Box::new),
+            preview: failure.preview,
+            report: failure.report.map(Box::new),
+            ..ExecError::new(failure.kind, failure.message)
+        }
+    }
+}
+
+// Options the server answers in-process, which a worker can't
+pub fn unsupported(options: &Options) -> Option<&'static str> {
+    [
+        ("dry_run", options.dry_run),
+        ("http_mocks", options.http_mocks.is_some()),
+        ("replay_http", options.replay_http.is_some()),
+        ("record_http", options.record_http),
+    ]
+    .into_iter()
+    .find_map(|(option, set)| set.then_some(option))
+}
+
+pub struct WorkerPool {
+    program: PathBuf,
+    // The first frame of every worker
+    config: Vec<u8>,
+    workers: usize,
+    max_executions: u32,
+    idle: Mutex<Vec<Worker>>,
+    // The process ids of workers running an execution
+    busy: Arc<Mutex<BTreeSet<u32>>>,
+}
+
+struct Worker {
+    // Killed when dropped
+    process: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    executions: u32,
+}
+
+// Takes a worker off the busy list however its execution ends
+struct Busy {
+    busy: Arc<Mutex<BTreeSet<u32>>>,
+    pid: u32,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.pid);
+    }
+}
+
+impl WorkerPool {
+    // None in-process, the default
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        match config.isolation_mode.trim() {
+            "" | "in-process" => return Ok(None),
+            "subprocess" => {}
+            other => {
+                return Err(format!(
+                    "Invalid ISOLATION_MODE {:?}: expected \"in-process\" or \"subprocess\"",
+                    other
+                ))
+            }
+        }
+        let program = match config.isolation_worker_binary.trim() {
+            "" => std::env::current_exe().map_err(|e| format!("Failed to find the binary for worker processes: {}", e))?,
+            binary => PathBuf::from(binary),
+        };
+        Ok(Some(WorkerPool {
+            program,
+            config: worker_config(config)?,
+            workers: config.isolation_workers,
+            max_executions: config.isolation_worker_max_executions,
+            idle: Mutex::new(Vec::new()),
+            busy: Arc::default(),
+        }))
+    }
+
+    // Starts the workers that wait for executions
+    pub async fn warm_up(&self) -> Result<(), String> {
+        while self.idle.lock().unwrap().len() < self.workers {
+            let worker = self.spawn().await?;
+            self.idle.lock().unwrap().push(worker);
+        }
+        Ok(())
+    }
+
+    // The process ids of the workers running an execution
+    pub fn busy(&self) -> Vec<u32> {
+        self.busy.lock().unwrap().iter().copied().collect()
+    }
+
+    // The outcome of the job, and the state writes to apply if it succeeded
+    pub async fn run(&self, job: &Job) -> Result<(Result<Execution, ExecError>, Value), ExecError> {
+        let frame = serde_json::to_vec(job).map_err(|e| format!("Failed to serialize the job: {}", e))?;
+        let mut worker = self.checkout().await?;
+        let pid = worker.process.id().unwrap_or_default();
+        self.busy.lock().unwrap().insert(pid);
+        let _busy = Busy { busy: self.busy.clone(), pid };
+        let answer = tokio::time::timeout(job.timeout + KILL_GRACE, async {
+            write_frame(&mut worker.stdin, &frame).await?;
+            read_frame(&mut worker.stdout).await
+        })
+        .await;
+        match answer {
+            Ok(Ok(frame)) => {
+                let done: Done =
+                    serde_json::from_slice(&frame).map_err(|e| format!("Invalid answer from a worker process: {}", e))?;
+                worker.executions += 1;
+                self.release(worker).await;
+                Ok((done.outcome.map_err(ExecError::from), done.state_writes))
+            }
+            // The worker closed its stdout, which it only does when it exits
+            Ok(Err(_)) => {
+                let status = match worker.process.wait().await {
+                    Ok(status) => status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                METRICS.worker_crash();
+                tracing::warn!(pid, %status, "Worker process died during an execution");
+                self.replace().await;
+                Err(ExecError::new(
+                    ErrorKind::WorkerCrashed,
+                    format!("The worker process running the execution died: {}", status),
+                ))
+            }
+            Err(_) => {
+                drop(worker);
+                self.replace().await;
+                Err(ExecError::interrupted(Interruption::TimedOut, job.timeout))
+            }
+        }
+    }
+
+    async fn checkout(&self) -> Result<Worker, String> {
+        loop {
+            let Some(mut worker) = self.idle.lock().unwrap().pop() else {
+                return self.spawn().await;
+            };
+            // Skips workers that died while idle
+            if let Ok(None) = worker.process.try_wait() {
+                return Ok(worker);
+            }
+        }
+    }
+
+    async fn release(&self, worker: Worker) {
+        match self.max_executions > 0 && worker.executions >= self.max_executions {
+            true => {
+                drop(worker);
+                self.replace().await;
+            }
+            false => self.idle.lock().unwrap().push(worker),
+        }
+    }
+
+    // Starts a worker in place of one that is gone, unless enough are waiting
+    async fn replace(&self) {
+        if self.idle.lock().unwrap().len() >= self.workers {
+            return;
+        }
+        match self.spawn().await {
+            Ok(worker) => self.idle.lock().unwrap().push(worker),
+            Err(e) => tracing::warn!(error = %e, "Failed to start a worker process"),
+        }
+    }
+
+    async fn spawn(&self) -> Result<Worker, String> {
+        let failed = |e: std::io::Error| format!("Failed to start a worker process {}: {}", self.program.display(), e);
+        let mut process = Command::new(&self.program)
+            .arg("--worker")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(failed)?;
+        let mut stdin = process.stdin.take().expect("stdin is piped");
+        let stdout = process.stdout.take().expect("stdout is piped");
+        write_frame(&mut stdin, &self.config).await.map_err(failed)?;
+        Ok(Worker {
+            process,
+            stdin,
+            stdout,
+            executions: 0,
+        })
+    }
+}
+
+// The configuration a worker runs its executions with: the server's, for one
+// execution at a time and without the state file the server keeps
+fn worker_config(config: &Config) -> Result<Vec<u8>, String> {
+    let failed = |e: serde_json::Error| format!("Failed to serialize the worker configuration: {}", e);
+    let mut worker: Config = serde_json::from_value(serde_json::to_value(config).map_err(failed)?).map_err(failed)?;
+    worker.isolation_mode = "in-process".to_string();
+    worker.state_path = String::new();
+    worker.max_concurrent_executions = 1;
+    worker.interactive_reserved_slots = 0;
+    serde_json::to_vec(&worker).map_err(failed)
+}
+
+async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(frame.len() as u32).to_be_bytes()).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Vec<u8>> {
+    let length = reader.read_u32().await?;
+    let mut frame = vec![0; length as usize];
+    reader.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+// The `--worker` mode of the binary: runs the jobs it reads from stdin until stdin
+// is closed
+pub fn serve_worker() -> ExitCode {
+    let mut stdin = std::io::stdin().lock();
+    let config = match read_frame_blocking(&mut stdin) {
+        Ok(Some(frame)) => serde_json::from_slice::<Config>(&frame).map_err(|e| e.to_string()),
+        Ok(None) => return ExitCode::SUCCESS,
+        Err(e) => Err(e.to_string()),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid worker configuration: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = limit_memory(config.isolation_worker_memory_bytes) {
+        eprintln!("Failed to limit the memory of the worker process: {}", e);
+        return ExitCode::FAILURE;
+    }
+    drop(stdin);
+    // Executions run on this thread, which needs room for the JavaScript stack
+    let stack_bytes = (config.js_max_stack_bytes + STACK_HEADROOM_BYTES).max(MIN_STACK_BYTES);
+    let worker = std::thread::Builder::new().stack_size(stack_bytes).spawn(move || run_jobs(config));
+    match worker.map_err(|e| e.to_string()).and_then(|worker| worker.join().map_err(|_| "panicked".to_string())) {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) | Err(e) => {
+            eprintln!("Worker process failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_jobs(config: Config) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start the Tokio runtime: {}", e))?;
+    let _runtime = runtime.enter();
+    let executor = Executor::new(&config)?;
+    let allowlists: HashMap<String, Arc<UrlPatterns>> = config
+        .tenants
+        .iter()
+        .filter(|(_, tenant)| !tenant.fetch_allowlist.is_empty())
+        .map(|(id, tenant)| (id.clone(), Arc::new(UrlPatterns::parse(&tenant.fetch_allowlist))))
+        .collect();
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    while let Some(frame) = read_frame_blocking(&mut stdin).map_err(|e| e.to_string())? {
+        let job: Job = serde_json::from_slice(&frame).map_err(|e| format!("Invalid job: {}", e))?;
+        let done = runtime.block_on(run_job(&executor, &allowlists, job));
+        let frame = serde_json::to_vec(&done).map_err(|e| format!("Failed to serialize the outcome: {}", e))?;
+        stdout
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .and_then(|()| stdout.write_all(&frame))
+            .and_then(|()| stdout.flush())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+async fn run_job(executor: &Executor, allowlists: &HashMap<String, Arc<UrlPatterns>>, job: Job) -> Done {
+    let state = executor.state();
+    let namespace = job.state_namespace.clone();
+    let entries = job.state.clone();
+    let outcome = match state.import(&namespace, entries.clone()).and_then(|()| job.options(allowlists)) {
+        Ok((code, inputs, options)) => executor.run(&code, &inputs, options).await,
+        Err(message) => Err(ExecError::new(ErrorKind::Internal, message)),
+    };
+    let state_writes = state.writes_since(&namespace, &entries);
+    // Nothing of the namespace stays behind for the worker's next job
+    let _ = state.import(&namespace, Value::Object(Default::default()));
+    Done {
+        outcome: outcome.map_err(Failure::from),
+        state_writes,
+    }
+}
+
+// None when the stream ends before a frame starts
+fn read_frame_blocking(reader: &mut impl Read) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut frame = vec![0; u32::from_be_bytes(length) as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+#[cfg(unix)]
+fn limit_memory(bytes: u64) -> std::io::Result<()> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    // SAFETY: setrlimit only reads the rlimit it is given
+    match unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+// Elsewhere workers run without a memory limit of their own
+#[cfg(not(unix))]
+fn limit_memory(_bytes: u64) -> std::io::Result<()> {
+    Ok(())
+}
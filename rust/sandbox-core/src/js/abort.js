@@ -0,0 +1,129 @@
+// `AbortController`, `AbortSignal` and a minimal `DOMException` for user code.
+//
+// httpRequest and fetch take a `signal` and reject once it aborts.
+// `AbortSignal.timeout` waits like setTimeout, but outside the sleep budget: it
+// never holds up the code, and the execution timeout still bounds it.
+
+const { wait } = globalThis.__timers;
+
+// The error of a failed web API call, with a name such as "AbortError"
+class DOMException extends Error {
+    constructor(message = "", name = "Error") {
+        super(message);
+        this.name = name;
+    }
+}
+
+// AbortSignal -> { aborted, reason, listeners }
+const states = new WeakMap();
+// Only the helpers below may create signals
+const token = Symbol("AbortSignal");
+
+const abort = (signal, reason) => {
+    const state = states.get(signal);
+    if (state.aborted) {
+        return;
+    }
+    state.aborted = true;
+    state.reason = reason === undefined ? new DOMException("This operation was aborted", "AbortError") : reason;
+    const event = { type: "abort", target: signal };
+    const listeners = state.listeners.splice(0);
+    if (typeof signal.onabort === "function") {
+        listeners.unshift(signal.onabort);
+    }
+    for (const listener of listeners) {
+        try {
+            listener.call(signal, event);
+        } catch (error) {
+            // Reported as an unhandled rejection, without stopping the other listeners
+            Promise.reject(error);
+        }
+    }
+};
+
+class AbortSignal {
+    constructor(key) {
+        if (key !== token) {
+            throw new TypeError("AbortSignal can't be constructed; use an AbortController");
+        }
+        states.set(this, { aborted: false, reason: undefined, listeners: [] });
+        this.onabort = null;
+    }
+
+    get aborted() {
+        return states.get(this).aborted;
+    }
+
+    get reason() {
+        return states.get(this).reason;
+    }
+
+    throwIfAborted() {
+        if (this.aborted) {
+            throw this.reason;
+        }
+    }
+
+    // Listeners for "abort"; other events never happen
+    addEventListener(type, listener) {
+        const { aborted, listeners } = states.get(this);
+        if (type === "abort" && typeof listener === "function" && !aborted && !listeners.includes(listener)) {
+            listeners.push(listener);
+        }
+    }
+
+    removeEventListener(type, listener) {
+        const listeners = states.get(this).listeners;
+        const index = listeners.indexOf(listener);
+        if (type === "abort" && index >= 0) {
+            listeners.splice(index, 1);
+        }
+    }
+
+    static abort(reason) {
+        const signal = new AbortSignal(token);
+        abort(signal, reason);
+        return signal;
+    }
+
+    // Aborts with a TimeoutError after `ms` milliseconds
+    static timeout(ms) {
+        const signal = new AbortSignal(token);
+        wait(Number(ms)).then(() => abort(signal, new DOMException("The operation timed out", "TimeoutError")));
+        return signal;
+    }
+
+    // Aborts with the reason of the first of `signals` to abort
+    static any(signals) {
+        const signal = new AbortSignal(token);
+        for (const source of signals) {
+            if (source.aborted) {
+                abort(signal, source.reason);
+                break;
+            }
+            source.addEventListener("abort", () => abort(signal, source.reason));
+        }
+        return signal;
+    }
+}
+
+// controller -> its signal
+const signals = new WeakMap();
+
+class AbortController {
+    constructor() {
+        signals.set(this, new AbortSignal(token));
+    }
+
+    get signal() {
+        return signals.get(this);
+    }
+
+    abort(reason) {
+        abort(signals.get(this), reason);
+    }
+}
+
+globalThis.DOMException = DOMException;
+globalThis.AbortSignal = AbortSignal;
+globalThis.AbortController = AbortController;
@@ -0,0 +1,23 @@
+// `console` for user code. Arguments are joined with spaces, non-strings as JSON
+// where possible, and handed to the host as (level, message).
+
+const send = globalThis.__console;
+delete globalThis.__console;
+
+const describe = (value) => {
+    if (typeof value === "string") {
+        return value;
+    }
+    try {
+        return JSON.stringify(value) ?? String(value);
+    } catch {
+        return String(value);
+    }
+};
+
+globalThis.console = Object.fromEntries(
+    ["log", "info", "warn", "error", "debug"].map((level) => [
+        level,
+        (...args) => send(level, args.map(describe).join(" ")),
+    ]),
+);
@@ -0,0 +1,203 @@
+// `fetch` and `Headers` for user code, on top of `httpRequest`.
+//
+// A request goes through httpRequest like any other, so limits, policy, mocks and
+// recordings apply. The Response is built from the finished HttpResult: its body
+// is already read, and `text()` and `json()` only hand it out.
+
+const httpRequest = globalThis.httpRequest;
+
+// Headers -> its [lowercase name, value] pairs
+const pairsOf = new WeakMap();
+
+const normalizeName = (name) => {
+    name = String(name);
+    if (!/^[!#$%&'*+\-.^_`|~0-9A-Za-z]+$/.test(name)) {
+        throw new TypeError(`Invalid header name '${name}'`);
+    }
+    return name.toLowerCase();
+};
+
+class Headers {
+    // From another Headers, [name, value] pairs or an object
+    constructor(init) {
+        pairsOf.set(this, []);
+        if (init === undefined || init === null) {
+            return;
+        }
+        if (init instanceof Headers) {
+            init = pairsOf.get(init);
+        }
+        if (typeof init[Symbol.iterator] === "function") {
+            for (const pair of init) {
+                const entry = Array.from(pair);
+                if (entry.length !== 2) {
+                    throw new TypeError("Headers pairs must have exactly two items");
+                }
+                this.append(entry[0], entry[1]);
+            }
+        } else if (typeof init === "object") {
+            for (const name of Object.keys(init)) {
+                this.append(name, init[name]);
+            }
+        } else {
+            throw new TypeError("Headers takes an object or [name, value] pairs");
+        }
+    }
+
+    append(name, value) {
+        pairsOf.get(this).push([normalizeName(name), String(value).trim()]);
+    }
+
+    delete(name) {
+        const wanted = normalizeName(name);
+        const pairs = pairsOf.get(this);
+        pairs.splice(0, pairs.length, ...pairs.filter(([n]) => n !== wanted));
+    }
+
+    // Every value of the header joined with ", ", or null
+    get(name) {
+        const wanted = normalizeName(name);
+        const values = pairsOf.get(this).filter(([n]) => n === wanted).map(([, value]) => value);
+        return values.length > 0 ? values.join(", ") : null;
+    }
+
+    // Set-Cookie values one by one, as joining them would mangle their dates
+    getSetCookie() {
+        return pairsOf.get(this).filter(([n]) => n === "set-cookie").map(([, value]) => value);
+    }
+
+    has(name) {
+        const wanted = normalizeName(name);
+        return pairsOf.get(this).some(([n]) => n === wanted);
+    }
+
+    set(name, value) {
+        this.delete(name);
+        this.append(name, value);
+    }
+
+    forEach(callback, thisArg) {
+        for (const [name, value] of this.entries()) {
+            callback.call(thisArg, value, name, this);
+        }
+    }
+
+    // Sorted by name, with repeated headers combined
+    *entries() {
+        const names = [...new Set(pairsOf.get(this).map(([name]) => name))].sort();
+        for (const name of names) {
+            yield [name, this.get(name)];
+        }
+    }
+
+    *keys() {
+        for (const [name] of this.entries()) {
+            yield name;
+        }
+    }
+
+    *values() {
+        for (const [, value] of this.entries()) {
+            yield value;
+        }
+    }
+
+    [Symbol.iterator]() {
+        return this.entries();
+    }
+}
+
+// Response -> its HttpResult
+const results = new WeakMap();
+// Responses whose body was read
+const used = new WeakSet();
+
+const consume = (response) => {
+    if (used.has(response)) {
+        return Promise.reject(new TypeError("Response body has already been read"));
+    }
+    used.add(response);
+    return Promise.resolve(results.get(response).text);
+};
+
+// What fetch resolves to. Not a global: it can only come from fetch
+class Response {
+    constructor(result, url) {
+        results.set(this, result);
+        this.ok = result.ok;
+        this.status = result.status;
+        this.statusText = result.statusText;
+        this.headers = new Headers(result.rawHeaders);
+        this.url = result.finalUrl ?? url;
+        this.redirected = (result.redirects ?? []).length > 0;
+        this.type = "basic";
+    }
+
+    get bodyUsed() {
+        return used.has(this);
+    }
+
+    text() {
+        return consume(this);
+    }
+
+    json() {
+        return consume(this).then((text) => JSON.parse(text));
+    }
+
+    clone() {
+        if (used.has(this)) {
+            throw new TypeError("Response body has already been read");
+        }
+        return new Response(results.get(this), this.url);
+    }
+}
+
+// The httpRequest body of a fetch body, with the Content-Type browsers would send
+const requestBody = (body, headers) => {
+    if (body === undefined || body === null) {
+        return undefined;
+    }
+    let contentType;
+    if (body instanceof URLSearchParams) {
+        contentType = "application/x-www-form-urlencoded;charset=UTF-8";
+        body = body.toString();
+    } else if (typeof body === "string") {
+        contentType = "text/plain;charset=UTF-8";
+    } else {
+        throw new TypeError("fetch takes a string or URLSearchParams body; use JSON.stringify() for objects");
+    }
+    if (!headers.has("content-type")) {
+        headers.set("content-type", contentType);
+    }
+    return body;
+};
+
+globalThis.fetch = async function fetch(input, init = {}) {
+    init = init ?? {};
+    const url = typeof input === "object" && input !== null && !(input instanceof URL) && "url" in input
+        ? String(input.url)
+        : String(input);
+    const method = String(init.method ?? "GET").toUpperCase();
+    const headers = new Headers(init.headers);
+    const body = requestBody(init.body, headers);
+    if (body !== undefined && (method === "GET" || method === "HEAD")) {
+        throw new TypeError(`A ${method} request can't have a body`);
+    }
+
+    const options = { method, headers: Object.fromEntries(headers) };
+    if (body !== undefined) options.body = body;
+    if (init.redirect === "manual" || init.redirect === "error") options.redirect = "manual";
+    if (init.signal) options.signal = init.signal;
+    const result = await httpRequest(url, options);
+    // Like browsers, fetch rejects only when there is no response at all
+    if (result.errorCode) {
+        throw new TypeError(`fetch ${url} failed: ${result.data}`, { cause: new HttpError(result.data, url, options, result) });
+    }
+    if (init.redirect === "error" && result.status >= 300 && result.status < 400) {
+        throw new TypeError(`fetch ${url} was redirected, and redirect is "error"`);
+    }
+    return new Response(result, url);
+};
+
+globalThis.Headers = Headers;
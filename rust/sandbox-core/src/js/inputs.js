@@ -0,0 +1,113 @@
+// `getInput`, `inputKeys` and `inputSize`, and for inputs above LAZY_INPUTS_BYTES the
+// INPUTS proxy, on top of the `__inputs` helpers.
+//
+// Paths are JSON pointers ("/a/b/0"), dotted paths ("a.b[0]") or arrays of keys
+// (["a", "b", 0]); no path is the whole input. Without the helpers INPUTS is a
+// plain value, looked up when called so that sessions can replace it.
+
+const helpers = globalThis.__inputs;
+delete globalThis.__inputs;
+
+const keysOf = (path) => {
+    if (path === undefined) return [];
+    if (Array.isArray(path)) return path.map(String);
+    const text = String(path);
+    if (text === "" || text.startsWith("/")) {
+        return text.split("/").slice(1).map((key) => key.replace(/~1/g, "/").replace(/~0/g, "~"));
+    }
+    return text.replace(/\[(\w+)\]/g, ".$1").split(".").filter((key) => key !== "");
+};
+
+const pointer = (keys) => keys.map((key) => "/" + key.replace(/~/g, "~0").replace(/\//g, "~1")).join("");
+
+const own = Object.prototype.hasOwnProperty;
+const INDEX = /^(0|[1-9][0-9]*)$/;
+
+// The value at the keys in a materialized INPUTS, the way JSON pointers find it
+const resolve = (keys) => {
+    let current = globalThis.INPUTS;
+    for (const key of keys) {
+        if (Array.isArray(current)) {
+            if (!INDEX.test(key) || Number(key) >= current.length) return undefined;
+        } else if (current === null || typeof current !== "object" || !own.call(current, key)) {
+            return undefined;
+        }
+        current = current[key];
+    }
+    return current;
+};
+
+let getInput;
+let inputKeys;
+let inputSize;
+
+if (helpers === undefined) {
+    getInput = (path) => resolve(keysOf(path));
+    inputKeys = (path) => {
+        const value = resolve(keysOf(path));
+        return value !== null && typeof value === "object" ? Object.keys(value) : undefined;
+    };
+    inputSize = (path) => {
+        const value = resolve(keysOf(path));
+        return value === undefined ? undefined : new TextEncoder().encode(JSON.stringify(value)).length;
+    };
+} else {
+    const parse = (json) => (json === undefined ? undefined : JSON.parse(json));
+    getInput = (path) => parse(helpers.get(pointer(keysOf(path))));
+    inputKeys = (path) => helpers.keys(pointer(keysOf(path)));
+    inputSize = (path) => helpers.size(pointer(keysOf(path)));
+
+    const readOnly = () => {
+        throw new TypeError(
+            "INPUTS is read-only because the inputs are larger than LAZY_INPUTS_BYTES; " +
+                "copy the parts to change with getInput(path)",
+        );
+    };
+
+    // Proxies and values by pointer, so that reading a path twice gives the same object
+    const children = new Map();
+    const child = (at) => {
+        if (children.has(at)) return children.get(at);
+        const kind = helpers.kind(at);
+        const value = kind === "value" ? parse(helpers.get(at)) : proxy(at, kind === "array");
+        children.set(at, value);
+        return value;
+    };
+    const property = (base, key) => (typeof key === "symbol" ? undefined : base + pointer([key]));
+
+    const proxy = (base, array) =>
+        new Proxy(array ? [] : {}, {
+            get(target, key, receiver) {
+                if (array && key === "length") return helpers.length(base);
+                const at = property(base, key);
+                if (at === undefined || helpers.kind(at) === undefined) return Reflect.get(target, key, receiver);
+                return child(at);
+            },
+            has(target, key) {
+                const at = property(base, key);
+                return (at !== undefined && helpers.kind(at) !== undefined) || Reflect.has(target, key);
+            },
+            ownKeys() {
+                const keys = helpers.keys(base);
+                return array ? [...keys, "length"] : keys;
+            },
+            getOwnPropertyDescriptor(target, key) {
+                if (array && key === "length") {
+                    return { value: helpers.length(base), writable: true, enumerable: false, configurable: false };
+                }
+                const at = property(base, key);
+                if (at === undefined || helpers.kind(at) === undefined) return undefined;
+                return { value: child(at), writable: false, enumerable: true, configurable: true };
+            },
+            set: readOnly,
+            deleteProperty: readOnly,
+            defineProperty: readOnly,
+        });
+
+    const root = helpers.kind("");
+    globalThis.INPUTS = root === "value" ? parse(helpers.get("")) : proxy("", root === "array");
+}
+
+globalThis.getInput = getInput;
+globalThis.inputKeys = inputKeys;
+globalThis.inputSize = inputSize;
@@ -0,0 +1,10 @@
+// `RESULTS` and `setResult` for named outputs. Once the code finished, a non-empty
+// RESULTS is the result instead of the code's own value; the engine empties it
+// before every evaluation.
+
+const results = {};
+Object.defineProperty(globalThis, "RESULTS", { value: results, enumerable: false });
+
+globalThis.setResult = (name, value) => {
+    results[String(name)] = value;
+};
@@ -0,0 +1,45 @@
+// JSON.stringify replacer turning values JSON has no representation for into
+// useful JSON. Dates already serialize through their toJSON method.
+
+const MAX_DEPTH = 100;
+const encodeBase64 = base64.encode;
+
+export function createReplacer(bigintMode) {
+    // Depth of every object visited, keyed by the object holding it
+    const depths = new WeakMap();
+
+    return function replacer(key, value) {
+        const depth = (depths.get(this) ?? -1) + 1;
+
+        if (typeof value === "bigint") {
+            if (bigintMode === "number" && Number.isSafeInteger(Number(value))) {
+                return Number(value);
+            }
+            // Left alone with "error", so JSON.stringify rejects it
+            return bigintMode === "error" ? value : String(value);
+        }
+        if (value === null || typeof value !== "object") {
+            return value;
+        }
+        if (depth > MAX_DEPTH) {
+            throw new RangeError(`result is nested more than ${MAX_DEPTH} levels deep`);
+        }
+
+        let normalized = value;
+        if (value instanceof Map) {
+            const keys = [...value.keys()];
+            normalized = keys.every((k) => typeof k === "string")
+                ? Object.fromEntries(value)
+                : [...value.entries()];
+        } else if (value instanceof Set) {
+            normalized = [...value];
+        } else if (value instanceof ArrayBuffer) {
+            normalized = { type: "ArrayBuffer", base64: encodeBase64(value) };
+        } else if (ArrayBuffer.isView(value)) {
+            const bytes = new Uint8Array(value.buffer, value.byteOffset, value.byteLength);
+            normalized = { type: value.constructor.name, base64: encodeBase64(bytes) };
+        }
+        depths.set(normalized, depth);
+        return normalized;
+    };
+}
@@ -0,0 +1,32 @@
+// `sleep`, `setTimeout` and `clearTimeout` for user code. The host takes each wait
+// out of the sleep budget, throwing when it doesn't fit, and then waits.
+
+const { reserve, wait } = globalThis.__timers;
+
+// Ids of the timers that haven't fired or been cleared
+const pending = new Set();
+let nextId = 1;
+
+globalThis.sleep = async function sleep(ms) {
+    reserve(Number(ms));
+    await wait(Number(ms));
+};
+
+globalThis.setTimeout = function setTimeout(callback, ms = 0, ...args) {
+    if (typeof callback !== "function") {
+        throw new TypeError("setTimeout needs a function");
+    }
+    reserve(Number(ms));
+    const id = nextId++;
+    pending.add(id);
+    wait(Number(ms)).then(() => {
+        if (pending.delete(id)) {
+            callback(...args);
+        }
+    });
+    return id;
+};
+
+globalThis.clearTimeout = function clearTimeout(id) {
+    pending.delete(id);
+};
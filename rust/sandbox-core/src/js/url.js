@@ -0,0 +1,191 @@
+// `URL` and `URLSearchParams` for user code, on top of the `__url` helpers.
+//
+// Internal state lives in WeakMaps so user code can't reach it. A URL's
+// `searchParams` is live: changing it rewrites the URL's query, and setting
+// `search` or `href` updates the params.
+
+const helpers = globalThis.__url;
+delete globalThis.__url;
+
+// URL -> its components
+const urls = new WeakMap();
+// URLSearchParams -> its [name, value] pairs
+const pairsOf = new WeakMap();
+// URLSearchParams -> the URL it belongs to, and back
+const owners = new WeakMap();
+const paramsOf = new WeakMap();
+
+const update = (params) => {
+    const url = owners.get(params);
+    if (url) {
+        const query = helpers.serializeSearch(pairsOf.get(params));
+        urls.set(url, helpers.set(urls.get(url).href, "search", query));
+    }
+};
+
+class URLSearchParams {
+    // From a query string (with or without its `?`), [name, value] pairs, an object
+    // or another URLSearchParams
+    constructor(init = "") {
+        let pairs;
+        if (init instanceof URLSearchParams) {
+            pairs = pairsOf.get(init).map(([name, value]) => [name, value]);
+        } else if (init !== null && typeof init === "object" && typeof init[Symbol.iterator] === "function") {
+            pairs = Array.from(init, (pair) => {
+                const entry = Array.from(pair);
+                if (entry.length !== 2) {
+                    throw new TypeError("URLSearchParams pairs must have exactly two items");
+                }
+                return [String(entry[0]), String(entry[1])];
+            });
+        } else if (init !== null && typeof init === "object") {
+            pairs = Object.keys(init).map((name) => [name, String(init[name])]);
+        } else {
+            pairs = helpers.parseSearch(String(init).replace(/^\?/, ""));
+        }
+        pairsOf.set(this, pairs);
+    }
+
+    get size() {
+        return pairsOf.get(this).length;
+    }
+
+    append(name, value) {
+        pairsOf.get(this).push([String(name), String(value)]);
+        update(this);
+    }
+
+    // Every pair with the name, or only those with the value too
+    delete(name, value) {
+        const pairs = pairsOf.get(this);
+        const keep = pairs.filter(([n, v]) => n !== String(name) || (value !== undefined && v !== String(value)));
+        pairs.splice(0, pairs.length, ...keep);
+        update(this);
+    }
+
+    get(name) {
+        const pair = pairsOf.get(this).find(([n]) => n === String(name));
+        return pair ? pair[1] : null;
+    }
+
+    getAll(name) {
+        return pairsOf.get(this).filter(([n]) => n === String(name)).map(([, value]) => value);
+    }
+
+    has(name, value) {
+        return pairsOf.get(this).some(([n, v]) => n === String(name) && (value === undefined || v === String(value)));
+    }
+
+    // Replaces the first pair with the name and removes the others, or appends one
+    set(name, value) {
+        name = String(name);
+        const pairs = pairsOf.get(this);
+        const index = pairs.findIndex(([n]) => n === name);
+        if (index < 0) {
+            pairs.push([name, String(value)]);
+        } else {
+            pairs[index] = [name, String(value)];
+            const keep = pairs.filter(([n], i) => n !== name || i === index);
+            pairs.splice(0, pairs.length, ...keep);
+        }
+        update(this);
+    }
+
+    // By name, keeping the order of pairs with the same name
+    sort() {
+        const pairs = pairsOf.get(this);
+        pairs.sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+        update(this);
+    }
+
+    forEach(callback, thisArg) {
+        for (const [name, value] of pairsOf.get(this)) {
+            callback.call(thisArg, value, name, this);
+        }
+    }
+
+    *entries() {
+        for (const [name, value] of pairsOf.get(this)) {
+            yield [name, value];
+        }
+    }
+
+    *keys() {
+        for (const [name] of pairsOf.get(this)) {
+            yield name;
+        }
+    }
+
+    *values() {
+        for (const [, value] of pairsOf.get(this)) {
+            yield value;
+        }
+    }
+
+    [Symbol.iterator]() {
+        return this.entries();
+    }
+
+    // application/x-www-form-urlencoded, without a leading `?`
+    toString() {
+        return helpers.serializeSearch(pairsOf.get(this));
+    }
+}
+
+class URL {
+    constructor(url, base) {
+        urls.set(this, helpers.parse(String(url), base === undefined ? undefined : String(base)));
+    }
+
+    static canParse(url, base) {
+        try {
+            helpers.parse(String(url), base === undefined ? undefined : String(base));
+            return true;
+        } catch {
+            return false;
+        }
+    }
+
+    get origin() {
+        return urls.get(this).origin;
+    }
+
+    get searchParams() {
+        let params = paramsOf.get(this);
+        if (!params) {
+            params = new URLSearchParams(urls.get(this).search);
+            owners.set(params, this);
+            paramsOf.set(this, params);
+        }
+        return params;
+    }
+
+    toString() {
+        return urls.get(this).href;
+    }
+
+    toJSON() {
+        return urls.get(this).href;
+    }
+}
+
+for (const component of ["href", "protocol", "username", "password", "host", "hostname", "port", "pathname", "search", "hash"]) {
+    Object.defineProperty(URL.prototype, component, {
+        get() {
+            return urls.get(this)[component];
+        },
+        set(value) {
+            const parts = helpers.set(urls.get(this).href, component, String(value));
+            urls.set(this, parts);
+            const params = paramsOf.get(this);
+            if (params && (component === "search" || component === "href")) {
+                pairsOf.set(params, helpers.parseSearch(parts.search.replace(/^\?/, "")));
+            }
+        },
+        enumerable: true,
+        configurable: true,
+    });
+}
+
+globalThis.URL = URL;
+globalThis.URLSearchParams = URLSearchParams;
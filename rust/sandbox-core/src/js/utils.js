@@ -0,0 +1,198 @@
+// Utility library installed as the frozen `utils` global and the `sandbox:utils` module.
+// Helpers never mutate their arguments, except `set`.
+
+const VERSION = "1.0.0";
+
+function toPath(path) {
+    if (Array.isArray(path)) return path;
+    return String(path)
+        .replace(/\[(\w+)\]/g, ".$1")
+        .split(".")
+        .filter((key) => key !== "");
+}
+
+function iteratee(fn) {
+    return typeof fn === "function" ? fn : (item) => get(item, fn);
+}
+
+function isPlainObject(value) {
+    if (value === null || typeof value !== "object") return false;
+    const proto = Object.getPrototypeOf(value);
+    return proto === Object.prototype || proto === null;
+}
+
+// get({a: {b: [1, 2]}}, "a.b[1]") === 2; missing paths return `fallback`
+function get(obj, path, fallback) {
+    let current = obj;
+    for (const key of toPath(path)) {
+        if (current === null || current === undefined) return fallback;
+        current = current[key];
+    }
+    return current === undefined ? fallback : current;
+}
+
+// Sets a value by path in place, creating intermediate objects or arrays, and returns obj
+function set(obj, path, value) {
+    const keys = toPath(path);
+    let current = obj;
+    keys.forEach((key, index) => {
+        if (index === keys.length - 1) {
+            current[key] = value;
+        } else {
+            if (current[key] === null || typeof current[key] !== "object") {
+                current[key] = /^\d+$/.test(keys[index + 1]) ? [] : {};
+            }
+            current = current[key];
+        }
+    });
+    return obj;
+}
+
+function groupBy(items, fn) {
+    const by = iteratee(fn);
+    const groups = {};
+    for (const item of items) {
+        const key = by(item);
+        (groups[key] = groups[key] || []).push(item);
+    }
+    return groups;
+}
+
+function uniqBy(items, fn) {
+    const by = iteratee(fn);
+    const seen = new Set();
+    return items.filter((item) => {
+        const key = by(item);
+        if (seen.has(key)) return false;
+        seen.add(key);
+        return true;
+    });
+}
+
+function chunk(items, size) {
+    if (!(size >= 1)) throw new RangeError("chunk size must be at least 1");
+    const chunks = [];
+    for (let i = 0; i < items.length; i += size) {
+        chunks.push(items.slice(i, i + size));
+    }
+    return chunks;
+}
+
+// range(3) -> [0, 1, 2]; range(1, 7, 2) -> [1, 3, 5]
+function range(start, end, step) {
+    if (end === undefined) {
+        end = start;
+        start = 0;
+    }
+    if (step === undefined) step = start <= end ? 1 : -1;
+    if (step === 0) throw new RangeError("range step must not be 0");
+    const result = [];
+    for (let i = start; step > 0 ? i < end : i > end; i += step) {
+        result.push(i);
+    }
+    return result;
+}
+
+function sum(items, fn) {
+    const by = fn === undefined ? (item) => item : iteratee(fn);
+    return items.reduce((total, item) => total + by(item), 0);
+}
+
+// NaN for an empty array, like dividing by zero items
+function mean(items, fn) {
+    return items.length === 0 ? NaN : sum(items, fn) / items.length;
+}
+
+// Stable ascending sort by one or more keys
+function sortBy(items, ...fns) {
+    const bys = (fns.length === 0 ? [(item) => item] : fns).map(iteratee);
+    return items
+        .map((item, index) => ({ item, index }))
+        .sort((a, b) => {
+            for (const by of bys) {
+                const left = by(a.item);
+                const right = by(b.item);
+                if (left < right) return -1;
+                if (left > right) return 1;
+            }
+            return a.index - b.index;
+        })
+        .map(({ item }) => item);
+}
+
+function deepEqual(a, b) {
+    if (Object.is(a, b)) return true;
+    if (a instanceof Date && b instanceof Date) return a.getTime() === b.getTime();
+    if (a === null || b === null || typeof a !== "object" || typeof b !== "object") return false;
+    if (Array.isArray(a) !== Array.isArray(b)) return false;
+
+    const keys = Object.keys(a);
+    if (keys.length !== Object.keys(b).length) return false;
+    return keys.every((key) => Object.prototype.hasOwnProperty.call(b, key) && deepEqual(a[key], b[key]));
+}
+
+// Merges plain objects recursively into a new object; arrays and other values are replaced
+function deepMerge(...sources) {
+    const result = {};
+    for (const source of sources) {
+        if (!isPlainObject(source)) continue;
+        for (const [key, value] of Object.entries(source)) {
+            result[key] = isPlainObject(value) && isPlainObject(result[key])
+                ? deepMerge(result[key], value)
+                : value;
+        }
+    }
+    return result;
+}
+
+function pad(value, length, fill) {
+    return String(value).padStart(length, fill === undefined ? "0" : fill);
+}
+
+// formatDate(new Date(), "YYYY-MM-DD HH:mm:ss"), in UTC
+function formatDate(date, format) {
+    const d = date instanceof Date ? date : new Date(date);
+    if (isNaN(d.getTime())) throw new RangeError("Invalid date");
+    const parts = {
+        YYYY: pad(d.getUTCFullYear(), 4),
+        MM: pad(d.getUTCMonth() + 1, 2),
+        DD: pad(d.getUTCDate(), 2),
+        HH: pad(d.getUTCHours(), 2),
+        mm: pad(d.getUTCMinutes(), 2),
+        ss: pad(d.getUTCSeconds(), 2),
+    };
+    return (format || "YYYY-MM-DD").replace(/YYYY|MM|DD|HH|mm|ss/g, (token) => parts[token]);
+}
+
+// formatNumber(1234.5, 2) -> "1,234.50"
+function formatNumber(value, decimals) {
+    const fixed = Number(value).toFixed(decimals === undefined ? 0 : decimals);
+    const [integer, fraction] = fixed.split(".");
+    const grouped = integer.replace(/\B(?=(\d{3})+(?!\d))/g, ",");
+    return fraction === undefined ? grouped : grouped + "." + fraction;
+}
+
+function truncate(value, length, suffix) {
+    const text = String(value);
+    const end = suffix === undefined ? "…" : suffix;
+    return text.length <= length ? text : text.slice(0, Math.max(0, length - end.length)) + end;
+}
+
+globalThis.utils = Object.freeze({
+    VERSION,
+    get,
+    set,
+    groupBy,
+    uniqBy,
+    chunk,
+    range,
+    sum,
+    mean,
+    sortBy,
+    deepEqual,
+    deepMerge,
+    pad,
+    formatDate,
+    formatNumber,
+    truncate,
+});
@@ -0,0 +1,189 @@
+// Structured details of exceptions thrown by user code.
+//
+// User code is the only classic script evaluated in an execution context under
+// `eval_script` (the helpers are loaded as modules, the prelude scripts have names
+// of their own), so every `eval_script` frame in a stack trace belongs to it. Those
+// frames are reported as `user_code.js`, the name user code evaluated as a module
+// has anyway. The code is evaluated as submitted, so line numbers need no
+// adjustment. Frames of the prelude scripts are left out, the server's scripts
+// being none of the caller's business.
+//
+// The thrown value itself is kept as JSON too: the enumerable own properties of an
+// Error (e.g. a `code` set by an Error subclass), or the whole value when something
+// else was thrown, such as `{ code: "VALIDATION", details }` or a string. Values
+// JSON can't represent (circular objects, BigInts) are left out.
+
+use rquickjs::{Coerced, Ctx, Value as JsValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::prelude_scripts::FILENAME_PREFIX;
+
+pub const USER_CODE_FILENAME: &str = "user_code.js";
+const EVAL_FILENAME: &str = "eval_script:";
+const USER_CODE_LOCATION: &str = "user_code.js:";
+// Thrown objects without a `message` are described by their JSON up to this size
+const MAX_JSON_MESSAGE_BYTES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JsError {
+    // Absent when something other than an Error object was thrown
+    pub name: Option<String>,
+    pub message: String,
+    pub stack: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    // Enumerable own properties of a thrown Error, other than the ones above
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<Value>,
+    // The value thrown, when it isn't an Error; reported as `thrown` next to `jsError`
+    #[serde(skip)]
+    pub thrown: Option<Value>,
+}
+
+impl JsError {
+    // Takes the pending exception after rquickjs reported `Error::Exception`
+    pub fn catch(ctx: &Ctx<'_>) -> Self {
+        let exception = ctx.catch();
+        let coerced = || {
+            exception
+                .get::<Coerced<String>>()
+                .map(|s| s.0)
+                .unwrap_or_else(|_| "<unprintable exception>".to_string())
+        };
+
+        let Some(obj) = exception.as_object() else {
+            return JsError {
+                name: None,
+                message: coerced(),
+                stack: None,
+                line: None,
+                column: None,
+                properties: None,
+                thrown: to_json(ctx, exception.clone()),
+            };
+        };
+
+        let field = |key: &str| obj.get::<_, Option<Coerced<String>>>(key).ok().flatten().map(|s| s.0);
+        if !exception.is_error() {
+            let thrown = to_json(ctx, exception.clone());
+            let message = match (field("message"), &thrown) {
+                (Some(message), _) => message,
+                (None, Some(thrown)) if thrown.to_string().len() <= MAX_JSON_MESSAGE_BYTES => thrown.to_string(),
+                (None, _) => coerced(),
+            };
+            return JsError {
+                name: None,
+                message,
+                stack: None,
+                line: None,
+                column: None,
+                properties: None,
+                thrown,
+            };
+        }
+
+        let (stack, location) = match field("stack") {
+            Some(stack) if !stack.is_empty() => {
+                let (stack, location) = rewrite_locations(stack.trim_end());
+                (Some(stack), location)
+            }
+            _ => (None, None),
+        };
+
+        JsError {
+            name: field("name"),
+            message: field("message").unwrap_or_default(),
+            stack,
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+            properties: properties(ctx, obj),
+            thrown: None,
+        }
+    }
+
+    // Drops the thrown value and the properties when their JSON is larger than
+    // `max_bytes`, the result size limit
+    pub fn cap(&mut self, max_bytes: usize) {
+        let too_large = |value: &Option<Value>| value.as_ref().is_some_and(|value| value.to_string().len() > max_bytes);
+        if too_large(&self.thrown) {
+            self.thrown = None;
+        }
+        if too_large(&self.properties) {
+            self.properties = None;
+        }
+    }
+
+    // `TypeError: message`, as JavaScript would print it
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) if !self.message.is_empty() => format!("{}: {}", name, self.message),
+            Some(name) => name.clone(),
+            None => self.message.clone(),
+        }
+    }
+}
+
+// The value as `JSON.stringify` would return it, or None when it throws or returns
+// nothing. Exceptions are cleared, so the exception being reported is unaffected.
+fn to_json<'js>(ctx: &Ctx<'js>, value: JsValue<'js>) -> Option<Value> {
+    match ctx.json_stringify(value) {
+        Ok(json) => serde_json::from_str(&json?.to_string().ok()?).ok(),
+        Err(_) => {
+            let _ = ctx.catch();
+            None
+        }
+    }
+}
+
+// Every enumerable own property that has a JSON representation; None when there
+// are none
+fn properties<'js>(ctx: &Ctx<'js>, obj: &rquickjs::Object<'js>) -> Option<Value> {
+    let mut properties = Map::new();
+    for key in obj.keys::<String>().flatten() {
+        if matches!(key.as_str(), "name" | "message" | "stack") {
+            continue;
+        }
+        // A getter may throw
+        let Ok(value) = obj.get::<_, JsValue>(key.as_str()) else {
+            let _ = ctx.catch();
+            continue;
+        };
+        if let Some(value) = to_json(ctx, value) {
+            properties.insert(key, value);
+        }
+    }
+    (!properties.is_empty()).then_some(Value::Object(properties))
+}
+
+// Renames `eval_script` locations to `user_code.js`, drops the frames of prelude
+// scripts and returns the rewritten stack along with the first user code location,
+// i.e. where the exception was thrown
+pub fn rewrite_locations(stack: &str) -> (String, Option<(u32, u32)>) {
+    let stack = stack
+        .replace(EVAL_FILENAME, USER_CODE_LOCATION)
+        .lines()
+        .filter(|line| !is_prelude_frame(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let first = stack
+        .match_indices(USER_CODE_LOCATION)
+        .find_map(|(start, prefix)| parse_location(&stack[start + prefix.len()..]));
+    (stack, first)
+}
+
+// `    at helper (prelude:lib.js:3:7)`, or `    at prelude:lib.js:1:1` for the
+// script's own top level
+fn is_prelude_frame(line: &str) -> bool {
+    let frame = line.trim_start().strip_prefix("at ").unwrap_or_default();
+    frame.starts_with(FILENAME_PREFIX) || frame.contains(&format!("({}", FILENAME_PREFIX))
+}
+
+// `12:5)...` -> (12, 5)
+fn parse_location(text: &str) -> Option<(u32, u32)> {
+    let end = text.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(text.len());
+    let mut parts = text[..end].splitn(2, ':');
+    let line = parts.next()?.parse().ok()?;
+    let column = parts.next()?.parse().ok()?;
+    Some((line, column))
+}
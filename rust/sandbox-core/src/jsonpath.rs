@@ -0,0 +1,607 @@
+// JSONPath queries over JavaScript values, installed as the `jsonpath` and
+// `jsonpathFirst` globals.
+//
+// Expressions follow RFC 9535: `$` followed by child segments (`.name`, `['name']`,
+// `[0]`, `[-1]`, `[1:5:2]`, `.*`, `[?filter]`) and descendant segments (`..name`,
+// `..*`, `..[...]`). Filters compare `@`/`$` paths and literals with `==`, `!=`,
+// `<`, `<=`, `>`, `>=`, combine them with `&&`, `||` and `!`, and test a path on
+// its own for existence; the Goessner form `[?(@.price > 10)]` parses too. The
+// value is walked in place, so documents aren't serialized or copied for a query,
+// and the matches are the values themselves, not copies.
+
+use rquickjs::function::Func;
+use rquickjs::{Coerced, Ctx, Exception, Result, Value};
+
+#[derive(Debug)]
+enum Segment {
+    Child(Vec<Selector>),
+    Descendant(Vec<Selector>),
+}
+
+#[derive(Debug)]
+enum Selector {
+    Name(String),
+    Wildcard,
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Filter(Filter),
+}
+
+#[derive(Debug)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(Operand, Comparison, Operand),
+    // A path on its own, true when it matches anything
+    Exists(Query),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Query(Query),
+    Literal(Literal),
+}
+
+#[derive(Debug)]
+struct Query {
+    // `@` rather than `$`
+    relative: bool,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(found) => format!(
+                "Invalid JSONPath '{}': expected {} at position {}, found '{}'",
+                self.expression, expected, self.position, found
+            ),
+            None => format!("Invalid JSONPath '{}': expected {} at the end", self.expression, expected),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.expression[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.position = self.expression.len() - trimmed.len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> std::result::Result<(), String> {
+        self.skip_whitespace();
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{}'", token))),
+        }
+    }
+
+    fn parse(mut self) -> std::result::Result<Vec<Segment>, String> {
+        self.skip_whitespace();
+        if !self.eat("$") {
+            return Err(self.error("'$'"));
+        }
+        let segments = self.segments()?;
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(segments),
+            Some(_) => Err(self.error("'.', '..' or '['")),
+        }
+    }
+
+    fn segments(&mut self) -> std::result::Result<Vec<Segment>, String> {
+        let mut segments = Vec::new();
+        loop {
+            // Whitespace may only come before a segment that starts with a bracket or dot
+            let start = self.position;
+            self.skip_whitespace();
+            if self.eat("..") {
+                let selectors = match self.peek() {
+                    Some('[') => self.bracket()?,
+                    _ => vec![self.dotted()?],
+                };
+                segments.push(Segment::Descendant(selectors));
+            } else if self.eat(".") {
+                segments.push(Segment::Child(vec![self.dotted()?]));
+            } else if self.peek() == Some('[') {
+                segments.push(Segment::Child(self.bracket()?));
+            } else {
+                self.position = start;
+                return Ok(segments);
+            }
+        }
+    }
+
+    // The `name` or `*` after a dot
+    fn dotted(&mut self) -> std::result::Result<Selector, String> {
+        if self.eat("*") {
+            return Ok(Selector::Wildcard);
+        }
+        let name: String = self
+            .rest()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '$' | '-') || !c.is_ascii())
+            .collect();
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            return Err(self.error("a member name or '*'"));
+        }
+        self.position += name.len();
+        Ok(Selector::Name(name))
+    }
+
+    fn bracket(&mut self) -> std::result::Result<Vec<Selector>, String> {
+        self.expect("[")?;
+        let mut selectors = vec![self.selector()?];
+        loop {
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(selectors);
+            }
+            self.expect(",")?;
+            selectors.push(self.selector()?);
+        }
+    }
+
+    fn selector(&mut self) -> std::result::Result<Selector, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('\'' | '"') => Ok(Selector::Name(self.string()?)),
+            Some('*') => {
+                self.position += 1;
+                Ok(Selector::Wildcard)
+            }
+            Some('?') => {
+                self.position += 1;
+                Ok(Selector::Filter(self.or()?))
+            }
+            Some(':') => self.slice(None),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let index = self.integer()?;
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(':') => self.slice(Some(index)),
+                    _ => Ok(Selector::Index(index)),
+                }
+            }
+            _ => Err(self.error("a name, index, slice, '*' or filter")),
+        }
+    }
+
+    // The rest of `start:end:step`, from the first colon
+    fn slice(&mut self, start: Option<i64>) -> std::result::Result<Selector, String> {
+        self.expect(":")?;
+        let bound = |parser: &mut Self| -> std::result::Result<Option<i64>, String> {
+            parser.skip_whitespace();
+            match parser.peek() {
+                Some(c) if c == '-' || c.is_ascii_digit() => parser.integer().map(Some),
+                _ => Ok(None),
+            }
+        };
+        let end = bound(self)?;
+        self.skip_whitespace();
+        let step = match self.eat(":") {
+            true => bound(self)?,
+            false => None,
+        };
+        if step == Some(0) {
+            return Err(format!("Invalid JSONPath '{}': a slice step can't be 0", self.expression));
+        }
+        Ok(Selector::Slice(start, end, step))
+    }
+
+    fn integer(&mut self) -> std::result::Result<i64, String> {
+        let length = self.rest().char_indices().take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && *c == '-')).count();
+        let digits = &self.rest()[..length];
+        match digits.parse() {
+            Ok(integer) => {
+                self.position += length;
+                Ok(integer)
+            }
+            Err(_) => Err(self.error("an integer")),
+        }
+    }
+
+    fn number(&mut self) -> std::result::Result<f64, String> {
+        // A sign only at the start or after the exponent
+        let mut previous = None;
+        let length = self
+            .rest()
+            .chars()
+            .take_while(|&c| {
+                let part = c.is_ascii_digit()
+                    || matches!(c, '.' | 'e' | 'E')
+                    || (matches!(c, '-' | '+') && matches!(previous, None | Some('e' | 'E')));
+                previous = Some(c);
+                part
+            })
+            .count();
+        match self.rest()[..length].parse() {
+            Ok(number) => {
+                self.position += length;
+                Ok(number)
+            }
+            Err(_) => Err(self.error("a number")),
+        }
+    }
+
+    // A single or double quoted string, with JSON style escapes
+    fn string(&mut self) -> std::result::Result<String, String> {
+        let quote = self.peek().unwrap_or('\'');
+        self.position += 1;
+        let mut string = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.position += offset + 1;
+                    return Ok(string);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                            Some(c) => string.push(c),
+                            None => {
+                                self.position += offset;
+                                return Err(self.error("an escape of four hex digits"));
+                            }
+                        }
+                    }
+                    Some(c) => string.push(c),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        self.position = self.expression.len();
+        Err(self.error(&format!("a closing {}", quote)))
+    }
+
+    fn or(&mut self) -> std::result::Result<Filter, String> {
+        let mut filter = self.and()?;
+        loop {
+            self.skip_whitespace();
+            if !self.eat("||") {
+                return Ok(filter);
+            }
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+    }
+
+    fn and(&mut self) -> std::result::Result<Filter, String> {
+        let mut filter = self.unary()?;
+        loop {
+            self.skip_whitespace();
+            if !self.eat("&&") {
+                return Ok(filter);
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> std::result::Result<Filter, String> {
+        self.skip_whitespace();
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let filter = self.or()?;
+            self.expect(")")?;
+            return Ok(filter);
+        }
+        let left = self.operand()?;
+        self.skip_whitespace();
+        let comparison = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.rest().starts_with(token));
+        match (comparison, left) {
+            (Some((token, comparison)), left) => {
+                self.position += token.len();
+                Ok(Filter::Compare(left, comparison, self.operand()?))
+            }
+            (None, Operand::Query(query)) => Ok(Filter::Exists(query)),
+            (None, Operand::Literal(_)) => Err(self.error("a comparison")),
+        }
+    }
+
+    fn operand(&mut self) -> std::result::Result<Operand, String> {
+        self.skip_whitespace();
+        for (token, relative) in [("@", true), ("$", false)] {
+            if self.eat(token) {
+                return Ok(Operand::Query(Query {
+                    relative,
+                    segments: self.segments()?,
+                }));
+            }
+        }
+        let literal = match self.peek() {
+            Some('\'' | '"') => Literal::String(self.string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => Literal::Number(self.number()?),
+            _ if self.eat("true") => Literal::Bool(true),
+            _ if self.eat("false") => Literal::Bool(false),
+            _ if self.eat("null") => Literal::Null,
+            _ => return Err(self.error("'@', '$' or a literal")),
+        };
+        Ok(Operand::Literal(literal))
+    }
+}
+
+fn parse(expression: &str) -> std::result::Result<Vec<Segment>, String> {
+    Parser { expression, position: 0 }.parse()
+}
+
+// A value a filter compares
+enum Compared<'js> {
+    // A path that matched nothing, or more than one value
+    Nothing,
+    Literal(Literal),
+    // Objects and arrays, equal only to themselves
+    Other(Value<'js>),
+}
+
+impl<'js> Compared<'js> {
+    fn of(value: &Value<'js>) -> Result<Self> {
+        Ok(if value.is_null() {
+            Compared::Literal(Literal::Null)
+        } else if value.is_undefined() {
+            Compared::Nothing
+        } else if let Some(boolean) = value.as_bool() {
+            Compared::Literal(Literal::Bool(boolean))
+        } else if let Some(number) = value.as_number() {
+            Compared::Literal(Literal::Number(number))
+        } else if let Some(string) = value.as_string() {
+            Compared::Literal(Literal::String(string.to_string()?))
+        } else {
+            Compared::Other(value.clone())
+        })
+    }
+
+    fn equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Compared::Nothing, Compared::Nothing) => true,
+            (Compared::Literal(Literal::Null), Compared::Literal(Literal::Null)) => true,
+            (Compared::Literal(Literal::Bool(a)), Compared::Literal(Literal::Bool(b))) => a == b,
+            (Compared::Literal(Literal::Number(a)), Compared::Literal(Literal::Number(b))) => a == b,
+            (Compared::Literal(Literal::String(a)), Compared::Literal(Literal::String(b))) => a == b,
+            (Compared::Other(a), Compared::Other(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn less(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Compared::Literal(Literal::Number(a)), Compared::Literal(Literal::Number(b))) => a < b,
+            (Compared::Literal(Literal::String(a)), Compared::Literal(Literal::String(b))) => a < b,
+            _ => false,
+        }
+    }
+}
+
+struct Evaluation<'js> {
+    root: Value<'js>,
+}
+
+impl<'js> Evaluation<'js> {
+    fn query(&self, current: &Value<'js>, segments: &[Segment]) -> Result<Vec<Value<'js>>> {
+        let mut nodes = vec![current.clone()];
+        for segment in segments {
+            let mut next = Vec::new();
+            match segment {
+                Segment::Child(selectors) => {
+                    for node in &nodes {
+                        for selector in selectors {
+                            self.select(node, selector, &mut next)?;
+                        }
+                    }
+                }
+                Segment::Descendant(selectors) => {
+                    for node in &nodes {
+                        for descendant in descendants(node)? {
+                            for selector in selectors {
+                                self.select(&descendant, selector, &mut next)?;
+                            }
+                        }
+                    }
+                }
+            }
+            nodes = next;
+        }
+        Ok(nodes)
+    }
+
+    fn select(&self, node: &Value<'js>, selector: &Selector, out: &mut Vec<Value<'js>>) -> Result<()> {
+        match selector {
+            Selector::Name(name) => {
+                if let Some(object) = node.as_object().filter(|_| !node.is_array()) {
+                    if object.contains_key(name.as_str())? {
+                        out.push(object.get(name.as_str())?);
+                    }
+                }
+            }
+            Selector::Wildcard => out.extend(children(node)?),
+            Selector::Index(index) => {
+                if let Some(array) = node.as_array() {
+                    let length = array.len() as i64;
+                    let index = if *index < 0 { length + index } else { *index };
+                    if (0..length).contains(&index) {
+                        out.push(array.get(index as usize)?);
+                    }
+                }
+            }
+            Selector::Slice(start, end, step) => {
+                if let Some(array) = node.as_array() {
+                    for index in slice_indices(array.len() as i64, *start, *end, step.unwrap_or(1)) {
+                        out.push(array.get(index as usize)?);
+                    }
+                }
+            }
+            Selector::Filter(filter) => {
+                for child in children(node)? {
+                    if self.test(filter, &child)? {
+                        out.push(child);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn test(&self, filter: &Filter, current: &Value<'js>) -> Result<bool> {
+        Ok(match filter {
+            Filter::Or(a, b) => self.test(a, current)? || self.test(b, current)?,
+            Filter::And(a, b) => self.test(a, current)? && self.test(b, current)?,
+            Filter::Not(filter) => !self.test(filter, current)?,
+            Filter::Exists(query) => !self.run(query, current)?.is_empty(),
+            Filter::Compare(left, comparison, right) => {
+                let (left, right) = (self.operand(left, current)?, self.operand(right, current)?);
+                match comparison {
+                    Comparison::Eq => left.equals(&right),
+                    Comparison::Ne => !left.equals(&right),
+                    Comparison::Lt => left.less(&right),
+                    Comparison::Le => left.less(&right) || left.equals(&right),
+                    Comparison::Gt => right.less(&left),
+                    Comparison::Ge => right.less(&left) || left.equals(&right),
+                }
+            }
+        })
+    }
+
+    fn run(&self, query: &Query, current: &Value<'js>) -> Result<Vec<Value<'js>>> {
+        let start = if query.relative { current } else { &self.root };
+        self.query(start, &query.segments)
+    }
+
+    fn operand(&self, operand: &Operand, current: &Value<'js>) -> Result<Compared<'js>> {
+        match operand {
+            Operand::Literal(literal) => Ok(Compared::Literal(literal.clone())),
+            Operand::Query(query) => match self.run(query, current)?.as_slice() {
+                [value] => Compared::of(value),
+                _ => Ok(Compared::Nothing),
+            },
+        }
+    }
+}
+
+// Elements of an array, values of an object, nothing for anything else
+fn children<'js>(node: &Value<'js>) -> Result<Vec<Value<'js>>> {
+    if let Some(array) = node.as_array() {
+        return array.iter().collect();
+    }
+    match node.as_object() {
+        Some(object) if !node.is_function() => object.values::<Value>().collect(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+// The node and everything below it, parents first. A value that contains itself
+// is visited once per path to it, never below itself.
+fn descendants<'js>(node: &Value<'js>) -> Result<Vec<Value<'js>>> {
+    fn visit<'js>(node: Value<'js>, ancestors: &mut Vec<Value<'js>>, out: &mut Vec<Value<'js>>) -> Result<()> {
+        if ancestors.contains(&node) {
+            return Ok(());
+        }
+        out.push(node.clone());
+        let below = children(&node)?;
+        ancestors.push(node);
+        for child in below {
+            visit(child, ancestors, out)?;
+        }
+        ancestors.pop();
+        Ok(())
+    }
+    let mut out = Vec::new();
+    visit(node.clone(), &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+// Indices a slice selects, in the order it selects them
+fn slice_indices(length: i64, start: Option<i64>, end: Option<i64>, step: i64) -> Vec<i64> {
+    let normalize = |index: i64| if index < 0 { length + index } else { index };
+    if step > 0 {
+        let start = start.map_or(0, normalize).clamp(0, length);
+        let end = end.map_or(length, normalize).clamp(0, length);
+        (start..end).step_by(step as usize).collect()
+    } else {
+        let start = start.map_or(length - 1, normalize).clamp(-1, length - 1);
+        let end = end.map_or(-1, normalize).clamp(-1, length - 1);
+        let mut indices = Vec::new();
+        let mut index = start;
+        while index > end {
+            indices.push(index);
+            index += step;
+        }
+        indices
+    }
+}
+
+fn matches<'js>(ctx: &Ctx<'js>, value: Value<'js>, expression: &str) -> Result<Vec<Value<'js>>> {
+    let segments = parse(expression).map_err(|message| Exception::throw_syntax(ctx, &message))?;
+    Evaluation { root: value.clone() }.query(&value, &segments)
+}
+
+// Every match, in document order
+fn jsonpath<'js>(ctx: Ctx<'js>, value: Value<'js>, expression: Coerced<String>) -> Result<Vec<Value<'js>>> {
+    matches(&ctx, value, &expression)
+}
+
+// The first match, undefined without one
+fn jsonpath_first<'js>(ctx: Ctx<'js>, value: Value<'js>, expression: Coerced<String>) -> Result<Option<Value<'js>>> {
+    Ok(matches(&ctx, value, &expression)?.into_iter().next())
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    ctx.globals().set("jsonpath", Func::from(jsonpath))?;
+    ctx.globals().set("jsonpathFirst", Func::from(jsonpath_first))?;
+    Ok(())
+}
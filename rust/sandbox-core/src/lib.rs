@@ -0,0 +1,59 @@
+// The JavaScript sandbox behind js-execution-service, without the HTTP server.
+//
+// `Executor` runs code in QuickJS with INPUTS, SECRETS and the sandbox globals, and
+// reports the result along with the console output, the outbound requests and timings.
+// httpRequest calls go to an `HttpBackend`, by default `HttpClients`, which
+// enforces the outbound request policy; tests can answer them in-process instead.
+//
+//     let executor = Executor::new(&Config::load(None)?)?;
+//     let execution = executor.execute("INPUTS.x * 2", &inputs).await?;
+
+pub mod admission;
+pub mod analyze;
+mod cache;
+pub mod cancel;
+pub mod circuit;
+pub mod clock;
+mod clone;
+pub mod code_cache;
+pub mod config;
+pub mod cpu;
+mod crypto;
+pub mod dry_run;
+mod encoding;
+pub mod engine;
+pub mod error;
+pub mod executor;
+pub mod fetch;
+pub mod files;
+pub mod inputs;
+pub mod isolation;
+pub mod js_error;
+mod jsonpath;
+pub mod memory;
+pub mod metrics;
+pub mod mocks;
+pub mod modules;
+pub mod policy;
+pub mod pool;
+pub mod prelude_scripts;
+pub mod proxy;
+mod random;
+pub mod recording;
+pub mod result_cache;
+pub mod secrets;
+pub mod serialize;
+mod signing;
+pub mod state;
+pub mod surface;
+pub mod timers;
+pub mod typescript;
+mod urls;
+pub mod validate;
+mod xml;
+mod yaml;
+
+pub use config::Config;
+pub use error::{ErrorKind, ExecError};
+pub use executor::{Execution, Executor, Options, Report};
+pub use fetch::{FetchSession, HttpBackend, HttpResult};
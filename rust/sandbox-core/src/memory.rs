@@ -0,0 +1,91 @@
+// Heap accounting for QuickJS runtimes.
+//
+// `AsyncRuntime::memory_usage` needs the runtime's lock, which an evaluation holds
+// until it finishes, so the size of the heap can't be asked for while code runs.
+// Runtimes instead allocate through a counting allocator that keeps the bytes in
+// use and their high-water mark in a `HeapMeter`, which can be read at any time.
+// QuickJS still enforces the memory limit itself, whatever the allocator.
+
+use rquickjs::allocator::{Allocator, RustAllocator};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct HeapMeter {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl HeapMeter {
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    // The most bytes in use at once since the last `reset_peak`
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    // Starts a new high-water mark from the bytes in use now, e.g. when a pooled
+    // runtime is handed to the next execution
+    pub fn reset_peak(&self) {
+        self.peak.store(self.current(), Ordering::Relaxed);
+    }
+
+    fn allocated(&self, bytes: usize) {
+        let current = self.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn freed(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+// Rust's global allocator, counting into a meter
+pub struct CountingAllocator(Arc<HeapMeter>);
+
+impl CountingAllocator {
+    pub fn new() -> (Self, Arc<HeapMeter>) {
+        let meter = Arc::new(HeapMeter::default());
+        (CountingAllocator(meter.clone()), meter)
+    }
+}
+
+unsafe impl Allocator for CountingAllocator {
+    fn alloc(&mut self, size: usize) -> *mut u8 {
+        let ptr = RustAllocator.alloc(size);
+        if !ptr.is_null() {
+            self.0.allocated(unsafe { RustAllocator::usable_size(ptr) });
+        }
+        ptr
+    }
+
+    fn calloc(&mut self, count: usize, size: usize) -> *mut u8 {
+        let ptr = RustAllocator.calloc(count, size);
+        if !ptr.is_null() {
+            self.0.allocated(unsafe { RustAllocator::usable_size(ptr) });
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        self.0.freed(RustAllocator::usable_size(ptr));
+        RustAllocator.dealloc(ptr);
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, new_size: usize) -> *mut u8 {
+        let old_size = RustAllocator::usable_size(ptr);
+        let new_ptr = RustAllocator.realloc(ptr, new_size);
+        // The old allocation is untouched when growing it failed
+        if !new_ptr.is_null() {
+            self.0.freed(old_size);
+            self.0.allocated(RustAllocator::usable_size(new_ptr));
+        }
+        new_ptr
+    }
+
+    unsafe fn usable_size(ptr: *mut u8) -> usize {
+        RustAllocator::usable_size(ptr)
+    }
+}
@@ -0,0 +1,297 @@
+// Prometheus metrics, served at GET /metrics in the text exposition format.
+//
+// Counters and histograms live in one process-wide set, so any module can record
+// into it. Labels only take values from fixed sets, except the `host` label on
+// outbound requests, which is added with METRICS_HOST_LABEL=true (see `use_host_label`)
+// and is as varied as the hosts scripts talk to.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::admission::Priority;
+use crate::executor::Usage;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Upper bounds in seconds
+const EXECUTION_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const FETCH_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const QUEUE_BUCKETS: &[f64] = &[0.0, 0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const CPU_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+// In bytes and interrupt handler calls
+const HEAP_BUCKETS: &[f64] = &[1e6, 2e6, 4e6, 8e6, 16e6, 32e6, 64e6, 128e6, 256e6, 512e6];
+const BYTES_BUCKETS: &[f64] = &[0.0, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8];
+const INTERRUPT_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 100.0, 1e3, 1e4, 1e5];
+
+#[derive(Clone, Copy)]
+pub enum Outcome {
+    Success,
+    // The code didn't parse, threw or produced a result that can't be returned
+    JsError,
+    // Timed out or cancelled
+    Timeout,
+    // Refused before or after running, e.g. invalid inputs or limits exceeded
+    Rejected,
+    Internal,
+}
+
+impl Outcome {
+    const ALL: [Outcome; 5] = [
+        Outcome::Success,
+        Outcome::JsError,
+        Outcome::Timeout,
+        Outcome::Rejected,
+        Outcome::Internal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::JsError => "js_error",
+            Outcome::Timeout => "timeout",
+            Outcome::Rejected => "rejected",
+            Outcome::Internal => "internal",
+        }
+    }
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    // Observations per bucket, not cumulative; the last one is +Inf
+    counts: Vec<AtomicU64>,
+    // In microseconds for durations, otherwise in the observed unit
+    sum: AtomicU64,
+    per_unit: f64,
+}
+
+impl Histogram {
+    // Of durations, in seconds
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram::with_unit(bounds, 1e6)
+    }
+
+    // Of whole numbers, e.g. bytes
+    fn counting(bounds: &'static [f64]) -> Self {
+        Histogram::with_unit(bounds, 1.0)
+    }
+
+    fn with_unit(bounds: &'static [f64], per_unit: f64) -> Self {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            per_unit,
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        self.observe_value(duration.as_secs_f64());
+    }
+
+    fn observe_value(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add((value * self.per_unit).round() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        self.render_series(out, name, "");
+    }
+
+    // The samples alone, with `labels` such as `priority="batch"`
+    fn render_series(&self, out: &mut String, name: &str, labels: &str) {
+        let (bucket_labels, labels) = match labels {
+            "" => (String::new(), String::new()),
+            labels => (format!("{},", labels), format!("{{{}}}", labels)),
+        };
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = self.bounds.get(i).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, bucket_labels, le, cumulative);
+        }
+        let sum = self.sum.load(Ordering::Relaxed) as f64 / self.per_unit;
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, cumulative);
+    }
+}
+
+pub struct Metrics {
+    executions: [AtomicU64; Outcome::ALL.len()],
+    execution_duration: Histogram,
+    fetch_duration: Histogram,
+    // By Priority
+    queue_wait: [Histogram; Priority::ALL.len()],
+    // What executions consumed, as reported in meta.usage
+    execution_heap_peak: Histogram,
+    execution_cpu: Histogram,
+    execution_interrupts: Histogram,
+    execution_fetched_bytes: Histogram,
+    execution_result_bytes: Histogram,
+    // (host, status class) -> count; the host only with METRICS_HOST_LABEL
+    outbound_requests: Mutex<BTreeMap<(Option<String>, &'static str), u64>>,
+    result_bytes: AtomicU64,
+    // Microseconds the startup warm-up took, 0 until it finished
+    warm_up_micros: AtomicU64,
+    // Worker processes that died during an execution, with ISOLATION_MODE=subprocess
+    worker_crashes: AtomicU64,
+    host_label: AtomicBool,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            executions: Default::default(),
+            execution_duration: Histogram::new(EXECUTION_BUCKETS),
+            fetch_duration: Histogram::new(FETCH_BUCKETS),
+            queue_wait: Priority::ALL.map(|_| Histogram::new(QUEUE_BUCKETS)),
+            execution_heap_peak: Histogram::counting(HEAP_BUCKETS),
+            execution_cpu: Histogram::new(CPU_BUCKETS),
+            execution_interrupts: Histogram::counting(INTERRUPT_BUCKETS),
+            execution_fetched_bytes: Histogram::counting(BYTES_BUCKETS),
+            execution_result_bytes: Histogram::counting(BYTES_BUCKETS),
+            outbound_requests: Mutex::new(BTreeMap::new()),
+            result_bytes: AtomicU64::new(0),
+            warm_up_micros: AtomicU64::new(0),
+            worker_crashes: AtomicU64::new(0),
+            host_label: AtomicBool::new(false),
+        }
+    }
+
+    // Set at startup, before any request is recorded
+    pub fn use_host_label(&self, enabled: bool) {
+        self.host_label.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn execution(&self, outcome: Outcome, duration: Duration) {
+        self.executions[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        self.execution_duration.observe(duration);
+    }
+
+    pub fn queue_wait(&self, priority: Priority, waited: Duration) {
+        self.queue_wait[priority as usize].observe(waited);
+    }
+
+    pub fn execution_usage(&self, usage: &Usage) {
+        self.execution_heap_peak.observe_value(usage.heap_peak_bytes as f64);
+        self.execution_cpu.observe(usage.cpu_time);
+        self.execution_interrupts.observe_value(usage.interrupts as f64);
+        self.execution_fetched_bytes.observe_value(usage.fetched_bytes as f64);
+        self.execution_result_bytes.observe_value(usage.result_bytes as f64);
+    }
+
+    // `status` is None for requests that got no response
+    pub fn outbound_request(&self, host: &str, status: Option<u16>, duration: Duration) {
+        let class = match status.map(|status| status / 100) {
+            Some(1) => "1xx",
+            Some(2) => "2xx",
+            Some(3) => "3xx",
+            Some(4) => "4xx",
+            Some(5) => "5xx",
+            _ => "error",
+        };
+        let host = self.host_label.load(Ordering::Relaxed).then(|| host.to_string());
+        *self.outbound_requests.lock().unwrap().entry((host, class)).or_default() += 1;
+        self.fetch_duration.observe(duration);
+    }
+
+    pub fn result_bytes(&self, bytes: usize) {
+        self.result_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn warm_up(&self, duration: Duration) {
+        self.warm_up_micros.store(duration.as_micros().max(1) as u64, Ordering::Relaxed);
+    }
+
+    pub fn worker_crash(&self) {
+        self.worker_crashes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, in_flight: usize, queued: usize) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP jsexec_executions_total Executions by outcome.");
+        let _ = writeln!(out, "# TYPE jsexec_executions_total counter");
+        for outcome in Outcome::ALL {
+            let count = self.executions[outcome as usize].load(Ordering::Relaxed);
+            let _ = writeln!(out, "jsexec_executions_total{{outcome=\"{}\"}} {}", outcome.as_str(), count);
+        }
+        self.execution_duration.render(
+            &mut out,
+            "jsexec_execution_duration_seconds",
+            "Time from receiving an execution until it finished.",
+        );
+
+        self.execution_heap_peak.render(
+            &mut out,
+            "jsexec_execution_heap_peak_bytes",
+            "Most heap an execution's runtime used at once.",
+        );
+        self.execution_cpu.render(&mut out, "jsexec_execution_cpu_seconds", "CPU time spent running an execution's code.");
+        self.execution_interrupts.render(
+            &mut out,
+            "jsexec_execution_interrupts",
+            "Interrupt handler calls while an execution's code ran.",
+        );
+        self.execution_fetched_bytes.render(
+            &mut out,
+            "jsexec_execution_fetched_bytes",
+            "Response body bytes an execution received from upstreams.",
+        );
+        self.execution_result_bytes.render(
+            &mut out,
+            "jsexec_execution_result_bytes",
+            "Bytes of an execution's serialized result.",
+        );
+
+        let _ = writeln!(out, "# HELP jsexec_executions_in_flight Executions currently running.");
+        let _ = writeln!(out, "# TYPE jsexec_executions_in_flight gauge");
+        let _ = writeln!(out, "jsexec_executions_in_flight {}", in_flight);
+        let _ = writeln!(out, "# HELP jsexec_executions_queued Executions waiting for a slot.");
+        let _ = writeln!(out, "# TYPE jsexec_executions_queued gauge");
+        let _ = writeln!(out, "jsexec_executions_queued {}", queued);
+        let _ = writeln!(out, "# HELP jsexec_queue_wait_seconds Time executions waited for a slot, by priority.");
+        let _ = writeln!(out, "# TYPE jsexec_queue_wait_seconds histogram");
+        for priority in Priority::ALL {
+            let labels = format!("priority=\"{}\"", priority.as_str());
+            self.queue_wait[priority as usize].render_series(&mut out, "jsexec_queue_wait_seconds", &labels);
+        }
+        let warm_up = self.warm_up_micros.load(Ordering::Relaxed);
+        if warm_up > 0 {
+            let _ = writeln!(out, "# HELP jsexec_warm_up_seconds Time the startup warm-up took.");
+            let _ = writeln!(out, "# TYPE jsexec_warm_up_seconds gauge");
+            let _ = writeln!(out, "jsexec_warm_up_seconds {}", warm_up as f64 / 1e6);
+        }
+
+        let _ = writeln!(out, "# HELP jsexec_outbound_requests_total Outbound requests by response status class.");
+        let _ = writeln!(out, "# TYPE jsexec_outbound_requests_total counter");
+        for ((host, class), count) in self.outbound_requests.lock().unwrap().iter() {
+            let host = host
+                .as_ref()
+                .map(|host| format!("host=\"{}\",", escape(host)))
+                .unwrap_or_default();
+            let _ = writeln!(out, "jsexec_outbound_requests_total{{{}status_class=\"{}\"}} {}", host, class, count);
+        }
+        self.fetch_duration.render(
+            &mut out,
+            "jsexec_fetch_duration_seconds",
+            "Duration of outbound requests, including retries.",
+        );
+
+        let _ = writeln!(out, "# HELP jsexec_result_bytes_total Bytes of serialized results returned.");
+        let _ = writeln!(out, "# TYPE jsexec_result_bytes_total counter");
+        let _ = writeln!(out, "jsexec_result_bytes_total {}", self.result_bytes.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP jsexec_worker_crashes_total Worker processes that died during an execution.");
+        let _ = writeln!(out, "# TYPE jsexec_worker_crashes_total counter");
+        let _ = writeln!(out, "jsexec_worker_crashes_total {}", self.worker_crashes.load(Ordering::Relaxed));
+        out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,188 @@
+// Canned responses for httpRequest calls, so that code can be tested without a
+// network.
+//
+// With `http_mocks` on a request, no request of the execution goes to the network.
+// Each one is answered by the first mock that matches its method and URL, either
+// exactly (`url`) or with `*` wildcards (`urlPattern`), and has uses left. A request
+// no mock matches throws, and fails the execution as an unmatched request even if
+// the code caught the error.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use crate::fetch::{request_url, ErrorCode, FetchSession, FromCache, HttpBackend, HttpResult, Timing};
+use crate::policy::wildcard_match;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct HttpMock {
+    #[serde(rename = "match")]
+    pub matcher: MockMatch,
+    #[serde(default)]
+    pub response: MockResponse,
+    // How often the mock answers, any number of times when absent
+    pub times: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MockMatch {
+    pub url: Option<String>,
+    #[serde(rename = "urlPattern")]
+    pub url_pattern: Option<String>,
+    // Any method when absent
+    pub method: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub data: Value,
+}
+
+impl Default for MockResponse {
+    fn default() -> Self {
+        MockResponse {
+            status: 200,
+            headers: BTreeMap::new(),
+            data: Value::Null,
+        }
+    }
+}
+
+impl HttpMock {
+    // Why the mock can't be used, if it can't
+    pub fn check(&self) -> Result<(), String> {
+        match (&self.matcher.url, &self.matcher.url_pattern) {
+            (Some(_), Some(_)) => Err("match can have url or urlPattern, not both".to_string()),
+            (None, None) => Err("match needs url or urlPattern".to_string()),
+            _ if !(100..=599).contains(&self.response.status) => {
+                Err(format!("response.status must be between 100 and 599, got {}", self.response.status))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn matches(&self, method: &str, url: &str) -> bool {
+        let method_matches = self.matcher.method.as_ref().is_none_or(|expected| expected.eq_ignore_ascii_case(method));
+        let url_matches = match (&self.matcher.url, &self.matcher.url_pattern) {
+            (Some(expected), _) => expected == url,
+            (None, Some(pattern)) => wildcard_match(pattern, url),
+            (None, None) => false,
+        };
+        method_matches && url_matches
+    }
+
+    fn describe(&self) -> String {
+        let method = self.matcher.method.as_deref().map_or("*".to_string(), str::to_ascii_uppercase);
+        let url = self.matcher.url.as_deref().or(self.matcher.url_pattern.as_deref()).unwrap_or("");
+        format!("{} {}", method, url)
+    }
+}
+
+pub struct HttpMocks {
+    mocks: Vec<HttpMock>,
+    // Uses of each mock so far
+    uses: Mutex<Vec<u32>>,
+    // Why the first request no mock answered failed
+    unmatched: Mutex<Option<String>>,
+}
+
+impl HttpMocks {
+    pub fn new(mocks: Vec<HttpMock>) -> Self {
+        HttpMocks {
+            uses: Mutex::new(vec![0; mocks.len()]),
+            mocks,
+            unmatched: Mutex::new(None),
+        }
+    }
+
+    fn answer(&self, url: String, options: Option<HashMap<String, Value>>) -> HttpResult {
+        let method = options
+            .as_ref()
+            .and_then(|options| options.get("method"))
+            .and_then(Value::as_str)
+            .unwrap_or("GET")
+            .to_ascii_uppercase();
+        let url = request_url(url, options.as_ref());
+
+        let mut uses = self.uses.lock().unwrap();
+        let found = self.mocks.iter().enumerate().find(|(index, mock)| {
+            mock.times.is_none_or(|times| uses[*index] < times) && mock.matches(&method, &url)
+        });
+        let Some((index, mock)) = found else {
+            let message = self.unmatched_message(&format!("{} {}", method, url), &uses);
+            self.unmatched.lock().unwrap().get_or_insert_with(|| message.clone());
+            return HttpResult::failure(ErrorCode::UnmatchedRequest, "Unmatched Request", message);
+        };
+        uses[index] += 1;
+
+        let response = &mock.response;
+        let headers: BTreeMap<String, String> =
+            response.headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.clone())).collect();
+        let content_type = headers.get("content-type").map(|value| value.to_ascii_lowercase());
+        HttpResult {
+            ok: (200..300).contains(&response.status),
+            status: response.status,
+            status_text: reqwest::StatusCode::from_u16(response.status)
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or("")
+                .to_string(),
+            raw_headers: response.headers.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+            headers,
+            data: response.data.clone(),
+            text: match &response.data {
+                Value::String(text) => text.clone(),
+                data => data.to_string(),
+            },
+            json_parse_error: None,
+            content_type,
+            content_length: None,
+            final_url: Some(url),
+            timing: Timing::default(),
+            redirects: Vec::new(),
+            attempts: 1,
+            set_cookies: Vec::new(),
+            from_cache: FromCache::No,
+            error_code: None,
+            stripped_headers: Vec::new(),
+            retry_after_ms: None,
+        }
+    }
+
+    fn unmatched_message(&self, request: &str, uses: &[u32]) -> String {
+        let available: Vec<String> = self
+            .mocks
+            .iter()
+            .zip(uses)
+            .map(|(mock, used)| match mock.times {
+                Some(times) if *used >= times => format!("{} (used up)", mock.describe()),
+                _ => mock.describe(),
+            })
+            .collect();
+        let available = if available.is_empty() { "none".to_string() } else { available.join(", ") };
+        format!("No http_mocks entry matches {}; available: {}", request, available)
+    }
+
+    // Why the first request no mock answered failed, if one didn't get an answer
+    pub fn unmatched(&self) -> Option<String> {
+        self.unmatched.lock().unwrap().clone()
+    }
+}
+
+impl HttpBackend for HttpMocks {
+    fn fetch<'a>(
+        &'a self,
+        _session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        Box::pin(std::future::ready(self.answer(url, options)))
+    }
+}
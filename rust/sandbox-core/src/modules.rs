@@ -0,0 +1,142 @@
+// Modules importable by user code evaluated with `module: true`: the built-in ones,
+// and those of the request's module map.
+//
+// Modules of the map import one another by name (`helpers/math`) or by a path
+// relative to the importing module (`./math`, `../helpers/math.js`), with or
+// without a `.js` or `.ts` extension. QuickJS compiles each module once per
+// execution and links circular imports as ES modules do. Everything else fails,
+// and the failed specifiers are recorded so the error response can name them.
+
+use rquickjs::loader::{Loader, Resolver};
+use rquickjs::module::Declared;
+use rquickjs::{Ctx, Error, Module, Result};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Extensions a specifier may have, or leave out, when naming a module of the map
+const EXTENSIONS: &[&str] = &[".js", ".mjs", ".ts"];
+
+// Re-export the globals installed in every context
+const BUILTIN_MODULES: &[(&str, &str)] = &[
+    (
+        "sandbox:http",
+        "export const httpRequest = globalThis.httpRequest;
+         export const httpGetStream = globalThis.httpGetStream;
+         export const headersGet = globalThis.headersGet;
+         export const graphql = globalThis.graphql;
+         export const fetch = globalThis.fetch;
+         export const Headers = globalThis.Headers;
+         export const GraphQLError = globalThis.GraphQLError;
+         export const Http = globalThis.Http;",
+    ),
+    (
+        "sandbox:utils",
+        "export default globalThis.utils;
+         export const {
+             VERSION, get, set, groupBy, uniqBy, chunk, range, sum, mean, sortBy,
+             deepEqual, deepMerge, pad, formatDate, formatNumber, truncate,
+         } = globalThis.utils;",
+    ),
+];
+
+// Sources of a request's modules by name, and the one evaluated first
+#[derive(Clone, Debug)]
+pub struct ModuleMap {
+    pub entry: String,
+    pub sources: BTreeMap<String, String>,
+}
+
+impl ModuleMap {
+    // The name of the module `specifier` imported by `base` refers to
+    fn resolve(&self, base: &str, specifier: &str) -> Option<String> {
+        let path = if specifier.starts_with("./") || specifier.starts_with("../") {
+            let mut segments: Vec<&str> = base.split('/').collect();
+            // The importing module itself
+            segments.pop();
+            for segment in specifier.split('/') {
+                match segment {
+                    "" | "." => {}
+                    ".." => {
+                        segments.pop()?;
+                    }
+                    segment => segments.push(segment),
+                }
+            }
+            segments.join("/")
+        } else {
+            specifier.to_string()
+        };
+        let stripped = EXTENSIONS.iter().find_map(|extension| path.strip_suffix(extension)).map(str::to_string);
+        let extended = EXTENSIONS.iter().map(|extension| format!("{}{}", path, extension));
+        std::iter::once(path.clone())
+            .chain(stripped)
+            .chain(extended)
+            .find(|name| self.sources.contains_key(name))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SandboxModules {
+    unresolved: Arc<Mutex<Vec<String>>>,
+    map: Option<Arc<ModuleMap>>,
+}
+
+impl SandboxModules {
+    pub fn new(map: Option<Arc<ModuleMap>>) -> Self {
+        SandboxModules {
+            map,
+            ..SandboxModules::default()
+        }
+    }
+
+    pub fn specifiers() -> Vec<&'static str> {
+        BUILTIN_MODULES.iter().map(|(name, _)| *name).collect()
+    }
+
+    // Specifiers that failed to resolve so far
+    pub fn unresolved(&self) -> Vec<String> {
+        self.unresolved.lock().unwrap().clone()
+    }
+
+    // Error message naming the unresolved specifiers, if there are any
+    pub fn unresolved_message(&self) -> Option<String> {
+        let unresolved = self.unresolved();
+        if unresolved.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "Cannot resolve module {} (available: {})",
+            unresolved.iter().map(|s| format!("'{}'", s)).collect::<Vec<_>>().join(", "),
+            SandboxModules::specifiers()
+                .into_iter()
+                .chain(self.map.iter().flat_map(|map| map.sources.keys().map(String::as_str)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+impl Resolver for SandboxModules {
+    fn resolve<'js>(&mut self, _ctx: &Ctx<'js>, base: &str, name: &str) -> Result<String> {
+        if BUILTIN_MODULES.iter().any(|(builtin, _)| *builtin == name) {
+            return Ok(name.to_string());
+        }
+        if let Some(resolved) = self.map.as_ref().and_then(|map| map.resolve(base, name)) {
+            return Ok(resolved);
+        }
+        self.unresolved.lock().unwrap().push(name.to_string());
+        Err(Error::new_resolving(base, name))
+    }
+}
+
+impl Loader for SandboxModules {
+    fn load<'js>(&mut self, ctx: &Ctx<'js>, name: &str) -> Result<Module<'js, Declared>> {
+        match BUILTIN_MODULES.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, source)) => Module::declare(ctx.clone(), name, *source),
+            None => match self.map.as_ref().and_then(|map| map.sources.get(name)) {
+                Some(source) => Module::declare(ctx.clone(), name, source.as_str()),
+                None => Err(Error::new_loading(name)),
+            },
+        }
+    }
+}
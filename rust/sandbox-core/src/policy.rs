@@ -0,0 +1,443 @@
+// Outbound request policy applied to every httpRequest call made by user code.
+//
+// User code is untrusted, so by default it must not be able to reach loopback,
+// link-local or private network addresses (SSRF). Hostnames are resolved by the
+// policy resolver itself and every resolved address is checked, which means the
+// addresses that were checked are exactly the ones reqwest connects to.
+//
+// Operators can additionally restrict which URLs may be called at all with
+// FETCH_ALLOWLIST / FETCH_DENYLIST (comma-separated patterns, `*` wildcards):
+//   - `https:`                      scheme
+//   - `*.example.com`               host
+//   - `https://api.example.com/v1/` URL prefix
+//
+// Cloud metadata endpoints, which hand out credentials, are blocked even where
+// private networks are allowed, unless BLOCK_CLOUD_METADATA is turned off: the
+// link-local range with 169.254.169.254, the other well-known metadata addresses and
+// the hostnames aliasing them. Addresses are normalized first, so IPv4 addresses
+// embedded in IPv6 ones are caught too; the URL parser already turns decimal, octal,
+// hex and mixed IPv4 literals into plain addresses.
+//
+// DNS_OVERRIDES (`host=ip` entries) answer the resolution of those hosts in place of
+// DNS. The overriding addresses are checked like any others.
+//
+// The SENSITIVE_HEADERS a script sets, such as Authorization, are only sent to URLs
+// matching HEADER_FORWARD_ALLOWLIST (same patterns) when it isn't empty, and are
+// stripped from requests and redirects to any other host.
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use crate::config::Config;
+
+const METADATA_HOSTS: [&str; 6] = [
+    "metadata",
+    "metadata.google.internal",
+    "metadata.goog",
+    "metadata.azure.com",
+    "instance-data",
+    "instance-data.ec2.internal",
+];
+
+#[derive(Debug)]
+pub struct BlockedError {
+    // Surfaced to user code as the HttpResult statusText
+    pub status_text: &'static str,
+    pub message: String,
+}
+
+impl BlockedError {
+    fn blocked(message: String) -> Self {
+        BlockedError {
+            status_text: "Blocked",
+            message,
+        }
+    }
+
+    fn forbidden(url: &Url) -> Self {
+        BlockedError {
+            status_text: "Forbidden by policy",
+            message: format!("URL is not permitted by the fetch policy: {}", url),
+        }
+    }
+}
+
+impl std::fmt::Display for BlockedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BlockedError {}
+
+#[derive(Clone, Debug)]
+enum UrlPattern {
+    Scheme(String),
+    Host(String),
+    UrlPrefix(String),
+}
+
+impl UrlPattern {
+    fn parse(pattern: &str) -> Self {
+        if pattern.contains("://") {
+            UrlPattern::UrlPrefix(pattern.to_string())
+        } else if let Some(scheme) = pattern.strip_suffix(':') {
+            UrlPattern::Scheme(scheme.to_ascii_lowercase())
+        } else {
+            UrlPattern::Host(pattern.to_ascii_lowercase())
+        }
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        match self {
+            UrlPattern::Scheme(scheme) => url.scheme() == scheme,
+            UrlPattern::Host(pattern) => url
+                .host_str()
+                .map(|host| wildcard_match(pattern, &host.to_ascii_lowercase()))
+                .unwrap_or(false),
+            UrlPattern::UrlPrefix(prefix) => wildcard_match(&format!("{}*", prefix), url.as_str()),
+        }
+    }
+}
+
+fn parse_patterns(patterns: &[String]) -> Vec<UrlPattern> {
+    patterns
+        .iter()
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(UrlPattern::parse)
+        .collect()
+}
+
+// Patterns like FETCH_ALLOWLIST's, for allowlists that apply on top of it, such as
+// a tenant's
+#[derive(Clone, Debug)]
+pub struct UrlPatterns(Vec<UrlPattern>);
+
+impl UrlPatterns {
+    pub fn parse(patterns: &[String]) -> Self {
+        UrlPatterns(parse_patterns(patterns))
+    }
+
+    pub fn check(&self, url: &Url) -> Result<(), BlockedError> {
+        match self.0.iter().any(|p| p.matches(url)) {
+            true => Ok(()),
+            false => Err(BlockedError {
+                status_text: "Forbidden by policy",
+                message: format!("URL is not permitted by the tenant's fetch allowlist: {}", url),
+            }),
+        }
+    }
+}
+
+// Glob match where `*` matches any (possibly empty) sequence of characters
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[derive(Clone, Debug)]
+pub struct OutboundPolicy {
+    pub allow_private_networks: bool,
+    allowlist: Vec<UrlPattern>,
+    denylist: Vec<UrlPattern>,
+    // Operator-configured hosts such as the egress proxy, exempt from address checks
+    trusted_hosts: Vec<String>,
+    // Off with NETWORK_DISABLED and for `exec --no-network`, which block every request
+    network: bool,
+    // DNS_OVERRIDES, by lowercase host
+    dns_overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    // BLOCK_CLOUD_METADATA
+    block_metadata: bool,
+    // SENSITIVE_HEADERS, lowercase
+    sensitive_headers: Vec<String>,
+    header_forward_allowlist: Vec<UrlPattern>,
+}
+
+impl OutboundPolicy {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Ok(OutboundPolicy {
+            allow_private_networks: config.allow_private_networks,
+            allowlist: parse_patterns(&config.fetch_allowlist),
+            denylist: parse_patterns(&config.fetch_denylist),
+            trusted_hosts: Vec::new(),
+            network: !config.network_disabled,
+            dns_overrides: Arc::new(parse_dns_overrides(&config.dns_overrides)?),
+            block_metadata: config.block_cloud_metadata,
+            sensitive_headers: config.sensitive_headers.iter().map(|name| name.trim().to_ascii_lowercase()).collect(),
+            header_forward_allowlist: parse_patterns(&config.header_forward_allowlist),
+        })
+    }
+
+    pub fn is_sensitive(&self, header: &str) -> bool {
+        self.sensitive_headers.iter().any(|name| name.eq_ignore_ascii_case(header))
+    }
+
+    // Whether the header may be sent to the URL
+    pub fn forwards_header(&self, header: &str, url: &Url) -> bool {
+        self.header_forward_allowlist.is_empty()
+            || !self.is_sensitive(header)
+            || self.header_forward_allowlist.iter().any(|p| p.matches(url))
+    }
+
+    pub fn without_network(mut self) -> Self {
+        self.network = false;
+        self
+    }
+
+    pub fn with_trusted_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.trusted_hosts = hosts.into_iter().map(|h| h.to_ascii_lowercase()).collect();
+        self
+    }
+
+    fn is_trusted(&self, host: &str) -> bool {
+        self.trusted_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+    }
+
+    // When a request goes through a proxy, the proxy resolves the destination and our
+    // resolver never sees it, so check what the host resolves to locally instead.
+    // Unresolvable hosts are left for the proxy to reject.
+    pub async fn check_host_addresses(&self, url: &Url) -> Result<(), BlockedError> {
+        let host = match url.host() {
+            Some(url::Host::Domain(host)) if !self.allow_private_networks || self.block_metadata => host,
+            _ => return Ok(()),
+        };
+
+        if let Ok(addrs) = self.lookup(host).await {
+            for addr in addrs {
+                if self.check_ip(addr.ip()).is_err() {
+                    return Err(BlockedError::blocked(format!(
+                        "Request blocked: {} resolves to private or loopback address {}",
+                        host,
+                        addr.ip()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), BlockedError> {
+        let ip = normalize(ip);
+        if self.block_metadata && is_metadata_address(ip) {
+            tracing::warn!("Blocked a request to the cloud metadata address {}", ip);
+            return Err(BlockedError::blocked(format!("Request blocked: {} is a cloud metadata address", ip)));
+        }
+        if !self.allow_private_networks && is_private_address(ip) {
+            return Err(BlockedError::blocked(format!(
+                "Request blocked: {} is a private or loopback address",
+                ip
+            )));
+        }
+        Ok(())
+    }
+
+    // Checks everything that can be decided without DNS: the scheme and IP literal hosts.
+    // Hostnames are checked by `PolicyResolver` once they are resolved.
+    pub fn check_url(&self, url: &Url) -> Result<(), BlockedError> {
+        match url.scheme() {
+            "http" | "https" => {}
+            scheme => {
+                return Err(BlockedError::blocked(format!(
+                    "Request blocked: unsupported URL scheme '{}'",
+                    scheme
+                )))
+            }
+        }
+
+        if !self.network {
+            return Err(BlockedError::blocked("Request blocked: networking is disabled".to_string()));
+        }
+
+        // Denylist wins over allowlist
+        if self.denylist.iter().any(|p| p.matches(url)) {
+            return Err(BlockedError::forbidden(url));
+        }
+        if !self.allowlist.is_empty() && !self.allowlist.iter().any(|p| p.matches(url)) {
+            return Err(BlockedError::forbidden(url));
+        }
+
+        match url.host() {
+            Some(url::Host::Ipv4(ip)) => self.check_ip(IpAddr::V4(ip)),
+            Some(url::Host::Ipv6(ip)) => self.check_ip(IpAddr::V6(ip)),
+            Some(url::Host::Domain(host)) => {
+                let host = host.trim_end_matches('.').to_ascii_lowercase();
+                if self.block_metadata && METADATA_HOSTS.contains(&host.as_str()) {
+                    tracing::warn!("Blocked a request to the cloud metadata host {}", host);
+                    return Err(BlockedError::blocked(format!("Request blocked: {} is a cloud metadata host", host)));
+                }
+                Ok(())
+            }
+            None => Err(BlockedError::blocked("Request blocked: URL has no host".to_string())),
+        }
+    }
+
+    // The addresses of a host, from DNS_OVERRIDES if it has any there
+    async fn lookup(&self, host: &str) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some(ips) = self.dns_overrides.get(&host.to_ascii_lowercase()) {
+            tracing::debug!("Resolved {} to {:?} from DNS_OVERRIDES", host, ips);
+            return Ok(ips.iter().map(|ip| SocketAddr::new(*ip, 0)).collect());
+        }
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+        tracing::debug!("Resolved {} to {:?}", host, addrs.iter().map(SocketAddr::ip).collect::<Vec<_>>());
+        Ok(addrs)
+    }
+
+    // DNS resolver enforcing this policy and the overrides, or None when there is
+    // nothing to enforce
+    pub fn resolver(&self) -> Option<Arc<PolicyResolver>> {
+        if self.allow_private_networks && !self.block_metadata && self.dns_overrides.is_empty() {
+            None
+        } else {
+            Some(Arc::new(PolicyResolver { policy: self.clone() }))
+        }
+    }
+}
+
+// `host=ip` entries; a port after the address is accepted and ignored, as the port
+// always comes from the URL
+fn parse_dns_overrides(entries: &[String]) -> Result<HashMap<String, Vec<IpAddr>>, String> {
+    let mut overrides: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for entry in entries.iter().map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+        let invalid = || format!("Invalid DNS_OVERRIDES entry '{}': expected host=ip", entry);
+        let (host, address) = entry.split_once('=').ok_or_else(invalid)?;
+        let (host, address) = (host.trim(), address.trim());
+        let ip = address
+            .parse::<IpAddr>()
+            .or_else(|_| address.parse::<SocketAddr>().map(|addr| addr.ip()))
+            .map_err(|_| invalid())?;
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        overrides.entry(host.to_ascii_lowercase()).or_default().push(ip);
+    }
+    Ok(overrides)
+}
+
+pub struct PolicyResolver {
+    policy: OutboundPolicy,
+}
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = policy.lookup(&host).await?;
+
+            if policy.is_trusted(&host) {
+                let addrs: Addrs = Box::new(addrs.into_iter());
+                return Ok(addrs);
+            }
+
+            // Reject the host if any address is private, so a hostname mixing public
+            // and private records can't be used to reach an internal service
+            for addr in &addrs {
+                if policy.check_ip(addr.ip()).is_err() {
+                    return Err(BlockedError::blocked(format!(
+                        "Request blocked: {} resolves to private or loopback address {}",
+                        host,
+                        addr.ip()
+                    ))
+                    .into());
+                }
+            }
+
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+// Finds a policy rejection anywhere in a reqwest error's source chain
+pub fn blocked_error(err: &reqwest::Error) -> Option<&BlockedError> {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(blocked) = e.downcast_ref::<BlockedError>() {
+            return Some(blocked);
+        }
+        source = e.source();
+    }
+    None
+}
+
+// The IPv4 address an IPv6 one stands for: IPv4-mapped (::ffff:a.b.c.d),
+// IPv4-compatible (::a.b.c.d) and NAT64 (64:ff9b::a.b.c.d) addresses
+fn normalize(ip: IpAddr) -> IpAddr {
+    let IpAddr::V6(v6) = ip else {
+        return ip;
+    };
+    let segments = v6.segments();
+    let embedded = Ipv4Addr::new(
+        (segments[6] >> 8) as u8,
+        segments[6] as u8,
+        (segments[7] >> 8) as u8,
+        segments[7] as u8,
+    );
+    match segments[..6] {
+        [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(embedded),
+        // Not ::1 and ::, which are IPv6 addresses of their own
+        [0, 0, 0, 0, 0, 0] if u32::from(embedded) > 1 => IpAddr::V4(embedded),
+        [0x64, 0xff9b, 0, 0, 0, 0] => IpAddr::V4(embedded),
+        _ => ip,
+    }
+}
+
+fn is_metadata_address(ip: IpAddr) -> bool {
+    match ip {
+        // Link-local 169.254.0.0/16 with AWS, GCP, Azure and ECS, and Alibaba Cloud
+        IpAddr::V4(ip) => ip.is_link_local() || ip == Ipv4Addr::new(100, 100, 100, 200),
+        // AWS over IPv6
+        IpAddr::V6(ip) => ip == Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254),
+    }
+}
+
+pub fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_private_ipv4(mapped);
+            }
+            is_private_ipv6(ip)
+        }
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+}
+
+fn is_private_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // Unique local fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local fe80::/10
+        || (first & 0xffc0) == 0xfe80
+}
@@ -0,0 +1,171 @@
+// Pool of QuickJS runtimes reused across executions.
+//
+// With JS_RUNTIME_POOL_SIZE set, up to that many runtimes are kept around instead of
+// creating one per execution. Every execution still gets a fresh context, which
+// shares no globals with earlier executions on the same runtime. Creating a runtime
+// takes tens of microseconds while setting up the context takes about a
+// millisecond, so pooling is off by default.
+// A runtime goes back to the pool only when its execution finished cleanly with no
+// work left queued, and is dropped after JS_RUNTIME_MAX_USES executions or when
+// its heap grew beyond JS_RUNTIME_MAX_HEAP_BYTES. When every pooled runtime is busy
+// for longer than JS_RUNTIME_POOL_WAIT_MS, the execution gets a runtime of its own.
+// The pool is filled at startup; the server reports ready once it is.
+// Runtimes are limited to JS_MAX_MEMORY_BYTES, and a pooled runtime gets that
+// limit back when it is released after an execution that lowered it. Each runtime
+// counts its heap in a meter of its own (see `memory`).
+
+use rquickjs::AsyncRuntime;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::Config;
+use crate::memory::{CountingAllocator, HeapMeter};
+
+const MAX_LEFTOVER_JOBS: usize = 8;
+
+struct PooledRuntime {
+    runtime: AsyncRuntime,
+    heap: Arc<HeapMeter>,
+    uses: u32,
+}
+
+pub struct RuntimePool {
+    idle: Mutex<Vec<PooledRuntime>>,
+    // One permit per pooled runtime
+    slots: Arc<Semaphore>,
+    size: usize,
+    max_uses: u32,
+    max_heap_bytes: usize,
+    wait: Duration,
+    max_stack_bytes: usize,
+    // Heap limit of every runtime; an execution can lower it while it has the runtime
+    max_memory_bytes: usize,
+}
+
+impl RuntimePool {
+    pub fn from_config(config: &Config) -> Self {
+        let size = config.js_runtime_pool_size;
+        RuntimePool {
+            idle: Mutex::new(Vec::with_capacity(size)),
+            slots: Arc::new(Semaphore::new(size)),
+            size,
+            max_uses: config.js_runtime_max_uses,
+            max_heap_bytes: config.js_runtime_max_heap_bytes,
+            wait: Duration::from_millis(config.js_runtime_pool_wait_ms),
+            max_stack_bytes: config.js_max_stack_bytes,
+            max_memory_bytes: config.js_max_memory_bytes,
+        }
+    }
+
+    // A runtime outside of the pool, also used by sessions that keep theirs
+    pub async fn create(&self) -> Result<(AsyncRuntime, Arc<HeapMeter>), String> {
+        let (allocator, heap) = CountingAllocator::new();
+        let runtime = AsyncRuntime::new_with_alloc(allocator).map_err(|e| format!("Runtime error: {}", e))?;
+        // Deep recursion throws a RangeError instead of overflowing the thread's stack
+        runtime.set_max_stack_size(self.max_stack_bytes).await;
+        runtime.set_memory_limit(self.max_memory_bytes).await;
+        Ok((runtime, heap))
+    }
+
+    // Fills the pool up front, so the first executions don't pay for its runtimes.
+    // Runtimes checked out in the meantime count towards the pool's size.
+    pub async fn warm_up(&self) -> Result<(), String> {
+        while self.idle.lock().unwrap().len() + (self.size - self.slots.available_permits()) < self.size {
+            let (runtime, heap) = self.create().await?;
+            self.idle.lock().unwrap().push(PooledRuntime { runtime, heap, uses: 0 });
+        }
+        Ok(())
+    }
+
+    // A pooled runtime, or a new one when the pool stays exhausted
+    pub async fn acquire(self: &Arc<Self>) -> Result<RuntimeLease, String> {
+        let permit = match self.size {
+            0 => None,
+            _ => tokio::time::timeout(self.wait, self.slots.clone().acquire_owned())
+                .await
+                .ok()
+                .and_then(Result::ok),
+        };
+        let Some(permit) = permit else {
+            tracing::debug!("Runtime pool exhausted, creating a runtime on demand");
+            let (runtime, heap) = self.create().await?;
+            return Ok(RuntimeLease {
+                runtime,
+                heap,
+                uses: 0,
+                slot: None,
+            });
+        };
+
+        let pooled = self.idle.lock().unwrap().pop();
+        let pooled = match pooled {
+            Some(pooled) => pooled,
+            None => {
+                let (runtime, heap) = self.create().await?;
+                PooledRuntime { runtime, heap, uses: 0 }
+            }
+        };
+        Ok(RuntimeLease {
+            runtime: pooled.runtime,
+            heap: pooled.heap,
+            uses: pooled.uses,
+            slot: Some((self.clone(), permit)),
+        })
+    }
+}
+
+// A runtime checked out for one execution. Dropping the lease instead of releasing
+// it, e.g. when the execution was aborted, discards the runtime.
+pub struct RuntimeLease {
+    runtime: AsyncRuntime,
+    heap: Arc<HeapMeter>,
+    uses: u32,
+    slot: Option<(Arc<RuntimePool>, OwnedSemaphorePermit)>,
+}
+
+impl RuntimeLease {
+    pub fn runtime(&self) -> &AsyncRuntime {
+        &self.runtime
+    }
+
+    pub fn heap(&self) -> &Arc<HeapMeter> {
+        &self.heap
+    }
+
+    // Returns the runtime to the pool if it can be reused
+    pub async fn release(self) {
+        let Some((pool, _permit)) = self.slot else {
+            return;
+        };
+        let runtime = self.runtime;
+        let heap = self.heap;
+
+        // Settling the result usually leaves a reaction job or two behind. They run
+        // under the execution's interrupt handler still; anything more than that, such
+        // as futures still in flight, stays with the discarded runtime.
+        for _ in 0..MAX_LEFTOVER_JOBS {
+            if !runtime.is_job_pending().await {
+                break;
+            }
+            if runtime.execute_pending_job().await.is_err() {
+                return;
+            }
+        }
+        if runtime.is_job_pending().await {
+            return;
+        }
+        runtime.set_interrupt_handler(None).await;
+        runtime.set_host_promise_rejection_tracker(None).await;
+        runtime.set_memory_limit(pool.max_memory_bytes).await;
+        runtime.run_gc().await;
+
+        let uses = self.uses + 1;
+        let heap_bytes = runtime.memory_usage().await.malloc_size.max(0) as usize;
+        if uses >= pool.max_uses || heap_bytes > pool.max_heap_bytes {
+            tracing::debug!("Recycling runtime after {} executions ({} heap bytes)", uses, heap_bytes);
+            return;
+        }
+        pool.idle.lock().unwrap().push(PooledRuntime { runtime, heap, uses });
+    }
+}
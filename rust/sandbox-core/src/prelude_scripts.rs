@@ -0,0 +1,116 @@
+// Server-configured scripts evaluated into every context before user code: the .js
+// files of PRELUDE_DIR in name order, then PRELUDE_FILES in the order given.
+//
+// Each file is compiled to bytecode once at startup, so one that doesn't compile
+// stops the server from starting, and the bytecode is run as a strict global script
+// in every fresh context, after the sandbox's globals. What a script declares at its
+// top level is a global of user code. Its frames are named `prelude:<file>`, which
+// `js_error` leaves out of the stack traces user code is shown.
+
+use rquickjs::{Coerced, Context, Ctx, Runtime};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::code_cache;
+use crate::config::Config;
+
+// What the file names of prelude scripts start with in stack traces
+pub const FILENAME_PREFIX: &str = "prelude:";
+
+// One loaded file, as GET /functions and GET /version list it
+#[derive(Serialize, Clone, Debug)]
+pub struct PreludeFile {
+    // The file name, without the directory
+    pub name: String,
+    pub bytes: usize,
+    pub sha256: String,
+}
+
+#[derive(Default)]
+pub struct PreludeScripts {
+    files: Vec<PreludeFile>,
+    bytecode: Vec<Vec<u8>>,
+}
+
+impl PreludeScripts {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut paths = Vec::new();
+        if !config.prelude_dir.is_empty() {
+            let unreadable = |e: std::io::Error| format!("Can't read PRELUDE_DIR {}: {}", config.prelude_dir, e);
+            let mut found = Vec::new();
+            for entry in std::fs::read_dir(&config.prelude_dir).map_err(unreadable)? {
+                let path = entry.map_err(unreadable)?.path();
+                if path.is_file() && path.extension().is_some_and(|extension| extension == "js") {
+                    found.push(path);
+                }
+            }
+            found.sort();
+            paths.extend(found);
+        }
+        paths.extend(config.prelude_files.iter().map(PathBuf::from));
+        if paths.is_empty() {
+            return Ok(PreludeScripts::default());
+        }
+
+        // Bytecode doesn't depend on the runtime it was compiled in
+        let runtime = Runtime::new().map_err(|e| format!("Can't compile the prelude scripts: {}", e))?;
+        let context = Context::full(&runtime).map_err(|e| format!("Can't compile the prelude scripts: {}", e))?;
+        let mut scripts = PreludeScripts::default();
+        for path in &paths {
+            let (file, bytecode) = context.with(|ctx| compile(&ctx, path))?;
+            scripts.files.push(file);
+            scripts.bytecode.push(bytecode);
+        }
+        Ok(scripts)
+    }
+
+    pub fn files(&self) -> &[PreludeFile] {
+        &self.files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Runs every script in the context, in order, stopping at the first that throws
+    pub fn evaluate(&self, ctx: &Ctx<'_>) -> Result<(), String> {
+        for (file, bytecode) in self.files.iter().zip(&self.bytecode) {
+            code_cache::run_script_bytecode(ctx, bytecode)
+                .map_err(|_| format!("Prelude script {} failed: {}", file.name, exception(ctx)))?;
+        }
+        Ok(())
+    }
+}
+
+fn compile(ctx: &Ctx<'_>, path: &Path) -> Result<(PreludeFile, Vec<u8>), String> {
+    let shown = path.display();
+    let source = std::fs::read_to_string(path).map_err(|e| format!("Can't read prelude script {}: {}", shown, e))?;
+    let name = path.file_name().map_or_else(|| shown.to_string(), |name| name.to_string_lossy().into_owned());
+    let filename = format!("{}{}", FILENAME_PREFIX, name);
+    let bytecode = code_cache::compile_named_script(ctx, &filename, &source)
+        .and_then(|function| code_cache::write_script(ctx, &function))
+        .map_err(|_| format!("Prelude script {} doesn't compile: {}", shown, exception(ctx)))?;
+    let file = PreludeFile {
+        name,
+        bytes: source.len(),
+        sha256: Sha256::digest(source.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+    Ok((file, bytecode))
+}
+
+// `SyntaxError: message at prelude:lib.js:3:7`, with the innermost frame of the
+// pending exception
+fn exception(ctx: &Ctx<'_>) -> String {
+    let exception = ctx.catch();
+    let text = exception.get::<Coerced<String>>().map(|s| s.0).unwrap_or_else(|_| "<unprintable exception>".to_string());
+    let frame = exception
+        .as_object()
+        .and_then(|obj| obj.get::<_, Option<Coerced<String>>>("stack").ok().flatten())
+        .and_then(|stack| stack.0.lines().next().map(|line| line.trim().to_string()))
+        .filter(|frame| !frame.is_empty());
+    match frame {
+        Some(frame) => format!("{} {}", text, frame),
+        None => text,
+    }
+}
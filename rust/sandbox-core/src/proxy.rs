@@ -0,0 +1,120 @@
+// Explicit egress proxy configuration for outbound fetches.
+//
+// Only OUTBOUND_HTTP_PROXY / OUTBOUND_HTTPS_PROXY / OUTBOUND_NO_PROXY are honored;
+// ambient HTTP_PROXY style variables are ignored so routing doesn't depend on
+// whatever environment the service happens to be started in.
+
+use ipnet::IpNet;
+use reqwest::Url;
+use std::net::IpAddr;
+
+use crate::config::Config;
+
+#[derive(Clone, Debug)]
+enum NoProxyRule {
+    All,
+    Cidr(IpNet),
+    Ip(IpAddr),
+    // Matches the domain itself and all of its subdomains
+    DomainSuffix(String),
+}
+
+impl NoProxyRule {
+    fn parse(rule: &str) -> Self {
+        if rule == "*" {
+            NoProxyRule::All
+        } else if let Ok(net) = rule.parse::<IpNet>() {
+            NoProxyRule::Cidr(net)
+        } else if let Ok(ip) = rule.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            NoProxyRule::Ip(ip)
+        } else {
+            NoProxyRule::DomainSuffix(rule.trim_start_matches("*.").trim_start_matches('.').to_ascii_lowercase())
+        }
+    }
+
+    fn matches(&self, host: &url::Host<&str>) -> bool {
+        let ip = match host {
+            url::Host::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            url::Host::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            url::Host::Domain(_) => None,
+        };
+
+        match (self, host) {
+            (NoProxyRule::All, _) => true,
+            (NoProxyRule::Cidr(net), _) => ip.map(|ip| net.contains(&ip)).unwrap_or(false),
+            (NoProxyRule::Ip(rule), _) => ip == Some(*rule),
+            (NoProxyRule::DomainSuffix(suffix), url::Host::Domain(domain)) => {
+                let domain = domain.to_ascii_lowercase();
+                domain == *suffix || domain.ends_with(&format!(".{}", suffix))
+            }
+            (NoProxyRule::DomainSuffix(_), _) => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<NoProxyRule>,
+}
+
+impl ProxyConfig {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let proxy_url = |var: &str, value: &str| -> Result<Option<Url>, String> {
+            match value.trim() {
+                "" => Ok(None),
+                value => Url::parse(value).map(Some).map_err(|e| format!("Invalid {}: {}", var, e)),
+            }
+        };
+
+        Ok(ProxyConfig {
+            http: proxy_url("OUTBOUND_HTTP_PROXY", &config.outbound_http_proxy)?,
+            https: proxy_url("OUTBOUND_HTTPS_PROXY", &config.outbound_https_proxy)?,
+            no_proxy: config
+                .outbound_no_proxy
+                .iter()
+                .map(|r| r.trim())
+                .filter(|r| !r.is_empty())
+                .map(NoProxyRule::parse)
+                .collect(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.http.is_some() || self.https.is_some()
+    }
+
+    // Hostnames of the configured proxies, which the operator trusts explicitly
+    pub fn hosts(&self) -> Vec<String> {
+        [&self.http, &self.https]
+            .into_iter()
+            .flatten()
+            .filter_map(|proxy| proxy.host_str().map(str::to_string))
+            .collect()
+    }
+
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let proxy = match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        }?;
+
+        let host = url.host()?;
+        if self.no_proxy.iter().any(|rule| rule.matches(&host)) {
+            return None;
+        }
+
+        Some(proxy.clone())
+    }
+
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if !self.is_enabled() {
+            return builder.no_proxy();
+        }
+
+        let config = self.clone();
+        builder.proxy(reqwest::Proxy::custom(move |url| config.proxy_for(url)))
+    }
+}
@@ -0,0 +1,44 @@
+// Seeded `Math.random`, so an execution can be reproduced from its seed.
+//
+// Every execution gets a seed, the request's `random_seed` or a random one, and
+// reports it in the execution metadata. The generator is SplitMix64, which is
+// small, fast and produces the same sequence on every platform.
+
+use rquickjs::function::Func;
+use rquickjs::{Ctx, Object, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+pub struct SeededRandom {
+    state: AtomicU64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u32) -> Self {
+        SeededRandom {
+            state: AtomicU64::new(u64::from(seed)),
+        }
+    }
+
+    fn next_u64(&self) -> u64 {
+        let mut z = self.state.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed).wrapping_add(GOLDEN_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1) with 53 bits of precision, like `Math.random`
+    pub fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Replaces `Math.random` with a generator seeded with `seed`
+pub fn install(ctx: &Ctx<'_>, seed: u32) -> Result<()> {
+    let random = Arc::new(SeededRandom::new(seed));
+    let math: Object = ctx.globals().get("Math")?;
+    math.set("random", Func::from(move || random.next_f64()))?;
+    Ok(())
+}
@@ -0,0 +1,280 @@
+// Recording the responses an execution's httpRequest calls got, and replaying them
+// so that the execution can be repeated exactly.
+//
+// A recording maps a key per request to the HttpResult the code got for it. The
+// key is the method and the URL with its query, followed by a hash of the body if
+// there is one; the second and later requests with the same key get " #2", " #3"
+// and so on appended, so repeated calls replay in order. Headers in MASKED_HEADERS
+// are masked in the recording. A replay answers every request from such a
+// recording and never from the network; a request that isn't in it throws, and
+// fails the execution as an unmatched request even if the code caught the error.
+// With `debug`, an execution keeps every request under its key, so a request that
+// should have been replayed can be told apart from the recorded one.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::dry_run::{masked_headers, MASKED};
+use crate::fetch::{request_url, ErrorCode, FetchSession, HttpBackend, HttpResult, Timing};
+use crate::secrets::Secrets;
+
+pub type Recording = BTreeMap<String, HttpResult>;
+
+// Hands out the keys of an execution's requests. Recording and replay build them
+// here alone, from the options as serde_json values, whose object keys are sorted:
+// the order the code wrote options in doesn't matter, and no options are the same
+// request as `{}`.
+#[derive(Default)]
+struct Keys {
+    seen: Mutex<HashMap<String, u32>>,
+}
+
+impl Keys {
+    // The key, and the method and URL it starts with
+    fn next(&self, url: String, options: Option<&HashMap<String, Value>>) -> (String, String) {
+        let option = |name: &str| options.and_then(|options| options.get(name));
+        let method = option("method").and_then(Value::as_str).unwrap_or("GET").to_ascii_uppercase();
+        let request = format!("{} {}", method, request_url(url, options));
+        let mut key = request.clone();
+        let body = match option("body").or_else(|| option("multipart")) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(body)) => Some(body.clone()),
+            Some(body) => Some(body.to_string()),
+        };
+        if let Some(body) = body {
+            let digest: String = Sha256::digest(body.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect();
+            key = format!("{} body:{}", key, digest);
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        let count = seen.entry(key.clone()).or_insert(0);
+        *count += 1;
+        let key = match *count {
+            1 => key,
+            count => format!("{} #{}", key, count),
+        };
+        (key, request)
+    }
+}
+
+// Sends the requests to another backend and keeps what came back
+pub struct Recorder {
+    inner: Arc<dyn HttpBackend>,
+    // Lowercase
+    masked_headers: Vec<String>,
+    keys: Keys,
+    recording: Mutex<Recording>,
+}
+
+impl Recorder {
+    pub fn new(inner: Arc<dyn HttpBackend>, masked_headers: &[String]) -> Self {
+        Recorder {
+            inner,
+            masked_headers: masked_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            keys: Keys::default(),
+            recording: Mutex::new(Recording::new()),
+        }
+    }
+
+    pub fn recording(&self) -> Recording {
+        self.recording.lock().unwrap().clone()
+    }
+
+    fn masked(&self, result: &HttpResult) -> HttpResult {
+        let masked = |name: &str| self.masked_headers.contains(&name.to_ascii_lowercase());
+        let mut result = result.clone();
+        for (name, value) in result.headers.iter_mut().chain(result.raw_headers.iter_mut().map(|(name, value)| (&*name, value))) {
+            if masked(name) {
+                *value = MASKED.to_string();
+            }
+        }
+        if masked("set-cookie") {
+            result.set_cookies.iter_mut().for_each(|cookie| *cookie = MASKED.to_string());
+        }
+        result
+    }
+}
+
+impl HttpBackend for Recorder {
+    fn fetch<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        let (key, _) = self.keys.next(url.clone(), options.as_ref());
+        Box::pin(async move {
+            let result = self.inner.fetch(session, url, options).await;
+            self.recording.lock().unwrap().insert(key, self.masked(&result));
+            result
+        })
+    }
+}
+
+// Answers the requests from a recording
+pub struct Replay {
+    recording: Recording,
+    keys: Keys,
+    // Why the first request missing from the recording failed
+    unmatched: Mutex<Option<String>>,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Replay {
+            recording,
+            keys: Keys::default(),
+            unmatched: Mutex::new(None),
+        }
+    }
+
+    // Why the first request missing from the recording failed, if one was
+    pub fn unmatched(&self) -> Option<String> {
+        self.unmatched.lock().unwrap().clone()
+    }
+
+    fn answer(&self, url: String, options: Option<HashMap<String, Value>>) -> HttpResult {
+        let (key, request) = self.keys.next(url, options.as_ref());
+        match self.recording.get(&key) {
+            // Timed as this execution's request, not the recorded one
+            Some(result) => HttpResult {
+                timing: Timing::default(),
+                ..result.clone()
+            },
+            None => {
+                // The same request with another body or more often than it was recorded
+                let similar: Vec<&str> = self
+                    .recording
+                    .keys()
+                    .filter(|recorded| *recorded == &request || recorded.starts_with(&format!("{} ", request)))
+                    .map(String::as_str)
+                    .collect();
+                let message = match similar.is_empty() {
+                    true => format!("replay_http has no response for {}", key),
+                    false => format!("replay_http has no response for {}; recorded: {}", key, similar.join(", ")),
+                };
+                self.unmatched.lock().unwrap().get_or_insert_with(|| message.clone());
+                HttpResult::failure(ErrorCode::UnmatchedRequest, "Unmatched Request", message)
+            }
+        }
+    }
+}
+
+impl HttpBackend for Replay {
+    fn fetch<'a>(
+        &'a self,
+        _session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        Box::pin(std::future::ready(self.answer(url, options)))
+    }
+}
+
+// One httpRequest call of an execution with `debug`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugRequest {
+    // What replay_http looks the response up by
+    pub key: String,
+    pub method: String,
+    pub url: String,
+    // As the script set them, MASKED_HEADERS masked
+    pub headers: BTreeMap<String, String>,
+    // None until the request finished, and for requests that got no response
+    pub status: Option<u16>,
+    pub error_code: Option<ErrorCode>,
+    pub duration_ms: u64,
+}
+
+impl DebugRequest {
+    pub fn redact(&mut self, secrets: &Secrets) {
+        secrets.redact_in_place(&mut self.key);
+        secrets.redact_in_place(&mut self.url);
+        for value in self.headers.values_mut() {
+            secrets.redact_in_place(value);
+        }
+    }
+}
+
+// Sends the requests to another backend and keeps a DebugRequest for each
+pub struct RequestLog {
+    inner: Arc<dyn HttpBackend>,
+    // Lowercase
+    masked_headers: Vec<String>,
+    keys: Keys,
+    requests: Mutex<Vec<DebugRequest>>,
+}
+
+impl RequestLog {
+    pub fn new(inner: Arc<dyn HttpBackend>, masked_headers: &[String]) -> Self {
+        RequestLog {
+            inner,
+            masked_headers: masked_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            keys: Keys::default(),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    // In the order they were made
+    pub fn requests(&self) -> Vec<DebugRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl HttpBackend for RequestLog {
+    fn fetch<'a>(
+        &'a self,
+        session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        let (key, request) = self.keys.next(url.clone(), options.as_ref());
+        let (method, url_with_query) = request.split_once(' ').unwrap_or(("GET", &request));
+        let entry = DebugRequest {
+            key,
+            method: method.to_string(),
+            url: url_with_query.to_string(),
+            headers: masked_headers(options.as_ref(), &self.masked_headers),
+            status: None,
+            error_code: None,
+            duration_ms: 0,
+        };
+        let index = {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(entry);
+            requests.len() - 1
+        };
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = self.inner.fetch(session, url, options).await;
+            if let Some(entry) = self.requests.lock().unwrap().get_mut(index) {
+                entry.status = result.error_code.is_none().then_some(result.status);
+                entry.error_code = result.error_code;
+                entry.duration_ms = started.elapsed().as_millis() as u64;
+            }
+            result
+        })
+    }
+}
+
+pub fn redact(recording: &mut Recording, secrets: &Secrets) {
+    *recording = std::mem::take(recording)
+        .into_iter()
+        .map(|(key, mut result)| {
+            secrets.redact_in_place(&mut result.status_text);
+            result.headers.values_mut().for_each(|value| secrets.redact_in_place(value));
+            result.raw_headers.iter_mut().for_each(|(_, value)| secrets.redact_in_place(value));
+            result.set_cookies.iter_mut().for_each(|cookie| secrets.redact_in_place(cookie));
+            secrets.redact_value(&mut result.data);
+            secrets.redact_in_place(&mut result.text);
+            (secrets.redact(&key), result)
+        })
+        .collect();
+}
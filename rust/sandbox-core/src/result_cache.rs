@@ -0,0 +1,175 @@
+// Cross-execution cache for results, opted into per request with
+// `cache = { ttlSeconds }`.
+//
+// Entries are keyed by the SHA-256 of the code and the SHA-256 of the inputs as
+// canonical JSON (serde_json keeps object keys sorted), with the evaluation mode
+// and the settings that change what the code sees next to them, such as the state
+// namespace, the random seed and a frozen clock, and the tenant in front. Only
+// executions that succeeded, sent nothing but GET and HEAD requests and left their
+// state alone are kept, since serving any other from the cache would skip a side
+// effect. A hit is answered without a runtime or an execution slot. RESULT_CACHE_MAX_ENTRIES and RESULT_CACHE_MAX_BYTES bound the
+// cache, evicting least recently used entries first.
+
+use crate::config::Config;
+use crate::executor::{Execution, Options, Report, Usage};
+use lru::LruCache;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    result: Value,
+    result_json: Option<String>,
+    report: Report,
+    size: usize,
+    expires_at: Instant,
+}
+
+struct Entries {
+    lru: LruCache<String, Entry>,
+    bytes: usize,
+}
+
+pub struct ResultCache {
+    entries: Mutex<Entries>,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl ResultCache {
+    pub fn from_config(config: &Config) -> Self {
+        ResultCache {
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_entries: config.result_cache_max_entries,
+            max_bytes: config.result_cache_max_bytes,
+        }
+    }
+
+    // None for executions whose result depends on more than their code and inputs:
+    // secrets and files, canned or replayed responses, and dry runs. Debug runs
+    // aren't served from the cache, which has no requests to show.
+    pub fn key(code: &str, inputs: &Value, options: &Options) -> Option<String> {
+        let depends_on_more = !options.secrets.is_empty()
+            || !options.files.is_empty()
+            || options.dry_run
+            || options.http_mocks.is_some()
+            || options.replay_http.is_some()
+            || options.record_http
+            || options.debug;
+        if depends_on_more {
+            return None;
+        }
+        let hex = |bytes: &[u8]| -> String { Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect() };
+        let mode = if options.module { "module" } else { "script" };
+        let entrypoint = match &options.entrypoint {
+            Some(entrypoint) => hex(serde_json::json!([entrypoint.name, entrypoint.args]).to_string().as_bytes()),
+            None => String::new(),
+        };
+        let modules = match &options.modules {
+            Some(map) => hex(serde_json::json!([map.entry, map.sources]).to_string().as_bytes()),
+            None => String::new(),
+        };
+        // A namespace's state can be read without changing it
+        let settings = serde_json::json!([
+            options.state_namespace,
+            options.random_seed,
+            options.freeze_time,
+            format!("{:?}", options.language),
+            options.disable_network,
+            options.disable_dynamic_eval,
+            options.skip_prelude,
+        ]);
+        Some(format!(
+            "{}:{}:{}:{}:{:?}:{}:{}:{}",
+            options.tenant.as_deref().unwrap_or_default(),
+            hex(code.as_bytes()),
+            hex(inputs.to_string().as_bytes()),
+            mode,
+            options.bigint_mode,
+            hex(settings.to_string().as_bytes()),
+            entrypoint,
+            modules
+        ))
+    }
+
+    // The cached execution, reported as a hit that made no requests
+    pub fn get(&self, key: &str, request_id: String, started: Instant) -> Option<Execution> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.lru.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                return Some(Execution {
+                    result: entry.result.clone(),
+                    result_json: entry.result_json.clone(),
+                    report: Report {
+                        duration: started.elapsed(),
+                        request_id,
+                        ..entry.report.clone()
+                    },
+                });
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            if let Some(entry) = entries.lru.pop(key) {
+                entries.bytes -= entry.size;
+            }
+        }
+        None
+    }
+
+    // Keeps the execution if nothing it did needs to happen again
+    pub fn insert(&self, key: String, execution: &Execution, ttl: Duration) {
+        let report = &execution.report;
+        let side_effects = report.state_changed
+            || report.http.iter().any(|request| request.method != "GET" && request.method != "HEAD");
+        let size = key.len() + report.usage.result_bytes;
+        if side_effects || self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        // Nor when the clock can't represent its expiry
+        let Some(expires_at) = Instant::now().checked_add(ttl) else {
+            return;
+        };
+
+        // What a hit reports; the limits stay those of the execution that ran
+        let report = Report {
+            logs: Vec::new(),
+            unhandled_rejections: Vec::new(),
+            http: Vec::new(),
+            queue_wait: Duration::ZERO,
+            fetch_duration: Duration::ZERO,
+            usage: Usage {
+                result_bytes: report.usage.result_bytes,
+                ..Usage::default()
+            },
+            http_request_count: 0,
+            code_cache_hit: false,
+            result_cache_hit: true,
+            ..report.clone()
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Entry {
+            result: execution.result.clone(),
+            result_json: execution.result_json.clone(),
+            report,
+            size,
+            expires_at,
+        };
+        if let Some(old) = entries.lru.put(key, entry) {
+            entries.bytes -= old.size;
+        }
+        entries.bytes += size;
+
+        while entries.lru.len() > self.max_entries || entries.bytes > self.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.bytes -= evicted.size,
+                None => break,
+            }
+        }
+    }
+}
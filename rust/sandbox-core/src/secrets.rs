@@ -0,0 +1,121 @@
+// Values the code can use but that must not leave the sandbox, e.g. API tokens.
+//
+// Secrets are available to the code as the frozen SECRETS global, never as part of
+// INPUTS. Whatever an execution hands back (its result, console output, error
+// messages, rejections and the trace of its requests) has every occurrence of a
+// secret value replaced with REDACTED. Only exact occurrences are found, so a secret
+// the code encoded or took apart comes out as it is.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const REDACTED: &str = "***REDACTED***";
+
+#[derive(Clone, Default)]
+pub struct Secrets {
+    values: BTreeMap<String, String>,
+    // The values to redact, longest first so that a secret containing another one
+    // is replaced as a whole
+    redacted: Vec<String>,
+}
+
+impl Secrets {
+    pub fn new(values: BTreeMap<String, String>) -> Self {
+        let mut redacted: Vec<String> = values.values().filter(|value| !value.is_empty()).cloned().collect();
+        redacted.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        redacted.dedup();
+        Secrets { values, redacted }
+    }
+
+    // By name
+    pub fn values(&self) -> &BTreeMap<String, String> {
+        &self.values
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.redacted.is_empty()
+    }
+
+    // The SECRETS object as JSON, for injection into the context
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.values).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.redacted
+            .iter()
+            .fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED))
+    }
+
+    pub fn redact_in_place(&self, text: &mut String) {
+        if self.redacted.iter().any(|secret| text.contains(secret.as_str())) {
+            *text = self.redact(text);
+        }
+    }
+
+    // Every string in the value, including object keys
+    pub fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => self.redact_in_place(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item)),
+            Value::Object(map) => {
+                let entries = std::mem::take(map);
+                for (mut key, mut item) in entries {
+                    self.redact_in_place(&mut key);
+                    self.redact_value(&mut item);
+                    map.insert(key, item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The strings of a serialized value, like `redact_value` does them. Secrets are
+    // looked for as JSON escapes them, and only within string literals, so that the
+    // text stays valid JSON.
+    pub fn redact_json(&self, json: &mut String) {
+        let escaped: Vec<String> = self
+            .redacted
+            .iter()
+            .map(|secret| {
+                let quoted = serde_json::to_string(secret).unwrap_or_default();
+                quoted[1..quoted.len() - 1].to_string()
+            })
+            .collect();
+        if !escaped.iter().any(|secret| json.contains(secret.as_str())) {
+            return;
+        }
+        let mut out = String::with_capacity(json.len());
+        let mut rest = json.as_str();
+        while let Some(start) = rest.find('"') {
+            out.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+            // The closing quote is the first one that isn't escaped
+            let bytes = rest.as_bytes();
+            let mut end = 0;
+            while end < bytes.len() && bytes[end] != b'"' {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            let end = end.min(rest.len());
+            let literal = escaped
+                .iter()
+                .fold(rest[..end].to_string(), |literal, secret| literal.replace(secret.as_str(), REDACTED));
+            out.push_str(&literal);
+            rest = &rest[end..];
+            if let Some(quote) = rest.strip_prefix('"') {
+                out.push('"');
+                rest = quote;
+            }
+        }
+        out.push_str(rest);
+        *json = out;
+    }
+}
+
+// Only the names, so that secrets can't end up in logs through `{:?}`
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.values.keys()).finish()
+    }
+}
@@ -0,0 +1,40 @@
+// Serialization of the result.
+//
+// The result is stringified with the replacer from `js/serialize.js`, which turns
+// Maps, Sets, typed arrays and BigInts into JSON. `bigint_mode` on the request picks
+// the BigInt representation: decimal strings (the default), numbers when they fit in
+// a double without losing precision, or an error.
+
+use rquickjs::{Ctx, Function, Object, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::code_cache;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BigIntMode {
+    #[default]
+    String,
+    // Numbers within the safe integer range, strings beyond it
+    Number,
+    Error,
+}
+
+impl BigIntMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            BigIntMode::String => "string",
+            BigIntMode::Number => "number",
+            BigIntMode::Error => "error",
+        }
+    }
+}
+
+// Replacer for `JSON.stringify` of the result
+pub fn replacer<'js>(ctx: &Ctx<'js>, bigint_mode: BigIntMode) -> Result<Function<'js>> {
+    let (module, promise) = code_cache::prelude(ctx, "serialize.js", include_str!("js/serialize.js"))?.eval()?;
+    promise.finish::<()>()?;
+    let namespace: Object = module.namespace()?;
+    let create_replacer: Function = namespace.get("createReplacer")?;
+    create_replacer.call((bigint_mode.as_str(),))
+}
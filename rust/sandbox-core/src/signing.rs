@@ -0,0 +1,354 @@
+// `options.sign`: signatures computed over the request as it is sent, which the
+// script can't make itself since it never sees the final bytes and headers.
+//
+//     { type: "hmac-sha256", secretRef, header, payloadFields, separator, encoding, prefix }
+//     { type: "aws-sigv4", region, service, credentialsRef }
+//
+// Keys are referenced by name and looked up in the execution's SECRETS, then in the
+// server's `[signing_secrets]`, so they never appear in the code. SigV4 takes the access
+// key from `<credentialsRef>_ACCESS_KEY_ID`, `<credentialsRef>_SECRET_ACCESS_KEY` and,
+// for temporary credentials, `<credentialsRef>_SESSION_TOKEN`. Error messages name
+// secrets but never hold their values.
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum Signing {
+    #[serde(rename = "hmac-sha256", rename_all = "camelCase")]
+    Hmac {
+        secret_ref: String,
+        #[serde(default = "default_signature_header")]
+        header: String,
+        // What is signed, joined by `separator`: "method", "path" (with the query
+        // string), "url", "body", "timestamp" or "header:<name>"
+        #[serde(default = "default_payload_fields")]
+        payload_fields: Vec<String>,
+        #[serde(default = "default_separator")]
+        separator: String,
+        // "hex" or "base64"
+        #[serde(default = "default_encoding")]
+        encoding: String,
+        // Put in front of the signature, e.g. "sha256="
+        #[serde(default)]
+        prefix: String,
+        // Where "timestamp" is sent; a value the script set is signed instead of the
+        // current time
+        #[serde(default = "default_timestamp_header")]
+        timestamp_header: String,
+    },
+    #[serde(rename = "aws-sigv4", rename_all = "camelCase")]
+    AwsSigV4 {
+        region: String,
+        service: String,
+        credentials_ref: String,
+    },
+}
+
+fn default_signature_header() -> String {
+    "X-Signature".to_string()
+}
+
+fn default_payload_fields() -> Vec<String> {
+    vec!["body".to_string()]
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+fn default_encoding() -> String {
+    "hex".to_string()
+}
+
+fn default_timestamp_header() -> String {
+    "X-Signature-Timestamp".to_string()
+}
+
+impl Signing {
+    pub fn from_options(sign: &Value) -> Result<Self, String> {
+        Signing::deserialize(sign).map_err(|e| format!("Invalid sign option: {}", e))
+    }
+
+    // The header the signature goes in
+    pub fn header(&self) -> &str {
+        match self {
+            Signing::Hmac { header, .. } => header,
+            Signing::AwsSigV4 { .. } => "authorization",
+        }
+    }
+
+    // Adds the signature to `request`. `script_headers` are the names of the headers
+    // the script set, which SigV4 signs along with its own; `secret` finds a secret
+    // by name.
+    pub fn sign(
+        &self,
+        request: &mut reqwest::Request,
+        script_headers: &[String],
+        secret: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(), String> {
+        let header = header_name(self.header())?;
+        if request.headers().contains_key(&header) {
+            return Err(format!("Invalid sign option: the signature goes in {}, which the request already sets", self.header()));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let value = match self {
+            Signing::Hmac { secret_ref, payload_fields, separator, encoding, prefix, timestamp_header, .. } => {
+                let key = lookup(secret, secret_ref)?;
+                let mut payload = Vec::new();
+                for (index, field) in payload_fields.iter().enumerate() {
+                    if index > 0 {
+                        payload.extend_from_slice(separator.as_bytes());
+                    }
+                    payload.extend(hmac_field(request, field, timestamp_header, now)?);
+                }
+                let signature = hmac(key.as_bytes(), &payload);
+                let signature = match encoding.as_str() {
+                    "hex" => hex(&signature),
+                    "base64" => BASE64_STANDARD.encode(signature),
+                    _ => return Err("Invalid sign option: encoding must be \"hex\" or \"base64\"".to_string()),
+                };
+                format!("{}{}", prefix, signature)
+            }
+            Signing::AwsSigV4 { region, service, credentials_ref } => {
+                let access_key_id = lookup(secret, &format!("{}_ACCESS_KEY_ID", credentials_ref))?;
+                let secret_access_key = lookup(secret, &format!("{}_SECRET_ACCESS_KEY", credentials_ref))?;
+                let session_token = secret(&format!("{}_SESSION_TOKEN", credentials_ref));
+                let credentials = Credentials { access_key_id, secret_access_key, session_token };
+                sigv4(request, script_headers, region, service, &credentials, now)?
+            }
+        };
+        let value = HeaderValue::from_str(&value).map_err(|_| "Invalid sign option: the prefix isn't a valid header value".to_string())?;
+        request.headers_mut().insert(header, value);
+        Ok(())
+    }
+}
+
+fn lookup(secret: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<String, String> {
+    secret(name).ok_or_else(|| format!("Invalid sign option: there is no secret named {}", name))
+}
+
+fn header_name(name: &str) -> Result<HeaderName, String> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid sign option: {:?} isn't a valid header name", name))
+}
+
+fn body(request: &reqwest::Request) -> Result<&[u8], String> {
+    match request.body() {
+        None => Ok(&[]),
+        Some(body) => body.as_bytes().ok_or_else(|| "Invalid sign option: multipart bodies can't be signed".to_string()),
+    }
+}
+
+fn hmac_field(request: &mut reqwest::Request, field: &str, timestamp_header: &str, now: u64) -> Result<Vec<u8>, String> {
+    let url = request.url();
+    Ok(match field {
+        "method" => request.method().as_str().as_bytes().to_vec(),
+        "path" => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query).into_bytes(),
+            None => url.path().as_bytes().to_vec(),
+        },
+        "url" => url.as_str().as_bytes().to_vec(),
+        "body" => body(request)?.to_vec(),
+        "timestamp" => {
+            let name = header_name(timestamp_header)?;
+            match request.headers().get(&name) {
+                Some(set_by_script) => set_by_script.as_bytes().to_vec(),
+                None => {
+                    request.headers_mut().insert(name, HeaderValue::from(now));
+                    now.to_string().into_bytes()
+                }
+            }
+        }
+        field => match field.strip_prefix("header:") {
+            Some(name) => match request.headers().get(header_name(name)?) {
+                Some(value) => value.as_bytes().to_vec(),
+                None => return Err(format!("Invalid sign option: payloadFields names {}, which the request doesn't have", name)),
+            },
+            None => {
+                return Err(format!(
+                    "Invalid sign option: unknown payload field {:?}, expected \"method\", \"path\", \"url\", \"body\", \"timestamp\" or \"header:<name>\"",
+                    field
+                ))
+            }
+        },
+    })
+}
+
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+// The Authorization header of AWS Signature Version 4, after adding X-Amz-Date (unless
+// the script set it, whose time is then signed), X-Amz-Security-Token for temporary
+// credentials and, for S3, X-Amz-Content-Sha256
+fn sigv4(
+    request: &mut reqwest::Request,
+    script_headers: &[String],
+    region: &str,
+    service: &str,
+    credentials: &Credentials,
+    now: u64,
+) -> Result<String, String> {
+    let amz_date = match request.headers().get("x-amz-date") {
+        Some(date) => {
+            let date = date.to_str().unwrap_or_default();
+            if date.len() != 16 || !date.ends_with('Z') || date.as_bytes()[8] != b'T' {
+                return Err("Invalid sign option: X-Amz-Date must look like 20150830T123600Z".to_string());
+            }
+            date.to_string()
+        }
+        None => {
+            let date = amz_date(now);
+            request.headers_mut().insert("x-amz-date", HeaderValue::from_str(&date).expect("the date is ASCII"));
+            date
+        }
+    };
+    let payload_hash = hex(&Sha256::digest(body(request)?));
+    if let Some(token) = &credentials.session_token {
+        let token = HeaderValue::from_str(token).map_err(|_| "Invalid sign option: the session token isn't a valid header value".to_string())?;
+        request.headers_mut().insert("x-amz-security-token", token);
+    }
+    if service == "s3" {
+        request.headers_mut().insert("x-amz-content-sha256", HeaderValue::from_str(&payload_hash).expect("the hash is hex"));
+    }
+
+    let url = request.url();
+    let host = match request.headers().get(HOST) {
+        Some(host) => host.to_str().unwrap_or_default().to_string(),
+        None => match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        },
+    };
+    // The host, content type, X-Amz-* headers and those the script set
+    let mut signed: Vec<(String, String)> = vec![("host".to_string(), host)];
+    for name in request.headers().keys() {
+        let lowercase = name.as_str();
+        let wanted = name == CONTENT_TYPE
+            || lowercase.starts_with("x-amz-")
+            || script_headers.iter().any(|set| set.eq_ignore_ascii_case(lowercase));
+        if !wanted || name == HOST || name == AUTHORIZATION || signed.iter().any(|(seen, _)| seen == lowercase) {
+            continue;
+        }
+        let values: Vec<String> = request
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        signed.push((lowercase.to_string(), values.join(",")));
+    }
+    signed.sort();
+    let signed_headers = signed.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = signed.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+
+    // S3 signs the path encoded once, every other service encodes it a second time
+    let path = if url.path().is_empty() { "/" } else { url.path() };
+    let canonical_uri = path
+        .split('/')
+        .map(|segment| match service {
+            "s3" => uri_encode(&percent_decode(segment)),
+            _ => uri_encode(segment.as_bytes()),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| (uri_encode(name.as_bytes()), uri_encode(value.as_bytes())))
+        .collect();
+    query.sort();
+    let canonical_query = query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method().as_str(),
+        canonical_uri,
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+    let key = [region, service, "aws4_request"]
+        .iter()
+        .fold(hmac(format!("AWS4{}", credentials.secret_access_key).as_bytes(), date.as_bytes()), |key, part| {
+            hmac(&key, part.as_bytes())
+        });
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        signed_headers,
+        hex(&hmac(&key, string_to_sign.as_bytes()))
+    ))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Every byte but the unreserved characters of RFC 3986 as %XX, as SigV4 wants it
+fn uri_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(segment: &str) -> Vec<u8> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+// `20150830T123600Z`
+fn amz_date(secs: u64) -> String {
+    // After Howard Hinnant's `civil_from_days`
+    let z = secs / SECONDS_PER_DAY + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + z / 146_097 * 400 + u64::from(month <= 2);
+    let time = secs % SECONDS_PER_DAY;
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
@@ -0,0 +1,314 @@
+// Durable key-value state for scripts, installed as the `state` global.
+//
+// Values are JSON, stored per namespace so that tenants can't see each other's
+// keys. An execution reads through its own writes, which are buffered and only
+// applied to the store when it succeeds; a failed, timed out or cancelled execution
+// leaves the state as it was. Each namespace may hold at most
+// STATE_MAX_NAMESPACE_BYTES of keys and serialized values, and `state.set` throws
+// past that. Entries set with `{ ttlSeconds }` disappear once they expire.
+//
+// With STATE_PATH set the store is kept in that JSON file, rewritten after every
+// execution that changed it and read back at startup; otherwise it lives in memory.
+
+use rquickjs::function::Opt;
+use rquickjs::{Ctx, Exception, Function, Object, Result, Value as JsValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    value: Value,
+    // Milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    fn size(&self, key: &str) -> usize {
+        key.len() + self.value.to_string().len()
+    }
+}
+
+type Namespace = BTreeMap<String, Entry>;
+
+pub struct StateStore {
+    namespaces: Mutex<HashMap<String, Namespace>>,
+    path: Option<PathBuf>,
+    max_namespace_bytes: usize,
+    // Held while the file is written, so that writes don't overtake each other
+    persisting: Mutex<()>,
+}
+
+impl StateStore {
+    pub fn from_config(config: &Config) -> std::result::Result<Self, String> {
+        let path = (!config.state_path.is_empty()).then(|| PathBuf::from(&config.state_path));
+        let namespaces = match &path {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read STATE_PATH {}: {}", path.display(), e))?;
+                serde_json::from_str(&text).map_err(|e| format!("Invalid state in {}: {}", path.display(), e))?
+            }
+            _ => HashMap::new(),
+        };
+        Ok(StateStore {
+            namespaces: Mutex::new(namespaces),
+            path,
+            max_namespace_bytes: config.state_max_namespace_bytes,
+            persisting: Mutex::new(()),
+        })
+    }
+
+    fn get(&self, namespace: &str, key: &str, now: u64) -> Option<Entry> {
+        let namespaces = self.namespaces.lock().unwrap();
+        let entry = namespaces.get(namespace)?.get(key)?;
+        entry.live(now).then(|| entry.clone())
+    }
+
+    // Live entries of the namespace with the writes applied
+    fn merged(&self, namespace: &str, writes: &BTreeMap<String, Option<Entry>>, now: u64) -> Namespace {
+        let namespaces = self.namespaces.lock().unwrap();
+        let mut merged: Namespace = namespaces.get(namespace).cloned().unwrap_or_default();
+        for (key, write) in writes {
+            match write {
+                Some(entry) => merged.insert(key.clone(), entry.clone()),
+                None => merged.remove(key),
+            };
+        }
+        merged.retain(|_, entry| entry.live(now));
+        merged
+    }
+
+    fn commit(&self, namespace: &str, writes: BTreeMap<String, Option<Entry>>) -> std::result::Result<(), String> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let now = now_ms();
+        {
+            let mut namespaces = self.namespaces.lock().unwrap();
+            let entries = namespaces.entry(namespace.to_string()).or_default();
+            for (key, write) in writes {
+                match write {
+                    Some(entry) => entries.insert(key, entry),
+                    None => entries.remove(&key),
+                };
+            }
+            entries.retain(|_, entry| entry.live(now));
+            if entries.is_empty() {
+                namespaces.remove(namespace);
+            }
+        }
+        self.persist()
+    }
+
+    // The live entries of a namespace, to hand to a worker process
+    pub fn export(&self, namespace: &str) -> Value {
+        let mut entries = self.namespaces.lock().unwrap().get(namespace).cloned().unwrap_or_default();
+        let now = now_ms();
+        entries.retain(|_, entry| entry.live(now));
+        serde_json::to_value(entries).unwrap_or_default()
+    }
+
+    // Replaces a namespace with entries from `export`, e.g. in a worker process
+    pub fn import(&self, namespace: &str, entries: Value) -> std::result::Result<(), String> {
+        let entries: Namespace = serde_json::from_value(entries).map_err(|e| format!("Invalid state: {}", e))?;
+        let mut namespaces = self.namespaces.lock().unwrap();
+        match entries.is_empty() {
+            true => namespaces.remove(namespace),
+            false => namespaces.insert(namespace.to_string(), entries),
+        };
+        Ok(())
+    }
+
+    // The writes that turn the `export`ed entries into the namespace as it is now
+    pub fn writes_since(&self, namespace: &str, exported: &Value) -> Value {
+        let before: Namespace = serde_json::from_value(exported.clone()).unwrap_or_default();
+        let namespaces = self.namespaces.lock().unwrap();
+        let empty = Namespace::new();
+        let after = namespaces.get(namespace).unwrap_or(&empty);
+        let mut writes: BTreeMap<String, Option<Entry>> = BTreeMap::new();
+        for (key, entry) in after {
+            let unchanged = before
+                .get(key)
+                .is_some_and(|previous| previous.value == entry.value && previous.expires_at == entry.expires_at);
+            if !unchanged {
+                writes.insert(key.clone(), Some(entry.clone()));
+            }
+        }
+        for key in before.keys().filter(|key| !after.contains_key(*key)) {
+            writes.insert(key.clone(), None);
+        }
+        serde_json::to_value(writes).unwrap_or_default()
+    }
+
+    // Applies writes from `writes_since`, as a successful execution's commit does
+    pub fn apply(&self, namespace: &str, writes: Value) -> std::result::Result<(), String> {
+        let writes = serde_json::from_value(writes).map_err(|e| format!("Invalid state writes: {}", e))?;
+        self.commit(namespace, writes)
+    }
+
+    fn persist(&self) -> std::result::Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _persisting = self.persisting.lock().unwrap();
+        let text = serde_json::to_string(&*self.namespaces.lock().unwrap()).map_err(|e| e.to_string())?;
+        // Replaced in one step, so a crash never leaves a half-written file
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, text)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|e| format!("Failed to save the state to {}: {}", path.display(), e))
+    }
+}
+
+// One execution's view of its namespace
+pub struct StateSession {
+    store: Arc<StateStore>,
+    namespace: String,
+    // None for deleted keys
+    writes: Mutex<BTreeMap<String, Option<Entry>>>,
+}
+
+impl StateSession {
+    pub fn new(store: Arc<StateStore>, namespace: String) -> Self {
+        StateSession {
+            store,
+            namespace,
+            writes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        let now = now_ms();
+        let entry = match self.writes.lock().unwrap().get(key) {
+            Some(write) => write.clone(),
+            None => self.store.get(&self.namespace, key, now),
+        };
+        entry.filter(|entry| entry.live(now)).map(|entry| entry.value)
+    }
+
+    fn set(&self, key: String, value: Value, ttl: Option<Duration>) -> std::result::Result<(), String> {
+        let now = now_ms();
+        let entry = Entry {
+            value,
+            // A TTL too long to represent never expires
+            expires_at: ttl.map(|ttl| now.saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX))),
+        };
+        let mut writes = self.writes.lock().unwrap();
+        let previous = writes.insert(key.clone(), Some(entry));
+        let bytes: usize = self
+            .store
+            .merged(&self.namespace, &writes, now)
+            .iter()
+            .map(|(key, entry)| entry.size(key))
+            .sum();
+        if bytes > self.store.max_namespace_bytes {
+            match previous {
+                Some(previous) => writes.insert(key.clone(), previous),
+                None => writes.remove(&key),
+            };
+            return Err(format!(
+                "state.set('{}') would grow the namespace to {} bytes, exceeding the STATE_MAX_NAMESPACE_BYTES limit of {}",
+                key, bytes, self.store.max_namespace_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let existed = self.get(key).is_some();
+        self.writes.lock().unwrap().insert(key.to_string(), None);
+        existed
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let writes = self.writes.lock().unwrap();
+        self.store
+            .merged(&self.namespace, &writes, now_ms())
+            .into_keys()
+            .filter(|key| key.starts_with(prefix))
+            .collect()
+    }
+
+    // Whether the execution set or deleted any key
+    pub fn changed(&self) -> bool {
+        !self.writes.lock().unwrap().is_empty()
+    }
+
+    // Applies the writes, once the execution succeeded
+    pub fn commit(&self) -> std::result::Result<(), String> {
+        let writes = std::mem::take(&mut *self.writes.lock().unwrap());
+        self.store.commit(&self.namespace, writes)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64)
+}
+
+pub fn install<'js>(ctx: &Ctx<'js>, session: Arc<StateSession>) -> Result<()> {
+    let state = Object::new(ctx.clone())?;
+
+    let get_session = session.clone();
+    state.set(
+        "get",
+        Function::new(ctx.clone(), move |ctx: Ctx<'js>, key: String| -> Result<JsValue<'js>> {
+            match get_session.get(&key) {
+                Some(value) => ctx.json_parse(value.to_string()),
+                None => Ok(JsValue::new_undefined(ctx)),
+            }
+        })?,
+    )?;
+
+    let set_session = session.clone();
+    state.set(
+        "set",
+        Function::new(
+            ctx.clone(),
+            move |ctx: Ctx<'js>, key: String, value: JsValue<'js>, options: Opt<Object<'js>>| -> Result<()> {
+                let json = ctx
+                    .json_stringify(value)?
+                    .map(|json| json.to_string())
+                    .transpose()?
+                    .ok_or_else(|| Exception::throw_type(&ctx, "state.set needs a value that JSON can represent"))?;
+                let value: Value = serde_json::from_str(&json)
+                    .map_err(|e| Exception::throw_type(&ctx, &format!("state.set: {}", e)))?;
+                let ttl = match &options.0 {
+                    Some(options) => match options.get::<_, Option<f64>>("ttlSeconds")? {
+                        Some(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                            Some(Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX))
+                        }
+                        Some(_) => return Err(Exception::throw_range(&ctx, "ttlSeconds must be a positive number")),
+                        None => None,
+                    },
+                    None => None,
+                };
+                set_session
+                    .set(key, value, ttl)
+                    .map_err(|message| Exception::throw_message(&ctx, &message))
+            },
+        )?,
+    )?;
+
+    let delete_session = session.clone();
+    state.set("delete", Function::new(ctx.clone(), move |key: String| delete_session.delete(&key))?)?;
+
+    state.set(
+        "list",
+        Function::new(ctx.clone(), move |prefix: Opt<String>| session.list(prefix.0.as_deref().unwrap_or("")))?,
+    )?;
+
+    let freeze: Function = ctx.globals().get::<_, Object>("Object")?.get("freeze")?;
+    freeze.call::<_, ()>((state.clone(),))?;
+    ctx.globals().set("state", state)?;
+    Ok(())
+}
@@ -0,0 +1,654 @@
+// The globals the sandbox installs next to the standard JavaScript ones, as listed
+// by GET /functions and used by /analyze.
+//
+// Debug builds check every context set up for an execution against the registry,
+// so a global added, removed or renamed without updating it fails every test that
+// executes code. Internal helpers start with `__` and aren't listed.
+
+use rquickjs::{Context, Ctx, Runtime};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::OnceLock;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Function,
+    Class,
+    // A frozen object of helpers, like `crypto`; its members are listed
+    Namespace,
+    Value,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Global {
+    pub name: &'static str,
+    pub kind: Kind,
+    // How it's called or constructed, empty for values and namespaces
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub signature: &'static str,
+    pub description: &'static str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub params: &'static [Param],
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub example: &'static str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub members: &'static [Global],
+    // Missing in sessions and contexts
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub executions_only: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+const fn param(name: &'static str, description: &'static str) -> Param {
+    Param { name, description }
+}
+
+const fn function(
+    name: &'static str,
+    signature: &'static str,
+    description: &'static str,
+    params: &'static [Param],
+    example: &'static str,
+) -> Global {
+    Global {
+        name,
+        kind: Kind::Function,
+        signature,
+        description,
+        params,
+        example,
+        members: &[],
+        executions_only: false,
+    }
+}
+
+const fn class(
+    name: &'static str,
+    signature: &'static str,
+    description: &'static str,
+    params: &'static [Param],
+    example: &'static str,
+) -> Global {
+    Global {
+        kind: Kind::Class,
+        ..function(name, signature, description, params, example)
+    }
+}
+
+const fn value(name: &'static str, description: &'static str, example: &'static str) -> Global {
+    Global {
+        kind: Kind::Value,
+        ..function(name, "", description, &[], example)
+    }
+}
+
+const fn namespace(name: &'static str, description: &'static str, members: &'static [Global]) -> Global {
+    Global {
+        kind: Kind::Namespace,
+        members,
+        ..function(name, "", description, &[], "")
+    }
+}
+
+const URL_PARAM: Param = param("url", "Absolute URL, as a string or a `URL`");
+const INPUT_PATH: Param = param("path", "`\"/a/b/0\"`, `\"a.b[0]\"` or `[\"a\", \"b\", 0]`; the whole input when left out");
+
+pub static GLOBALS: &[Global] = &[
+    value(
+        "INPUTS",
+        "The request's `inputs`, any JSON value; a read-only proxy when larger than LAZY_INPUTS_BYTES",
+        "INPUTS.userId",
+    ),
+    function(
+        "getInput",
+        "getInput(path?) => any",
+        "The part of INPUTS at `path`, also a JSON pointer like `\"/a/b/0\"`, converted on its own from large inputs",
+        &[INPUT_PATH],
+        "getInput(\"orders[0].id\")",
+    ),
+    function(
+        "inputKeys",
+        "inputKeys(path?) => string[] | undefined",
+        "The keys of the object or the indices of the array at `path` of INPUTS",
+        &[INPUT_PATH],
+        "inputKeys(\"/orders\").length",
+    ),
+    function(
+        "inputSize",
+        "inputSize(path?) => number | undefined",
+        "Bytes of the JSON of INPUTS, or of the part at `path`",
+        &[INPUT_PATH],
+        "inputSize() > 1024 * 1024",
+    ),
+    value(
+        "RESULTS",
+        "Named outputs; when any are set, this object is the result instead of the code's value",
+        "RESULTS.summary = { total }",
+    ),
+    function(
+        "setResult",
+        "setResult(name, value) => void",
+        "Sets a named output in RESULTS, replacing one of the same name",
+        &[param("name", "Key in the result"), param("value", "Any value the result could be")],
+        "setResult(\"nextCursor\", page.cursor)",
+    ),
+    value("ENV", "Frozen constants configured on the server", "ENV.BASE_URL"),
+    value(
+        "SECRETS",
+        "Frozen secrets of the request; their values are redacted from logs and responses",
+        "SECRETS.apiToken",
+    ),
+    value(
+        "FILES",
+        "Files uploaded with a multipart request, by field name: `{ name, size, contentType, bytes() }`",
+        "crypto.sha256(base64.encode(FILES.upload.bytes()), { inputEncoding: \"base64\" })",
+    ),
+    namespace(
+        "EXECUTION_TIME",
+        "The instant the execution started",
+        &[
+            value("iso", "ISO 8601 timestamp", ""),
+            value("epochMs", "Milliseconds since the Unix epoch", ""),
+        ],
+    ),
+    function(
+        "httpRequest",
+        "httpRequest(url, options?) => Promise<HttpResult>",
+        "Makes an outbound request and resolves to `{ ok, status, statusText, headers, rawHeaders, data, text, errorCode }`; \
+         failed requests resolve with `ok: false` unless `throwOnError` is set",
+        &[
+            URL_PARAM,
+            param(
+                "options",
+                "`method`, `headers`, `body`, `multipart`, `query`, `redirect`, `maxRedirects`, `timeoutMs`, `retry`, \
+                 `cache`, `sign`, `throwOnError` and `signal`",
+            ),
+        ],
+        "const { data } = await httpRequest('https://api.example.com/users/1');",
+    ),
+    function(
+        "httpGetStream",
+        "httpGetStream(url, options?, onChunk) => Promise<StreamResult>",
+        "Makes an outbound request and hands its body to `onChunk` a chunk at a time, strings for text and `Uint8Array`s \
+         otherwise, waiting for it before reading on; resolves to `{ ok, status, statusText, headers, rawHeaders, bytesRead, \
+         errorCode }`",
+        &[
+            URL_PARAM,
+            param("options", "Options of `httpRequest`, except `cache` and `throwOnError`"),
+            param("onChunk", "Called with each chunk; a returned promise is awaited"),
+        ],
+        "let lines = 0; await httpGetStream(url, (chunk) => { lines += chunk.split('\\n').length - 1; });",
+    ),
+    function(
+        "headersGet",
+        "headersGet(result, name) => string[]",
+        "All values of a response header, case-insensitively",
+        &[param("result", "An `httpRequest` result"), param("name", "Header name")],
+        "headersGet(res, 'set-cookie')",
+    ),
+    function(
+        "graphql",
+        "graphql(url, query, variables?, options?) => Promise<{ data, response }>",
+        "POSTs a GraphQL query and resolves to its `data`; GraphQL errors throw a `GraphQLError`",
+        &[
+            URL_PARAM,
+            param("query", "The GraphQL document"),
+            param("variables", "Object of variables"),
+            param("options", "Options of `httpRequest`, plus `operationName`"),
+        ],
+        "const { data } = await graphql(url, 'query ($id: ID!) { user(id: $id) { name } }', { id: 7 });",
+    ),
+    function(
+        "fetch",
+        "fetch(input, init?) => Promise<Response>",
+        "WHATWG fetch over `httpRequest`; error statuses resolve, and only requests without a response reject",
+        &[
+            param("input", "URL string, `URL` or an object with a `url`"),
+            param("init", "`method`, `headers`, `body`, `redirect` and `signal`"),
+        ],
+        "const user = await (await fetch('https://api.example.com/users/1')).json();",
+    ),
+    class(
+        "Headers",
+        "new Headers(init?)",
+        "Case-insensitive header map of `fetch`",
+        &[param("init", "Object, `[name, value]` pairs or `Headers`")],
+        "new Headers({ accept: 'application/json' })",
+    ),
+    namespace(
+        "Http",
+        "Constants of HTTP results",
+        &[value("ErrorCode", "The `errorCode` values of results without an HTTP response", "Http.ErrorCode.TIMEOUT")],
+    ),
+    class(
+        "HttpError",
+        "new HttpError(message, url, options, result)",
+        "Thrown by `httpRequest` past the request limit, for unmatched mocks and with `throwOnError`; carries `url`, \
+         `options`, `status`, `statusText`, `response` and `reason`",
+        &[],
+        "if (e instanceof HttpError && e.status === 404) return null;",
+    ),
+    class(
+        "GraphQLError",
+        "new GraphQLError(message, url, options, result, errors, data)",
+        "The `HttpError` `graphql` throws for a response with `errors`",
+        &[],
+        "e instanceof GraphQLError && e.errors",
+    ),
+    class(
+        "NetworkDisabledError",
+        "new NetworkDisabledError(message)",
+        "Thrown by `httpRequest`, `fetch` and `graphql` in executions without network access",
+        &[],
+        "e instanceof NetworkDisabledError",
+    ),
+    namespace(
+        "crypto",
+        "Hashing helpers; digests are lowercase hex, or base64 with `{ encoding: 'base64' }`",
+        &[
+            function("sha256", "crypto.sha256(data, options?) => string", "SHA-256 digest", DIGEST_PARAMS, "crypto.sha256('abc')"),
+            function("sha1", "crypto.sha1(data, options?) => string", "SHA-1 digest", DIGEST_PARAMS, ""),
+            function("md5", "crypto.md5(data, options?) => string", "MD5 digest", DIGEST_PARAMS, ""),
+            function(
+                "hmacSha256",
+                "crypto.hmacSha256(key, data, options?) => string",
+                "HMAC-SHA256 signature",
+                &[
+                    param("key", "Signing key"),
+                    DIGEST_DATA,
+                    param("options", "`{ encoding: 'base64' }`, `{ inputEncoding: 'base64' }` and `{ keyEncoding: 'base64' }`"),
+                ],
+                "crypto.hmacSha256(SECRETS.signingKey, body)",
+            ),
+            function("randomUUID", "crypto.randomUUID() => string", "Random version 4 UUID", &[], ""),
+        ],
+    ),
+    Global {
+        executions_only: true,
+        ..namespace(
+            "state",
+            "JSON values kept between executions, per namespace; writes apply when the execution succeeds",
+            &[
+                function("get", "state.get(key) => any", "The value, or `undefined`", &[param("key", "String key")], ""),
+                function(
+                    "set",
+                    "state.set(key, value, options?)",
+                    "Stores a JSON value",
+                    &[
+                        param("key", "String key"),
+                        param("value", "Any value `JSON.stringify` can represent"),
+                        param("options", "`{ ttlSeconds }` to let it expire"),
+                    ],
+                    "state.set('cursor', next, { ttlSeconds: 3600 })",
+                ),
+                function(
+                    "delete",
+                    "state.delete(key) => boolean",
+                    "Removes the key; `true` if it had a value",
+                    &[param("key", "String key")],
+                    "",
+                ),
+                function(
+                    "list",
+                    "state.list(prefix?) => string[]",
+                    "The keys starting with `prefix`, sorted",
+                    &[param("prefix", "Key prefix")],
+                    "",
+                ),
+            ],
+        )
+    },
+    namespace(
+        "console",
+        "Logs to the response's `logs`; arguments are joined with spaces",
+        &[
+            function("log", "console.log(...values)", "Logs at info level", &[], "console.log('fetched', items.length)"),
+            function("info", "console.info(...values)", "Logs at info level", &[], ""),
+            function("warn", "console.warn(...values)", "Logs at warn level", &[], ""),
+            function("error", "console.error(...values)", "Logs at error level", &[], ""),
+            function("debug", "console.debug(...values)", "Logs at debug level", &[], ""),
+        ],
+    ),
+    namespace(
+        "performance",
+        "High-resolution time of the execution",
+        &[
+            function("now", "performance.now() => number", "Milliseconds since the execution started", &[], ""),
+            value("timeOrigin", "When the execution started, in milliseconds since the Unix epoch", ""),
+        ],
+    ),
+    function(
+        "sleep",
+        "sleep(ms) => Promise<void>",
+        "Waits; all waits of an execution share the sleep budget",
+        &[param("ms", "Milliseconds")],
+        "await sleep(500);",
+    ),
+    function(
+        "setTimeout",
+        "setTimeout(callback, ms?, ...args) => number",
+        "Calls `callback` once after `ms` milliseconds",
+        &[param("callback", "Function to call"), param("ms", "Milliseconds, default 0")],
+        "const id = setTimeout(() => controller.abort(), 1000);",
+    ),
+    function(
+        "clearTimeout",
+        "clearTimeout(id)",
+        "Cancels a pending `setTimeout`",
+        &[param("id", "What `setTimeout` returned")],
+        "",
+    ),
+    class(
+        "AbortController",
+        "new AbortController()",
+        "Aborts requests started with its `signal`",
+        &[],
+        "const controller = new AbortController();",
+    ),
+    class(
+        "AbortSignal",
+        "AbortSignal.timeout(ms), AbortSignal.abort(reason?), AbortSignal.any(signals)",
+        "Passed as `signal` to `httpRequest` and `fetch` to abort them",
+        &[],
+        "await fetch(url, { signal: AbortSignal.timeout(2000) })",
+    ),
+    class(
+        "DOMException",
+        "new DOMException(message?, name?)",
+        "Error of aborted requests and failed clones, e.g. `AbortError`",
+        &[],
+        "",
+    ),
+    function(
+        "structuredClone",
+        "structuredClone(value) => any",
+        "Deep copy, keeping cycles and shared references",
+        &[param("value", "Value to copy")],
+        "const copy = structuredClone(res.data);",
+    ),
+    class(
+        "URL",
+        "new URL(input, base?)",
+        "WHATWG URL with a live `searchParams`",
+        &[param("input", "URL, resolved against `base`"), param("base", "Base URL")],
+        "new URL('../orders', 'https://api.example.com/v1/users/7')",
+    ),
+    class(
+        "URLSearchParams",
+        "new URLSearchParams(init?)",
+        "Query string parameters",
+        &[param("init", "Query string, `[name, value]` pairs or an object")],
+        "new URLSearchParams({ page: 2 }).toString()",
+    ),
+    class("TextEncoder", "new TextEncoder()", "Encodes strings to UTF-8 `Uint8Array`s", &[], ""),
+    class(
+        "TextDecoder",
+        "new TextDecoder(label?, options?)",
+        "Decodes bytes to strings",
+        &[
+            param("label", "`utf-8` (default) or `latin1`"),
+            param("options", "`{ fatal, ignoreBOM }`"),
+        ],
+        "new TextDecoder().decode(bytes)",
+    ),
+    function(
+        "btoa",
+        "btoa(string) => string",
+        "Base64 of a latin-1 string",
+        &[param("string", "Characters up to U+00FF")],
+        "",
+    ),
+    function(
+        "atob",
+        "atob(base64) => string",
+        "Latin-1 string of base64",
+        &[param("base64", "Base64 text")],
+        "",
+    ),
+    namespace(
+        "base64",
+        "Base64 of UTF-8 text and binary data",
+        &[
+            function(
+                "encode",
+                "base64.encode(data) => string",
+                "Encodes a string as UTF-8, or bytes",
+                &[param("data", "String, `Uint8Array`, `ArrayBuffer` or array of byte values")],
+                "base64.encode('héllo')",
+            ),
+            function(
+                "decode",
+                "base64.decode(text, options?) => string | number[]",
+                "Decodes to a UTF-8 string, throwing a TypeError for bytes that aren't UTF-8, or byte values with `{ output: 'bytes' }`",
+                &[param("text", "Base64 text"), param("options", "`{ output: 'bytes' }`")],
+                "",
+            ),
+        ],
+    ),
+    namespace(
+        "utils",
+        "Helpers for data; only `set` changes its argument",
+        &[
+            value("VERSION", "Version of the helpers", ""),
+            function(
+                "get",
+                "utils.get(obj, path, fallback?) => any",
+                "Value at a path, `fallback` when missing",
+                &[PATH_OBJECT, PATH, param("fallback", "Returned when the path is missing")],
+                "utils.get(res.data, 'items[0].id')",
+            ),
+            function(
+                "set",
+                "utils.set(obj, path, value) => object",
+                "Sets a value by path, creating objects and arrays on the way",
+                &[PATH_OBJECT, PATH, param("value", "Value to set")],
+                "",
+            ),
+            function(
+                "groupBy",
+                "utils.groupBy(items, keyOrFn) => object",
+                "Object of arrays by key",
+                &[ITEMS, KEY_OR_FN],
+                "",
+            ),
+            function(
+                "uniqBy",
+                "utils.uniqBy(items, keyOrFn) => any[]",
+                "First item for each distinct key",
+                &[ITEMS, KEY_OR_FN],
+                "",
+            ),
+            function(
+                "chunk",
+                "utils.chunk(items, size) => any[][]",
+                "Arrays of at most `size` items",
+                &[ITEMS, param("size", "Largest chunk")],
+                "",
+            ),
+            function(
+                "range",
+                "utils.range(start?, end, step?) => number[]",
+                "Numbers from `start` (default 0) up to, not including, `end`",
+                &[],
+                "utils.range(5)",
+            ),
+            function("sum", "utils.sum(items, keyOrFn?) => number", "Total", &[ITEMS, KEY_OR_FN], ""),
+            function("mean", "utils.mean(items, keyOrFn?) => number", "Average, `NaN` for none", &[ITEMS, KEY_OR_FN], ""),
+            function(
+                "sortBy",
+                "utils.sortBy(items, ...keysOrFns) => any[]",
+                "Stable ascending sort into a new array",
+                &[ITEMS, KEY_OR_FN],
+                "",
+            ),
+            function(
+                "deepEqual",
+                "utils.deepEqual(a, b) => boolean",
+                "Structural equality of arrays, plain objects and dates",
+                &[],
+                "",
+            ),
+            function(
+                "deepMerge",
+                "utils.deepMerge(...objects) => object",
+                "New object merging plain objects recursively",
+                &[],
+                "",
+            ),
+            function(
+                "pad",
+                "utils.pad(value, length, fill?) => string",
+                "Left-pads to `length`",
+                &[param("value", "Value to pad"), param("length", "Length to reach"), param("fill", "Default `\"0\"`")],
+                "",
+            ),
+            function(
+                "formatDate",
+                "utils.formatDate(date, format?) => string",
+                "UTC date using `YYYY MM DD HH mm ss` tokens",
+                &[param("date", "Date, timestamp or string"), param("format", "Default `\"YYYY-MM-DD\"`")],
+                "",
+            ),
+            function(
+                "formatNumber",
+                "utils.formatNumber(value, decimals?) => string",
+                "Fixed decimals with `,` thousands separators",
+                &[param("value", "Number"), param("decimals", "Default 0")],
+                "",
+            ),
+            function(
+                "truncate",
+                "utils.truncate(value, length, suffix?) => string",
+                "Shortens to at most `length` characters",
+                &[param("value", "String"), param("length", "Longest result"), param("suffix", "Default `\"…\"`")],
+                "",
+            ),
+        ],
+    ),
+    function(
+        "parseXml",
+        "parseXml(text, options?) => object",
+        "Turns an XML document into plain objects",
+        &[
+            param("text", "The document"),
+            param("options", "`attributeKey`, `textKey` and `namespaces`"),
+        ],
+        "parseXml(res.text).rss.channel.item",
+    ),
+    function(
+        "parseYaml",
+        "parseYaml(text) => any",
+        "Turns a YAML document into plain values, with aliases copied and `<<` merged",
+        &[param("text", "A single document")],
+        "parseYaml(res.text).spec.containers",
+    ),
+    function(
+        "parseYamlAll",
+        "parseYamlAll(text) => any[]",
+        "The documents of a YAML stream, separated by `---`",
+        &[param("text", "Any number of documents")],
+        "parseYamlAll(manifests).filter((doc) => doc.kind === \"Deployment\")",
+    ),
+    function(
+        "toYaml",
+        "toYaml(value) => string",
+        "Writes a value as a YAML document in block style, as `JSON.stringify` sees it",
+        &[param("value", "Any value JSON can represent")],
+        "toYaml({ replicas: 3 })",
+    ),
+    function(
+        "jsonpath",
+        "jsonpath(value, expression) => any[]",
+        "The values a JSONPath expression selects",
+        JSONPATH_PARAMS,
+        "jsonpath(res.data, '$.items[?(@.price > 10)].name')",
+    ),
+    function(
+        "jsonpathFirst",
+        "jsonpathFirst(value, expression) => any",
+        "The first value a JSONPath expression selects, or `undefined`",
+        JSONPATH_PARAMS,
+        "",
+    ),
+];
+
+const DIGEST_DATA: Param = param("data", "String, hashed as UTF-8");
+const DIGEST_OPTIONS: Param = param("options", "`{ encoding: 'base64' }` and `{ inputEncoding: 'base64' }`");
+const DIGEST_PARAMS: &[Param] = &[DIGEST_DATA, DIGEST_OPTIONS];
+const PATH_OBJECT: Param = param("obj", "Object or array");
+const PATH: Param = param("path", "`\"a.b[0]\"` or `[\"a\", \"b\", 0]`");
+const ITEMS: Param = param("items", "Array");
+const KEY_OR_FN: Param = param("keyOrFn", "Property path or function of the item");
+const JSONPATH_PARAMS: &[Param] = &[
+    param("value", "Any value, e.g. a result's `data`"),
+    param("expression", "JSONPath starting with `$`"),
+];
+
+pub fn find(name: &str) -> Option<&'static Global> {
+    GLOBALS.iter().find(|global| global.name == name)
+}
+
+// The globals of a context without any of the sandbox's, the same for every call
+pub(crate) fn standard_globals() -> Result<&'static HashSet<String>, String> {
+    static STANDARD: OnceLock<HashSet<String>> = OnceLock::new();
+    if let Some(globals) = STANDARD.get() {
+        return Ok(globals);
+    }
+    let runtime = Runtime::new().map_err(|e| format!("Runtime error: {}", e))?;
+    let context = Context::full(&runtime).map_err(|e| format!("Context error: {}", e))?;
+    let names = context
+        .with(|ctx| ctx.eval::<Vec<String>, _>("Object.getOwnPropertyNames(globalThis)"))
+        .map_err(|e| format!("Listing the standard globals failed: {}", e))?;
+    Ok(STANDARD.get_or_init(|| names.into_iter().collect()))
+}
+
+// Compares the globals of a set up context with the registry, including the
+// members of namespaces
+pub(crate) fn check(ctx: &Ctx<'_>, executions: bool) -> Result<(), String> {
+    let standard = standard_globals()?;
+    let installed: BTreeMap<String, Option<Vec<String>>> = ctx
+        .eval::<String, _>(
+            "JSON.stringify(Object.fromEntries(Object.getOwnPropertyNames(globalThis).map((name) => {
+                const value = globalThis[name];
+                return [name, typeof value === 'object' && value !== null ? Object.keys(value) : null];
+            })))",
+        )
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .ok_or("Listing the installed globals failed")?;
+
+    let mut drift = Vec::new();
+    for (name, members) in &installed {
+        if standard.contains(name) || name.starts_with("__") {
+            continue;
+        }
+        match find(name) {
+            None => drift.push(format!("{} isn't registered", name)),
+            Some(global) if global.kind == Kind::Namespace => {
+                let registered: BTreeSet<&str> = global.members.iter().map(|member| member.name).collect();
+                let installed: BTreeSet<&str> = members.iter().flatten().map(String::as_str).collect();
+                if registered != installed {
+                    drift.push(format!("{} has {:?}, registered as {:?}", name, installed, registered));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    for global in GLOBALS {
+        if !installed.contains_key(global.name) && (executions || !global.executions_only) {
+            drift.push(format!("{} is registered but not installed", global.name));
+        }
+    }
+    if drift.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("The globals differ from the registry in surface.rs: {}", drift.join("; ")))
+    }
+}
@@ -0,0 +1,76 @@
+// `sleep(ms)`, `setTimeout` and `clearTimeout`, on Tokio timers, and the
+// AbortController and AbortSignal that go with them.
+//
+// The waits of an execution add up against SLEEP_BUDGET_MS. A wait that would
+// pass it throws a RangeError before it starts: `sleep` rejects and `setTimeout`
+// throws. Waits run on the wall clock, so they count against the execution timeout
+// too; the budget only caps how much of it code can spend waiting. Timers still
+// pending when the code finishes never fire.
+
+use rquickjs::function::{Async, Func};
+use rquickjs::{Ctx, Exception, Object, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::code_cache;
+
+pub struct SleepBudget {
+    limit_ms: u64,
+    used_ms: AtomicU64,
+}
+
+impl SleepBudget {
+    pub fn new(limit: Duration) -> Self {
+        SleepBudget {
+            limit_ms: limit.as_millis() as u64,
+            used_ms: AtomicU64::new(0),
+        }
+    }
+
+    // Takes a wait out of the budget, or says why it doesn't fit
+    fn reserve(&self, ms: u64) -> std::result::Result<(), String> {
+        self.used_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(ms).filter(|total| *total <= self.limit_ms)
+            })
+            .map(drop)
+            .map_err(|used| {
+                format!(
+                    "Sleep budget exceeded: waiting {} ms would pass SLEEP_BUDGET_MS ({} ms, {} ms used)",
+                    ms, self.limit_ms, used
+                )
+            })
+    }
+}
+
+// Milliseconds of a delay as JavaScript takes them: anything that isn't a positive
+// number is 0
+fn milliseconds(ms: f64) -> u64 {
+    if ms.is_finite() && ms > 0.0 {
+        ms as u64
+    } else {
+        0
+    }
+}
+
+pub fn install(ctx: &Ctx<'_>, budget: Arc<SleepBudget>) -> Result<()> {
+    let helpers = Object::new(ctx.clone())?;
+    helpers.set(
+        "reserve",
+        Func::from(move |ctx: Ctx<'_>, ms: f64| {
+            budget.reserve(milliseconds(ms)).map_err(|message| Exception::throw_range(&ctx, &message))
+        }),
+    )?;
+    helpers.set(
+        "wait",
+        Func::from(Async(|ms: f64| async move {
+            tokio::time::sleep(Duration::from_millis(milliseconds(ms))).await;
+            Ok::<(), rquickjs::Error>(())
+        })),
+    )?;
+    ctx.globals().set("__timers", helpers)?;
+    code_cache::evaluate_prelude(ctx, "timers.js", include_str!("js/timers.js"))?.finish::<()>()?;
+    code_cache::evaluate_prelude(ctx, "abort.js", include_str!("js/abort.js"))?.finish::<()>()?;
+    ctx.globals().remove("__timers")
+}
@@ -0,0 +1,1430 @@
+// TypeScript for `language: "typescript"`, run by erasing its types.
+//
+// Type-only syntax is replaced with spaces: annotations, interfaces, type aliases,
+// generics, `as` and `satisfies`, non-null assertions, access modifiers, overloads,
+// `declare` and `import type`. Line breaks are kept, so every remaining token stays
+// at its line and column and errors thrown by the code point at the TypeScript
+// source. Enums are the one construct with a value at run time; they become an
+// object built by a function on the same lines. Namespaces, parameter properties,
+// `import x = require()` and `export =` can't be erased and are refused, as TypeScript
+// does with `--erasableSyntaxOnly`. Types aren't checked.
+
+use crate::analyze::{find, is_identifier_byte, skip_number, skip_regex, skip_string, skip_template, REGEX_KEYWORDS};
+use crate::error::{ErrorKind, ExecError};
+use crate::js_error::JsError;
+use crate::modules::ModuleMap;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    JavaScript,
+    TypeScript,
+}
+
+// A construct the type stripper can't turn into JavaScript, with its 1-based position
+#[derive(Debug, PartialEq)]
+pub struct TsError {
+    pub message: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+// Reported like the engine's syntax errors, as a SyntaxError at the position
+impl From<TsError> for ExecError {
+    fn from(error: TsError) -> Self {
+        ExecError {
+            js_error: Some(Box::new(JsError {
+                name: Some("SyntaxError".to_string()),
+                message: error.message.clone(),
+                stack: None,
+                line: Some(error.line),
+                column: Some(error.column),
+                properties: None,
+                thrown: None,
+            })),
+            ..ExecError::new(ErrorKind::Syntax, format!("TypeScript error: {}", error.message))
+        }
+    }
+}
+
+pub fn strip_types(code: &str) -> Result<String> {
+    let mut stripper = Stripper {
+        code,
+        tokens: tokenize(code),
+        pos: 0,
+        frames: Vec::new(),
+        colon_was_ternary: false,
+        control_parens: Vec::new(),
+        edits: Vec::new(),
+    };
+    stripper.frame(Frame::Top)?;
+    Ok(stripper.output())
+}
+
+// Every module of the map, with errors naming the module
+pub fn strip_module_types(map: &ModuleMap) -> std::result::Result<ModuleMap, ExecError> {
+    let mut sources = std::collections::BTreeMap::new();
+    for (name, source) in &map.sources {
+        let stripped = strip_types(source).map_err(|error| {
+            let message = format!("TypeScript error in module '{}': {}", name, error.message);
+            ExecError {
+                message,
+                ..ExecError::from(error)
+            }
+        })?;
+        sources.insert(name.clone(), stripped);
+    }
+    Ok(ModuleMap {
+        entry: map.entry.clone(),
+        sources,
+    })
+}
+
+// Operators spelled with more than one character, longest first. `>` is always a
+// token of its own (except in `>=`), so nested type arguments close one at a time.
+const PUNCTUATORS: &[&str] = &[
+    "...", "===", "!==", "**=", "<<=", "&&=", "||=", "??=", "=>", "==", "!=", "<=", ">=", "&&", "||",
+    "??", "?.", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "**", "<<",
+];
+
+// Keywords that start or continue an expression, so a value doesn't end at them
+const OPERATOR_KEYWORDS: &[&str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void", "throw", "case", "do",
+    "else", "yield", "await", "extends", "as", "satisfies", "if", "while", "for", "with", "switch",
+    "export", "import", "default", "let", "const", "var", "function", "class",
+];
+
+// Modifiers of constructor parameters that would declare a field
+const PARAMETER_PROPERTY_MODIFIERS: &[&str] = &["public", "private", "protected", "readonly", "override"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Identifier,
+    Punct,
+    String,
+    Number,
+    Regex,
+    // A template literal without substitutions, and the parts of one with them
+    Template,
+    TemplateHead,
+    TemplateMiddle,
+    TemplateTail,
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    start: usize,
+    end: usize,
+    // Whether a line break separates the token from the previous one
+    newline_before: bool,
+}
+
+// Splits the code into tokens, dropping comments and whitespace
+fn tokenize(code: &str) -> Vec<Token<'_>> {
+    let bytes = code.as_bytes();
+    let mut tokens: Vec<Token<'_>> = Vec::new();
+    // Brace depths at which a `}` continues a template literal
+    let mut templates: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    let mut newline = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let byte = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        let kind = match byte {
+            b'\n' | b'\r' => {
+                newline = true;
+                i += 1;
+                continue;
+            }
+            _ if byte.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'/' if next == Some(b'/') => {
+                i = find(bytes, i, b"\n").unwrap_or(bytes.len());
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = find(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+                newline |= bytes[start..i].contains(&b'\n');
+                continue;
+            }
+            b'\'' | b'"' => {
+                i = skip_string(bytes, i + 1, byte);
+                Kind::String
+            }
+            b'`' => {
+                let (end, substitution) = skip_template(bytes, i + 1);
+                i = end;
+                if substitution {
+                    depth += 1;
+                    templates.push(depth);
+                    Kind::TemplateHead
+                } else {
+                    Kind::Template
+                }
+            }
+            b'}' if templates.last() == Some(&depth) => {
+                templates.pop();
+                depth -= 1;
+                let (end, substitution) = skip_template(bytes, i + 1);
+                i = end;
+                if substitution {
+                    depth += 1;
+                    templates.push(depth);
+                    Kind::TemplateMiddle
+                } else {
+                    Kind::TemplateTail
+                }
+            }
+            b'/' if regex_allowed(tokens.last()) => {
+                i = skip_regex(bytes, i + 1);
+                Kind::Regex
+            }
+            b'0'..=b'9' => {
+                i = skip_number(bytes, i);
+                Kind::Number
+            }
+            b'.' if next.is_some_and(|next| next.is_ascii_digit()) => {
+                i = skip_number(bytes, i);
+                Kind::Number
+            }
+            // Private names are names too
+            b'#' if next.is_some_and(is_identifier_byte) => {
+                i += 1;
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Identifier
+            }
+            _ if is_identifier_byte(byte) => {
+                while i < bytes.len() && is_identifier_byte(bytes[i]) {
+                    i += 1;
+                }
+                Kind::Identifier
+            }
+            _ => {
+                let rest = &bytes[i..];
+                let optional_chain_digit = rest.starts_with(b"?.") && rest.get(2).is_some_and(u8::is_ascii_digit);
+                i += PUNCTUATORS
+                    .iter()
+                    .find(|punctuator| rest.starts_with(punctuator.as_bytes()) && !optional_chain_digit)
+                    .map_or(1, |punctuator| punctuator.len());
+                match byte {
+                    b'{' => depth += 1,
+                    b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                Kind::Punct
+            }
+        };
+        // Multi-byte characters are identifier bytes, so tokens end at char boundaries
+        tokens.push(Token {
+            kind,
+            text: &code[start..i.min(bytes.len())],
+            start,
+            end: i.min(bytes.len()),
+            newline_before: newline,
+        });
+        newline = false;
+    }
+    tokens
+}
+
+fn regex_allowed(previous: Option<&Token<'_>>) -> bool {
+    match previous {
+        None => true,
+        Some(token) => match token.kind {
+            Kind::Punct => !matches!(token.text, ")" | "]" | "}"),
+            Kind::Identifier => REGEX_KEYWORDS.contains(&token.text),
+            Kind::TemplateHead | Kind::TemplateMiddle => true,
+            _ => false,
+        },
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Frame {
+    Top,
+    Block,
+    Object,
+    Class,
+    Params,
+    Paren,
+    Bracket,
+}
+
+impl Frame {
+    fn closer(self) -> &'static str {
+        match self {
+            Frame::Top => "",
+            Frame::Block | Frame::Object | Frame::Class => "}",
+            Frame::Params | Frame::Paren => ")",
+            Frame::Bracket => "]",
+        }
+    }
+}
+
+struct FrameState {
+    kind: Frame,
+    // `?` of conditional expressions whose `:` hasn't been seen yet
+    ternaries: usize,
+}
+
+struct Stripper<'a> {
+    code: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    frames: Vec<FrameState>,
+    // Whether the last `:` was one of a conditional expression
+    colon_was_ternary: bool,
+    // Indices of the `)` closing the condition of `if`, `while` and the like, which
+    // don't end a value
+    control_parens: Vec<usize>,
+    // Byte ranges to blank out, or to replace with the given code
+    edits: Vec<(usize, usize, Option<String>)>,
+}
+
+type Result<T> = std::result::Result<T, TsError>;
+
+impl<'a> Stripper<'a> {
+    fn text(&self, i: usize) -> &'a str {
+        self.tokens.get(i).map_or("", |token| token.text)
+    }
+
+    fn kind(&self, i: usize) -> Option<Kind> {
+        self.tokens.get(i).map(|token| token.kind)
+    }
+
+    // Whether token `i` is the punctuator or name `text`, not a string spelling it
+    fn is(&self, i: usize, text: &str) -> bool {
+        self.tokens
+            .get(i)
+            .is_some_and(|token| matches!(token.kind, Kind::Punct | Kind::Identifier) && token.text == text)
+    }
+
+    fn is_name(&self, i: usize) -> bool {
+        self.kind(i) == Some(Kind::Identifier)
+    }
+
+    // A property name, or the `[` of a computed one
+    fn is_key(&self, i: usize) -> bool {
+        self.is(i, "[") || matches!(self.kind(i), Some(Kind::Identifier | Kind::String | Kind::Number))
+    }
+
+    fn newline_before(&self, i: usize) -> bool {
+        self.tokens.get(i).is_some_and(|token| token.newline_before)
+    }
+
+    fn error(&self, i: usize, message: &str) -> TsError {
+        let at = self.tokens.get(i).map_or(self.code.len(), |token| token.start);
+        let before = &self.code[..at];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        TsError {
+            message: message.to_string(),
+            line: before.matches('\n').count() as u32 + 1,
+            column: before[line_start..].chars().count() as u32 + 1,
+        }
+    }
+
+    // Blanks out tokens `from..to` and whatever is between them
+    fn blank(&mut self, from: usize, to: usize) {
+        if from < to && to <= self.tokens.len() {
+            self.edits.push((self.tokens[from].start, self.tokens[to - 1].end, None));
+        }
+    }
+
+    fn output(mut self) -> String {
+        self.edits.sort_by_key(|&(start, end, _)| (start, std::cmp::Reverse(end)));
+        let mut output = String::with_capacity(self.code.len());
+        let mut cursor = 0;
+        for (start, end, replacement) in self.edits {
+            // Edits within a blanked out range
+            if end <= cursor {
+                continue;
+            }
+            let start = start.max(cursor);
+            output.push_str(&self.code[cursor..start]);
+            match replacement {
+                Some(code) => output.push_str(&code),
+                None => output.extend(
+                    self.code[start..end]
+                        .chars()
+                        .map(|c| if c == '\n' || c == '\r' { c } else { ' ' }),
+                ),
+            }
+            cursor = end;
+        }
+        output.push_str(&self.code[cursor..]);
+        output
+    }
+
+    // The index of the bracket closing the one at `i`
+    fn matching(&self, i: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        for (j, token) in self.tokens.iter().enumerate().skip(i) {
+            if token.kind != Kind::Punct {
+                continue;
+            }
+            match token.text {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0 {
+                        return Some(j);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // Whether token `i` ends a value, so that `!`, `as` or `<` after it are TypeScript
+    fn ends_value(&self, i: usize) -> bool {
+        let Some(token) = self.tokens.get(i) else {
+            return false;
+        };
+        match token.kind {
+            Kind::Identifier => !OPERATOR_KEYWORDS.contains(&token.text),
+            Kind::Punct => matches!(token.text, ")" | "]" | "}") && !self.control_parens.contains(&i),
+            Kind::TemplateHead | Kind::TemplateMiddle => false,
+            _ => true,
+        }
+    }
+
+    fn at_statement_start(&self) -> bool {
+        self.pos == 0
+            || self.newline_before(self.pos)
+            || (self.kind(self.pos - 1) == Some(Kind::Punct) && matches!(self.text(self.pos - 1), ";" | "{" | "}"))
+    }
+
+    fn frame(&mut self, kind: Frame) -> Result<()> {
+        self.frames.push(FrameState { kind, ternaries: 0 });
+        let result = match kind {
+            Frame::Class => self.class_body(),
+            Frame::Object => self.object(),
+            Frame::Params => self.params(),
+            _ => self.statements(kind),
+        };
+        self.frames.pop();
+        result
+    }
+
+    // Consumes the closer of the current frame at `pos`, if it is there. Returns
+    // whether the frame ends; it does at a closer of an enclosing frame too.
+    fn closes(&mut self, kind: Frame) -> bool {
+        let Some(token) = self.tokens.get(self.pos) else {
+            return true;
+        };
+        if token.kind != Kind::Punct || !matches!(token.text, ")" | "]" | "}") {
+            return false;
+        }
+        if token.text == kind.closer() {
+            self.pos += 1;
+            return true;
+        }
+        // Unbalanced code is left for the engine to report
+        if kind == Frame::Top {
+            self.pos += 1;
+            return false;
+        }
+        true
+    }
+
+    fn statements(&mut self, kind: Frame) -> Result<()> {
+        loop {
+            if self.closes(kind) {
+                return Ok(());
+            }
+            if matches!(kind, Frame::Top | Frame::Block) && self.at_statement_start() && self.statement()? {
+                continue;
+            }
+            self.token()?;
+        }
+    }
+
+    // TypeScript statements, at the start of a statement. Returns whether it was one.
+    fn statement(&mut self) -> Result<bool> {
+        let i = self.pos;
+        let same_line_name = self.is_name(i + 1) && !self.newline_before(i + 1);
+        match self.text(i) {
+            "interface" if same_line_name => {
+                let end = self.interface_end(i)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "type" if same_line_name && (self.is(i + 2, "=") || self.is(i + 2, "<")) => {
+                let end = self.alias_end(i)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "declare" if same_line_name => {
+                let end = self.declare_end(i)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "namespace" | "module"
+                if (same_line_name || self.kind(i + 1) == Some(Kind::String)) && !self.newline_before(i + 1) =>
+            {
+                return Err(self.error(
+                    i,
+                    "Namespaces aren't supported, only TypeScript whose types can be erased; \
+                     use an object or a module",
+                ));
+            }
+            "abstract" if self.is(i + 1, "class") => {
+                self.blank(i, i + 1);
+                self.pos += 1;
+            }
+            "import" if !self.is(i + 1, "(") && !self.is(i + 1, ".") => self.import()?,
+            "export" => self.export()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // One token of an expression or statement, and the brackets it opens
+    fn token(&mut self) -> Result<()> {
+        let i = self.pos;
+        let previous_ends_value = i > 0 && self.ends_value(i - 1);
+        let token = &self.tokens[i];
+        let (kind, text, newline_before) = (token.kind, token.text, token.newline_before);
+        match (kind, text) {
+            (Kind::Punct, "(") => self.paren(),
+            (Kind::Punct, "{") => {
+                let object = self.brace_is_object();
+                self.pos += 1;
+                self.frame(if object { Frame::Object } else { Frame::Block })
+            }
+            (Kind::Punct, "[") => {
+                self.pos += 1;
+                self.frame(Frame::Bracket)
+            }
+            (Kind::Punct, "<") => {
+                self.angle(previous_ends_value);
+                Ok(())
+            }
+            (Kind::Punct, "?") => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.ternaries += 1;
+                }
+                self.pos += 1;
+                Ok(())
+            }
+            (Kind::Punct, ":") => {
+                let frame = self.frames.last_mut().filter(|frame| frame.ternaries > 0);
+                self.colon_was_ternary = frame.is_some();
+                if let Some(frame) = frame {
+                    frame.ternaries -= 1;
+                }
+                self.pos += 1;
+                Ok(())
+            }
+            // Non-null assertion
+            (Kind::Punct, "!") if previous_ends_value && !newline_before => {
+                self.blank(i, i + 1);
+                self.pos += 1;
+                Ok(())
+            }
+            (Kind::Identifier, "as" | "satisfies") if previous_ends_value => {
+                // `as const`, and types of any kind
+                let end = self.skip_type(i + 1).ok_or_else(|| self.error(i + 1, "Type expected"))?;
+                self.blank(i, end);
+                self.pos = end;
+                Ok(())
+            }
+            (Kind::Identifier, "function") => self.function(i),
+            (Kind::Identifier, "class") => self.class(),
+            (Kind::Identifier, "enum") if self.is_name(i + 1) && self.is(i + 2, "{") => self.enumeration(i, i),
+            (Kind::Identifier, "const") if self.is(i + 1, "enum") && self.is_name(i + 2) => self.enumeration(i, i + 1),
+            (Kind::Identifier, "const" | "var") => self.declaration(),
+            (Kind::Identifier, "let") if self.is_name(i + 1) || self.is(i + 1, "{") || self.is(i + 1, "[") => {
+                self.declaration()
+            }
+            (Kind::Identifier, "if" | "while" | "for" | "with" | "switch") if self.is(i + 1, "(") => {
+                if let Some(close) = self.matching(i + 1) {
+                    self.control_parens.push(close);
+                }
+                self.pos += 1;
+                Ok(())
+            }
+            _ => {
+                self.pos += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn brace_is_object(&self) -> bool {
+        let Some(previous) = self.pos.checked_sub(1).and_then(|i| self.tokens.get(i)) else {
+            return false;
+        };
+        match previous.kind {
+            Kind::Punct => match previous.text {
+                ")" | "]" | "}" | ";" | "=>" => false,
+                ":" => {
+                    self.colon_was_ternary
+                        || !matches!(self.frames.last().map(|frame| frame.kind), Some(Frame::Top | Frame::Block))
+                }
+                _ => true,
+            },
+            Kind::Identifier => matches!(
+                previous.text,
+                "return" | "typeof" | "instanceof" | "in" | "of" | "new" | "delete" | "void" | "throw"
+                    | "case" | "yield" | "await" | "default"
+            ),
+            Kind::TemplateHead | Kind::TemplateMiddle => true,
+            _ => false,
+        }
+    }
+
+    // `(` of a call, a grouping, or the parameters of an arrow function
+    fn paren(&mut self) -> Result<()> {
+        let i = self.pos;
+        let close = self.matching(i);
+        let mut return_type = None;
+        let arrow = close.is_some_and(|close| {
+            if self.is(close + 1, "=>") {
+                return true;
+            }
+            if self.is(close + 1, ":") {
+                if let Some(end) = self.skip_type(close + 2).filter(|&end| self.is(end, "=>")) {
+                    return_type = Some((close + 1, end));
+                    return true;
+                }
+            }
+            false
+        });
+        self.pos += 1;
+        if !arrow {
+            return self.frame(Frame::Paren);
+        }
+        self.frame(Frame::Params)?;
+        if let Some((colon, end)) = return_type {
+            self.blank(colon, end);
+            self.pos = end;
+        }
+        Ok(())
+    }
+
+    // `<` of type arguments, of type parameters of an arrow function, or a comparison
+    fn angle(&mut self, previous_ends_value: bool) {
+        let i = self.pos;
+        let end = if previous_ends_value {
+            // Type arguments of a call or a tagged template: `f<T>()`, `new Map<K, V>()`
+            self.skip_type_arguments(i).filter(|&end| {
+                self.is(end, "(") || matches!(self.kind(end), Some(Kind::Template | Kind::TemplateHead))
+            })
+        } else {
+            // `<T>(value: T) => value`
+            self.skip_type_arguments(i).filter(|&end| self.is(end, "("))
+        };
+        match end {
+            Some(end) => {
+                self.blank(i, end);
+                self.pos = end;
+            }
+            None => self.pos += 1,
+        }
+    }
+
+    // The type annotation at `pos` if it starts with a `:`, which is blanked out
+    fn annotation(&mut self) -> Result<()> {
+        if self.is(self.pos, ":") {
+            let end = self.skip_type(self.pos + 1).ok_or_else(|| self.error(self.pos + 1, "Type expected"))?;
+            self.blank(self.pos, end);
+            self.pos = end;
+        }
+        Ok(())
+    }
+
+    fn type_parameters(&mut self) -> Result<()> {
+        if self.is(self.pos, "<") {
+            let end = self.skip_type_arguments(self.pos).ok_or_else(|| self.error(self.pos, "'>' expected"))?;
+            self.blank(self.pos, end);
+            self.pos = end;
+        }
+        Ok(())
+    }
+
+    // A binding name or pattern
+    fn binding(&mut self) -> Result<bool> {
+        if self.is_name(self.pos) {
+            self.pos += 1;
+        } else if self.is(self.pos, "{") {
+            self.pos += 1;
+            self.frame(Frame::Object)?;
+        } else if self.is(self.pos, "[") {
+            self.pos += 1;
+            self.frame(Frame::Bracket)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    // Parameters after the `(`, up to the `)`
+    fn params(&mut self) -> Result<()> {
+        loop {
+            if self.closes(Frame::Params) {
+                return Ok(());
+            }
+            let i = self.pos;
+            if self.is(i, ",") || self.is(i, "...") {
+                self.pos += 1;
+                continue;
+            }
+            if PARAMETER_PROPERTY_MODIFIERS.contains(&self.text(i))
+                && (self.is_name(i + 1) || self.is(i + 1, "{") || self.is(i + 1, "["))
+            {
+                return Err(self.error(
+                    i,
+                    "Parameter properties aren't supported, only TypeScript whose types can be erased; \
+                     assign the field in the constructor",
+                ));
+            }
+            // `this` parameters only type `this`
+            if self.is(i, "this") && self.is(i + 1, ":") {
+                let end = self.skip_type(i + 2).ok_or_else(|| self.error(i + 2, "Type expected"))?;
+                let end = if self.is(end, ",") { end + 1 } else { end };
+                self.blank(i, end);
+                self.pos = end;
+                continue;
+            }
+            if !self.binding()? {
+                self.token()?;
+                continue;
+            }
+            if self.is(self.pos, "?") {
+                self.blank(self.pos, self.pos + 1);
+                self.pos += 1;
+            }
+            self.annotation()?;
+            if self.is(self.pos, "=") {
+                self.pos += 1;
+                self.expression(&[","])?;
+            }
+        }
+    }
+
+    // An expression up to one of `stops` or the closer of the frame. A line break
+    // ends it when the next line starts a new statement.
+    fn expression(&mut self, stops: &[&str]) -> Result<()> {
+        loop {
+            let Some(token) = self.tokens.get(self.pos) else {
+                return Ok(());
+            };
+            if token.kind == Kind::Punct && (matches!(token.text, ")" | "]" | "}" | ";") || stops.contains(&token.text)) {
+                return Ok(());
+            }
+            if token.newline_before
+                && self.ends_value(self.pos - 1)
+                && token.kind == Kind::Identifier
+                && !matches!(token.text, "instanceof" | "in" | "as" | "satisfies")
+            {
+                return Ok(());
+            }
+            self.token()?;
+        }
+    }
+
+    // `let`, `const` or `var` and their bindings
+    fn declaration(&mut self) -> Result<()> {
+        self.pos += 1;
+        loop {
+            if !self.binding()? {
+                return Ok(());
+            }
+            // Definite assignment assertion
+            if self.is(self.pos, "!") {
+                self.blank(self.pos, self.pos + 1);
+                self.pos += 1;
+            }
+            self.annotation()?;
+            if self.is(self.pos, "=") {
+                self.pos += 1;
+                self.expression(&[","])?;
+            }
+            if !self.is(self.pos, ",") {
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+    }
+
+    // A function at `function`; declarations without a body are overloads
+    fn function(&mut self, i: usize) -> Result<()> {
+        self.pos = i + 1;
+        if self.is(self.pos, "*") {
+            self.pos += 1;
+        }
+        if self.is_name(self.pos) {
+            self.pos += 1;
+        }
+        self.type_parameters()?;
+        if !self.is(self.pos, "(") {
+            return Ok(());
+        }
+        self.pos += 1;
+        self.frame(Frame::Params)?;
+        self.annotation()?;
+        if self.is(self.pos, "{") {
+            self.pos += 1;
+            return self.frame(Frame::Block);
+        }
+        // An overload signature, with the `export` and `async` before it
+        let mut start = i;
+        while start > 0 && matches!(self.text(start - 1), "export" | "default" | "async") {
+            start -= 1;
+        }
+        let end = if self.is(self.pos, ";") { self.pos + 1 } else { self.pos };
+        self.blank(start, end);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn class(&mut self) -> Result<()> {
+        self.pos += 1;
+        if self.is_name(self.pos) && !matches!(self.text(self.pos), "extends" | "implements") {
+            self.pos += 1;
+        }
+        self.type_parameters()?;
+        if self.is(self.pos, "extends") {
+            self.pos += 1;
+            while !self.is(self.pos, "{") && !self.is(self.pos, "implements") && self.pos < self.tokens.len() {
+                // `extends Base<T>`
+                if self.is(self.pos, "<") && self.ends_value(self.pos - 1) {
+                    if let Some(end) = self.skip_type_arguments(self.pos) {
+                        self.blank(self.pos, end);
+                        self.pos = end;
+                        continue;
+                    }
+                }
+                self.token()?;
+            }
+        }
+        if self.is(self.pos, "implements") {
+            let mut end = self.pos + 1;
+            loop {
+                end = self.skip_type(end).ok_or_else(|| self.error(end, "Type expected"))?;
+                if !self.is(end, ",") {
+                    break;
+                }
+                end += 1;
+            }
+            self.blank(self.pos, end);
+            self.pos = end;
+        }
+        if self.is(self.pos, "{") {
+            self.pos += 1;
+            self.frame(Frame::Class)?;
+        }
+        Ok(())
+    }
+
+    fn class_body(&mut self) -> Result<()> {
+        loop {
+            if self.closes(Frame::Class) {
+                return Ok(());
+            }
+            if self.is(self.pos, ";") {
+                self.pos += 1;
+                continue;
+            }
+            self.member()?;
+        }
+    }
+
+    fn member(&mut self) -> Result<()> {
+        let start = self.pos;
+        // `declare` fields and `abstract` members have no JavaScript at all
+        let mut type_only = false;
+        loop {
+            let i = self.pos;
+            let modifies = !matches!(self.text(i + 1), "(" | "=" | ";" | ":" | "?" | "!" | "<" | "}" | "");
+            match self.text(i) {
+                "public" | "private" | "protected" | "readonly" | "override" if modifies => self.blank(i, i + 1),
+                "declare" | "abstract" if modifies => type_only = true,
+                "static" | "async" | "get" | "set" | "accessor" | "*" if modifies => {}
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let i = self.pos;
+        // Static blocks
+        if self.is(i, "{") {
+            self.pos += 1;
+            return self.frame(Frame::Block);
+        }
+        // Index signatures like `[key: string]: number`
+        if self.is(i, "[") && self.is_name(i + 1) && self.is(i + 2, ":") {
+            let close = self.matching(i).ok_or_else(|| self.error(i, "']' expected"))?;
+            let mut end = close + 1;
+            if self.is(end, ":") {
+                end = self.skip_type(end + 1).ok_or_else(|| self.error(end + 1, "Type expected"))?;
+            }
+            if self.is(end, ";") {
+                end += 1;
+            }
+            self.blank(start, end);
+            self.pos = end;
+            return Ok(());
+        }
+        if self.is(i, "[") {
+            self.pos += 1;
+            self.frame(Frame::Bracket)?;
+        } else if matches!(self.kind(i), Some(Kind::Identifier | Kind::String | Kind::Number)) {
+            self.pos += 1;
+        } else {
+            return self.token();
+        }
+        if self.is(self.pos, "?") || self.is(self.pos, "!") {
+            self.blank(self.pos, self.pos + 1);
+            self.pos += 1;
+        }
+        self.type_parameters()?;
+        if self.is(self.pos, "(") {
+            self.pos += 1;
+            self.frame(Frame::Params)?;
+            self.annotation()?;
+            if self.is(self.pos, "{") {
+                self.pos += 1;
+                self.frame(Frame::Block)?;
+                if type_only {
+                    self.blank(start, self.pos);
+                }
+                return Ok(());
+            }
+            // An overload signature or an abstract method
+            let end = if self.is(self.pos, ";") { self.pos + 1 } else { self.pos };
+            self.blank(start, end);
+            self.pos = end;
+            return Ok(());
+        }
+        self.annotation()?;
+        if self.is(self.pos, "=") {
+            self.pos += 1;
+            self.expression(&[])?;
+        }
+        if type_only {
+            let end = if self.is(self.pos, ";") { self.pos + 1 } else { self.pos };
+            self.blank(start, end);
+            self.pos = end;
+        }
+        Ok(())
+    }
+
+    // An object literal or pattern after the `{`, with its methods
+    fn object(&mut self) -> Result<()> {
+        let mut key = true;
+        loop {
+            if self.closes(Frame::Object) {
+                return Ok(());
+            }
+            let i = self.pos;
+            if self.is(i, ",") {
+                key = true;
+                self.pos += 1;
+                continue;
+            }
+            if !key {
+                self.token()?;
+                continue;
+            }
+            key = false;
+            let mut name = i;
+            while matches!(self.text(name), "async" | "get" | "set" | "*") && (self.is_key(name + 1) || self.is(name + 1, "*")) {
+                name += 1;
+            }
+            self.pos = name;
+            if self.is(name, "[") {
+                self.pos += 1;
+                self.frame(Frame::Bracket)?;
+            } else if matches!(self.kind(name), Some(Kind::Identifier | Kind::String | Kind::Number)) {
+                self.pos += 1;
+            } else {
+                continue;
+            }
+            let generic = self.is(self.pos, "<") && self.skip_type_arguments(self.pos).is_some_and(|end| self.is(end, "("));
+            if self.is(self.pos, "(") || generic {
+                self.type_parameters()?;
+                self.pos += 1;
+                self.frame(Frame::Params)?;
+                self.annotation()?;
+                if self.is(self.pos, "{") {
+                    self.pos += 1;
+                    self.frame(Frame::Block)?;
+                }
+            }
+        }
+    }
+
+    // An enum at `keyword`, or at the `const` before it at `start`
+    fn enumeration(&mut self, start: usize, keyword: usize) -> Result<()> {
+        let name = self.text(keyword + 1);
+        let open = keyword + 2;
+        if !self.is(open, "{") {
+            return Err(self.error(open, "'{' expected"));
+        }
+        let close = self.matching(open).ok_or_else(|| self.error(open, "'}' expected"))?;
+        let mut code = format!(
+            "var {name}; (function (__enum) {{ let __last = -1; const __member = (key, value) => (__enum[key] = value, \
+             typeof value === \"number\" && (__enum[value] = key), __last = value);"
+        );
+        // Line breaks of the source go where they were
+        let mut anchor = self.tokens[start].end;
+        let mut i = open + 1;
+        while i < close {
+            let member = &self.tokens[i];
+            let member_name = match member.kind {
+                Kind::Identifier => Some(member.text),
+                Kind::String => None,
+                _ => return Err(self.error(i, "Enum member expected")),
+            };
+            code.push_str(&"\n".repeat(self.code[anchor..member.start].matches('\n').count()));
+            let (value, end) = if self.is(i + 1, "=") {
+                let mut end = i + 2;
+                while end < close && !self.is(end, ",") {
+                    end = match self.text(end) {
+                        "(" | "[" | "{" => self.matching(end).map_or(close, |matching| matching + 1),
+                        _ => end + 1,
+                    };
+                }
+                if end == i + 2 {
+                    return Err(self.error(end, "Expression expected"));
+                }
+                (self.code[self.tokens[i + 2].start..self.tokens[end - 1].end].to_string(), end)
+            } else {
+                ("__last + 1".to_string(), i + 1)
+            };
+            let key = match member_name {
+                Some(member_name) => format!("\"{member_name}\""),
+                None => member.text.to_string(),
+            };
+            // Later members can refer to earlier ones by name
+            match member_name {
+                Some(member_name) => code.push_str(&format!(" const {member_name} = __member({key}, {value});")),
+                None => code.push_str(&format!(" __member({key}, {value});")),
+            }
+            anchor = self.tokens[end - 1].end;
+            if end < close && !self.is(end, ",") {
+                return Err(self.error(end, "',' expected"));
+            }
+            i = end + 1;
+        }
+        code.push_str(&"\n".repeat(self.code[anchor..self.tokens[close].start].matches('\n').count()));
+        code.push_str(&format!(" }})({name} || ({name} = {{}}));"));
+        self.edits.push((self.tokens[start].start, self.tokens[close].end, Some(code)));
+        self.pos = close + 1;
+        Ok(())
+    }
+
+    fn import(&mut self) -> Result<()> {
+        let i = self.pos;
+        if self.is_name(i + 1) && self.is(i + 2, "=") {
+            return Err(self.error(
+                i,
+                "`import … = require()` isn't supported, only TypeScript whose types can be erased; \
+                     use `import … from`",
+            ));
+        }
+        // The whole import is of types
+        if self.is(i + 1, "type") && !self.is(i + 2, ",") && !self.is(i + 2, "from") {
+            let end = self.module_specifier_end(i + 2);
+            self.blank(i, end);
+            self.pos = end;
+            return Ok(());
+        }
+        self.pos += 1;
+        while self.pos < self.tokens.len() && self.kind(self.pos) != Some(Kind::String) {
+            if self.is(self.pos, "{") {
+                self.specifiers();
+            } else {
+                self.pos += 1;
+            }
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn export(&mut self) -> Result<()> {
+        let i = self.pos;
+        let next = i + 1;
+        match self.text(next) {
+            "type" if self.is(next + 1, "{") || self.is(next + 1, "*") => {
+                let end = self.module_specifier_end(next + 1);
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "type" if self.is_name(next + 1) => {
+                let end = self.alias_end(next)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "interface" => {
+                let end = self.interface_end(next)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "default" if self.is(next + 1, "interface") => {
+                let end = self.interface_end(next + 1)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "declare" => {
+                let end = self.declare_end(next)?;
+                self.blank(i, end);
+                self.pos = end;
+            }
+            // `export as namespace Library`
+            "as" if self.is(next + 1, "namespace") => {
+                let end = if self.is(next + 3, ";") { next + 4 } else { next + 3 };
+                self.blank(i, end);
+                self.pos = end;
+            }
+            "=" | "import" => {
+                return Err(self.error(
+                    i,
+                    "`export =` and `export import` aren't supported, only TypeScript whose types can be erased; \
+                     use `export default`",
+                ))
+            }
+            "namespace" | "module" => {
+                self.pos = next;
+                self.statement()?;
+            }
+            "abstract" if self.is(next + 1, "class") => {
+                self.blank(next, next + 1);
+                self.pos = next + 1;
+            }
+            "{" => {
+                self.pos = next;
+                self.specifiers();
+            }
+            _ => self.pos = next,
+        }
+        Ok(())
+    }
+
+    // Import or export specifiers from the `{`, blanking out the ones of types
+    fn specifiers(&mut self) {
+        self.pos += 1;
+        while self.pos < self.tokens.len() {
+            let i = self.pos;
+            if self.is(i, "}") {
+                self.pos += 1;
+                return;
+            }
+            if self.is(i, "type") && (self.is_name(i + 1) || self.kind(i + 1) == Some(Kind::String)) {
+                let mut end = i + 2;
+                if self.is(end, "as") {
+                    end += 2;
+                }
+                if self.is(end, ",") {
+                    end += 1;
+                }
+                self.blank(i, end);
+                self.pos = end;
+                continue;
+            }
+            self.pos += 1;
+        }
+    }
+
+    // The token after the module specifier of an import or export starting before
+    // `i`, and its `;`, or after the `}` of a local export
+    fn module_specifier_end(&self, i: usize) -> usize {
+        let mut end = i;
+        while end < self.tokens.len() && self.kind(end) != Some(Kind::String) && !self.is(end, "}") {
+            end += 1;
+        }
+        if self.is(end, "}") && self.is(end + 1, "from") {
+            end += 2;
+        }
+        end += 1;
+        if self.is(end, ";") {
+            end += 1;
+        }
+        end.min(self.tokens.len())
+    }
+
+    // The token after the interface at `i`
+    fn interface_end(&self, i: usize) -> Result<usize> {
+        let mut end = i + 2;
+        if self.is(end, "<") {
+            end = self.skip_type_arguments(end).ok_or_else(|| self.error(end, "'>' expected"))?;
+        }
+        if self.is(end, "extends") {
+            end += 1;
+            loop {
+                end = self.skip_type(end).ok_or_else(|| self.error(end, "Type expected"))?;
+                if !self.is(end, ",") {
+                    break;
+                }
+                end += 1;
+            }
+        }
+        if !self.is(end, "{") {
+            return Err(self.error(end, "'{' expected"));
+        }
+        self.matching(end).map(|close| close + 1).ok_or_else(|| self.error(end, "'}' expected"))
+    }
+
+    // The token after the type alias at `i`, including its `;`
+    fn alias_end(&self, i: usize) -> Result<usize> {
+        let mut end = i + 2;
+        if self.is(end, "<") {
+            end = self.skip_type_arguments(end).ok_or_else(|| self.error(end, "'>' expected"))?;
+        }
+        if !self.is(end, "=") {
+            return Err(self.error(end, "'=' expected"));
+        }
+        end = self.skip_type(end + 1).ok_or_else(|| self.error(end + 1, "Type expected"))?;
+        Ok(if self.is(end, ";") { end + 1 } else { end })
+    }
+
+    // The token after the ambient declaration at `declare`
+    fn declare_end(&self, i: usize) -> Result<usize> {
+        let mut end = i + 1;
+        match self.text(end) {
+            "const" | "let" | "var" if !self.is(end + 1, "enum") => loop {
+                end += 1;
+                if !self.is_name(end) {
+                    return Err(self.error(end, "Identifier expected"));
+                }
+                end += 1;
+                if self.is(end, ":") {
+                    end = self.skip_type(end + 1).ok_or_else(|| self.error(end + 1, "Type expected"))?;
+                }
+                if self.is(end, "=") && self.tokens.get(end + 1).is_some() {
+                    end += 2;
+                }
+                if !self.is(end, ",") {
+                    break;
+                }
+            },
+            "function" => {
+                end += 1;
+                while self.is_name(end) || self.is(end, "*") {
+                    end += 1;
+                }
+                if self.is(end, "<") {
+                    end = self.skip_type_arguments(end).ok_or_else(|| self.error(end, "'>' expected"))?;
+                }
+                if !self.is(end, "(") {
+                    return Err(self.error(end, "'(' expected"));
+                }
+                end = self.matching(end).ok_or_else(|| self.error(end, "')' expected"))? + 1;
+                if self.is(end, ":") {
+                    end = self.skip_type(end + 1).ok_or_else(|| self.error(end + 1, "Type expected"))?;
+                }
+            }
+            "type" => return self.alias_end(end),
+            "interface" => return self.interface_end(end),
+            "class" | "abstract" | "enum" | "const" | "module" | "namespace" | "global" => {
+                while !self.is(end, "{") && !self.is(end, ";") {
+                    if end >= self.tokens.len() {
+                        return Err(self.error(end, "'{' expected"));
+                    }
+                    end = match self.text(end) {
+                        "(" | "[" => self.matching(end).ok_or_else(|| self.error(end, "')' expected"))? + 1,
+                        _ => end + 1,
+                    };
+                }
+                if self.is(end, "{") {
+                    return self.matching(end).map(|close| close + 1).ok_or_else(|| self.error(end, "'}' expected"));
+                }
+            }
+            _ => return Err(self.error(end, "Declaration expected")),
+        }
+        Ok(if self.is(end, ";") { end + 1 } else { end })
+    }
+
+    // The token after the type starting at `i`, or None when there is no type there
+    fn skip_type(&self, i: usize) -> Option<usize> {
+        let mut end = i;
+        if self.is(end, "|") || self.is(end, "&") {
+            end += 1;
+        }
+        end = self.skip_union(end)?;
+        // Conditional types
+        if self.is(end, "extends") {
+            let check = self.skip_union(end + 1)?;
+            if self.is(check, "?") {
+                let when_true = self.skip_type(check + 1)?;
+                if self.is(when_true, ":") {
+                    return self.skip_type(when_true + 1);
+                }
+                return None;
+            }
+        }
+        Some(end)
+    }
+
+    fn skip_union(&self, i: usize) -> Option<usize> {
+        let mut end = self.skip_type_operand(i)?;
+        while self.is(end, "|") || self.is(end, "&") {
+            end = self.skip_type_operand(end + 1)?;
+        }
+        Some(end)
+    }
+
+    fn skip_type_operand(&self, i: usize) -> Option<usize> {
+        match self.text(i) {
+            "keyof" | "unique" | "readonly" if self.tokens.get(i + 1).is_some_and(|next| !next.newline_before) => {
+                return self.skip_type_operand(i + 1);
+            }
+            "infer" if self.is_name(i + 1) => return Some(i + 2),
+            // Assertion signatures like `asserts value is string`
+            "asserts" if self.is_name(i + 1) && !self.newline_before(i + 1) => {
+                return if self.is(i + 2, "is") { self.skip_type(i + 3) } else { Some(i + 2) };
+            }
+            "typeof" => {
+                let mut end = i + 1;
+                // `typeof import("module")`
+                if self.is(end, "import") && self.is(end + 1, "(") {
+                    return self.matching(end + 1).map(|close| close + 1);
+                }
+                end += 1;
+                while self.is(end, ".") {
+                    end += 2;
+                }
+                return Some(end);
+            }
+            "abstract" if self.is(i + 1, "new") => return self.skip_type_operand(i + 1),
+            // Constructor types
+            "new" => {
+                let mut end = i + 1;
+                if self.is(end, "<") {
+                    end = self.skip_type_arguments(end)?;
+                }
+                if !self.is(end, "(") {
+                    return None;
+                }
+                end = self.matching(end)? + 1;
+                return if self.is(end, "=>") { self.skip_type(end + 1) } else { None };
+            }
+            _ => {}
+        }
+        // Type predicates like `value is string`
+        if (self.is_name(i) || self.is(i, "this")) && self.is(i + 1, "is") && !self.newline_before(i + 1) {
+            return self.skip_type(i + 2);
+        }
+        let mut end = self.skip_primary_type(i)?;
+        // Array and indexed access types
+        while self.is(end, "[") && !self.newline_before(end) {
+            end = if self.is(end + 1, "]") {
+                end + 2
+            } else {
+                let index = self.skip_type(end + 1)?;
+                if !self.is(index, "]") {
+                    return None;
+                }
+                index + 1
+            };
+        }
+        Some(end)
+    }
+
+    fn skip_primary_type(&self, i: usize) -> Option<usize> {
+        let token = self.tokens.get(i)?;
+        match token.kind {
+            Kind::String | Kind::Number | Kind::Template => Some(i + 1),
+            // Template literal types
+            Kind::TemplateHead => {
+                let mut depth = 0;
+                for (j, token) in self.tokens.iter().enumerate().skip(i) {
+                    match token.kind {
+                        Kind::TemplateHead => depth += 1,
+                        Kind::TemplateTail => {
+                            depth -= 1;
+                            if depth == 0 {
+                                return Some(j + 1);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }
+            Kind::Identifier => {
+                if matches!(token.text, "extends" | "as" | "satisfies" | "in" | "instanceof" | "implements" | "is") {
+                    return None;
+                }
+                let mut end = i + 1;
+                while self.is(end, ".") && self.is_name(end + 1) {
+                    end += 2;
+                }
+                if self.is(end, "<") && !self.newline_before(end) {
+                    end = self.skip_type_arguments(end)?;
+                }
+                Some(end)
+            }
+            Kind::Punct => match token.text {
+                // Function types, and parenthesized types
+                "(" => {
+                    let close = self.matching(i)?;
+                    if self.is(close + 1, "=>") {
+                        return self.skip_type(close + 2);
+                    }
+                    let end = self.skip_type(i + 1)?;
+                    if end == close {
+                        Some(close + 1)
+                    } else {
+                        None
+                    }
+                }
+                "<" => {
+                    let end = self.skip_type_arguments(i)?;
+                    if !self.is(end, "(") {
+                        return None;
+                    }
+                    let close = self.matching(end)?;
+                    if self.is(close + 1, "=>") {
+                        self.skip_type(close + 2)
+                    } else {
+                        None
+                    }
+                }
+                // Object and tuple types
+                "{" | "[" => self.matching(i).map(|close| close + 1),
+                "-" if self.kind(i + 1) == Some(Kind::Number) => Some(i + 2),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The token after the type arguments or parameters at the `<` at `i`
+    fn skip_type_arguments(&self, i: usize) -> Option<usize> {
+        let mut end = i + 1;
+        loop {
+            if self.is(end, ">") {
+                return Some(end + 1);
+            }
+            // Variance and `const` modifiers of type parameters
+            while matches!(self.text(end), "in" | "out" | "const") && self.is_name(end + 1) {
+                end += 1;
+            }
+            end = self.skip_type(end)?;
+            if self.is(end, "extends") {
+                end = self.skip_type(end + 1)?;
+            }
+            if self.is(end, "=") {
+                end = self.skip_type(end + 1)?;
+            }
+            if self.is(end, ",") {
+                end += 1;
+            } else if !self.is(end, ">") {
+                return None;
+            }
+        }
+    }
+}
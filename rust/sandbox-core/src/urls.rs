@@ -0,0 +1,91 @@
+// The `URL` and `URLSearchParams` globals.
+//
+// The classes are defined in js/url.js; parsing, the WHATWG setters and the
+// application/x-www-form-urlencoded rules come from the `url` crate, through the
+// `__url` helpers installed here. A URL is kept as its components, reparsed from
+// `href` on every change.
+
+use rquickjs::function::{Func, Opt};
+use rquickjs::{Coerced, Ctx, Exception, FromJs, Object, Result, Value};
+use url::{form_urlencoded, quirks, Url};
+
+use crate::code_cache;
+
+fn components<'js>(ctx: &Ctx<'js>, url: &Url) -> Result<Object<'js>> {
+    let object = Object::new(ctx.clone())?;
+    object.set("href", quirks::href(url))?;
+    object.set("origin", quirks::origin(url))?;
+    object.set("protocol", quirks::protocol(url))?;
+    object.set("username", quirks::username(url))?;
+    object.set("password", quirks::password(url))?;
+    object.set("host", quirks::host(url))?;
+    object.set("hostname", quirks::hostname(url))?;
+    object.set("port", quirks::port(url))?;
+    object.set("pathname", quirks::pathname(url))?;
+    object.set("search", quirks::search(url))?;
+    object.set("hash", quirks::hash(url))?;
+    Ok(object)
+}
+
+fn invalid_url(ctx: &Ctx<'_>, input: &str) -> rquickjs::Error {
+    Exception::throw_type(ctx, &format!("Invalid URL: '{}'", input))
+}
+
+// `new URL(input, base)`, as components
+fn parse<'js>(ctx: Ctx<'js>, input: Coerced<String>, base: Opt<Value<'js>>) -> Result<Object<'js>> {
+    let base = match base.0 {
+        Some(base) if !base.is_undefined() => {
+            let base = Coerced::<String>::from_js(&ctx, base)?.0;
+            Some(Url::parse(&base).map_err(|_| invalid_url(&ctx, &base))?)
+        }
+        _ => None,
+    };
+    let url = Url::options().base_url(base.as_ref()).parse(&input).map_err(|_| invalid_url(&ctx, &input))?;
+    components(&ctx, &url)
+}
+
+// The components after setting one of them. As in browsers, values a component
+// can't take leave the URL unchanged, except for `href`, which throws.
+fn set<'js>(ctx: Ctx<'js>, href: String, component: String, value: Coerced<String>) -> Result<Object<'js>> {
+    let mut url = Url::parse(&href).map_err(|_| invalid_url(&ctx, &href))?;
+    let value = value.0.as_str();
+    match component.as_str() {
+        "href" => quirks::set_href(&mut url, value).map_err(|_| invalid_url(&ctx, value))?,
+        "protocol" => quirks::set_protocol(&mut url, value).unwrap_or(()),
+        "username" => quirks::set_username(&mut url, value).unwrap_or(()),
+        "password" => quirks::set_password(&mut url, value).unwrap_or(()),
+        "host" => quirks::set_host(&mut url, value).unwrap_or(()),
+        "hostname" => quirks::set_hostname(&mut url, value).unwrap_or(()),
+        "port" => quirks::set_port(&mut url, value).unwrap_or(()),
+        "pathname" => quirks::set_pathname(&mut url, value),
+        "search" => quirks::set_search(&mut url, value),
+        "hash" => quirks::set_hash(&mut url, value),
+        _ => return Err(Exception::throw_type(&ctx, &format!("Unknown URL component '{}'", component))),
+    }
+    components(&ctx, &url)
+}
+
+// A query string, without its leading `?`, as [name, value] pairs
+fn parse_search(query: Coerced<String>) -> Vec<Vec<String>> {
+    form_urlencoded::parse(query.0.as_bytes()).map(|(name, value)| vec![name.into_owned(), value.into_owned()]).collect()
+}
+
+fn serialize_search(pairs: Vec<Vec<String>>) -> String {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for pair in &pairs {
+        if let [name, value] = pair.as_slice() {
+            serializer.append_pair(name, value);
+        }
+    }
+    serializer.finish()
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    let helpers = Object::new(ctx.clone())?;
+    helpers.set("parse", Func::from(parse))?;
+    helpers.set("set", Func::from(set))?;
+    helpers.set("parseSearch", Func::from(parse_search))?;
+    helpers.set("serializeSearch", Func::from(serialize_search))?;
+    ctx.globals().set("__url", helpers)?;
+    code_cache::evaluate_prelude(ctx, "url.js", include_str!("js/url.js"))?.finish::<()>()
+}
@@ -0,0 +1,104 @@
+// Syntax checking without execution, for POST /validate.
+//
+// The code is compiled the way /execute compiles it, but in a throwaway runtime with
+// none of the sandbox globals installed, and never run. Host function usage is a
+// plain textual scan, so calls inside comments and strings are counted too.
+
+use crate::code_cache;
+use crate::js_error::{JsError, USER_CODE_FILENAME};
+use crate::modules::SandboxModules;
+use rquickjs::{Context, Ctx, Module, Runtime};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Globals of the sandbox that reach outside of it
+const HOST_FUNCTIONS: &[&str] = &["httpRequest", "httpGetStream", "headersGet", "graphql", "fetch"];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Validation {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ValidationError>,
+    // Number of calls to each host function that is called at all
+    pub host_functions: BTreeMap<&'static str, usize>,
+}
+
+#[derive(Serialize)]
+pub struct ValidationError {
+    pub message: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+pub fn validate(code: &str, module: bool, max_stack_bytes: usize) -> Result<Validation, String> {
+    let errors = compile_errors(code, module, max_stack_bytes)?;
+    Ok(Validation {
+        valid: errors.is_empty(),
+        errors,
+        host_functions: HOST_FUNCTIONS
+            .iter()
+            .map(|name| (*name, count_calls(code, name)))
+            .filter(|(_, count)| *count > 0)
+            .collect(),
+    })
+}
+
+// Compiles the code in a throwaway runtime, empty when it compiled
+pub(crate) fn compile_errors(code: &str, module: bool, max_stack_bytes: usize) -> Result<Vec<ValidationError>, String> {
+    let runtime = Runtime::new().map_err(|e| format!("Runtime error: {}", e))?;
+    runtime.set_max_stack_size(max_stack_bytes);
+    let modules = SandboxModules::default();
+    runtime.set_loader(modules.clone(), modules.clone());
+    let context = Context::full(&runtime).map_err(|e| format!("Context error: {}", e))?;
+
+    Ok(context.with(|ctx| {
+        let compiled = if module {
+            Module::declare(ctx.clone(), USER_CODE_FILENAME, code).map(drop)
+        } else {
+            code_cache::compile_script(&ctx, code).map(drop)
+        };
+        match compiled {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![compile_error(&ctx, e, &modules)],
+        }
+    }))
+}
+
+fn compile_error(ctx: &Ctx<'_>, error: rquickjs::Error, modules: &SandboxModules) -> ValidationError {
+    if let Some(message) = modules.unresolved_message() {
+        let _ = ctx.catch();
+        return ValidationError {
+            message,
+            line: None,
+            column: None,
+        };
+    }
+    match error {
+        rquickjs::Error::Exception => {
+            let js_error = JsError::catch(ctx);
+            ValidationError {
+                message: js_error.describe(),
+                line: js_error.line,
+                column: js_error.column,
+            }
+        }
+        e => ValidationError {
+            message: e.to_string(),
+            line: None,
+            column: None,
+        },
+    }
+}
+
+// Occurrences of `name` as a whole identifier followed by an opening parenthesis
+fn count_calls(code: &str, name: &str) -> usize {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    code.match_indices(name)
+        .filter(|(start, _)| {
+            let before = code[..*start].chars().next_back();
+            let after = code[start + name.len()..].trim_start();
+            !before.is_some_and(is_identifier) && after.starts_with('(')
+        })
+        .count()
+}
@@ -0,0 +1,407 @@
+// XML parsing into plain objects, installed as the `parseXml` global.
+//
+// The document becomes `{ rootName: element }`. An element with neither attributes
+// nor child elements becomes its text; any other element becomes an object with
+// its attributes under `@attr`, its child elements under their names (an array
+// when a name repeats) and its text, if it has any besides whitespace, under
+// `#text`. CDATA sections count as text, and comments, processing instructions
+// and the doctype are skipped. Names keep their namespace prefixes unless
+// `namespaces: "strip"`, which also drops the `xmlns` attributes. Entities other
+// than the five predefined ones and character references are errors, as are
+// mismatched tags, reported with their line and column as a SyntaxError.
+
+use rquickjs::function::{Func, Opt};
+use rquickjs::{Coerced, Ctx, Exception, Object, Result, Value};
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+type Parsed<T> = std::result::Result<T, String>;
+
+impl<'a> Parser<'a> {
+    // Where the parser is, as in "line 3, column 7"
+    fn error(&self, message: &str) -> String {
+        let before = &self.text[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        format!("Invalid XML at line {}, column {}: {}", line, column, message)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Parsed<()> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{}'", token))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let trimmed = self.rest().trim_start_matches([' ', '\t', '\r', '\n']);
+        let skipped = self.rest().len() - trimmed.len();
+        self.position += skipped;
+        skipped > 0
+    }
+
+    // Everything up to the terminator, which is consumed too
+    fn until(&mut self, terminator: &str, what: &str) -> Parsed<&'a str> {
+        match self.rest().find(terminator) {
+            Some(end) => {
+                let skipped = &self.rest()[..end];
+                self.position += end + terminator.len();
+                Ok(skipped)
+            }
+            None => Err(self.error(&format!("unterminated {}", what))),
+        }
+    }
+
+    fn document(mut self) -> Parsed<Element> {
+        self.eat("\u{feff}");
+        self.misc()?;
+        if self.rest().starts_with("<!DOCTYPE") {
+            self.doctype()?;
+            self.misc()?;
+        }
+        if !self.rest().starts_with('<') {
+            return Err(self.error("expected the root element"));
+        }
+        let root = self.element()?;
+        self.misc()?;
+        match self.rest().is_empty() {
+            true => Ok(root),
+            false => Err(self.error("unexpected content after the root element")),
+        }
+    }
+
+    // Whitespace, comments and processing instructions outside of the root element
+    fn misc(&mut self) -> Parsed<()> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<!--") {
+                self.until("-->", "comment")?;
+            } else if self.eat("<?") {
+                self.until("?>", "processing instruction")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // Skipped, internal subset and all
+    fn doctype(&mut self) -> Parsed<()> {
+        let start = self.position;
+        let mut depth = 0;
+        for (offset, c) in self.rest().char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth == 0 => {
+                    self.position += offset + 1;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        self.position = start;
+        Err(self.error("unterminated doctype"))
+    }
+
+    fn name(&mut self) -> Parsed<String> {
+        let name: String = self
+            .rest()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.') || !c.is_ascii())
+            .collect();
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+            return Err(self.error("expected a name"));
+        }
+        self.position += name.len();
+        Ok(name)
+    }
+
+    fn element(&mut self) -> Parsed<Element> {
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        loop {
+            let spaced = self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(Element {
+                    name,
+                    attributes,
+                    children: Vec::new(),
+                });
+            }
+            if self.eat(">") {
+                break;
+            }
+            if !spaced {
+                return Err(self.error("expected whitespace, '>' or '/>'"));
+            }
+            let start = self.position;
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let value = self.attribute_value()?;
+            if attributes.iter().any(|(existing, _)| *existing == attribute) {
+                self.position = start;
+                return Err(self.error(&format!("duplicate attribute '{}'", attribute)));
+            }
+            attributes.push((attribute, value));
+        }
+
+        let mut children = Vec::new();
+        let mut text = String::new();
+        loop {
+            if self.rest().is_empty() {
+                return Err(self.error(&format!("expected '</{}>'", name)));
+            }
+            if self.rest().starts_with("</") {
+                let start = self.position;
+                self.position += 2;
+                let closing = self.name()?;
+                if closing != name {
+                    self.position = start;
+                    return Err(self.error(&format!("expected '</{}>', found '</{}>'", name, closing)));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                break;
+            }
+            if self.eat("<![CDATA[") {
+                text.push_str(self.until("]]>", "CDATA section")?);
+            } else if self.eat("<!--") {
+                self.until("-->", "comment")?;
+            } else if self.eat("<?") {
+                self.until("?>", "processing instruction")?;
+            } else if self.rest().starts_with('<') {
+                if !text.is_empty() {
+                    children.push(Node::Text(std::mem::take(&mut text)));
+                }
+                children.push(Node::Element(self.element()?));
+            } else {
+                let end = self.rest().find('<').unwrap_or(self.rest().len());
+                let raw = &self.rest()[..end];
+                text.push_str(&self.unescape(raw)?);
+                self.position += end;
+            }
+        }
+        if !text.is_empty() {
+            children.push(Node::Text(text));
+        }
+        Ok(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    fn attribute_value(&mut self) -> Parsed<String> {
+        let quote = match self.rest().chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err(self.error("expected a quoted attribute value")),
+        };
+        self.position += 1;
+        let Some(end) = self.rest().find(quote) else {
+            return Err(self.error("unterminated attribute value"));
+        };
+        let raw = &self.rest()[..end];
+        if let Some(offset) = raw.find('<') {
+            self.position += offset;
+            return Err(self.error("'<' in an attribute value"));
+        }
+        let value = self.unescape(raw)?;
+        self.position += end + 1;
+        Ok(value)
+    }
+
+    // Resolves entity and character references in text that starts at the position
+    fn unescape(&mut self, raw: &str) -> Parsed<String> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(start) = rest.find('&') {
+            out.push_str(&rest[..start]);
+            let reference = &rest[start + 1..];
+            let Some(end) = reference.find(';') else {
+                self.position += raw.len() - rest.len() + start;
+                return Err(self.error("unterminated entity reference"));
+            };
+            let entity = &reference[..end];
+            let resolved = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "apos" => Some('\''),
+                "quot" => Some('"'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(|code| code.ok())
+                    .and_then(char::from_u32),
+            };
+            match resolved {
+                Some(c) => out.push(c),
+                None => {
+                    self.position += raw.len() - rest.len() + start;
+                    return Err(self.error(&format!("unknown entity '&{};'", entity)));
+                }
+            }
+            rest = &reference[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+struct Conversion {
+    attribute_key: String,
+    text_key: String,
+    strip_namespaces: bool,
+}
+
+impl Conversion {
+    // Without options, or with `null`, everything is the default
+    fn from_options<'js>(ctx: &Ctx<'js>, options: &Opt<Value<'js>>) -> Result<Self> {
+        let options = match &options.0 {
+            Some(value) if value.is_null() || value.is_undefined() => None,
+            Some(value) => match value.as_object() {
+                Some(options) => Some(options),
+                None => return Err(Exception::throw_type(ctx, "parseXml options must be an object")),
+            },
+            None => None,
+        };
+        let field = |name: &str| -> Result<Option<String>> {
+            match options {
+                Some(options) => options.get(name),
+                None => Ok(None),
+            }
+        };
+        let strip_namespaces = match field("namespaces")?.as_deref() {
+            None | Some("preserve") => false,
+            Some("strip") => true,
+            Some(other) => {
+                return Err(Exception::throw_type(
+                    ctx,
+                    &format!("namespaces must be \"preserve\" or \"strip\", got \"{}\"", other),
+                ))
+            }
+        };
+        Ok(Conversion {
+            attribute_key: field("attributeKey")?.unwrap_or_else(|| "@attr".to_string()),
+            text_key: field("textKey")?.unwrap_or_else(|| "#text".to_string()),
+            strip_namespaces,
+        })
+    }
+
+    fn name<'n>(&self, name: &'n str) -> &'n str {
+        match self.strip_namespaces {
+            true => name.rsplit(':').next().unwrap_or(name),
+            false => name,
+        }
+    }
+
+    fn element<'js>(&self, ctx: &Ctx<'js>, element: &Element) -> Result<Value<'js>> {
+        let attributes: Vec<&(String, String)> = element
+            .attributes
+            .iter()
+            .filter(|(name, _)| !(self.strip_namespaces && (name == "xmlns" || name.starts_with("xmlns:"))))
+            .collect();
+        let text: String = element
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Text(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect();
+        let elements: Vec<&Element> = element
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Element(element) => Some(element),
+                Node::Text(_) => None,
+            })
+            .collect();
+        if attributes.is_empty() && elements.is_empty() {
+            return rquickjs::String::from_str(ctx.clone(), text.trim()).map(|text| text.into_value());
+        }
+
+        let object = Object::new(ctx.clone())?;
+        if !attributes.is_empty() {
+            let values = Object::new(ctx.clone())?;
+            for (name, value) in attributes {
+                values.set(self.name(name), value.as_str())?;
+            }
+            object.set(self.attribute_key.as_str(), values)?;
+        }
+        // Grouped by name, in the order the names first appear
+        let mut groups: Vec<(&str, Vec<&Element>)> = Vec::new();
+        for child in elements {
+            let name = self.name(&child.name);
+            match groups.iter_mut().find(|(existing, _)| *existing == name) {
+                Some((_, group)) => group.push(child),
+                None => groups.push((name, vec![child])),
+            }
+        }
+        for (name, group) in groups {
+            let value = match group.as_slice() {
+                [single] => self.element(ctx, single)?,
+                many => {
+                    let array = rquickjs::Array::new(ctx.clone())?;
+                    for (index, child) in many.iter().enumerate() {
+                        array.set(index, self.element(ctx, child)?)?;
+                    }
+                    array.into_value()
+                }
+            };
+            object.set(name, value)?;
+        }
+        if !text.trim().is_empty() {
+            object.set(self.text_key.as_str(), text.trim())?;
+        }
+        Ok(object.into_value())
+    }
+}
+
+fn parse_xml<'js>(ctx: Ctx<'js>, text: Coerced<String>, options: Opt<Value<'js>>) -> Result<Value<'js>> {
+    let conversion = Conversion::from_options(&ctx, &options)?;
+    let root = Parser {
+        text: &text,
+        position: 0,
+    }
+    .document()
+    .map_err(|message| Exception::throw_syntax(&ctx, &message))?;
+    let document = Object::new(ctx.clone())?;
+    document.set(conversion.name(&root.name), conversion.element(&ctx, &root)?)?;
+    Ok(document.into_value())
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    ctx.globals().set("parseXml", Func::from(parse_xml))
+}
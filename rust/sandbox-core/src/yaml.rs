@@ -0,0 +1,507 @@
+// YAML to and from plain values, installed as the `parseYaml`, `parseYamlAll` and
+// `toYaml` globals.
+//
+// Documents are read with the YAML 1.2 core schema: plain `null`, `~` and empty
+// scalars are null, `true`/`false` booleans and decimal, `0o` octal, `0x` hex and
+// float scalars (with `.inf` and `.nan`) numbers; everything else, and every quoted
+// or block scalar, is a string. The `!!str`, `!!int`, `!!float`, `!!bool` and
+// `!!null` tags force a type, and scalars with any other tag (`!!timestamp`,
+// `!!binary`, `!Ref`, ...) are their text; tags on collections are ignored. Keys are
+// strings, `<<` merges mappings into the one it is in, and aliases are copies of
+// their anchor. To keep a few lines of aliases from expanding into billions of
+// nodes, aliases may copy at most MAX_ALIAS_NODES nodes per call and nothing may be
+// nested deeper than MAX_DEPTH; past that a RangeError is thrown. Malformed YAML
+// throws a SyntaxError; both carry the `line` and `column` of the problem.
+//
+// `toYaml` writes block style. Strings are quoted, JSON style, whenever they would
+// read back as something else.
+
+use rquickjs::function::Func;
+use rquickjs::object::Property;
+use rquickjs::{Coerced, Ctx, Exception, Object, Result, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser, Tag};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+const MAX_ALIAS_NODES: usize = 1_000_000;
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone)]
+enum Node {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Seq(Vec<Node>),
+    // In document order, without duplicate keys
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    // Itself and everything in it
+    fn count(&self) -> usize {
+        match self {
+            Node::Seq(items) => 1 + items.iter().map(Node::count).sum::<usize>(),
+            Node::Map(entries) => 1 + entries.iter().map(|(_, value)| value.count()).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Node::Seq(items) => 1 + items.iter().map(Node::depth).max().unwrap_or(0),
+            Node::Map(entries) => 1 + entries.iter().map(|(_, value)| value.depth()).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    // A mapping key, as JavaScript would turn the value into a string
+    fn key(self) -> std::result::Result<String, &'static str> {
+        match self {
+            Node::Null => Ok("null".to_string()),
+            Node::Bool(value) => Ok(value.to_string()),
+            Node::Number(value) => Ok(number(value)),
+            Node::String(value) => Ok(value),
+            Node::Seq(_) | Node::Map(_) => Err("mapping keys must be scalars"),
+        }
+    }
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn decimal_digits(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+// `[-+]? ( \. [0-9]+ | [0-9]+ ( \. [0-9]* )? ) ( [eE] [-+]? [0-9]+ )?`
+fn is_float(text: &str) -> bool {
+    let text = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(at) => (&text[..at], Some(&text[at + 1..])),
+        None => (text, None),
+    };
+    let mantissa_ok = match mantissa.split_once('.') {
+        Some(("", fraction)) => decimal_digits(fraction),
+        Some((whole, fraction)) => decimal_digits(whole) && (fraction.is_empty() || decimal_digits(fraction)),
+        None => decimal_digits(mantissa),
+    };
+    let exponent_ok = exponent.is_none_or(|exponent| decimal_digits(exponent.strip_prefix(['-', '+']).unwrap_or(exponent)));
+    mantissa_ok && exponent_ok
+}
+
+// A plain scalar, by the core schema
+fn resolve(text: &str) -> Node {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Node::Null,
+        "true" | "True" | "TRUE" => return Node::Bool(true),
+        "false" | "False" | "FALSE" => return Node::Bool(false),
+        ".nan" | ".NaN" | ".NAN" => return Node::Number(f64::NAN),
+        _ => {}
+    }
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    if matches!(unsigned, ".inf" | ".Inf" | ".INF") {
+        return Node::Number(if text.starts_with('-') { f64::NEG_INFINITY } else { f64::INFINITY });
+    }
+    let radix = |digits: &str, radix: u32| {
+        let valid = !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix));
+        valid.then(|| u64::from_str_radix(digits, radix).ok()).flatten().map(|value| Node::Number(value as f64))
+    };
+    if let Some(node) = text.strip_prefix("0o").and_then(|digits| radix(digits, 8)) {
+        return node;
+    }
+    if let Some(node) = text.strip_prefix("0x").and_then(|digits| radix(digits, 16)) {
+        return node;
+    }
+    if is_float(text) {
+        if let Ok(value) = text.parse::<f64>() {
+            return Node::Number(value);
+        }
+    }
+    Node::String(text.to_string())
+}
+
+fn scalar(text: String, style: TScalarStyle, tag: Option<Tag>) -> std::result::Result<Node, String> {
+    let tag = match tag {
+        None if style == TScalarStyle::Plain => return Ok(resolve(&text)),
+        None => return Ok(Node::String(text)),
+        Some(tag) => tag,
+    };
+    if tag.handle != "tag:yaml.org,2002:" {
+        return Ok(Node::String(text));
+    }
+    let resolved = resolve(&text);
+    let matches = match tag.suffix.as_str() {
+        "null" => matches!(resolved, Node::Null),
+        "bool" => matches!(resolved, Node::Bool(_)),
+        "int" => matches!(resolved, Node::Number(value) if value.fract() == 0.0),
+        "float" => matches!(resolved, Node::Number(_)),
+        _ => return Ok(Node::String(text)),
+    };
+    match matches {
+        true => Ok(resolved),
+        false => Err(format!("{:?} isn't a valid !!{}", text, tag.suffix)),
+    }
+}
+
+enum Frame {
+    Seq {
+        anchor: usize,
+        items: Vec<Node>,
+    },
+    Map {
+        anchor: usize,
+        entries: Vec<(String, Node)>,
+        positions: HashMap<String, usize>,
+        // The values of `<<` keys, merged in once the mapping ends
+        merges: Vec<Node>,
+        key: Option<Key>,
+    },
+}
+
+enum Key {
+    Name(String),
+    Merge,
+}
+
+enum Failure {
+    Syntax(String, Marker),
+    Limit(String, Marker),
+}
+
+#[derive(Default)]
+struct Builder {
+    documents: Vec<Node>,
+    stack: Vec<Frame>,
+    anchors: HashMap<usize, Node>,
+    copied: usize,
+    // Where the second document starts, for parseYaml
+    second: Option<Marker>,
+    failure: Option<Failure>,
+}
+
+impl Builder {
+    fn event(&mut self, event: Event, mark: Marker) -> std::result::Result<(), Failure> {
+        let syntax = |message: String| Failure::Syntax(message, mark);
+        match event {
+            Event::DocumentStart if self.documents.len() == 1 && self.second.is_none() => self.second = Some(mark),
+            Event::Scalar(text, style, anchor, tag) => {
+                if let Some(Frame::Map { key: key @ None, .. }) = self.stack.last_mut() {
+                    if style == TScalarStyle::Plain && tag.is_none() && text == "<<" {
+                        *key = Some(Key::Merge);
+                        return Ok(());
+                    }
+                }
+                let node = scalar(text, style, tag).map_err(syntax)?;
+                if anchor > 0 {
+                    self.anchors.insert(anchor, node.clone());
+                }
+                self.insert(node, mark)?;
+            }
+            Event::SequenceStart(anchor, _) | Event::MappingStart(anchor, _) => {
+                if self.stack.len() >= MAX_DEPTH {
+                    return Err(Failure::Limit(format!("YAML nested deeper than {} levels", MAX_DEPTH), mark));
+                }
+                self.stack.push(match event {
+                    Event::SequenceStart(..) => Frame::Seq { anchor, items: Vec::new() },
+                    _ => Frame::Map {
+                        anchor,
+                        entries: Vec::new(),
+                        positions: HashMap::new(),
+                        merges: Vec::new(),
+                        key: None,
+                    },
+                });
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                let (anchor, node) = match self.stack.pop() {
+                    Some(Frame::Seq { anchor, items }) => (anchor, Node::Seq(items)),
+                    Some(Frame::Map {
+                        anchor,
+                        mut entries,
+                        mut positions,
+                        merges,
+                        ..
+                    }) => {
+                        // Keys of the mapping itself win, then earlier merged mappings
+                        for merged in merges {
+                            let mappings = match merged {
+                                Node::Seq(items) => items,
+                                node => vec![node],
+                            };
+                            for mapping in mappings {
+                                let Node::Map(merged) = mapping else {
+                                    return Err(syntax("<< must be given a mapping or a list of mappings".to_string()));
+                                };
+                                for (key, value) in merged {
+                                    if !positions.contains_key(&key) {
+                                        positions.insert(key.clone(), entries.len());
+                                        entries.push((key, value));
+                                    }
+                                }
+                            }
+                        }
+                        (anchor, Node::Map(entries))
+                    }
+                    None => return Ok(()),
+                };
+                if anchor > 0 {
+                    self.anchors.insert(anchor, node.clone());
+                }
+                self.insert(node, mark)?;
+            }
+            Event::Alias(anchor) => {
+                let node = self.anchors.get(&anchor).cloned().ok_or_else(|| syntax("unknown alias".to_string()))?;
+                self.copied += node.count();
+                if self.copied > MAX_ALIAS_NODES {
+                    return Err(Failure::Limit(format!("YAML aliases expand to more than {} nodes", MAX_ALIAS_NODES), mark));
+                }
+                if self.stack.len() + node.depth() > MAX_DEPTH {
+                    return Err(Failure::Limit(format!("YAML nested deeper than {} levels", MAX_DEPTH), mark));
+                }
+                self.insert(node, mark)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, node: Node, mark: Marker) -> std::result::Result<(), Failure> {
+        match self.stack.last_mut() {
+            None => self.documents.push(node),
+            Some(Frame::Seq { items, .. }) => items.push(node),
+            Some(Frame::Map {
+                entries,
+                positions,
+                merges,
+                key,
+                ..
+            }) => match key.take() {
+                None => *key = Some(Key::Name(node.key().map_err(|message| Failure::Syntax(message.to_string(), mark))?)),
+                Some(Key::Merge) => merges.push(node),
+                // A repeated key keeps its place and takes the last value
+                Some(Key::Name(name)) => match positions.get(&name) {
+                    Some(&at) => entries[at].1 = node,
+                    None => {
+                        positions.insert(name.clone(), entries.len());
+                        entries.push((name, node));
+                    }
+                },
+            },
+        }
+        Ok(())
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if self.failure.is_none() {
+            if let Err(failure) = self.event(event, mark) {
+                self.failure = Some(failure);
+            }
+        }
+    }
+}
+
+// A SyntaxError or RangeError with `line` and `column`, both from 1
+fn throw(ctx: &Ctx<'_>, failure: Failure) -> rquickjs::Error {
+    let (message, mark, syntax) = match failure {
+        Failure::Syntax(message, mark) => (message, mark, true),
+        Failure::Limit(message, mark) => (message, mark, false),
+    };
+    let (line, column) = (mark.line(), mark.col() + 1);
+    let message = match syntax {
+        true => format!("Invalid YAML at line {}, column {}: {}", line, column, message),
+        false => format!("{} at line {}, column {}", message, line, column),
+    };
+    let _ = match syntax {
+        true => Exception::throw_syntax(ctx, &message),
+        false => Exception::throw_range(ctx, &message),
+    };
+    let error = ctx.catch();
+    if let Some(object) = error.as_object() {
+        let _ = object.set("line", line);
+        let _ = object.set("column", column);
+    }
+    ctx.throw(error)
+}
+
+fn load(ctx: &Ctx<'_>, text: &str) -> Result<Builder> {
+    let mut builder = Builder::default();
+    let loaded = Parser::new_from_str(text).load(&mut builder, true);
+    if let Some(failure) = builder.failure.take() {
+        return Err(throw(ctx, failure));
+    }
+    if let Err(error) = loaded {
+        return Err(throw(ctx, Failure::Syntax(error.info().to_string(), *error.marker())));
+    }
+    Ok(builder)
+}
+
+fn to_js<'js>(ctx: &Ctx<'js>, node: Node) -> Result<Value<'js>> {
+    Ok(match node {
+        Node::Null => Value::new_null(ctx.clone()),
+        Node::Bool(value) => Value::new_bool(ctx.clone(), value),
+        Node::Number(value) => Value::new_number(ctx.clone(), value),
+        Node::String(value) => rquickjs::String::from_str(ctx.clone(), &value)?.into_value(),
+        Node::Seq(items) => {
+            let array = rquickjs::Array::new(ctx.clone())?;
+            for (index, item) in items.into_iter().enumerate() {
+                array.set(index, to_js(ctx, item)?)?;
+            }
+            array.into_value()
+        }
+        Node::Map(entries) => {
+            // Defined rather than set, so that a `__proto__` key is just a key
+            let object = Object::new(ctx.clone())?;
+            for (key, value) in entries {
+                object.prop(key, Property::from(to_js(ctx, value)?).writable().enumerable().configurable())?;
+            }
+            object.into_value()
+        }
+    })
+}
+
+// The only document, or null for none
+fn parse_yaml<'js>(ctx: Ctx<'js>, text: Coerced<String>) -> Result<Value<'js>> {
+    let mut builder = load(&ctx, &text)?;
+    if let Some(second) = builder.second {
+        let message = "expected a single document, found more; use parseYamlAll for streams".to_string();
+        return Err(throw(&ctx, Failure::Syntax(message, second)));
+    }
+    to_js(&ctx, builder.documents.pop().unwrap_or(Node::Null))
+}
+
+fn parse_yaml_all<'js>(ctx: Ctx<'js>, text: Coerced<String>) -> Result<Value<'js>> {
+    let builder = load(&ctx, &text)?;
+    to_js(&ctx, Node::Seq(builder.documents))
+}
+
+fn from_js<'js>(value: &Value<'js>) -> Result<Node> {
+    if let Some(array) = value.as_array() {
+        return array.iter::<Value>().map(|item| from_js(&item?)).collect::<Result<_>>().map(Node::Seq);
+    }
+    if let Some(object) = value.as_object() {
+        let entries = object.props::<String, Value>().map(|entry| {
+            let (key, value) = entry?;
+            Ok((key, from_js(&value)?))
+        });
+        return entries.collect::<Result<_>>().map(Node::Map);
+    }
+    Ok(if let Some(value) = value.as_bool() {
+        Node::Bool(value)
+    } else if let Some(value) = value.as_number() {
+        Node::Number(value)
+    } else if let Some(value) = value.as_string() {
+        Node::String(value.to_string()?)
+    } else {
+        Node::Null
+    })
+}
+
+// Whether a string has to be quoted to be read back as the same string
+fn needs_quotes(text: &str) -> bool {
+    let Some(first) = text.chars().next() else {
+        return true;
+    };
+    !matches!(resolve(text), Node::String(_))
+        || !(first.is_alphanumeric() || matches!(first, '_' | '/' | '$' | '(' | '^' | '+'))
+        || text.ends_with([' ', ':'])
+        || text.contains(": ")
+        || text.contains(" #")
+        || text.chars().any(|c| c.is_control())
+}
+
+fn write_scalar(out: &mut String, node: &Node) {
+    match node {
+        Node::Null => out.push_str("null"),
+        Node::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+        Node::Number(value) => out.push_str(&number(*value)),
+        Node::String(text) if needs_quotes(text) => out.push_str(&serde_json::to_string(text).unwrap_or_default()),
+        Node::String(text) => out.push_str(text),
+        Node::Seq(_) => out.push_str("[]"),
+        Node::Map(_) => out.push_str("{}"),
+    }
+}
+
+fn is_block(node: &Node) -> bool {
+    match node {
+        Node::Seq(items) => !items.is_empty(),
+        Node::Map(entries) => !entries.is_empty(),
+        _ => false,
+    }
+}
+
+// A non-empty collection at `indent`; `inline` when its first line goes after a `- `
+fn write_block(out: &mut String, node: &Node, indent: usize, mut inline: bool) {
+    let mut line_start = |out: &mut String| {
+        if !inline {
+            let _ = write!(out, "{:indent$}", "", indent = indent);
+        }
+        inline = false;
+    };
+    match node {
+        Node::Seq(items) => {
+            for item in items {
+                line_start(out);
+                out.push_str("- ");
+                if is_block(item) {
+                    write_block(out, item, indent + 2, true);
+                } else {
+                    write_scalar(out, item);
+                    out.push('\n');
+                }
+            }
+        }
+        Node::Map(entries) => {
+            for (key, value) in entries {
+                line_start(out);
+                write_scalar(out, &Node::String(key.clone()));
+                out.push(':');
+                if is_block(value) {
+                    out.push('\n');
+                    write_block(out, value, indent + 2, false);
+                } else {
+                    out.push(' ');
+                    write_scalar(out, value);
+                    out.push('\n');
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// As JSON.stringify would see the value, so `toJSON`, dates and dropped
+// `undefined`s work the same
+fn to_yaml<'js>(ctx: Ctx<'js>, value: Value<'js>) -> Result<String> {
+    let Some(json) = ctx.json_stringify(value)? else {
+        return Err(Exception::throw_type(&ctx, "toYaml can't serialize undefined or a function"));
+    };
+    let node = from_js(&ctx.json_parse(json.to_string()?)?)?;
+    let mut out = String::new();
+    if is_block(&node) {
+        write_block(&mut out, &node, 0, false);
+    } else {
+        write_scalar(&mut out, &node);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+pub fn install(ctx: &Ctx<'_>) -> Result<()> {
+    let globals = ctx.globals();
+    globals.set("parseYaml", Func::from(parse_yaml))?;
+    globals.set("parseYamlAll", Func::from(parse_yaml_all))?;
+    globals.set("toYaml", Func::from(to_yaml))
+}
@@ -0,0 +1,88 @@
+// ExecError: the kind and message every way of failing maps to.
+
+use serde_json::json;
+use std::time::Duration;
+
+use sandbox_core::{Config, ErrorKind, ExecError, Executor, Options};
+
+async fn fail(config: Config, code: &str, options: Options) -> ExecError {
+    let executor = Executor::new(&config).unwrap();
+    executor.run(code, &json!({}), options).await.err().unwrap()
+}
+
+async fn fail_with_defaults(code: &str) -> ExecError {
+    fail(Config::default(), code, Options::default()).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_code_that_doesnt_parse_to_syntax() {
+    let error = fail_with_defaults("let = ;").await;
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!(error.kind.error(), "SyntaxError");
+    assert_eq!(error.js_error.unwrap().name.as_deref(), Some("SyntaxError"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_exceptions_to_runtime_with_the_js_error() {
+    let error = fail_with_defaults("console.log('before'); throw new RangeError('too far')").await;
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert!(error.message.contains("RangeError: too far"), "{}", error.message);
+    let js_error = error.js_error.unwrap();
+    assert_eq!((js_error.name.as_deref(), js_error.message.as_str()), (Some("RangeError"), "too far"));
+    // What ran before the exception is still reported
+    assert_eq!(error.report.unwrap().logs[0].message, "before");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_rejected_promises_to_runtime() {
+    let error = fail_with_defaults("await sleep(1); throw new Error('later')").await;
+    assert_eq!(error.kind, ErrorKind::Runtime);
+    assert_eq!(error.js_error.unwrap().message, "later");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_timeouts_to_interrupted() {
+    let options = Options { timeout: Some(Duration::from_millis(100)), ..Options::default() };
+    let error = fail(Config::default(), "while (true) {}", options).await;
+    assert_eq!(error.kind, ErrorKind::Interrupted);
+    assert_eq!(error.message, "Execution exceeded the timeout of 100 ms");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_too_large_results() {
+    let options = Options { max_result_bytes: Some(16), ..Options::default() };
+    let error = fail(Config::default(), "'x'.repeat(100)", options).await;
+    assert_eq!(error.kind, ErrorKind::ResultTooLarge);
+    assert!(error.preview.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_requests_over_the_limit() {
+    // Blocked as a private address, without leaving the machine, but still counted
+    let options = Options { max_requests: Some(1), ..Options::default() };
+    let code = "await httpRequest('http://127.0.0.1:9/'); try { await httpRequest('http://127.0.0.1:9/') } catch {} 1";
+    let error = fail(Config::default(), code, options).await;
+    assert_eq!(error.kind, ErrorKind::RequestLimit, "{}", error.message);
+    assert_eq!(error.message, "Execution attempted 2 outbound requests, exceeding the max_requests limit of 1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_unknown_imports() {
+    let options = Options { module: true, ..Options::default() };
+    let error = fail(Config::default(), "import missing from 'nowhere'; export default missing;", options).await;
+    assert_eq!(error.kind, ErrorKind::Import);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps_allocations_over_the_memory_limit() {
+    let config = Config { js_max_memory_bytes: 8 * 1024 * 1024, ..Config::default() };
+    let error = fail(config, "const chunks = []; for (;;) chunks.push('x'.repeat(1 << 20) + chunks.length)", Options::default()).await;
+    assert_eq!(error.kind, ErrorKind::MemoryLimit);
+}
+
+#[test]
+fn displays_the_title_and_message() {
+    let error = ExecError::new(ErrorKind::Busy, "No execution slot became free".to_string());
+    assert_eq!(error.to_string(), "Server busy: No execution slot became free");
+    assert_eq!(ExecError::from("broken".to_string()).kind, ErrorKind::Internal);
+}
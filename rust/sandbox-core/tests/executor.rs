@@ -0,0 +1,127 @@
+// Executor: running code and reporting what it did, with no HTTP server involved.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sandbox_core::fetch::ErrorCode;
+use sandbox_core::{Config, Executor, FetchSession, HttpBackend, HttpResult, Options};
+
+// Answers every request in-process with the URL and method it was asked for
+#[derive(Default)]
+struct Echo {
+    urls: Mutex<Vec<String>>,
+}
+
+impl HttpBackend for Echo {
+    fn fetch<'a>(
+        &'a self,
+        _session: &'a FetchSession,
+        url: String,
+        options: Option<HashMap<String, Value>>,
+    ) -> Pin<Box<dyn Future<Output = HttpResult> + Send + 'a>> {
+        self.urls.lock().unwrap().push(url.clone());
+        let method = options.and_then(|options| options.get("method").cloned()).unwrap_or(json!("GET"));
+        let data = json!({ "url": url, "method": method });
+        Box::pin(std::future::ready(HttpResult {
+            ok: true,
+            status: 200,
+            status_text: "OK".to_string(),
+            text: data.to_string(),
+            data,
+            error_code: None,
+            ..HttpResult::failure(ErrorCode::Network, "", String::new())
+        }))
+    }
+}
+
+fn executor() -> Executor {
+    Executor::new(&Config::default()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_result_with_the_inputs() {
+    let execution = executor().execute("INPUTS.items.map((item) => item * 2)", &json!({ "items": [1, 2, 3] })).await.unwrap();
+    assert_eq!(execution.result, json!([2, 4, 6]));
+    assert_eq!(execution.report.http_request_count, 0);
+    assert!(execution.report.duration > Duration::ZERO);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_console_output() {
+    let execution = executor().execute("console.log('a', 1); console.warn({ b: 2 }); 'done'", &json!({})).await.unwrap();
+    assert_eq!(execution.result, "done");
+    let logs: Vec<_> = execution.report.logs.iter().map(|line| (line.level.as_str(), line.message.as_str())).collect();
+    assert_eq!(logs, [("log", "a 1"), ("warn", "{\"b\":2}")]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn settles_awaited_promises_and_timers() {
+    let code = "const wait = (ms, value) => new Promise((resolve) => setTimeout(() => resolve(value), ms));
+        const [a, b] = await Promise.all([wait(20, 'a'), sleep(10).then(() => 'b')]);
+        a + b";
+    assert_eq!(executor().execute(code, &json!({})).await.unwrap().result, "ab");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evaluates_modules() {
+    let options = Options { module: true, ..Options::default() };
+    let execution = executor().run("const double = (x) => x * 2; export default double(INPUTS.x);", &json!({ "x": 21 }), options).await;
+    assert_eq!(execution.unwrap().result, 42);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_requests_to_the_http_backend() {
+    let echo = Arc::new(Echo::default());
+    let executor = Executor::with_http_backend(&Config::default(), echo.clone()).unwrap();
+    let code = "const [a, b] = await Promise.all([
+            httpRequest('https://api.example/a'),
+            httpRequest('https://api.example/b', { method: 'POST', body: {} }),
+        ]);
+        [a.status, a.data.url, b.data.method]";
+    let execution = executor.execute(code, &json!({})).await.unwrap();
+    assert_eq!(execution.result, json!([200, "https://api.example/a", "POST"]));
+    assert_eq!(execution.report.http_request_count, 2);
+    let mut traced: Vec<_> = execution.report.http.iter().map(|trace| (trace.method.as_str(), trace.status)).collect();
+    traced.sort();
+    assert_eq!(traced, [("GET", Some(200)), ("POST", Some(200))]);
+    assert_eq!(echo.urls.lock().unwrap().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_executions_apart() {
+    let executor = executor();
+    executor.execute("globalThis.leaked = 1", &json!({})).await.unwrap();
+    assert_eq!(executor.execute("typeof leaked", &json!({})).await.unwrap().result, "undefined");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_code_cached_for_longer_than_the_clock_reaches() {
+    let executor = executor();
+    let options = || Options { cache_ttl: Some(Duration::from_secs(u64::MAX)), ..Options::default() };
+    let first = executor.run("Math.random()", &json!({}), options()).await.unwrap();
+    // Not kept, so it runs again
+    let second = executor.run("Math.random()", &json!({}), options()).await.unwrap();
+    assert!(!second.report.result_cache_hit);
+    assert_ne!(first.result, second.result);
+}
+
+#[test]
+fn refuses_configurations_it_cant_start_with() {
+    let path = std::env::temp_dir().join(format!("sandbox-core-state-{}.json", std::process::id()));
+    std::fs::write(&path, "not json").unwrap();
+    let cases = [
+        (Config { state_path: path.display().to_string(), ..Config::default() }, format!("Invalid state in {}", path.display())),
+        (Config { isolation_mode: "container".to_string(), ..Config::default() }, "Invalid ISOLATION_MODE".to_string()),
+    ];
+    for (config, expected) in cases {
+        let error = Executor::new(&config).err().expect("the configuration is refused");
+        assert!(error.starts_with(&expected), "{}", error);
+        let error = Executor::with_http_backend(&config, Arc::new(Echo::default())).err().unwrap();
+        assert!(error.starts_with(&expected), "{}", error);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,1103 @@
+// Executions as the API sees them, independent of the web framework.
+//
+// The request and response bodies of /execute and the endpoints built on it, the
+// checks that run before the code, and the orchestration from a request to its
+// response or to the status and body of its error. The HTTP handlers, jobs and
+// `exec` only do the transport around `execute`.
+
+use futures::{stream, StreamExt};
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use sandbox_core::admission::Priority;
+use sandbox_core::engine::Entrypoint;
+use sandbox_core::js_error::JsError;
+use sandbox_core::secrets::Secrets;
+use sandbox_core::metrics::{Outcome, METRICS};
+use sandbox_core::serialize::BigIntMode;
+use sandbox_core::typescript::Language;
+use sandbox_core::dry_run::PlannedRequest;
+use sandbox_core::fetch::{ErrorCode, HttpTrace};
+use sandbox_core::files::Files;
+use sandbox_core::mocks::{HttpMock, HttpMocks};
+use sandbox_core::modules::ModuleMap;
+use sandbox_core::recording::{DebugRequest, Recording, Replay};
+use sandbox_core::{ErrorKind, ExecError, Executor, Options, Report};
+
+use crate::audit::{self, AuditRecord, AuditRequest};
+use crate::quotas::ExceededQuota;
+use crate::request_id::RequestId;
+use crate::schema::{self, Violation};
+use crate::AppState;
+
+const MAX_STATE_NAMESPACE_LEN: usize = 128;
+const DEFAULT_ENTRY_MODULE: &str = "main";
+
+#[derive(Deserialize, Clone)]
+pub struct ExecuteRequest {
+    // Left out when the code is given as `modules`
+    pub code: Option<String>,
+    // Any JSON value, available to the code as INPUTS
+    #[serde(default = "empty_inputs")]
+    pub inputs: Value,
+    // Available to the code as the frozen SECRETS, and redacted from the response
+    #[serde(default)]
+    pub secrets: BTreeMap<String, String>,
+    // Include diagnostics such as unhandled rejections in successful responses, and
+    // a `debug` object with the httpRequest calls in every response
+    #[serde(default)]
+    pub debug: bool,
+    #[serde(default)]
+    pub limits: ExecutionLimits,
+    // Replaces EXECUTION_TIMEOUT_MS, up to MAX_EXEC_TIMEOUT_MS. Signed, so that a
+    // negative value is reported like an out of range one.
+    pub timeout_ms: Option<i64>,
+    // Include timing and request counts as `meta` in the response
+    #[serde(default)]
+    pub include_meta: bool,
+    // JSON Schema the inputs must satisfy before anything is executed
+    pub inputs_schema: Option<Value>,
+    // JSON Schema the result must satisfy
+    pub output_schema: Option<Value>,
+    // Evaluate the code as an ES module instead of a script
+    #[serde(default)]
+    pub module: bool,
+    // Stop the clock at the execution start time
+    #[serde(default)]
+    pub freeze_time: bool,
+    // Seed for Math.random, generated when absent
+    pub random_seed: Option<u32>,
+    // How BigInt values in the result are serialized
+    #[serde(default)]
+    pub bigint_mode: BigIntMode,
+    // Whose `state` the code gets, within the caller's API key when it has one
+    pub state_namespace: Option<String>,
+    // Hash of the caller's API key, set by the handler
+    #[serde(skip)]
+    pub state_owner: Option<String>,
+    // The tenant the caller claims to be, set by the handler and checked with the request
+    #[serde(skip)]
+    pub tenant: Option<String>,
+    // The files of a multipart body, set by the handler
+    #[serde(skip)]
+    pub files: Arc<Files>,
+    // Report the httpRequest calls instead of sending them
+    #[serde(default)]
+    pub dry_run: bool,
+    // Answer the httpRequest calls instead of the network
+    pub http_mocks: Option<Vec<HttpMock>>,
+    // False to run without network access; NETWORK_DISABLED turns it off for every request
+    #[serde(default = "network_allowed")]
+    pub allow_network: bool,
+    // Return the responses the httpRequest calls got as `httpTrace`
+    #[serde(default)]
+    pub record_http: bool,
+    // An earlier `httpTrace`, which answers the httpRequest calls instead of the network
+    pub replay_http: Option<Recording>,
+    // Serve the result of an earlier identical request, or keep this one's
+    pub cache: Option<ResultCacheOptions>,
+    // A function of the code to call once it was evaluated, whose return value is
+    // the result
+    pub entrypoint: Option<String>,
+    // Arguments of the entrypoint, `[INPUTS]` when absent
+    pub args: Option<Vec<Value>>,
+    // "typescript" to strip the code of its types before running it
+    #[serde(default)]
+    pub language: Language,
+    // Module sources by name, in place of `code`, evaluated as ES modules
+    pub modules: Option<BTreeMap<String, String>>,
+    // The module evaluated first, whose default export is the result; "main" when absent
+    pub entry_module: Option<String>,
+    // Which queue the execution waits in when all slots are busy
+    #[serde(default)]
+    pub priority: Priority,
+    // Stream the response as NDJSON, with `Accept: application/x-ndjson`
+    #[serde(default)]
+    pub stream_result: bool,
+    // Run without the PRELUDE_DIR scripts
+    #[serde(default)]
+    pub skip_prelude: bool,
+    // Keep the result as the engine's JSON text, for a streamed response; set by the handler
+    #[serde(skip)]
+    pub raw_result: bool,
+}
+
+impl ExecuteRequest {
+    // The code evaluated first: `code`, or the source of the entry module
+    pub fn entry_code(&self) -> &str {
+        match &self.modules {
+            Some(modules) => modules.get(self.entry_module()).map_or("", String::as_str),
+            None => self.code.as_deref().unwrap_or_default(),
+        }
+    }
+
+    fn entry_module(&self) -> &str {
+        self.entry_module.as_deref().unwrap_or(DEFAULT_ENTRY_MODULE)
+    }
+
+    // All of the code, as hashed, logged and limited by MAX_CODE_BYTES: `code`, or
+    // the modules as JSON
+    fn source(&self) -> Cow<'_, str> {
+        match &self.modules {
+            Some(modules) => Cow::Owned(serde_json::to_string(modules).unwrap_or_default()),
+            None => Cow::Borrowed(self.code.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultCacheOptions {
+    pub ttl_seconds: u64,
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub jobs: Vec<BatchJob>,
+}
+
+// An /execute request body with an id chosen by the caller
+#[derive(Deserialize)]
+pub struct BatchJob {
+    pub id: Value,
+    #[serde(flatten)]
+    pub request: ExecuteRequest,
+}
+
+// An /execute request body with `input_sets` in place of `inputs`
+#[derive(Deserialize)]
+pub struct MapRequest {
+    pub input_sets: Vec<Value>,
+    #[serde(flatten)]
+    pub options: serde_json::Map<String, Value>,
+}
+
+// Per-request limits, which can only tighten the server-wide ones. Asking for more
+// than the server allows is refused, except for `timeout_ms`, which is shortened
+#[derive(Deserialize, Default, Clone)]
+pub struct ExecutionLimits {
+    #[serde(alias = "max_http_requests")]
+    pub max_requests: Option<u32>,
+    pub max_result_bytes: Option<usize>,
+    pub max_fetch_body_bytes: Option<usize>,
+    pub memory_bytes: Option<usize>,
+    pub timeout_ms: Option<u64>,
+    pub cpu_ms: Option<u64>,
+    // Can only turn DISABLE_DYNAMIC_EVAL on
+    #[serde(default)]
+    pub disable_dynamic_eval: bool,
+}
+
+#[derive(Serialize)]
+pub struct ExecuteResponse {
+    pub result: Value,
+    // In place of `result` with `raw_result`, unless the result had to be parsed
+    #[serde(skip)]
+    pub result_json: Option<String>,
+    #[serde(rename = "unhandledRejections", skip_serializing_if = "Vec::is_empty")]
+    pub unhandled_rejections: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ExecutionMeta>,
+    // The httpRequest calls of a dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests: Option<Vec<PlannedRequest>>,
+    #[serde(rename = "dryRun", skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    // Why the code of a dry run stopped, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+    #[serde(rename = "httpTrace", skip_serializing_if = "Option::is_none")]
+    pub http_trace: Option<Recording>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugInfo>,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub id: Value,
+    #[serde(flatten)]
+    pub execution: ExecutionResult,
+}
+
+#[derive(Serialize)]
+pub struct MapResponse {
+    // One per input set, in the same order
+    pub results: Vec<ExecutionResult>,
+    pub meta: MapMeta,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MapMeta {
+    pub duration_ms: u64,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+// The response /execute would have sent, for one job of a batch or input set of a map
+#[derive(Serialize)]
+pub struct ExecutionResult {
+    pub ok: bool,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(rename = "unhandledRejections", skip_serializing_if = "Vec::is_empty")]
+    pub unhandled_rejections: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ExecutionMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugInfo>,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize, Default)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub message: String,
+    #[serde(rename = "unhandledRejections", skip_serializing_if = "Vec::is_empty")]
+    pub unhandled_rejections: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ExecutionMeta>,
+    #[serde(rename = "jsError", skip_serializing_if = "Option::is_none")]
+    pub js_error: Option<JsError>,
+    // The value the code threw, as JSON, when it isn't an Error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thrown: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>,
+    // The rejected result, when it failed output_schema validation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    // Start of a result that was too large, with `debug`
+    #[serde(rename = "resultPreview", skip_serializing_if = "Option::is_none")]
+    pub result_preview: Option<String>,
+    // The size limit a request exceeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exceeded: Option<ExceededLimit>,
+    // The responses the httpRequest calls got until the code failed, with `record_http`
+    #[serde(rename = "httpTrace", skip_serializing_if = "Option::is_none")]
+    pub http_trace: Option<Recording>,
+    // The API key's budget that is used up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<ExceededQuota>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugInfo>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceededLimit {
+    // The setting, e.g. MAX_CODE_BYTES
+    pub limit: &'static str,
+    pub max_bytes: usize,
+    // Unknown when a body without Content-Length was cut off at the limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual_bytes: Option<usize>,
+}
+
+impl ErrorResponse {
+    // 413 for a part of the request, or the whole body, that is larger than `limit` allows
+    pub fn too_large(what: &str, limit: &'static str, max_bytes: usize, actual_bytes: Option<usize>) -> Self {
+        let message = match actual_bytes {
+            Some(actual) => format!(
+                "{} is {} bytes, {} over the {} limit of {}",
+                what,
+                actual,
+                actual - max_bytes,
+                limit,
+                max_bytes
+            ),
+            None => format!("{} exceeds the {} limit of {} bytes", what, limit, max_bytes),
+        };
+        ErrorResponse {
+            error: "Request too large".to_string(),
+            message,
+            exceeded: Some(ExceededLimit { limit, max_bytes, actual_bytes }),
+            ..Default::default()
+        }
+    }
+}
+
+// INPUTS when a request has none
+pub fn empty_inputs() -> Value {
+    Value::Object(Default::default())
+}
+
+fn network_allowed() -> bool {
+    true
+}
+
+// HTTP status of the response to a failed execution
+pub fn error_status(kind: ErrorKind) -> StatusCode {
+    match kind {
+        ErrorKind::Syntax | ErrorKind::Import | ErrorKind::Entrypoint | ErrorKind::Unserializable => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        ErrorKind::Runtime | ErrorKind::RequestLimit | ErrorKind::MemoryLimit | ErrorKind::CpuLimit => {
+            StatusCode::BAD_REQUEST
+        }
+        ErrorKind::Interrupted => StatusCode::REQUEST_TIMEOUT,
+        ErrorKind::ResultTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorKind::UnmatchedRequest => StatusCode::FAILED_DEPENDENCY,
+        ErrorKind::Busy => StatusCode::TOO_MANY_REQUESTS,
+        ErrorKind::WorkerCrashed | ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionMeta {
+    pub duration_ms: u64,
+    // Time spent waiting for an execution slot, part of durationMs
+    pub queue_ms: u64,
+    // Time spent running JavaScript, i.e. not waiting for outbound requests
+    pub eval_ms: u64,
+    pub fetch_ms: u64,
+    // Time on the CPU running the code
+    pub cpu_ms: u64,
+    pub http_request_count: u32,
+    // Requests refused for their method or by the outbound policy
+    pub blocked_request_count: u32,
+    // The timeout and limits the execution ran with
+    pub timeout_ms: u64,
+    pub limits: LimitsMeta,
+    pub code_bytes: usize,
+    pub result_bytes: usize,
+    // Seed Math.random was initialized with, to reproduce the execution
+    pub random_seed: u32,
+    pub network_allowed: bool,
+    // Whether the result came from the result cache, without running the code
+    pub cache_hit: bool,
+    pub code_cache: CodeCacheMeta,
+    pub request_id: String,
+    // The httpRequest calls by host
+    pub fetch_by_host: BTreeMap<String, HostFetchMeta>,
+    pub usage: UsageMeta,
+}
+
+// With `debug`: the httpRequest calls under the keys replay_http looks them up by,
+// the keys it has responses for and the ones that got none
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugInfo {
+    pub requests: Vec<DebugRequest>,
+    // The keys of replay_http, null without it
+    pub replay_keys: Option<Vec<String>>,
+    // Requests neither http_mocks nor replay_http had a response for
+    pub unmatched_keys: Vec<String>,
+    pub durations: DebugDurations,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugDurations {
+    pub queue_ms: u64,
+    // Running JavaScript, i.e. not waiting for outbound requests
+    pub eval_ms: u64,
+    pub fetch_ms: u64,
+    pub total_ms: u64,
+}
+
+impl DebugInfo {
+    pub fn collect(report: &Report, replay_http: Option<&Recording>) -> Self {
+        DebugInfo {
+            requests: report.debug_requests.clone(),
+            replay_keys: replay_http.map(|recording| recording.keys().cloned().collect()),
+            unmatched_keys: report
+                .debug_requests
+                .iter()
+                .filter(|request| request.error_code == Some(ErrorCode::UnmatchedRequest))
+                .map(|request| request.key.clone())
+                .collect(),
+            durations: DebugDurations {
+                queue_ms: report.queue_wait.as_millis() as u64,
+                eval_ms: report.duration.saturating_sub(report.fetch_duration + report.queue_wait).as_millis() as u64,
+                fetch_ms: report.fetch_duration.as_millis() as u64,
+                total_ms: report.duration.as_millis() as u64,
+            },
+        }
+    }
+}
+
+// What the execution consumed, the figures the usage histograms get
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMeta {
+    pub heap_peak_bytes: usize,
+    pub cpu_ms: u64,
+    pub interrupts: u64,
+    pub fetched_bytes: u64,
+    pub sent_bytes: u64,
+    pub result_bytes: usize,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HostFetchMeta {
+    pub requests: u32,
+    // Including retries
+    pub attempts: u32,
+    // Summed over the requests, so concurrent ones count each
+    pub total_ms: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitsMeta {
+    pub max_requests: u32,
+    pub max_result_bytes: usize,
+    pub max_fetch_body_bytes: usize,
+    pub max_fetch_total_bytes: usize,
+    pub memory_bytes: usize,
+    pub cpu_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct CodeCacheMeta {
+    // Whether this execution's code was loaded from the cache
+    pub hit: bool,
+    // Totals since the server started
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ExecutionMeta {
+    pub fn collect(report: &Report, executor: &Executor, result: Option<&Value>) -> Self {
+        let code_cache = executor.code_cache();
+        ExecutionMeta {
+            duration_ms: report.duration.as_millis() as u64,
+            queue_ms: report.queue_wait.as_millis() as u64,
+            eval_ms: report.duration.saturating_sub(report.fetch_duration).as_millis() as u64,
+            fetch_ms: report.fetch_duration.as_millis() as u64,
+            cpu_ms: report.usage.cpu_time.as_millis() as u64,
+            http_request_count: report.http_request_count,
+            blocked_request_count: report
+                .http
+                .iter()
+                .filter(|request| matches!(request.error_code, Some(ErrorCode::MethodNotAllowed | ErrorCode::BlockedByPolicy)))
+                .count() as u32,
+            timeout_ms: report.timeout.as_millis() as u64,
+            limits: LimitsMeta {
+                max_requests: report.max_requests,
+                max_result_bytes: report.max_result_bytes,
+                max_fetch_body_bytes: report.max_fetch_body_bytes,
+                max_fetch_total_bytes: report.max_fetch_total_bytes,
+                memory_bytes: report.memory_bytes,
+                cpu_ms: report.cpu_budget.as_millis() as u64,
+            },
+            code_bytes: report.code_bytes,
+            result_bytes: result
+                .and_then(|r| serde_json::to_vec(r).ok())
+                .map_or(0, |bytes| bytes.len()),
+            random_seed: report.random_seed,
+            network_allowed: report.network_allowed,
+            cache_hit: report.result_cache_hit,
+            code_cache: CodeCacheMeta {
+                hit: report.code_cache_hit,
+                hits: code_cache.hits(),
+                misses: code_cache.misses(),
+            },
+            request_id: report.request_id.clone(),
+            fetch_by_host: fetch_by_host(report),
+            usage: UsageMeta {
+                heap_peak_bytes: report.usage.heap_peak_bytes,
+                cpu_ms: report.usage.cpu_time.as_millis() as u64,
+                interrupts: report.usage.interrupts,
+                fetched_bytes: report.usage.fetched_bytes,
+                sent_bytes: report.usage.sent_bytes,
+                result_bytes: report.usage.result_bytes,
+            },
+        }
+    }
+}
+
+fn fetch_by_host(report: &Report) -> BTreeMap<String, HostFetchMeta> {
+    let mut hosts: BTreeMap<String, HostFetchMeta> = BTreeMap::new();
+    for request in &report.http {
+        let Some(host) = reqwest::Url::parse(&request.url).ok().and_then(|url| url.host_str().map(str::to_string)) else {
+            continue;
+        };
+        let meta = hosts.entry(host).or_default();
+        meta.requests += 1;
+        meta.attempts += request.attempts;
+        meta.total_ms += request.duration_ms;
+    }
+    hosts
+}
+
+// Checks that can fail a request before anything runs. Returns the compiled output
+// schema, if there is one.
+pub fn check_request(
+    state: &AppState,
+    req: &ExecuteRequest,
+) -> Result<Option<jsonschema::Validator>, Box<(StatusCode, ErrorResponse)>> {
+    if req.code.is_none() && req.modules.is_none() {
+        return Err(Box::new((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorResponse {
+                error: "Invalid request".to_string(),
+                message: "The request has neither code nor modules".to_string(),
+                ..Default::default()
+            },
+        )));
+    }
+    if req.code.as_deref() == Some("") {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid code parameter".to_string(),
+                message: "Code cannot be empty".to_string(),
+                ..Default::default()
+            },
+        )));
+    }
+
+    let max_timeout_ms = state.executor.limits().max_execution_timeout.as_millis() as i64;
+    if let Some(timeout_ms) = req.timeout_ms.filter(|ms| !(1..=max_timeout_ms).contains(ms)) {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid timeout_ms".to_string(),
+                message: format!("timeout_ms must be between 1 and {} ms, got {}", max_timeout_ms, timeout_ms),
+                ..Default::default()
+            },
+        )));
+    }
+
+    let max_ttl_seconds = state.result_cache_max_ttl_seconds;
+    if let Some(cache) = req.cache.as_ref().filter(|cache| cache.ttl_seconds > max_ttl_seconds) {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid cache".to_string(),
+                message: format!("cache.ttlSeconds can be at most {}, got {}", max_ttl_seconds, cache.ttl_seconds),
+                ..Default::default()
+            },
+        )));
+    }
+
+    if req.args.is_some() && req.entrypoint.is_none() {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid args".to_string(),
+                message: "args are passed to the entrypoint, but no entrypoint was given".to_string(),
+                ..Default::default()
+            },
+        )));
+    }
+
+    if let Some(tenant) = &req.tenant {
+        state.tenants.check(tenant)?;
+    }
+    // Answered within the server's process, which executions in worker processes can't reach
+    let in_process_only = [
+        ("dry_run", req.dry_run),
+        ("http_mocks", req.http_mocks.is_some()),
+        ("replay_http", req.replay_http.is_some()),
+        ("record_http", req.record_http),
+    ];
+    let unsupported = in_process_only.into_iter().find_map(|(option, set)| set.then_some(option));
+    if let (Some(option), Some(_)) = (unsupported, state.executor.workers()) {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid request".to_string(),
+                message: format!("{} isn't available with ISOLATION_MODE=subprocess", option),
+                ..Default::default()
+            },
+        )));
+    }
+    check_modules(req)?;
+    check_limits(state, &req.limits)?;
+    let may_be_interactive = match &req.state_owner {
+        _ if state.interactive_keys.is_empty() => true,
+        Some(owner) => state.interactive_keys.contains(owner),
+        None => false,
+    };
+    if req.priority == Priority::Interactive && !may_be_interactive {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid priority".to_string(),
+                message: "priority \"interactive\" needs an API key listed in INTERACTIVE_API_KEYS".to_string(),
+                ..Default::default()
+            },
+        )));
+    }
+
+    let valid_namespace = |namespace: &str| {
+        (1..=MAX_STATE_NAMESPACE_LEN).contains(&namespace.len())
+            && namespace.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+    };
+    if let Some(namespace) = req.state_namespace.as_deref().filter(|namespace| !valid_namespace(namespace)) {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid state_namespace".to_string(),
+                message: format!(
+                    "state_namespace must be 1 to {} letters, digits, '-', '_' or '.', got {:?}",
+                    MAX_STATE_NAMESPACE_LEN, namespace
+                ),
+                ..Default::default()
+            },
+        )));
+    }
+
+    let invalid_mock = req.http_mocks.iter().flatten().enumerate().find_map(|(index, mock)| {
+        mock.check().err().map(|message| format!("http_mocks[{}]: {}", index, message))
+    });
+    if let Some(message) = invalid_mock {
+        return Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "Invalid http_mocks".to_string(),
+                message,
+                ..Default::default()
+            },
+        )));
+    }
+
+    let too_large = |what, limit, max, actual| {
+        Box::new((StatusCode::PAYLOAD_TOO_LARGE, ErrorResponse::too_large(what, limit, max, Some(actual))))
+    };
+    let code_bytes = req.source().len();
+    if code_bytes > state.max_code_bytes {
+        return Err(too_large("code", "MAX_CODE_BYTES", state.max_code_bytes, code_bytes));
+    }
+    let input_bytes = serde_json::to_vec(&req.inputs).map_or(0, |inputs| inputs.len());
+    if input_bytes > state.max_inputs_bytes {
+        return Err(too_large("inputs", "MAX_INPUTS_BYTES", state.max_inputs_bytes, input_bytes));
+    }
+
+    let invalid_schema = |message: String| {
+        Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: "InvalidSchema".to_string(),
+                message,
+                ..Default::default()
+            },
+        ))
+    };
+
+    if let Some(inputs_schema) = &req.inputs_schema {
+        let validator = match schema::compile(inputs_schema) {
+            Ok(validator) => validator,
+            Err(message) => return Err(invalid_schema(message)),
+        };
+
+        let violations = schema::violations(&validator, &req.inputs);
+        if !violations.is_empty() {
+            return Err(Box::new((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    error: "InvalidInputs".to_string(),
+                    message: "inputs do not match inputs_schema".to_string(),
+                    violations,
+                    ..Default::default()
+                },
+            )));
+        }
+    }
+
+    // Compiled up front so an invalid output schema fails before anything runs
+    req.output_schema.as_ref().map(schema::compile).transpose().map_err(invalid_schema)
+}
+
+fn check_modules(req: &ExecuteRequest) -> Result<(), Box<(StatusCode, ErrorResponse)>> {
+    let invalid = |error: &str, message: String| {
+        Err(Box::new((
+            StatusCode::BAD_REQUEST,
+            ErrorResponse {
+                error: error.to_string(),
+                message,
+                ..Default::default()
+            },
+        )))
+    };
+    let Some(modules) = &req.modules else {
+        if req.entry_module.is_some() {
+            return invalid("Invalid entry_module", "entry_module names one of the modules, but no modules were given".to_string());
+        }
+        return Ok(());
+    };
+    if req.code.is_some() {
+        return invalid("Invalid modules", "code and modules can't both be given; the entry module is the code".to_string());
+    }
+    let reserved = |name: &str| name.is_empty() || name.starts_with(['.', '/']) || name.starts_with("sandbox:");
+    if let Some(name) = modules.keys().find(|name| reserved(name)) {
+        return invalid(
+            "Invalid modules",
+            format!("Module name {:?} is invalid: names can't be empty or start with '.', '/' or 'sandbox:'", name),
+        );
+    }
+    if !modules.contains_key(req.entry_module()) {
+        let names = modules.keys().map(String::as_str).collect::<Vec<_>>().join(", ");
+        return invalid(
+            "Invalid entry_module",
+            format!("entry_module {:?} isn't one of the modules ({})", req.entry_module(), names),
+        );
+    }
+    Ok(())
+}
+
+// Runs one execution request, failing with the status and body of the error response.
+// Logged as an `execution` span that ends with a summary; the code itself only by
+// hash and size.
+pub async fn execute(state: &AppState, req: ExecuteRequest, request_id: &RequestId) -> Result<ExecuteResponse, (StatusCode, ErrorResponse)> {
+    let started = Instant::now();
+    let code = req.source();
+    let code_sha256: String = Sha256::digest(code.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    let api_key_id = req.state_owner.clone();
+    let span = tracing::info_span!(
+        "execution",
+        code_sha256 = %code_sha256,
+        code_bytes = code.len(),
+        input_bytes = serde_json::to_vec(&req.inputs).map_or(0, |inputs| inputs.len()),
+        http_request_count = tracing::field::Empty,
+    );
+    if state.log_code {
+        let inputs = serde_json::to_string(&req.inputs).unwrap_or_default();
+        tracing::debug!(parent: &span, code = %code, inputs = %inputs, "Execution code");
+    }
+    drop(code);
+
+    let mut http = Vec::new();
+    let outcome = run_execution(state, req, request_id, &mut http).instrument(span.clone()).await;
+    let status = match &outcome {
+        Ok(_) => StatusCode::OK,
+        Err((status, _)) => *status,
+    };
+    METRICS.execution(execution_outcome(&outcome), started.elapsed());
+    state.audit.record(AuditRecord {
+        timestamp: audit::timestamp(),
+        request_id: request_id.0.clone(),
+        api_key_id,
+        code_sha256,
+        requests: http.iter().map(AuditRequest::from_trace).collect(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        status: status.as_u16(),
+        outcome: execution_outcome(&outcome).as_str(),
+        error: outcome.as_ref().err().map(|(_, error)| error.error.clone()),
+    });
+    tracing::info!(
+        parent: &span,
+        duration_ms = started.elapsed().as_millis() as u64,
+        status = status.as_u16(),
+        "Execution finished"
+    );
+    outcome
+}
+
+fn execution_outcome(outcome: &Result<ExecuteResponse, (StatusCode, ErrorResponse)>) -> Outcome {
+    let js_errors = [
+        ErrorKind::Syntax,
+        ErrorKind::Runtime,
+        ErrorKind::Import,
+        ErrorKind::Entrypoint,
+        ErrorKind::Unserializable,
+    ];
+    match outcome {
+        Ok(_) => Outcome::Success,
+        Err((_, error)) if js_errors.iter().any(|kind| kind.error() == error.error) => Outcome::JsError,
+        Err((_, error)) if error.error == ErrorKind::Interrupted.error() => Outcome::Timeout,
+        Err((status, _)) if status.is_server_error() => Outcome::Internal,
+        Err(_) => Outcome::Rejected,
+    }
+}
+
+// `http` gets the execution's httpRequest calls, for the audit record
+async fn run_execution(
+    state: &AppState,
+    req: ExecuteRequest,
+    request_id: &RequestId,
+    http: &mut Vec<HttpTrace>,
+) -> Result<ExecuteResponse, (StatusCode, ErrorResponse)> {
+    let output_validator = check_request(state, &req).map_err(|e| *e)?;
+    // Held until the execution finished
+    let _tenant_slot = match &req.tenant {
+        Some(tenant) => Some(state.tenants.admit(tenant).await?),
+        None => None,
+    };
+    // Counted from here on, given back if the execution doesn't get to run
+    let quota = match &req.state_owner {
+        Some(key) => Some(state.quotas().admit(key).map_err(|e| *e)?),
+        None => None,
+    };
+    let options = Options {
+        module: req.module || req.modules.is_some(),
+        bigint_mode: req.bigint_mode,
+        freeze_time: req.freeze_time,
+        random_seed: req.random_seed,
+        timeout: Some(effective_timeout(state, &req)),
+        cpu_budget: req.limits.cpu_ms.map(Duration::from_millis),
+        max_requests: req.limits.max_requests,
+        max_result_bytes: req.limits.max_result_bytes,
+        memory_bytes: req.limits.memory_bytes,
+        max_fetch_body_bytes: req.limits.max_fetch_body_bytes,
+        disable_dynamic_eval: req.limits.disable_dynamic_eval,
+        disable_network: !req.allow_network,
+        request_id: Some(request_id.0.clone()),
+        secrets: Arc::new(Secrets::new(req.secrets.clone())),
+        files: req.files.clone(),
+        state_namespace: Some(state_namespace(&req)),
+        dry_run: req.dry_run,
+        http_mocks: req.http_mocks.clone().map(|mocks| Arc::new(HttpMocks::new(mocks))),
+        replay_http: req.replay_http.clone().map(|recording| Arc::new(Replay::new(recording))),
+        record_http: req.record_http,
+        cache_ttl: req.cache.as_ref().map(|cache| Duration::from_secs(cache.ttl_seconds)).filter(|ttl| !ttl.is_zero()),
+        entrypoint: req.entrypoint.clone().map(|name| Entrypoint {
+            name,
+            args: req.args.clone(),
+        }),
+        language: req.language,
+        modules: req.modules.clone().map(|sources| {
+            Arc::new(ModuleMap {
+                entry: req.entry_module().to_string(),
+                sources,
+            })
+        }),
+        priority: req.priority,
+        tenant: req.tenant.clone(),
+        fetch_allowlist: req.tenant.as_deref().and_then(|tenant| state.tenants.allowlist(tenant)),
+        // The output schema is checked against the parsed result
+        raw_result: req.raw_result && output_validator.is_none(),
+        skip_prelude: req.skip_prelude,
+        debug: req.debug,
+    };
+
+    let outcome = state.executor.run(req.entry_code(), &req.inputs, options).await;
+    let report = match &outcome {
+        Ok(execution) => Some(&execution.report),
+        Err(e) => e.report.as_deref(),
+    };
+    if let Some(report) = report {
+        tracing::Span::current().record("http_request_count", report.http_request_count);
+        http.clone_from(&report.http);
+        if let Some(tenant) = &req.tenant {
+            state.tenants.record(tenant, &report.usage);
+        }
+        if let Some(quota) = quota {
+            quota.finish(&report.usage);
+        }
+    }
+    let meta = |report: &Report, result: Option<&Value>| {
+        req.include_meta.then(|| ExecutionMeta::collect(report, &state.executor, result))
+    };
+    let debug = |report: &Report| req.debug.then(|| DebugInfo::collect(report, req.replay_http.as_ref()));
+
+    // The planned requests, whether the code succeeded or not; only a dry run that
+    // didn't get to run fails
+    if req.dry_run {
+        let (report, error) = match outcome {
+            Ok(execution) => (execution.report, None),
+            Err(mut e) => match e.report.take() {
+                Some(report) => (*report, Some(error_response(e, req.debug, |_, _| None))),
+                None => return Err((error_status(e.kind), error_response(e, req.debug, meta))),
+            },
+        };
+        let debug = debug(&report);
+        return Ok(ExecuteResponse {
+            result: Value::Null,
+            result_json: None,
+            unhandled_rejections: if req.debug { report.unhandled_rejections.clone() } else { Vec::new() },
+            meta: meta(&report, None),
+            requests: Some(report.planned_requests),
+            dry_run: true,
+            error,
+            http_trace: report.recorded_http,
+            debug,
+        });
+    }
+    match outcome {
+        Ok(mut execution) => {
+            let http_trace = execution.report.recorded_http.take();
+            let result = execution.result;
+            let result_json = execution.result_json;
+            let unhandled_rejections = if req.debug { execution.report.unhandled_rejections.clone() } else { Vec::new() };
+            let mut meta = meta(&execution.report, Some(&result));
+            if let (Some(meta), Some(json)) = (&mut meta, &result_json) {
+                meta.result_bytes = json.len();
+            }
+            let debug = debug(&execution.report);
+
+            // Validated by reference; the result is moved into whichever response is sent
+            if let Some(validator) = &output_validator {
+                let violations = schema::violations(validator, &result);
+                if !violations.is_empty() {
+                    return Err((
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        ErrorResponse {
+                            error: "InvalidOutput".to_string(),
+                            message: "result does not match output_schema".to_string(),
+                            unhandled_rejections,
+                            meta,
+                            violations,
+                            result: Some(result),
+                            http_trace,
+                            debug,
+                            ..Default::default()
+                        },
+                    ));
+                }
+            }
+
+            Ok(ExecuteResponse {
+                result,
+                result_json,
+                unhandled_rejections,
+                meta,
+                requests: None,
+                dry_run: false,
+                error: None,
+                http_trace,
+                debug,
+            })
+        }
+        Err(e) => {
+            let debug = e.report.as_deref().and_then(debug);
+            Err((error_status(e.kind), ErrorResponse { debug, ..error_response(e, req.debug, meta) }))
+        }
+    }
+}
+
+
+// Callers with an API key get namespaces of their own, which `state_namespace`
+// subdivides; the others share "default" unless they name one. Tenants have all of
+// these to themselves.
+fn state_namespace(req: &ExecuteRequest) -> String {
+    let namespace = match (&req.state_owner, &req.state_namespace) {
+        (Some(owner), Some(namespace)) => format!("key:{}/{}", owner, namespace),
+        (Some(owner), None) => format!("key:{}", owner),
+        (None, Some(namespace)) => namespace.clone(),
+        (None, None) => "default".to_string(),
+    };
+    match &req.tenant {
+        Some(tenant) => format!("tenant:{}/{}", tenant, namespace),
+        None => namespace,
+    }
+}
+
+// What identifies the X-API-Key's state, without keeping the key itself
+pub fn state_owner(headers: &HeaderMap) -> Option<String> {
+    Some(api_key_id(headers.get("x-api-key")?.to_str().ok()?))
+}
+
+pub fn api_key_id(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes())[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Each of `limits` at most what the server allows
+fn check_limits(state: &AppState, limits: &ExecutionLimits) -> Result<(), Box<(StatusCode, ErrorResponse)>> {
+    let server = state.executor.limits();
+    let requested = [
+        ("max_requests", limits.max_requests.map(|v| v as usize), server.max_requests as usize, "MAX_REQUESTS_PER_EXECUTION"),
+        ("max_result_bytes", limits.max_result_bytes, server.max_result_bytes, "MAX_RESULT_BYTES"),
+        ("max_fetch_body_bytes", limits.max_fetch_body_bytes, server.max_fetch_body_bytes, "FETCH_MAX_BODY_BYTES"),
+        ("memory_bytes", limits.memory_bytes, server.memory_bytes, "JS_MAX_MEMORY_BYTES"),
+        ("cpu_ms", limits.cpu_ms.map(|v| v as usize), server.cpu_budget.as_millis() as usize, "EXEC_CPU_MS"),
+    ];
+    for (field, value, max, setting) in requested {
+        if let Some(value) = value.filter(|value| *value > max) {
+            return Err(Box::new((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    error: "Invalid limits".to_string(),
+                    message: format!(
+                        "limits.{} can't exceed the server's {} of {}, got {}",
+                        field, setting, max, value
+                    ),
+                    ..Default::default()
+                },
+            )));
+        }
+    }
+    Ok(())
+}
+
+// `timeout_ms` or the default, shortened by `limits.timeout_ms`, which can't extend it
+fn effective_timeout(state: &AppState, req: &ExecuteRequest) -> Duration {
+    let timeout = req
+        .timeout_ms
+        .map_or(state.executor.limits().execution_timeout, |ms| Duration::from_millis(ms as u64));
+    match req.limits.timeout_ms {
+        Some(ms) => timeout.min(Duration::from_millis(ms)),
+        None => timeout,
+    }
+}
+
+pub fn error_response(
+    e: ExecError,
+    debug: bool,
+    meta: impl FnOnce(&Report, Option<&Value>) -> Option<ExecutionMeta>,
+) -> ErrorResponse {
+    let (unhandled_rejections, meta, http_trace) = match e.report.as_deref() {
+        Some(report) => (report.unhandled_rejections.clone(), meta(report, None), report.recorded_http.clone()),
+        None => (Vec::new(), None, None),
+    };
+    ErrorResponse {
+        error: e.kind.error().to_string(),
+        message: e.message,
+        unhandled_rejections,
+        meta,
+        thrown: e.js_error.as_ref().and_then(|js_error| js_error.thrown.clone()),
+        js_error: e.js_error.map(|js_error| *js_error),
+        result_preview: e.preview.filter(|_| debug),
+        http_trace,
+        ..Default::default()
+    }
+}
+
+// Runs the requests concurrently, each one as if it had been sent to /execute. A
+// failing request only fails its own result. All of them share the id of the
+// batch or map request.
+pub async fn execute_all(state: &AppState, requests: Vec<ExecuteRequest>, request_id: &RequestId) -> Vec<ExecutionResult> {
+    // `buffered` keeps the results in the order of the requests
+    stream::iter(requests)
+        .map(|req| async move {
+            let started = Instant::now();
+            let outcome = execute(state, req, request_id).await;
+            let duration_ms = started.elapsed().as_millis() as u64;
+            match outcome {
+                Ok(response) => ExecutionResult {
+                    ok: true,
+                    status: StatusCode::OK.as_u16(),
+                    result: Some(response.result),
+                    unhandled_rejections: response.unhandled_rejections,
+                    meta: response.meta,
+                    error: None,
+                    debug: response.debug,
+                    duration_ms,
+                },
+                Err((status, error)) => ExecutionResult {
+                    ok: false,
+                    status: status.as_u16(),
+                    result: None,
+                    unhandled_rejections: Vec::new(),
+                    meta: None,
+                    error: Some(error),
+                    debug: None,
+                    duration_ms,
+                },
+            }
+        })
+        .buffered(state.batch_parallelism.max(1))
+        .collect()
+        .await
+}
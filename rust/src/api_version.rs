@@ -0,0 +1,90 @@
+// Versions of the HTTP API, mounted under `/v1/...`.
+//
+// A version only ever adds to its responses: new fields may appear, but existing
+// ones keep their meaning, and JSON objects it returns carry `apiVersion` so
+// callers can tell which contract they got. Changes that would break callers go
+// into a new version, which mounts a router of its own next to the others. The
+// unprefixed paths of the first releases are deprecated aliases of v1 that answer
+// with a `Deprecation` header and a link to their successor.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+// The version the unprefixed aliases answer as
+pub const LEGACY: &str = "v1";
+
+// Every mounted version, oldest first
+pub const SUPPORTED: &[&str] = &["v1"];
+
+// Adds `"apiVersion"` to the JSON objects of a version's responses
+pub async fn envelope(version: &'static str, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Some(rest) = bytes.strip_prefix(b"{") else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let mut enveloped = format!("{{\"apiVersion\":\"{}\"", version).into_bytes();
+    if rest.first() != Some(&b'}') {
+        enveloped.push(b',');
+    }
+    enveloped.extend_from_slice(rest);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(enveloped))
+}
+
+// Marks the responses of unprefixed paths as deprecated, pointing to the v1 path
+pub async fn deprecated(request: Request, next: Next) -> Response {
+    let successor = format!("</{}{}>; rel=\"successor-version\"", LEGACY, request.uri().path());
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append(header::LINK, link);
+    }
+    response
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NotFound {
+    error: &'static str,
+    message: String,
+    supported_versions: &'static [&'static str],
+}
+
+// Paths no route matched. Those with a version prefix that isn't mounted, like
+// `/v2/execute`, say which versions exist.
+pub async fn not_found(request: Request) -> Response {
+    let path = request.uri().path();
+    let prefix = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+    let is_version = prefix.len() > 1 && prefix.starts_with('v') && prefix[1..].bytes().all(|b| b.is_ascii_digit());
+    let body = if is_version && !SUPPORTED.contains(&prefix) {
+        NotFound {
+            error: "Unknown API version",
+            message: format!("API version {} doesn't exist; this server supports {}", prefix, SUPPORTED.join(", ")),
+            supported_versions: SUPPORTED,
+        }
+    } else {
+        NotFound {
+            error: "Not found",
+            message: format!("No endpoint at {} {}", request.method(), path),
+            supported_versions: SUPPORTED,
+        }
+    };
+    (StatusCode::NOT_FOUND, Json(body)).into_response()
+}
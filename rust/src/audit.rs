@@ -0,0 +1,266 @@
+// Audit records of executions, one per execution that went through `api::execute`,
+// i.e. /execute, batch and map entries and jobs.
+//
+// A record says who called (the API key's id, never the key), what ran (the code's
+// SHA-256), which URLs it contacted (method, host and path, without query strings
+// or headers), how long it took and how it ended. Records are queued and written
+// by a task of their own per sink, so a slow sink never holds up a response; when
+// a sink's queue of AUDIT_QUEUE_RECORDS is full, further records are logged as
+// lost. AUDIT_FILE appends JSON lines to a file, rotated once it would exceed
+// AUDIT_FILE_MAX_BYTES, and AUDIT_URL posts them to a collector in batches,
+// retrying network errors and 5xx responses like job callbacks.
+
+use reqwest::Url;
+use serde::Serialize;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+
+use sandbox_core::fetch::{ErrorCode, HttpTrace};
+use sandbox_core::Config;
+
+// Records handed to a sink at once
+const MAX_BATCH_RECORDS: usize = 100;
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub request_id: String,
+    // What identifies the caller's X-API-Key, as for state namespaces
+    pub api_key_id: Option<String>,
+    pub code_sha256: String,
+    pub requests: Vec<AuditRequest>,
+    pub duration_ms: u64,
+    pub status: u16,
+    // As in jsexec_executions_total
+    pub outcome: &'static str,
+    // The `error` of the error response
+    pub error: Option<String>,
+}
+
+// One httpRequest call of the execution
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRequest {
+    pub method: String,
+    pub host: String,
+    pub path: String,
+    pub status: Option<u16>,
+    pub error_code: Option<ErrorCode>,
+}
+
+impl AuditRequest {
+    pub fn from_trace(trace: &HttpTrace) -> Self {
+        let url = Url::parse(&trace.url).ok();
+        AuditRequest {
+            method: trace.method.clone(),
+            host: url.as_ref().and_then(Url::host_str).unwrap_or_default().to_string(),
+            path: url.as_ref().map_or_else(String::new, |url| url.path().to_string()),
+            status: trace.status,
+            error_code: trace.error_code,
+        }
+    }
+}
+
+pub fn timestamp() -> String {
+    let mut timestamp = String::new();
+    let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+    timestamp
+}
+
+// Where audit records are stored
+pub trait AuditSink: Send + Sync {
+    // Stores the records in order, or fails naming why they were lost
+    fn write<'a>(&'a self, records: &'a [AuditRecord]) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+}
+
+pub struct Audit {
+    // One queue per sink
+    queues: Vec<(&'static str, mpsc::Sender<AuditRecord>)>,
+}
+
+impl Audit {
+    // No sinks, and records are dropped, unless AUDIT_FILE or AUDIT_URL is set
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut sinks: Vec<(&'static str, Arc<dyn AuditSink>)> = Vec::new();
+        if !config.audit_file.is_empty() {
+            sinks.push(("file", Arc::new(FileSink::from_config(config))));
+        }
+        if !config.audit_url.is_empty() {
+            sinks.push(("http", Arc::new(HttpSink::from_config(config)?)));
+        }
+        Ok(Audit::with_sinks(sinks, config.audit_queue_records))
+    }
+
+    // Spawns a writer for every sink
+    pub fn with_sinks(sinks: Vec<(&'static str, Arc<dyn AuditSink>)>, queue_records: usize) -> Self {
+        let queues = sinks
+            .into_iter()
+            .map(|(name, sink)| {
+                let (queue, records) = mpsc::channel(queue_records.max(1));
+                tokio::spawn(drain(name, sink, records));
+                (name, queue)
+            })
+            .collect();
+        Audit { queues }
+    }
+
+    pub fn record(&self, record: AuditRecord) {
+        for (name, queue) in &self.queues {
+            if queue.try_send(record.clone()).is_err() {
+                tracing::error!("Audit queue of the {} sink is full, lost the record of {}", name, record.request_id);
+            }
+        }
+    }
+}
+
+async fn drain(name: &'static str, sink: Arc<dyn AuditSink>, mut records: mpsc::Receiver<AuditRecord>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH_RECORDS);
+    while records.recv_many(&mut batch, MAX_BATCH_RECORDS).await > 0 {
+        if let Err(e) = sink.write(&batch).await {
+            tracing::error!("Audit sink {} lost {} records: {}", name, batch.len(), e);
+        }
+        batch.clear();
+    }
+}
+
+fn json_lines(records: &[AuditRecord]) -> Vec<u8> {
+    let mut lines = Vec::new();
+    for record in records {
+        // Plain data, which always serializes
+        let _ = serde_json::to_writer(&mut lines, record);
+        lines.push(b'\n');
+    }
+    lines
+}
+
+// Appends to AUDIT_FILE. Before a write would take it past AUDIT_FILE_MAX_BYTES it
+// becomes `<file>.1`, older files move up to `<file>.<AUDIT_FILE_KEEP>`, and the
+// oldest is deleted.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+}
+
+impl FileSink {
+    pub fn from_config(config: &Config) -> Self {
+        FileSink {
+            path: PathBuf::from(&config.audit_file),
+            max_bytes: config.audit_file_max_bytes,
+            keep: config.audit_file_keep,
+        }
+    }
+
+    fn rotated(&self, generation: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", generation));
+        PathBuf::from(path)
+    }
+
+    async fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return tokio::fs::remove_file(&self.path).await;
+        }
+        for generation in (1..self.keep).rev() {
+            match tokio::fs::rename(self.rotated(generation), self.rotated(generation + 1)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated(1)).await
+    }
+
+    async fn append(&self, lines: &[u8]) -> std::io::Result<()> {
+        let size = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        // A single write larger than the limit still goes to a file of its own
+        if size > 0 && size + lines.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+        file.write_all(lines).await?;
+        file.flush().await
+    }
+}
+
+impl AuditSink for FileSink {
+    fn write<'a>(&'a self, records: &'a [AuditRecord]) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>> {
+        Box::pin(async move {
+            self.append(&json_lines(records))
+                .await
+                .map_err(|e| format!("writing {} failed: {}", self.path.display(), e))
+        })
+    }
+}
+
+// Posts batches of records to AUDIT_URL as JSON lines. Network errors and 5xx
+// responses are retried up to AUDIT_HTTP_MAX_ATTEMPTS times in all, waiting
+// AUDIT_HTTP_RETRY_MS before the first retry and twice as long before each further
+// one; records queue up meanwhile.
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: Url,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+impl HttpSink {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let url = Url::parse(&config.audit_url).map_err(|e| format!("AUDIT_URL {} isn't a valid URL: {}", config.audit_url, e))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.audit_http_timeout_ms))
+            .build()
+            .map_err(|e| format!("Failed to build the audit client: {}", e))?;
+        Ok(HttpSink {
+            client,
+            url,
+            max_attempts: config.audit_http_max_attempts.max(1),
+            retry_delay: Duration::from_millis(config.audit_http_retry_ms),
+        })
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Result<(), String> {
+        let mut delay = self.retry_delay;
+        for attempt in 1..=self.max_attempts {
+            let response = self
+                .client
+                .post(self.url.clone())
+                .header("content-type", "application/x-ndjson")
+                .body(body.clone())
+                .send()
+                .await;
+            let failure = match response {
+                Ok(response) if response.status().is_server_error() => format!("status {}", response.status()),
+                Ok(response) if response.status().is_client_error() => {
+                    return Err(format!("the collector refused them with status {}", response.status()));
+                }
+                Ok(_) => return Ok(()),
+                Err(e) => e.to_string(),
+            };
+            if attempt == self.max_attempts {
+                return Err(format!("undeliverable after {} attempts: {}", attempt, failure));
+            }
+            tracing::debug!("Posting audit records failed ({}), retrying in {:?}", failure, delay);
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        Ok(())
+    }
+}
+
+impl AuditSink for HttpSink {
+    fn write<'a>(&'a self, records: &'a [AuditRecord]) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>> {
+        Box::pin(self.post(json_lines(records)))
+    }
+}
@@ -0,0 +1,362 @@
+// Request bodies: the size limit and JSON parsing, failing with JSON error bodies
+// like every other error instead of axum's plain-text rejections.
+//
+// Bodies may be at most MAX_BODY_BYTES. One that declares a larger Content-Length is
+// refused before anything is read; one without a length is read up to the limit.
+// Handlers take their bodies with this module's `Json` in place of axum's.
+//
+// `Negotiated` bodies may also be MessagePack, with `Content-Type: application/msgpack`,
+// and are answered in MessagePack when `Accept` asks for it, errors included. The
+// decoded value is handled exactly like the JSON one, so the same limits apply to it;
+// binary values, which JSON has no counterpart for, become base64 strings. They may
+// also be `multipart/form-data`, whose parts with a file name are kept as files of at
+// most MAX_FILE_BYTES each and whose other parts are the request's fields.
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::multipart::MultipartError;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Multipart, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use serde::de::{DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::api::ErrorResponse;
+use sandbox_core::files::{File, Files};
+use sandbox_core::Config;
+
+#[derive(Clone, Copy)]
+pub struct BodyLimit {
+    pub max_bytes: usize,
+    pub max_file_bytes: usize,
+}
+
+impl BodyLimit {
+    pub fn from_config(config: &Config) -> Self {
+        BodyLimit {
+            max_bytes: config.max_body_bytes,
+            max_file_bytes: config.max_file_bytes,
+        }
+    }
+
+    fn exceeded(self, actual_bytes: Option<usize>, format: Format) -> Response {
+        let error = ErrorResponse::too_large("Request body", "MAX_BODY_BYTES", self.max_bytes, actual_bytes);
+        format.respond(StatusCode::PAYLOAD_TOO_LARGE, &error)
+    }
+}
+
+const MSGPACK: &str = "application/msgpack";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    MessagePack,
+}
+
+impl Format {
+    // MessagePack when `Accept` lists it, JSON otherwise
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        if accept.split(',').any(|media| is_msgpack(media.split(';').next().unwrap_or_default())) {
+            Format::MessagePack
+        } else {
+            Format::Json
+        }
+    }
+
+    fn of_content(headers: &HeaderMap) -> Self {
+        let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        if is_msgpack(content_type.split(';').next().unwrap_or_default()) {
+            Format::MessagePack
+        } else {
+            Format::Json
+        }
+    }
+
+    pub fn respond<T: Serialize>(self, status: StatusCode, body: &T) -> Response {
+        match self {
+            Format::Json => (status, axum::Json(body)).into_response(),
+            // Maps keep their field names, as in JSON
+            Format::MessagePack => match rmp_serde::to_vec_named(body) {
+                Ok(bytes) => (status, [(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK))], bytes).into_response(),
+                Err(e) => {
+                    let error = ErrorResponse {
+                        error: "Serialization error".to_string(),
+                        message: format!("Failed to encode the response as MessagePack: {}", e),
+                        ..Default::default()
+                    };
+                    (StatusCode::INTERNAL_SERVER_ERROR, axum::Json(error)).into_response()
+                }
+            },
+        }
+    }
+}
+
+fn is_msgpack(media_type: &str) -> bool {
+    let media_type = media_type.trim();
+    media_type.eq_ignore_ascii_case(MSGPACK) || media_type.eq_ignore_ascii_case("application/x-msgpack")
+}
+
+// Refuses bodies by their declared length, and leaves the limit for `Json` to report
+// bodies that turn out larger
+pub async fn middleware(State(limit): State<BodyLimit>, mut request: Request, next: Next) -> Response {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+    if let Some(length) = declared.filter(|length| *length > limit.max_bytes) {
+        return limit.exceeded(Some(length), Format::accepted(request.headers()));
+    }
+    request.extensions_mut().insert(limit);
+    next.run(request).await
+}
+
+// axum's `Json`, with rejections answered as ErrorResponse
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Response> {
+        let limit = request.extensions().get::<BodyLimit>().copied();
+        match axum::Json::<T>::from_request(request, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(match limit {
+                Some(limit) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => limit.exceeded(None, Format::Json),
+                _ => reject(rejection, Format::Json),
+            }),
+        }
+    }
+}
+
+// A JSON, MessagePack or multipart body, and the format to answer in
+pub struct Negotiated<T> {
+    pub body: T,
+    pub format: Format,
+    // The files of a multipart body, by field name
+    pub files: Files,
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Negotiated<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Response> {
+        let format = Format::accepted(request.headers());
+        let limit = request.extensions().get::<BodyLimit>().copied();
+        let too_large = |status: StatusCode| limit.filter(|_| status == StatusCode::PAYLOAD_TOO_LARGE);
+        if is_multipart(request.headers()) {
+            let (body, files) = multipart(request, state, limit, format).await?;
+            return Ok(Negotiated { body, format, files });
+        }
+        if Format::of_content(request.headers()) == Format::Json {
+            return match axum::Json::<T>::from_request(request, state).await {
+                Ok(axum::Json(body)) => Ok(Negotiated { body, format, files: Files::new() }),
+                Err(rejection) => Err(match too_large(rejection.status()) {
+                    Some(limit) => limit.exceeded(None, format),
+                    None => reject(rejection, format),
+                }),
+            };
+        }
+
+        let bytes = Bytes::from_request(request, state).await.map_err(|rejection| match too_large(rejection.status()) {
+            Some(limit) => limit.exceeded(None, format),
+            None => invalid(format, rejection.status(), "Invalid request", rejection.body_text()),
+        })?;
+        let MsgpackValue(value) = rmp_serde::from_slice(&bytes)
+            .map_err(|e| invalid(format, StatusCode::BAD_REQUEST, "Invalid MessagePack", format!("Failed to parse the request body as MessagePack: {}", e)))?;
+        // Mismatches are answered like those of JSON bodies
+        let body = serde_json::from_value(value).map_err(|e| {
+            invalid(format, StatusCode::UNPROCESSABLE_ENTITY, "Invalid request", format!("Failed to deserialize the MessagePack body: {}", e))
+        })?;
+        Ok(Negotiated { body, format, files: Files::new() })
+    }
+}
+
+fn is_multipart(headers: &HeaderMap) -> bool {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    content_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("multipart/form-data")
+}
+
+// The request of a multipart body and its files. Parts with a file name are files;
+// the others are fields of the request, `code` as text and the rest, like `inputs`,
+// as JSON.
+async fn multipart<T, S>(request: Request, state: &S, limit: Option<BodyLimit>, format: Format) -> Result<(T, Files), Response>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    let failed = |e: MultipartError| match limit.filter(|_| e.status() == StatusCode::PAYLOAD_TOO_LARGE) {
+        Some(limit) => limit.exceeded(None, format),
+        None => invalid(format, e.status(), "Invalid multipart", e.body_text()),
+    };
+    let mut multipart = Multipart::from_request(request, state)
+        .await
+        .map_err(|rejection| invalid(format, rejection.status(), "Invalid multipart", rejection.body_text()))?;
+    let max_file_bytes = limit.map_or(usize::MAX, |limit| limit.max_file_bytes);
+    let mut fields = Map::new();
+    let mut files = Files::new();
+    while let Some(mut field) = multipart.next_field().await.map_err(failed)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if fields.contains_key(&name) || files.contains_key(&name) {
+            return Err(invalid(format, StatusCode::BAD_REQUEST, "Invalid multipart", format!("The part {} appears more than once", name)));
+        }
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            let text = field.text().await.map_err(failed)?;
+            let value = match name.as_str() {
+                "code" => Value::String(text),
+                _ => serde_json::from_str(&text).map_err(|e| {
+                    invalid(format, StatusCode::BAD_REQUEST, "Invalid JSON", format!("The {} part isn't JSON: {}", name, e))
+                })?,
+            };
+            fields.insert(name, value);
+            continue;
+        };
+
+        let content_type = field.content_type().map(str::to_string);
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(failed)? {
+            if bytes.len() + chunk.len() > max_file_bytes {
+                let error = ErrorResponse::too_large(&format!("File {}", name), "MAX_FILE_BYTES", max_file_bytes, None);
+                return Err(format.respond(StatusCode::PAYLOAD_TOO_LARGE, &error));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        files.insert(
+            name,
+            File {
+                name: Some(file_name),
+                content_type,
+                bytes: bytes.into(),
+            },
+        );
+    }
+    let body = serde_json::from_value(Value::Object(fields)).map_err(|e| {
+        invalid(format, StatusCode::UNPROCESSABLE_ENTITY, "Invalid request", format!("Failed to deserialize the multipart body: {}", e))
+    })?;
+    Ok((body, files))
+}
+
+fn invalid(format: Format, status: StatusCode, error: &str, message: String) -> Response {
+    let body = ErrorResponse {
+        error: error.to_string(),
+        message,
+        ..Default::default()
+    };
+    format.respond(status, &body)
+}
+
+// A MessagePack value as the JSON value it stands for
+struct MsgpackValue(Value);
+
+impl<'de> Deserialize<'de> for MsgpackValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MsgpackVisitor).map(MsgpackValue)
+    }
+}
+
+struct MsgpackVisitor;
+
+impl<'de> Visitor<'de> for MsgpackVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a MessagePack value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    // NaN and infinities become null, as JSON.stringify has them
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Value, E> {
+        Ok(Value::String(base64::engine::general_purpose::STANDARD.encode(value)))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(MsgpackValue(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    // Keys that aren't strings, like integers, become their JSON text
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some((MsgpackValue(key), MsgpackValue(value))) = map.next_entry()? {
+            let key = match key {
+                Value::String(key) => key,
+                key => key.to_string(),
+            };
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+fn reject(rejection: JsonRejection, format: Format) -> Response {
+    let error = match rejection {
+        JsonRejection::JsonSyntaxError(_) => "Invalid JSON",
+        JsonRejection::MissingJsonContentType(_) => "Unsupported media type",
+        _ => "Invalid request",
+    };
+    let body = ErrorResponse {
+        error: error.to_string(),
+        message: rejection.body_text(),
+        ..Default::default()
+    };
+    format.respond(rejection.status(), &body)
+}
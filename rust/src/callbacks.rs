@@ -0,0 +1,103 @@
+// Calls back the `callback_url` of a job once it finished, so that clients don't
+// have to poll GET /jobs/{id}.
+//
+// The callback is a POST of the job's status body, as GET /jobs/{id} would return
+// it, with the job id in X-Job-Id and `sha256=<hex HMAC-SHA256 of the body>` keyed
+// with JOB_CALLBACK_SECRET in X-Signature-256. Callback URLs go through the same
+// outbound policy as httpRequest calls: they are checked when the job is submitted,
+// and the addresses they resolve to when the callback is sent. Redirects aren't
+// followed. Network errors and 5xx responses are retried up to
+// JOB_CALLBACK_MAX_ATTEMPTS times in all, waiting JOB_CALLBACK_RETRY_MS before the
+// first retry and twice as long before each further one; a callback that can't be
+// delivered is logged and dropped.
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha2::Sha256;
+use std::time::Duration;
+
+use sandbox_core::policy::OutboundPolicy;
+use sandbox_core::Config;
+
+const SIGNATURE_HEADER: &str = "x-signature-256";
+const JOB_ID_HEADER: &str = "x-job-id";
+
+pub struct Callbacks {
+    policy: OutboundPolicy,
+    client: reqwest::Client,
+    secret: String,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+impl Callbacks {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let policy = OutboundPolicy::from_config(config)?;
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.job_callback_timeout_ms))
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(resolver) = policy.resolver() {
+            client = client.dns_resolver(resolver);
+        }
+        Ok(Callbacks {
+            policy,
+            client: client.build().map_err(|e| format!("Failed to build the callback client: {}", e))?,
+            secret: config.job_callback_secret.clone(),
+            max_attempts: config.job_callback_max_attempts.max(1),
+            retry_delay: Duration::from_millis(config.job_callback_retry_ms),
+        })
+    }
+
+    // The URL, if callbacks can be sent to it
+    pub async fn check(&self, url: &str) -> Result<Url, String> {
+        if self.secret.is_empty() {
+            return Err("callback_url needs the server to have a JOB_CALLBACK_SECRET".to_string());
+        }
+        let url = Url::parse(url).map_err(|e| format!("callback_url {} isn't a valid URL: {}", url, e))?;
+        self.policy.check_url(&url).map_err(|e| e.message)?;
+        self.policy.check_host_addresses(&url).await.map_err(|e| e.message)?;
+        Ok(url)
+    }
+
+    fn sign(&self, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(body);
+        let signature: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256={}", signature)
+    }
+
+    pub async fn deliver(&self, url: Url, job_id: &str, body: Vec<u8>) {
+        let signature = self.sign(&body);
+        let mut delay = self.retry_delay;
+        for attempt in 1..=self.max_attempts {
+            let response = self
+                .client
+                .post(url.clone())
+                .header("content-type", "application/json")
+                .header(JOB_ID_HEADER, job_id)
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await;
+            let failure = match response {
+                Ok(response) if response.status().is_server_error() => format!("status {}", response.status()),
+                Ok(response) if response.status().is_client_error() => {
+                    tracing::warn!("Callback of job {} refused with status {}", job_id, response.status());
+                    return;
+                }
+                Ok(_) => {
+                    tracing::debug!("Callback of job {} delivered", job_id);
+                    return;
+                }
+                Err(e) => e.to_string(),
+            };
+            if attempt == self.max_attempts {
+                tracing::warn!("Callback of job {} undeliverable after {} attempts: {}", job_id, attempt, failure);
+                return;
+            }
+            tracing::debug!("Callback of job {} failed ({}), retrying in {:?}", job_id, failure, delay);
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Command line of the binary.
+//
+// Without a command, or with `serve`, it runs the server. `exec` runs a single
+// script through the same execution path as POST /execute and exits:
+//
+//   js-execution-service exec --code-file script.js --inputs inputs.json
+//   echo 'INPUTS.x * 2' | js-execution-service exec --code - --inputs inputs.json
+//
+// The result is printed to stdout as JSON. An execution that fails prints the error
+// body /execute would have answered with to stderr and exits with 1; invalid
+// arguments exit with 2. Logs go to stderr. `--no-network` blocks every httpRequest,
+// which then resolves with a `blocked_by_policy` result. `--config path` (or
+// CONFIG_PATH) names the config file for either command. The hidden `--worker` runs
+// a worker process for ISOLATION_MODE=subprocess, which gets its configuration from
+// the server over stdin.
+
+use serde_json::{json, Value};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use sandbox_core::fetch::HttpClients;
+use sandbox_core::policy::OutboundPolicy;
+use sandbox_core::proxy::ProxyConfig;
+use sandbox_core::{Config, Executor};
+
+use crate::api::{empty_inputs, execute, ExecuteRequest};
+use crate::app_state;
+use crate::request_id::RequestId;
+
+const USAGE: &str = "Usage:
+  js-execution-service [--config path] [serve]
+  js-execution-service [--config path] exec (--code-file path | --code code | --code -) [--inputs path] [--no-network]";
+
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub command: Command,
+}
+
+pub enum Command {
+    Serve,
+    Exec(Exec),
+    Worker,
+}
+
+pub struct Exec {
+    code: Code,
+    inputs: Option<PathBuf>,
+    no_network: bool,
+}
+
+enum Code {
+    File(PathBuf),
+    Inline(String),
+    Stdin,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        let mut config = None;
+        let mut command = None;
+        let mut code = None;
+        let mut inputs = None;
+        let mut no_network = false;
+        let mut worker = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--config" => config = Some(PathBuf::from(value("--config")?)),
+                "--code-file" => code = Some(Code::File(PathBuf::from(value("--code-file")?))),
+                "--code" => {
+                    code = Some(match value("--code")? {
+                        code if code == "-" => Code::Stdin,
+                        code => Code::Inline(code),
+                    })
+                }
+                "--inputs" => inputs = Some(PathBuf::from(value("--inputs")?)),
+                "--no-network" => no_network = true,
+                "--worker" => worker = true,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                "serve" | "exec" if command.is_none() => command = Some(arg),
+                _ => match arg.strip_prefix("--config=") {
+                    Some(path) => config = Some(PathBuf::from(path)),
+                    None => return Err(format!("Unexpected argument {}\n\n{}", arg, USAGE)),
+                },
+            }
+        }
+        let config = config.or_else(|| {
+            std::env::var("CONFIG_PATH").ok().filter(|path| !path.trim().is_empty()).map(PathBuf::from)
+        });
+
+        if worker {
+            return Ok(Args { config, command: Command::Worker });
+        }
+        let exec_only = code.is_some() || inputs.is_some() || no_network;
+        let command = match command.as_deref() {
+            Some("exec") => Command::Exec(Exec {
+                code: code.ok_or_else(|| format!("exec needs --code-file or --code\n\n{}", USAGE))?,
+                inputs,
+                no_network,
+            }),
+            _ if exec_only => return Err(format!("--code-file, --code, --inputs and --no-network only apply to exec\n\n{}", USAGE)),
+            _ => Command::Serve,
+        };
+        Ok(Args { config, command })
+    }
+}
+
+impl Exec {
+    pub async fn run(self, config: Config) -> ExitCode {
+        let (code, inputs) = match self.read() {
+            Ok(read) => read,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(2);
+            }
+        };
+
+        let executor = match self.no_network {
+            true => ProxyConfig::from_config(&config)
+                .and_then(|proxy| HttpClients::new(&config, OutboundPolicy::from_config(&config)?.without_network(), proxy))
+                .and_then(|http| Executor::with_http_backend(&config, Arc::new(http))),
+            false => Executor::new(&config),
+        };
+        let state = app_state(&config, executor.unwrap_or_else(|e| panic!("{}", e)));
+        // Parsed like a request body, so every other field has its /execute default
+        let req: ExecuteRequest = match serde_json::from_value(json!({ "code": code, "inputs": inputs })) {
+            Ok(req) => req,
+            Err(e) => {
+                eprintln!("Invalid request: {}", e);
+                return ExitCode::from(2);
+            }
+        };
+        let request_id = RequestId(uuid::Uuid::now_v7().to_string());
+
+        match execute(&state, req, &request_id).await {
+            Ok(response) => {
+                println!("{}", response.result);
+                ExitCode::SUCCESS
+            }
+            Err((_, error)) => {
+                eprintln!("{}", serde_json::to_string(&error).unwrap_or_default());
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    fn read(&self) -> Result<(String, Value), String> {
+        let code = match &self.code {
+            Code::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read the code file {}: {}", path.display(), e))?,
+            Code::Inline(code) => code.clone(),
+            Code::Stdin => {
+                let mut code = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut code)
+                    .map_err(|e| format!("Failed to read the code from stdin: {}", e))?;
+                code
+            }
+        };
+        let inputs = match &self.inputs {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read the inputs file {}: {}", path.display(), e))?;
+                serde_json::from_str(&text)
+                    .map_err(|e| format!("Inputs file {} isn't valid JSON: {}", path.display(), e))?
+            }
+            None => empty_inputs(),
+        };
+        Ok((code, inputs))
+    }
+}
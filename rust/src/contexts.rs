@@ -0,0 +1,223 @@
+// Contexts that live across requests, for code that sets up something expensive
+// once and then runs many small evaluations against it.
+//
+// POST /contexts creates a context, running the optional `init_code` with `inputs`
+// in it, and answers with its id. POST /contexts/{id}/execute evaluates code in the
+// live context, so globals defined by one call are available to the next; calls on
+// the same context wait for each other. DELETE /contexts/{id} drops it. Contexts
+// work like sessions: the memory limit applies to the runtime for the context's
+// whole lifetime, and the request limit to all of its calls together. Contexts
+// unused for CONTEXT_IDLE_TIMEOUT_MS are dropped, and at most MAX_CONTEXTS exist
+// at a time.
+
+use axum::extract::{Extension, Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::{empty_inputs, error_status, ErrorResponse};
+use crate::body::Json;
+use crate::request_id::RequestId;
+use crate::session::Session;
+use crate::AppState;
+use sandbox_core::{Config, ExecError};
+
+struct LiveContext {
+    session: tokio::sync::Mutex<Session>,
+    last_used: Mutex<Instant>,
+}
+
+impl LiveContext {
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+}
+
+pub struct ContextStore {
+    contexts: Mutex<HashMap<String, Arc<LiveContext>>>,
+    max_contexts: usize,
+    idle_timeout: Duration,
+}
+
+impl ContextStore {
+    pub fn from_config(config: &Config) -> Self {
+        ContextStore {
+            contexts: Mutex::new(HashMap::new()),
+            max_contexts: config.max_contexts,
+            idle_timeout: Duration::from_millis(config.context_idle_timeout_ms),
+        }
+    }
+
+    // Drops idle contexts; one that is evaluating is busy, however long it takes
+    fn prune(&self, contexts: &mut HashMap<String, Arc<LiveContext>>) {
+        contexts.retain(|_, context| {
+            context.session.try_lock().is_err() || context.last_used.lock().unwrap().elapsed() <= self.idle_timeout
+        });
+    }
+
+    fn full(&self) -> bool {
+        let mut contexts = self.contexts.lock().unwrap();
+        self.prune(&mut contexts);
+        contexts.len() >= self.max_contexts
+    }
+
+    fn insert(&self, session: Session) -> Result<String, ()> {
+        let mut contexts = self.contexts.lock().unwrap();
+        self.prune(&mut contexts);
+        if contexts.len() >= self.max_contexts {
+            return Err(());
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let context = LiveContext {
+            session: tokio::sync::Mutex::new(session),
+            last_used: Mutex::new(Instant::now()),
+        };
+        contexts.insert(id.clone(), Arc::new(context));
+        Ok(id)
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<LiveContext>> {
+        let mut contexts = self.contexts.lock().unwrap();
+        self.prune(&mut contexts);
+        contexts.get(id).cloned()
+    }
+
+    fn remove(&self, id: &str) -> bool {
+        let mut contexts = self.contexts.lock().unwrap();
+        self.prune(&mut contexts);
+        contexts.remove(id).is_some()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    pub init_code: Option<String>,
+    #[serde(default = "empty_inputs")]
+    pub inputs: Value,
+}
+
+#[derive(Deserialize)]
+pub struct EvalRequest {
+    pub code: String,
+    #[serde(default = "empty_inputs")]
+    pub inputs: Value,
+}
+
+#[derive(Serialize)]
+struct Created {
+    id: String,
+    // Of `init_code`, null without it
+    result: Value,
+}
+
+#[derive(Serialize)]
+struct Evaluated {
+    result: Value,
+}
+
+fn too_many(state: &AppState) -> Response {
+    let error = ErrorResponse {
+        error: "Too many contexts".to_string(),
+        message: format!("At most {} contexts can exist at a time", state.contexts.max_contexts),
+        ..Default::default()
+    };
+    (StatusCode::SERVICE_UNAVAILABLE, Json(error)).into_response()
+}
+
+fn not_found(id: &str) -> Response {
+    let error = ErrorResponse {
+        error: "Context not found".to_string(),
+        message: format!("No context with id {}, or it expired", id),
+        ..Default::default()
+    };
+    (StatusCode::NOT_FOUND, Json(error)).into_response()
+}
+
+fn failed(e: ExecError) -> Response {
+    let error = ErrorResponse {
+        error: e.kind.error().to_string(),
+        message: e.message,
+        thrown: e.js_error.as_ref().and_then(|js_error| js_error.thrown.clone()),
+        js_error: e.js_error.map(|js_error| *js_error),
+        ..Default::default()
+    };
+    (error_status(e.kind), Json(error)).into_response()
+}
+
+// The refusal of code or inputs over the size limits of /execute
+fn oversized(state: &AppState, code: &str, inputs: &Value) -> Option<Response> {
+    let too_large = |what, limit, max, actual| {
+        (StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorResponse::too_large(what, limit, max, Some(actual)))).into_response()
+    };
+    if code.len() > state.max_code_bytes {
+        return Some(too_large("code", "MAX_CODE_BYTES", state.max_code_bytes, code.len()));
+    }
+    let input_bytes = serde_json::to_vec(inputs).map_or(0, |inputs| inputs.len());
+    if input_bytes > state.max_inputs_bytes {
+        return Some(too_large("inputs", "MAX_INPUTS_BYTES", state.max_inputs_bytes, input_bytes));
+    }
+    None
+}
+
+pub async fn create_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    Json(req): Json<CreateRequest>,
+) -> Response {
+    if let Some(response) = oversized(&state, req.init_code.as_deref().unwrap_or(""), &req.inputs) {
+        return response;
+    }
+    if state.contexts.full() {
+        return too_many(&state);
+    }
+
+    let session = match Session::start(&state, Arc::new(|_, _| {}), request_id.0).await {
+        Ok(session) => session,
+        Err(e) => return failed(e),
+    };
+    let result = match &req.init_code {
+        Some(code) => match session.eval(&state, code, &req.inputs, || false).await {
+            Ok(result) => result,
+            Err(e) => return failed(e),
+        },
+        None => Value::Null,
+    };
+    match state.contexts.insert(session) {
+        Ok(id) => (StatusCode::CREATED, Json(Created { id, result })).into_response(),
+        Err(()) => too_many(&state),
+    }
+}
+
+pub async fn execute_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<EvalRequest>,
+) -> Response {
+    if let Some(response) = oversized(&state, &req.code, &req.inputs) {
+        return response;
+    }
+    let Some(context) = state.contexts.get(&id) else {
+        return not_found(&id);
+    };
+
+    let session = context.session.lock().await;
+    context.touch();
+    let outcome = session.eval(&state, &req.code, &req.inputs, || false).await;
+    context.touch();
+    drop(session);
+    match outcome {
+        Ok(result) => (StatusCode::OK, Json(Evaluated { result })).into_response(),
+        Err(e) => failed(e),
+    }
+}
+
+pub async fn delete_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.contexts.remove(&id) {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => not_found(&id),
+    }
+}
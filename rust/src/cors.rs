@@ -0,0 +1,64 @@
+// CORS for browser-based callers, off unless CORS_ALLOWED_ORIGINS is set.
+//
+// CORS_ALLOWED_ORIGINS is a comma-separated list of exact origins, or `*` for any
+// origin. Responses only carry `Access-Control-Allow-Origin` for allowed origins.
+// With CORS_ALLOW_CREDENTIALS=true the origins must be listed explicitly, since
+// browsers reject a wildcard on credentialed requests.
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use sandbox_core::config::Config;
+
+// Response headers scripts in the browser may read
+const EXPOSED_HEADERS: &[&str] = &[
+    "retry-after",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "x-request-id",
+];
+
+pub fn from_config(config: &Config) -> Result<Option<CorsLayer>, String> {
+    let origins = &config.cors_allowed_origins;
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let credentials = config.cors_allow_credentials;
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        if credentials {
+            return Err("CORS_ALLOWED_ORIGINS can't be * with CORS_ALLOW_CREDENTIALS=true".to_string());
+        }
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|_| format!("Invalid origin in CORS_ALLOWED_ORIGINS: {}", origin))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    let headers = config
+        .cors_allowed_headers
+        .iter()
+        .map(|name| {
+            HeaderName::try_from(name.as_str())
+                .map_err(|_| format!("Invalid header in CORS_ALLOWED_HEADERS: {}", name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers(headers)
+            .allow_credentials(credentials)
+            .expose_headers(EXPOSED_HEADERS.iter().map(|name| HeaderName::from_static(name)).collect::<Vec<_>>())
+            .max_age(Duration::from_secs(config.cors_max_age)),
+    ))
+}
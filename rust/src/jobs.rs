@@ -0,0 +1,315 @@
+// Asynchronous executions for code that runs longer than clients care to wait.
+//
+// POST /jobs checks the request, queues it and answers with a job id right away.
+// GET /jobs/{id} reports the status and, once finished, the result or error, and
+// DELETE /jobs/{id} cancels a queued or running job. Jobs run through the same code
+// path as /execute, at most JOBS_CONCURRENCY at a time. Finished jobs are kept for
+// JOB_RESULT_TTL_MS, and at most JOBS_MAX_STORED jobs are stored at once. Jobs
+// submitted with a `callback_url` are posted there when they finish (see callbacks).
+
+use crate::api::{check_request, error_status, execute, state_owner, ErrorResponse, ExecuteRequest, ExecuteResponse};
+use crate::body::Json;
+use crate::callbacks::Callbacks;
+use crate::request_id::RequestId;
+use crate::AppState;
+use axum::extract::{Extension, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+use sandbox_core::config::Config;
+use sandbox_core::ErrorKind;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct Job {
+    status: JobStatus,
+    submitted: Instant,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    outcome: Option<Result<ExecuteResponse, (StatusCode, ErrorResponse)>>,
+    task: Option<AbortHandle>,
+}
+
+pub struct JobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    // One permit per job allowed to run
+    slots: Arc<Semaphore>,
+    max_stored: usize,
+    ttl: Duration,
+    callbacks: Callbacks,
+}
+
+#[derive(Deserialize)]
+pub struct JobRequest {
+    #[serde(flatten)]
+    request: ExecuteRequest,
+    // Receives the job's status body once it finished
+    callback_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JobView<'a> {
+    job_id: &'a str,
+    status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a serde_json::Value>,
+    #[serde(rename = "unhandledRejections", skip_serializing_if = "<[String]>::is_empty")]
+    unhandled_rejections: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a ErrorResponse>,
+    meta: JobMeta<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobMeta<'a> {
+    // Time spent waiting for a slot, so far if the job is still queued
+    queued_ms: u64,
+    // Time spent running, so far if the job is still running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    // The execution's own metadata, with `include_meta`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    execution: Option<&'a crate::api::ExecutionMeta>,
+}
+
+impl JobStore {
+    pub fn from_config(config: &Config) -> Self {
+        JobStore {
+            jobs: Mutex::new(HashMap::new()),
+            slots: Arc::new(Semaphore::new(config.jobs_concurrency.max(1))),
+            max_stored: config.jobs_max_stored,
+            ttl: Duration::from_millis(config.job_result_ttl_ms),
+            callbacks: Callbacks::from_config(config).unwrap_or_else(|e| panic!("{}", e)),
+        }
+    }
+
+    fn expired(&self, job: &Job) -> bool {
+        job.finished.is_some_and(|finished| finished.elapsed() > self.ttl)
+    }
+
+    // Stores a new queued job, making room by dropping expired and then the oldest
+    // finished jobs. Fails when every stored job is still pending.
+    fn insert(&self, id: &str) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| !self.expired(job));
+        if jobs.len() >= self.max_stored {
+            let oldest = jobs
+                .iter()
+                .filter_map(|(id, job)| job.finished.map(|finished| (finished, id.clone())))
+                .min();
+            match oldest {
+                Some((_, oldest)) => {
+                    jobs.remove(&oldest);
+                }
+                None => return Err(format!("{} jobs are already queued or running", jobs.len())),
+            }
+        }
+        jobs.insert(
+            id.to_string(),
+            Job {
+                status: JobStatus::Queued,
+                submitted: Instant::now(),
+                started: None,
+                finished: None,
+                outcome: None,
+                task: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn update(&self, id: &str, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            update(job);
+        }
+    }
+
+    fn finish(&self, id: &str, outcome: Result<ExecuteResponse, (StatusCode, ErrorResponse)>) {
+        self.update(id, |job| {
+            // A cancelled job keeps its cancellation error
+            if job.finished.is_some() {
+                return;
+            }
+            job.status = if outcome.is_ok() { JobStatus::Succeeded } else { JobStatus::Failed };
+            job.finished = Some(Instant::now());
+            job.outcome = Some(outcome);
+            job.task = None;
+        });
+    }
+}
+
+fn not_found(id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "Job not found".to_string(),
+            message: format!("No job with id {}, or its result expired", id),
+            ..Default::default()
+        }),
+    )
+        .into_response()
+}
+
+// The job runs under the id of the request that submitted it
+pub async fn submit_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Json(JobRequest { request: mut req, callback_url }): Json<JobRequest>,
+) -> Response {
+    req.state_owner = state_owner(&headers);
+    req.tenant = state.tenants.resolve(&headers);
+    if let Err(e) = check_request(&state, &req) {
+        let (status, error) = *e;
+        return (status, Json(error)).into_response();
+    }
+    let callback_url = match callback_url {
+        Some(url) => match state.jobs.callbacks.check(&url).await {
+            Ok(url) => Some(url),
+            Err(message) => {
+                let error = ErrorResponse {
+                    error: "Invalid callback_url".to_string(),
+                    message,
+                    ..Default::default()
+                };
+                return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+            }
+        },
+        None => None,
+    };
+
+    let id = uuid::Uuid::new_v4().to_string();
+    if let Err(message) = state.jobs.insert(&id) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "Too many jobs".to_string(),
+                message,
+                ..Default::default()
+            }),
+        )
+            .into_response();
+    }
+
+    let task = tokio::spawn({
+        let state = state.clone();
+        let id = id.clone();
+        async move {
+            let Ok(permit) = state.jobs.slots.clone().acquire_owned().await else {
+                return;
+            };
+            state.jobs.update(&id, |job| {
+                job.status = JobStatus::Running;
+                job.started = Some(Instant::now());
+            });
+            let outcome = execute(&state, req, &request_id).await;
+            state.jobs.finish(&id, outcome);
+            // Retrying the callback doesn't keep another job from running
+            drop(permit);
+            if let Some(url) = callback_url {
+                let body = {
+                    let jobs = state.jobs.jobs.lock().unwrap();
+                    jobs.get(&id).and_then(|job| serde_json::to_vec(&view(&id, job)).ok())
+                };
+                if let Some(body) = body {
+                    state.jobs.callbacks.deliver(url, &id, body).await;
+                }
+            }
+        }
+        .in_current_span()
+    });
+    state.jobs.update(&id, |job| {
+        if job.finished.is_none() {
+            job.task = Some(task.abort_handle());
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(serde_json::json!({ "job_id": id }))).into_response()
+}
+
+pub async fn status_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let jobs = state.jobs.jobs.lock().unwrap();
+    let Some(job) = jobs.get(&id).filter(|job| !state.jobs.expired(job)) else {
+        return not_found(&id);
+    };
+    (StatusCode::OK, Json(view(&id, job))).into_response()
+}
+
+fn view<'a>(id: &'a str, job: &'a Job) -> JobView<'a> {
+    let (result, unhandled_rejections, error, execution) = match &job.outcome {
+        Some(Ok(response)) => (
+            Some(&response.result),
+            response.unhandled_rejections.as_slice(),
+            None,
+            response.meta.as_ref(),
+        ),
+        Some(Err((_, error))) => (None, error.unhandled_rejections.as_slice(), Some(error), error.meta.as_ref()),
+        None => (None, &[][..], None, None),
+    };
+    let queued_until = job.started.or(job.finished).unwrap_or_else(Instant::now);
+    JobView {
+        job_id: id,
+        status: job.status,
+        result,
+        unhandled_rejections,
+        error,
+        meta: JobMeta {
+            queued_ms: queued_until.duration_since(job.submitted).as_millis() as u64,
+            duration_ms: job.started.map(|started| {
+                let until = job.finished.unwrap_or_else(Instant::now);
+                until.duration_since(started).as_millis() as u64
+            }),
+            execution,
+        },
+    }
+}
+
+// Aborting the job's task drops its execution, which interrupts the script the same
+// way a client disconnecting from /execute does
+pub async fn cancel_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let mut jobs = state.jobs.jobs.lock().unwrap();
+    let Some(job) = jobs.get_mut(&id).filter(|job| !state.jobs.expired(job)) else {
+        return not_found(&id);
+    };
+    if job.finished.is_some() {
+        return (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "Job finished".to_string(),
+                message: format!("Job {} already finished and can't be cancelled", id),
+                ..Default::default()
+            }),
+        )
+            .into_response();
+    }
+
+    if let Some(task) = job.task.take() {
+        task.abort();
+    }
+    job.status = JobStatus::Failed;
+    job.finished = Some(Instant::now());
+    job.outcome = Some(Err((
+        error_status(ErrorKind::Interrupted),
+        ErrorResponse {
+            error: ErrorKind::Interrupted.error().to_string(),
+            message: "Job was cancelled".to_string(),
+            ..Default::default()
+        },
+    )));
+    (StatusCode::OK, Json(serde_json::json!({ "job_id": id, "status": job.status }))).into_response()
+}
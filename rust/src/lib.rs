@@ -0,0 +1,656 @@
+// The HTTP server: its state, routes and handlers. `main` parses the arguments and
+// either serves or runs a single script; tests build the router in-process.
+
+use axum::{
+    extract::{DefaultBodyLimit, Extension, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+use sandbox_core::cancel::Cancellation;
+use sandbox_core::circuit::CircuitBreakers;
+use sandbox_core::engine::{execute_js_with_quickjs, RejectionLog};
+use sandbox_core::metrics::METRICS;
+use sandbox_core::prelude_scripts::PreludeFile;
+use sandbox_core::{analyze, surface, validate, Config, Executor, FetchSession};
+
+mod api;
+mod api_version;
+mod audit;
+mod body;
+mod callbacks;
+pub mod cli;
+mod contexts;
+mod cors;
+mod jobs;
+mod listen;
+mod ndjson;
+pub mod logging;
+mod openapi;
+pub mod quotas;
+mod readiness;
+mod rate_limit;
+mod request_id;
+mod schema;
+mod session;
+mod shutdown;
+mod telemetry;
+mod tenants;
+mod tls;
+mod version;
+
+use audit::Audit;
+use api::{execute, execute_all, state_owner, BatchRequest, BatchResponse, BatchResult, ErrorResponse, ExecuteRequest, MapMeta, MapRequest, MapResponse};
+use body::{BodyLimit, Format, Json, Negotiated};
+use contexts::ContextStore;
+use jobs::JobStore;
+use listen::Listeners;
+use quotas::Quotas;
+use rate_limit::RateLimiter;
+use readiness::{NotReady, Readiness};
+use request_id::RequestId;
+use tenants::Tenants;
+
+// Run by the deep health check; the result must come back as {"sum":2}
+const HEALTH_CHECK_CODE: &str = "JSON.parse(JSON.stringify({ sum: 1 + 1 }))";
+// Suggested to clients turned away because all execution slots are busy
+const RETRY_AFTER_SECS: u64 = 1;
+
+#[derive(Clone)]
+pub struct AppState {
+    // Runs the executions, with the limits that apply to all of them
+    executor: Arc<Executor>,
+    // Jobs accepted by one /execute/batch request (MAX_BATCH_JOBS)
+    max_batch_jobs: usize,
+    // Jobs of a batch or input sets of a map executed at the same time (BATCH_PARALLELISM)
+    batch_parallelism: usize,
+    // Input sets accepted by one /execute/map request (MAX_INPUT_SETS)
+    max_input_sets: usize,
+    // Sizes of `code` and of the serialized `inputs` (MAX_CODE_BYTES, MAX_INPUTS_BYTES)
+    max_code_bytes: usize,
+    max_inputs_bytes: usize,
+    // The longest TTL of a cached result (RESULT_CACHE_MAX_TTL_SECONDS)
+    result_cache_max_ttl_seconds: u64,
+    // One permit per open WebSocket session, MAX_SESSIONS in total
+    sessions: Arc<Semaphore>,
+    max_sessions: usize,
+    // Sessions without messages for this long are closed (SESSION_IDLE_TIMEOUT_MS)
+    session_idle_timeout: Duration,
+    // Executions submitted to /jobs
+    jobs: Arc<JobStore>,
+    // Contexts created with POST /contexts
+    contexts: Arc<ContextStore>,
+    readiness: Arc<Readiness>,
+    // Whether code and inputs are logged at debug level (LOG_CODE)
+    log_code: bool,
+    // Time the deep health check's canary may take (HEALTH_CHECK_TIMEOUT_MS)
+    health_check_timeout: Duration,
+    // Where the records of executions go (AUDIT_FILE, AUDIT_URL)
+    audit: Arc<Audit>,
+    // Ids of the API keys that may ask for priority "interactive" (INTERACTIVE_API_KEYS)
+    interactive_keys: Arc<HashSet<String>>,
+    // The `[tenants]` of the config file, with their limits and usage
+    tenants: Arc<Tenants>,
+    // What each API key used of its QUOTA_DAILY_* and QUOTA_MONTHLY_* budgets
+    quotas: Arc<Quotas>,
+    // Results streamed as NDJSON without being asked to (STREAM_RESULT_BYTES), 0 for none
+    stream_result_bytes: usize,
+}
+
+impl AppState {
+    pub fn executor(&self) -> &Arc<Executor> {
+        &self.executor
+    }
+
+    pub fn quotas(&self) -> &Arc<Quotas> {
+        &self.quotas
+    }
+
+    // What shutdown does to /readyz, before it waits for the executions
+    pub fn start_draining(&self) {
+        self.readiness.start_draining();
+    }
+}
+
+#[derive(Deserialize)]
+struct ValidateRequest {
+    code: String,
+    // Check the code as an ES module, as `module` on /execute would run it
+    #[serde(default)]
+    module: bool,
+}
+
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: String,
+    // Why the server isn't ready, when it isn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    executions: ExecutionLoad,
+    // Result of the canary script, with `deep`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    engine: Option<EngineCheck>,
+}
+
+#[derive(Deserialize)]
+struct HealthQuery {
+    #[serde(default)]
+    deep: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EngineCheck {
+    ok: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExecutionLoad {
+    in_flight: usize,
+    queued: usize,
+    max_concurrent: usize,
+}
+
+async fn execute_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Negotiated { body: mut req, format, files }: Negotiated<ExecuteRequest>,
+) -> Response {
+    req.state_owner = state_owner(&headers);
+    req.tenant = state.tenants.resolve(&headers);
+    req.files = Arc::new(files);
+    if req.stream_result && ndjson::accepted(&headers) {
+        req.raw_result = true;
+        return ndjson::respond(state, req, request_id);
+    }
+    req.raw_result = state.stream_result_bytes > 0 && format == Format::Json;
+    match execute(&state, req, &request_id).await {
+        Ok(response) if response.result_json.as_ref().is_some_and(|json| json.len() >= state.stream_result_bytes) => {
+            ndjson::streamed(Ok(response))
+        }
+        // Smaller results are answered as usual
+        Ok(mut response) => {
+            if let Some(json) = response.result_json.take() {
+                response.result = serde_json::from_str(&json).unwrap_or_default();
+            }
+            format.respond(StatusCode::OK, &response)
+        }
+        Err((status, error)) if status == StatusCode::TOO_MANY_REQUESTS => {
+            // Until the quota resets, for one that is used up
+            let retry_after = error.quota.as_ref().map_or(RETRY_AFTER_SECS, |quota| quota.resets_in_secs);
+            ([(header::RETRY_AFTER, retry_after.to_string())], format.respond(status, &error)).into_response()
+        }
+        Err((status, error)) => format.respond(status, &error),
+    }
+}
+
+// Runs the jobs concurrently, each one as if it had been sent to /execute. A failing
+// job only fails its own result.
+async fn batch_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Json(req): Json<BatchRequest>,
+) -> Response {
+    if req.jobs.len() > state.max_batch_jobs {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Batch too large".to_string(),
+                message: format!(
+                    "Batch has {} jobs, exceeding the limit of {}",
+                    req.jobs.len(),
+                    state.max_batch_jobs
+                ),
+                ..Default::default()
+            }),
+        ).into_response();
+    }
+    
+    let owner = state_owner(&headers);
+    let tenant = state.tenants.resolve(&headers);
+    let (ids, requests): (Vec<_>, Vec<_>) = req
+        .jobs
+        .into_iter()
+        .map(|job| (job.id, ExecuteRequest { state_owner: owner.clone(), tenant: tenant.clone(), ..job.request }))
+        .unzip();
+    let results = execute_all(&state, requests, &request_id)
+        .await
+        .into_iter()
+        .zip(ids)
+        .map(|(execution, id)| BatchResult { id, execution })
+        .collect();
+    
+    (StatusCode::OK, Json(BatchResponse { results })).into_response()
+}
+
+// Runs the code once per input set, like a batch of jobs that only differ in their
+// inputs. The code is compiled once and taken from the code cache after that.
+async fn map_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Json(req): Json<MapRequest>,
+) -> Response {
+    let started = Instant::now();
+    let invalid = |error: &str, message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+                message,
+                ..Default::default()
+            }),
+        ).into_response()
+    };
+    
+    if req.input_sets.len() > state.max_input_sets {
+        return invalid(
+            "Too many input sets",
+            format!(
+                "Request has {} input sets, exceeding the limit of {}",
+                req.input_sets.len(),
+                state.max_input_sets
+            ),
+        );
+    }
+    if req.options.contains_key("inputs") {
+        return invalid("Invalid inputs", "Use input_sets instead of inputs".to_string());
+    }
+    
+    // Parsed once as an /execute request, then copied for every input set
+    let mut options = req.options;
+    options.insert("inputs".to_string(), Value::Object(Default::default()));
+    let mut template: ExecuteRequest = match serde_json::from_value(Value::Object(options)) {
+        Ok(template) => template,
+        Err(e) => return invalid("Invalid request", e.to_string()),
+    };
+    template.state_owner = state_owner(&headers);
+    template.tenant = state.tenants.resolve(&headers);
+    let requests = req
+        .input_sets
+        .into_iter()
+        .map(|inputs| ExecuteRequest { inputs, ..template.clone() })
+        .collect();
+    
+    let results = execute_all(&state, requests, &request_id).await;
+    let succeeded = results.iter().filter(|result| result.ok).count();
+    let meta = MapMeta {
+        duration_ms: started.elapsed().as_millis() as u64,
+        succeeded,
+        failed: results.len() - succeeded,
+    };
+    (StatusCode::OK, Json(MapResponse { results, meta })).into_response()
+}
+
+// Cheap by default; `?deep=true` also runs a canary script through the engine
+async fn health_handler(State(state): State<AppState>, Query(query): Query<HealthQuery>) -> Response {
+    let not_ready = state.readiness.check(state.executor.admission()).err();
+    // The canary only runs once the server is otherwise ready
+    let engine = match query.deep && not_ready.is_none() {
+        true => Some(check_engine(&state).await),
+        false => None,
+    };
+    let status = match (&not_ready, &engine) {
+        (Some(_), _) => "not ready",
+        (None, Some(engine)) if !engine.ok => "unhealthy",
+        _ => "ok",
+    };
+    let code = if status == "ok" { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(HealthResponse {
+        status: status.to_string(),
+        condition: not_ready.as_ref().map(NotReady::condition),
+        message: not_ready.as_ref().map(NotReady::message),
+        executions: ExecutionLoad {
+            in_flight: state.executor.admission().in_flight(),
+            queued: state.executor.admission().queued(),
+            max_concurrent: state.executor.admission().max_concurrent(),
+        },
+        engine,
+    })).into_response()
+}
+
+// Up as long as the process answers at all; readiness is /readyz
+async fn liveness_handler() -> Response {
+    Json(serde_json::json!({ "status": "ok" })).into_response()
+}
+
+// Runs the canary on a runtime from the pool, like an execution would, but without
+// taking an execution slot, so a busy server still reports a working engine
+async fn check_engine(state: &AppState) -> EngineCheck {
+    let started = Instant::now();
+    let timeout = state.health_check_timeout;
+    let cancellation = Cancellation::new(timeout);
+    let canary = async {
+        let lease = state.executor.runtimes().acquire().await?;
+        let session = Arc::new(FetchSession::new(0, "health-check".to_string()));
+        let options = state.executor.options(cancellation.clone());
+        let outcome = execute_js_with_quickjs(
+            lease.runtime(),
+            HEALTH_CHECK_CODE,
+            &api::empty_inputs(),
+            state.executor.http(),
+            session,
+            &RejectionLog::default(),
+            &options,
+        )
+        .await;
+        lease.release().await;
+        outcome
+    };
+    let timed_out = || format!("Canary script didn't finish within {} ms", timeout.as_millis());
+    let error = match tokio::time::timeout(timeout, canary).await {
+        Ok(Ok(result)) if result == serde_json::json!({ "sum": 2 }) => None,
+        Ok(Ok(result)) => Some(format!("Canary script returned {} instead of {{\"sum\":2}}", result)),
+        Ok(Err(_)) if cancellation.interruption().is_some() => Some(timed_out()),
+        Ok(Err(e)) => Some(format!("Canary script failed: {}", e.message)),
+        Err(_) => Some(timed_out()),
+    };
+    if let Some(error) = &error {
+        tracing::warn!("Deep health check failed: {}", error);
+    }
+    EngineCheck {
+        ok: error.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+async fn metrics_handler(State(state): State<AppState>) -> Response {
+    let mut metrics = METRICS.render(state.executor.admission().in_flight(), state.executor.admission().queued());
+    metrics.push_str(&state.tenants.render_metrics());
+    if let Some(circuits) = state.executor.circuits() {
+        metrics.push_str(&circuits.render_metrics());
+    }
+    (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics).into_response()
+}
+
+// The circuit breakers of the outbound hosts that failed lately
+async fn circuits_handler(State(state): State<AppState>) -> Response {
+    Json(state.executor.circuits().map(CircuitBreakers::report).unwrap_or_default()).into_response()
+}
+
+// Compiles the code without running it. Syntax errors are a successful validation
+// with `valid: false`.
+async fn validate_handler(State(state): State<AppState>, Json(req): Json<ValidateRequest>) -> Response {
+    if req.code.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Invalid code parameter".to_string(),
+                message: "Code cannot be empty".to_string(),
+                ..Default::default()
+            }),
+        ).into_response();
+    }
+    
+    match validate::validate(&req.code, req.module, state.executor.limits().js_max_stack_bytes) {
+        Ok(validation) => (StatusCode::OK, Json(validation)).into_response(),
+        Err(message) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Validation failed".to_string(),
+                message,
+                ..Default::default()
+            }),
+        ).into_response(),
+    }
+}
+
+// Lists the globals the code refers to without running it. Like validation, syntax
+// errors are reported in the response.
+async fn analyze_handler(State(state): State<AppState>, Json(req): Json<ValidateRequest>) -> Response {
+    if req.code.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Invalid code parameter".to_string(),
+                message: "Code cannot be empty".to_string(),
+                ..Default::default()
+            }),
+        ).into_response();
+    }
+
+    match analyze::analyze(&req.code, req.module, state.executor.limits().js_max_stack_bytes) {
+        Ok(analysis) => (StatusCode::OK, Json(analysis)).into_response(),
+        Err(message) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "Analysis failed".to_string(),
+                message,
+                ..Default::default()
+            }),
+        ).into_response(),
+    }
+}
+
+// What scripts get from the server: the ENV values, the globals and the limits an
+// execution runs with unless the request tightens them
+#[derive(Serialize)]
+struct FunctionsResponse<'a> {
+    env: &'a BTreeMap<String, String>,
+    globals: &'static [surface::Global],
+    limits: FunctionsLimits,
+    // The PRELUDE_DIR scripts evaluated before the code, whose globals aren't listed
+    #[serde(rename = "preludeScripts")]
+    prelude_scripts: &'a [PreludeFile],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionsLimits {
+    max_requests: u32,
+    timeout_ms: u64,
+    max_timeout_ms: u64,
+    cpu_ms: u64,
+    sleep_budget_ms: u64,
+    memory_bytes: usize,
+    max_result_bytes: usize,
+    max_fetch_body_bytes: usize,
+    max_fetch_total_bytes: usize,
+    max_code_bytes: usize,
+    max_inputs_bytes: usize,
+    disable_dynamic_eval: bool,
+    network_disabled: bool,
+}
+
+async fn functions_handler(State(state): State<AppState>) -> Response {
+    let limits = state.executor.limits();
+    Json(FunctionsResponse {
+        env: state.executor.env(),
+        globals: surface::GLOBALS,
+        limits: FunctionsLimits {
+            max_requests: limits.max_requests,
+            timeout_ms: limits.execution_timeout.as_millis() as u64,
+            max_timeout_ms: limits.max_execution_timeout.as_millis() as u64,
+            cpu_ms: limits.cpu_budget.as_millis() as u64,
+            sleep_budget_ms: limits.sleep_budget.as_millis() as u64,
+            memory_bytes: limits.memory_bytes,
+            max_result_bytes: limits.max_result_bytes,
+            max_fetch_body_bytes: limits.max_fetch_body_bytes,
+            max_fetch_total_bytes: limits.max_fetch_total_bytes,
+            max_code_bytes: state.max_code_bytes,
+            max_inputs_bytes: state.max_inputs_bytes,
+            disable_dynamic_eval: limits.disable_dynamic_eval,
+            network_disabled: limits.network_disabled,
+        },
+        prelude_scripts: state.executor.prelude_scripts().files(),
+    })
+    .into_response()
+}
+
+async fn version_handler(State(state): State<AppState>) -> Response {
+    let prelude_scripts = state.executor.prelude_scripts().files();
+    Json(version::build_info(state.readiness.warm_up_duration(), prelude_scripts)).into_response()
+}
+
+// Shared by the server and `exec`
+pub fn app_state(config: &Config, executor: Executor) -> AppState {
+    AppState {
+        executor: Arc::new(executor),
+        max_batch_jobs: config.max_batch_jobs,
+        batch_parallelism: config.batch_parallelism,
+        max_input_sets: config.max_input_sets,
+        max_code_bytes: config.max_code_bytes,
+        max_inputs_bytes: config.max_inputs_bytes,
+        result_cache_max_ttl_seconds: config.result_cache_max_ttl_seconds,
+        sessions: Arc::new(Semaphore::new(config.max_sessions)),
+        max_sessions: config.max_sessions,
+        session_idle_timeout: Duration::from_millis(config.session_idle_timeout_ms),
+        jobs: Arc::new(JobStore::from_config(config)),
+        contexts: Arc::new(ContextStore::from_config(config)),
+        readiness: Arc::new(Readiness::from_config(config)),
+        log_code: config.log_code,
+        health_check_timeout: Duration::from_millis(config.health_check_timeout_ms),
+        audit: Arc::new(Audit::from_config(config).unwrap_or_else(|e| panic!("{}", e))),
+        interactive_keys: Arc::new(config.interactive_api_keys.iter().map(|key| api::api_key_id(key)).collect()),
+        tenants: Arc::new(Tenants::from_config(config).unwrap_or_else(|e| panic!("{}", e))),
+        quotas: Arc::new(Quotas::from_config(config).unwrap_or_else(|e| panic!("{}", e))),
+        stream_result_bytes: config.stream_result_bytes,
+    }
+}
+
+// All routes with their middleware, as served on every listener
+pub fn router(config: &Config, state: AppState) -> Router {
+    let body_limit = BodyLimit::from_config(config);
+    let limiter = RateLimiter::from_config(config);
+    if let Some(limiter) = &limiter {
+        limiter.spawn_cleanup();
+    }
+    // A /v2 would be nested next to v1 with routes of its own, and listed in
+    // api_version::SUPPORTED
+    let v1 = v1_routes(limiter);
+    // The description of the API, outside of its versions
+    let app = Router::new().route("/openapi.json", get(openapi::document_handler));
+    let app = match config.openapi_docs {
+        true => app.route("/docs", get(openapi::docs_handler)),
+        false => app,
+    };
+    let app = app
+        .nest(
+            "/v1",
+            v1.clone().layer(middleware::from_fn(|request: Request, next: Next| api_version::envelope("v1", request, next))),
+        )
+        .merge(v1.layer(middleware::from_fn(api_version::deprecated)))
+        .fallback(api_version::not_found)
+        .with_state(state)
+        .layer(DefaultBodyLimit::max(body_limit.max_bytes))
+        .layer(middleware::from_fn_with_state(body_limit, body::middleware))
+        .layer(middleware::from_fn(request_id::middleware));
+    // Outermost, so preflights and rejections carry CORS headers too
+    let app = match cors::from_config(config).unwrap_or_else(|e| panic!("{}", e)) {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+    app.layer(middleware::from_fn(version::middleware))
+}
+
+// The endpoints of API v1, relative to its prefix
+fn v1_routes(limiter: Option<Arc<RateLimiter>>) -> Router<AppState> {
+    let app = Router::new()
+        .route("/execute", post(execute_handler))
+        .route("/execute/batch", post(batch_handler))
+        .route("/execute/map", post(map_handler))
+        .route("/validate", post(validate_handler))
+        .route("/analyze", post(analyze_handler))
+        .route("/functions", get(functions_handler))
+        .route("/session", get(session::session_handler))
+        .route("/jobs", post(jobs::submit_handler))
+        .route("/jobs/:id", get(jobs::status_handler).delete(jobs::cancel_handler))
+        .route("/contexts", post(contexts::create_handler))
+        .route("/contexts/:id", delete(contexts::delete_handler))
+        .route("/contexts/:id/execute", post(contexts::execute_handler))
+        .route("/tenants/:id/usage", get(tenants::usage_handler))
+        .route("/usage", get(quotas::usage_handler));
+    // Health checks and metrics stay outside of the rate limit
+    let app = match limiter {
+        Some(limiter) => app.route_layer(middleware::from_fn_with_state(limiter, rate_limit::middleware)),
+        None => app,
+    };
+    app.route("/health", get(health_handler))
+        .route("/livez", get(liveness_handler))
+        .route("/readyz", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/admin/circuits", get(circuits_handler))
+        .route("/version", get(version_handler))
+}
+
+// Fills the runtime pool and compiles the prelude modules, then reports the server
+// ready. A warm-up that fails or takes longer than `timeout` leaves it not ready.
+pub async fn warm_up(state: AppState, timeout: Duration) {
+    let started = Instant::now();
+    let error = match tokio::time::timeout(timeout, state.executor.warm_up()).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e),
+        Err(_) => Some(format!("didn't finish within WARM_UP_TIMEOUT_MS ({} ms)", timeout.as_millis())),
+    };
+    match error {
+        None => {
+            let duration = started.elapsed();
+            tracing::info!(warm_up_ms = duration.as_millis() as u64, "Warmed up the runtime pool");
+            METRICS.warm_up(duration);
+            state.readiness.warmed_up(duration);
+        }
+        Some(error) => {
+            tracing::error!("Warming up failed, the server stays not ready: {}", error);
+            state.readiness.warm_up_failed(error);
+        }
+    }
+}
+
+pub async fn serve(config: Config) {
+    logging::init(&config, std::io::stdout);
+    tracing::info!(version = version::full(), config = %config.redacted(), "Effective configuration");
+    METRICS.use_host_label(config.metrics_host_label);
+    
+    let listen = Listeners::from_config(&config).unwrap_or_else(|e| panic!("{}", e));
+    let executor = Executor::new(&config).unwrap_or_else(|e| panic!("{}", e));
+    executor.check_prelude_scripts().await.unwrap_or_else(|e| panic!("{}", e));
+    let state = app_state(&config, executor);
+    
+    // Ready once the runtime pool is filled and the prelude compiled
+    tokio::spawn(warm_up(state.clone(), Duration::from_millis(config.warm_up_timeout_ms)));
+    let readiness = state.readiness.clone();
+    let admission = state.executor.admission().clone();
+    
+    let app = router(&config, state);
+    
+    // Run the server, on every listener until shutdown
+    let (stop, stopped) = tokio::sync::watch::channel(());
+    let mut servers = tokio::task::JoinSet::new();
+    for address in &listen.addresses {
+        let listener = listen.bind(address).await.unwrap_or_else(|e| panic!("{}", e));
+        tracing::info!("Server listening on {}", address);
+        let mut stopped = stopped.clone();
+        servers.spawn(listen::serve(listener, app.clone(), async move {
+            let _ = stopped.changed().await;
+        }));
+    }
+    
+    shutdown::signal().await;
+    readiness.start_draining();
+    let _ = stop.send(());
+    let grace = Duration::from_secs(config.shutdown_grace_seconds);
+    tracing::info!("Shutting down, waiting up to {} s for executions to finish", grace.as_secs());
+    let summary = shutdown::drain(&admission, grace).await;
+    // Interrupted executions still get to answer
+    let _ = tokio::time::timeout(shutdown::STRAGGLER_WAIT, servers.join_all()).await;
+    tracing::info!(finished = summary.finished, aborted = summary.aborted, "Shutdown complete");
+}
@@ -0,0 +1,211 @@
+// Where the server listens, set with LISTEN.
+//
+// LISTEN is a comma-separated list of `tcp://host:port` and `unix:///path/to.sock`
+// addresses, all served by the same app, e.g. `tcp://[::1]:3000,unix:///run/sandbox.sock`.
+// Without it the server listens on 0.0.0.0:PORT (default 3000). A stale socket file
+// left behind by an earlier run is replaced on start and the socket is removed
+// again on shutdown; LISTEN_SOCKET_MODE (octal, e.g. 660) sets its permissions.
+// Clients on a Unix socket have no IP address, so only their API key rate limits them.
+// With TLS configured, TCP listeners serve HTTPS (see tls).
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+
+use sandbox_core::config::Config;
+use crate::tls::Tls;
+
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Address {
+    fn parse(address: &str) -> Result<Self, String> {
+        if let Some(host_port) = address.strip_prefix("tcp://") {
+            host_port
+                .parse()
+                .map(Address::Tcp)
+                .map_err(|_| format!("LISTEN address {} isn't an IP address with a port", address))
+        } else if let Some(path) = address.strip_prefix("unix://") {
+            match path.starts_with('/') {
+                true => Ok(Address::Unix(PathBuf::from(path))),
+                false => Err(format!("LISTEN address {} needs an absolute socket path", address)),
+            }
+        } else {
+            Err(format!("LISTEN address {} must start with tcp:// or unix://", address))
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Address::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+pub struct Listeners {
+    pub addresses: Vec<Address>,
+    socket_mode: Option<u32>,
+    tls: Option<Arc<Tls>>,
+}
+
+impl Listeners {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let addresses = match config.listen.is_empty() {
+            false => config.listen.iter().map(|address| Address::parse(address.trim())).collect::<Result<Vec<_>, _>>()?,
+            true => vec![Address::Tcp(SocketAddr::from(([0, 0, 0, 0], config.port)))],
+        };
+        let socket_mode = match config.listen_socket_mode.trim() {
+            "" => None,
+            mode => Some(
+                u32::from_str_radix(mode, 8)
+                    .map_err(|_| format!("LISTEN_SOCKET_MODE {} isn't an octal file mode", mode))?,
+            ),
+        };
+        let tls = Tls::from_config(config)?;
+        if let Some(tls) = &tls {
+            tls.spawn_reload();
+        }
+        Ok(Listeners { addresses, socket_mode, tls })
+    }
+
+    pub async fn bind(&self, address: &Address) -> Result<Listener, String> {
+        match address {
+            Address::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+                Ok(match &self.tls {
+                    Some(tls) => Listener::Tls(listener, tls.clone()),
+                    None => Listener::Tcp(listener),
+                })
+            }
+            Address::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+                if let Some(mode) = self.socket_mode {
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                        .map_err(|e| format!("Failed to set the mode of {}: {}", path.display(), e))?;
+                }
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+}
+
+// A socket file nobody accepts connections on is left over from an earlier run
+fn remove_stale_socket(path: &PathBuf) -> Result<(), String> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} exists and isn't a socket", path.display()));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(format!("{} is in use by another server", path.display()));
+    }
+    std::fs::remove_file(path).map_err(|e| format!("Failed to remove the stale socket {}: {}", path.display(), e))
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, Arc<Tls>),
+    Unix(UnixListener, PathBuf),
+}
+
+// Serves `app` until `shutdown` resolves and the open connections are done
+pub async fn serve(listener: Listener, app: Router, shutdown: impl Future<Output = ()> + Send + 'static) {
+    match listener {
+        // Peer addresses key the rate limit
+        Listener::Tcp(listener) => {
+            let result = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown)
+                .await;
+            if let Err(e) = result {
+                tracing::error!("Server failed: {}", e);
+            }
+        }
+        Listener::Tls(listener, tls) => {
+            let (listener, app, tls) = (&listener, &app, &tls);
+            let accept = move || async move {
+                let (stream, peer) = listener.accept().await?;
+                let handshake = tls.acceptor().accept(stream);
+                Ok((handshake, app.clone().layer(Extension(ConnectInfo(peer)))))
+            };
+            serve_connections(accept, shutdown).await;
+        }
+        Listener::Unix(listener, path) => {
+            let (listener, app) = (&listener, &app);
+            let accept = move || async move {
+                let (stream, _) = listener.accept().await?;
+                Ok((std::future::ready(Ok(stream)), app.clone()))
+            };
+            serve_connections(accept, shutdown).await;
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// axum::serve only takes plain TCP listeners, so TLS and Unix socket connections are
+// served by hyper directly, with upgrades for sessions. `accept` waits for the next
+// connection and returns its handshake along with the app serving it.
+async fn serve_connections<A, F, H, S>(mut accept: A, shutdown: impl Future<Output = ()>)
+where
+    A: FnMut() -> F,
+    F: Future<Output = io::Result<(H, Router)>>,
+    H: Future<Output = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let (handshake, app) = tokio::select! {
+            accepted = accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // e.g. out of file descriptors; wait for some to be closed
+                    tracing::error!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match handshake.await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::debug!("Handshake failed: {}", e);
+                    return;
+                }
+            };
+            let connection = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(app))
+                .into_owned();
+            if let Err(e) = watcher.watch(connection).await {
+                tracing::debug!("Connection failed: {}", e);
+            }
+        });
+    }
+    graceful.shutdown().await;
+}
@@ -0,0 +1,86 @@
+// Results of /execute streamed as NDJSON, without parsing them into a value first.
+//
+// The response is `application/x-ndjson` in chunked transfer encoding, with two
+// records: `{"result": ...}`, the JSON text QuickJS produced written out in chunks
+// as it is, and one with `"done": true` and the rest of the response. Streams the
+// request asked for start before the code ran, so their status is always 200 and
+// a failure ends them with `"ok": false`, the status /execute would have answered
+// with and the error body instead.
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::{stream, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+
+use crate::api::{execute, ErrorResponse, ExecuteRequest, ExecuteResponse};
+use crate::request_id::RequestId;
+use crate::AppState;
+
+const NDJSON: &str = "application/x-ndjson";
+
+// Bytes of the result per chunk of the body
+const CHUNK_BYTES: usize = 64 * 1024;
+
+// Whether `Accept` lists NDJSON
+pub fn accepted(headers: &HeaderMap) -> bool {
+    let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    accept
+        .split(',')
+        .any(|media| media.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(NDJSON))
+}
+
+// Sends the headers right away and the records once the execution finished
+pub fn respond(state: AppState, req: ExecuteRequest, request_id: RequestId) -> Response {
+    let records = stream::once(async move { records(execute(&state, req, &request_id).await) }).flat_map(stream::iter);
+    body(records)
+}
+
+// The response of an execution that already finished
+pub fn streamed(outcome: Result<ExecuteResponse, (StatusCode, ErrorResponse)>) -> Response {
+    body(stream::iter(records(outcome)))
+}
+
+fn body(records: impl Stream<Item = Bytes> + Send + 'static) -> Response {
+    (
+        [(header::CONTENT_TYPE, HeaderValue::from_static(NDJSON))],
+        Body::from_stream(records.map(Ok::<_, Infallible>)),
+    )
+        .into_response()
+}
+
+fn records(outcome: Result<ExecuteResponse, (StatusCode, ErrorResponse)>) -> Vec<Bytes> {
+    let (mut records, end) = match outcome {
+        Ok(mut response) => {
+            // Parsed after all when the result had to be validated against `output_schema`
+            let result = match response.result_json.take() {
+                Some(json) => json,
+                None => std::mem::take(&mut response.result).to_string(),
+            };
+            let mut records = vec![Bytes::from_static(b"{\"result\":")];
+            let result = Bytes::from(result);
+            let chunks = (0..result.len()).step_by(CHUNK_BYTES);
+            records.extend(chunks.map(|start| result.slice(start..(start + CHUNK_BYTES).min(result.len()))));
+            records.push(Bytes::from_static(b"}\n"));
+            let mut rest = serde_json::to_value(&response).unwrap_or_else(|_| json!({}));
+            if let Some(rest) = rest.as_object_mut() {
+                rest.remove("result");
+            }
+            (records, end(rest, true, StatusCode::OK))
+        }
+        Err((status, error)) => (Vec::new(), end(serde_json::to_value(&error).unwrap_or_else(|_| json!({})), false, status)),
+    };
+    records.push(Bytes::from(format!("{}\n", end)));
+    records
+}
+
+// The last record: the rest of the response or the error body, marked as the end
+fn end(mut record: Value, ok: bool, status: StatusCode) -> Value {
+    if let Some(record) = record.as_object_mut() {
+        record.insert("done".to_string(), Value::Bool(true));
+        record.insert("ok".to_string(), Value::Bool(ok));
+        record.insert("status".to_string(), status.as_u16().into());
+    }
+    record
+}
@@ -0,0 +1,917 @@
+// The OpenAPI 3.1 description of API v1, served at GET /openapi.json, and the
+// Swagger UI at /docs that renders it (OPENAPI_DOCS).
+//
+// The schemas mirror the request and response types of the handlers field by
+// field, with their serde names; tests/openapi.rs checks real responses against
+// them, so a field added to a type has to be added here too.
+
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// The page of /docs, which loads Swagger UI from its CDN
+const DOCS_PAGE: &str = r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>JS Execution Service API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+pub async fn document_handler() -> Response {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    let body = DOCUMENT.get_or_init(|| document().to_string());
+    ([(header::CONTENT_TYPE, "application/json")], body.as_str()).into_response()
+}
+
+pub async fn docs_handler() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "JS Execution Service",
+            "version": VERSION,
+            "description": "Runs JavaScript in a QuickJS sandbox. JSON objects returned under /v1 carry \
+                `apiVersion`; the unprefixed paths are deprecated aliases of /v1.",
+        },
+        "servers": [{ "url": "/v1" }],
+        "paths": paths(),
+        "components": {
+            "schemas": schemas(),
+            "responses": error_responses(),
+            "parameters": {
+                "JobId": path_parameter("id", "The job_id POST /jobs answered with"),
+                "ContextId": path_parameter("id", "The id POST /contexts answered with"),
+                "TenantId": path_parameter("id", "A tenant id from the config file"),
+            },
+        },
+    })
+}
+
+fn paths() -> Value {
+    let execute_request = json!({
+        "required": true,
+        "content": {
+            "application/json": { "schema": schema_ref("ExecuteRequest") },
+            "application/msgpack": { "schema": schema_ref("ExecuteRequest") },
+            // `code` as text, the other fields as JSON text, and files
+            "multipart/form-data": { "schema": { "type": "object", "additionalProperties": true } },
+        },
+    });
+    let execute_ok = json!({
+        "description": "The result, in MessagePack when Accept asks for it, or streamed as NDJSON",
+        "content": {
+            "application/json": { "schema": schema_ref("ExecuteResponse") },
+            "application/msgpack": { "schema": schema_ref("ExecuteResponse") },
+            "application/x-ndjson": {
+                "schema": {
+                    "type": "string",
+                    "description": "A `{\"result\": ...}` record, then one with `done: true`, `ok`, `status` and the rest of the response or the error body",
+                },
+            },
+        },
+    });
+    let health = operation(
+        "health",
+        "Reports whether the server is ready",
+        None,
+        [
+            ("200", ok("Ready", "HealthResponse")),
+            ("503", ok("Not ready, or the canary of a deep check failed", "HealthResponse")),
+        ],
+    );
+    let health = with_parameters(
+        health,
+        json!([{
+            "name": "deep",
+            "in": "query",
+            "description": "Also run a canary script",
+            "schema": { "type": "boolean", "default": false },
+        }]),
+    );
+
+    json!({
+        "/execute": {
+            "post": limited(operation(
+                "execute",
+                "Runs code and answers with its result",
+                Some(execute_request),
+                [
+                    ("200", execute_ok),
+                    ("400", error_ref("BadRequest")),
+                    ("408", error_ref("Timeout")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                    ("424", error_ref("UnmatchedRequest")),
+                    ("429", error_ref("TooManyRequests")),
+                    ("500", error_ref("InternalError")),
+                ],
+            )),
+        },
+        "/execute/batch": {
+            "post": limited(operation(
+                "executeBatch",
+                "Runs several executions, each answered as /execute would",
+                Some(body("BatchRequest")),
+                [
+                    ("200", ok("One result per job, in the same order", "BatchResponse")),
+                    ("400", error_ref("BadRequest")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                ],
+            )),
+        },
+        "/execute/map": {
+            "post": limited(operation(
+                "executeMap",
+                "Runs the code once per input set",
+                Some(body("MapRequest")),
+                [
+                    ("200", ok("One result per input set, in the same order", "MapResponse")),
+                    ("400", error_ref("BadRequest")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                ],
+            )),
+        },
+        "/validate": {
+            "post": limited(operation(
+                "validate",
+                "Compiles the code without running it",
+                Some(body("ValidateRequest")),
+                [
+                    ("200", ok("Syntax errors are reported with `valid: false`", "Validation")),
+                    ("400", error_ref("BadRequest")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                    ("500", error_ref("InternalError")),
+                ],
+            )),
+        },
+        "/analyze": {
+            "post": limited(operation(
+                "analyze",
+                "Lists the globals the code refers to without running it",
+                Some(body("ValidateRequest")),
+                [
+                    ("200", ok("Syntax errors are reported with `syntaxValid: false`", "Analysis")),
+                    ("400", error_ref("BadRequest")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                    ("500", error_ref("InternalError")),
+                ],
+            )),
+        },
+        "/functions": {
+            "get": limited(operation(
+                "functions",
+                "Describes the ENV values, globals and limits scripts get",
+                None,
+                [("200", ok("What scripts get", "FunctionsResponse"))],
+            )),
+        },
+        "/session": {
+            "get": limited(operation(
+                "session",
+                "Upgrades to a WebSocket with a context of its own",
+                None,
+                [
+                    ("101", json!({ "description": "Switching to the WebSocket protocol" })),
+                    ("503", error_ref("Unavailable")),
+                ],
+            )),
+        },
+        "/jobs": {
+            "post": limited(operation(
+                "submitJob",
+                "Queues an execution and answers with its id",
+                Some(body("JobRequest")),
+                [
+                    ("202", ok("Queued", "JobAccepted")),
+                    ("400", error_ref("BadRequest")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                    ("503", error_ref("Unavailable")),
+                ],
+            )),
+        },
+        "/jobs/{id}": {
+            "parameters": [parameter_ref("JobId")],
+            "get": limited(operation(
+                "getJob",
+                "Reports the status of a job and, once finished, its result or error",
+                None,
+                [("200", ok("The job", "Job")), ("404", error_ref("NotFound"))],
+            )),
+            "delete": limited(operation(
+                "cancelJob",
+                "Cancels a job that is queued or running",
+                None,
+                [
+                    ("200", ok("Cancelled; the job failed", "JobCancelled")),
+                    ("404", error_ref("NotFound")),
+                    ("409", error_ref("Conflict")),
+                ],
+            )),
+        },
+        "/contexts": {
+            "post": limited(operation(
+                "createContext",
+                "Creates a context that keeps its globals between executions",
+                Some(body("CreateContextRequest")),
+                [
+                    ("201", ok("Created", "ContextCreated")),
+                    ("400", error_ref("BadRequest")),
+                    ("408", error_ref("Timeout")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                    ("503", error_ref("Unavailable")),
+                ],
+            )),
+        },
+        "/contexts/{id}": {
+            "parameters": [parameter_ref("ContextId")],
+            "delete": limited(operation(
+                "deleteContext",
+                "Deletes a context",
+                None,
+                [
+                    ("204", json!({ "description": "Deleted" })),
+                    ("404", error_ref("NotFound")),
+                ],
+            )),
+        },
+        "/contexts/{id}/execute": {
+            "parameters": [parameter_ref("ContextId")],
+            "post": limited(operation(
+                "executeInContext",
+                "Runs code in a context",
+                Some(body("ContextEvalRequest")),
+                [
+                    ("200", ok("The result", "ContextEvaluated")),
+                    ("400", error_ref("BadRequest")),
+                    ("404", error_ref("NotFound")),
+                    ("408", error_ref("Timeout")),
+                    ("413", error_ref("TooLarge")),
+                    ("422", error_ref("Unprocessable")),
+                ],
+            )),
+        },
+        "/health": { "get": health.clone() },
+        "/readyz": { "get": with_id(health, "readyz") },
+        "/livez": {
+            "get": operation(
+                "livez",
+                "Answers as long as the process serves requests",
+                None,
+                [("200", ok("Alive", "Liveness"))],
+            ),
+        },
+        "/tenants/{id}/usage": {
+            "parameters": [parameter_ref("TenantId")],
+            "get": operation(
+                "getTenantUsage",
+                "Reports the usage and limits of the caller's own tenant",
+                None,
+                [("200", ok("The usage", "TenantUsage")), ("404", error_ref("NotFound"))],
+            ),
+        },
+        "/usage": {
+            "get": operation(
+                "getUsage",
+                "Reports what the caller's API key used of its quotas, or with an ADMIN_API_KEYS key every key's",
+                None,
+                [("200", ok("The usage", "UsageReport")), ("401", error_ref("Unauthorized"))],
+            ),
+        },
+        "/metrics": {
+            "get": operation(
+                "metrics",
+                "Prometheus metrics",
+                None,
+                [(
+                    "200",
+                    json!({
+                        "description": "The Prometheus text format",
+                        "content": { "text/plain": { "schema": { "type": "string" } } },
+                    }),
+                )],
+            ),
+        },
+        "/admin/circuits": {
+            "get": operation(
+                "listCircuits",
+                "Lists the circuit breakers of the outbound hosts that failed lately",
+                None,
+                [("200", ok("The circuits", "CircuitsReport"))],
+            ),
+        },
+        "/version": {
+            "get": operation("version", "What build is running", None, [("200", ok("The build", "BuildInfo"))]),
+        },
+    })
+}
+
+fn operation<const N: usize>(id: &str, summary: &str, request_body: Option<Value>, responses: [(&str, Value); N]) -> Value {
+    let mut operation = json!({
+        "operationId": id,
+        "summary": summary,
+        "responses": Map::from_iter(responses.into_iter().map(|(status, response)| (status.to_string(), response))),
+    });
+    if let Some(request_body) = request_body {
+        operation["requestBody"] = request_body;
+    }
+    operation
+}
+
+// Operations behind the rate limit, when it is enabled
+fn limited(mut operation: Value) -> Value {
+    operation["responses"]["429"] = error_ref("TooManyRequests");
+    operation
+}
+
+fn with_parameters(mut operation: Value, parameters: Value) -> Value {
+    operation["parameters"] = parameters;
+    operation
+}
+
+fn with_id(mut operation: Value, id: &str) -> Value {
+    operation["operationId"] = json!(id);
+    operation
+}
+
+fn body(schema: &str) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema_ref(schema) } } })
+}
+
+fn ok(description: &str, schema: &str) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema_ref(schema) } } })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn error_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/responses/{}", name) })
+}
+
+fn parameter_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/parameters/{}", name) })
+}
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "description": description, "schema": { "type": "string" } })
+}
+
+// Every error answers with an ErrorResponse; the descriptions list the `error`
+// values each status code comes with
+fn error_responses() -> Value {
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": { "schema": schema_ref("ErrorResponse") },
+                "application/msgpack": { "schema": schema_ref("ErrorResponse") },
+            },
+        })
+    };
+    let mut too_many = error("`Server busy` when all execution slots and the queue are taken, `Rate limit exceeded`, \
+         `Quota exceeded` when a tenant used up its monthly quota, `quota_exceeded` when the API key used up one of its \
+         quotas, with the budget in `quota`");
+    too_many["headers"] = json!({
+        "Retry-After": { "description": "Seconds to wait before retrying", "schema": { "type": "integer" } },
+    });
+    json!({
+        "BadRequest": error(
+            "The request was refused (`Invalid request`, `Invalid code parameter`, `Invalid limits`, \
+             `Invalid timeout_ms`, `Invalid cache`, `Invalid args`, `Invalid modules`, `Invalid entry_module`, `Invalid http_mocks`, \
+             `Invalid state_namespace`, `Invalid callback_url`, `Unknown tenant`, `InvalidSchema`, `InvalidInputs`, `Batch too large`, \
+             `Too many input sets`, `Invalid JSON`, `Invalid MessagePack`, `Invalid multipart`), or the code failed \
+             (`RuntimeError`, `InvalidOutput`, `Request limit exceeded`, `Memory limit exceeded`, `CPU budget exceeded`)"
+        ),
+        "Unauthorized": error("`API key required`: the endpoint reports on the caller's X-API-Key"),
+        "NotFound": error("`Job not found`, `Context not found` or `Tenant not found`"),
+        "Conflict": error("`Job finished`: the job can't be cancelled anymore"),
+        "Timeout": error("`Execution interrupted`: the execution ran past its timeout"),
+        "TooLarge": error(
+            "`Request too large`, with the limit in `exceeded`, or `Result too large` for a result over \
+             max_result_bytes"
+        ),
+        "Unprocessable": error(
+            "The body doesn't match the request (`Invalid request`), or the code can't be run: `SyntaxError`, \
+             `ModuleResolutionError`, `Invalid entrypoint`, `UnserializableResult`"
+        ),
+        "UnmatchedRequest": error("`Unmatched request`: no mock or recorded response answered an httpRequest call"),
+        "TooManyRequests": too_many,
+        "InternalError": error("`Execution failed`, `Worker crashed`, `Validation failed`, `Analysis failed` or `Serialization error`"),
+        "Unavailable": error("`Too many jobs`, `Too many contexts` or `Too many sessions`"),
+    })
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({ "type": "object", "required": required, "properties": properties })
+}
+
+// An object schema with further properties, for request types that flatten another
+fn extend(mut schema: Value, required: &[&str], properties: Value) -> Value {
+    if let (Some(base), Value::Object(extra)) = (schema["properties"].as_object_mut(), properties) {
+        base.extend(extra);
+    }
+    if let Some(base) = schema["required"].as_array_mut() {
+        base.extend(required.iter().map(|name| json!(name)));
+    }
+    schema
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": [kind, "null"] })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn map_of(values: Value) -> Value {
+    json!({ "type": "object", "additionalProperties": values })
+}
+
+fn strings() -> Value {
+    array_of(json!({ "type": "string" }))
+}
+
+fn schemas() -> Value {
+    let any = json!({});
+    let integer = json!({ "type": "integer", "minimum": 0 });
+    let boolean = json!({ "type": "boolean" });
+    let string = json!({ "type": "string" });
+
+    let execute_request = object(
+        &[],
+        json!({
+            "code": { "type": "string", "description": "Left out when the code is given as `modules`" },
+            "inputs": { "description": "Any JSON value, available to the code as INPUTS", "default": {} },
+            "secrets": map_of(string.clone()),
+            "debug": { "type": "boolean", "description": "Adds `debug` to the response, and unhandledRejections on success" },
+            "limits": schema_ref("ExecutionLimits"),
+            "timeout_ms": { "type": "integer", "description": "Up to MAX_EXEC_TIMEOUT_MS" },
+            "include_meta": boolean,
+            "inputs_schema": { "description": "JSON Schema the inputs must satisfy" },
+            "output_schema": { "description": "JSON Schema the result must satisfy" },
+            "module": boolean,
+            "freeze_time": boolean,
+            "random_seed": { "type": "integer", "minimum": 0, "maximum": u32::MAX },
+            "bigint_mode": { "enum": ["string", "number", "error"], "default": "string" },
+            "state_namespace": string,
+            "dry_run": boolean,
+            "http_mocks": array_of(schema_ref("HttpMock")),
+            "allow_network": { "type": "boolean", "default": true },
+            "record_http": boolean,
+            "replay_http": map_of(schema_ref("HttpResult")),
+            "cache": object(&["ttlSeconds"], json!({ "ttlSeconds": integer })),
+            "entrypoint": string,
+            "args": { "type": "array", "description": "Arguments of the entrypoint, [INPUTS] when absent" },
+            "language": { "enum": ["javascript", "typescript"], "default": "javascript" },
+            "modules": map_of(string.clone()),
+            "entry_module": { "type": "string", "default": "main" },
+            "priority": {
+                "enum": ["interactive", "normal", "batch"],
+                "default": "normal",
+                "description": "Which queue the execution waits in when all slots are busy",
+            },
+            "stream_result": {
+                "type": "boolean",
+                "default": false,
+                "description": "Stream the response as NDJSON, with Accept: application/x-ndjson",
+            },
+            "skip_prelude": { "type": "boolean", "default": false, "description": "Run without the PRELUDE_DIR scripts" },
+        }),
+    );
+    let execution_result = object(
+        &["ok", "status", "durationMs"],
+        json!({
+            "ok": boolean,
+            "status": { "type": "integer", "description": "The status /execute would have answered with" },
+            "result": any,
+            "unhandledRejections": strings(),
+            "meta": schema_ref("ExecutionMeta"),
+            "error": schema_ref("ErrorResponse"),
+            "debug": schema_ref("DebugInfo"),
+            "durationMs": integer,
+        }),
+    );
+    let error_message = object(&["message", "line", "column"], json!({
+        "message": string,
+        "line": nullable("integer"),
+        "column": nullable("integer"),
+    }));
+
+    let schemas = [
+        ("ExecuteRequest", execute_request.clone()),
+        ("ExecutionLimits", json!({
+            "type": "object",
+            "description": "Can only tighten the server's limits; a longer timeout_ms is shortened",
+            "properties": {
+                "max_requests": integer,
+                "max_http_requests": { "type": "integer", "minimum": 0, "deprecated": true },
+                "max_result_bytes": integer,
+                "max_fetch_body_bytes": integer,
+                "memory_bytes": integer,
+                "timeout_ms": integer,
+                "cpu_ms": integer,
+                "disable_dynamic_eval": boolean,
+            },
+        })),
+        ("HttpMock", object(&["match"], json!({
+            "match": {
+                "type": "object",
+                "description": "url or urlPattern, and optionally the method",
+                "additionalProperties": false,
+                "properties": { "url": string, "urlPattern": string, "method": string },
+            },
+            "response": {
+                "type": "object",
+                "properties": {
+                    "status": { "type": "integer", "minimum": 100, "maximum": 599, "default": 200 },
+                    "headers": map_of(string.clone()),
+                    "data": any,
+                },
+            },
+            "times": integer,
+        }))),
+        ("ExecuteResponse", api_version(object(&["result"], json!({
+            "result": any,
+            "unhandledRejections": strings(),
+            "meta": schema_ref("ExecutionMeta"),
+            "requests": array_of(schema_ref("PlannedRequest")),
+            "dryRun": boolean,
+            "error": schema_ref("ErrorResponse"),
+            "httpTrace": map_of(schema_ref("HttpResult")),
+            "debug": schema_ref("DebugInfo"),
+        })))),
+        ("ErrorResponse", api_version(object(&["error", "message"], json!({
+            "error": string,
+            "message": string,
+            "unhandledRejections": strings(),
+            "meta": schema_ref("ExecutionMeta"),
+            "jsError": schema_ref("JsError"),
+            "thrown": { "description": "The value the code threw, when it isn't an Error" },
+            "violations": array_of(schema_ref("Violation")),
+            "result": { "description": "The result that failed output_schema" },
+            "resultPreview": string,
+            "exceeded": object(&["limit", "maxBytes"], json!({
+                "limit": string,
+                "maxBytes": integer,
+                "actualBytes": integer,
+            })),
+            "httpTrace": map_of(schema_ref("HttpResult")),
+            "quota": object(&["budget", "limit", "used", "period", "resetsAt"], json!({
+                "budget": { "type": "string", "description": "e.g. daily_executions or monthly_cpu_ms" },
+                "limit": integer,
+                "used": integer,
+                "period": string,
+                "resetsAt": { "type": "string", "format": "date-time" },
+            })),
+            "debug": schema_ref("DebugInfo"),
+            "requestId": { "type": "string", "description": "The X-Request-Id, on top-level error bodies" },
+        })))),
+        ("JsError", object(&["name", "message", "stack", "line", "column"], json!({
+            "name": nullable("string"),
+            "message": string,
+            "stack": nullable("string"),
+            "line": nullable("integer"),
+            "column": nullable("integer"),
+            "properties": { "type": "object" },
+        }))),
+        ("Violation", object(&["instancePath", "keyword", "message"], json!({
+            "instancePath": string,
+            "keyword": string,
+            "message": string,
+        }))),
+        ("PlannedRequest", object(&["url", "method", "headers", "bodyPreview", "dependent"], json!({
+            "url": string,
+            "method": string,
+            "headers": map_of(string.clone()),
+            "bodyPreview": nullable("string"),
+            "dependent": boolean,
+        }))),
+        ("DebugInfo", object(&["requests", "replayKeys", "unmatchedKeys", "durations"], json!({
+            "requests": array_of(schema_ref("DebugRequest")),
+            "replayKeys": { "type": ["array", "null"], "items": string, "description": "The keys of replay_http" },
+            "unmatchedKeys": { "type": "array", "items": string, "description": "Requests no mock or recording answered" },
+            "durations": object(&["queueMs", "evalMs", "fetchMs", "totalMs"], json!({
+                "queueMs": integer,
+                "evalMs": integer,
+                "fetchMs": integer,
+                "totalMs": integer,
+            })),
+        }))),
+        ("DebugRequest", object(&["key", "method", "url", "headers", "status", "errorCode", "durationMs"], json!({
+            "key": { "type": "string", "description": "What replay_http looks the response up by" },
+            "method": string,
+            "url": string,
+            "headers": map_of(string.clone()),
+            "status": nullable("integer"),
+            "errorCode": nullable("string"),
+            "durationMs": integer,
+        }))),
+        ("HttpResult", object(&["status"], json!({
+            "ok": boolean,
+            "status": integer,
+            "statusText": string,
+            "headers": map_of(string.clone()),
+            "rawHeaders": array_of(json!({ "type": "array", "items": string, "minItems": 2, "maxItems": 2 })),
+            "data": any,
+            "text": string,
+            "jsonParseError": nullable("string"),
+            "contentType": nullable("string"),
+            "contentLength": nullable("integer"),
+            "finalUrl": nullable("string"),
+            "timing": object(&["totalMs"], json!({ "totalMs": integer, "ttfbMs": nullable("integer") })),
+            "redirects": array_of(object(&["url", "status"], json!({ "url": string, "status": integer, "note": string }))),
+            "attempts": integer,
+            "setCookies": strings(),
+            "fromCache": { "enum": [false, true, "revalidated"] },
+            "errorCode": nullable("string"),
+            "retryAfterMs": { "type": "integer", "description": "With circuit_open, until the circuit lets a probe through" },
+            "strippedHeaders": strings(),
+        }))),
+        ("ExecutionMeta", object(
+            &[
+                "durationMs", "queueMs", "evalMs", "fetchMs", "cpuMs", "httpRequestCount", "blockedRequestCount", "timeoutMs",
+                "limits", "codeBytes", "resultBytes", "randomSeed", "networkAllowed", "cacheHit",
+                "codeCache", "requestId", "fetchByHost", "usage",
+            ],
+            json!({
+                "durationMs": integer,
+                "queueMs": integer,
+                "evalMs": integer,
+                "fetchMs": integer,
+                "cpuMs": integer,
+                "httpRequestCount": integer,
+                "blockedRequestCount": integer,
+                "timeoutMs": integer,
+                "limits": schema_ref("LimitsMeta"),
+                "codeBytes": integer,
+                "resultBytes": integer,
+                "randomSeed": integer,
+                "networkAllowed": boolean,
+                "cacheHit": boolean,
+                "codeCache": object(&["hit", "hits", "misses"], json!({ "hit": boolean, "hits": integer, "misses": integer })),
+                "requestId": string,
+                "fetchByHost": map_of(object(&["requests", "attempts", "totalMs"], json!({
+                    "requests": integer,
+                    "attempts": integer,
+                    "totalMs": integer,
+                }))),
+                "usage": object(
+                    &["heapPeakBytes", "cpuMs", "interrupts", "fetchedBytes", "sentBytes", "resultBytes"],
+                    json!({
+                        "heapPeakBytes": integer,
+                        "cpuMs": integer,
+                        "interrupts": integer,
+                        "fetchedBytes": integer,
+                        "sentBytes": integer,
+                        "resultBytes": integer,
+                    }),
+                ),
+            }),
+        )),
+        ("LimitsMeta", object(
+            &["maxRequests", "maxResultBytes", "maxFetchBodyBytes", "maxFetchTotalBytes", "memoryBytes", "cpuMs"],
+            json!({
+                "maxRequests": integer,
+                "maxResultBytes": integer,
+                "maxFetchBodyBytes": integer,
+                "maxFetchTotalBytes": integer,
+                "memoryBytes": integer,
+                "cpuMs": integer,
+            }),
+        )),
+        ("BatchRequest", object(&["jobs"], json!({
+            "jobs": array_of(extend(execute_request.clone(), &["id"], json!({
+                "id": { "description": "Chosen by the caller, and returned with the result" },
+            }))),
+        }))),
+        ("BatchResponse", api_version(object(&["results"], json!({
+            "results": array_of(extend(execution_result.clone(), &["id"], json!({ "id": any }))),
+        })))),
+        ("MapRequest", extend(without(&execute_request, "inputs"), &["input_sets"], json!({
+            "input_sets": { "type": "array", "description": "The code runs once per entry, with it as INPUTS" },
+        }))),
+        ("MapResponse", api_version(object(&["results", "meta"], json!({
+            "results": array_of(execution_result),
+            "meta": object(&["durationMs", "succeeded", "failed"], json!({
+                "durationMs": integer,
+                "succeeded": integer,
+                "failed": integer,
+            })),
+        })))),
+        ("ValidateRequest", object(&["code"], json!({ "code": string, "module": boolean }))),
+        ("Validation", api_version(object(&["valid", "hostFunctions"], json!({
+            "valid": boolean,
+            "errors": array_of(error_message.clone()),
+            "hostFunctions": map_of(integer.clone()),
+        })))),
+        ("Analysis", api_version(object(&["syntaxValid", "uses", "unknownGlobals", "computedAccess"], json!({
+            "syntaxValid": boolean,
+            "errors": array_of(error_message),
+            "uses": strings(),
+            "unknownGlobals": strings(),
+            "computedAccess": strings(),
+        })))),
+        ("FunctionsResponse", api_version(object(&["env", "globals", "limits", "preludeScripts"], json!({
+            "env": map_of(string.clone()),
+            "globals": array_of(schema_ref("Global")),
+            "limits": object(
+                &[
+                    "maxRequests", "timeoutMs", "maxTimeoutMs", "cpuMs", "sleepBudgetMs", "memoryBytes",
+                    "maxResultBytes", "maxFetchBodyBytes", "maxFetchTotalBytes", "maxCodeBytes", "maxInputsBytes",
+                    "disableDynamicEval", "networkDisabled",
+                ],
+                json!({
+                    "maxRequests": integer,
+                    "timeoutMs": integer,
+                    "maxTimeoutMs": integer,
+                    "cpuMs": integer,
+                    "sleepBudgetMs": integer,
+                    "memoryBytes": integer,
+                    "maxResultBytes": integer,
+                    "maxFetchBodyBytes": integer,
+                    "maxFetchTotalBytes": integer,
+                    "maxCodeBytes": integer,
+                    "maxInputsBytes": integer,
+                    "disableDynamicEval": boolean,
+                    "networkDisabled": boolean,
+                }),
+            ),
+            "preludeScripts": array_of(schema_ref("PreludeFile")),
+        })))),
+        ("PreludeFile", object(&["name", "bytes", "sha256"], json!({
+            "name": string,
+            "bytes": integer,
+            "sha256": string,
+        }))),
+        ("Global", object(&["name", "kind", "description"], json!({
+            "name": string,
+            "kind": { "enum": ["function", "class", "namespace", "value"] },
+            "signature": string,
+            "description": string,
+            "params": array_of(object(&["name", "description"], json!({ "name": string, "description": string }))),
+            "example": string,
+            "members": array_of(schema_ref("Global")),
+            "executionsOnly": boolean,
+        }))),
+        ("JobRequest", extend(execute_request, &[], json!({
+            "callback_url": { "type": "string", "description": "Receives the job's status body once it finished" },
+        }))),
+        ("JobAccepted", api_version(object(&["job_id"], json!({ "job_id": string })))),
+        ("JobStatus", json!({ "enum": ["queued", "running", "succeeded", "failed"] })),
+        ("Job", api_version(object(&["job_id", "status", "meta"], json!({
+            "job_id": string,
+            "status": schema_ref("JobStatus"),
+            "result": any,
+            "unhandledRejections": strings(),
+            "error": schema_ref("ErrorResponse"),
+            "meta": object(&["queuedMs"], json!({
+                "queuedMs": integer,
+                "durationMs": integer,
+                "execution": schema_ref("ExecutionMeta"),
+            })),
+        })))),
+        ("JobCancelled", api_version(object(&["job_id", "status"], json!({
+            "job_id": string,
+            "status": schema_ref("JobStatus"),
+        })))),
+        ("CreateContextRequest", object(&[], json!({
+            "init_code": { "type": "string", "description": "Run once when the context is created" },
+            "inputs": { "default": {} },
+        }))),
+        ("ContextCreated", api_version(object(&["id", "result"], json!({
+            "id": string,
+            "result": { "description": "Of init_code, null without it" },
+        })))),
+        ("ContextEvalRequest", object(&["code"], json!({ "code": string, "inputs": { "default": {} } }))),
+        ("ContextEvaluated", api_version(object(&["result"], json!({ "result": any })))),
+        ("HealthResponse", api_version(object(&["status", "executions"], json!({
+            "status": { "enum": ["ok", "not ready", "unhealthy"] },
+            "condition": { "enum": ["warming_up", "warm_up_failed", "draining", "saturated"] },
+            "message": string,
+            "executions": object(&["inFlight", "queued", "maxConcurrent"], json!({
+                "inFlight": integer,
+                "queued": integer,
+                "maxConcurrent": integer,
+            })),
+            "engine": object(&["ok", "latencyMs"], json!({ "ok": boolean, "latencyMs": integer, "error": string })),
+        })))),
+        ("TenantUsage", api_version(object(
+            &["tenant", "period", "executions", "cpuMs", "fetchedBytes", "inFlight", "limits"],
+            json!({
+                "tenant": string,
+                "period": { "type": "string", "description": "The month counted, as YYYY-MM (UTC)" },
+                "executions": integer,
+                "cpuMs": integer,
+                "fetchedBytes": integer,
+                "inFlight": integer,
+                "limits": object(
+                    &["maxConcurrentExecutions", "rateLimitPerSecond", "rateLimitBurst", "monthlyExecutions", "monthlyCpuMs"],
+                    json!({
+                        "maxConcurrentExecutions": nullable("integer"),
+                        "rateLimitPerSecond": nullable("number"),
+                        "rateLimitBurst": nullable("number"),
+                        "monthlyExecutions": nullable("integer"),
+                        "monthlyCpuMs": nullable("integer"),
+                    }),
+                ),
+            }),
+        ))),
+        ("UsageReport", api_version(object(&["keys"], json!({
+            "keys": array_of(object(&["apiKeyId", "daily", "monthly"], json!({
+                "apiKeyId": string,
+                "daily": schema_ref("QuotaWindow"),
+                "monthly": schema_ref("QuotaWindow"),
+            }))),
+        })))),
+        ("QuotaWindow", object(
+            &["period", "resetsAt", "executions", "cpuMs", "fetchedBytes", "limits"],
+            json!({
+                "period": { "type": "string", "description": "The UTC day as YYYY-MM-DD, or month as YYYY-MM" },
+                "resetsAt": { "type": "string", "format": "date-time" },
+                "executions": integer,
+                "cpuMs": integer,
+                "fetchedBytes": integer,
+                "limits": object(&["executions", "cpuMs", "fetchedBytes"], json!({
+                    "executions": nullable("integer"),
+                    "cpuMs": nullable("integer"),
+                    "fetchedBytes": nullable("integer"),
+                })),
+            }),
+        )),
+        ("CircuitsReport", api_version(object(
+            &["enabled", "failureThreshold", "windowMs", "cooldownMs", "circuits"],
+            json!({
+                "enabled": boolean,
+                "failureThreshold": integer,
+                "windowMs": integer,
+                "cooldownMs": integer,
+                "circuits": array_of(object(
+                    &["host", "state", "consecutiveFailures", "retryAfterMs", "opened", "rejected"],
+                    json!({
+                        "host": { "type": "string", "description": "host:port" },
+                        "state": { "enum": ["closed", "open", "half_open"] },
+                        "consecutiveFailures": integer,
+                        "retryAfterMs": nullable("integer"),
+                        "opened": integer,
+                        "rejected": integer,
+                    }),
+                )),
+            }),
+        ))),
+        ("Liveness", api_version(object(&["status"], json!({ "status": { "const": "ok" } })))),
+        ("BuildInfo", api_version(object(
+            &[
+                "version", "gitCommit", "gitDirty", "buildTimestamp", "rustcVersion", "rquickjsVersion",
+                "quickjsVersion", "full", "warmUpMs", "preludeScripts",
+            ],
+            json!({
+                "version": string,
+                "gitCommit": string,
+                "gitDirty": boolean,
+                "buildTimestamp": string,
+                "rustcVersion": string,
+                "rquickjsVersion": string,
+                "quickjsVersion": string,
+                "full": string,
+                "warmUpMs": nullable("integer"),
+                "preludeScripts": array_of(schema_ref("PreludeFile")),
+            }),
+        ))),
+    ];
+    Value::Object(schemas.into_iter().map(|(name, schema)| (name.to_string(), schema)).collect())
+}
+
+// Adds the `apiVersion` the /v1 envelope puts into JSON objects
+fn api_version(schema: Value) -> Value {
+    extend(schema, &[], json!({ "apiVersion": { "type": "string", "description": "Only under /v1" } }))
+}
+
+fn without(schema: &Value, property: &str) -> Value {
+    let mut schema = schema.clone();
+    if let Some(properties) = schema["properties"].as_object_mut() {
+        properties.remove(property);
+    }
+    schema
+}
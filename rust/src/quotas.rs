@@ -0,0 +1,449 @@
+// Usage quotas of API keys: how many executions, CPU milliseconds and bytes fetched
+// from upstreams every key may use per UTC day (QUOTA_DAILY_*) and per calendar month
+// (QUOTA_MONTHLY_*).
+//
+// An execution is counted when it is admitted, under the same lock that checks the
+// budgets, so concurrent executions can't get past an execution budget. Its CPU time
+// and fetched bytes are added once it finished, so the execution that reaches one of
+// those budgets may go over it; the next one is refused. Executions that didn't get
+// to run give their count back. Requests without an X-API-Key aren't counted. With
+// QUOTA_PATH the counts are saved after every change and loaded at startup.
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sandbox_core::config::Config;
+use sandbox_core::executor::Usage;
+
+use crate::api::{api_key_id, state_owner, ErrorResponse};
+use crate::body::Json;
+use crate::AppState;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// What time it is for the quotas, replaceable in tests
+pub type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+
+#[derive(Clone, Copy)]
+enum Window {
+    Daily,
+    Monthly,
+}
+
+impl Window {
+    const ALL: [Window; 2] = [Window::Daily, Window::Monthly];
+
+    fn name(self) -> &'static str {
+        match self {
+            Window::Daily => "daily",
+            Window::Monthly => "monthly",
+        }
+    }
+
+    // The period `now` is in, e.g. "2026-10-14" or "2026-10"
+    fn period(self, now: u64) -> String {
+        let (year, month, day) = civil_from_days(now / SECONDS_PER_DAY);
+        match self {
+            Window::Daily => format!("{:04}-{:02}-{:02}", year, month, day),
+            Window::Monthly => format!("{:04}-{:02}", year, month),
+        }
+    }
+
+    // When the next period starts, in seconds since the epoch
+    fn resets_at(self, now: u64) -> u64 {
+        let days = now / SECONDS_PER_DAY;
+        let next = match self {
+            Window::Daily => days + 1,
+            Window::Monthly => match civil_from_days(days) {
+                (year, 12, _) => days_from_civil(year + 1, 1, 1),
+                (year, month, _) => days_from_civil(year, month + 1, 1),
+            },
+        };
+        next * SECONDS_PER_DAY
+    }
+}
+
+// 0 for no budget
+#[derive(Clone, Copy)]
+struct Budget {
+    executions: u64,
+    cpu_ms: u64,
+    fetched_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct Counters {
+    period: String,
+    executions: u64,
+    cpu_micros: u64,
+    fetched_bytes: u64,
+}
+
+// What one key used, in the current period of each window
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct KeyUsage {
+    daily: Counters,
+    monthly: Counters,
+}
+
+impl KeyUsage {
+    // The counters of the period `now` is in, which start over when a new one begins
+    fn counters(&mut self, window: Window, now: u64) -> &mut Counters {
+        let counters = match window {
+            Window::Daily => &mut self.daily,
+            Window::Monthly => &mut self.monthly,
+        };
+        let period = window.period(now);
+        if counters.period != period {
+            *counters = Counters {
+                period,
+                ..Counters::default()
+            };
+        }
+        counters
+    }
+}
+
+pub struct Quotas {
+    // By API key id
+    usage: Mutex<BTreeMap<String, KeyUsage>>,
+    daily: Budget,
+    monthly: Budget,
+    // Ids of the ADMIN_API_KEYS
+    admin_keys: HashSet<String>,
+    path: Option<PathBuf>,
+    // Held while the file is written, so that saves don't interleave
+    persisting: Mutex<()>,
+    clock: RwLock<Clock>,
+}
+
+// The budget an execution was refused for, in the 429 response
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceededQuota {
+    // e.g. "daily_executions"
+    pub budget: String,
+    pub limit: u64,
+    pub used: u64,
+    pub period: String,
+    // When the budget starts over, e.g. "2026-10-15T00:00:00Z"
+    pub resets_at: String,
+    // For Retry-After
+    #[serde(skip)]
+    pub resets_in_secs: u64,
+}
+
+// An execution counted against the key's budgets. Dropped without `finish`, for an
+// execution that didn't run, it gives the count back.
+pub struct QuotaReservation {
+    quotas: Arc<Quotas>,
+    key: String,
+    counted_at: u64,
+    finished: bool,
+}
+
+impl QuotaReservation {
+    // Adds what the execution used
+    pub fn finish(mut self, usage: &Usage) {
+        self.finished = true;
+        self.quotas.update(&self.key, |key_usage, now| {
+            for window in Window::ALL {
+                let counters = key_usage.counters(window, now);
+                counters.cpu_micros += usage.cpu_time.as_micros() as u64;
+                counters.fetched_bytes += usage.fetched_bytes;
+            }
+        });
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let counted_at = self.counted_at;
+        self.quotas.update(&self.key, |key_usage, now| {
+            for window in Window::ALL {
+                // Unless the period it was counted in is over
+                if window.period(counted_at) == window.period(now) {
+                    let counters = key_usage.counters(window, now);
+                    counters.executions = counters.executions.saturating_sub(1);
+                }
+            }
+        });
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    keys: Vec<KeyReport>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyReport {
+    api_key_id: String,
+    daily: WindowReport,
+    monthly: WindowReport,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WindowReport {
+    period: String,
+    resets_at: String,
+    // Including executions still running
+    executions: u64,
+    cpu_ms: u64,
+    fetched_bytes: u64,
+    limits: WindowLimits,
+}
+
+// Null where there is no budget
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WindowLimits {
+    executions: Option<u64>,
+    cpu_ms: Option<u64>,
+    fetched_bytes: Option<u64>,
+}
+
+impl Quotas {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let path = (!config.quota_path.is_empty()).then(|| PathBuf::from(&config.quota_path));
+        let usage = match &path {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read the quota usage from {}: {}", path.display(), e))?;
+                serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid quota usage in {}: {}", path.display(), e))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Quotas {
+            usage: Mutex::new(usage),
+            daily: Budget {
+                executions: config.quota_daily_executions,
+                cpu_ms: config.quota_daily_cpu_ms,
+                fetched_bytes: config.quota_daily_fetched_bytes,
+            },
+            monthly: Budget {
+                executions: config.quota_monthly_executions,
+                cpu_ms: config.quota_monthly_cpu_ms,
+                fetched_bytes: config.quota_monthly_fetched_bytes,
+            },
+            admin_keys: config.admin_api_keys.iter().map(|key| api_key_id(key)).collect(),
+            path,
+            persisting: Mutex::new(()),
+            clock: RwLock::new(Arc::new(SystemTime::now)),
+        })
+    }
+
+    pub fn set_clock(&self, clock: impl Fn() -> SystemTime + Send + Sync + 'static) {
+        *self.clock.write().unwrap() = Arc::new(clock);
+    }
+
+    // Seconds since the epoch
+    fn now(&self) -> u64 {
+        let now = (self.clock.read().unwrap())();
+        now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+    }
+
+    fn budget(&self, window: Window) -> Budget {
+        match window {
+            Window::Daily => self.daily,
+            Window::Monthly => self.monthly,
+        }
+    }
+
+    // Counts an execution of the key, unless one of its budgets is used up
+    pub fn admit(self: &Arc<Self>, key: &str) -> Result<QuotaReservation, Box<(StatusCode, ErrorResponse)>> {
+        let now = self.now();
+        {
+            let mut usage = self.usage.lock().unwrap();
+            let key_usage = usage.entry(key.to_string()).or_default();
+            for window in Window::ALL {
+                let budget = self.budget(window);
+                let counters = key_usage.counters(window, now);
+                let exhausted = [
+                    ("executions", budget.executions, counters.executions),
+                    ("cpu_ms", budget.cpu_ms, counters.cpu_micros / 1000),
+                    ("fetched_bytes", budget.fetched_bytes, counters.fetched_bytes),
+                ]
+                .into_iter()
+                .find(|&(_, limit, used)| limit > 0 && used >= limit);
+                if let Some((name, limit, used)) = exhausted {
+                    let resets_at = window.resets_at(now);
+                    let quota = ExceededQuota {
+                        budget: format!("{}_{}", window.name(), name),
+                        limit,
+                        used,
+                        period: counters.period.clone(),
+                        resets_at: timestamp(resets_at),
+                        resets_in_secs: resets_at - now,
+                    };
+                    let message = format!(
+                        "This API key used up its {} quota of {} for {}, which resets at {}",
+                        quota.budget, limit, quota.period, quota.resets_at
+                    );
+                    return Err(Box::new((
+                        StatusCode::TOO_MANY_REQUESTS,
+                        ErrorResponse {
+                            error: "quota_exceeded".to_string(),
+                            message,
+                            quota: Some(quota),
+                            ..Default::default()
+                        },
+                    )));
+                }
+            }
+            for window in Window::ALL {
+                key_usage.counters(window, now).executions += 1;
+            }
+        }
+        self.persist();
+        Ok(QuotaReservation {
+            quotas: self.clone(),
+            key: key.to_string(),
+            counted_at: now,
+            finished: false,
+        })
+    }
+
+    fn update(&self, key: &str, change: impl FnOnce(&mut KeyUsage, u64)) {
+        let now = self.now();
+        change(self.usage.lock().unwrap().entry(key.to_string()).or_default(), now);
+        self.persist();
+    }
+
+    pub fn is_admin(&self, key: &str) -> bool {
+        self.admin_keys.contains(key)
+    }
+
+    // The usage of the keys, or of every key that has any with None
+    pub fn report(&self, keys: Option<&[String]>) -> UsageReport {
+        let now = self.now();
+        let usage = self.usage.lock().unwrap();
+        let ids: Vec<String> = match keys {
+            Some(keys) => keys.to_vec(),
+            None => usage.keys().cloned().collect(),
+        };
+        let keys = ids
+            .into_iter()
+            .map(|id| {
+                let mut key_usage = usage.get(&id).cloned().unwrap_or_default();
+                let mut window = |window: Window| self.window_report(&mut key_usage, window, now);
+                KeyReport {
+                    daily: window(Window::Daily),
+                    monthly: window(Window::Monthly),
+                    api_key_id: id,
+                }
+            })
+            .collect();
+        UsageReport { keys }
+    }
+
+    fn window_report(&self, key_usage: &mut KeyUsage, window: Window, now: u64) -> WindowReport {
+        let budget = self.budget(window);
+        let counters = key_usage.counters(window, now);
+        let limit = |value: u64| (value > 0).then_some(value);
+        WindowReport {
+            period: counters.period.clone(),
+            resets_at: timestamp(window.resets_at(now)),
+            executions: counters.executions,
+            cpu_ms: counters.cpu_micros / 1000,
+            fetched_bytes: counters.fetched_bytes,
+            limits: WindowLimits {
+                executions: limit(budget.executions),
+                cpu_ms: limit(budget.cpu_ms),
+                fetched_bytes: limit(budget.fetched_bytes),
+            },
+        }
+    }
+
+    // A failed save is logged and retried with the next change
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _persisting = self.persisting.lock().unwrap();
+        let text = match serde_json::to_string(&*self.usage.lock().unwrap()) {
+            Ok(text) => text,
+            Err(e) => return tracing::error!("Failed to serialize the quota usage: {}", e),
+        };
+        // Replaced in one step, so a crash never leaves a half-written file
+        let temporary = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&temporary, text).and_then(|_| std::fs::rename(&temporary, path)) {
+            tracing::error!("Failed to save the quota usage to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// `2026-10-15T00:00:00Z`
+fn timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days(secs / SECONDS_PER_DAY);
+    let time = secs % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// The date of a day since 1970-01-01, after Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse, for dates from 1970 on
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The caller's own usage, or with one of the ADMIN_API_KEYS every key's
+pub async fn usage_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(key) = state_owner(&headers) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "API key required".to_string(),
+                message: "GET /usage reports the usage of the X-API-Key it is sent with".to_string(),
+                ..Default::default()
+            }),
+        )
+            .into_response();
+    };
+    let quotas = state.quotas();
+    let report = match quotas.is_admin(&key) {
+        true => quotas.report(None),
+        false => quotas.report(Some(&[key])),
+    };
+    Json(report).into_response()
+}
@@ -0,0 +1,212 @@
+// Per-client rate limiting, enabled with RATE_LIMIT_ENABLED=true.
+//
+// Every client has a token bucket: a request takes a token, and tokens come back
+// at a steady rate up to the bucket's burst size. Clients sending `X-Api-Key` are
+// keyed by the key and get the RATE_LIMIT_API_KEY_* rate, everyone else is keyed by
+// IP address and gets the RATE_LIMIT_IP_* rate. Behind a proxy, TRUST_PROXY=true
+// takes the address from `X-Forwarded-For`. Buckets that have filled up again are
+// dropped periodically, so idle clients take no memory.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use sandbox_core::config::Config;
+use crate::api::ErrorResponse;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+// Longest Retry-After and reset reported, however slow the rate
+const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl Rate {
+    // `Config::validate` rejects rates that aren't positive
+    pub fn new(per_second: f64, burst: f64) -> Self {
+        Rate {
+            per_second,
+            burst: burst.max(1.0),
+        }
+    }
+
+    // Until `tokens` more tokens have come back
+    fn wait(&self, tokens: f64) -> Duration {
+        Duration::try_from_secs_f64(tokens / self.per_second).map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    ApiKey(String),
+    Ip(IpAddr),
+}
+
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub enum Decision {
+    Allowed { remaining: f64 },
+    // Until the next token is available
+    Limited { retry_after: Duration },
+}
+
+impl Bucket {
+    pub fn full(rate: Rate, now: Instant) -> Self {
+        Bucket { tokens: rate.burst, updated: now }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = now;
+    }
+
+    pub fn take(&mut self, rate: Rate, now: Instant) -> Decision {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Decision::Allowed { remaining: self.tokens }
+        } else {
+            Decision::Limited {
+                retry_after: rate.wait(1.0 - self.tokens),
+            }
+        }
+    }
+}
+
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<Key, Bucket>>,
+    api_key: Rate,
+    ip: Rate,
+    trust_proxy: bool,
+}
+
+impl RateLimiter {
+    // None unless RATE_LIMIT_ENABLED=true
+    pub fn from_config(config: &Config) -> Option<Arc<Self>> {
+        if !config.rate_limit_enabled {
+            return None;
+        }
+        Some(Arc::new(RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            api_key: Rate::new(config.rate_limit_api_key_per_second, config.rate_limit_api_key_burst),
+            ip: Rate::new(config.rate_limit_ip_per_second, config.rate_limit_ip_burst),
+            trust_proxy: config.trust_proxy,
+        }))
+    }
+
+    fn rate(&self, key: &Key) -> Rate {
+        match key {
+            Key::ApiKey(_) => self.api_key,
+            Key::Ip(_) => self.ip,
+        }
+    }
+
+    fn key(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<Key> {
+        if let Some(api_key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+            return Some(Key::ApiKey(api_key.to_string()));
+        }
+        // The last address is the one the proxy itself saw; earlier ones are
+        // whatever the client claimed
+        let forwarded = || {
+            let header = headers.get("x-forwarded-for")?.to_str().ok()?;
+            header.rsplit(',').next()?.trim().parse().ok()
+        };
+        let forwarded = if self.trust_proxy { forwarded() } else { None };
+        forwarded.or(peer).map(Key::Ip)
+    }
+
+    fn check(&self, key: Key, now: Instant) -> (Decision, Rate) {
+        let rate = self.rate(&key);
+        let mut buckets = self.buckets.lock().unwrap();
+        let decision = buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(rate, now))
+            .take(rate, now);
+        (decision, rate)
+    }
+
+    // Drops buckets that are full again, which behave like new ones
+    fn cleanup(&self, now: Instant) {
+        self.buckets.lock().unwrap().retain(|key, bucket| {
+            let rate = self.rate(key);
+            bucket.refill(rate, now);
+            bucket.tokens < rate.burst
+        });
+    }
+
+    pub fn spawn_cleanup(self: &Arc<Self>) {
+        let limiter = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(limiter) = limiter.upgrade() else {
+                    return;
+                };
+                limiter.cleanup(Instant::now());
+            }
+        });
+    }
+}
+
+fn seconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from(duration.as_secs_f64().ceil() as u64)
+}
+
+// Requests without a key, e.g. over a socket without a peer address, aren't limited
+pub async fn middleware(State(limiter): State<Arc<RateLimiter>>, request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let Some(key) = limiter.key(request.headers(), peer) else {
+        return next.run(request).await;
+    };
+
+    let (decision, rate) = limiter.check(key, Instant::now());
+    match decision {
+        Decision::Allowed { remaining } => {
+            let mut response = next.run(request).await;
+            let headers = response.headers_mut();
+            headers.insert("x-ratelimit-limit", HeaderValue::from(rate.burst as u64));
+            headers.insert("x-ratelimit-remaining", HeaderValue::from(remaining.floor() as u64));
+            // Until the bucket is full again
+            let reset = rate.wait(rate.burst - remaining);
+            headers.insert("x-ratelimit-reset", seconds(reset));
+            response
+        }
+        Decision::Limited { retry_after } => {
+            let mut response = (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ErrorResponse {
+                    error: "Rate limit exceeded".to_string(),
+                    message: format!(
+                        "At most {} requests per second with bursts of {} are allowed",
+                        rate.per_second, rate.burst
+                    ),
+                    ..Default::default()
+                }),
+            )
+                .into_response();
+            let headers = response.headers_mut();
+            headers.insert("x-ratelimit-limit", HeaderValue::from(rate.burst as u64));
+            headers.insert("x-ratelimit-remaining", HeaderValue::from(0));
+            headers.insert("x-ratelimit-reset", seconds(retry_after));
+            headers.insert("retry-after", seconds(retry_after));
+            response
+        }
+    }
+}
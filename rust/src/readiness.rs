@@ -0,0 +1,109 @@
+// Readiness for traffic, reported by GET /readyz (and GET /health).
+//
+// The server isn't ready while it is still warming up (or when the warm-up failed
+// or took longer than WARM_UP_TIMEOUT_MS), once shutdown started draining, or when all execution slots have been busy with executions
+// waiting for longer than READINESS_SATURATION_WINDOW_MS. Saturation is sampled by
+// the probes themselves, so the window is measured between probes.
+
+use sandbox_core::admission::Admission;
+use sandbox_core::config::Config;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct Readiness {
+    warm_up: Mutex<WarmUp>,
+    draining: AtomicBool,
+    saturated_since: Mutex<Option<Instant>>,
+    saturation_window: Duration,
+}
+
+enum WarmUp {
+    Running,
+    Done(Duration),
+    Failed(String),
+}
+
+pub enum NotReady {
+    WarmingUp,
+    WarmUpFailed(String),
+    Draining,
+    Saturated(Duration),
+}
+
+impl NotReady {
+    pub fn condition(&self) -> &'static str {
+        match self {
+            NotReady::WarmingUp => "warming_up",
+            NotReady::WarmUpFailed(_) => "warm_up_failed",
+            NotReady::Draining => "draining",
+            NotReady::Saturated(_) => "saturated",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            NotReady::WarmingUp => "The runtime pool is still warming up".to_string(),
+            NotReady::WarmUpFailed(error) => format!("Warming up failed: {}", error),
+            NotReady::Draining => "The server is shutting down".to_string(),
+            NotReady::Saturated(since) => format!(
+                "All execution slots have been busy with executions waiting for {} ms",
+                since.as_millis()
+            ),
+        }
+    }
+}
+
+impl Readiness {
+    pub fn from_config(config: &Config) -> Self {
+        Readiness {
+            warm_up: Mutex::new(WarmUp::Running),
+            draining: AtomicBool::new(false),
+            saturated_since: Mutex::new(None),
+            saturation_window: Duration::from_millis(config.readiness_saturation_window_ms),
+        }
+    }
+
+    pub fn warmed_up(&self, duration: Duration) {
+        *self.warm_up.lock().unwrap() = WarmUp::Done(duration);
+    }
+
+    pub fn warm_up_failed(&self, error: String) {
+        *self.warm_up.lock().unwrap() = WarmUp::Failed(error);
+    }
+
+    // How long warming up took, once it finished
+    pub fn warm_up_duration(&self) -> Option<Duration> {
+        match *self.warm_up.lock().unwrap() {
+            WarmUp::Done(duration) => Some(duration),
+            _ => None,
+        }
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn check(&self, admission: &Admission) -> Result<(), NotReady> {
+        if self.draining.load(Ordering::Relaxed) {
+            return Err(NotReady::Draining);
+        }
+        match &*self.warm_up.lock().unwrap() {
+            WarmUp::Running => return Err(NotReady::WarmingUp),
+            WarmUp::Failed(error) => return Err(NotReady::WarmUpFailed(error.clone())),
+            WarmUp::Done(_) => {}
+        }
+
+        let saturated = admission.queued() > 0 && admission.in_flight() >= admission.max_concurrent();
+        let mut saturated_since = self.saturated_since.lock().unwrap();
+        if !saturated {
+            *saturated_since = None;
+            return Ok(());
+        }
+        let since = saturated_since.get_or_insert_with(Instant::now).elapsed();
+        if since > self.saturation_window {
+            return Err(NotReady::Saturated(since));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+// Request ids, for correlating an execution across the gateway, this service and
+// upstream APIs.
+//
+// Every request is handled under the id from its `X-Request-Id` header, or a new
+// UUIDv7 if it has none (or one that isn't a short printable token). The id is
+// echoed in the response header and as `requestId` in JSON error bodies, and
+// attached to every log line written while the request is handled. Executions
+// also report it in their meta and send it on outbound requests.
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+
+use crate::telemetry;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+fn accept(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let usable = !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+    usable.then(|| id.to_string())
+}
+
+pub async fn middleware(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&HEADER)
+        .and_then(accept)
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let span = tracing::info_span!("request", request_id = %id, route = %request.uri().path());
+    telemetry::continue_trace(&span, request.headers());
+    let response = next.run(request).instrument(span).await;
+    let mut response = with_id_in_error(response, &id).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
+
+// Adds `requestId` to error bodies shaped like ErrorResponse. The id is spliced in
+// before the closing brace so the other fields keep their order.
+async fn with_id_in_error(response: Response, id: &str) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !is_json || !(response.status().is_client_error() || response.status().is_server_error()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let is_error = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .is_ok_and(|body| body.get("error").is_some());
+    let Some(end) = bytes.iter().rposition(|b| *b == b'}').filter(|_| is_error) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    let mut body = bytes[..end].to_vec();
+    body.extend_from_slice(b",\"requestId\":");
+    body.extend_from_slice(serde_json::Value::from(id).to_string().as_bytes());
+    body.extend_from_slice(&bytes[end..]);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,35 @@
+// Validation of execution inputs and results against caller-provided JSON Schemas.
+//
+// Remote `$ref` resolution is compiled out (jsonschema without default features),
+// so a schema can't be used to make the service fetch arbitrary URLs.
+
+use jsonschema::Validator;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    pub instance_path: String,
+    pub keyword: String,
+    pub message: String,
+}
+
+pub fn compile(schema: &Value) -> Result<Validator, String> {
+    jsonschema::validator_for(schema).map_err(|e| format!("Invalid JSON Schema: {}", e))
+}
+
+pub fn violations(validator: &Validator, instance: &Value) -> Vec<Violation> {
+    validator
+        .iter_errors(instance)
+        .map(|error| {
+            let schema_path = error.schema_path.to_string();
+            Violation {
+                instance_path: error.instance_path.to_string(),
+                // The failing keyword is the last segment of the schema path
+                keyword: schema_path.rsplit('/').next().unwrap_or_default().to_string(),
+                message: error.to_string(),
+            }
+        })
+        .collect()
+}
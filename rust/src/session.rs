@@ -0,0 +1,283 @@
+// Interactive sessions over a WebSocket, at GET /session.
+//
+// Every connection gets a runtime and context of its own, which live as long as
+// the socket does, so globals defined by one `eval` message are available to the
+// next. Each eval runs with the execution timeout, and the request limit applies to
+// the whole session. `console` output is sent back as `log` messages while the code
+// runs. The socket is read on a separate task, so a client closing it interrupts
+// even a busy script and the runtime is dropped right away.
+
+use crate::api::{empty_inputs, ErrorResponse};
+use crate::request_id::RequestId;
+use crate::AppState;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Extension, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::{SinkExt, StreamExt};
+use rquickjs::AsyncContext;
+use sandbox_core::cancel::{Cancellation, Interruption};
+use sandbox_core::cpu::CpuTime;
+use sandbox_core::engine::{create_context, evaluate, ConsoleSink};
+use sandbox_core::js_error::JsError;
+use sandbox_core::modules::SandboxModules;
+use sandbox_core::{ErrorKind, ExecError, FetchSession};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+use tracing::Instrument;
+
+// Messages read from the client but not handled yet
+const INBOX_CAPACITY: usize = 16;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Eval {
+        // Echoed on the reply
+        id: Option<Value>,
+        code: String,
+        #[serde(default = "crate::api::empty_inputs")]
+        inputs: Value,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    Result {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        result: Value,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        error: String,
+        message: String,
+        #[serde(rename = "jsError", skip_serializing_if = "Option::is_none")]
+        js_error: Option<JsError>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        thrown: Option<Value>,
+    },
+    Log {
+        level: String,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn error(id: Option<Value>, error: &str, message: String) -> Self {
+        ServerMessage::Error {
+            id,
+            error: error.to_string(),
+            message,
+            js_error: None,
+            thrown: None,
+        }
+    }
+
+    fn into_message(self) -> Message {
+        Message::Text(serde_json::to_string(&self).unwrap_or_default())
+    }
+}
+
+// The whole session runs under the id of the upgrade request
+pub async fn session_handler(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let Ok(permit) = state.sessions.clone().try_acquire_owned() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "Too many sessions".to_string(),
+                message: format!("At most {} sessions can be open at a time", state.max_sessions),
+                ..Default::default()
+            }),
+        )
+            .into_response();
+    };
+    let span = tracing::Span::current();
+    ws.on_upgrade(move |socket| run(state, socket, permit, request_id).instrument(span))
+}
+
+async fn run(state: AppState, socket: WebSocket, _permit: OwnedSemaphorePermit, request_id: RequestId) {
+    let (mut sender, mut receiver) = socket.split();
+    let (outbox, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    let (inbox, mut incoming) = mpsc::channel::<String>(INBOX_CAPACITY);
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            let close = matches!(message, Message::Close(_));
+            if sender.send(message).await.is_err() || close {
+                break;
+            }
+        }
+    });
+    let reader = tokio::spawn({
+        let closed = closed.clone();
+        async move {
+            while let Some(Ok(message)) = receiver.next().await {
+                let text = match message {
+                    Message::Text(text) => text,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                if inbox.send(text).await.is_err() {
+                    break;
+                }
+            }
+            closed.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let console: ConsoleSink = {
+        let outbox = outbox.clone();
+        Arc::new(move |level, message| {
+            let _ = outbox.send(ServerMessage::Log { level, message }.into_message());
+        })
+    };
+    match Session::start(&state, console, request_id.0).await {
+        Ok(session) => loop {
+            let text = match tokio::time::timeout(state.session_idle_timeout, incoming.recv()).await {
+                Ok(Some(text)) => text,
+                Ok(None) => break,
+                Err(_) => {
+                    let message = format!(
+                        "Session closed after {} ms without messages",
+                        state.session_idle_timeout.as_millis()
+                    );
+                    let _ = outbox.send(ServerMessage::error(None, "Session idle", message).into_message());
+                    let _ = outbox.send(Message::Close(Some(CloseFrame {
+                        code: close_code::NORMAL,
+                        reason: "idle timeout".into(),
+                    })));
+                    break;
+                }
+            };
+            let reply = session.handle(&state, &text, &closed).await;
+            if closed.load(Ordering::Relaxed) {
+                break;
+            }
+            let _ = outbox.send(reply.into_message());
+        },
+        Err(e) => {
+            let _ = outbox.send(ServerMessage::error(None, "Session failed", e.message).into_message());
+            let _ = outbox.send(Message::Close(None));
+        }
+    }
+
+    // The session is dropped by now, and with it the runtime and the console's
+    // sender, so the writer ends once everything queued is sent
+    drop(outbox);
+    reader.abort();
+    let _ = writer.await;
+}
+
+// A runtime and context that outlive a single evaluation. Also behind /contexts.
+pub struct Session {
+    // Declared before the runtime it belongs to, so it is dropped first
+    context: AsyncContext,
+    runtime: rquickjs::AsyncRuntime,
+    modules: SandboxModules,
+}
+
+impl Session {
+    // `console` output goes to `console` for as long as the session lives
+    pub async fn start(state: &AppState, console: ConsoleSink, request_id: String) -> Result<Self, ExecError> {
+        let executor = &state.executor;
+        let (runtime, _) = executor.runtimes().create().await?;
+        let modules = SandboxModules::default();
+        runtime.set_loader(modules.clone(), modules.clone()).await;
+        let limits = executor.limits();
+        let http_session = Arc::new(
+            FetchSession::new(limits.max_requests, request_id)
+                .with_concurrency(limits.fetch_concurrency, limits.fetch_concurrency_per_host),
+        );
+        let mut options = executor.options(Cancellation::new(executor.limits().execution_timeout));
+        options.console = console;
+        let context = create_context(&runtime, &empty_inputs(), executor.http(), http_session, &options).await?;
+
+        Ok(Session { context, runtime, modules })
+    }
+
+    async fn handle(&self, state: &AppState, text: &str, closed: &Arc<AtomicBool>) -> ServerMessage {
+        let (id, code, inputs) = match serde_json::from_str(text) {
+            Ok(ClientMessage::Eval { id, code, inputs }) => (id, code, inputs),
+            Err(e) => return ServerMessage::error(None, "Invalid message", e.to_string()),
+        };
+
+        let interrupted = {
+            let closed = closed.clone();
+            move || closed.load(Ordering::Relaxed)
+        };
+        match self.eval(state, &code, &inputs, interrupted).await {
+            Ok(result) => ServerMessage::Result { id, result },
+            Err(e) => ServerMessage::Error {
+                id,
+                error: e.kind.error().to_string(),
+                message: e.message,
+                thrown: e.js_error.as_ref().and_then(|js_error| js_error.thrown.clone()),
+                js_error: e.js_error.map(|js_error| *js_error),
+            },
+        }
+    }
+
+    // Runs the code with the execution timeout and INPUTS replaced, stopping early
+    // once `interrupted` returns true
+    pub async fn eval(
+        &self,
+        state: &AppState,
+        code: &str,
+        inputs: &Value,
+        interrupted: impl Fn() -> bool + Send + 'static,
+    ) -> Result<Value, ExecError> {
+        let timeout = state.executor.limits().execution_timeout;
+        let cpu = CpuTime::new(state.executor.limits().cpu_budget);
+        let cancellation = Cancellation::with_cpu_budget(timeout, cpu.clone());
+        let interrupt = {
+            let cancellation = cancellation.clone();
+            move || cancellation.interruption().is_some() || interrupted()
+        };
+        self.runtime.set_interrupt_handler(Some(Box::new(interrupt))).await;
+
+        let inputs_json = serde_json::to_string(inputs).unwrap_or_else(|_| "{}".to_string());
+        let set_inputs = self.context.with(|ctx| {
+            let inputs = ctx.json_parse(inputs_json)?;
+            ctx.globals().set("INPUTS", inputs)
+        });
+        if let Err(e) = set_inputs.await {
+            return Err(format!("INPUTS injection error: {}", e).into());
+        }
+
+        let options = state.executor.options(cancellation.clone());
+        let evaluation = cpu.measure(evaluate(&self.context, code, &self.modules, &options));
+        match tokio::time::timeout(timeout, evaluation).await {
+            Ok(outcome) => match (outcome, cancellation.interruption()) {
+                (Err(_), Some(Interruption::CpuBudget)) => Err(ExecError::new(
+                    ErrorKind::CpuLimit,
+                    format!(
+                        "Execution exceeded the EXEC_CPU_MS limit of {} ms of CPU time",
+                        state.executor.limits().cpu_budget.as_millis()
+                    ),
+                )),
+                (Err(_), Some(interruption)) => Err(ExecError::interrupted(interruption, timeout)),
+                (Err(e), None) if e.is_out_of_memory() => Err(ExecError::new(
+                    ErrorKind::MemoryLimit,
+                    format!(
+                        "Execution exceeded the JS_MAX_MEMORY_BYTES limit of {} bytes",
+                        state.executor.limits().memory_bytes
+                    ),
+                )),
+                (outcome, _) => outcome,
+            },
+            Err(_) => Err(ExecError::interrupted(Interruption::TimedOut, timeout)),
+        }
+    }
+}
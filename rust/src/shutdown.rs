@@ -0,0 +1,56 @@
+// Graceful shutdown on SIGTERM or Ctrl-C.
+//
+// The server stops accepting connections and reports not ready, then waits up to
+// SHUTDOWN_GRACE_SECONDS (default 30) for executions running or queued at that
+// point to finish. Executions still running after that are interrupted, and the
+// process exits once they have answered, or after STRAGGLER_WAIT at the latest.
+
+use sandbox_core::admission::Admission;
+use sandbox_core::cancel;
+use std::time::Duration;
+
+// How long interrupted executions get to send their error response
+pub const STRAGGLER_WAIT: Duration = Duration::from_secs(2);
+
+// Resolves on SIGTERM or Ctrl-C
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+pub struct Summary {
+    pub finished: usize,
+    pub aborted: usize,
+}
+
+// Waits for the executions running or queued now, and interrupts those still
+// running once the grace period is over
+pub async fn drain(admission: &Admission, grace: Duration) -> Summary {
+    let pending = admission.in_flight() + admission.queued();
+    if tokio::time::timeout(grace, admission.drained()).await.is_ok() {
+        return Summary { finished: pending, aborted: 0 };
+    }
+    let aborted = admission.in_flight() + admission.queued();
+    cancel::interrupt_all();
+    Summary {
+        finished: pending.saturating_sub(aborted),
+        aborted,
+    }
+}
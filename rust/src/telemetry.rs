@@ -0,0 +1,79 @@
+// OpenTelemetry tracing, enabled by setting OTEL_EXPORTER_OTLP_ENDPOINT.
+//
+// The request, execution and fetch spans are exported over OTLP/HTTP; the standard
+// OTEL_* variables configure the exporter and OTEL_SERVICE_NAME the service name.
+// The endpoint and service name may also come from the config file.
+// A `traceparent` header on the incoming request continues the caller's trace, and
+// outbound requests carry the trace context of their fetch span. Without an
+// endpoint nothing is exported or propagated.
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::global;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::level_filters::LevelFilter;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use sandbox_core::config::Config;
+
+// Instrumentation scope of the spans
+const TRACER_NAME: &str = "js-execution-service";
+
+// The layer exporting spans, if an endpoint is configured
+pub fn layer<S>(config: &Config) -> Result<Option<impl Layer<S>>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let endpoint = config.otel_exporter_otlp_endpoint.trim().trim_end_matches('/');
+    if endpoint.is_empty() {
+        return Ok(None);
+    }
+    // Like the SDK does with OTEL_EXPORTER_OTLP_ENDPOINT, which
+    // OTEL_EXPORTER_OTLP_TRACES_ENDPOINT still takes precedence over
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint))
+        .build()
+        .map_err(|e| format!("Failed to build the OTLP exporter: {}", e))?;
+    let resource = Resource::builder()
+        .with_service_name(config.otel_service_name.clone())
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(TRACER_NAME);
+    global::set_tracer_provider(provider);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(LevelFilter::INFO),
+    ))
+}
+
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+// Makes `span` part of the trace the incoming request belongs to
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&Headers(headers)));
+    span.set_parent(context);
+}
@@ -0,0 +1,342 @@
+// Tenants: the teams sharing a server, each with guardrails and usage of its own.
+//
+// Tenants are `[tenants.<id>]` tables of the config file. A request runs for the
+// tenant whose `api_keys` list its X-API-Key, or, when it has no API key, for the
+// tenant named by its X-Tenant header; any other request runs for no tenant, with
+// the server-wide limits alone. A tenant's concurrency limit, rate limit and monthly
+// budgets refuse its executions with 429 without affecting other tenants, and its
+// fetch allowlist applies on top of the outbound policy. State namespaces, cached
+// results and cached responses are kept apart by tenant.
+//
+// Usage is counted per calendar month (UTC) for GET /tenants/{id}/usage, and since
+// startup for the jsexec_tenant_* metrics.
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use sandbox_core::config::{Config, TenantConfig};
+use sandbox_core::executor::Usage;
+use sandbox_core::policy::UrlPatterns;
+
+use crate::api::{api_key_id, ErrorResponse};
+use crate::audit;
+use crate::body::Json;
+use crate::rate_limit::{Bucket, Decision, Rate};
+use crate::AppState;
+
+const MAX_TENANT_ID_LEN: usize = 64;
+
+pub struct Tenants {
+    tenants: HashMap<String, Arc<Tenant>>,
+    // Tenant ids by the id of each of their API keys
+    by_key: HashMap<String, String>,
+    // QUEUE_WAIT_TIMEOUT_MS, also the longest wait for one of a tenant's slots
+    wait: Duration,
+}
+
+struct Tenant {
+    config: TenantConfig,
+    slots: Option<Arc<Semaphore>>,
+    rate: Option<Rate>,
+    bucket: Mutex<Bucket>,
+    allowlist: Option<Arc<UrlPatterns>>,
+    // Executions admitted and not finished yet
+    in_flight: AtomicUsize,
+    usage: Mutex<TenantCounters>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    executions: u64,
+    cpu_micros: u64,
+    fetched_bytes: u64,
+}
+
+impl Counters {
+    fn add(&mut self, usage: &Usage) {
+        self.executions += 1;
+        self.cpu_micros += usage.cpu_time.as_micros() as u64;
+        self.fetched_bytes += usage.fetched_bytes;
+    }
+}
+
+struct TenantCounters {
+    // e.g. "2026-10"
+    month: String,
+    this_month: Counters,
+    since_startup: Counters,
+}
+
+impl TenantCounters {
+    // The counters of the current month, which start over when a new one begins
+    fn this_month(&mut self) -> &mut Counters {
+        let month = current_month();
+        if self.month != month {
+            self.month = month;
+            self.this_month = Counters::default();
+        }
+        &mut self.this_month
+    }
+}
+
+// An execution of a tenant, admitted until it is dropped
+pub struct TenantSlot {
+    tenant: Arc<Tenant>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for TenantSlot {
+    fn drop(&mut self) {
+        self.tenant.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantUsage {
+    tenant: String,
+    // The calendar month the counts are of
+    period: String,
+    executions: u64,
+    cpu_ms: u64,
+    fetched_bytes: u64,
+    in_flight: usize,
+    limits: TenantLimits,
+}
+
+// The tenant's limits, null where it has none of its own
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TenantLimits {
+    max_concurrent_executions: Option<usize>,
+    rate_limit_per_second: Option<f64>,
+    rate_limit_burst: Option<f64>,
+    monthly_executions: Option<u64>,
+    monthly_cpu_ms: Option<u64>,
+}
+
+impl Tenants {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut tenants = HashMap::new();
+        let mut by_key = HashMap::new();
+        for (id, tenant) in &config.tenants {
+            let valid = (1..=MAX_TENANT_ID_LEN).contains(&id.len())
+                && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+            if !valid {
+                return Err(format!(
+                    "Invalid tenant id {:?}: ids are 1 to {} letters, digits, '-', '_' or '.'",
+                    id, MAX_TENANT_ID_LEN
+                ));
+            }
+            for key in &tenant.api_keys {
+                if let Some(other) = by_key.insert(api_key_id(key), id.clone()) {
+                    return Err(format!("The same API key is listed by the tenants {} and {}", other, id));
+                }
+            }
+            // Bursts of a second's worth unless set
+            let burst = if tenant.rate_limit_burst > 0.0 { tenant.rate_limit_burst } else { tenant.rate_limit_per_second };
+            let rate = (tenant.rate_limit_per_second > 0.0).then(|| Rate::new(tenant.rate_limit_per_second, burst));
+            let tenant = Tenant {
+                config: tenant.clone(),
+                slots: (tenant.max_concurrent_executions > 0)
+                    .then(|| Arc::new(Semaphore::new(tenant.max_concurrent_executions))),
+                bucket: Mutex::new(Bucket::full(rate.unwrap_or(Rate::new(1.0, 1.0)), Instant::now())),
+                rate,
+                allowlist: (!tenant.fetch_allowlist.is_empty())
+                    .then(|| Arc::new(UrlPatterns::parse(&tenant.fetch_allowlist))),
+                in_flight: AtomicUsize::new(0),
+                usage: Mutex::new(TenantCounters {
+                    month: current_month(),
+                    this_month: Counters::default(),
+                    since_startup: Counters::default(),
+                }),
+            };
+            tenants.insert(id.clone(), Arc::new(tenant));
+        }
+        Ok(Tenants {
+            tenants,
+            by_key,
+            wait: Duration::from_millis(config.queue_wait_timeout_ms),
+        })
+    }
+
+    // The tenant the request claims to run for: the one listing its API key, or
+    // without one the X-Tenant header, which `check` refuses if it names no tenant
+    pub fn resolve(&self, headers: &HeaderMap) -> Option<String> {
+        match headers.get("x-api-key").and_then(|key| key.to_str().ok()) {
+            Some(key) => self.by_key.get(&api_key_id(key)).cloned(),
+            None => headers.get("x-tenant")?.to_str().ok().map(|tenant| tenant.trim().to_string()),
+        }
+    }
+
+    pub fn check(&self, tenant: &str) -> Result<(), Box<(StatusCode, ErrorResponse)>> {
+        match self.tenants.contains_key(tenant) {
+            true => Ok(()),
+            false => Err(Box::new((
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    error: "Unknown tenant".to_string(),
+                    message: format!("X-Tenant names no configured tenant: {:?}", tenant),
+                    ..Default::default()
+                },
+            ))),
+        }
+    }
+
+    pub fn allowlist(&self, tenant: &str) -> Option<Arc<UrlPatterns>> {
+        self.tenants.get(tenant)?.allowlist.clone()
+    }
+
+    // Admits an execution within the tenant's budgets, rate limit and slots, waiting
+    // up to QUEUE_WAIT_TIMEOUT_MS for a slot
+    pub async fn admit(&self, id: &str) -> Result<TenantSlot, (StatusCode, ErrorResponse)> {
+        let refused = |error: &str, message: String| {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorResponse {
+                    error: error.to_string(),
+                    message,
+                    ..Default::default()
+                },
+            )
+        };
+        let Some(tenant) = self.tenants.get(id) else {
+            return Err(*self.check(id).unwrap_err());
+        };
+        let config = &tenant.config;
+        {
+            let mut usage = tenant.usage.lock().unwrap();
+            let month = usage.this_month();
+            let exhausted = if config.monthly_executions > 0 && month.executions >= config.monthly_executions {
+                Some(("monthly_executions", config.monthly_executions))
+            } else if config.monthly_cpu_ms > 0 && month.cpu_micros / 1000 >= config.monthly_cpu_ms {
+                Some(("monthly_cpu_ms", config.monthly_cpu_ms))
+            } else {
+                None
+            };
+            if let Some((budget, limit)) = exhausted {
+                let message = format!("Tenant {} used up its {} of {} for {}", id, budget, limit, usage.month);
+                return Err(refused("Quota exceeded", message));
+            }
+        }
+        if let Some(rate) = tenant.rate {
+            if let Decision::Limited { .. } = tenant.bucket.lock().unwrap().take(rate, Instant::now()) {
+                let message = format!(
+                    "Tenant {} may run at most {} executions per second with bursts of {}",
+                    id, rate.per_second, rate.burst
+                );
+                return Err(refused("Rate limit exceeded", message));
+            }
+        }
+        let permit = match &tenant.slots {
+            Some(slots) => match tokio::time::timeout(self.wait, slots.clone().acquire_owned()).await {
+                // Never closed
+                Ok(permit) => Some(permit.unwrap()),
+                Err(_) => {
+                    let message = format!(
+                        "All {} execution slots of tenant {} stayed busy for {} ms",
+                        config.max_concurrent_executions,
+                        id,
+                        self.wait.as_millis()
+                    );
+                    return Err(refused("Server busy", message));
+                }
+            },
+            None => None,
+        };
+        tenant.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(TenantSlot { tenant: tenant.clone(), _permit: permit })
+    }
+
+    // Counts an execution that ran, or came from the result cache
+    pub fn record(&self, id: &str, usage: &Usage) {
+        if let Some(tenant) = self.tenants.get(id) {
+            let mut counters = tenant.usage.lock().unwrap();
+            counters.this_month().add(usage);
+            counters.since_startup.add(usage);
+        }
+    }
+
+    pub fn usage(&self, id: &str) -> Option<TenantUsage> {
+        let tenant = self.tenants.get(id)?;
+        let config = &tenant.config;
+        let mut counters = tenant.usage.lock().unwrap();
+        let month = *counters.this_month();
+        let limit = |value: u64| (value > 0).then_some(value);
+        Some(TenantUsage {
+            tenant: id.to_string(),
+            period: counters.month.clone(),
+            executions: month.executions,
+            cpu_ms: month.cpu_micros / 1000,
+            fetched_bytes: month.fetched_bytes,
+            in_flight: tenant.in_flight.load(Ordering::Relaxed),
+            limits: TenantLimits {
+                max_concurrent_executions: (config.max_concurrent_executions > 0).then_some(config.max_concurrent_executions),
+                rate_limit_per_second: tenant.rate.map(|rate| rate.per_second),
+                rate_limit_burst: tenant.rate.map(|rate| rate.burst),
+                monthly_executions: limit(config.monthly_executions),
+                monthly_cpu_ms: limit(config.monthly_cpu_ms),
+            },
+        })
+    }
+
+    // The usage since startup by tenant, in the exposition format; empty without tenants
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        if self.tenants.is_empty() {
+            return out;
+        }
+        let mut ids: Vec<&String> = self.tenants.keys().collect();
+        ids.sort();
+        let totals: Vec<(&String, Counters)> =
+            ids.into_iter().map(|id| (id, self.tenants[id].usage.lock().unwrap().since_startup)).collect();
+        let mut counter = |name: &str, help: &str, value: fn(&Counters) -> f64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (id, counters) in &totals {
+                let _ = writeln!(out, "{}{{tenant=\"{}\"}} {}", name, id, value(counters));
+            }
+        };
+        counter("jsexec_tenant_executions_total", "Executions by tenant.", |c| c.executions as f64);
+        counter("jsexec_tenant_cpu_seconds_total", "CPU time of executions by tenant.", |c| c.cpu_micros as f64 / 1e6);
+        counter(
+            "jsexec_tenant_fetched_bytes_total",
+            "Response body bytes executions received from upstreams, by tenant.",
+            |c| c.fetched_bytes as f64,
+        );
+        out
+    }
+}
+
+// The UTC month of now, e.g. "2026-10"
+fn current_month() -> String {
+    audit::timestamp()[..7].to_string()
+}
+
+// The caller's own tenant only, as resolved for /execute
+pub async fn usage_handler(State(state): State<AppState>, Path(id): Path<String>, headers: HeaderMap) -> Response {
+    let usage = match state.tenants.resolve(&headers) {
+        Some(tenant) if tenant == id => state.tenants.usage(&id),
+        _ => None,
+    };
+    match usage {
+        Some(usage) => Json(usage).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Tenant not found".to_string(),
+                message: format!("No tenant {} for this API key or X-Tenant", id),
+                ..Default::default()
+            }),
+        )
+            .into_response(),
+    }
+}
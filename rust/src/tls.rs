@@ -0,0 +1,136 @@
+// HTTPS, enabled by setting TLS_CERT_PATH and TLS_KEY_PATH to PEM files.
+//
+// TCP listeners then only accept TLS, over HTTP/2 or HTTP/1.1; Unix sockets stay
+// plaintext. The certificate is reloaded on SIGHUP and when one of the files
+// changes, so it can be rotated without a restart. A certificate that fails to load
+// at startup stops the server, while a failed reload keeps the one in use. With
+// TLS_CLIENT_CA_PATH, clients must present a certificate issued by one of its CAs.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::TlsAcceptor;
+
+use sandbox_core::config::Config;
+
+// How often the files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Tls {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    client_ca_path: Option<PathBuf>,
+    config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Tls {
+    pub fn from_config(config: &Config) -> Result<Option<Arc<Self>>, String> {
+        let path = |value: &str| Some(value.trim()).filter(|v| !v.is_empty()).map(PathBuf::from);
+        let (cert_path, key_path) = match (path(&config.tls_cert_path), path(&config.tls_key_path)) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        };
+        let client_ca_path = path(&config.tls_client_ca_path);
+        let config = load(&cert_path, &key_path, client_ca_path.as_ref())?;
+        Ok(Some(Arc::new(Tls {
+            cert_path,
+            key_path,
+            client_ca_path,
+            config: RwLock::new(Arc::new(config)),
+        })))
+    }
+
+    // For one connection, with the certificate current when it was accepted
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.read().unwrap().clone())
+    }
+
+    fn reload(&self) {
+        match load(&self.cert_path, &self.key_path, self.client_ca_path.as_ref()) {
+            Ok(config) => {
+                *self.config.write().unwrap() = Arc::new(config);
+                tracing::info!("Reloaded the TLS certificate");
+            }
+            Err(e) => tracing::error!("Keeping the current TLS certificate: {}", e),
+        }
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert_path), Some(&self.key_path), self.client_ca_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    pub fn spawn_reload(self: &Arc<Self>) {
+        let tls = self.clone();
+        tokio::spawn(async move {
+            let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(hangup) => Some(hangup),
+                Err(e) => {
+                    tracing::error!("Can't reload the TLS certificate on SIGHUP: {}", e);
+                    None
+                }
+            };
+            let mut modified = tls.modified();
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let now = tls.modified();
+                        if now == modified {
+                            continue;
+                        }
+                        modified = now;
+                    }
+                    Some(_) = async { hangup.as_mut()?.recv().await } => {}
+                }
+                tls.reload();
+            }
+        });
+    }
+}
+
+fn load(cert_path: &PathBuf, key_path: &PathBuf, client_ca_path: Option<&PathBuf>) -> Result<ServerConfig, String> {
+    let certs = certificates(cert_path, "TLS_CERT_PATH")?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("TLS_KEY_PATH {} has no usable private key: {}", key_path.display(), e))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(path, "TLS_CLIENT_CA_PATH")? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("TLS_CLIENT_CA_PATH {} has an invalid CA: {}", path.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| format!("TLS_CLIENT_CA_PATH {} can't verify clients: {}", path.display(), e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("TLS_CERT_PATH and TLS_KEY_PATH don't form a usable pair: {}", e))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn certificates(path: &PathBuf, var: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{} {} can't be read: {}", var, path.display(), e))?;
+    match certs.is_empty() {
+        true => Err(format!("{} {} contains no certificates", var, path.display())),
+        false => Ok(certs),
+    }
+}
@@ -0,0 +1,78 @@
+// What build is running, for GET /version, the X-Sandbox-Version header and the
+// startup log. The build metadata is captured by build.rs.
+
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Serialize;
+use sandbox_core::prelude_scripts::PreludeFile;
+use std::ffi::CStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const GIT_COMMIT: &str = env!("BUILD_GIT_COMMIT");
+const GIT_DIRTY: &str = env!("BUILD_GIT_DIRTY");
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+const RUSTC_VERSION: &str = env!("BUILD_RUSTC_VERSION");
+const RQUICKJS_VERSION: &str = env!("BUILD_RQUICKJS_VERSION");
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo<'a> {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    // Whether tracked files had uncommitted changes
+    pub git_dirty: bool,
+    pub build_timestamp: &'static str,
+    pub rustc_version: &'static str,
+    pub rquickjs_version: &'static str,
+    pub quickjs_version: String,
+    // `version (commit)`, as in the header
+    pub full: &'static str,
+    // How long the startup warm-up took; null until it finished
+    pub warm_up_ms: Option<u64>,
+    // The PRELUDE_DIR scripts every context gets
+    pub prelude_scripts: &'a [PreludeFile],
+}
+
+pub fn build_info(warm_up: Option<Duration>, prelude_scripts: &[PreludeFile]) -> BuildInfo<'_> {
+    BuildInfo {
+        version: VERSION,
+        git_commit: GIT_COMMIT,
+        git_dirty: GIT_DIRTY == "true",
+        build_timestamp: BUILD_TIMESTAMP,
+        rustc_version: RUSTC_VERSION,
+        rquickjs_version: RQUICKJS_VERSION,
+        quickjs_version: quickjs_version(),
+        full: full(),
+        warm_up_ms: warm_up.map(|duration| duration.as_millis() as u64),
+        prelude_scripts,
+    }
+}
+
+// E.g. `1.0.0 (0e2c975-dirty)`
+pub fn full() -> &'static str {
+    static FULL: OnceLock<String> = OnceLock::new();
+    FULL.get_or_init(|| {
+        let commit = GIT_COMMIT.get(..7).unwrap_or(GIT_COMMIT);
+        let dirty = if GIT_DIRTY == "true" { "-dirty" } else { "" };
+        format!("{} ({}{})", VERSION, commit, dirty)
+    })
+}
+
+// The engine bundled by rquickjs-sys reports its own version
+fn quickjs_version() -> String {
+    // SAFETY: JS_GetVersion returns a static, NUL-terminated string
+    unsafe { CStr::from_ptr(rquickjs::qjs::JS_GetVersion()) }.to_string_lossy().into_owned()
+}
+
+// Adds X-Sandbox-Version to every response
+pub async fn middleware(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(full()) {
+        response.headers_mut().insert("x-sandbox-version", value);
+    }
+    response
+}
@@ -0,0 +1,81 @@
+// AbortController and AbortSignal: cancelling requests while they are in flight.
+
+mod support;
+
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use support::{MockResponse, MockUpstream, TestApp};
+
+// The upstream notices the closed connection shortly after the abort
+async fn wait_for_cancelled(upstream: &MockUpstream, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while upstream.cancelled() < count {
+        assert!(Instant::now() < deadline, "the upstream saw {} cancelled requests", upstream.cancelled());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn aborts_a_slow_request() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!({})).with_delay(Duration::from_secs(5)));
+    let code = format!(
+        "const controller = new AbortController();
+        setTimeout(() => controller.abort(), 100);
+        try {{ await httpRequest('{}', {{ signal: controller.signal }}); }}
+        catch (e) {{ [e instanceof DOMException, e.name, e.message, controller.signal.aborted] }}",
+        app.upstream.url("/slow")
+    );
+
+    let started = Instant::now();
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([true, "AbortError", "This operation was aborted", true]));
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    wait_for_cancelled(&app.upstream, 1).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancels_the_loser_of_a_race() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/fast", MockResponse::json(200, json!({ "name": "fast" })).with_delay(Duration::from_millis(50)));
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!({ "name": "slow" })).with_delay(Duration::from_secs(5)));
+    let code = format!(
+        "const controller = new AbortController();
+        const slow = fetch('{0}/slow', {{ signal: controller.signal }}).then((r) => r.json());
+        const fast = fetch('{0}/fast').then((r) => r.json());
+        const winner = await Promise.race([fast, slow]);
+        controller.abort('lost the race');
+        const loser = await slow.catch((e) => [e.name, e.message, e.reason]);
+        [winner.name, loser]",
+        app.upstream.url("")
+    );
+
+    let started = Instant::now();
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["fast", ["AbortError", "The request was aborted: lost the race", "lost the race"]]));
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    wait_for_cancelled(&app.upstream, 1).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_out_with_abort_signal_timeout() {
+    // Timeout signals don't wait out of the sleep budget
+    let app = TestApp::with_config(|config| config.sleep_budget_ms = 0).await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!({})).with_delay(Duration::from_secs(5)));
+    let code = format!(
+        "const url = '{}';
+        const timedOut = await httpRequest(url, {{ signal: AbortSignal.timeout(100) }}).catch((e) => e.name);
+        const aborted = await fetch(url, {{ signal: AbortSignal.abort('not needed') }}).catch((e) => e.reason);
+        let thrown;
+        try {{ AbortSignal.abort(new RangeError('stop')).throwIfAborted(); }} catch (e) {{ thrown = e.message; }}
+        [timedOut, aborted, thrown]",
+        app.upstream.url("/slow")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["TimeoutError", "not needed", "stop"]));
+    // A signal aborted beforehand sends nothing
+    assert_eq!(app.upstream.requests().len(), 1);
+    wait_for_cancelled(&app.upstream, 1).await;
+}
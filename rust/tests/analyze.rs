@@ -0,0 +1,103 @@
+// Globals referenced by code, found by POST /analyze without running it.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+async fn analyze(app: &TestApp, code: &str) -> Value {
+    let (status, body) = app.post("/analyze", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_host_functions_and_namespace_members() {
+    let app = TestApp::start().await;
+    let body = analyze(
+        &app,
+        "// httpRequest in a comment isn't a use
+        const { data } = await httpRequest(INPUTS.url, { headers: { authorization: ENV.TOKEN } });
+        const digest = crypto.sha256(JSON.stringify(data));
+        const label = `${utils.pad(digest, 70)} from fetch`;
+        const re = /graphql/g;
+        const response = await globalThis.fetch(INPUTS.url);
+        console.log(label, re, response.status, data.crypto.md5);
+        ({ digest, items: [1, 2].map((x) => x * 2) });",
+    )
+    .await;
+
+    assert_eq!(body["syntaxValid"], json!(true), "{}", body);
+    assert_eq!(body.get("errors"), None);
+    assert_eq!(
+        body["uses"],
+        json!(["ENV", "INPUTS", "console.log", "crypto.sha256", "fetch", "httpRequest", "utils.pad"])
+    );
+    assert_eq!(body["unknownGlobals"], json!([]));
+    assert_eq!(body["computedAccess"], json!([]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_globals_the_sandbox_lacks() {
+    let app = TestApp::start().await;
+    let body = analyze(
+        &app,
+        "function read(path, { encoding = 'utf8' } = {}) {
+            return require('fs').readFileSync(path, encoding);
+        }
+        class Reader extends EventEmitter {}
+        try { read(process.env.HOME) } catch (e) { label: for (const [k, v] of Object.entries(e)) break label; }
+        new Reader(Buffer.from('x'), typeof window === 'undefined' ? Date.now() : 0);",
+    )
+    .await;
+
+    assert_eq!(body["uses"], json!([]), "{}", body);
+    assert_eq!(body["unknownGlobals"], json!(["Buffer", "EventEmitter", "process", "require", "window"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_computed_access_it_cant_follow() {
+    let app = TestApp::start().await;
+    let body = analyze(
+        &app,
+        "const name = 'fe' + 'tch';
+        const hash = crypto['sha' + '256'];
+        globalThis[name](INPUTS.url);",
+    )
+    .await;
+
+    assert_eq!(body["uses"], json!(["INPUTS", "crypto"]), "{}", body);
+    assert_eq!(body["computedAccess"], json!(["crypto", "globalThis"]));
+    assert_eq!(body["unknownGlobals"], json!([]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_syntax_errors_like_validation() {
+    let app = TestApp::start().await;
+    let body = analyze(&app, "const x = ;\nfetch(x)").await;
+
+    assert_eq!(body["syntaxValid"], json!(false), "{}", body);
+    let (_, validation) = app.post("/validate", json!({ "code": "const x = ;\nfetch(x)" })).await;
+    assert_eq!(body["errors"], validation["errors"]);
+    // The scan still runs
+    assert_eq!(body["uses"], json!(["fetch"]));
+
+    let (status, _) = app.post("/analyze", json!({ "code": "" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn knows_every_global_of_an_execution() {
+    let app = TestApp::start().await;
+    let (status, body) = app
+        .exec("Object.getOwnPropertyNames(globalThis).filter((name) => !name.startsWith('__'))", json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let globals: Vec<&str> = body["result"].as_array().unwrap().iter().map(|name| name.as_str().unwrap()).collect();
+
+    let analysis = analyze(&app, &globals.join(";\n")).await;
+    assert_eq!(analysis["unknownGlobals"], json!([]), "{}", analysis);
+    assert!(analysis["uses"].as_array().unwrap().contains(&json!("SECRETS")));
+}
@@ -0,0 +1,71 @@
+// The API under /v1, and the deprecated unprefixed aliases.
+
+mod support;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+fn post(path: &str, body: Value) -> Request {
+    Request::post(path).header("content-type", "application/json").body(Body::from(body.to_string())).unwrap()
+}
+
+async fn json_body(response: Response) -> Value {
+    serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_the_api_under_v1() {
+    let app = TestApp::start().await;
+    let response = app.response(post("/v1/execute", json!({ "code": "INPUTS.a + 1", "inputs": { "a": 41 } }))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("deprecation"));
+    assert_eq!(json_body(response).await, json!({ "apiVersion": "v1", "result": 42 }));
+
+    let (status, body) = app.get("/v1/livez").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["apiVersion"], json!("v1"));
+    let (status, body) = app.post("/v1/execute", json!({ "code": "throw new Error('no')" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!((body["apiVersion"].clone(), body["error"].clone()), (json!("v1"), json!("RuntimeError")));
+    let (status, metrics) = app.get("/v1/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(metrics.as_str().unwrap().contains("jsexec_executions_total"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_unprefixed_paths_as_deprecated_aliases() {
+    let app = TestApp::start().await;
+    let response = app.response(post("/execute", json!({ "code": "1 + 1" }))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "true");
+    assert_eq!(response.headers()["link"], "</v1/execute>; rel=\"successor-version\"");
+    // The shapes of the first releases, without apiVersion
+    assert_eq!(json_body(response).await, json!({ "result": 2 }));
+
+    let submitted = app.response(post("/jobs", json!({ "code": "3" }))).await;
+    assert_eq!(submitted.status(), StatusCode::ACCEPTED);
+    let job_id = json_body(submitted).await["job_id"].as_str().unwrap().to_string();
+    // State is shared between the two
+    let status = app.response(Request::get(format!("/v1/jobs/{}", job_id)).body(Body::empty()).unwrap()).await;
+    assert_eq!(status.status(), StatusCode::OK);
+    assert!(!status.headers().contains_key("deprecation"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_unknown_versions_with_the_supported_ones() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/v2/execute", json!({ "code": "1" })).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], json!("Unknown API version"));
+    assert_eq!(body["supportedVersions"], json!(["v1"]));
+    assert!(body["message"].as_str().unwrap().contains("v2"), "{}", body);
+
+    let (status, body) = app.get("/v1/nowhere").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], json!("Not found"));
+}
@@ -0,0 +1,141 @@
+// Audit records of executions, written to AUDIT_FILE and posted to AUDIT_URL.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use support::{MockResponse, MockUpstream, TestApp};
+
+// The records are written in the background, shortly after the responses
+async fn read_records(path: &Path, count: usize) -> Vec<Value> {
+    let deadline = Instant::now() + Duration::from_secs(3);
+    loop {
+        let records: Vec<Value> = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if records.len() >= count {
+            return records;
+        }
+        assert!(Instant::now() < deadline, "{} has {} records", path.display(), records.len());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn appends_a_record_per_execution() {
+    let path = std::env::temp_dir().join(format!("sandbox-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let app = TestApp::with_config(|config| config.audit_file = path.to_string_lossy().into_owned()).await;
+    app.upstream.mock("GET", "/users/1", MockResponse::json(200, json!({ "id": 1 })));
+
+    let code = format!(
+        "(await httpRequest('{}?token=' + SECRETS.token, {{ headers: {{ authorization: SECRETS.token }} }})).data.id",
+        app.upstream.url("/users/1")
+    );
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("x-api-key", "key-of-the-caller")
+        .body(Body::from(json!({ "code": code, "secrets": { "token": "hunter2" } }).to_string()))
+        .unwrap();
+    let (status, body) = app.send(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = app.exec("throw new TypeError('nope')", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let records = read_records(&path, 2).await;
+    let _ = std::fs::remove_file(&path);
+    let text = serde_json::to_string(&records).unwrap();
+    assert!(!text.contains("hunter2") && !text.contains("key-of-the-caller"), "{}", text);
+
+    let fetched = &records[0];
+    assert_eq!(fetched["apiKeyId"].as_str().unwrap().len(), 32, "{}", fetched);
+    assert_eq!(fetched["codeSha256"].as_str().unwrap().len(), 64);
+    assert_eq!(fetched["status"], json!(200));
+    assert_eq!(fetched["outcome"], json!("success"));
+    assert_eq!(fetched["error"], Value::Null);
+    assert_eq!(
+        fetched["requests"],
+        json!([{ "method": "GET", "host": "127.0.0.1", "path": "/users/1", "status": 200, "errorCode": null }])
+    );
+
+    let failed = &records[1];
+    assert_eq!(failed["apiKeyId"], Value::Null);
+    assert_eq!(failed["status"], json!(400));
+    assert_eq!(failed["outcome"], json!("js_error"));
+    assert_eq!(failed["error"], json!("RuntimeError"));
+    assert_eq!(failed["requests"], json!([]));
+    assert_ne!(failed["requestId"], fetched["requestId"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rotates_the_file_when_it_grows_too_large() {
+    let path = std::env::temp_dir().join(format!("sandbox-audit-rotated-{}.jsonl", std::process::id()));
+    let rotated = Path::new(&format!("{}.1", path.display())).to_path_buf();
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&rotated);
+    let app = TestApp::with_config(|config| {
+        config.audit_file = path.to_string_lossy().into_owned();
+        config.audit_file_max_bytes = 300;
+    })
+    .await;
+
+    let (status, _) = app.exec("1", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    read_records(&path, 1).await;
+    let (status, _) = app.exec("2", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while !rotated.exists() {
+        assert!(Instant::now() < deadline, "{} wasn't rotated", path.display());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let current = read_records(&path, 1).await;
+    let older = read_records(&rotated, 1).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&rotated);
+    assert_eq!((current.len(), older.len()), (1, 1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_a_failing_collector() {
+    let collector = MockUpstream::start().await;
+    collector.mock_sequence(
+        "POST",
+        "/audit",
+        vec![
+            MockResponse::text(503, "unavailable"),
+            MockResponse::text(502, "bad gateway"),
+            MockResponse::text(204, ""),
+        ],
+    );
+    let app = TestApp::with_config(|config| {
+        config.audit_url = collector.url("/audit");
+        config.audit_http_retry_ms = 20;
+    })
+    .await;
+
+    let (status, body) = app.exec("'audited'", json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while collector.requests().len() < 3 {
+        assert!(Instant::now() < deadline, "the collector got {} posts", collector.requests().len());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let posts = collector.requests();
+    assert_eq!(posts.len(), 3);
+    // Every attempt carries the same record
+    assert!(posts.iter().all(|post| post.body == posts[0].body));
+    assert_eq!(posts[0].headers["content-type"], "application/x-ndjson");
+    let record = posts[2].json();
+    assert_eq!(record["outcome"], json!("success"));
+    assert_eq!(record["status"], json!(200));
+}
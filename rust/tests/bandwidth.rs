@@ -0,0 +1,69 @@
+// MAX_FETCH_TOTAL_BYTES, the bytes all httpRequest calls of an execution may move.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+async fn exec(app: &TestApp, code: &str) -> (StatusCode, Value) {
+    app.post("/execute", json!({ "code": code, "include_meta": true })).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_the_request_after_the_quota_is_used_up() {
+    let app = TestApp::with_config(|config| config.max_fetch_total_bytes = 700_000).await;
+    app.upstream.mock("GET", "/export", MockResponse::text(200, &"x".repeat(400_000)));
+    let url = app.upstream.url("/export");
+    let code = format!(
+        "const results = [];
+        for (let i = 0; i < 3; i++) results.push(await httpRequest('{}'));
+        [results.map((r) => r.errorCode), results[2].data]",
+        url
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"][0], json!([null, null, "bandwidth_quota_exceeded"]));
+    assert_eq!(
+        body["result"][1],
+        json!(format!("GET {} wasn't sent: the execution already moved 800000 bytes, MAX_FETCH_TOTAL_BYTES is 700000", url))
+    );
+    assert_eq!(body["meta"]["usage"]["fetchedBytes"], json!(800_000));
+    assert_eq!(body["meta"]["limits"]["maxFetchTotalBytes"], json!(700_000));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_uploaded_bodies_towards_the_quota() {
+    let app = TestApp::with_config(|config| config.max_fetch_total_bytes = 1000).await;
+    app.upstream.mock("POST", "/upload", MockResponse::text(204, ""));
+    let code = format!(
+        "const upload = () => httpRequest('{}', {{ method: 'POST', body: 'y'.repeat(600) }});
+        [(await upload()).status, (await upload()).status, (await upload()).errorCode]",
+        app.upstream.url("/upload")
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([204, 204, "bandwidth_quota_exceeded"]));
+    assert_eq!(body["meta"]["usage"]["sentBytes"], json!(1200));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shares_the_quota_between_parallel_requests() {
+    let app = TestApp::with_config(|config| config.max_fetch_total_bytes = 1_000_000).await;
+    app.upstream.mock("GET", "/chunk", MockResponse::text(200, &"z".repeat(300_000)));
+    let url = app.upstream.url("/chunk");
+    // All five start within the quota, so all of them finish
+    let code = format!(
+        "const all = await Promise.all([1, 2, 3, 4, 5].map(() => httpRequest('{0}')));
+        const after = await httpRequest('{0}');
+        [all.filter((r) => r.ok).length, after.errorCode]",
+        url
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([5, "bandwidth_quota_exceeded"]));
+    assert_eq!(body["meta"]["usage"]["fetchedBytes"], json!(1_500_000));
+}
@@ -0,0 +1,56 @@
+// POST /execute/batch: several executions in one request, each failing on its own.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_every_job_in_order() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/rates", MockResponse::json(200, json!({ "eur": 0.92 })));
+    let jobs = json!([
+        { "id": "sum", "code": "INPUTS.a + INPUTS.b", "inputs": { "a": 1, "b": 2 } },
+        { "id": "fetch", "code": format!("(await httpRequest('{}')).data.eur", app.upstream.url("/rates")) },
+        { "id": "throws", "code": "throw new RangeError('too far')" },
+        { "id": "syntax", "code": "let = ;" },
+        { "id": "slow", "code": "while (true) {}", "timeout_ms": 100 },
+        { "id": "after", "code": "'still runs'" },
+    ]);
+    let (status, body) = app.post("/execute/batch", json!({ "jobs": jobs })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results = body["results"].as_array().unwrap();
+    let summary: Vec<Value> = results.iter().map(|r| json!([r["id"], r["ok"], r["status"]])).collect();
+    assert_eq!(
+        summary,
+        [
+            json!(["sum", true, 200]),
+            json!(["fetch", true, 200]),
+            json!(["throws", false, 400]),
+            json!(["syntax", false, 422]),
+            json!(["slow", false, 408]),
+            json!(["after", true, 200]),
+        ]
+    );
+    assert_eq!((&results[0]["result"], &results[1]["result"], &results[5]["result"]), (&json!(3), &json!(0.92), &json!("still runs")));
+    assert_eq!(results[2]["error"]["jsError"]["name"], "RangeError");
+    assert_eq!(results[3]["error"]["error"], "SyntaxError");
+    assert_eq!(results[4]["error"]["message"], "Execution exceeded the timeout of 100 ms");
+    assert!(results.iter().all(|r| r["durationMs"].is_u64()), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_batches_over_the_maximum() {
+    let app = TestApp::with_config(|config| config.max_batch_jobs = 2).await;
+    let jobs = |count: usize| json!({ "jobs": (0..count).map(|id| json!({ "id": id, "code": "1" })).collect::<Vec<_>>() });
+    let (status, body) = app.post("/execute/batch", jobs(3)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Batch too large");
+    assert_eq!(body["message"], "Batch has 3 jobs, exceeding the limit of 2");
+
+    let (status, body) = app.post("/execute/batch", jobs(2)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["results"].as_array().unwrap().len(), 2);
+}
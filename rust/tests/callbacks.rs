@@ -0,0 +1,114 @@
+// Jobs submitted with a callback_url: delivery, signature, retries and the
+// outbound policy.
+
+mod support;
+
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::time::Duration;
+
+use support::{MockResponse, MockUpstream, RecordedRequest, TestApp};
+
+const SECRET: &str = "callback-secret";
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.job_callback_secret = SECRET.to_string();
+        config.job_callback_retry_ms = 300;
+    })
+    .await
+}
+
+// The first `count` requests the upstream receives, failing after a few seconds
+async fn received(upstream: &MockUpstream, count: usize) -> Vec<RecordedRequest> {
+    for _ in 0..100 {
+        let requests = upstream.requests();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("the upstream received {} requests instead of {}", upstream.requests().len(), count);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn posts_the_finished_job_to_the_callback_url() {
+    let app = app().await;
+    app.upstream.mock("POST", "/done", MockResponse::text(204, ""));
+    let (status, body) = app
+        .post("/jobs", json!({ "code": "INPUTS.x * 2", "inputs": { "x": 21 }, "callback_url": app.upstream.url("/done") }))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+    let id = body["job_id"].as_str().unwrap();
+
+    let requests = received(&app.upstream, 1).await;
+    let callback = requests[0].json();
+    assert_eq!(callback["job_id"], id);
+    assert_eq!(callback["status"], "succeeded");
+    assert_eq!(callback["result"], 42);
+    assert_eq!(requests[0].headers["x-job-id"], id);
+    assert_eq!(requests[0].headers["content-type"], "application/json");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signs_the_body_with_the_secret() {
+    let app = app().await;
+    app.upstream.mock("POST", "/done", MockResponse::text(200, ""));
+    let (status, body) = app
+        .post("/jobs", json!({ "code": "throw new Error('nope')", "callback_url": app.upstream.url("/done") }))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+
+    let requests = received(&app.upstream, 1).await;
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(&requests[0].body);
+    let expected: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(requests[0].headers["x-signature-256"], format!("sha256={}", expected));
+    assert_eq!(requests[0].json()["status"], "failed");
+    assert!(requests[0].json()["error"]["message"].as_str().unwrap().contains("nope"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_after_a_server_error() {
+    let app = app().await;
+    app.upstream.mock("POST", "/done", MockResponse::text(503, "try again"));
+    let (status, body) = app.post("/jobs", json!({ "code": "1", "callback_url": app.upstream.url("/done") })).await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+
+    received(&app.upstream, 1).await;
+    app.upstream.mock("POST", "/done", MockResponse::text(200, ""));
+    let requests = received(&app.upstream, 2).await;
+    assert_eq!(requests[0].body, requests[1].body);
+    assert_eq!(requests[0].headers["x-signature-256"], requests[1].headers["x-signature-256"]);
+
+    // Delivered, so no further attempts
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    assert_eq!(app.upstream.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_callback_urls_the_policy_blocks() {
+    let app = TestApp::with_config(|config| {
+        config.job_callback_secret = SECRET.to_string();
+        config.allow_private_networks = false;
+    })
+    .await;
+    for url in [app.upstream.url("/done"), "file:///etc/passwd".to_string(), "not a url".to_string()] {
+        let (status, body) = app.post("/jobs", json!({ "code": "1", "callback_url": url })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid callback_url");
+    }
+    let (_, body) = app.post("/jobs", json!({ "code": "1", "callback_url": app.upstream.url("/done") })).await;
+    assert!(body["message"].as_str().unwrap().contains("private or loopback address"), "{}", body);
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_callbacks_without_a_secret() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/jobs", json!({ "code": "1", "callback_url": app.upstream.url("/done") })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["message"], "callback_url needs the server to have a JOB_CALLBACK_SECRET");
+}
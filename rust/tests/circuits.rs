@@ -0,0 +1,118 @@
+// Per-host circuit breakers: hosts that keep failing are refused with
+// `circuit_open` until a probe after the cooldown gets through.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+async fn breaking(failures: u32) -> TestApp {
+    TestApp::with_config(|config| {
+        config.circuit_breaker_failures = failures;
+        config.circuit_breaker_window_ms = 60_000;
+        config.circuit_breaker_cooldown_ms = 30_000;
+    })
+    .await
+}
+
+// `[status, errorCode, retryAfterMs]` of a GET
+async fn call(app: &TestApp, url: &str) -> Value {
+    let code = format!("const r = await httpRequest('{}'); [r.status, r.errorCode, r.retryAfterMs ?? null]", url);
+    app.result(&code, json!({})).await
+}
+
+fn advance(app: &TestApp, by: Duration) {
+    app.executor().circuits().unwrap().clock().advance(by);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn opens_the_circuit_and_probes_after_the_cooldown() {
+    let app = breaking(3).await;
+    let url = app.upstream.url("/flaky");
+    let host = url.trim_start_matches("http://").split('/').next().unwrap().to_string();
+    app.upstream.mock("GET", "/flaky", MockResponse::text(503, "down"));
+    for _ in 0..3 {
+        assert_eq!(call(&app, &url).await, json!([503, null, null]));
+    }
+    assert_eq!(app.upstream.requests().len(), 3);
+
+    let refused = call(&app, &url).await;
+    assert_eq!((&refused[0], &refused[1]), (&json!(0), &json!("circuit_open")));
+    let retry_after = refused[2].as_u64().unwrap();
+    assert!(retry_after > 29_000 && retry_after <= 30_000, "{}", refused);
+    assert_eq!(app.upstream.requests().len(), 3);
+
+    let (status, report) = app.get("/admin/circuits").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["enabled"], true);
+    assert_eq!(report["failureThreshold"], 3);
+    assert_eq!(report["circuits"][0]["host"], host.as_str());
+    assert_eq!(report["circuits"][0]["state"], "open");
+    assert_eq!(report["circuits"][0]["consecutiveFailures"], 3);
+    assert_eq!(report["circuits"][0]["opened"], 1);
+    assert_eq!(report["circuits"][0]["rejected"], 1);
+
+    // The probe after the cooldown fails, so the circuit opens again
+    advance(&app, Duration::from_secs(31));
+    assert_eq!(app.get("/admin/circuits").await.1["circuits"][0]["state"], "half_open");
+    assert_eq!(call(&app, &url).await, json!([503, null, null]));
+    assert_eq!(call(&app, &url).await[1], "circuit_open");
+    assert_eq!(app.upstream.requests().len(), 4);
+
+    // A successful probe closes it
+    advance(&app, Duration::from_secs(31));
+    app.upstream.mock("GET", "/flaky", MockResponse::json(200, json!({ "ok": true })));
+    assert_eq!(call(&app, &url).await, json!([200, null, null]));
+    assert_eq!(call(&app, &url).await, json!([200, null, null]));
+    let report = app.get("/admin/circuits").await.1;
+    assert_eq!(
+        report["circuits"][0],
+        json!({ "host": host, "state": "closed", "consecutiveFailures": 0, "retryAfterMs": null, "opened": 2, "rejected": 2 })
+    );
+
+    let (_, metrics) = app.get("/metrics").await;
+    let metrics = metrics.as_str().unwrap();
+    assert!(metrics.contains(&format!("jsexec_circuit_state{{host=\"{}\"}} 0", host)), "{}", metrics);
+    assert!(metrics.contains(&format!("jsexec_circuit_opened_total{{host=\"{}\"}} 2", host)), "{}", metrics);
+    assert!(metrics.contains(&format!("jsexec_circuit_rejected_total{{host=\"{}\"}} 2", host)), "{}", metrics);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_only_consecutive_failures_within_the_window() {
+    let app = breaking(2).await;
+    let url = app.upstream.url("/flaky");
+    app.upstream.mock("GET", "/flaky", MockResponse::text(500, "oops"));
+    app.upstream.mock("GET", "/fine", MockResponse::text(404, "missing"));
+    call(&app, &url).await;
+    // A response that isn't a 5xx resets the run, even a 404
+    call(&app, &app.upstream.url("/fine")).await;
+    call(&app, &url).await;
+    // So does a gap longer than the window
+    advance(&app, Duration::from_secs(61));
+    call(&app, &url).await;
+    assert_eq!(app.get("/admin/circuits").await.1["circuits"][0]["consecutiveFailures"], 1);
+    assert_eq!(call(&app, &url).await, json!([500, null, null]));
+    assert_eq!(call(&app, &url).await[1], "circuit_open");
+
+    // Connection failures count, and other hosts aren't affected
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead = format!("http://{}/", closed.local_addr().unwrap());
+    drop(closed);
+    assert_eq!(call(&app, &dead).await[1], "connect");
+    assert_eq!(call(&app, &dead).await[1], "connect");
+    assert_eq!(call(&app, &dead).await[1], "circuit_open");
+    let report = app.get("/admin/circuits").await.1;
+    assert_eq!(report["circuits"].as_array().unwrap().len(), 2, "{}", report);
+
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/flaky", MockResponse::text(503, "down"));
+    for _ in 0..10 {
+        assert_eq!(call(&app, &app.upstream.url("/flaky")).await[0], 503);
+    }
+    let report = app.get("/admin/circuits").await.1;
+    assert_eq!(report["enabled"], false);
+    assert_eq!(report["circuits"], json!([]));
+}
@@ -0,0 +1,47 @@
+// EXECUTION_TIME, and `freeze_time` stopping the clock at it.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_the_frozen_time_in_every_request() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/events", MockResponse::json(200, json!([])));
+    let code = format!(
+        "const poll = () => httpRequest(`{}?since=${{Date.now()}}`);
+        await poll();
+        await sleep(30);
+        await poll();
+        [EXECUTION_TIME.epochMs, new Date().toISOString() === EXECUTION_TIME.iso, performance.now()]",
+        app.upstream.url("/events")
+    );
+    let result = app.result_of(json!({ "code": code, "freeze_time": true })).await;
+    let epoch_ms = result[0].as_u64().unwrap();
+    assert_eq!(&result.as_array().unwrap()[1..], [json!(true), json!(0)]);
+
+    // Both polls are the same request, though the code slept in between
+    let uris: Vec<String> = app.upstream.requests().iter().map(|request| request.uri.clone()).collect();
+    assert_eq!(uris, [format!("/events?since={}", epoch_ms), format!("/events?since={}", epoch_ms)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_the_same_request_key_to_requests_built_from_the_time() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/events", MockResponse::json(200, json!([])));
+    let code = format!(
+        "await httpRequest(`{0}?since=${{Date.now()}}`); await sleep(30); await httpRequest(`{0}?since=${{Date.now()}}`); EXECUTION_TIME.epochMs",
+        app.upstream.url("/events")
+    );
+    let (_, body) = app.post("/execute", json!({ "code": code, "freeze_time": true, "debug": true })).await;
+    let key = format!("GET {}?since={}", app.upstream.url("/events"), body["result"]);
+    let keys: Vec<&Value> = body["debug"]["requests"].as_array().unwrap().iter().map(|request| &request["key"]).collect();
+    assert_eq!(keys, [&json!(key), &json!(format!("{} #2", key))], "{}", body);
+
+    // Without freeze_time the clock moves on
+    let (_, body) = app.post("/execute", json!({ "code": code, "debug": true })).await;
+    let requests = &body["debug"]["requests"];
+    assert_ne!(requests[0]["key"].as_str().unwrap(), requests[1]["key"].as_str().unwrap().trim_end_matches(" #2"), "{}", body);
+}
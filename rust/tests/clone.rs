@@ -0,0 +1,83 @@
+// structuredClone(), and INPUTS staying what the request sent.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clones_a_cyclic_structure() {
+    let result = TestApp::start().await.result(
+        "const team = { name: 'core', members: [] };
+        const ada = { name: 'Ada', team };
+        team.members.push(ada, ada);
+        team.self = team;
+        const copy = structuredClone(team);
+        copy.members[0].name = 'Grace';
+        [copy !== team, copy.self === copy, copy.members[0] === copy.members[1], copy.members[0].team === copy, ada.name]",
+        json!({}),
+    )
+    .await;
+    assert_eq!(result, json!([true, true, true, true, "Ada"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clones_a_map_with_object_keys() {
+    let result = TestApp::start().await.result(
+        "const key = { id: 1 };
+        const map = new Map([[key, { seen: 1 }], ['plain', new Set([key, 2])]]);
+        const copy = structuredClone(map);
+        const [copiedKey] = copy.keys();
+        copy.get(copiedKey).seen = 2;
+        [copy instanceof Map, copiedKey !== key, copiedKey.id, copy.get('plain').has(copiedKey), copy.get('plain').size, map.get(key).seen]",
+        json!({}),
+    )
+    .await;
+    assert_eq!(result, json!([true, true, 1, true, 2, 1]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clones_dates_binary_data_and_errors() {
+    let result = TestApp::start().await.result(
+        "const date = new Date('2024-05-01T12:00:00Z');
+        const bytes = new Uint8Array([1, 2, 3]);
+        const [dateCopy, bytesCopy, errorCopy, regexCopy] = structuredClone([date, bytes, new TypeError('bad'), /a+/gi]);
+        bytesCopy[0] = 9;
+        [dateCopy instanceof Date, dateCopy !== date, dateCopy.toISOString(), bytesCopy instanceof Uint8Array, bytes[0],
+         errorCopy instanceof TypeError, errorCopy.message, regexCopy.flags, structuredClone(null), structuredClone('text')]",
+        json!({}),
+    )
+    .await;
+    assert_eq!(result, json!([true, true, "2024-05-01T12:00:00.000Z", true, 1, true, "bad", "gi", null, "text"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_a_data_clone_error_for_functions() {
+    let result = TestApp::start().await.result(
+        "try { structuredClone({ handlers: [{ onDone() {} }] }); } catch (e) { [e.name, e.message] }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(result, json!(["DataCloneError", "structuredClone can't clone a function (at value.handlers[0].onDone)"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_each_call_the_inputs_it_was_sent() {
+    let app = TestApp::start().await;
+    let (status, body) = app
+        .post("/contexts", json!({ "init_code": "var first = INPUTS; INPUTS.items.push(4); INPUTS.items.length", "inputs": { "items": [1, 2, 3] } }))
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["result"], 4);
+    let id = body["id"].as_str().unwrap();
+
+    let inputs = json!({ "items": [1, 2, 3] });
+    let path = format!("/contexts/{}/execute", id);
+    let (status, body) = app.post(&path, json!({ "code": "INPUTS.items.push(5); [INPUTS.items, first.items]", "inputs": inputs })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([[1, 2, 3, 5], [1, 2, 3, 4]]));
+    let (_, body) = app.post(&path, json!({ "code": "INPUTS.items", "inputs": inputs })).await;
+    assert_eq!(body["result"], json!([1, 2, 3]));
+}
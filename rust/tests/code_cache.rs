@@ -0,0 +1,95 @@
+// CODE_CACHE_MAX_ENTRIES: compiled user code reused by later executions of the same source.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+async fn cached(app: &TestApp, request: Value) -> Value {
+    let mut request = request;
+    request["include_meta"] = json!(true);
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+fn code_cache(body: &Value) -> (bool, u64, u64) {
+    let meta = &body["meta"]["codeCache"];
+    (meta["hit"].as_bool().unwrap(), meta["hits"].as_u64().unwrap(), meta["misses"].as_u64().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reuses_the_compiled_code_with_other_inputs() {
+    let app = TestApp::start().await;
+    let code = "const double = (n) => n * 2; double(INPUTS.n)";
+    let first = cached(&app, json!({ "code": code, "inputs": { "n": 2 } })).await;
+    assert_eq!(first["result"], 4);
+    assert_eq!(code_cache(&first), (false, 0, 1));
+
+    let again = cached(&app, json!({ "code": code, "inputs": { "n": 5 } })).await;
+    assert_eq!(again["result"], 10);
+    assert_eq!(code_cache(&again), (true, 1, 1));
+
+    // Any change to the source is other code
+    let other = cached(&app, json!({ "code": format!("{} ", code), "inputs": { "n": 5 } })).await;
+    assert_eq!(code_cache(&other), (false, 1, 2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caches_modules_apart_from_scripts() {
+    let app = TestApp::start().await;
+    let code = "export default (inputs) => inputs.n + 1";
+    let request = json!({ "code": code, "module": true, "inputs": { "n": 1 } });
+    assert_eq!(code_cache(&cached(&app, request.clone()).await), (false, 0, 1));
+    let again = cached(&app, request).await;
+    assert_eq!(again["result"], 2);
+    assert_eq!(code_cache(&again), (true, 1, 1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_errors_of_cached_code_like_freshly_compiled_code() {
+    let app = TestApp::start().await;
+    let code = "function f() { throw new Error('boom'); }\nf()";
+    let (status, first) = app.exec(code, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", first);
+    let (_, again) = app.exec(code, json!({})).await;
+    assert_eq!(app.executor().code_cache().hits(), 1);
+    assert_eq!(again["jsError"], first["jsError"]);
+    assert!(again["jsError"]["stack"].as_str().unwrap().contains("user_code.js:1:"), "{}", again);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn doesnt_cache_code_that_fails_to_compile() {
+    let app = TestApp::start().await;
+    for _ in 0..2 {
+        let (status, body) = app.exec("const = 1", json!({})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+        assert_eq!(body["error"], "SyntaxError", "{}", body);
+    }
+    assert_eq!(app.executor().code_cache().hits(), 0);
+    assert_eq!(app.executor().code_cache().misses(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evicts_the_least_recently_used_code() {
+    let app = TestApp::with_config(|config| config.code_cache_max_entries = 2).await;
+    for code in ["1", "2", "1", "3"] {
+        cached(&app, json!({ "code": code })).await;
+    }
+    // "2" was evicted for "3", "1" was used since
+    assert!(code_cache(&cached(&app, json!({ "code": "1" })).await).0);
+    assert!(!code_cache(&cached(&app, json!({ "code": "2" })).await).0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compiles_every_execution_when_disabled() {
+    let app = TestApp::with_config(|config| config.code_cache_max_entries = 0).await;
+    for _ in 0..2 {
+        let body = cached(&app, json!({ "code": "40 + 2" })).await;
+        assert_eq!(body["result"], 42);
+        assert!(!code_cache(&body).0);
+    }
+    assert_eq!(app.executor().code_cache().hits(), 0);
+}
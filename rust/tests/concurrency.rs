@@ -0,0 +1,49 @@
+// MAX_CONCURRENT_EXECUTIONS and EXECUTION_QUEUE_DEPTH: executions past the slots
+// and the queue are turned away with a 429.
+
+mod support;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::StatusCode;
+use futures::future::join_all;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_executions_beyond_the_slots_and_the_queue() {
+    let app = TestApp::with_config(|config| {
+        config.max_concurrent_executions = 2;
+        config.execution_queue_depth = 1;
+    })
+    .await;
+    let request = || {
+        Request::post("/execute")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "code": "await sleep(300); 'done'" }).to_string()))
+            .unwrap()
+    };
+    let responses = join_all((0..6).map(|_| async {
+        let response = app.response(request()).await;
+        let status = response.status();
+        let retry_after = response.headers().get("retry-after").map(|value| value.to_str().unwrap().to_string());
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        (status, retry_after, body)
+    }))
+    .await;
+
+    let (admitted, refused): (Vec<_>, Vec<_>) = responses.iter().partition(|(status, ..)| *status == StatusCode::OK);
+    // Two run at once and one waits for a slot
+    assert_eq!(admitted.len(), 3, "{:?}", responses);
+    assert!(admitted.iter().all(|(_, retry_after, body)| retry_after.is_none() && body["result"] == "done"), "{:?}", admitted);
+    for (status, retry_after, body) in refused {
+        assert_eq!(*status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+        assert_eq!(retry_after.as_deref(), Some("1"));
+        assert_eq!(body["error"], "Server busy");
+    }
+
+    // Once they are done, there is room again
+    let (status, body) = app.exec("'again'", json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
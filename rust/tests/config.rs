@@ -0,0 +1,77 @@
+// Config::load: the TOML file, environment variables over it, and values refused at startup.
+//
+// Tests of one binary run at once and share the environment, so every test reads
+// keys no other test here sets as a variable.
+
+use sandbox_core::Config;
+
+fn load(test: &str, toml: &str) -> Result<Config, String> {
+    let path = std::env::temp_dir().join(format!("sandbox-config-{}-{}.toml", test, std::process::id()));
+    std::fs::write(&path, toml).unwrap();
+    let config = Config::load(Some(&path));
+    std::fs::remove_file(&path).unwrap();
+    config
+}
+
+#[test]
+fn reads_the_file_alone() {
+    let toml = "max_code_bytes = 1234\nfetch_allowlist = [\"https://a.example/\", \"https://b.example/\"]\n\n\
+        [tenants.search]\napi_keys = [\"key-s\"]\nrate_limit_per_second = 2.5\n";
+    let config = load("file", toml).unwrap();
+    assert_eq!(config.max_code_bytes, 1234);
+    assert_eq!(config.fetch_allowlist, ["https://a.example/", "https://b.example/"]);
+    assert_eq!(config.tenants["search"].api_keys, ["key-s"]);
+    assert_eq!(config.tenants["search"].rate_limit_per_second, 2.5);
+    // Anything else keeps its default
+    assert_eq!(config.max_inputs_bytes, Config::default().max_inputs_bytes);
+}
+
+#[test]
+fn lets_the_environment_override_the_file() {
+    std::env::set_var("FETCH_TIMEOUT_MS", "4321");
+    std::env::set_var("CORS_ALLOWED_ORIGINS", "https://env.example, https://other.example");
+    std::env::set_var("MAX_SESSIONS", " ");
+    let config = load("env", "fetch_timeout_ms = 1000\ncors_allowed_origins = [\"https://file.example\"]\nmax_sessions = 7\n");
+    std::env::remove_var("FETCH_TIMEOUT_MS");
+    std::env::remove_var("CORS_ALLOWED_ORIGINS");
+    std::env::remove_var("MAX_SESSIONS");
+
+    let config = config.unwrap();
+    assert_eq!(config.fetch_timeout_ms, 4321);
+    assert_eq!(config.cors_allowed_origins, ["https://env.example", "https://other.example"]);
+    // An empty variable counts as unset
+    assert_eq!(config.max_sessions, 7);
+}
+
+#[test]
+fn refuses_values_of_the_wrong_type_and_unknown_keys() {
+    let error = load("type", "max_result_bytes = \"lots\"\n").err().unwrap();
+    assert!(error.contains("max_result_bytes"), "{}", error);
+    let error = load("unknown", "max_result_byte = 10\n").err().unwrap();
+    assert!(error.contains("unknown field `max_result_byte`"), "{}", error);
+}
+
+#[test]
+fn refuses_limits_of_0() {
+    for (line, message) in [
+        ("execution_timeout_ms = 0", "Invalid EXECUTION_TIMEOUT_MS: expected a number above 0"),
+        ("max_result_bytes = 0", "Invalid MAX_RESULT_BYTES: expected a number above 0"),
+        ("max_concurrent_executions = 0", "Invalid MAX_CONCURRENT_EXECUTIONS: expected a number above 0"),
+        ("fetch_max_attempts = 0", "Invalid FETCH_MAX_ATTEMPTS: expected a number above 0"),
+    ] {
+        assert_eq!(load("zero", line).err().as_deref(), Some(message));
+    }
+    // 0 keeps meaning none where it has a meaning
+    let config = load("none", "quota_daily_executions = 0\nlazy_inputs_bytes = 0\nfetch_concurrency = 0\n").unwrap();
+    assert_eq!(config.quota_daily_executions, 0);
+}
+
+#[test]
+fn refuses_tenant_rates_that_arent_positive_or_0() {
+    let error = load("tenant", "[tenants.search]\nrate_limit_per_second = -1.0\n").err();
+    assert_eq!(error.as_deref(), Some("Invalid rate_limit_per_second of tenant search: expected 0 or a positive number, got -1"));
+    let error = load("tenant-burst", "[tenants.search]\nrate_limit_per_second = 1.0\nrate_limit_burst = inf\n").err();
+    assert_eq!(error.as_deref(), Some("Invalid rate_limit_burst of tenant search: expected 0 or a positive number, got inf"));
+    // 0 turns the tenant's limit off
+    assert!(load("tenant-off", "[tenants.search]\nrate_limit_per_second = 0.0\n").is_ok());
+}
@@ -0,0 +1,201 @@
+// Runs the cases in tests/conformance against POST /execute.
+//
+// Each file holds an array of cases, which describe the API rather than this
+// implementation, so any implementation of the service can be checked against them:
+//
+//     name               Reported when the case fails
+//     code               `{{upstream}}` is replaced with the mock upstream's URL
+//     inputs             Left out of the request when absent
+//     request            Further fields of the request body, e.g. {"module": true}
+//     mock_http          [{method, path, status, json | body, headers: [[name, value]], delay_ms}]
+//     expected_result    The `result` of a successful response
+//     expected_error     Fields the error body must have, compared recursively
+//     expected_status    Default 200, or 400 with expected_error
+//     expected_requests  Requests the upstream must have received, in order: {method,
+//                        uri, headers, json | body}, with headers compared as a subset
+//
+// All cases run, and the test fails with a list of every case that diverged.
+
+mod support;
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::time::Duration;
+
+use support::{MockResponse, MockUpstream, RecordedRequest, TestApp};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    code: String,
+    #[serde(default, deserialize_with = "present")]
+    inputs: Option<Value>,
+    #[serde(default)]
+    request: Map<String, Value>,
+    #[serde(default)]
+    mock_http: Vec<Mock>,
+    expected_result: Option<Value>,
+    expected_error: Option<Value>,
+    expected_status: Option<u16>,
+    expected_requests: Option<Vec<ExpectedRequest>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Mock {
+    method: String,
+    path: String,
+    status: u16,
+    json: Option<Value>,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpectedRequest {
+    method: String,
+    uri: String,
+    #[serde(default)]
+    headers: Map<String, Value>,
+    json: Option<Value>,
+    body: Option<String>,
+}
+
+// Some for any value that is there, including null
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn conformance() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let mut files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    files.sort();
+
+    let mut count = 0;
+    let mut failures = Vec::new();
+    for file in &files {
+        let text = std::fs::read_to_string(file).unwrap();
+        let cases: Vec<Case> = serde_json::from_str(&text)
+            .unwrap_or_else(|e| panic!("{} doesn't hold conformance cases: {}", file.display(), e));
+        for case in cases {
+            count += 1;
+            if let Err(failure) = run(&case).await {
+                let file = file.file_name().unwrap().to_string_lossy();
+                failures.push(format!("{} / {}: {}", file, case.name, failure));
+            }
+        }
+    }
+    assert!(count > 0, "no cases in {}", directory.display());
+    assert!(failures.is_empty(), "{} of {} cases diverged:\n{}", failures.len(), count, failures.join("\n"));
+}
+
+// Fresh app per case, so no case sees another's cached responses or mocks
+async fn run(case: &Case) -> Result<(), String> {
+    let app = TestApp::start().await;
+    for mock in &case.mock_http {
+        app.upstream.mock(&mock.method, &mock.path, mock_response(mock));
+    }
+
+    let mut body = case.request.clone();
+    body.insert("code".to_string(), case.code.replace("{{upstream}}", &app.upstream.url("")).into());
+    if let Some(inputs) = &case.inputs {
+        body.insert("inputs".to_string(), inputs.clone());
+    }
+    let (status, response) = app.post("/execute", Value::Object(body)).await;
+
+    let expected_status = match (case.expected_status, &case.expected_error) {
+        (Some(status), _) => status,
+        (None, Some(_)) => 400,
+        (None, None) => 200,
+    };
+    if status.as_u16() != expected_status {
+        return Err(format!("status {} instead of {}, body {}", status.as_u16(), expected_status, response));
+    }
+    if let Some(expected) = &case.expected_result {
+        if response.get("result") != Some(expected) {
+            return Err(format!("result {} instead of {}", response.get("result").unwrap_or(&Value::Null), expected));
+        }
+    }
+    if let Some(expected) = &case.expected_error {
+        if !contains(&response, expected) {
+            return Err(format!("error {} doesn't match {}", response, expected));
+        }
+    }
+    if let Some(expected) = &case.expected_requests {
+        check_requests(&app.upstream, expected)?;
+    }
+    Ok(())
+}
+
+fn mock_response(mock: &Mock) -> MockResponse {
+    let response = match &mock.json {
+        Some(value) => MockResponse::json(mock.status, value.clone()),
+        None => MockResponse::text(mock.status, &mock.body),
+    };
+    let response = mock
+        .headers
+        .iter()
+        .fold(response, |response, (name, value)| response.with_header(name, value));
+    response.with_delay(Duration::from_millis(mock.delay_ms))
+}
+
+fn check_requests(upstream: &MockUpstream, expected: &[ExpectedRequest]) -> Result<(), String> {
+    let received = upstream.requests();
+    if received.len() != expected.len() {
+        return Err(format!("upstream received {} requests instead of {}", received.len(), expected.len()));
+    }
+    for (index, (request, expected)) in received.iter().zip(expected).enumerate() {
+        let actual = describe(request);
+        let expected = json!({
+            "method": expected.method,
+            "uri": expected.uri,
+            "headers": expected.headers,
+            "json": expected.json,
+            "body": expected.body,
+        });
+        // Absent expectations are null, and match anything
+        let expected = Value::Object(expected.as_object().unwrap().clone().into_iter().filter(|(_, v)| !v.is_null()).collect());
+        if !contains(&actual, &expected) {
+            return Err(format!("upstream request {} was {} instead of {}", index + 1, actual, expected));
+        }
+    }
+    Ok(())
+}
+
+fn describe(request: &RecordedRequest) -> Value {
+    let headers: Map<String, Value> = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into()))
+        .collect();
+    json!({
+        "method": request.method.as_str(),
+        "uri": request.uri,
+        "headers": headers,
+        "json": serde_json::from_slice::<Value>(&request.body).ok(),
+        "body": String::from_utf8_lossy(&request.body),
+    })
+}
+
+// Whether every field of `expected` is in `actual` with the same value; objects are
+// compared this way recursively, everything else exactly
+fn contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| contains(actual, value))),
+        _ => actual == expected,
+    }
+}
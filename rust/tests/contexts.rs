@@ -0,0 +1,112 @@
+// Contexts that keep their globals across requests.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+async fn create(app: &TestApp, body: Value) -> String {
+    let (status, body) = app.post("/contexts", body).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    body["id"].as_str().unwrap().to_string()
+}
+
+async fn eval(app: &TestApp, id: &str, code: &str) -> (StatusCode, Value) {
+    app.post(&format!("/contexts/{}/execute", id), json!({ "code": code })).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_globals_between_calls() {
+    let app = TestApp::start().await;
+    let id = create(&app, json!({ "init_code": "var index = new Map(INPUTS.map((u) => [u.id, u])); index.size", "inputs": [{ "id": 1, "name": "Ada" }] })).await;
+
+    let (status, body) = eval(&app, &id, "var calls = 1; index.get(1).name").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], "Ada");
+    let (status, body) = eval(&app, &id, "calls += 1; [calls, index.size]").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([2, 1]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_init_result() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/contexts", json!({ "init_code": "var x = 41; x + 1" })).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["result"], 42);
+
+    let (status, body) = app.post("/contexts", json!({ "init_code": "throw new Error('setup failed')" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["message"], "setup failed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn survives_a_failed_call() {
+    let app = TestApp::start().await;
+    let id = create(&app, json!({ "init_code": "var total = 0" })).await;
+    let (status, body) = eval(&app, &id, "total += 5; null.x").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "RuntimeError");
+    assert_eq!(eval(&app, &id, "total").await.1["result"], 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serializes_concurrent_calls() {
+    let app = TestApp::start().await;
+    let id = create(&app, json!({ "init_code": "var order = []" })).await;
+    let slow = "order.push('slow start'); const end = Date.now() + 200; while (Date.now() < end) {} order.push('slow end'); 1";
+    let (first, second) = tokio::join!(eval(&app, &id, slow), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        eval(&app, &id, "order.push('fast'); 2").await
+    });
+    assert_eq!(first.0, StatusCode::OK, "{}", first.1);
+    assert_eq!(second.0, StatusCode::OK, "{}", second.1);
+    assert_eq!(eval(&app, &id, "order").await.1["result"], json!(["slow start", "slow end", "fast"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applies_the_memory_limit_to_the_whole_context() {
+    let app = TestApp::with_config(|config| config.js_max_memory_bytes = 32 * 1024 * 1024).await;
+    let id = create(&app, json!({})).await;
+    let allocate = "(globalThis.kept ??= []).push(new Array(1500000).fill(1)); kept.length";
+    assert_eq!(eval(&app, &id, allocate).await.1["result"], 1);
+    let (status, body) = eval(&app, &id, allocate).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Memory limit exceeded");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expires_an_idle_context() {
+    let app = TestApp::with_config(|config| config.context_idle_timeout_ms = 200).await;
+    let id = create(&app, json!({ "init_code": "var x = 1" })).await;
+    assert_eq!(eval(&app, &id, "x").await.1["result"], 1);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (status, body) = eval(&app, &id, "x").await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert_eq!(body["error"], "Context not found");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deletes_a_context() {
+    let app = TestApp::start().await;
+    let id = create(&app, json!({})).await;
+    let delete = || Request::delete(format!("/contexts/{}", id)).body(Body::empty()).unwrap();
+    let (status, _) = app.send(delete()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(eval(&app, &id, "1").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(app.send(delete()).await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_number_of_contexts() {
+    let app = TestApp::with_config(|config| config.max_contexts = 1).await;
+    create(&app, json!({})).await;
+    let (status, body) = app.post("/contexts", json!({})).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["error"], "Too many contexts");
+}
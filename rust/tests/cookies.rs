@@ -0,0 +1,86 @@
+// The per-execution cookie jar, and `setCookies` on httpRequest results.
+
+mod support;
+
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+fn cookie_header(request: &support::RecordedRequest) -> Option<&str> {
+    request.headers.get("cookie").map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_cookies_set_earlier_in_the_execution() {
+    let app = TestApp::start().await;
+    let login = MockResponse::json(200, json!("welcome"))
+        .with_header("set-cookie", "session=abc; Path=/")
+        .with_header("set-cookie", "theme=dark; Path=/");
+    app.upstream.mock("POST", "/login", login);
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!("Ada")));
+    let code = format!(
+        "const login = await httpRequest('{}', {{ method: 'POST' }});
+        const me = await httpRequest('{}');
+        [login.setCookies, me.setCookies, me.data]",
+        app.upstream.url("/login"),
+        app.upstream.url("/me")
+    );
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([["session=abc; Path=/", "theme=dark; Path=/"], [], "Ada"]));
+    let requests = app.upstream.requests();
+    assert_eq!(cookie_header(&requests[0]), None);
+    // In no particular order
+    let mut sent: Vec<&str> = cookie_header(&requests[1]).unwrap().split("; ").collect();
+    sent.sort();
+    assert_eq!(sent, ["session=abc", "theme=dark"]);
+
+    // The next execution starts with an empty jar
+    app.result(&format!("(await httpRequest('{}')).status", app.upstream.url("/me")), json!({})).await;
+    assert_eq!(cookie_header(&app.upstream.requests()[2]), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn leaves_the_jar_alone_with_cookies_false() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/login", MockResponse::json(200, json!({})).with_header("set-cookie", "session=abc"));
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!({})));
+    let (login, me) = (app.upstream.url("/login"), app.upstream.url("/me"));
+    let code = format!(
+        "const skipped = await httpRequest('{0}', {{ cookies: false }});
+        await httpRequest('{1}');
+        await httpRequest('{0}');
+        await httpRequest('{1}', {{ cookies: false }});
+        await httpRequest('{1}');
+        skipped.setCookies",
+        login, me
+    );
+    // The response still reports what it set
+    assert_eq!(app.result(&code, json!({})).await, json!(["session=abc"]));
+    let requests = app.upstream.requests();
+    let sent: Vec<Option<&str>> = requests.iter().map(cookie_header).collect();
+    assert_eq!(sent, [None, None, None, None, Some("session=abc")]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_cached_responses_of_sessions_apart() {
+    let app = TestApp::start().await;
+    for user in ["ada", "bob"] {
+        let login = MockResponse::json(200, json!({})).with_header("set-cookie", &format!("session={}; Path=/", user));
+        app.upstream.mock("POST", &format!("/login/{}", user), login);
+    }
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!("profile")));
+    let (login, me) = (app.upstream.url("/login/"), app.upstream.url("/me"));
+    let logged_in = |user: &str| {
+        format!(
+            "await httpRequest('{}{}', {{ method: 'POST' }});
+            (await httpRequest('{}', {{ cache: {{ ttlSeconds: 60 }} }})).fromCache",
+            login, user, me
+        )
+    };
+    assert_eq!(app.result(&logged_in("ada"), json!({})).await, json!(false));
+    assert_eq!(app.result(&logged_in("bob"), json!({})).await, json!(false));
+    assert_eq!(app.result(&logged_in("ada"), json!({})).await, json!(true));
+    let requests = app.upstream.requests();
+    let sent: Vec<Option<&str>> = requests.iter().filter(|request| request.uri == "/me").map(cookie_header).collect();
+    assert_eq!(sent, [Some("session=ada"), Some("session=bob")]);
+}
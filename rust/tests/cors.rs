@@ -0,0 +1,132 @@
+// CORS_ALLOWED_ORIGINS and friends: which browser origins may call the service.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::json;
+
+use sandbox_core::Config;
+use support::TestApp;
+
+async fn with_origins(origins: &[&str], configure: impl FnOnce(&mut Config)) -> TestApp {
+    TestApp::with_config(|config| {
+        config.cors_allowed_origins = origins.iter().map(|origin| origin.to_string()).collect();
+        configure(config);
+    })
+    .await
+}
+
+async fn preflight(app: &TestApp, origin: &str) -> Response {
+    let request = Request::options("/execute")
+        .header("origin", origin)
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type,x-api-key")
+        .body(Body::empty())
+        .unwrap();
+    app.response(request).await
+}
+
+async fn execute_from(app: &TestApp, origin: &str) -> Response {
+    let request = Request::post("/execute")
+        .header("origin", origin)
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "code": "1" }).to_string()))
+        .unwrap();
+    app.response(request).await
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_preflights_of_allowed_origins() {
+    let app = with_origins(&["https://ide.example.com", "https://admin.example.com/"], |_| {}).await;
+    let response = preflight(&app, "https://ide.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "access-control-allow-origin").as_deref(), Some("https://ide.example.com"));
+    assert_eq!(header(&response, "access-control-allow-methods").as_deref(), Some("GET,POST,DELETE"));
+    assert_eq!(header(&response, "access-control-allow-headers").as_deref(), Some("content-type,x-api-key,x-request-id"));
+    assert_eq!(header(&response, "access-control-max-age").as_deref(), Some("600"));
+    assert_eq!(header(&response, "access-control-allow-credentials"), None);
+
+    // A trailing slash in the setting is ignored
+    let response = preflight(&app, "https://admin.example.com").await;
+    assert_eq!(header(&response, "access-control-allow-origin").as_deref(), Some("https://admin.example.com"));
+
+    let response = preflight(&app, "https://evil.example.com").await;
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exposes_the_rate_limit_and_request_id_headers() {
+    let app = with_origins(&["https://ide.example.com"], |_| {}).await;
+    let response = execute_from(&app, "https://ide.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "access-control-allow-origin").as_deref(), Some("https://ide.example.com"));
+    assert_eq!(
+        header(&response, "access-control-expose-headers").as_deref(),
+        Some("retry-after,x-ratelimit-limit,x-ratelimit-remaining,x-ratelimit-reset,x-request-id")
+    );
+
+    // Other origins still get their answer, which browsers keep from the script
+    let response = execute_from(&app, "https://evil.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn adds_the_headers_to_rejections_too() {
+    let app = with_origins(&["https://ide.example.com"], |config| config.max_code_bytes = 4).await;
+    let request = Request::post("/execute")
+        .header("origin", "https://ide.example.com")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "code": "'far too long'" }).to_string()))
+        .unwrap();
+    let response = app.response(request).await;
+    assert!(response.status().is_client_error(), "{}", response.status());
+    assert_eq!(header(&response, "access-control-allow-origin").as_deref(), Some("https://ide.example.com"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn allows_any_origin_with_a_wildcard() {
+    let app = with_origins(&["*"], |config| {
+        config.cors_allowed_headers = vec!["content-type".to_string()];
+        config.cors_max_age = 60;
+    })
+    .await;
+    let response = preflight(&app, "https://anywhere.example").await;
+    assert_eq!(header(&response, "access-control-allow-origin").as_deref(), Some("*"));
+    assert_eq!(header(&response, "access-control-allow-headers").as_deref(), Some("content-type"));
+    assert_eq!(header(&response, "access-control-max-age").as_deref(), Some("60"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn allows_credentials_for_listed_origins() {
+    let app = with_origins(&["https://ide.example.com"], |config| config.cors_allow_credentials = true).await;
+    let response = preflight(&app, "https://ide.example.com").await;
+    assert_eq!(header(&response, "access-control-allow-credentials").as_deref(), Some("true"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_no_cors_headers_unless_configured() {
+    let app = TestApp::start().await;
+    let response = execute_from(&app, "https://ide.example.com").await;
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+    assert_eq!(header(&response, "access-control-expose-headers"), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[should_panic(expected = "CORS_ALLOWED_ORIGINS can't be * with CORS_ALLOW_CREDENTIALS=true")]
+async fn refuses_a_wildcard_with_credentials() {
+    with_origins(&["*"], |config| config.cors_allow_credentials = true).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[should_panic(expected = "Invalid header in CORS_ALLOWED_HEADERS: x api key")]
+async fn refuses_invalid_header_names() {
+    with_origins(&["https://ide.example.com"], |config| config.cors_allowed_headers = vec!["x api key".to_string()]).await;
+}
@@ -0,0 +1,95 @@
+// EXEC_CPU_MS: a budget for time on the CPU, which waiting doesn't use up.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use support::{MockResponse, TestApp};
+
+// Keeps the script busy for about `ms` milliseconds
+fn busy(ms: u64) -> String {
+    format!("const end = Date.now() + {}; while (Date.now() < end) {{}} 'done'", ms)
+}
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.exec_cpu_ms = 300;
+        config.execution_timeout_ms = 5000;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_a_busy_loop_over_the_budget() {
+    let app = app().await;
+    let started = Instant::now();
+    let (status, body) = app.post("/execute", json!({ "code": busy(2000), "include_meta": true })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "CPU budget exceeded");
+    assert_eq!(body["message"], "Execution exceeded the EXEC_CPU_MS limit of 300 ms of CPU time");
+    assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+    assert!(body["meta"]["cpuMs"].as_u64().unwrap() >= 300, "{}", body);
+
+    // Catching doesn't help, the interruption can't be caught
+    let code = format!("try {{ {} }} catch (e) {{ 'caught' }}", busy(2000));
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "CPU budget exceeded");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_a_loop_that_awaits_between_its_busy_parts() {
+    let app = app().await;
+    let started = Instant::now();
+    let code = "for (;;) { const end = Date.now() + 50; while (Date.now() < end) {} await sleep(1); }";
+    let (status, body) = app.exec(code, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "CPU budget exceeded");
+    assert!(started.elapsed() < Duration::from_millis(1500), "{:?}", started.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lets_a_waiting_script_run_past_the_budget() {
+    let app = app().await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!({ "ok": true })).with_delay(Duration::from_millis(400)));
+    let code = format!(
+        "let ok = 0;
+        for (let i = 0; i < 3; i++) if ((await httpRequest('{}')).data.ok) ok++;
+        await sleep(400);
+        ok",
+        app.upstream.url("/slow")
+    );
+
+    let started = Instant::now();
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], 3);
+    // Longer than the busy loop above ran before it was stopped
+    assert!(started.elapsed() >= Duration::from_millis(1600), "{:?}", started.elapsed());
+    assert!(body["meta"]["cpuMs"].as_u64().unwrap() < 300, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn takes_a_tighter_budget_from_the_request() {
+    let app = app().await;
+    let (status, body) = app.post("/execute", json!({ "code": busy(1000), "limits": { "cpu_ms": 100 } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["message"], "Execution exceeded the cpu_ms limit of 100 ms of CPU time");
+
+    let (status, body) = app.post("/execute", json!({ "code": busy(150), "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["meta"]["cpuMs"].as_u64().unwrap() >= 100, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_before_running_code_that_compiled_past_the_budget() {
+    let app = app().await;
+    // Slow to compile, never called
+    let code = format!("function unused() {{ return 1{}; }} 'ran'", " + 1".repeat(60_000));
+    let (status, body) = app.post("/execute", json!({ "code": code, "limits": { "cpu_ms": 20 } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "CPU budget exceeded");
+    assert_eq!(body["message"], "Execution exceeded the cpu_ms limit of 20 ms of CPU time");
+}
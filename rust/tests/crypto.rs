@@ -0,0 +1,101 @@
+// The `crypto` global: digests and HMAC signatures checked against known answers.
+
+mod support;
+
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn computes_known_digests() {
+    let code = "[
+        crypto.sha256('abc'), crypto.sha256(''), crypto.sha256('abc', { encoding: 'base64' }), crypto.sha256('héllo ✓'),
+        crypto.sha256('AP8B', { inputEncoding: 'base64' }),
+        crypto.sha1('abc'), crypto.sha1(''), crypto.md5('abc'), crypto.md5(''),
+    ]";
+    let result = TestApp::start().await.result(code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=",
+            "5657cdef8a85a584e0e961e6f8247cf5d3f8ed21496ed6fdbcfd43a761e94245",
+            "47ffa3ea45a70b8a41c2c0825df323c00a8b7a01c1ea06083cc41dddcc001123",
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "900150983cd24fb0d6963f7d28e17f72",
+            "d41d8cd98f00b204e9800998ecf8427e",
+        ])
+    );
+}
+
+// RFC 4231, test cases 1, 2 and 6
+#[tokio::test(flavor = "multi_thread")]
+async fn signs_the_rfc_4231_test_cases() {
+    let code = "[
+        crypto.hmacSha256(INPUTS.key1, 'Hi There', { keyEncoding: 'base64' }),
+        crypto.hmacSha256('Jefe', 'what do ya want for nothing?'),
+        crypto.hmacSha256(INPUTS.key6, 'Test Using Larger Than Block-Size Key - Hash Key First', { keyEncoding: 'base64' }),
+        // keyEncoding follows inputEncoding unless it is given
+        crypto.hmacSha256(btoa('Jefe'), btoa('what do ya want for nothing?'), { inputEncoding: 'base64' }),
+        crypto.hmacSha256('Jefe', btoa('what do ya want for nothing?'), { inputEncoding: 'base64', keyEncoding: 'utf8' }),
+    ]";
+    let inputs = json!({ "key1": "CwsLCwsLCwsLCwsLCwsLCwsLCws=", "key6": "qqqq".repeat(43) + "qqo=" });
+    let result = TestApp::start().await.result(code, inputs).await;
+    let jefe = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+    assert_eq!(
+        result,
+        json!([
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            jefe,
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            jefe,
+            jefe,
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_unknown_encodings_and_invalid_base64() {
+    let app = TestApp::start().await;
+    let code = "const thrown = (f) => { try { f(); return null; } catch (e) { return [e.name, e.message]; } };
+        [
+            thrown(() => crypto.sha256('x', { encoding: 'hex32' })),
+            thrown(() => crypto.hmacSha256('k', 'x', { keyEncoding: 'hex' })),
+            thrown(() => crypto.hmacSha256('not base64!', 'x', { keyEncoding: 'base64' })),
+            thrown(() => crypto.sha256('not base64!', { inputEncoding: 'base64' })),
+        ].map((e) => e && [e[0], e[1].split(':')[0]])";
+    let result = app.result(code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([
+            ["TypeError", "Unsupported encoding 'hex32', expected \"hex\" or \"base64\""],
+            ["TypeError", "Unsupported keyEncoding 'hex', expected \"utf8\" or \"base64\""],
+            ["TypeError", "Invalid base64 key"],
+            ["TypeError", "Invalid base64 input"],
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_a_signature_the_receiver_can_verify() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/hook", MockResponse::json(204, json!(null)));
+    let code = format!(
+        "const body = JSON.stringify(INPUTS.payload);
+        (await httpRequest('{}', {{ method: 'POST', body, headers: {{ 'X-Signature': crypto.hmacSha256(SECRETS.secret, body) }} }})).status",
+        app.upstream.url("/hook")
+    );
+    let request = json!({ "code": code, "inputs": { "payload": { "event": "paid", "amount": 12 } }, "secrets": { "secret": "whsec" } });
+    assert_eq!(app.result_of(request).await, 204);
+
+    let request = &app.upstream.requests()[0];
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"whsec").unwrap();
+    mac.update(&request.body);
+    let expected: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(request.headers["x-signature"], expected);
+    assert_eq!(request.json(), json!({ "event": "paid", "amount": 12 }));
+}
@@ -0,0 +1,107 @@
+// `debug`: the httpRequest calls under their replay keys, the keys that got no
+// response, and the durations.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pinpoints_the_request_a_replay_has_no_response_for() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/users", MockResponse::json(200, json!({ "name": "Ada" })));
+    app.upstream.mock("POST", "/audit", MockResponse::json(201, json!({})));
+    let users = app.upstream.url("/users");
+    let code = |id: u32, event: &str| {
+        format!(
+            r#"const headers = {{ Authorization: "Bearer " + SECRETS.token, "X-Trace": SECRETS.token + "-1" }};
+            const user = await httpRequest("{}", {{ query: {{ id: {} }}, headers }}).catch(() => null);
+            await httpRequest("{}", {{ method: "POST", body: '{{"event":"{}"}}' }});
+            user && user.data.name"#,
+            users,
+            id,
+            app.upstream.url("/audit"),
+            event
+        )
+    };
+
+    let request = json!({ "code": code(1, "view"), "secrets": { "token": "hunter2" }, "record_http": true });
+    let (status, recorded) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", recorded);
+    assert!(recorded.get("debug").is_none());
+
+    // Another id, and the same POST with another body
+    let request = json!({
+        "code": code(2, "edit"),
+        "secrets": { "token": "hunter2" },
+        "replay_http": recorded["httpTrace"],
+        "debug": true,
+    });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::FAILED_DEPENDENCY, "{}", body);
+    let debug = &body["debug"];
+    let user_key = format!("GET {}?id=2", users);
+    let post_key = debug["requests"][1]["key"].as_str().unwrap().to_string();
+    assert!(post_key.starts_with(&format!("POST {} body:", app.upstream.url("/audit"))), "{}", post_key);
+    assert_eq!(debug["unmatchedKeys"], json!([user_key, post_key]));
+    let mut replay_keys: Vec<&str> = recorded["httpTrace"].as_object().unwrap().keys().map(String::as_str).collect();
+    replay_keys.sort();
+    assert_eq!(debug["replayKeys"], json!(replay_keys));
+    assert_eq!(debug["replayKeys"][0], format!("GET {}?id=1", users));
+    assert_ne!(debug["replayKeys"][1], json!(post_key));
+
+    let user = &debug["requests"][0];
+    assert_eq!((&user["key"], &user["method"]), (&json!(user_key), &json!("GET")));
+    assert_eq!((&user["status"], &user["errorCode"]), (&Value::Null, &json!("unmatched_request")));
+    assert_eq!(user["headers"], json!({ "Authorization": "***MASKED***", "X-Trace": "***REDACTED***-1" }));
+    assert!(!body.to_string().contains("hunter2"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_the_requests_of_successful_executions() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/a", MockResponse::json(200, json!({ "n": 1 })));
+    app.upstream.mock("GET", "/b", MockResponse::json(404, json!({})));
+    let code = format!(
+        r#"const a = await httpRequest("{0}", {{ auth: {{ type: "bearer", token: "t" }} }});
+        const again = await httpRequest("{0}");
+        const b = await httpRequest("{1}");
+        [a.data.n, again.data.n, b.status]"#,
+        app.upstream.url("/a"),
+        app.upstream.url("/b")
+    );
+    let (status, body) = app.post("/execute", json!({ "code": code, "debug": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([1, 1, 404]));
+    let debug = &body["debug"];
+    let keys: Vec<&Value> = debug["requests"].as_array().unwrap().iter().map(|request| &request["key"]).collect();
+    let a = format!("GET {}", app.upstream.url("/a"));
+    assert_eq!(keys, [&json!(a), &json!(format!("{} #2", a)), &json!(format!("GET {}", app.upstream.url("/b")))]);
+    assert_eq!(debug["requests"][0]["headers"], json!({ "authorization": "***MASKED***" }));
+    assert_eq!((&debug["requests"][2]["status"], &debug["requests"][2]["errorCode"]), (&json!(404), &Value::Null));
+    assert_eq!((&debug["replayKeys"], &debug["unmatchedKeys"]), (&Value::Null, &json!([])));
+    let durations = &debug["durations"];
+    let total = durations["totalMs"].as_u64().unwrap();
+    let parts = ["queueMs", "evalMs", "fetchMs"].iter().map(|part| durations[part].as_u64().unwrap()).sum::<u64>();
+    assert!(parts <= total + 1, "{}", durations);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_the_request_no_mock_matched() {
+    let app = TestApp::start().await;
+    let code = r#"
+        const ok = await httpRequest("https://api.example.com/users/1");
+        const missed = await httpRequest("https://api.example.com/user/1").catch(() => null);
+        ok.data.name"#;
+    let request = json!({
+        "code": code,
+        "http_mocks": [{ "match": { "urlPattern": "https://api.example.com/users/*" }, "response": { "data": { "name": "Ada" } } }],
+        "debug": true,
+    });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::FAILED_DEPENDENCY, "{}", body);
+    assert_eq!(body["debug"]["unmatchedKeys"], json!(["GET https://api.example.com/user/1"]));
+    assert_eq!(body["debug"]["requests"][0]["status"], 200);
+}
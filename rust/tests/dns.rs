@@ -0,0 +1,45 @@
+// DNS_OVERRIDES: hosts resolved from the configuration instead of DNS.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::config::Config;
+use sandbox_core::executor::Executor;
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn routes_an_overridden_host_to_its_address() {
+    let app = TestApp::with_config(|config| config.dns_overrides = vec!["API.internal=127.0.0.1:443".to_string()]).await;
+    app.upstream.mock("GET", "/ping", MockResponse::json(200, json!("pong")));
+    let url = app.upstream.url("/ping").replace("127.0.0.1", "api.internal");
+    let result = app.result(&format!("(await httpRequest('{}')).data", url), json!({})).await;
+    assert_eq!(result, "pong");
+    assert_eq!(app.upstream.requests()[0].headers["host"], url["http://".len()..url.len() - "/ping".len()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checks_the_overriding_address_against_the_policy() {
+    let app = TestApp::with_config(|config| {
+        config.allow_private_networks = false;
+        config.dns_overrides = vec!["api.internal=127.0.0.1".to_string()];
+    })
+    .await;
+    let url = app.upstream.url("/ping").replace("127.0.0.1", "api.internal");
+    let (status, body) = app.exec(&format!("const r = await httpRequest('{}'); [r.errorCode, r.data]", url), json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"][0], "blocked_by_policy");
+    assert!(body["result"][1].as_str().unwrap().contains("api.internal resolves to private or loopback address 127.0.0.1"), "{}", body);
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[test]
+fn refuses_an_invalid_override() {
+    let config = Config {
+        dns_overrides: vec!["api.internal".to_string()],
+        ..Default::default()
+    };
+    let error = Executor::new(&config).err().unwrap();
+    assert_eq!(error, "Invalid DNS_OVERRIDES entry 'api.internal': expected host=ip");
+}
@@ -0,0 +1,95 @@
+// Dry runs: httpRequest calls are reported, never sent.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_requests_without_sending_them() {
+    let app = TestApp::start().await;
+    let code = format!(
+        "const [a, b] = await Promise.all([httpRequest('{0}'), httpRequest('{0}', {{ query: {{ page: 2 }} }})]); a.status + b.status",
+        app.upstream.url("/users")
+    );
+    let (status, body) = app.post("/execute", json!({ "code": code, "dry_run": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["dryRun"], true);
+    assert_eq!(body["result"], json!(null));
+    assert_eq!(
+        body["requests"],
+        json!([
+            { "url": app.upstream.url("/users"), "method": "GET", "headers": {}, "bodyPreview": null, "dependent": false },
+            { "url": app.upstream.url("/users?page=2"), "method": "GET", "headers": {}, "bodyPreview": null, "dependent": false },
+        ])
+    );
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_post_bodies_and_masks_sensitive_headers() {
+    let app = TestApp::start().await;
+    let code = format!(
+        "await httpRequest('{}', {{ method: 'post', headers: {{ 'Content-Type': 'application/json', Authorization: 'Bearer abc' }}, body: JSON.stringify({{ name: 'Ada' }}) }})",
+        app.upstream.url("/users")
+    );
+    let (status, body) = app.post("/execute", json!({ "code": code, "dry_run": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let request = &body["requests"][0];
+    assert_eq!(request["method"], "POST");
+    assert_eq!(request["headers"], json!({ "Content-Type": "application/json", "Authorization": "***MASKED***" }));
+    assert_eq!(request["bodyPreview"], r#"{"name":"Ada"}"#);
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn masks_the_configured_headers() {
+    let app = TestApp::with_config(|config| config.masked_headers = vec!["X-Tenant".to_string()]).await;
+    let code = format!(
+        "await httpRequest('{}', {{ headers: {{ 'x-tenant': 'acme', Authorization: 'Bearer abc' }} }})",
+        app.upstream.url("/")
+    );
+    let (_, body) = app.post("/execute", json!({ "code": code, "dry_run": true })).await;
+    assert_eq!(body["requests"][0]["headers"], json!({ "x-tenant": "***MASKED***", "Authorization": "Bearer abc" }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flags_requests_that_depend_on_earlier_responses() {
+    let app = TestApp::start().await;
+    let code = format!(
+        "const user = await httpRequest('{0}/users/me'); await httpRequest('{0}/orders/' + user.data.id); 'done'",
+        app.upstream.url("")
+    );
+    let (status, body) = app.post("/execute", json!({ "code": code, "dry_run": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let requests = body["requests"].as_array().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["dependent"], false);
+    assert_eq!(requests[1]["dependent"], true);
+    assert_eq!(requests[1]["url"], app.upstream.url("/orders/undefined"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_requests_made_before_the_code_failed() {
+    let app = TestApp::start().await;
+    let code = format!(
+        "const res = await httpRequest('{}'); res.data.items.map(item => item.id)",
+        app.upstream.url("/items")
+    );
+    let (status, body) = app.post("/execute", json!({ "code": code, "dry_run": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["requests"].as_array().unwrap().len(), 1);
+    assert_eq!(body["error"]["error"], "RuntimeError");
+    assert!(body["error"]["message"].as_str().unwrap().contains("map"), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_no_state_changes() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/execute", json!({ "code": "state.set('k', 1)", "dry_run": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (_, body) = app.exec("state.get('k') ?? 'unset'", json!({})).await;
+    assert_eq!(body["result"], "unset");
+}
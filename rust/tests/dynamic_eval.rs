@@ -0,0 +1,46 @@
+// DISABLE_DYNAMIC_EVAL and `limits.disable_dynamic_eval`: no code built from strings.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+// `[name, message]` of what each way of evaluating a string threw, null where it ran
+const ATTEMPTS: &str = "const thrown = (f) => { try { f(); return null; } catch (e) { return [e.name, e.message]; } };
+    [
+        thrown(() => eval('1 + 1')),
+        thrown(() => new Function('return 1')()),
+        thrown(() => Function('return 1')()),
+        thrown(() => new (async function () {}).constructor('return 1')),
+        thrown(() => new (function* () {}).constructor('yield 1')),
+        thrown(() => new (async function* () {}).constructor('yield 1')),
+        thrown(() => (() => {}).constructor('return 1')()),
+    ]";
+
+fn all_blocked(result: &Value) -> bool {
+    result.as_array().unwrap().iter().all(|e| e[0] == "EvalError")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_eval_and_function_constructors_on_the_server() {
+    let app = TestApp::with_config(|config| config.disable_dynamic_eval = true).await;
+    let result = app.result(ATTEMPTS, json!({})).await;
+    assert!(all_blocked(&result), "{}", result);
+    assert!(result[0][1].as_str().unwrap().contains("disabled"), "{}", result);
+
+    // A request can't turn it off, and the stubs stay in place
+    let request = json!({ "code": format!("try {{ globalThis.eval = (s) => s; }} catch {{}} {}", ATTEMPTS), "limits": { "disable_dynamic_eval": false } });
+    let result = app.result_of(request).await;
+    assert!(all_blocked(&result), "{}", result);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_them_for_a_request_that_asks() {
+    let app = TestApp::start().await;
+    let result = app.result_of(json!({ "code": ATTEMPTS, "limits": { "disable_dynamic_eval": true } })).await;
+    assert!(all_blocked(&result), "{}", result);
+
+    let result = app.result(ATTEMPTS, json!({})).await;
+    assert_eq!(result, json!([null, null, null, null, null, null, null]));
+}
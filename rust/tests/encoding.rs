@@ -0,0 +1,122 @@
+// `btoa`/`atob` with browser semantics, the binary safe `base64` helpers, and
+// `TextEncoder`/`TextDecoder`.
+
+mod support;
+
+use serde_json::json;
+
+use support::TestApp;
+
+// `[name, message]` of what a call throws
+const THROWN: &str = "const thrown = (f) => { try { f(); return null; } catch (e) { return [e.name, e.message]; } };";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_trips_unicode_through_base64() {
+    let code = "const encoded = base64.encode('héllo ✓ 🎉');
+        [encoded, base64.decode(encoded), base64.encode(new TextEncoder().encode('✓')), base64.decode('4pyT', { output: 'bytes' })]";
+    let result = TestApp::start().await.result(code, json!({})).await;
+    assert_eq!(result, json!(["aMOpbGxvIOKckyDwn46J", "héllo ✓ 🎉", "4pyT", [0xe2, 0x9c, 0x93]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pads_and_accepts_missing_padding() {
+    let code = "[
+        ['', 'f', 'fo', 'foo', 'foob'].map((s) => base64.encode(s)),
+        ['Zg', 'Zm8', 'Zg==', 'Zm9v\\nYmFy', ' Zm 9v '].map((s) => base64.decode(s)),
+        [btoa(''), btoa('\\u00ff'), atob('/w').charCodeAt(0), atob('Zm8=')],
+    ]";
+    let result = TestApp::start().await.result(code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg=="], ["f", "fo", "f", "foobar", "foo"], ["", "/w==", 255, "fo"]])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_base64() {
+    let code = format!(
+        "{} [thrown(() => base64.decode('Zm9v!')), thrown(() => base64.decode('Z')), thrown(() => base64.decode('Zg=', {{ output: 'hex' }}))]
+            .map((e) => e && [e[0], e[1].split(':')[0]])",
+        THROWN
+    );
+    let result = TestApp::start().await.result(&code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([
+            ["TypeError", "Invalid base64 input"],
+            ["TypeError", "Invalid base64 input"],
+            ["TypeError", "Unsupported output 'hex', expected \"string\" or \"bytes\""],
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_invalid_character_errors_like_browsers() {
+    let code = format!(
+        "{} [thrown(() => btoa('✓')), thrown(() => atob('***')), thrown(() => atob('Zg=a'))]",
+        THROWN
+    );
+    let result = TestApp::start().await.result(&code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([
+            ["InvalidCharacterError", "btoa: the string contains characters outside of the Latin1 range"],
+            ["InvalidCharacterError", "atob: the string to be decoded is not correctly encoded"],
+            ["InvalidCharacterError", "atob: the string to be decoded is not correctly encoded"],
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_to_decode_binary_data_as_a_string() {
+    let code = format!(
+        "{} const encoded = base64.encode([0xff, 0xfe, 0x41]); [thrown(() => base64.decode(encoded)), base64.decode(encoded, {{ output: 'bytes' }})]",
+        THROWN
+    );
+    let result = TestApp::start().await.result(&code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([
+            ["TypeError", "base64.decode: the data is not valid UTF-8, use { output: \"bytes\" } for binary data"],
+            [255, 254, 65],
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_for_invalid_utf8_only_when_fatal() {
+    let code = format!(
+        "{} const invalid = [0x61, 0xff, 0x62, 0xc3];
+        [new TextDecoder().decode(invalid), thrown(() => new TextDecoder('utf-8', {{ fatal: true }}).decode(invalid)),
+            new TextDecoder('utf-8', {{ fatal: true }}).decode([0xe2, 0x9c, 0x93]), new TextDecoder().fatal, new TextDecoder('utf8', {{ fatal: true }}).fatal]
+            .map((value) => Array.isArray(value) ? value[0] : value)",
+        THROWN
+    );
+    let result = TestApp::start().await.result(&code, json!({})).await;
+    assert_eq!(result, json!(["a\u{fffd}b\u{fffd}", "TypeError", "✓", false, true]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn encodes_lone_surrogates_as_replacement_characters() {
+    let code = "const encoder = new TextEncoder();
+        [Array.from(encoder.encode('a\\ud800b')), Array.from(encoder.encode('\\udfff')), new TextDecoder().decode(encoder.encode('x\\ud83dy')),
+            Array.from(encoder.encode('\\ud83d\\ude00'))]";
+    let result = TestApp::start().await.result(code, json!({})).await;
+    assert_eq!(
+        result,
+        json!([[0x61, 0xef, 0xbf, 0xbd, 0x62], [0xef, 0xbf, 0xbd], "x\u{fffd}y", [0xf0, 0x9f, 0x98, 0x80]])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decodes_latin1_byte_for_byte() {
+    let code = format!(
+        "{} const bytes = [0x63, 0x61, 0x66, 0xe9, 0x80, 0xff];
+        [...['latin1', 'ISO-8859-1', 'ascii'].map((label) => new TextDecoder(label).decode(bytes)), new TextDecoder('latin1').encoding,
+            thrown(() => new TextDecoder('utf-16le'))[0]]",
+        THROWN
+    );
+    let result = TestApp::start().await.result(&code, json!({})).await;
+    let latin1 = "caf\u{e9}\u{80}\u{ff}";
+    assert_eq!(result, json!([latin1, latin1, latin1, "iso-8859-1", "RangeError"]));
+}
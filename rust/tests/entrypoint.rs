@@ -0,0 +1,85 @@
+// `entrypoint` and `args`: a function of the code called for the result.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+const HANDLERS: &str = "function total(order) { return order.items.reduce((sum, item) => sum + item.price, 0); }
+    const fetchTotal = async (order) => { await sleep(5); return { total: total(order) }; };
+    const scale = (a, b, c) => [a * 2, b, c];
+    let limit = 3;
+    'top-level value'";
+
+async fn call(app: &TestApp, body: Value) -> (StatusCode, Value) {
+    let mut request = json!({ "code": HANDLERS, "inputs": { "items": [{ "price": 2 }, { "price": 5 }] } });
+    request.as_object_mut().unwrap().extend(body.as_object().unwrap().clone());
+    app.post("/execute", request).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn calls_a_function_with_the_inputs() {
+    let app = TestApp::start().await;
+    let (status, body) = call(&app, json!({ "entrypoint": "total" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(7));
+
+    let (status, body) = app
+        .post("/execute", json!({ "code": "export const double = (inputs) => inputs.n * 2;", "module": true, "entrypoint": "double", "inputs": { "n": 21 } }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(42));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn awaits_an_async_entrypoint() {
+    let app = TestApp::start().await;
+    let (status, body) = call(&app, json!({ "entrypoint": "fetchTotal" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!({ "total": 7 }));
+
+    let (status, body) = call(&app, json!({ "entrypoint": "fetchTotal", "args": [{ "items": [] }] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!({ "total": 0 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn passes_explicit_args() {
+    let app = TestApp::start().await;
+    let (status, body) = call(&app, json!({ "entrypoint": "scale", "args": [4, "x", { "deep": [null] }] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([8, "x", { "deep": [null] }]));
+
+    let (status, body) = call(&app, json!({ "entrypoint": "scale", "args": [] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([null, null, null]));
+
+    let (status, body) = app.post("/execute", json!({ "code": "1", "args": [1] })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Invalid args");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_what_was_found_instead_of_the_entrypoint() {
+    let app = TestApp::start().await;
+    let cases = [
+        ("missing", "Entrypoint \"missing\" isn't defined by the code"),
+        ("limit", "Entrypoint \"limit\" is an int, not a function"),
+        ("INPUTS", "Entrypoint \"INPUTS\" is an object, not a function"),
+        ("total()", "Entrypoint \"total()\" isn't a valid identifier"),
+    ];
+    for (entrypoint, message) in cases {
+        let (status, body) = call(&app, json!({ "entrypoint": entrypoint })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}: {}", entrypoint, body);
+        assert_eq!(body["error"], "Invalid entrypoint");
+        assert_eq!(body["message"], message);
+    }
+
+    let (status, body) = app
+        .post("/execute", json!({ "code": "export const a = 1; export function b() {}", "module": true, "entrypoint": "main" }))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["message"], "Entrypoint \"main\" isn't exported by the module, which exports a, b");
+}
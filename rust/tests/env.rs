@@ -0,0 +1,68 @@
+// ENV, the operator's constants injected into every execution.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::Config;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use support::TestApp;
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.sandbox_env = BTreeMap::from([
+            ("BASE_URL".to_string(), "https://api.internal".to_string()),
+            ("TENANT".to_string(), "acme".to_string()),
+        ]);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_env_values() {
+    let result = app().await.result("ENV.BASE_URL + '/users?tenant=' + ENV.TENANT", json!({})).await;
+    assert_eq!(result, "https://api.internal/users?tenant=acme");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_writes_to_env() {
+    let app = app().await;
+    let code = "'use strict'; const refused = []; \
+        for (const write of [() => { ENV.BASE_URL = 'x' }, () => { ENV.NEW = 'x' }, () => { delete ENV.TENANT }, () => { ENV = {} }]) { \
+            try { write() } catch (e) { refused.push(e.name) } \
+        } \
+        [refused, ENV.BASE_URL]";
+    let result = app.result(code, json!({})).await;
+    assert_eq!(result, json!([["TypeError", "TypeError", "TypeError", "TypeError"], "https://api.internal"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_inputs_from_shadowing_env() {
+    let result = app().await.result("[ENV.BASE_URL, INPUTS.ENV.BASE_URL]", json!({ "ENV": { "BASE_URL": "evil" } })).await;
+    assert_eq!(result, json!(["https://api.internal", "evil"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_env_values() {
+    let (status, body) = app().await.get("/functions").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["env"], json!({ "BASE_URL": "https://api.internal", "TENANT": "acme" }));
+}
+
+#[test]
+fn merges_the_environment_over_the_file() {
+    let path = std::env::temp_dir().join(format!("sandbox-env-{}.toml", std::process::id()));
+    std::fs::write(&path, "[sandbox_env]\nENV_TEST_BASE_URL = \"https://file\"\nENV_TEST_REGION = \"eu\"\n").unwrap();
+    std::env::set_var("SANDBOX_ENV_ENV_TEST_BASE_URL", "https://environment");
+    std::env::set_var("SANDBOX_ENV_ENV_TEST_STAGE", "prod");
+    let config = Config::load(Some(&path));
+    std::env::remove_var("SANDBOX_ENV_ENV_TEST_BASE_URL");
+    std::env::remove_var("SANDBOX_ENV_ENV_TEST_STAGE");
+    std::fs::remove_file(&path).unwrap();
+
+    let env = config.unwrap().sandbox_env;
+    assert_eq!(env["ENV_TEST_BASE_URL"], "https://environment");
+    assert_eq!(env["ENV_TEST_REGION"], "eu");
+    assert_eq!(env["ENV_TEST_STAGE"], "prod");
+}
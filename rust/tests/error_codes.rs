@@ -0,0 +1,71 @@
+// `errorCode` on httpRequest results that never got a response, and the
+// `Http.ErrorCode` constants naming them.
+
+mod support;
+
+use serde_json::json;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exposes_every_code_as_a_frozen_constant() {
+    let app = TestApp::start().await;
+    let result = app.result("[Http.ErrorCode, Object.isFrozen(Http.ErrorCode), Object.isFrozen(Http)]", json!({})).await;
+    let codes = [
+        "dns",
+        "connect",
+        "tls",
+        "timeout",
+        "too_many_redirects",
+        "body_too_large",
+        "blocked_by_policy",
+        "invalid_request",
+        "request_limit_exceeded",
+        "unmatched_request",
+        "aborted",
+        "network",
+        "method_not_allowed",
+        "bandwidth_quota_exceeded",
+        "circuit_open",
+    ];
+    let constants = serde_json::Map::from_iter(codes.iter().map(|code| (code.to_ascii_uppercase(), json!(code))));
+    assert_eq!(result, json!([constants, true, true]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tells_transport_failures_apart() {
+    let app = TestApp::with_config(|config| config.fetch_max_body_bytes = 16).await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!("late")).with_delay(Duration::from_secs(5)));
+    app.upstream.mock("GET", "/large", MockResponse::text(200, &"x".repeat(64)));
+    // A port nothing listens on any more
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let calls = [
+        (format!("http://{}/", closed), "{}", "CONNECT"),
+        ("http://no-such-host.invalid/".to_string(), "{}", "DNS"),
+        (app.upstream.url("/slow"), "{ timeoutMs: 50 }", "TIMEOUT"),
+        (app.upstream.url("/large"), "{}", "BODY_TOO_LARGE"),
+        ("http://exa mple.com/".to_string(), "{}", "INVALID_REQUEST"),
+    ];
+    for (url, options, constant) in calls {
+        let code = format!(
+            "const r = await httpRequest('{}', {}); [r.status, r.ok, r.errorCode === Http.ErrorCode.{}, r.errorCode]",
+            url, options, constant
+        );
+        let result = app.result(&code, json!({})).await;
+        assert_eq!(&result.as_array().unwrap()[..3], [json!(0), json!(false), json!(true)], "{}: {}", constant, result);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_code_null_for_error_statuses() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/missing", MockResponse::json(404, json!({})));
+    app.upstream.mock("GET", "/broken", MockResponse::text(500, "boom"));
+    let code = format!(
+        "const results = [await httpRequest('{}'), await httpRequest('{}')]; results.map((r) => [r.status, r.ok, r.errorCode])",
+        app.upstream.url("/missing"),
+        app.upstream.url("/broken")
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!([[404, false, null], [500, false, null]]));
+}
@@ -0,0 +1,82 @@
+// What thrown values look like in error responses: `jsError.properties` of Errors,
+// and `thrown` for everything else.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_properties_of_an_error_subclass() {
+    let app = TestApp::start().await;
+    let code = "class ValidationError extends Error {
+            constructor(field) {
+                super(`${field} is required`);
+                this.name = 'ValidationError';
+                this.code = 'E_REQUIRED';
+                this.details = [{ field }];
+            }
+        }
+        throw new ValidationError('email')";
+    let (status, body) = app.exec(code, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["name"], "ValidationError");
+    assert_eq!(body["jsError"]["message"], "email is required");
+    assert!(body["jsError"]["stack"].as_str().unwrap().contains("user_code.js:"), "{}", body);
+    assert_eq!(body["jsError"]["properties"], json!({ "code": "E_REQUIRED", "details": [{ "field": "email" }] }));
+    assert_eq!(body["thrown"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_a_thrown_object_verbatim() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("throw { code: 'VALIDATION', details: [{ field: 'email', min: 3 }] }", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "RuntimeError");
+    assert_eq!(body["thrown"], json!({ "code": "VALIDATION", "details": [{ "field": "email", "min": 3 }] }));
+    assert_eq!(body["jsError"]["name"], Value::Null);
+    assert_eq!(
+        body["jsError"]["message"],
+        r#"{"code":"VALIDATION","details":[{"field":"email","min":3}]}"#
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_thrown_primitives() {
+    let app = TestApp::start().await;
+    for (code, thrown) in [("throw 'boom'", json!("boom")), ("throw 42", json!(42)), ("throw null", Value::Null)] {
+        let (status, body) = app.exec(code, json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["thrown"], thrown, "{}: {}", code, body);
+    }
+    let (_, body) = app.exec("throw 'boom'", json!({})).await;
+    assert_eq!(body["jsError"]["message"], "boom");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn leaves_out_values_without_a_json_representation() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("const loop = { code: 'LOOP' }; loop.self = loop; throw loop", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "RuntimeError");
+    assert!(body.get("thrown").is_none(), "{}", body);
+    assert_eq!(body["jsError"]["message"], "[object Object]");
+
+    // Properties that can't be serialized are skipped, the others still reported
+    let code = "const e = new Error('partial'); e.code = 'E1'; e.cause_loop = e; e.big = 1n; throw e";
+    let (_, body) = app.exec(code, json!({})).await;
+    assert_eq!(body["jsError"]["properties"], json!({ "code": "E1" }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drops_a_thrown_value_over_the_result_size_limit() {
+    let app = TestApp::with_config(|config| config.max_result_bytes = 100).await;
+    let (status, body) = app.exec("throw { blob: 'x'.repeat(200) }", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body.get("thrown").is_none(), "{}", body);
+
+    let (_, body) = app.exec("throw { code: 'SMALL' }", json!({})).await;
+    assert_eq!(body["thrown"], json!({ "code": "SMALL" }));
+}
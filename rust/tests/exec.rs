@@ -0,0 +1,93 @@
+// `exec`: one script run from the command line, with its result on stdout.
+
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn exec(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_js-execution-service"))
+        .arg("exec")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> Value {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+// A file of the test's own, removed once read
+fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("sandbox-exec-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn prints_the_result_of_inline_code() {
+    let output = exec(&["--code", "({ sum: 1 + 2, list: [1, 'a'] })"], "");
+    assert_eq!(stdout(&output), json!({ "sum": 3, "list": [1, "a"] }));
+}
+
+#[test]
+fn reads_the_code_and_inputs_from_files() {
+    let code = temp_file("code.js", "INPUTS.items.map((item) => item.price * item.quantity)");
+    let inputs = temp_file("inputs.json", r#"{ "items": [{ "price": 2, "quantity": 3 }, { "price": 5, "quantity": 1 }] }"#);
+    let output = exec(&["--code-file", code.to_str().unwrap(), "--inputs", inputs.to_str().unwrap()], "");
+    std::fs::remove_file(&code).unwrap();
+    std::fs::remove_file(&inputs).unwrap();
+    assert_eq!(stdout(&output), json!([6, 5]));
+}
+
+#[test]
+fn reads_the_code_from_stdin() {
+    let inputs = temp_file("stdin.json", r#"{ "x": 21 }"#);
+    let output = exec(&["--code", "-", "--inputs", inputs.to_str().unwrap()], "INPUTS.x * 2");
+    std::fs::remove_file(&inputs).unwrap();
+    assert_eq!(stdout(&output), json!(42));
+}
+
+#[test]
+fn blocks_requests_without_the_network() {
+    let code = "const r = await httpRequest('https://example.com/', { timeoutMs: 2000 }); [r.errorCode, r.data]";
+    let output = exec(&["--code", code, "--no-network"], "");
+    assert_eq!(stdout(&output), json!(["blocked_by_policy", "Request blocked: networking is disabled"]));
+}
+
+#[test]
+fn prints_the_error_body_of_a_failed_execution() {
+    let output = exec(&["--code", "throw new TypeError('nope')"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error: Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["error"], "RuntimeError");
+    assert!(error["message"].as_str().unwrap().contains("nope"), "{}", error);
+}
+
+#[test]
+fn exits_with_2_on_invalid_arguments() {
+    for (args, message) in [
+        (&["--inputs", "inputs.json"][..], "exec needs --code-file or --code"),
+        (&["--code"][..], "--code needs a value"),
+        (&["--code", "1", "--bogus"][..], "Unexpected argument --bogus"),
+        (&["--code-file", "/nonexistent/script.js"][..], "Failed to read the code file /nonexistent/script.js"),
+    ] {
+        let output = exec(args, "");
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{:?}: {}", args, stderr);
+    }
+
+    let inputs = temp_file("broken.json", "{ not json");
+    let output = exec(&["--code", "1", "--inputs", inputs.to_str().unwrap()], "");
+    std::fs::remove_file(&inputs).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("isn't valid JSON"));
+}
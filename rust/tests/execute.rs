@@ -0,0 +1,516 @@
+// POST /execute end to end, with httpRequest calls answered by the mock upstream.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_result() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("INPUTS.x + INPUTS.y", json!({ "x": 20, "y": 22 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "result": 42 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetches_with_get() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/users/1", MockResponse::json(200, json!({ "name": "Ada" })));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ query: {{ fields: 'name' }} }}); [r.status, r.ok, r.data.name]",
+        app.upstream.url("/users/1")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([200, true, "Ada"]));
+    let requests = app.upstream.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].uri, "/users/1?fields=name");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn posts_a_json_body() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/orders", MockResponse::json(201, json!({ "id": 7 })));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ method: 'POST', headers: {{ 'Content-Type': 'application/json' }}, body: JSON.stringify(INPUTS.order) }}); [r.status, r.data.id]",
+        app.upstream.url("/orders")
+    );
+
+    let result = app.result(&code, json!({ "order": { "item": "tea", "count": 2 } })).await;
+    assert_eq!(result, json!([201, 7]));
+    let request = &app.upstream.requests()[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.headers["content-type"], "application/json");
+    assert_eq!(request.json(), json!({ "item": "tea", "count": 2 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_body_text_with_the_parsed_json() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/valid", MockResponse::json(200, json!({ "a": [1, 2] })));
+    app.upstream.mock("GET", "/trailing-comma", MockResponse::text(200, r#"{"a": [1, 2],}"#).with_header("content-type", "application/json"));
+    app.upstream.mock("GET", "/page", MockResponse::text(200, "<html><title>Status: green</title></html>").with_header("content-type", "text/html"));
+    let code = format!(
+        "const results = [];
+        for (const path of ['/valid', '/trailing-comma', '/page']) {{
+            const r = await httpRequest('{}' + path);
+            results.push([r.data, r.text, r.jsonParseError]);
+        }}
+        results.push(results[2][1].match(/Status: (\\w+)/)[1]);
+        results",
+        app.upstream.url("")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results = &body["result"];
+    assert_eq!(results[0], json!([{ "a": [1, 2] }, r#"{"a":[1,2]}"#, null]));
+    assert_eq!(results[1][0], "");
+    assert_eq!(results[1][1], r#"{"a": [1, 2],}"#);
+    assert_eq!(results[1][2], "trailing comma at line 1 column 14");
+    assert_eq!(results[2][1], "<html><title>Status: green</title></html>");
+    assert!(results[2][2].as_str().unwrap().starts_with("expected value"), "{}", body);
+    assert_eq!(results[3], "green");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn describes_the_response_after_redirects() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/old", MockResponse::text(302, "").with_header("location", "/new"));
+    app.upstream.mock("GET", "/new", MockResponse::text(200, "{}").with_header("content-type", "Application/JSON; Charset=UTF-8"));
+    let code = format!(
+        "const r = await httpRequest('{}'); [r.status, r.finalUrl, r.contentType, r.contentLength]",
+        app.upstream.url("/old")
+    );
+
+    let (status, body) = app.post("/execute", json!({ "code": code, "record_http": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([200, app.upstream.url("/new"), "application/json; charset=utf-8", 2]));
+    assert!(body["httpTrace"].get(format!("GET {}", app.upstream.url("/old"))).is_some(), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_redirects_followed() {
+    let app = TestApp::start().await;
+    for (from, to) in [("/r1", "/r2"), ("/r2", "/r3"), ("/r3", "/final")] {
+        app.upstream.mock("GET", from, MockResponse::text(if from == "/r2" { 301 } else { 302 }, "").with_header("location", to));
+    }
+    app.upstream.mock("GET", "/final", MockResponse::json(200, json!("here")));
+    let code = format!("const r = await httpRequest('{}'); [r.data, r.redirects]", app.upstream.url("/r1"));
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!(["here", [
+            { "url": app.upstream.url("/r1"), "status": 302 },
+            { "url": app.upstream.url("/r2"), "status": 301 },
+            { "url": app.upstream.url("/r3"), "status": 302 },
+        ]])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_redirects_of_a_loop_past_max_redirects() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/loop", MockResponse::text(302, "").with_header("location", "/loop"));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ maxRedirects: 3 }}); [r.errorCode, r.redirects.length, r.redirects[0].status]",
+        app.upstream.url("/loop")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["too_many_redirects", 4, 302]));
+    assert_eq!(app.upstream.requests().len(), 4);
+
+    let code = format!("const r = await httpRequest('{}', {{ maxRedirects: 0 }}); [r.status, r.redirects]", app.upstream.url("/loop"));
+    let (_, body) = app.exec(&code, json!({})).await;
+    assert_eq!(body["result"], json!([302, []]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_redirect_itself_in_manual_mode() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/old", MockResponse::text(302, "moved").with_header("location", "/new?from=old"));
+    app.upstream.mock("GET", "/new", MockResponse::json(200, json!("here")));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ redirect: 'manual' }});
+        [r.status, r.ok, r.headers.location, r.text, r.finalUrl, r.redirects]",
+        app.upstream.url("/old")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([302, false, "/new?from=old", "moved", app.upstream.url("/old"), []]));
+    assert_eq!(app.upstream.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drops_the_authorization_header_on_a_cross_origin_redirect() {
+    let app = TestApp::start().await;
+    let elsewhere = app.upstream.url("/target").replace("127.0.0.1", "localhost");
+    app.upstream.mock("GET", "/away", MockResponse::text(302, "").with_header("location", &elsewhere));
+    app.upstream.mock("GET", "/target", MockResponse::json(200, json!("done")));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ headers: {{ Authorization: 'Bearer abc' }} }}); [r.data, r.redirects]",
+        app.upstream.url("/away")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!(["done", [{
+            "url": app.upstream.url("/away"),
+            "status": 302,
+            "note": "Authorization header removed for the redirect to another origin",
+        }]])
+    );
+    let requests = app.upstream.requests();
+    assert_eq!(requests[0].headers["authorization"], "Bearer abc");
+    assert!(requests[1].headers.get("authorization").is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warns_in_the_logs_when_the_authorization_header_overrides_auth() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!("Ada")));
+    let code = format!(
+        "(await httpRequest('{}', {{ headers: {{ authorization: 'Bearer explicit' }}, auth: {{ type: 'basic', username: 'ada', password: 's3cret' }} }})).data",
+        app.upstream.url("/me")
+    );
+    let execution = app.executor().run(&code, &json!({}), Default::default()).await.unwrap();
+    assert_eq!(execution.result, json!("Ada"));
+    assert_eq!(app.upstream.requests()[0].headers["authorization"], "Bearer explicit");
+    let logs = &execution.report.logs;
+    assert_eq!(logs.len(), 1, "{:?}", logs);
+    assert_eq!(logs[0].level, "warn");
+    assert_eq!(
+        logs[0].message,
+        "Ignoring options.auth for request to 127.0.0.1: an explicit Authorization header was provided"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_the_default_user_agent() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/ua", MockResponse::json(200, json!({})));
+    let (status, body) = app.exec(&format!("(await httpRequest('{}')).status", app.upstream.url("/ua")), json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let expected = format!("js-execution-service/{}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(app.upstream.requests()[0].headers["user-agent"], expected.as_str());
+
+    let app = TestApp::with_config(|config| config.outbound_user_agent = "acme-sync/2.1".to_string()).await;
+    app.upstream.mock("GET", "/ua", MockResponse::json(200, json!({})));
+    app.exec(&format!("await httpRequest('{}')", app.upstream.url("/ua")), json!({})).await;
+    assert_eq!(app.upstream.requests()[0].headers["user-agent"], "acme-sync/2.1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_a_user_agent_set_by_the_script_across_redirects() {
+    let app = TestApp::with_config(|config| config.ua_include_request_id = true).await;
+    app.upstream.mock("GET", "/old", MockResponse::text(302, "").with_header("location", "/new"));
+    app.upstream.mock("GET", "/new", MockResponse::json(200, json!("moved")));
+    let code = format!(
+        "(await httpRequest('{}', {{ headers: {{ 'User-Agent': 'my-script/1.0' }} }})).data",
+        app.upstream.url("/old")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, "moved");
+    let requests = app.upstream.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().all(|request| request.headers["user-agent"] == "my-script/1.0"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn appends_the_request_id_to_the_user_agent() {
+    let app = TestApp::with_config(|config| {
+        config.outbound_user_agent = "acme-sync/2.1".to_string();
+        config.ua_include_request_id = true;
+    })
+    .await;
+    app.upstream.mock("GET", "/ua", MockResponse::json(200, json!({})));
+    let code = format!("(await httpRequest('{}')).status", app.upstream.url("/ua"));
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("x-request-id", "job-17")
+        .body(Body::from(json!({ "code": code }).to_string()))
+        .unwrap();
+
+    let (status, body) = app.send(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let headers = &app.upstream.requests()[0].headers;
+    assert_eq!(headers["user-agent"], "acme-sync/2.1 (request-id job-17)");
+    assert_eq!(headers["x-request-id"], "job-17");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn asks_for_uncompressed_bodies() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/plain", MockResponse::json(200, json!({ "a": 1 })));
+    app.upstream.mock("GET", "/packed", MockResponse::text(200, "\u{1f}\u{8b}packed").with_header("content-encoding", "gzip"));
+    let code = format!(
+        "const plain = await httpRequest('{0}/plain');
+        const packed = await httpRequest('{0}/packed', {{ headers: {{ 'Accept-Encoding': 'gzip' }} }});
+        [plain.data, packed.data, packed.headers['content-encoding'], packed.jsonParseError]",
+        app.upstream.url("")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([{ "a": 1 }, "", "gzip", "body is gzip-encoded, which can't be decompressed"]));
+    let requests = app.upstream.requests();
+    assert_eq!(requests[0].headers["accept-encoding"], "identity");
+    assert_eq!(requests[1].headers["accept-encoding"], "gzip");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_missing_content_length_as_null() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/stream", MockResponse::text(200, "chunks").streamed());
+    let code = format!(
+        "const r = await httpRequest('{}'); [r.text, r.contentType, r.contentLength]",
+        app.upstream.url("/stream")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["chunks", null, null]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_other_methods_as_given() {
+    let app = TestApp::start().await;
+    for method in ["PATCH", "OPTIONS", "PROPFIND"] {
+        app.upstream.mock(method, "/items/1", MockResponse::json(200, json!(method)));
+    }
+    let code = format!(
+        "const results = [];
+        for (const method of ['PATCH', 'patch', 'OPTIONS', 'propfind']) {{
+            const r = await httpRequest('{}', {{ method, body: method === 'PATCH' ? '{{}}' : undefined }});
+            results.push([r.status, r.data]);
+        }}
+        results",
+        app.upstream.url("/items/1")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([[200, "PATCH"], [200, "PATCH"], [200, "OPTIONS"], [200, "PROPFIND"]]));
+    let methods: Vec<String> = app.upstream.requests().iter().map(|request| request.method.to_string()).collect();
+    assert_eq!(methods, ["PATCH", "PATCH", "OPTIONS", "PROPFIND"]);
+    assert_eq!(app.upstream.requests()[0].body, "{}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_head_responses_without_a_body() {
+    let app = TestApp::with_config(|config| config.fetch_max_body_bytes = 8).await;
+    app.upstream.mock("HEAD", "/report", MockResponse::json(200, json!({ "rows": [1, 2, 3, 4, 5] })));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ method: 'HEAD' }}); [r.status, r.data, r.headers['content-type'], r.headers['content-length']]",
+        app.upstream.url("/report")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([200, "", "application/json", "20"]));
+    assert_eq!(app.upstream.requests()[0].method, "HEAD");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_an_invalid_method() {
+    let app = TestApp::start().await;
+    let code = format!("const r = await httpRequest('{}', {{ method: 'GE T' }}); [r.status, r.errorCode, r.data]", app.upstream.url("/"));
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([0, "invalid_request", "Invalid HTTP method 'GE T'"]));
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_an_upstream_error_status_to_the_script() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/flaky", MockResponse::text(500, "boom"));
+    let code = format!("const r = await httpRequest('{}'); [r.status, r.ok, r.errorCode]", app.upstream.url("/flaky"));
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([500, false, null]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_every_repeated_request() {
+    let app = TestApp::start().await;
+    let counts = (1..=3).map(|count| MockResponse::json(200, json!({ "count": count }))).collect();
+    app.upstream.mock_sequence("GET", "/counter", counts);
+    let code = format!(
+        "const counts = [];
+        for (let i = 0; i < INPUTS.polls; i++) counts.push((await httpRequest('{}')).data.count);
+        counts",
+        app.upstream.url("/counter")
+    );
+
+    let result = app.result(&code, json!({ "polls": 3 })).await;
+    assert_eq!(result, json!([1, 2, 3]));
+    assert_eq!(app.upstream.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_concurrent_requests_in_parallel() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(500)));
+    let code = format!(
+        "(await Promise.all(Array.from({{ length: 10 }}, () => httpRequest('{}')))).reduce((sum, r) => sum + r.data, 0)",
+        app.upstream.url("/slow")
+    );
+
+    let started = std::time::Instant::now();
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, 10);
+    assert!(started.elapsed() < Duration::from_millis(2000), "took {:?}", started.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_requests_in_flight() {
+    let app = TestApp::with_config(|config| config.fetch_concurrency = 3).await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(100)));
+    let code = format!(
+        "(await Promise.all(Array.from({{ length: 12 }}, (_, i) => httpRequest('{}?i=' + i)))).map(r => r.data)",
+        app.upstream.url("/slow")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(vec![1; 12]));
+    assert_eq!(app.upstream.requests().len(), 12);
+    assert_eq!(app.upstream.max_in_flight(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_requests_in_flight_per_host() {
+    let app = TestApp::with_config(|config| {
+        config.fetch_concurrency = 8;
+        config.fetch_concurrency_per_host = 2;
+    })
+    .await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(100)));
+    let by_address = app.upstream.url("/slow");
+    let by_name = by_address.replace("127.0.0.1", "localhost");
+    let code = format!(
+        "const urls = Array.from({{ length: 12 }}, (_, i) => (i % 2 ? '{}' : '{}') + '?i=' + i);
+        (await Promise.all(urls.map(url => httpRequest(url)))).map(r => r.data)",
+        by_address, by_name
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(vec![1; 12]));
+    assert_eq!(app.upstream.max_in_flight(), 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_out_a_slow_upstream() {
+    let app = TestApp::with_config(|config| config.fetch_timeout_ms = 200).await;
+    app.upstream.mock("GET", "/slow", MockResponse::text(200, "late").with_delay(Duration::from_secs(5)));
+    let code = format!("const r = await httpRequest('{}'); [r.status, r.errorCode]", app.upstream.url("/slow"));
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([0, "timeout"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_broken_connection_to_the_script() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/reset", MockResponse::abort());
+    let code = format!("const r = await httpRequest('{}'); [r.status, r.ok]", app.upstream.url("/reset"));
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([0, false]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_an_http_error_for_a_failed_status_with_throw_on_error() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/flaky", MockResponse::json(503, json!({ "retry": true })));
+    app.upstream.mock("GET", "/fine", MockResponse::text(200, "ok"));
+    let code = format!(
+        "const fine = await httpRequest('{0}/fine', {{ throwOnError: true }});
+        try {{ await httpRequest('{0}/flaky', {{ throwOnError: true }}); }}
+        catch (e) {{ [fine.status, e instanceof HttpError, e.name, e.reason, e.status, e.statusText, e.url, e.options.throwOnError, e.response.data.retry, e.message] }}",
+        app.upstream.url("")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    let url = app.upstream.url("/flaky");
+    assert_eq!(
+        result,
+        json!([200, true, "HttpError", "http_status", 503, "Service Unavailable", url, true, true, format!("GET {} returned 503 Service Unavailable", url)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_an_http_error_for_a_transport_failure_with_throw_on_error() {
+    let app = TestApp::with_config(|config| config.fetch_timeout_ms = 200).await;
+    app.upstream.mock("POST", "/slow", MockResponse::text(200, "late").with_delay(Duration::from_secs(5)));
+    let code = format!(
+        "try {{ await httpRequest('{}', {{ method: 'post', throwOnError: true }}); }} catch (e) {{ [e.reason, e.status, e.message.startsWith('POST ')] }}",
+        app.upstream.url("/slow")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["timeout", 0, true]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_an_uncaught_http_error() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/missing", MockResponse::text(404, "no"));
+    let code = format!("await httpRequest('{}', {{ throwOnError: true }})", app.upstream.url("/missing"));
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["name"], "HttpError");
+    assert!(body["message"].as_str().unwrap().contains("returned 404 Not Found"), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_malformed_code() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("const x = ;", json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "SyntaxError");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_the_code_for_scalar_input_sets() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/execute/map", json!({ "code": "INPUTS * 2", "input_sets": [1, [2], "x"] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results: Vec<_> = body["results"].as_array().unwrap().iter().map(|r| r["result"].clone()).collect();
+    assert_eq!(results, [json!(2), json!(4), json!(null)]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_where_a_malformed_body_fails_to_parse() {
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .body(Body::from("{\"code\": \"1\",\n \"inputs\": [1,]}"))
+        .unwrap();
+    let (status, body) = TestApp::start().await.send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Invalid JSON");
+    assert!(body["message"].as_str().unwrap().contains("line 2 column 15"), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_thrown_error() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("throw new TypeError('nope')", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "RuntimeError");
+    assert_eq!(body["jsError"]["name"], "TypeError");
+}
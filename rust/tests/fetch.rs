@@ -0,0 +1,104 @@
+// fetch(): the WHATWG idioms scripts use, over httpRequest.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_a_json_response() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/users/1",
+        MockResponse::json(200, json!({ "name": "Ada" })).with_header("x-total", "1"),
+    );
+    let code = format!(
+        "const url = '{}';
+        const user = await (await fetch(url)).json();
+        const name = await fetch(new URL(url)).then((r) => r.json()).then((u) => u.name);
+        const response = await fetch(url);
+        [user.name, name, response.ok, response.status, response.statusText, response.headers.get('X-Total'),
+         response.headers.get('content-type'), response.url, response.bodyUsed, await response.text(), response.bodyUsed]",
+        app.upstream.url("/users/1")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(
+        result,
+        json!(["Ada", "Ada", true, 200, "OK", "1", "application/json", app.upstream.url("/users/1"), false, "{\"name\":\"Ada\"}", true])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn posts_a_json_string_body() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/orders", MockResponse::json(201, json!({ "id": 7 })));
+    let code = format!(
+        "const response = await fetch('{}', {{
+            method: 'post',
+            headers: {{ 'Content-Type': 'application/json', Authorization: 'Bearer token' }},
+            body: JSON.stringify({{ item: 'book', quantity: 2 }}),
+        }});
+        [response.status, (await response.json()).id]",
+        app.upstream.url("/orders")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([201, 7]));
+    let request = &app.upstream.requests()[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.headers["content-type"], "application/json");
+    assert_eq!(request.headers["authorization"], "Bearer token");
+    assert_eq!(request.json(), json!({ "item": "book", "quantity": 2 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn takes_headers_as_pairs_or_a_headers_object() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/a", MockResponse::json(200, json!({})));
+    app.upstream.mock("POST", "/b", MockResponse::json(200, json!({})));
+    let code = format!(
+        "const headers = new Headers({{ 'X-Tenant': 'acme' }});
+        headers.append('Accept', 'application/json');
+        await fetch('{0}/a', {{ headers: [['X-Trace', 'abc'], ['X-Trace', 'def']] }});
+        await fetch('{0}/b', {{ method: 'POST', headers, body: new URLSearchParams({{ q: 'a b' }}) }});
+        [headers.get('x-tenant'), headers.has('ACCEPT'), [...headers.keys()]]",
+        app.upstream.url("")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["acme", true, ["accept", "x-tenant"]]));
+    let requests = app.upstream.requests();
+    assert_eq!(requests[0].headers["x-trace"], "abc, def");
+    assert_eq!(requests[1].headers["x-tenant"], "acme");
+    assert_eq!(requests[1].headers["accept"], "application/json");
+    assert_eq!(requests[1].headers["content-type"], "application/x-www-form-urlencoded;charset=UTF-8");
+    assert_eq!(requests[1].body, "q=a+b");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolves_error_statuses_and_rejects_failed_requests() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/missing", MockResponse::text(404, "Not here"));
+    app.upstream.mock("GET", "/broken", MockResponse::abort());
+    let code = format!(
+        "const missing = await fetch('{0}/missing');
+        let failure;
+        try {{ await fetch('{0}/broken'); }} catch (e) {{ failure = [e.name, e.cause.name, e.cause.reason]; }}
+        let misuse;
+        try {{ await fetch('{0}/missing', {{ body: 'x' }}); }} catch (e) {{ misuse = e.message; }}
+        [missing.ok, missing.status, await missing.clone().text(), await missing.json().catch((e) => e.name), failure, misuse]",
+        app.upstream.url("")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!([false, 404, "Not here", "SyntaxError", ["TypeError", "HttpError", "network"], "A GET request can't have a body"])
+    );
+}
@@ -0,0 +1,129 @@
+// `options.cache`: the cross-execution GET cache, and the revalidation of stale
+// entries with their ETag or Last-Modified.
+
+mod support;
+
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+// `[fromCache, data, status]` of a cached GET, with a TTL of 200 ms
+async fn cached_get(app: &TestApp, path: &str) -> Value {
+    let code = format!(
+        "const r = await httpRequest('{}', {{ cache: {{ ttlSeconds: 0.2 }} }}); [r.fromCache, r.data, r.status]",
+        app.upstream.url(path)
+    );
+    app.result(&code, json!({})).await
+}
+
+fn sent(app: &TestApp, header: &str) -> Vec<Option<String>> {
+    app.upstream
+        .requests()
+        .iter()
+        .map(|request| request.headers.get(header).map(|value| value.to_str().unwrap().to_string()))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_a_stale_entry_the_upstream_confirmed() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/report",
+        MockResponse::json(200, json!({ "rows": 3 })).with_header("etag", "\"v1\"").conditional(),
+    );
+    assert_eq!(cached_get(&app, "/report").await, json!([false, { "rows": 3 }, 200]));
+    assert_eq!(cached_get(&app, "/report").await, json!([true, { "rows": 3 }, 200]));
+    assert_eq!(app.upstream.requests().len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(cached_get(&app, "/report").await, json!(["revalidated", { "rows": 3 }, 200]));
+    assert_eq!(sent(&app, "if-none-match"), [None, Some("\"v1\"".to_string())]);
+    // Fresh again for another TTL
+    assert_eq!(cached_get(&app, "/report").await, json!([true, { "rows": 3 }, 200]));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replaces_an_entry_whose_content_changed() {
+    let app = TestApp::start().await;
+    let modified = "Tue, 13 Oct 2026 08:00:00 GMT";
+    app.upstream.mock(
+        "GET",
+        "/report",
+        MockResponse::json(200, json!({ "rows": 3 })).with_header("last-modified", modified).conditional(),
+    );
+    assert_eq!(cached_get(&app, "/report").await, json!([false, { "rows": 3 }, 200]));
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    app.upstream.mock(
+        "GET",
+        "/report",
+        MockResponse::json(200, json!({ "rows": 4 }))
+            .with_header("last-modified", "Wed, 14 Oct 2026 08:00:00 GMT")
+            .conditional(),
+    );
+    assert_eq!(cached_get(&app, "/report").await, json!([false, { "rows": 4 }, 200]));
+    assert_eq!(sent(&app, "if-modified-since"), [None, Some(modified.to_string())]);
+    assert_eq!(cached_get(&app, "/report").await, json!([true, { "rows": 4 }, 200]));
+
+    // Without validators a stale entry is fetched again unconditionally
+    app.upstream.mock("GET", "/plain", MockResponse::json(200, json!({ "rows": 1 })));
+    cached_get(&app, "/plain").await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(cached_get(&app, "/plain").await, json!([false, { "rows": 1 }, 200]));
+    assert_eq!(sent(&app, "if-modified-since")[2..], [None, None]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hands_back_the_304_for_validators_of_the_script() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/report",
+        MockResponse::json(200, json!({ "rows": 3 })).with_header("etag", "\"v1\"").conditional(),
+    );
+    let code = format!(
+        "const url = '{}';
+        const unchanged = await httpRequest(url, {{ ifNoneMatch: '\"v1\"' }});
+        const changed = await httpRequest(url, {{ ifNoneMatch: '\"v0\"' }});
+        [unchanged.status, unchanged.ok, unchanged.text, unchanged.headers.etag, unchanged.fromCache, changed.status, changed.data]",
+        app.upstream.url("/report")
+    );
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([304, false, "", "\"v1\"", false, 200, { "rows": 3 }]));
+    assert_eq!(sent(&app, "if-none-match"), [Some("\"v1\"".to_string()), Some("\"v0\"".to_string())]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_a_huge_ttl() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/rates", MockResponse::json(200, json!({ "eur": 0.92 })));
+    let code = format!(
+        "const get = () => httpRequest('{}', {{ cache: {{ ttlSeconds: 1e20 }} }});
+        const first = await get();
+        const second = await get();
+        [first.fromCache, second.fromCache, second.data]",
+        app.upstream.url("/rates")
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!([false, true, { "eur": 0.92 }]));
+    assert_eq!(app.upstream.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shares_an_entry_however_the_request_is_written() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/geo", MockResponse::json(200, json!({ "city": "Zürich" })));
+    let url = app.upstream.url("/geo");
+    let code = format!(
+        "const cache = {{ ttlSeconds: 60 }};
+        const first = await httpRequest('{0}', {{ cache, headers: {{ 'X-B': '2', 'X-A': '1' }}, query: {{ q: 'grüße ☃', n: 1 }} }});
+        const second = await httpRequest('{0}', {{ query: {{ n: 1, q: 'grüße ☃' }}, headers: {{ 'x-a': '1', 'X-B': '2' }}, cache }});
+        const literal = await httpRequest('{0}?n=1&q=gr%C3%BC%C3%9Fe+%E2%98%83', {{ headers: {{ 'X-A': '1', 'X-B': '2' }}, cache }});
+        [first.fromCache, second.fromCache, literal.fromCache, literal.data.city]",
+        url
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!([false, true, true, "Zürich"]));
+    assert_eq!(app.upstream.requests().len(), 1);
+}
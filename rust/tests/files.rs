@@ -0,0 +1,108 @@
+// Multipart /execute bodies and the FILES global.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use support::TestApp;
+
+const BOUNDARY: &str = "sandbox-boundary";
+
+enum Part<'a> {
+    Field(&'a str, &'a str),
+    File(&'a str, &'a str, &'a str, &'a [u8]),
+}
+
+fn multipart(parts: &[Part]) -> Request {
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+        match part {
+            Part::Field(name, value) => {
+                body.extend_from_slice(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n{}", name, value).as_bytes());
+            }
+            Part::File(name, file_name, content_type, bytes) => {
+                body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                        name, file_name, content_type
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(bytes);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    Request::post("/execute")
+        .header("content-type", format!("multipart/form-data; boundary={}", BOUNDARY))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hashes_an_uploaded_binary_file() {
+    let app = TestApp::start().await;
+    let archive: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let code = "const file = FILES.archive;
+        ({
+            name: file.name,
+            size: file.size,
+            contentType: file.contentType,
+            first: file.bytes()[1],
+            sha256: crypto.sha256(base64.encode(file.bytes()), { inputEncoding: 'base64' }),
+            label: INPUTS.label,
+        })";
+    let (status, body) = app
+        .send(multipart(&[
+            Part::Field("code", code),
+            Part::Field("inputs", r#"{"label": "backup"}"#),
+            Part::File("archive", "backup.zip", "application/zip", &archive),
+        ]))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let sha256: String = Sha256::digest(&archive).iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(
+        body["result"],
+        json!({
+            "name": "backup.zip",
+            "size": 100_000,
+            "contentType": "application/zip",
+            "first": 7,
+            "sha256": sha256,
+            "label": "backup",
+        })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_files_frozen_and_json_bodies_without_them() {
+    let app = TestApp::start().await;
+    let code = "'use strict'; try { FILES.notes.size = 0; } catch (e) {} [Object.keys(FILES), FILES.notes.size, Object.isFrozen(FILES)]";
+    let (status, body) = app.send(multipart(&[Part::Field("code", code), Part::File("notes", "notes.txt", "text/plain", b"hello")])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([["notes"], 5, true]));
+
+    let result = app.result("[Object.keys(FILES).length, INPUTS.x]", json!({ "x": 1 })).await;
+    assert_eq!(result, json!([0, 1]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_files_over_the_limit() {
+    let app = TestApp::with_config(|config| config.max_file_bytes = 1024).await;
+    let (status, body) = app
+        .send(multipart(&[Part::Field("code", "1"), Part::File("image", "photo.png", "image/png", &[0u8; 2048])]))
+        .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+    assert_eq!(body["exceeded"]["limit"], json!("MAX_FILE_BYTES"));
+    assert_eq!(body["exceeded"]["maxBytes"], json!(1024));
+
+    let (status, body) = app.send(multipart(&[Part::Field("code", "1"), Part::Field("inputs", "{not json")])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], Value::from("Invalid JSON"));
+}
@@ -0,0 +1,84 @@
+// The sandbox API as described by GET /functions.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+use support::TestApp;
+
+// The globals every QuickJS context has before the sandbox installs its own
+fn standard_globals() -> BTreeSet<String> {
+    let runtime = rquickjs::Runtime::new().unwrap();
+    let context = rquickjs::Context::full(&runtime).unwrap();
+    context.with(|ctx| ctx.eval::<Vec<String>, _>("Object.getOwnPropertyNames(globalThis)").unwrap()).into_iter().collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_every_installed_global_and_nothing_else() {
+    let app = TestApp::start().await;
+    let (status, functions) = app.get("/functions").await;
+    assert_eq!(status, StatusCode::OK, "{}", functions);
+    let listed: BTreeMap<String, Value> =
+        functions["globals"].as_array().unwrap().iter().map(|global| (global["name"].as_str().unwrap().to_string(), global.clone())).collect();
+
+    let (status, body) = app
+        .exec(
+            "Object.fromEntries(Object.getOwnPropertyNames(globalThis)
+                .filter((name) => !name.startsWith('__'))
+                .map((name) => [name, typeof globalThis[name] === 'object' && globalThis[name] !== null ? Object.keys(globalThis[name]) : null]))",
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let installed: BTreeMap<String, Value> = serde_json::from_value(body["result"].clone()).unwrap();
+
+    // The sandbox replaces some standard globals, like DOMException, so those may be listed too
+    let standard = standard_globals();
+    let unlisted: Vec<&String> = installed.keys().filter(|name| !standard.contains(*name) && !listed.contains_key(*name)).collect();
+    assert_eq!(unlisted, Vec::<&String>::new());
+    let missing: Vec<&String> = listed.keys().filter(|name| !installed.contains_key(*name)).collect();
+    assert_eq!(missing, Vec::<&String>::new());
+    for (name, global) in &listed {
+        if global["kind"] == "namespace" {
+            let members: BTreeSet<&str> = global["members"].as_array().unwrap().iter().map(|member| member["name"].as_str().unwrap()).collect();
+            let keys: BTreeSet<&str> = installed[name].as_array().unwrap().iter().map(|key| key.as_str().unwrap()).collect();
+            assert_eq!(members, keys, "{}", name);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn describes_host_functions() {
+    let (_, functions) = TestApp::start().await.get("/functions").await;
+    let globals = functions["globals"].as_array().unwrap();
+    let http_request = globals.iter().find(|global| global["name"] == "httpRequest").unwrap();
+    assert_eq!(http_request["kind"], json!("function"));
+    assert_eq!(http_request["signature"], json!("httpRequest(url, options?) => Promise<HttpResult>"));
+    assert_eq!(http_request["params"][0]["name"], json!("url"));
+    assert!(http_request["example"].as_str().unwrap().contains("await httpRequest("));
+
+    let crypto = globals.iter().find(|global| global["name"] == "crypto").unwrap();
+    assert_eq!(crypto["kind"], json!("namespace"));
+    assert!(crypto["members"].as_array().unwrap().iter().any(|member| member["signature"] == "crypto.sha256(data, options?) => string"));
+    let state = globals.iter().find(|global| global["name"] == "state").unwrap();
+    assert_eq!(state["executionsOnly"], json!(true));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_limits_in_effect() {
+    let app = TestApp::with_config(|config| {
+        config.max_requests_per_execution = 7;
+        config.execution_timeout_ms = 4000;
+        config.exec_cpu_ms = 1500;
+    })
+    .await;
+    let (_, functions) = app.get("/functions").await;
+    let limits = &functions["limits"];
+    assert_eq!(limits["maxRequests"], json!(7), "{}", limits);
+    assert_eq!(limits["timeoutMs"], json!(4000));
+    assert_eq!(limits["cpuMs"], json!(1500));
+    assert!(limits["maxCodeBytes"].as_u64().unwrap() > 0);
+    assert_eq!(limits["disableDynamicEval"], json!(false));
+}
@@ -0,0 +1,110 @@
+// graphql(): GraphQL POSTs against a mock endpoint.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+const QUERY: &str = "query User($id: ID!) { user(id: $id) { name } }";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_data_of_a_query() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/graphql", MockResponse::json(200, json!({ "data": { "user": { "name": "Ada" } } })));
+    let code = format!(
+        "const r = await graphql('{}', INPUTS.query, {{ id: 7 }}, {{ operationName: 'User', headers: {{ 'X-Tenant': 'acme' }} }});
+        [r.data.user.name, r.response.status]",
+        app.upstream.url("/graphql")
+    );
+
+    let result = app.result(&code, json!({ "query": QUERY })).await;
+    assert_eq!(result, json!(["Ada", 200]));
+    let request = &app.upstream.requests()[0];
+    assert_eq!(request.headers["content-type"], "application/json");
+    assert_eq!(request.headers["accept"], "application/json");
+    assert_eq!(request.headers["x-tenant"], "acme");
+    assert_eq!(request.json(), json!({ "query": QUERY, "variables": { "id": 7 }, "operationName": "User" }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_the_errors_of_a_partial_response() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "POST",
+        "/graphql",
+        MockResponse::json(
+            200,
+            json!({
+                "data": { "user": { "name": "Ada", "orders": null } },
+                "errors": [{ "message": "Not authorized", "path": ["user", "orders"] }, { "message": "Rate limited" }],
+            }),
+        ),
+    );
+    let code = format!(
+        "try {{ await graphql('{}', '{{ user {{ name orders {{ id }} }} }}'); }}
+        catch (e) {{ [e instanceof GraphQLError, e instanceof HttpError, e.reason, e.message, e.errors.length, e.data.user.name] }}",
+        app.upstream.url("/graphql")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    let message = format!(
+        "GraphQL request to {} failed: Not authorized (at user.orders); Rate limited",
+        app.upstream.url("/graphql")
+    );
+    assert_eq!(result, json!([true, true, "graphql", message, 2, "Ada"]));
+    assert_eq!(app.upstream.requests()[0].json()["variables"], json!({}));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_an_http_error_for_a_transport_failure() {
+    let app = TestApp::with_config(|config| config.fetch_timeout_ms = 200).await;
+    app.upstream.mock("POST", "/slow", MockResponse::json(200, json!({ "data": {} })).with_delay(Duration::from_secs(5)));
+    app.upstream.mock("POST", "/down", MockResponse::text(502, "Bad Gateway"));
+    let code = format!(
+        "const reasons = [];
+        for (const path of ['/slow', '/down']) {{
+            try {{ await graphql('{}' + path, '{{ ping }}'); }} catch (e) {{ reasons.push([e.name, e.reason, e.status]); }}
+        }}
+        reasons",
+        app.upstream.url("")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([["HttpError", "timeout", 0], ["HttpError", "http_status", 502]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_queries_with_different_variables_apart() {
+    let app = TestApp::start().await;
+    app.upstream.mock_sequence(
+        "POST",
+        "/graphql",
+        vec![
+            MockResponse::json(200, json!({ "data": { "user": { "name": "Ada" } } })),
+            MockResponse::json(200, json!({ "data": { "user": { "name": "Grace" } } })),
+        ],
+    );
+    let code = format!(
+        "const url = '{}';
+        const names = [];
+        for (const id of [2, 1]) names.push((await graphql(url, INPUTS.query, {{ id }})).data.user.name);
+        names",
+        app.upstream.url("/graphql")
+    );
+    let (status, recorded) = app.post("/execute", json!({ "code": code, "inputs": { "query": QUERY }, "record_http": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", recorded);
+    assert_eq!(recorded["result"], json!(["Ada", "Grace"]));
+    assert_eq!(recorded["httpTrace"].as_object().unwrap().len(), 2, "{}", recorded);
+
+    // Replayed in the other order, each query still gets its own response
+    let code = code.replace("[2, 1]", "[1, 2]");
+    let (status, replayed) = TestApp::start()
+        .await
+        .post("/execute", json!({ "code": code, "inputs": { "query": QUERY }, "replay_http": recorded["httpTrace"] }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", replayed);
+    assert_eq!(replayed["result"], json!(["Grace", "Ada"]));
+}
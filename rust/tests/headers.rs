@@ -0,0 +1,95 @@
+// SENSITIVE_HEADERS, only sent to the hosts of HEADER_FORWARD_ALLOWLIST.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::{Config, Executor, Options};
+use serde_json::json;
+
+use support::{MockResponse, MockUpstream, TestApp};
+
+// Both resolve to the mock upstream
+const HOSTS: [&str; 2] = ["api.trusted=127.0.0.1", "elsewhere.test=127.0.0.1"];
+
+fn configure(config: &mut Config) {
+    config.dns_overrides = HOSTS.map(String::from).to_vec();
+    config.header_forward_allowlist = vec!["api.trusted".to_string()];
+}
+
+fn on(host: &str, url: String) -> String {
+    url.replace("127.0.0.1", host)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_sensitive_headers_to_allowed_hosts_only() {
+    let app = TestApp::with_config(configure).await;
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!({})));
+    let code = format!(
+        "const headers = {{ 'x-api-key': 'k-123', Authorization: 'Bearer t', 'X-Trace': 'a' }};
+        const trusted = await httpRequest('{}', {{ headers }});
+        const other = await httpRequest('{}', {{ headers }});
+        [trusted.strippedHeaders, other.strippedHeaders]",
+        on("api.trusted", app.upstream.url("/me")),
+        on("elsewhere.test", app.upstream.url("/me")),
+    );
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([null, ["Authorization", "x-api-key"]]));
+
+    let requests = app.upstream.requests();
+    assert_eq!(requests[0].headers["x-api-key"], "k-123");
+    assert_eq!(requests[0].headers["authorization"], "Bearer t");
+    assert!(!requests[1].headers.contains_key("x-api-key"));
+    assert!(!requests[1].headers.contains_key("authorization"));
+    assert_eq!(requests[1].headers["x-trace"], "a");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warns_in_the_logs_about_stripped_headers() {
+    let upstream = MockUpstream::start().await;
+    upstream.mock("GET", "/me", MockResponse::json(200, json!({})));
+    let mut config = Config {
+        allow_private_networks: true,
+        ..Config::default()
+    };
+    configure(&mut config);
+    let executor = Executor::new(&config).unwrap();
+    let url = on("elsewhere.test", upstream.url("/me"));
+    let code = format!("(await httpRequest('{}', {{ auth: {{ type: 'bearer', token: 't' }} }})).status", url);
+    let execution = executor.run(&code, &json!({}), Options::default()).await.unwrap();
+    assert_eq!(execution.result, json!(200));
+    assert_eq!(execution.report.logs[0].level, "warn");
+    assert_eq!(
+        execution.report.logs[0].message,
+        format!("Authorization left out of GET {}: only sent to hosts in HEADER_FORWARD_ALLOWLIST", url)
+    );
+    assert!(!upstream.requests()[0].headers.contains_key("authorization"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn strips_sensitive_headers_on_redirects_to_other_hosts() {
+    let app = TestApp::with_config(configure).await;
+    let away = on("elsewhere.test", app.upstream.url("/landing"));
+    app.upstream.mock("POST", "/start", MockResponse::text(303, "").with_header("location", &away));
+    app.upstream.mock("GET", "/landing", MockResponse::json(200, json!("landed")));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ method: 'POST', body: 'x', headers: {{ 'X-API-Key': 'k-123' }} }});
+        [r.data, r.strippedHeaders, r.redirects.map((hop) => hop.note)]",
+        on("api.trusted", app.upstream.url("/start")),
+    );
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!([
+            "landed",
+            ["X-API-Key"],
+            ["X-API-Key removed for the redirect to a host outside HEADER_FORWARD_ALLOWLIST"]
+        ])
+    );
+
+    let requests = app.upstream.requests();
+    assert_eq!(requests[0].headers["x-api-key"], "k-123");
+    assert_eq!((requests[1].method.as_str(), requests[1].uri.as_str()), ("GET", "/landing"));
+    assert!(!requests[1].headers.contains_key("x-api-key"));
+    assert!(requests[1].body.is_empty());
+}
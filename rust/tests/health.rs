@@ -0,0 +1,55 @@
+// GET /health?deep=true: the canary script on a runtime from the pool.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn passes_when_the_canary_runs() {
+    let app = TestApp::with_config(|config| config.js_runtime_pool_size = 1).await;
+    app.warm_up(TIMEOUT).await;
+    let (status, body) = app.get("/health?deep=true").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["status"], json!("ok"));
+    assert_eq!(body["engine"]["ok"], json!(true));
+    assert!(body["engine"]["latencyMs"].is_u64(), "{}", body);
+    assert_eq!(body["engine"]["error"], Value::Null);
+
+    // The cheap check doesn't run it
+    let (status, body) = app.get("/health").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["engine"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_when_the_pool_is_wedged() {
+    // The only runtime is busy and the canary would wait for it longer than it may take
+    let app = TestApp::with_config(|config| {
+        config.js_runtime_pool_size = 1;
+        config.js_runtime_pool_wait_ms = 10_000;
+        config.health_check_timeout_ms = 200;
+    })
+    .await;
+    app.warm_up(TIMEOUT).await;
+
+    let probe = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        app.get("/health?deep=true").await
+    };
+    let (busy, (status, body)) = tokio::join!(app.exec("await sleep(1500); 'done'", json!({})), probe);
+    assert_eq!(busy.0, StatusCode::OK, "{}", busy.1);
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["status"], json!("unhealthy"));
+    assert_eq!(body["engine"]["ok"], json!(false));
+    assert_eq!(body["engine"]["error"], json!("Canary script didn't finish within 200 ms"));
+
+    // Healthy again once the runtime is back
+    let (status, body) = app.get("/health?deep=true").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
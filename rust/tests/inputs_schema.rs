@@ -0,0 +1,62 @@
+// `inputs_schema`: inputs checked against a JSON Schema before any code runs.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["user"],
+        "properties": {
+            "user": {
+                "type": "object",
+                "properties": { "name": { "type": "string" }, "age": { "type": "integer", "minimum": 0 } },
+            },
+        },
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_inputs_violating_the_schema() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/ran", MockResponse::json(200, json!({})));
+    let code = format!("await httpRequest('{}'); INPUTS.user.name", app.upstream.url("/ran"));
+    let request = json!({ "code": code, "inputs": { "user": { "name": 7, "age": -1 } }, "inputs_schema": schema() });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!((&body["error"], &body["message"]), (&json!("InvalidInputs"), &json!("inputs do not match inputs_schema")));
+    let mut violations: Vec<(&str, &str)> = body["violations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["instancePath"].as_str().unwrap(), v["keyword"].as_str().unwrap()))
+        .collect();
+    violations.sort();
+    assert_eq!(violations, [("/user/age", "minimum"), ("/user/name", "type")]);
+    assert!(app.upstream.requests().is_empty());
+
+    let (status, body) = app.post("/execute", json!({ "code": "1", "inputs": {}, "inputs_schema": schema() })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["violations"][0]["instancePath"], "");
+    assert_eq!(body["violations"][0]["keyword"], "required");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_code_with_matching_inputs() {
+    let app = TestApp::start().await;
+    let request = json!({ "code": "INPUTS.user.name", "inputs": { "user": { "name": "Ada", "age": 36 } }, "inputs_schema": schema() });
+    assert_eq!(app.result_of(request).await, "Ada");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_a_schema_that_does_not_compile() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/execute", json!({ "code": "1", "inputs": {}, "inputs_schema": { "type": "nope" } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "InvalidSchema");
+    assert!(body["message"].as_str().unwrap().starts_with("Invalid JSON Schema:"), "{}", body);
+}
@@ -0,0 +1,86 @@
+// ISOLATION_MODE=subprocess: executions round-trip through worker processes, and a
+// worker that dies fails only its own execution.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+async fn isolated(configure: impl FnOnce(&mut sandbox_core::Config)) -> TestApp {
+    TestApp::with_config(|config| {
+        config.isolation_mode = "subprocess".to_string();
+        config.isolation_worker_binary = env!("CARGO_BIN_EXE_js-execution-service").to_string();
+        config.isolation_workers = 1;
+        configure(config);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_executions_in_worker_processes() {
+    let app = isolated(|_| {}).await;
+    app.upstream.mock("GET", "/users/1", MockResponse::json(200, json!({ "name": "Ada" })));
+    let code = format!(
+        "const count = (state.get('count') ?? 0) + 1; state.set('count', count); \
+         const user = (await httpRequest('{}')).data; \
+         ({{ doubled: INPUTS.x * 2, name: user.name, count, secret: SECRETS.token }})",
+        app.upstream.url("/users/1")
+    );
+    let request = json!({ "code": code, "inputs": { "x": 21 }, "secrets": { "token": "s3cr3t" }, "include_meta": true });
+    let (status, body) = app.post("/execute", request.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!({ "doubled": 42, "name": "Ada", "count": 1, "secret": "***REDACTED***" }));
+    assert_eq!(body["meta"]["httpRequestCount"], 1, "{}", body);
+
+    // Each execution gets a fresh worker, and the state stays with the server
+    let (_, body) = app.post("/execute", request).await;
+    assert_eq!(body["result"]["count"], 2, "{}", body);
+
+    let execution = app.executor().execute("console.log('hi', INPUTS.x); 1", &json!({ "x": 1 })).await.unwrap();
+    assert_eq!(execution.report.logs[0].message, "hi 1");
+
+    let (status, body) = app.exec("throw new TypeError('nope')", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "RuntimeError");
+    assert_eq!(body["jsError"]["name"], "TypeError", "{}", body);
+
+    let (status, body) = app.post("/execute", json!({ "code": "while (true) {}", "timeout_ms": 200 })).await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT, "{}", body);
+
+    let (status, body) = app.post("/execute", json!({ "code": "1", "dry_run": true })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "dry_run isn't available with ISOLATION_MODE=subprocess");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn survives_a_worker_killed_mid_execution() {
+    let app = isolated(|_| {}).await;
+    let execution = app.post("/execute", json!({ "code": "while (true) {}", "timeout_ms": 20000 }));
+    let kill = async {
+        let pid = loop {
+            match app.executor().workers().unwrap().busy().first() {
+                Some(pid) => break *pid,
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        // Give the worker time to start on the code
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let killed = std::process::Command::new("kill").args(["-9", &pid.to_string()]).status().unwrap();
+        assert!(killed.success());
+    };
+    let ((status, body), ()) = tokio::join!(execution, kill);
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(body["error"], "Worker crashed");
+    assert!(body["message"].as_str().unwrap().contains("signal: 9"), "{}", body);
+
+    let (status, body) = app.exec("INPUTS.x + 1", json!({ "x": 1 })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], 2);
+    let (status, _) = app.get("/livez").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, metrics) = app.get("/metrics").await;
+    assert!(!metrics.as_str().unwrap().contains("jsexec_worker_crashes_total 0"), "{}", metrics);
+}
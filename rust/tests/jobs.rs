@@ -0,0 +1,99 @@
+// /jobs: executions submitted to run in the background and polled for their result.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+async fn submit(app: &TestApp, request: Value) -> String {
+    let (status, body) = app.post("/jobs", request).await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+    body["job_id"].as_str().unwrap().to_string()
+}
+
+// GET /jobs/{id} until the job has the status, failing after a few seconds
+async fn polled(app: &TestApp, id: &str, status: &str) -> Value {
+    for _ in 0..100 {
+        let (code, body) = app.get(&format!("/jobs/{}", id)).await;
+        assert_eq!(code, StatusCode::OK, "{}", body);
+        if body["status"] == status {
+            return body;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("job {} didn't become {}", id, status);
+}
+
+async fn cancel(app: &TestApp, id: &str) -> (StatusCode, Value) {
+    app.send(Request::delete(format!("/jobs/{}", id)).body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_a_job_to_its_result() {
+    let app = TestApp::start().await;
+    let id = submit(&app, json!({ "code": "INPUTS.x * 2", "inputs": { "x": 21 } })).await;
+    let body = polled(&app, &id, "succeeded").await;
+    assert_eq!(body["job_id"], id.as_str());
+    assert_eq!(body["result"], 42);
+    assert!(body["meta"]["queuedMs"].is_u64() && body["meta"]["durationMs"].is_u64(), "{}", body);
+
+    let id = submit(&app, json!({ "code": "throw new Error('nope')" })).await;
+    let body = polled(&app, &id, "failed").await;
+    assert_eq!(body["error"]["error"], "RuntimeError");
+    assert_eq!(body["error"]["jsError"]["message"], "nope");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_a_job_that_is_still_running() {
+    let app = TestApp::start().await;
+    let id = submit(&app, json!({ "code": "await sleep(300); 'done'" })).await;
+    let body = polled(&app, &id, "running").await;
+    assert_eq!(body["result"], Value::Null);
+    assert!(body["meta"]["durationMs"].is_u64(), "{}", body);
+
+    let body = polled(&app, &id, "succeeded").await;
+    assert_eq!(body["result"], "done");
+}
+
+// The job's task is dropped on a worker thread, so one has to be free while the
+// loop holds another
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn cancels_a_running_job() {
+    // A single slot, which the next job only gets if the loop was interrupted
+    let app = TestApp::with_config(|config| config.max_concurrent_executions = 1).await;
+    let id = submit(&app, json!({ "code": "while (true) {}", "timeout_ms": 30000 })).await;
+    polled(&app, &id, "running").await;
+
+    let (status, body) = cancel(&app, &id).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body, json!({ "job_id": id, "status": "failed" }));
+    let (_, body) = app.get(&format!("/jobs/{}", id)).await;
+    assert_eq!(body["status"], "failed");
+    assert_eq!(body["error"]["message"], "Job was cancelled");
+
+    let next = submit(&app, json!({ "code": "'next'" })).await;
+    let body = polled(&app, &next, "succeeded").await;
+    assert_eq!(body["result"], "next");
+    assert!(body["meta"]["queuedMs"].as_u64().unwrap() < 5000, "{}", body);
+    // Finished jobs can't be cancelled
+    let (status, body) = cancel(&app, &next).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forgets_results_after_their_ttl() {
+    let app = TestApp::with_config(|config| config.job_result_ttl_ms = 200).await;
+    let id = submit(&app, json!({ "code": "1" })).await;
+    polled(&app, &id, "succeeded").await;
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (status, body) = app.get(&format!("/jobs/{}", id)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert_eq!(body["message"], format!("No job with id {}, or its result expired", id));
+    assert_eq!(cancel(&app, &id).await.0, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,94 @@
+// jsonpath() and jsonpathFirst() over the values code works with.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+fn order() -> Value {
+    json!({
+        "id": 7,
+        "items": [
+            { "name": "tea", "price": 4, "tags": ["hot"] },
+            { "name": "cake", "price": 12 },
+            { "name": "pie", "price": 18, "tags": [] },
+        ],
+        "customer": { "name": "Ada", "address": { "city": "London" } },
+    })
+}
+
+async fn query(code: &str) -> Value {
+    let result = TestApp::start().await.result(code, json!({ "order": order() })).await;
+    result.clone()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn filters_array_elements() {
+    let result = query(
+        "[jsonpath(INPUTS.order, '$.items[?(@.price>10)].name'),
+          jsonpath(INPUTS.order, '$.items[?@.price >= 4 && @.price < 18].name'),
+          jsonpath(INPUTS.order, \"$.items[?(@.name == 'tea' || !@.tags)].price\"),
+          jsonpath(INPUTS.order, '$.items[?@.price > $.id].name')]",
+    )
+    .await;
+    assert_eq!(result, json!([["cake", "pie"], ["tea", "cake"], [4, 12], ["cake", "pie"]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn descends_with_wildcards() {
+    let result = query(
+        "[jsonpath(INPUTS.order, '$..name'),
+          jsonpath(INPUTS.order, '$.items[*].price'),
+          jsonpath(INPUTS.order, '$.customer.*').length,
+          jsonpath(INPUTS.order, '$..address.city'),
+          jsonpath(INPUTS.order, \"$.items[-1:]['name']\"),
+          jsonpath(INPUTS.order, '$.items[::2].name')]",
+    )
+    .await;
+    assert_eq!(result, json!([["Ada", "tea", "cake", "pie"], [4, 12, 18], 2, ["London"], ["pie"], ["tea", "pie"]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_nothing_for_absent_paths() {
+    let result = query(
+        "[jsonpath(INPUTS.order, '$.customer.phone'),
+          jsonpath(INPUTS.order, '$.items[9].name'),
+          jsonpathFirst(INPUTS.order, '$.missing.deeper') === undefined,
+          jsonpathFirst(INPUTS.order, '$.items[?@.price > 10].name'),
+          jsonpath(null, '$.anything')]",
+    )
+    .await;
+    assert_eq!(result, json!([[], [], true, "cake", []]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_values_themselves() {
+    let result = query(
+        "const cheapest = jsonpathFirst(INPUTS.order, '$.items[0]');
+        cheapest.price = 3;
+        INPUTS.order.items[0].price",
+    )
+    .await;
+    assert_eq!(result, 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_a_catchable_error_for_a_malformed_expression() {
+    let result = query(
+        "const errors = [];
+        for (const expression of ['items[0]', '$.items[?(@.price >)]', '$.items[0']) {
+            try { jsonpath(INPUTS.order, expression); } catch (e) { errors.push([e.name, e.message]); }
+        }
+        errors",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            ["SyntaxError", "Invalid JSONPath 'items[0]': expected '$' at position 0, found 'i'"],
+            ["SyntaxError", "Invalid JSONPath '$.items[?(@.price >)]': expected '@', '$' or a literal at position 19, found ')'"],
+            ["SyntaxError", "Invalid JSONPath '$.items[0': expected ',' at the end"],
+        ])
+    );
+}
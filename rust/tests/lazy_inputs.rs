@@ -0,0 +1,109 @@
+// Inputs above LAZY_INPUTS_BYTES are read as the code touches them, through the
+// INPUTS proxy or `getInput`, instead of being evaluated up front.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+async fn lazy_above(bytes: usize) -> TestApp {
+    TestApp::with_config(|config| {
+        config.lazy_inputs_bytes = bytes;
+        config.max_inputs_bytes = 64 * 1024 * 1024;
+        config.max_body_bytes = 64 * 1024 * 1024;
+    })
+    .await
+}
+
+// About 16 MiB of JSON
+fn large_inputs() -> Value {
+    let items: Vec<Value> = (0..16_000)
+        .map(|i| json!({ "id": i, "name": format!("item {}", i), "pad": format!("{:x<1000}", i) }))
+        .collect();
+    json!({ "meta": { "source": "test", "tags": ["a", "b"] }, "data": { "items": items } })
+}
+
+async fn exec_with_usage(app: &TestApp, code: &str, inputs: &Value) -> (Value, u64) {
+    let request = json!({ "code": code, "inputs": inputs, "include_meta": true });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body["message"]);
+    (body["result"].clone(), body["meta"]["usage"]["heapPeakBytes"].as_u64().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_one_field_of_a_large_input() {
+    let app = lazy_above(1024 * 1024).await;
+    let inputs = large_inputs();
+    let size = serde_json::to_string(&inputs).unwrap().len();
+    let code = r#"
+        let readOnly;
+        try { INPUTS.meta.source = "changed"; } catch (e) { readOnly = [e.name, e.message.includes("getInput")]; }
+        ({
+            name: getInput("/data/items/12345/name"),
+            dotted: getInput("data.items[12345].id"),
+            proxied: INPUTS.data.items[12345].name,
+            length: INPUTS.data.items.length,
+            isArray: Array.isArray(INPUTS.data.items),
+            same: INPUTS.data === INPUTS.data,
+            keys: inputKeys(),
+            items: inputKeys("/data/items").length,
+            size: inputSize(),
+            itemSize: inputSize(["data", "items", 0]),
+            meta: JSON.stringify(INPUTS.meta),
+            spread: { ...INPUTS.meta.tags },
+            has: ["meta" in INPUTS, "missing" in INPUTS, INPUTS.missing, getInput("/data/nope")],
+            readOnly,
+            source: INPUTS.meta.source,
+        })
+    "#;
+    let (result, heap_peak) = exec_with_usage(&app, code, &inputs).await;
+    assert_eq!(result["name"], "item 12345");
+    assert_eq!(result["dotted"], 12345);
+    assert_eq!(result["proxied"], "item 12345");
+    assert_eq!(result["length"], 16_000);
+    assert_eq!((&result["isArray"], &result["same"]), (&json!(true), &json!(true)));
+    assert_eq!(result["keys"], json!(["data", "meta"]));
+    assert_eq!(result["items"], 16_000);
+    assert_eq!(result["size"], size);
+    assert_eq!(result["itemSize"], serde_json::to_string(&inputs["data"]["items"][0]).unwrap().len());
+    assert_eq!(result["meta"], r#"{"source":"test","tags":["a","b"]}"#);
+    assert_eq!(result["spread"], json!({ "0": "a", "1": "b" }));
+    assert_eq!(result["has"], json!([true, false, null, null]));
+    assert_eq!(result["readOnly"], json!(["TypeError", true]));
+    assert_eq!(result["source"], "test");
+    assert!(heap_peak < size as u64 / 4, "{} of {}", heap_peak, size);
+
+    // Evaluated up front, INPUTS takes more heap than its JSON
+    let app = lazy_above(0).await;
+    let (result, eager_peak) = exec_with_usage(&app, "getInput('/data/items/12345/name')", &inputs).await;
+    assert_eq!(result, "item 12345");
+    assert!(eager_peak > size as u64, "{} of {}", eager_peak, size);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_small_inputs_as_they_are() {
+    let app = TestApp::start().await;
+    let inputs = json!({ "a": { "b": [10, { "c/d": "é" }] }, "n": 1 });
+    let code = r#"
+        INPUTS.n += 1;
+        [
+            INPUTS.n,
+            getInput("a.b[0]"),
+            getInput("/a/b/1/c~1d"),
+            getInput(["a", "b", 1]),
+            getInput("/a/b/length"),
+            getInput("a.missing.deeper"),
+            inputKeys("a.b"),
+            inputKeys("/n"),
+            inputSize("/a/b/1"),
+            getInput() === INPUTS,
+        ]
+    "#;
+    let result = app.result(code, inputs).await;
+    assert_eq!(
+        result,
+        json!([2, 10, "é", { "c/d": "é" }, null, null, ["0", "1"], null, r#"{"c/d":"é"}"#.len(), true])
+    );
+}
@@ -0,0 +1,96 @@
+// Size limits on request bodies, code and inputs.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.max_code_bytes = 100;
+        config.max_inputs_bytes = 1000;
+        config.max_body_bytes = 4000;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_oversized_code() {
+    let code = format!("'{}'", "x".repeat(148));
+    let (status, body) = app().await.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["error"], "Request too large");
+    assert_eq!(body["message"], "code is 150 bytes, 50 over the MAX_CODE_BYTES limit of 100");
+    assert_eq!(body["exceeded"], json!({ "limit": "MAX_CODE_BYTES", "maxBytes": 100, "actualBytes": 150 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accepts_code_right_at_the_limit() {
+    let app = app().await;
+    let code = format!("'{}'", "x".repeat(98));
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["codeBytes"], json!(100));
+
+    let code = format!("'{}'", "x".repeat(99));
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["exceeded"], json!({ "limit": "MAX_CODE_BYTES", "maxBytes": 100, "actualBytes": 101 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_deeply_nested_code_while_compiling() {
+    let app = TestApp::start().await;
+    let code = format!("{}1{}", "[".repeat(50_000), "]".repeat(50_000));
+    let started = std::time::Instant::now();
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"], "SyntaxError");
+    assert_eq!(body["jsError"]["message"], "Maximum call stack size exceeded");
+    assert!(started.elapsed() < std::time::Duration::from_secs(2), "{:?}", started.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_oversized_inputs() {
+    let (status, body) = app().await.exec("1", json!({ "s": "x".repeat(2000) })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["exceeded"]["limit"], "MAX_INPUTS_BYTES");
+    assert_eq!(body["exceeded"]["actualBytes"], 2008);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_a_body_by_its_declared_length() {
+    let payload = json!({ "code": "1", "inputs": { "s": "x".repeat(5000) } }).to_string();
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("content-length", payload.len())
+        .body(Body::from(payload.clone()))
+        .unwrap();
+    let (status, body) = app().await.send(request).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["exceeded"], json!({ "limit": "MAX_BODY_BYTES", "maxBytes": 4000, "actualBytes": payload.len() }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_a_body_without_length_at_the_limit() {
+    let (status, body) = app().await.post("/execute", json!({ "code": "1", "inputs": { "s": "x".repeat(5000) } })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["message"], "Request body exceeds the MAX_BODY_BYTES limit of 4000 bytes");
+    assert_eq!(body["exceeded"], json!({ "limit": "MAX_BODY_BYTES", "maxBytes": 4000 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_malformed_json_with_an_error_body() {
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .body(Body::from("{\"code\": "))
+        .unwrap();
+    let (status, body) = app().await.send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Invalid JSON");
+    assert!(body["requestId"].is_string(), "{}", body);
+}
@@ -0,0 +1,86 @@
+// LISTEN: TCP and Unix socket listeners of the server process.
+
+mod support;
+
+use serde_json::{json, Value};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use support::{free_port, Server};
+
+fn socket_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sandbox-{}-{}.sock", test, std::process::id()))
+}
+
+// One HTTP/1.1 request over the socket, answered with its status and JSON body
+async fn request(socket: &Path, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let mut stream = UnixStream::connect(socket).await.unwrap();
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap_or(Value::Null))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_the_api_on_a_unix_socket_and_tcp_alike() {
+    let socket = socket_path("listen");
+    let address = format!("127.0.0.1:{}", free_port());
+    let listen = format!("tcp://{},unix://{}", address, socket.display());
+    let mut server = Server::start(&[("LISTEN", &listen), ("LISTEN_SOCKET_MODE", "600")]).await;
+
+    let metadata = std::fs::metadata(&socket).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+    let execute = json!({ "code": "INPUTS.n * 2", "inputs": { "n": 21 } });
+    assert_eq!(request(&socket, "POST", "/execute", Some(execute.clone())).await, (200, json!({ "result": 42 })));
+    assert_eq!(request(&socket, "GET", "/livez", None).await.0, 200);
+    let over_tcp = reqwest::Client::new().post(format!("http://{}/execute", address)).json(&execute).send().await.unwrap();
+    assert_eq!(over_tcp.json::<Value>().await.unwrap(), json!({ "result": 42 }));
+
+    // The socket is removed on shutdown
+    server.terminate();
+    assert!(server.exited().await.success());
+    assert!(!socket.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replaces_a_stale_socket_file() {
+    let socket = socket_path("stale");
+    let _ = std::fs::remove_file(&socket);
+    // Bound and dropped, so the file stays without anyone accepting on it
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    assert!(socket.exists());
+
+    let mut server = Server::start(&[("LISTEN", &format!("unix://{}", socket.display()))]).await;
+    assert_eq!(request(&socket, "GET", "/livez", None).await.0, 200);
+    server.terminate();
+    assert!(server.exited().await.success());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_to_replace_a_file_that_isnt_a_socket() {
+    let path = socket_path("regular");
+    std::fs::write(&path, "not a socket").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_js-execution-service"))
+        .env("LISTEN", format!("unix://{}", path.display()))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("{} exists and isn't a socket", path.display())), "{}", stderr);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    std::fs::remove_file(&path).unwrap();
+}
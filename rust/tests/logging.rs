@@ -0,0 +1,45 @@
+// LOG_FORMAT=json: one JSON object per line, with the fields of the request and
+// the execution, and never the code at info level.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{free_port, Server};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn logs_executions_as_json_lines() {
+    let address = format!("127.0.0.1:{}", free_port());
+    // Even with LOG_CODE, code is only logged at debug level
+    let mut server = Server::start(&[("LISTEN", &format!("tcp://{}", address)), ("LOG_CODE", "true")]).await;
+    let code = "const marker = 'logged-code-marker'; INPUTS.n + 1";
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/execute", address))
+        .json(&json!({ "code": code, "inputs": { "n": 41 } }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let request_id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert_eq!(response.json::<Value>().await.unwrap(), json!({ "result": 42 }));
+
+    let finished = server.logged("Execution finished").await;
+    assert_eq!(finished["level"], "INFO");
+    assert_eq!(finished["request_id"], json!(request_id));
+    assert_eq!(finished["route"], "/execute");
+    assert!(finished["duration_ms"].is_u64(), "{}", finished);
+    assert_eq!(finished["status"], 200);
+    let sha256 = finished["code_sha256"].as_str().unwrap();
+    assert!(sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()), "{}", finished);
+    assert_eq!(finished["code_bytes"], code.len());
+
+    server.terminate();
+    server.logged("Shutdown complete").await;
+    assert!(server.exited().await.success());
+    let lines = server.lines();
+    // Lines that didn't parse as JSON only have a `message`
+    for line in &lines {
+        assert!(line["level"].is_string() && line["timestamp"].is_string(), "not a JSON log line: {}", line);
+        assert!(!line.to_string().contains("logged-code-marker"), "code was logged: {}", line);
+    }
+}
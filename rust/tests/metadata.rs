@@ -0,0 +1,73 @@
+// Cloud metadata endpoints, blocked even with private networks allowed.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+// errorCode and data for each URL
+async fn fetch_all(app: &TestApp, urls: &[&str]) -> Vec<Value> {
+    let code = "const results = [];
+        for (const url of INPUTS.urls) { const r = await httpRequest(url, { timeoutMs: 2000 }); results.push([r.errorCode, r.data]); }
+        results";
+    let result = app.result(code, json!({ "urls": urls })).await;
+    result.as_array().unwrap().clone()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_metadata_addresses_in_every_notation() {
+    let app = TestApp::start().await;
+    let urls = [
+        "http://169.254.169.254/latest/meta-data/",
+        "http://2852039166/",
+        "http://0251.0376.0251.0376/",
+        "http://0xa9fea9fe/",
+        "http://0xa9.254.0251.254/",
+        "http://169.254.43518/",
+        "http://[::ffff:169.254.169.254]/",
+        "http://[::ffff:a9fe:a9fe]/",
+        "http://[::a9fe:a9fe]/",
+        "http://[64:ff9b::a9fe:a9fe]/",
+    ];
+    for (url, result) in urls.iter().zip(fetch_all(&app, &urls).await) {
+        assert_eq!(result, json!(["blocked_by_policy", "Request blocked: 169.254.169.254 is a cloud metadata address"]), "{}", url);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_the_other_metadata_addresses() {
+    let app = TestApp::start().await;
+    let results = fetch_all(&app, &["http://169.254.170.2/v2/credentials", "http://100.100.100.200/", "http://[fd00:ec2::254]/"]).await;
+    assert_eq!(results[0][1], "Request blocked: 169.254.170.2 is a cloud metadata address");
+    assert_eq!(results[1][1], "Request blocked: 100.100.100.200 is a cloud metadata address");
+    assert_eq!(results[2][1], "Request blocked: fd00:ec2::254 is a cloud metadata address");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_metadata_hostnames_and_hosts_resolving_to_metadata_addresses() {
+    let app = TestApp::with_config(|config| config.dns_overrides = vec!["alias.test=169.254.169.254".to_string()]).await;
+    let results = fetch_all(
+        &app,
+        &["http://metadata.google.internal/computeMetadata/v1/", "http://METADATA.google.internal./", "http://metadata/", "http://alias.test/"],
+    )
+    .await;
+    assert_eq!(results[0], json!(["blocked_by_policy", "Request blocked: metadata.google.internal is a cloud metadata host"]));
+    assert_eq!(results[1], results[0]);
+    assert_eq!(results[2][1], "Request blocked: metadata is a cloud metadata host");
+    assert_eq!(results[3][0], "blocked_by_policy");
+    assert!(results[3][1].as_str().unwrap().contains("169.254.169.254"), "{:?}", results[3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn allows_metadata_endpoints_when_turned_off() {
+    let app = TestApp::with_config(|config| {
+        config.block_cloud_metadata = false;
+        config.dns_overrides = vec!["metadata.google.internal=127.0.0.1".to_string()];
+    })
+    .await;
+    app.upstream.mock("GET", "/token", MockResponse::json(200, json!("secret")));
+    let url = app.upstream.url("/token").replace("127.0.0.1", "metadata.google.internal");
+    let results = fetch_all(&app, &[&url]).await;
+    assert_eq!(results[0], json!([null, "secret"]));
+}
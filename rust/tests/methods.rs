@@ -0,0 +1,64 @@
+// OUTBOUND_ALLOWED_METHODS, for deployments whose scripts may only read.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use sandbox_core::{Config, Executor};
+use support::{MockResponse, TestApp};
+
+async fn read_only() -> TestApp {
+    TestApp::with_config(|config| config.outbound_allowed_methods = vec!["get".to_string(), "HEAD".to_string()]).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_methods_that_arent_allowed() {
+    let app = read_only().await;
+    app.upstream.mock("GET", "/items", MockResponse::json(200, json!([1, 2])));
+    app.upstream.mock("POST", "/items", MockResponse::json(201, json!({})));
+    let code = "const url = INPUTS.url;
+        const post = await httpRequest(url, { method: 'POST', body: { name: 'new' } });
+        const put = await fetch(url, { method: 'PUT', body: '{}' }).catch((e) => e.cause.response.errorCode);
+        const graph = await graphql(url, '{ items }').catch((e) => e.reason);
+        const get = await httpRequest(url);
+        ({ post: [post.errorCode, post.data], put, graph, get: get.data })";
+    let (status, body) = app
+        .post("/execute", json!({ "code": code, "inputs": { "url": app.upstream.url("/items") }, "include_meta": true }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!({
+            "post": ["method_not_allowed", "Request blocked: POST isn't allowed, OUTBOUND_ALLOWED_METHODS is GET, HEAD"],
+            "put": "method_not_allowed",
+            "graph": "method_not_allowed",
+            "get": [1, 2],
+        })
+    );
+    assert_eq!(body["meta"]["blockedRequestCount"], json!(3));
+    let requests = app.upstream.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn allows_every_method_by_default() {
+    let app = TestApp::start().await;
+    app.upstream.mock("DELETE", "/items/1", MockResponse::text(204, ""));
+    let code = format!("(await httpRequest('{}', {{ method: 'DELETE' }})).status", app.upstream.url("/items/1"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(204));
+    assert_eq!(body["meta"]["blockedRequestCount"], json!(0));
+}
+
+#[test]
+fn rejects_invalid_methods_at_startup() {
+    let config = Config {
+        outbound_allowed_methods: vec!["GET".to_string(), "NOT A METHOD".to_string()],
+        ..Config::default()
+    };
+    let error = Executor::new(&config).err().unwrap();
+    assert!(error.contains("Invalid OUTBOUND_ALLOWED_METHODS entry 'NOT A METHOD'"), "{}", error);
+}
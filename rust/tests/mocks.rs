@@ -0,0 +1,108 @@
+// http_mocks: canned responses in place of the network.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+fn mocked(code: &str, mocks: Value) -> Value {
+    json!({ "code": code, "http_mocks": mocks })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_requests_matching_a_pattern() {
+    let app = TestApp::start().await;
+    let mocks = json!([
+        { "match": { "urlPattern": "https://api.example.com/users/*" }, "response": { "status": 200, "headers": { "X-Mock": "yes" }, "data": { "name": "Ada" } } },
+    ]);
+    let code = "const [a, b] = await Promise.all([httpRequest('https://api.example.com/users/1'), httpRequest('https://api.example.com/users/2?full=1')]);
+        [a.status, a.data.name, a.headers['x-mock'], b.data.name]";
+    let result = app.result_of(mocked(code, mocks)).await;
+    assert_eq!(result, json!([200, "Ada", "yes", "Ada"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tells_methods_apart() {
+    let app = TestApp::start().await;
+    let mocks = json!([
+        { "match": { "url": "https://api.example.com/items", "method": "POST" }, "response": { "status": 201, "data": { "created": true } } },
+        { "match": { "url": "https://api.example.com/items", "method": "get" }, "response": { "data": [1, 2] } },
+    ]);
+    let code = "const created = await httpRequest('https://api.example.com/items', { method: 'POST', body: '{}' });
+        const listed = await httpRequest('https://api.example.com/items');
+        [created.status, created.ok, created.data, listed.status, listed.data]";
+    let result = app.result_of(mocked(code, mocks)).await;
+    assert_eq!(result, json!([201, true, { "created": true }, 200, [1, 2]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uses_the_first_match_with_uses_left() {
+    let app = TestApp::start().await;
+    let mocks = json!([
+        { "match": { "urlPattern": "*/token" }, "response": { "data": "first" }, "times": 1 },
+        { "match": { "urlPattern": "*/token" }, "response": { "data": "later" } },
+    ]);
+    let code = "const results = [];
+        for (let i = 0; i < 3; i++) results.push((await httpRequest('https://auth.example.com/token')).data);
+        results";
+    let result = app.result_of(mocked(code, mocks)).await;
+    assert_eq!(result, json!(["first", "later", "later"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_repeated_requests_in_order() {
+    let app = TestApp::start().await;
+    let mocks: Vec<_> = (1..=3)
+        .map(|count| json!({ "match": { "url": "https://api.example.com/counter" }, "response": { "data": count }, "times": 1 }))
+        .collect();
+    let code = "const counts = [];
+        for (let i = 0; i < 3; i++) counts.push((await httpRequest('https://api.example.com/counter')).data);
+        counts";
+    let result = app.result_of(mocked(code, json!(mocks))).await;
+    assert_eq!(result, json!([1, 2, 3]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_unmatched_requests_even_when_caught() {
+    let app = TestApp::start().await;
+    let mocks = json!([
+        { "match": { "url": "https://api.example.com/a", "method": "GET" }, "times": 1 },
+        { "match": { "urlPattern": "https://api.example.com/b/*" } },
+    ]);
+    let code = "await httpRequest('https://api.example.com/a');
+        try { await httpRequest('https://api.example.com/a'); } catch (e) { 'caught' }";
+    let (status, body) = app.post("/execute", mocked(code, mocks)).await;
+    assert_eq!(status, StatusCode::FAILED_DEPENDENCY, "{}", body);
+    assert_eq!(body["error"], "Unmatched request");
+    assert_eq!(
+        body["message"],
+        "No http_mocks entry matches GET https://api.example.com/a; available: GET https://api.example.com/a (used up), * https://api.example.com/b/*"
+    );
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_mocks() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/execute", mocked("1", json!([{ "match": { "method": "GET" } }]))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Invalid http_mocks");
+    assert_eq!(body["message"], "http_mocks[0]: match needs url or urlPattern");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_mocks_with_the_network_disabled() {
+    let app = TestApp::with_config(|config| config.network_disabled = true).await;
+    let code = "(await httpRequest('https://api.example.com/ping')).data";
+    let (status, body) = app.post("/execute", mocked(code, json!([{ "match": { "url": "https://api.example.com/ping" }, "response": { "data": "pong" } }]))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], "pong");
+
+    // Without mocks the request functions fail before anything is sent
+    let code = format!("(await httpRequest('{}')).statusText", app.upstream.url("/ping"));
+    let (_, body) = app.exec(&code, json!({})).await;
+    assert_eq!(body["jsError"]["name"], "NetworkDisabledError", "{}", body);
+    assert!(app.upstream.requests().is_empty());
+}
@@ -0,0 +1,106 @@
+// Code split into `modules` that import one another, run from `entry_module`.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+fn modular(modules: Value, inputs: Value) -> Value {
+    json!({ "modules": modules, "inputs": inputs })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn imports_a_module_of_the_map() {
+    let app = TestApp::start().await;
+    let modules = json!({
+        "main": "import { add, RATE } from './math';
+            import { sum } from 'sandbox:utils';
+            export default (inputs) => ({ total: add(inputs.a, inputs.b), rate: RATE, sum: sum([1, 2]) });",
+        "math": "export const RATE = 1.5; export function add(a, b) { return a + b; }",
+    });
+    let (status, body) = app.post("/execute", modular(modules, json!({ "a": 2, "b": 5 }))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!({ "total": 7, "rate": 1.5, "sum": 3 }));
+
+    // Another entry module, and an import by name
+    let request = json!({
+        "modules": {
+            "main": "export default 'main'",
+            "jobs/report": "import { PI } from 'math'; export default PI * 2",
+            "math": "export const PI = 3;",
+        },
+        "entry_module": "jobs/report",
+    });
+    let result = app.result_of(request).await;
+    assert_eq!(result, 6);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolves_paths_relative_to_the_importing_module() {
+    let app = TestApp::start().await;
+    let modules = json!({
+        "main": "import { format } from './lib/format.js'; export default format(INPUTS.n);",
+        "lib/format": "import { unit } from '../config/units'; export const format = (n) => `${n} ${unit}`;",
+        "config/units.js": "export const unit = 'kg';",
+    });
+    let (status, body) = app.post("/execute", modular(modules, json!({ "n": 4 }))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], "4 kg");
+
+    // Relative paths can't leave the map
+    let modules = json!({ "main": "import x from '../main'; export default x;" });
+    let (status, body) = app.post("/execute", modular(modules, json!({}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"], "ModuleResolutionError");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_on_a_missing_module() {
+    let app = TestApp::start().await;
+    let modules = json!({
+        "main": "import { helper } from './helpers'; export default helper();",
+        "utils": "export const helper = () => 1;",
+    });
+    let (status, body) = app.post("/execute", modular(modules, json!({}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"], "ModuleResolutionError");
+    assert_eq!(
+        body["message"],
+        "Cannot resolve module './helpers' (available: sandbox:http, sandbox:utils, main, utils)"
+    );
+
+    let (status, body) = app.post("/execute", json!({ "modules": { "lib": "export default 1" } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Invalid entry_module");
+    assert_eq!(body["message"], r#"entry_module "main" isn't one of the modules (lib)"#);
+
+    let (status, body) = app.post("/execute", json!({ "code": "1", "modules": { "main": "export default 1" } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Invalid modules");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn links_circular_imports() {
+    let app = TestApp::start().await;
+    // Both halves of the cycle are evaluated before the entry module reads them
+    let modules = json!({
+        "main": "import { isEven } from './even'; export default [isEven(10), isEven(7)];",
+        "even": "import { isOdd } from './odd'; export function isEven(n) { return n === 0 || isOdd(n - 1); }",
+        "odd": "import { isEven } from './even'; export function isOdd(n) { return n !== 0 && isEven(n - 1); }",
+    });
+    let (status, body) = app.post("/execute", modular(modules, json!({}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([true, false]));
+
+    // A binding read before the module declaring it ran, as in any ES module cycle
+    let modules = json!({
+        "main": "import { a } from './a'; export default a;",
+        "a": "import { b } from './b'; export const a = b + 1;",
+        "b": "import { a } from './a'; export const b = a + 1;",
+    });
+    let (status, body) = app.post("/execute", modular(modules, json!({}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["name"], "ReferenceError");
+}
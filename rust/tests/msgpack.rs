@@ -0,0 +1,100 @@
+// MessagePack bodies on /execute, chosen by Content-Type and Accept.
+
+mod support;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use support::TestApp;
+
+const MSGPACK: &str = "application/msgpack";
+
+#[derive(Serialize)]
+struct Execute {
+    code: &'static str,
+    inputs: BTreeMap<&'static str, serde_bytes::ByteBuf>,
+}
+
+fn request(body: Vec<u8>) -> Request {
+    Request::post("/execute").header("content-type", MSGPACK).header("accept", MSGPACK).body(Body::from(body)).unwrap()
+}
+
+// The status, content type and decoded body
+async fn send(app: &TestApp, request: Request) -> (StatusCode, String, Value) {
+    let response = app.response(request).await;
+    let status = response.status();
+    let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, content_type, rmp_serde::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_trips_binary_inputs_as_base64() {
+    let app = TestApp::start().await;
+    let blob: Vec<u8> = (0..=255).cycle().take(4096).collect();
+    let body = rmp_serde::to_vec_named(&Execute {
+        code: "({ length: atob(INPUTS.blob).length, last: atob(INPUTS.blob).charCodeAt(4095) })",
+        inputs: BTreeMap::from([("blob", serde_bytes::ByteBuf::from(blob))]),
+    })
+    .unwrap();
+
+    let (status, content_type, body) = send(&app, request(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(content_type, MSGPACK);
+    assert_eq!(body["result"], json!({ "length": 4096, "last": 255 }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_json_bodies_in_msgpack_when_accepted() {
+    let app = TestApp::start().await;
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("accept", "application/msgpack, application/json;q=0.5")
+        .body(Body::from(json!({ "code": "INPUTS.n * 2", "inputs": { "n": 21 } }).to_string()))
+        .unwrap();
+    let (status, content_type, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, MSGPACK);
+    assert_eq!(body["result"], json!(42));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_malformed_msgpack_in_msgpack() {
+    let app = TestApp::start().await;
+    // A map of two entries that ends after its first key
+    let (status, content_type, body) = send(&app, request(vec![0x82, 0xa4, b'c', b'o', b'd', b'e'])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(content_type, MSGPACK);
+    assert_eq!(body["error"], json!("Invalid MessagePack"));
+
+    let (status, _, body) = send(&app, request(rmp_serde::to_vec_named(&json!({ "inputs": {} })).unwrap())).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"], json!("Invalid request"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decodes_multi_megabyte_bodies_within_the_limits() {
+    let app = TestApp::start().await;
+    let body = rmp_serde::to_vec_named(&Execute {
+        code: "INPUTS.blob.length",
+        inputs: BTreeMap::from([("blob", serde_bytes::ByteBuf::from(vec![7u8; 3 * 1024 * 1024]))]),
+    })
+    .unwrap();
+    let (status, _, body) = send(&app, request(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(4 * 1024 * 1024));
+
+    // The limits apply to the decoded inputs, whose base64 is larger than the body
+    let app = TestApp::with_config(|config| config.max_inputs_bytes = 1024 * 1024).await;
+    let body = rmp_serde::to_vec_named(&Execute {
+        code: "1",
+        inputs: BTreeMap::from([("blob", serde_bytes::ByteBuf::from(vec![7u8; 900 * 1024]))]),
+    })
+    .unwrap();
+    let (status, _, body) = send(&app, request(body)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+}
@@ -0,0 +1,138 @@
+// `options.multipart` on httpRequest: multipart/form-data bodies as the upstream
+// receives them.
+
+mod support;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::json;
+
+use support::{MockResponse, RecordedRequest, TestApp};
+
+// A part of a received multipart body
+#[derive(Debug)]
+struct Part {
+    // The Content-Disposition and Content-Type lines, as sent
+    headers: Vec<String>,
+    content: Vec<u8>,
+}
+
+// Splits the body at the boundary of its Content-Type, checking the framing
+fn parts(request: &RecordedRequest) -> Vec<Part> {
+    let content_type = request.headers["content-type"].to_str().unwrap();
+    let boundary = content_type.strip_prefix("multipart/form-data; boundary=").expect("a multipart Content-Type");
+    let delimiter = format!("--{}", boundary);
+    let body = &request.body[..];
+    let closing = format!("{}--\r\n", delimiter);
+    assert!(body.ends_with(closing.as_bytes()), "{:?}", String::from_utf8_lossy(body));
+
+    let mut parts = Vec::new();
+    let mut rest = body.strip_prefix(format!("{}\r\n", delimiter).as_bytes()).expect("the body starts with a boundary");
+    while !rest.is_empty() {
+        let end = find(rest, format!("\r\n{}", delimiter).as_bytes()).expect("every part ends with a boundary");
+        let (part, after) = rest.split_at(end);
+        let split = find(part, b"\r\n\r\n").expect("the part headers end with an empty line");
+        let headers = String::from_utf8(part[..split].to_vec()).unwrap().split("\r\n").map(str::to_string).collect();
+        parts.push(Part { headers, content: part[split + 4..].to_vec() });
+        rest = &after[2 + delimiter.len()..];
+        rest = match rest.strip_prefix(b"\r\n") {
+            Some(next) => next,
+            None => {
+                assert_eq!(rest, b"--\r\n");
+                &[]
+            }
+        };
+    }
+    parts
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_each_part_framed_by_the_boundary() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/upload", MockResponse::json(201, json!({ "stored": true })));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ method: 'POST', multipart: [
+            {{ name: 'title', value: 'Quarterly report' }},
+            {{ name: 'count', value: 3 }},
+            {{ name: 'report', filename: 'report.csv', contentBase64: 'YSxiCjEsMgo=', contentType: 'text/csv' }},
+        ] }});
+        [r.status, r.data]",
+        app.upstream.url("/upload")
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!([201, { "stored": true }]));
+
+    let parts = parts(&app.upstream.requests()[0]);
+    assert_eq!(parts.len(), 3, "{:?}", parts);
+    assert_eq!(parts[0].headers, ["Content-Disposition: form-data; name=\"title\""]);
+    assert_eq!(parts[0].content, b"Quarterly report");
+    assert_eq!(parts[1].content, b"3");
+    assert_eq!(
+        parts[2].headers,
+        ["Content-Disposition: form-data; name=\"report\"; filename=\"report.csv\"", "Content-Type: text/csv"]
+    );
+    assert_eq!(parts[2].content, b"a,b\n1,2\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_binary_content_unchanged() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/upload", MockResponse::json(201, json!({})));
+    // Every byte value, with CR LF and what looks like a boundary inside
+    let mut bytes: Vec<u8> = (0..=255).collect();
+    bytes.extend_from_slice(b"\r\n--not-the-boundary\r\n\x00\xff");
+    let encoded = BASE64_STANDARD.encode(&bytes);
+    let code = format!(
+        "(await httpRequest('{}', {{ method: 'POST', multipart: [{{ name: 'blob', filename: 'blob.bin', contentBase64: '{}' }}] }})).status",
+        app.upstream.url("/upload"),
+        encoded
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!(201));
+    let parts = parts(&app.upstream.requests()[0]);
+    assert_eq!(parts[0].content, bytes);
+    // No contentType, no Content-Type
+    assert_eq!(parts[0].headers, ["Content-Disposition: form-data; name=\"blob\"; filename=\"blob.bin\""]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_unicode_names() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/upload", MockResponse::json(201, json!({})));
+    let code = format!(
+        "(await httpRequest('{}', {{ method: 'POST', multipart: [{{ name: 'lebenslauf_ü', filename: 'résumé ☃.pdf', contentBase64: 'JVBERg==' }}] }})).status",
+        app.upstream.url("/upload")
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!(201));
+    let parts = parts(&app.upstream.requests()[0]);
+    // File names as UTF-8, as RFC 7578 has them, and field names encoded as in RFC 5987
+    assert_eq!(
+        parts[0].headers[0],
+        "Content-Disposition: form-data; name*=utf-8''lebenslauf_%C3%BC; filename=\"résumé ☃.pdf\""
+    );
+    assert_eq!(parts[0].content, b"%PDF");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ignores_a_content_type_and_body_of_the_script() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/upload", MockResponse::json(201, json!({})));
+    let code = format!(
+        "(await httpRequest('{}', {{
+            method: 'POST',
+            headers: {{ 'Content-Type': 'application/json' }},
+            body: '{{\"ignored\": true}}',
+            multipart: [{{ name: 'field', value: 'kept' }}],
+        }})).status",
+        app.upstream.url("/upload")
+    );
+    assert_eq!(app.result(&code, json!({})).await, json!(201));
+    let request = &app.upstream.requests()[0];
+    let content_types: Vec<_> = request.headers.get_all("content-type").iter().collect();
+    assert_eq!(content_types.len(), 1);
+    assert!(content_types[0].to_str().unwrap().starts_with("multipart/form-data; boundary="));
+    let parts = parts(request);
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].content, b"kept");
+}
@@ -0,0 +1,62 @@
+// RESULTS and setResult(): named outputs that replace the code's value as the result.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collects_named_outputs_instead_of_the_final_value() {
+    let app = TestApp::start().await;
+    let code = r#"
+        setResult("summary", { total: INPUTS.items.length });
+        const records = INPUTS.items.map((item) => ({ ...item, seen: true }));
+        RESULTS.records = records;
+        await sleep(1);
+        setResult("next_cursor", "c2");
+        setResult("summary", { total: records.length, overwritten: true });
+        setResult("tags", new Set(["a", "b"]));
+        "the final expression"
+    "#;
+    let (status, body) = app.exec(code, json!({ "items": [{ "id": 1 }, { "id": 2 }] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!({
+            "summary": { "total": 2, "overwritten": true },
+            "records": [{ "id": 1, "seen": true }, { "id": 2, "seen": true }],
+            "next_cursor": "c2",
+            "tags": ["a", "b"],
+        })
+    );
+
+    // Without named outputs the final value is the result, as before
+    let (_, body) = app.exec("RESULTS.x = 1; delete RESULTS.x; 'plain'", json!({})).await;
+    assert_eq!(body["result"], "plain");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_the_output_that_cannot_be_serialized() {
+    let app = TestApp::start().await;
+    let (status, body) = app.exec("setResult('ok', 1); setResult('handler', () => 1); 2", json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"], "UnserializableResult");
+    assert_eq!(body["message"], "RESULTS[\"handler\"] is not serializable: function values can't be represented as JSON");
+
+    let (_, body) = app.exec("const loop = {}; loop.self = loop; RESULTS['a loop'] = loop", json!({})).await;
+    assert_eq!(body["message"], "RESULTS[\"a loop\"] contains circular references");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_the_outputs_against_the_result_size_limit() {
+    let app = TestApp::with_config(|config| config.max_result_bytes = 1000).await;
+    let (status, body) = app.exec("setResult('a', 'x'.repeat(600)); setResult('b', 'y'.repeat(600)); null", json!({})).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+    assert_eq!(body["error"], "Result too large");
+    assert!(body["message"].as_str().unwrap().contains("MAX_RESULT_BYTES"), "{}", body);
+
+    let result = app.result("setResult('a', 'x'.repeat(600)); 'y'.repeat(5000)", json!({})).await;
+    assert_eq!(result["a"].as_str().unwrap().len(), 600);
+}
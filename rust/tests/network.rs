@@ -0,0 +1,146 @@
+// Executions without network access, with `allow_network: false` or NETWORK_DISABLED,
+// and FETCH_ALLOWLIST and FETCH_DENYLIST narrowing what they and their redirects
+// may reach.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::Config;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+use support::{fixture, MockResponse, MockUpstream, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_fetching_code_before_anything_is_sent() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/slow", MockResponse::text(200, "late").with_delay(Duration::from_secs(5)));
+    let url = app.upstream.url("/slow");
+    for call in [format!("await httpRequest('{}')", url), format!("await fetch('{}')", url), format!("await graphql('{}', '{{ ping }}')", url)] {
+        let started = Instant::now();
+        let (status, body) = app
+            .post("/execute", json!({ "code": call, "allow_network": false, "include_meta": true }))
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(2), "{}", call);
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["jsError"]["name"], "NetworkDisabledError", "{}", body);
+        assert!(body["jsError"]["message"].as_str().unwrap().contains("has no network access"), "{}", body);
+        assert_eq!(body["meta"]["networkAllowed"], json!(false));
+    }
+
+    let (_, body) = app
+        .post("/execute", json!({ "code": "try { await fetch('https://example.com'); } catch (e) { e instanceof NetworkDisabledError }", "allow_network": false }))
+        .await;
+    assert_eq!(body["result"], json!(true));
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_pure_code_without_network_access() {
+    let app = TestApp::start().await;
+    let (status, body) = app
+        .post("/execute", json!({ "code": "INPUTS.values.map((v) => v * 2)", "inputs": { "values": [1, 2] }, "allow_network": false, "include_meta": true }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([2, 4]));
+    assert_eq!(body["meta"]["networkAllowed"], json!(false));
+
+    let (_, body) = app.post("/execute", json!({ "code": "1", "include_meta": true })).await;
+    assert_eq!(body["meta"]["networkAllowed"], json!(true));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_network_off_when_disabled_on_the_server() {
+    let app = TestApp::with_config(|config| config.network_disabled = true).await;
+    let code = format!("(await httpRequest('{}')).status", app.upstream.url("/ping"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "allow_network": true, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["name"], "NetworkDisabledError");
+    assert_eq!(body["meta"]["networkAllowed"], json!(false));
+    assert!(app.upstream.requests().is_empty());
+
+    let (_, functions) = app.get("/functions").await;
+    assert_eq!(functions["limits"]["networkDisabled"], json!(true));
+}
+
+// `[status, errorCode, data]` of a GET to each URL
+async fn statuses(app: &TestApp, urls: &[String]) -> Value {
+    let code = "Promise.all(INPUTS.urls.map(async (url) => { const r = await httpRequest(url); return [r.status, r.errorCode ?? null, r.data]; }))";
+    app.result(code, json!({ "urls": urls })).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetches_only_from_allowed_hosts() {
+    let app = TestApp::with_config(|config| config.fetch_allowlist = vec!["127.0.0.1".to_string()]).await;
+    app.upstream.mock("GET", "/ping", MockResponse::json(200, json!("pong")));
+    let allowed = app.upstream.url("/ping");
+    let denied = allowed.replace("127.0.0.1", "localhost");
+    let result = statuses(&app, &[allowed, denied.clone()]).await;
+    assert_eq!(result[0], json!([200, null, "pong"]));
+    assert_eq!(result[1], json!([0, "blocked_by_policy", format!("URL is not permitted by the fetch policy: {}", denied)]));
+    assert_eq!(app.upstream.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn matches_allowlist_wildcards_and_url_prefixes() {
+    let upstream = MockUpstream::start().await;
+    let prefix = upstream.url("/public/");
+    let app = TestApp::with_config(|config| {
+        config.fetch_allowlist = vec!["*.internal".to_string(), prefix.clone()];
+        config.fetch_denylist = vec!["secret.internal".to_string()];
+        config.dns_overrides = ["api.internal", "deep.api.internal", "secret.internal", "internal.example.com"]
+            .iter()
+            .map(|host| format!("{}=127.0.0.1", host))
+            .collect();
+    })
+    .await;
+    app.upstream.mock("GET", "/ping", MockResponse::json(200, json!("pong")));
+    upstream.mock("GET", "/public/a", MockResponse::json(200, json!("a")));
+    let on = |host: &str| app.upstream.url("/ping").replace("127.0.0.1", host);
+
+    let result = statuses(
+        &app,
+        &[on("api.internal"), on("deep.api.internal"), upstream.url("/public/a"), on("internal.example.com"), on("secret.internal"), upstream.url("/private")],
+    )
+    .await;
+    let codes: Vec<&Value> = result.as_array().unwrap().iter().map(|r| &r[1]).collect();
+    let blocked = json!("blocked_by_policy");
+    assert_eq!(codes, [&Value::Null, &Value::Null, &Value::Null, &blocked, &blocked, &blocked], "{}", result);
+    assert_eq!((app.upstream.requests().len(), upstream.requests().len()), (2, 1));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restricts_the_schemes() {
+    let https = MockUpstream::start_tls().await;
+    https.mock("GET", "/ping", MockResponse::json(200, json!("secure")));
+    let allow_https = |config: &mut Config| config.fetch_allowlist = vec!["https:".to_string()];
+    let deny_http = |config: &mut Config| config.fetch_denylist = vec!["http:".to_string()];
+    for restrict in [allow_https, deny_http] {
+        let app = TestApp::with_config(|config| {
+            config.outbound_ca_bundle = fixture("tls/ca.pem").display().to_string();
+            restrict(config);
+        })
+        .await;
+        app.upstream.mock("GET", "/ping", MockResponse::json(200, json!("plain")));
+        let plain = app.upstream.url("/ping");
+        let result = statuses(&app, &[https.url("/ping"), plain.clone()]).await;
+        assert_eq!(result[0], json!([200, null, "secure"]));
+        assert_eq!(result[1], json!([0, "blocked_by_policy", format!("URL is not permitted by the fetch policy: {}", plain)]));
+        assert!(app.upstream.requests().is_empty());
+    }
+    assert_eq!(https.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checks_redirect_targets_against_the_policy() {
+    let app = TestApp::with_config(|config| config.fetch_allowlist = vec!["127.0.0.1".to_string()]).await;
+    let denied = app.upstream.url("/target").replace("127.0.0.1", "localhost");
+    app.upstream.mock("GET", "/away", MockResponse::text(302, "").with_header("location", &denied));
+    app.upstream.mock("GET", "/target", MockResponse::json(200, json!("reached")));
+    let result = statuses(&app, &[app.upstream.url("/away")]).await;
+    assert_eq!(result[0][1], json!("blocked_by_policy"), "{}", result);
+    assert!(result[0][2].as_str().unwrap().contains(&denied), "{}", result);
+    // The redirect was received, its target never asked
+    let requests: Vec<String> = app.upstream.requests().into_iter().map(|request| request.uri).collect();
+    assert_eq!(requests, ["/away"]);
+}
@@ -0,0 +1,443 @@
+// The OpenAPI document at /openapi.json: its structure, the routes it lists, and
+// whether real responses match the schemas it gives for them.
+
+mod support;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+// Every route of v1_routes, as the document must list them
+const ROUTES: &[(&str, &str)] = &[
+    ("post", "/execute"),
+    ("post", "/execute/batch"),
+    ("post", "/execute/map"),
+    ("post", "/validate"),
+    ("post", "/analyze"),
+    ("get", "/functions"),
+    ("get", "/session"),
+    ("post", "/jobs"),
+    ("get", "/jobs/{id}"),
+    ("delete", "/jobs/{id}"),
+    ("post", "/contexts"),
+    ("delete", "/contexts/{id}"),
+    ("post", "/contexts/{id}/execute"),
+    ("get", "/tenants/{id}/usage"),
+    ("get", "/usage"),
+    ("get", "/health"),
+    ("get", "/livez"),
+    ("get", "/readyz"),
+    ("get", "/metrics"),
+    ("get", "/admin/circuits"),
+    ("get", "/version"),
+];
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+// The parts of the OpenAPI 3.1 schema (spec.openapis.org/oas/3.1/schema) for the
+// objects the document uses, with unknown fields refused
+fn openapi_schema() -> Value {
+    let or_reference = |name: &str| {
+        json!({
+            "if": { "type": "object", "required": ["$ref"] },
+            "then": { "$ref": "#/$defs/reference" },
+            "else": { "$ref": format!("#/$defs/{}", name) },
+        })
+    };
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "required": ["openapi", "info", "paths"],
+        "properties": {
+            "openapi": { "type": "string", "pattern": "^3\\.1\\.\\d+(-.+)?$" },
+            "info": {
+                "type": "object",
+                "required": ["title", "version"],
+                "properties": {
+                    "title": { "type": "string" },
+                    "version": { "type": "string" },
+                    "description": { "type": "string" },
+                },
+                "additionalProperties": false,
+            },
+            "servers": {
+                "type": "array",
+                "items": { "type": "object", "required": ["url"], "properties": { "url": { "type": "string" } } },
+            },
+            "paths": {
+                "type": "object",
+                "propertyNames": { "pattern": "^/" },
+                "additionalProperties": { "$ref": "#/$defs/path-item" },
+            },
+            "components": {
+                "type": "object",
+                "properties": {
+                    "schemas": { "type": "object", "additionalProperties": { "type": ["object", "boolean"] } },
+                    "responses": { "type": "object", "additionalProperties": or_reference("response") },
+                    "parameters": { "type": "object", "additionalProperties": or_reference("parameter") },
+                },
+                "additionalProperties": false,
+            },
+        },
+        "additionalProperties": false,
+        "$defs": {
+            "path-item": {
+                "type": "object",
+                "properties": {
+                    "summary": { "type": "string" },
+                    "description": { "type": "string" },
+                    "parameters": { "type": "array", "items": or_reference("parameter") },
+                    "get": { "$ref": "#/$defs/operation" },
+                    "put": { "$ref": "#/$defs/operation" },
+                    "post": { "$ref": "#/$defs/operation" },
+                    "delete": { "$ref": "#/$defs/operation" },
+                    "options": { "$ref": "#/$defs/operation" },
+                    "head": { "$ref": "#/$defs/operation" },
+                    "patch": { "$ref": "#/$defs/operation" },
+                    "trace": { "$ref": "#/$defs/operation" },
+                },
+                "additionalProperties": false,
+            },
+            "operation": {
+                "type": "object",
+                "required": ["responses"],
+                "properties": {
+                    "operationId": { "type": "string" },
+                    "summary": { "type": "string" },
+                    "description": { "type": "string" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                    "parameters": { "type": "array", "items": or_reference("parameter") },
+                    "requestBody": or_reference("request-body"),
+                    "responses": {
+                        "type": "object",
+                        "minProperties": 1,
+                        "propertyNames": { "pattern": "^([1-5](?:[0-9]{2}|XX)|default)$" },
+                        "additionalProperties": or_reference("response"),
+                    },
+                    "deprecated": { "type": "boolean" },
+                },
+                "additionalProperties": false,
+            },
+            "request-body": {
+                "type": "object",
+                "required": ["content"],
+                "properties": {
+                    "description": { "type": "string" },
+                    "content": { "$ref": "#/$defs/content" },
+                    "required": { "type": "boolean" },
+                },
+                "additionalProperties": false,
+            },
+            "response": {
+                "type": "object",
+                "required": ["description"],
+                "properties": {
+                    "description": { "type": "string" },
+                    "headers": { "type": "object", "additionalProperties": or_reference("header") },
+                    "content": { "$ref": "#/$defs/content" },
+                },
+                "additionalProperties": false,
+            },
+            "content": {
+                "type": "object",
+                "propertyNames": { "pattern": "^[a-z]+/[a-z0-9.+-]+$" },
+                "additionalProperties": {
+                    "type": "object",
+                    "properties": { "schema": { "type": ["object", "boolean"] } },
+                    "additionalProperties": false,
+                },
+            },
+            "parameter": {
+                "type": "object",
+                "required": ["name", "in"],
+                "properties": {
+                    "name": { "type": "string" },
+                    "in": { "enum": ["query", "header", "path", "cookie"] },
+                    "description": { "type": "string" },
+                    "required": { "type": "boolean" },
+                    "deprecated": { "type": "boolean" },
+                    "schema": { "type": ["object", "boolean"] },
+                },
+                "if": { "properties": { "in": { "const": "path" } } },
+                "then": { "required": ["required"], "properties": { "required": { "const": true } } },
+                "additionalProperties": false,
+            },
+            "header": {
+                "type": "object",
+                "properties": {
+                    "description": { "type": "string" },
+                    "required": { "type": "boolean" },
+                    "schema": { "type": ["object", "boolean"] },
+                },
+                "additionalProperties": false,
+            },
+            "reference": {
+                "type": "object",
+                "required": ["$ref"],
+                "properties": { "$ref": { "type": "string" }, "summary": { "type": "string" }, "description": { "type": "string" } },
+                "additionalProperties": false,
+            },
+        },
+    })
+}
+
+async fn document(app: &TestApp) -> Value {
+    let (status, document) = app.get("/openapi.json").await;
+    assert_eq!(status, StatusCode::OK, "{}", document);
+    document
+}
+
+// Follows a local `$ref`, e.g. to a response of the components
+fn resolve<'a>(document: &'a Value, value: &'a Value) -> &'a Value {
+    match value["$ref"].as_str() {
+        Some(pointer) => document
+            .pointer(pointer.trim_start_matches('#'))
+            .unwrap_or_else(|| panic!("{} doesn't resolve", pointer)),
+        None => value,
+    }
+}
+
+fn refs(value: &Value, found: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(pointer)) = object.get("$ref") {
+                found.push(pointer.clone());
+            }
+            object.values().for_each(|value| refs(value, found));
+        }
+        Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+        _ => {}
+    }
+}
+
+// Refuses fields a schema doesn't list, so a field added to a response type
+// without adding it to the document fails the test
+fn closed(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            if object.contains_key("properties") && !object.contains_key("additionalProperties") {
+                object.insert("additionalProperties".to_string(), json!(false));
+            }
+            object.values_mut().for_each(closed);
+        }
+        Value::Array(values) => values.iter_mut().for_each(closed),
+        _ => {}
+    }
+}
+
+// Checks a body against the schema the document gives for the operation's status
+fn check(document: &Value, method: &str, path: &str, status: StatusCode, body: &Value) {
+    let operation = &document["paths"][path][method];
+    let status = status.as_u16().to_string();
+    let response = resolve(document, &operation["responses"][&status]);
+    assert!(response.is_object(), "{} {} doesn't document {}: {}", method, path, status, body);
+    let mut schema = response["content"]["application/json"]["schema"].clone();
+    schema["components"] = document["components"].clone();
+    closed(&mut schema);
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    let errors: Vec<String> = validator.iter_errors(body).map(|e| format!("{} at {}", e, e.instance_path)).collect();
+    assert!(errors.is_empty(), "{} {} {}: {:?}\n{}", method, path, status, errors, body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_a_valid_openapi_3_1_document() {
+    let app = TestApp::start().await;
+    let document = document(&app).await;
+
+    let validator = jsonschema::validator_for(&openapi_schema()).unwrap();
+    let errors: Vec<String> = validator.iter_errors(&document).map(|e| format!("{} at {}", e, e.instance_path)).collect();
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let mut found = Vec::new();
+    refs(&document, &mut found);
+    for pointer in found {
+        assert!(document.pointer(pointer.trim_start_matches('#')).is_some(), "{} doesn't resolve", pointer);
+    }
+    // Every schema is a valid JSON Schema 2020-12
+    for (name, schema) in document["components"]["schemas"].as_object().unwrap() {
+        let mut schema = schema.clone();
+        schema["components"] = document["components"].clone();
+        assert!(jsonschema::validator_for(&schema).is_ok(), "{}", name);
+    }
+
+    let mut operation_ids = BTreeSet::new();
+    for (path, item) in document["paths"].as_object().unwrap() {
+        let declared: Vec<&str> = path.split('/').filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}')).collect();
+        for method in METHODS.iter().filter(|method| item.get(**method).is_some()) {
+            let parameters = item["parameters"].as_array().into_iter().chain(item[*method]["parameters"].as_array()).flatten();
+            let in_path: Vec<&str> = parameters
+                .map(|parameter| resolve(&document, parameter))
+                .filter(|parameter| parameter["in"] == "path")
+                .filter_map(|parameter| parameter["name"].as_str())
+                .collect();
+            assert_eq!(in_path, declared, "{} {}", method, path);
+            let id = item[*method]["operationId"].as_str().unwrap();
+            assert!(operation_ids.insert(id), "operationId {} appears twice", id);
+        }
+    }
+
+    let (_, version) = app.get("/version").await;
+    assert_eq!(document["info"]["version"], version["version"]);
+    assert_eq!(document["servers"], json!([{ "url": "/v1" }]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lists_every_registered_route() {
+    let app = TestApp::start().await;
+    let document = document(&app).await;
+    let documented: BTreeSet<(String, String)> = document["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, item)| {
+            METHODS.iter().filter(|method| item.get(**method).is_some()).map(|method| (method.to_string(), path.clone()))
+        })
+        .collect();
+    let routes: BTreeSet<(String, String)> =
+        ROUTES.iter().map(|(method, path)| (method.to_string(), path.to_string())).collect();
+    assert_eq!(documented, routes);
+
+    // And every documented operation reaches a handler rather than the fallback
+    let (status, body) = app.get("/v1/nowhere").await;
+    assert_eq!((status, &body["error"]), (StatusCode::NOT_FOUND, &json!("Not found")));
+    for (method, path) in &documented {
+        let uri = format!("/v1{}", path.replace("{id}", "missing"));
+        let body = match method.as_str() {
+            "post" => Body::from("{}"),
+            _ => Body::empty(),
+        };
+        let request = Request::builder()
+            .method(method.to_uppercase().as_str())
+            .uri(&uri)
+            .header("content-type", "application/json")
+            .body(body)
+            .unwrap();
+        let (status, body) = app.send(request).await;
+        assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, uri);
+        assert_ne!(body["error"], "Not found", "{} {}", method, uri);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn describes_execution_responses() {
+    let app = TestApp::start().await;
+    let document = document(&app).await;
+    app.upstream.mock("GET", "/items", MockResponse::json(200, json!([{ "id": 1 }])));
+
+    let code = format!("(async () => (await httpRequest('{}')).data)()", app.upstream.url("/items"));
+    let cases = [
+        json!({ "code": code, "include_meta": true, "record_http": true }),
+        json!({ "code": code, "dry_run": true }),
+        json!({ "code": "Promise.reject('late'); 1", "debug": true }),
+        json!({ "code": "throw Object.assign(new Error('no'), { code: 7 })", "include_meta": true }),
+        json!({ "code": "throw { reason: 'plain' }" }),
+        json!({ "code": "({ n: 'x' })", "output_schema": { "properties": { "n": { "type": "number" } } } }),
+        json!({ "code": "let x = ;" }),
+        json!({ "code": "while (true) {}", "timeout_ms": 100 }),
+        json!({ "code": "'x'.repeat(100)", "limits": { "max_result_bytes": 10 }, "debug": true }),
+        json!({ "code": code, "http_mocks": [{ "match": { "url": "https://api.example.com/" } }] }),
+        json!({ "code": "1", "timeout_ms": -1 }),
+        json!({ "inputs": {} }),
+    ];
+    let mut statuses = BTreeSet::new();
+    for request in cases {
+        let (status, body) = app.post("/v1/execute", request).await;
+        check(&document, "post", "/execute", status, &body);
+        statuses.insert(status.as_u16());
+    }
+    assert_eq!(statuses, BTreeSet::from([200, 400, 408, 413, 422, 424]));
+
+    let small = TestApp::with_config(|config| config.max_code_bytes = 10).await;
+    let (status, body) = small.post("/v1/execute", json!({ "code": "'too long for the limit'" })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    check(&document, "post", "/execute", status, &body);
+
+    let batch = json!({
+        "jobs": [
+            { "id": "a", "code": "1", "include_meta": true },
+            { "id": 2, "code": "throw new Error('b')" },
+        ],
+    });
+    let (status, body) = app.post("/v1/execute/batch", batch).await;
+    check(&document, "post", "/execute/batch", status, &body);
+    let (status, body) = app.post("/v1/execute/map", json!({ "code": "INPUTS.n", "input_sets": [{ "n": 1 }, null] })).await;
+    check(&document, "post", "/execute/map", status, &body);
+    let (status, body) = app.post("/v1/execute/map", json!({ "code": "1", "inputs": {}, "input_sets": [] })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    check(&document, "post", "/execute/map", status, &body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn describes_the_other_responses() {
+    let app = TestApp::start().await;
+    let document = document(&app).await;
+
+    for code in ["httpRequest('https://example.com'); fetch", "let x = ;", ""] {
+        for path in ["/validate", "/analyze"] {
+            let (status, body) = app.post(&format!("/v1{}", path), json!({ "code": code })).await;
+            check(&document, "post", path, status, &body);
+        }
+    }
+    for path in ["/functions", "/health?deep=true", "/readyz", "/livez", "/version"] {
+        let (status, body) = app.get(&format!("/v1{}", path)).await;
+        let path = path.split('?').next().unwrap();
+        check(&document, "get", path, status, &body);
+    }
+
+    let (status, body) = app.post("/v1/jobs", json!({ "code": "INPUTS.x", "inputs": { "x": 1 }, "include_meta": true })).await;
+    check(&document, "post", "/jobs", status, &body);
+    let id = body["job_id"].as_str().unwrap().to_string();
+    let mut job = Value::Null;
+    for _ in 0..100 {
+        let (status, body) = app.get(&format!("/v1/jobs/{}", id)).await;
+        check(&document, "get", "/jobs/{id}", status, &body);
+        job = body;
+        if job["status"] == "succeeded" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["status"], "succeeded", "{}", job);
+    let cancel = |id: String| Request::delete(format!("/v1/jobs/{}", id)).body(Body::empty()).unwrap();
+    let (status, body) = app.send(cancel(id)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    check(&document, "delete", "/jobs/{id}", status, &body);
+    let (status, body) = app.post("/v1/jobs", json!({ "code": "new Promise(() => {})" })).await;
+    check(&document, "post", "/jobs", status, &body);
+    let (status, body) = app.send(cancel(body["job_id"].as_str().unwrap().to_string())).await;
+    check(&document, "delete", "/jobs/{id}", status, &body);
+    let (status, body) = app.get("/v1/jobs/missing").await;
+    check(&document, "get", "/jobs/{id}", status, &body);
+
+    let (status, body) = app.post("/v1/contexts", json!({ "init_code": "var total = 1; total" })).await;
+    assert_eq!(status, StatusCode::CREATED);
+    check(&document, "post", "/contexts", status, &body);
+    let id = body["id"].as_str().unwrap().to_string();
+    let (status, body) = app.post(&format!("/v1/contexts/{}/execute", id), json!({ "code": "total + 1" })).await;
+    check(&document, "post", "/contexts/{id}/execute", status, &body);
+    let (status, body) = app.post(&format!("/v1/contexts/{}/execute", id), json!({ "code": "nope()" })).await;
+    check(&document, "post", "/contexts/{id}/execute", status, &body);
+    let delete = Request::delete(format!("/v1/contexts/{}", id)).body(Body::empty()).unwrap();
+    let (status, _) = app.send(delete).await;
+    assert!(document["paths"]["/contexts/{id}"]["delete"]["responses"][status.as_str()].is_object());
+    let (status, body) = app.post(&format!("/v1/contexts/{}/execute", id), json!({ "code": "1" })).await;
+    check(&document, "post", "/contexts/{id}/execute", status, &body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_swagger_ui_when_enabled() {
+    let app = TestApp::start().await;
+    let (status, _) = app.get("/docs").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let app = TestApp::with_config(|config| config.openapi_docs = true).await;
+    let response = app.response(Request::get("/docs").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    let (_, page) = app.get("/docs").await;
+    assert!(page.as_str().unwrap().contains(r#"url: "/openapi.json""#), "{}", page);
+}
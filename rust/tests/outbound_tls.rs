@@ -0,0 +1,50 @@
+// HTTPS upstreams: OUTBOUND_CA_BUNDLE, and `insecureSkipTlsVerify` behind
+// ALLOW_INSECURE_TLS.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{fixture, MockResponse, MockUpstream, TestApp};
+
+// `[status, errorCode, statusText]` of a GET with the options
+async fn get(app: &TestApp, url: &str, options: &str) -> Value {
+    let code = format!("const r = await httpRequest('{}', {}); [r.status, r.errorCode, r.statusText]", url, options);
+    app.result(&code, json!({})).await
+}
+
+async fn https_upstream() -> MockUpstream {
+    let upstream = MockUpstream::start_tls().await;
+    upstream.mock("GET", "/secure", MockResponse::json(200, json!("ok")));
+    upstream
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_a_certificate_from_an_unknown_issuer() {
+    let app = TestApp::with_config(|config| config.allow_insecure_tls = true).await;
+    let upstream = https_upstream().await;
+    let result = get(&app, &upstream.url("/secure"), "{}").await;
+    assert_eq!((&result[0], &result[1]), (&json!(0), &json!("tls")), "{}", result);
+    assert!(upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn trusts_the_issuers_of_the_ca_bundle() {
+    let app = TestApp::with_config(|config| config.outbound_ca_bundle = fixture("tls/ca.pem").display().to_string()).await;
+    let upstream = https_upstream().await;
+    assert_eq!(get(&app, &upstream.url("/secure"), "{}").await[0], 200);
+    assert_eq!(get(&app, &upstream.url("/secure").replace("127.0.0.1", "localhost"), "{}").await[0], 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skips_verification_only_when_the_server_allows_it() {
+    let upstream = https_upstream().await;
+    let app = TestApp::with_config(|config| config.allow_insecure_tls = true).await;
+    let result = get(&app, &upstream.url("/secure"), "{ insecureSkipTlsVerify: true }").await;
+    assert_eq!(result[0], 200, "{}", result);
+
+    let app = TestApp::start().await;
+    let result = get(&app, &upstream.url("/secure"), "{ insecureSkipTlsVerify: true }").await;
+    assert_eq!(result, json!([0, "blocked_by_policy", "Forbidden by policy"]));
+    assert_eq!(upstream.requests().len(), 1);
+}
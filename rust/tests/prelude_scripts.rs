@@ -0,0 +1,142 @@
+// PRELUDE_DIR and PRELUDE_FILES: server scripts evaluated into every context before
+// user code.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::{Config, Executor};
+use serde_json::json;
+use std::path::PathBuf;
+
+use support::TestApp;
+
+// A directory of its own for each test, holding `files`
+fn prelude_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sandbox-prelude-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, source) in files {
+        std::fs::write(dir.join(name), source).unwrap();
+    }
+    dir
+}
+
+async fn with_prelude(dir: &std::path::Path) -> TestApp {
+    let dir = dir.to_string_lossy().into_owned();
+    TestApp::with_config(move |config| config.prelude_dir = dir).await
+}
+
+const HELPERS: &str = r#"
+const TAX_RATE = 0.2;
+function withTax(amount) {
+    return Math.round(amount * (1 + TAX_RATE) * 100) / 100;
+}
+function mustBePositive(n) {
+    if (n <= 0) throw new RangeError("not positive: " + n);
+    return n;
+}
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn user_code_calls_prelude_functions() {
+    let orders = "var orderTotal = (items) => withTax(items.reduce((a, b) => a + b, 0));";
+    let dir = prelude_dir("calls", &[("20-orders.js", orders), ("10-helpers.js", HELPERS), ("notes.txt", "not a script")]);
+    let app = with_prelude(&dir).await;
+
+    let (status, body) = app.exec("[withTax(10), orderTotal(INPUTS.items), TAX_RATE]", json!({ "items": [5, 5] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([12, 12, 0.2]));
+
+    // The prelude's frames are left out of the stack, the error is located in user code
+    let (status, body) = app.exec("function check() {\n  return mustBePositive(-1);\n}\ncheck()", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let error = &body["jsError"];
+    assert_eq!(error["message"], "not positive: -1");
+    assert_eq!((&error["line"], &error["column"]), (&json!(2), &json!(10)));
+    assert!(!error["stack"].as_str().unwrap().contains("prelude"), "{}", error);
+
+    // Without it, as a request asks for
+    let request = json!({ "code": "typeof withTax", "skip_prelude": true });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], "undefined");
+
+    // Contexts get it too
+    let (status, body) = app.post("/contexts", json!({ "init_code": "withTax(100)" })).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["result"], 120);
+
+    let (_, functions) = app.get("/functions").await;
+    let names: Vec<_> = functions["preludeScripts"].as_array().unwrap().iter().map(|file| file["name"].clone()).collect();
+    assert_eq!(names, [json!("10-helpers.js"), json!("20-orders.js")]);
+    assert_eq!(functions["preludeScripts"][0]["bytes"], HELPERS.len());
+    assert_eq!(functions["preludeScripts"][0]["sha256"].as_str().unwrap().len(), 64);
+    let (_, version) = app.get("/version").await;
+    assert_eq!(version["preludeScripts"], functions["preludeScripts"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prelude_globals_sit_between_the_sandbox_and_user_code() {
+    let prelude = r#"
+        // Replaces a sandbox global
+        globalThis.parseXml = (text) => "from the prelude";
+        function greet() { return "prelude"; }
+        // ENV, SECRETS and RESULTS can't be replaced
+        let locked;
+        try { ENV = {}; } catch (e) { locked = e.name; }
+        var envLocked = locked;
+    "#;
+    let dir = prelude_dir("precedence", &[("lib.js", prelude)]);
+    let app = with_prelude(&dir).await;
+
+    let code = r#"
+        const before = greet();
+        function greet() { return "user"; }
+        [parseXml("<a/>"), before, greet(), envLocked, typeof ENV]
+    "#;
+    let (status, body) = app.exec(code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["from the prelude", "user", "user", "TypeError", "object"]));
+
+    // A lexical declaration of a name the prelude declared is a redeclaration
+    let (status, body) = app.exec("const greet = 1; greet", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body["jsError"]["message"].as_str().unwrap().contains("redeclaration of 'greet'"), "{}", body);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn broken_prelude_scripts_fail_startup() {
+    let broken = "function ok() {}\nfunction broken( {\n";
+    let dir = prelude_dir("broken", &[("10-ok.js", "function ok() {}"), ("20-broken.js", broken)]);
+    let config = Config {
+        prelude_dir: dir.to_string_lossy().into_owned(),
+        ..Config::default()
+    };
+    let error = Executor::new(&config).err().unwrap();
+    assert!(error.starts_with("Prelude script "), "{}", error);
+    assert!(error.contains("20-broken.js doesn't compile: SyntaxError: "), "{}", error);
+    assert!(error.contains("prelude:20-broken.js:"), "{}", error);
+
+    let config = Config {
+        prelude_files: vec![dir.join("missing.js").to_string_lossy().into_owned()],
+        ..Config::default()
+    };
+    let error = Executor::new(&config).err().unwrap();
+    assert!(error.starts_with("Can't read prelude script ") && error.contains("missing.js"), "{}", error);
+
+    // One that throws when evaluated is caught before the server starts
+    std::fs::write(dir.join("20-broken.js"), "const x = 1;\nundefinedThing();\n").unwrap();
+    let config = Config {
+        prelude_dir: dir.to_string_lossy().into_owned(),
+        ..Config::default()
+    };
+    let executor = Executor::new(&config).unwrap();
+    let error = executor.check_prelude_scripts().await.err().unwrap();
+    assert_eq!(
+        error,
+        "Prelude script 20-broken.js failed: ReferenceError: undefinedThing is not defined at <eval> (prelude:20-broken.js:2:1)"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,120 @@
+// Priorities of executions waiting for a slot: interactive ones are admitted
+// ahead of queued batch work, and may have slots of their own.
+
+mod support;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+const SLOW: &str = "sleep(80).then(() => 'done')";
+
+fn batch_of(count: usize, code: &str) -> Value {
+    let jobs: Vec<Value> = (0..count)
+        .map(|id| json!({ "id": id, "code": code, "priority": "batch", "include_meta": true }))
+        .collect();
+    json!({ "jobs": jobs })
+}
+
+fn queue_ms(meta: &Value) -> u64 {
+    meta["queueMs"].as_u64().unwrap_or_else(|| panic!("no queueMs in {}", meta))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn admits_an_interactive_execution_ahead_of_queued_batch_jobs() {
+    let app = TestApp::with_config(|config| {
+        config.max_concurrent_executions = 1;
+        config.batch_parallelism = 12;
+        config.queue_wait_timeout_ms = 30_000;
+    })
+    .await;
+    let interactive = async {
+        // Once the batch fills the queue
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        app.post("/execute", json!({ "code": "INPUTS.n", "inputs": { "n": 1 }, "priority": "interactive", "include_meta": true }))
+            .await
+    };
+    let ((batch_status, batch), (status, body)) = tokio::join!(app.post("/execute/batch", batch_of(12, SLOW)), interactive);
+    assert_eq!(batch_status, StatusCode::OK, "{}", batch);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // It waits for the batch job running when it arrived, not for the queue, which
+    // takes about a second to go through
+    let waited = queue_ms(&body["meta"]);
+    assert!(waited <= 300, "waited {} ms", waited);
+    let admitted_later = batch["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|result| queue_ms(&result["meta"]) > 150 + waited)
+        .count();
+    assert!(admitted_later >= 6, "{} batch jobs were admitted after it: {}", admitted_later, batch);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_reserved_slots_for_interactive_executions() {
+    let app = TestApp::with_config(|config| {
+        config.max_concurrent_executions = 2;
+        config.interactive_reserved_slots = 1;
+    })
+    .await;
+    // The second batch job waits for the first although a slot is free
+    let interactive = async {
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        app.post("/execute", json!({ "code": "1", "priority": "interactive", "include_meta": true })).await
+    };
+    let ((_, batch), (status, body)) = tokio::join!(app.post("/execute/batch", batch_of(2, SLOW)), interactive);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(queue_ms(&body["meta"]) < 40, "{}", body);
+    let waits: Vec<u64> = batch["results"].as_array().unwrap().iter().map(|result| queue_ms(&result["meta"])).collect();
+    assert!(*waits.iter().min().unwrap() < 40, "{:?}", waits);
+    assert!(*waits.iter().max().unwrap() >= 60, "{:?}", waits);
+
+    // Normal executions are held back the same way
+    let normal = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        app.post("/execute", json!({ "code": "1", "include_meta": true })).await
+    };
+    let (_, (status, body)) = tokio::join!(app.exec(SLOW, json!({})), normal);
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(queue_ms(&body["meta"]) >= 40, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restricts_interactive_to_listed_api_keys() {
+    let app = TestApp::with_config(|config| config.interactive_api_keys = vec!["ui-key".to_string()]).await;
+    let request = |api_key: Option<&str>| {
+        let body = json!({ "code": "2", "priority": "interactive", "include_meta": true });
+        let request = Request::post("/execute").header("content-type", "application/json");
+        let request = match api_key {
+            Some(key) => request.header("x-api-key", key),
+            None => request,
+        };
+        request.body(Body::from(body.to_string())).unwrap()
+    };
+    for api_key in [None, Some("other-key")] {
+        let (status, body) = app.send(request(api_key)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid priority");
+    }
+    let (status, body) = app.send(request(Some("ui-key"))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["queueMs"], 0);
+
+    // Anyone may ask for the others
+    let (status, body) = app.post("/execute", json!({ "code": "3", "priority": "batch" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = app.post("/execute", json!({ "code": "3", "priority": "urgent" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (_, metrics) = app.get("/metrics").await;
+    let metrics = metrics.as_str().unwrap();
+    for priority in ["interactive", "normal", "batch"] {
+        let count = format!("jsexec_queue_wait_seconds_count{{priority=\"{}\"}}", priority);
+        assert!(metrics.contains(&count), "{}", metrics);
+    }
+    assert!(metrics.contains("jsexec_queue_wait_seconds_bucket{priority=\"batch\",le=\"0\"}"), "{}", metrics);
+}
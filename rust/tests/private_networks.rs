@@ -0,0 +1,72 @@
+// ALLOW_PRIVATE_NETWORKS=false: loopback, private and link-local addresses refused,
+// for IP literals and for whatever a host name resolves to.
+
+mod support;
+
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+fn blocking(config: &mut sandbox_core::Config) {
+    config.allow_private_networks = false;
+}
+
+fn call(url: &str) -> String {
+    format!("const r = await httpRequest('{}'); [r.errorCode, r.data]", url)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_loopback_and_private_addresses() {
+    let app = TestApp::with_config(blocking).await;
+    app.upstream.mock("GET", "/admin", MockResponse::json(200, json!("secret")));
+    let admin = app.upstream.url("/admin");
+    let cases = [
+        (admin.clone(), "127.0.0.1"),
+        ("http://10.1.2.3/".to_string(), "10.1.2.3"),
+        ("http://192.168.0.1/".to_string(), "192.168.0.1"),
+        ("http://172.16.5.4/".to_string(), "172.16.5.4"),
+        (admin.replace("127.0.0.1", "[::1]"), "::1"),
+        // An IPv4-mapped IPv6 address is the IPv4 address it stands for
+        (admin.replace("127.0.0.1", "[::ffff:127.0.0.1]"), "127.0.0.1"),
+    ];
+    for (url, ip) in cases {
+        let result = app.result(&call(&url), json!({})).await;
+        assert_eq!(result[0], "blocked_by_policy", "{}", url);
+        assert_eq!(result[1], format!("Request blocked: {} is a private or loopback address", ip), "{}", url);
+    }
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_host_names_resolving_to_private_addresses() {
+    let app = TestApp::with_config(|config| {
+        blocking(config);
+        // A name with a public record next to the private one is refused all the same
+        config.dns_overrides = vec!["mixed.test=203.0.113.7".to_string(), "mixed.test=127.0.0.1".to_string()];
+    })
+    .await;
+    app.upstream.mock("GET", "/admin", MockResponse::json(200, json!("secret")));
+    for host in ["localhost", "mixed.test"] {
+        let url = app.upstream.url("/admin").replace("127.0.0.1", host);
+        let result = app.result(&call(&url), json!({})).await;
+        assert_eq!(result[0], "blocked_by_policy", "{}", result);
+        assert!(result[1].as_str().unwrap().contains(&format!("{} resolves to private or loopback address", host)), "{}", result);
+    }
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reaches_private_addresses_when_allowed() {
+    let app = TestApp::with_config(|config| config.allow_private_networks = true).await;
+    app.upstream.mock("GET", "/admin", MockResponse::json(200, json!("internal")));
+    let url = app.upstream.url("/admin");
+    let result = app.result(&format!("(await httpRequest('{}')).data", url), json!({})).await;
+    assert_eq!(result, "internal");
+    let result = app.result(&format!("(await httpRequest('{}')).data", url.replace("127.0.0.1", "localhost")), json!({})).await;
+    assert_eq!(result, "internal");
+
+    // The cloud metadata address stays blocked
+    let result = app.result(&call("http://169.254.169.254/latest/meta-data/"), json!({})).await;
+    assert_eq!(result, json!(["blocked_by_policy", "Request blocked: 169.254.169.254 is a cloud metadata address"]));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
@@ -0,0 +1,58 @@
+// Promises returned as the result of the code, awaited before serialization.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_value_a_promise_resolves_to() {
+    let app = TestApp::start().await;
+    let cases = [
+        ("Promise.resolve(42)", json!(42)),
+        ("new Promise((resolve) => setTimeout(() => resolve({ ok: true }), 10))", json!({ "ok": true })),
+        // A promise resolving to a promise resolving to a thenable
+        (
+            "Promise.resolve(new Promise((resolve) => resolve({ then: (done) => done([1, 2]) })))",
+            json!([1, 2]),
+        ),
+        ("({ then(resolve) { resolve('thenable') } })", json!("thenable")),
+        ("const pending = (async () => INPUTS.x * 2)(); pending", json!(14)),
+    ];
+    for (code, expected) in cases {
+        let (status, body) = app.exec(code, json!({ "x": 7 })).await;
+        assert_eq!(status, StatusCode::OK, "{}: {}", code, body);
+        assert_eq!(body["result"], expected, "{}", code);
+    }
+
+    let result = app.result_of(json!({ "code": "export default Promise.resolve('module')", "module": true })).await;
+    assert_eq!(result, "module");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_with_the_reason_of_a_rejected_promise() {
+    let app = TestApp::start().await;
+    let code = "class QuotaError extends Error { constructor() { super('over quota'); this.name = 'QuotaError'; this.limit = 5; } }
+        Promise.reject(new QuotaError())";
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "RuntimeError");
+    assert_eq!(body["message"], "Promise resolution error: QuotaError: over quota");
+    assert_eq!(body["jsError"]["properties"], json!({ "limit": 5 }));
+    // It is the execution's error, not an unhandled rejection
+    assert!(body.get("unhandledRejections").is_none(), "{}", body);
+
+    let (status, body) = app.exec("Promise.reject({ code: 'DENIED' })", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["thrown"], json!({ "code": "DENIED" }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_out_waiting_for_a_promise_that_never_settles() {
+    let app = TestApp::with_config(|config| config.execution_timeout_ms = 300).await;
+    let (status, body) = app.exec("new Promise(() => {})", json!({})).await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT, "{}", body);
+    assert_eq!(body["message"], "Execution exceeded the timeout of 300 ms");
+}
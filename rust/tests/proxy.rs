@@ -0,0 +1,69 @@
+// OUTBOUND_HTTP_PROXY, OUTBOUND_HTTPS_PROXY and OUTBOUND_NO_PROXY: requests
+// routed through an egress proxy, here a mock upstream that records what reaches it.
+
+mod support;
+
+use axum::http::Method;
+use serde_json::json;
+
+use support::{MockResponse, MockUpstream, TestApp};
+
+async fn behind_proxy(no_proxy: &[&str]) -> (TestApp, MockUpstream) {
+    let proxy = MockUpstream::start().await;
+    let url = proxy.url("");
+    let no_proxy: Vec<String> = no_proxy.iter().map(|rule| rule.to_string()).collect();
+    let app = TestApp::with_config(|config| {
+        config.outbound_http_proxy = url.clone();
+        config.outbound_https_proxy = url;
+        config.outbound_no_proxy = no_proxy;
+        config.dns_overrides = vec!["svc.internal.test=127.0.0.1".to_string(), "svc.notinternal.test=127.0.0.1".to_string()];
+    })
+    .await;
+    (app, proxy)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_http_requests_to_the_proxy_in_absolute_form() {
+    let (app, proxy) = behind_proxy(&[]).await;
+    proxy.mock("GET", "/rates", MockResponse::json(200, json!({ "eur": 0.92 })));
+    let result = app.result("(await httpRequest('http://api.example/rates?day=1')).data", json!({})).await;
+    assert_eq!(result, json!({ "eur": 0.92 }));
+    let requests = proxy.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].uri, "http://api.example/rates?day=1");
+    assert_eq!(requests[0].headers["host"], "api.example");
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tunnels_https_requests_through_connect() {
+    let (app, proxy) = behind_proxy(&[]).await;
+    // The mock refuses the tunnel, so the request fails after the CONNECT
+    let result = app.result("(await httpRequest('https://secure.example/account')).ok", json!({})).await;
+    assert_eq!(result, json!(false));
+    let requests = proxy.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, Method::CONNECT);
+    assert_eq!(requests[0].uri, "secure.example:443");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bypasses_the_proxy_for_no_proxy_hosts() {
+    let (app, proxy) = behind_proxy(&["internal.test", "127.0.0.0/8"]).await;
+    app.upstream.mock("GET", "/ping", MockResponse::json(200, json!("direct")));
+    proxy.mock("GET", "/ping", MockResponse::json(200, json!("proxied")));
+    let port = app.upstream.url("").rsplit(':').next().unwrap().to_string();
+    let code = format!(
+        "const get = async (host) => (await httpRequest(`http://${{host}}:{}/ping`)).data;
+        [await get('svc.internal.test'), await get('127.0.0.1'), await get('svc.notinternal.test'), await get('api.example')]",
+        port
+    );
+    // A suffix matches the domain and its subdomains only, a CIDR range addresses in it
+    assert_eq!(app.result(&code, json!({})).await, json!(["direct", "direct", "proxied", "proxied"]));
+    let proxied: Vec<String> = proxy.requests().into_iter().map(|request| request.uri).collect();
+    assert_eq!(
+        proxied,
+        [format!("http://svc.notinternal.test:{}/ping", port), format!("http://api.example:{}/ping", port)]
+    );
+    assert_eq!(app.upstream.requests().len(), 2);
+}
@@ -0,0 +1,184 @@
+// QUOTA_DAILY_* and QUOTA_MONTHLY_*: what every API key may use per day and month,
+// and GET /usage.
+
+mod support;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use support::{MockResponse, TestApp};
+
+fn execute_as(key: &str, code: &str) -> Request<Body> {
+    Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("x-api-key", key)
+        .body(Body::from(json!({ "code": code, "include_meta": true }).to_string()))
+        .unwrap()
+}
+
+fn usage_as(key: &str) -> Request<Body> {
+    Request::get("/usage").header("x-api-key", key).body(Body::empty()).unwrap()
+}
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+// 2026-10-14T23:59:59Z plus `secs`
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_792_022_399 + secs)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_executions_once_the_budget_is_used_up() {
+    let app = TestApp::with_config(|config| config.quota_daily_executions = 2).await;
+    app.quotas().set_clock(|| at(0));
+    for _ in 0..2 {
+        let (status, body) = app.send(execute_as("key-a", "1 + 1")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let response = app.response(execute_as("key-a", "1 + 1")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "1");
+    let (status, body) = app.send(execute_as("key-a", "1 + 1")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"], "quota_exceeded");
+    assert_eq!(
+        body["quota"],
+        json!({ "budget": "daily_executions", "limit": 2, "used": 2, "period": "2026-10-14", "resetsAt": "2026-10-15T00:00:00Z" })
+    );
+    assert_eq!(
+        body["message"],
+        "This API key used up its daily_executions quota of 2 for 2026-10-14, which resets at 2026-10-15T00:00:00Z"
+    );
+
+    // Other keys have budgets of their own, and requests without a key aren't counted
+    let (status, _) = app.send(execute_as("key-b", "1 + 1")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.exec("1 + 1", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Executions that didn't get to run don't count
+    let (status, _) = app.send(execute_as("key-b", "this is not javascript")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (_, usage) = app.send(usage_as("key-b")).await;
+    assert_eq!(usage["keys"][0]["daily"]["executions"], 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_executions_do_not_get_past_the_budget() {
+    let app = TestApp::with_config(|config| config.quota_monthly_executions = 3).await;
+    let executions = (0..10).map(|_| app.send(execute_as("key-a", "await sleep(20); 1")));
+    let statuses: Vec<StatusCode> = futures::future::join_all(executions).await.into_iter().map(|(status, _)| status).collect();
+    assert_eq!(statuses.iter().filter(|&&status| status == StatusCode::OK).count(), 3, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|&&status| status == StatusCode::TOO_MANY_REQUESTS).count(), 7);
+
+    let (_, usage) = app.send(usage_as("key-a")).await;
+    assert_eq!(usage["keys"][0]["monthly"]["executions"], 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_new_day_resets_the_daily_budget_but_not_the_monthly_one() {
+    let app = TestApp::with_config(|config| {
+        config.quota_daily_executions = 1;
+        config.quota_monthly_executions = 3;
+    })
+    .await;
+    app.quotas().set_clock(|| at(0));
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+    let (status, body) = app.send(execute_as("key-a", "1")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["quota"]["budget"], "daily_executions");
+
+    // One second later it is 2026-10-15
+    app.quotas().set_clock(|| at(1));
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+    let (_, usage) = app.send(usage_as("key-a")).await;
+    let report = &usage["keys"][0];
+    assert_eq!((&report["daily"]["period"], &report["daily"]["executions"]), (&json!("2026-10-15"), &json!(1)));
+    assert_eq!((&report["monthly"]["period"], &report["monthly"]["executions"]), (&json!("2026-10"), &json!(2)));
+    assert_eq!(report["monthly"]["resetsAt"], "2026-11-01T00:00:00Z");
+
+    app.quotas().set_clock(|| at(SECONDS_PER_DAY + 1));
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+    app.quotas().set_clock(|| at(2 * SECONDS_PER_DAY + 1));
+    let (status, body) = app.send(execute_as("key-a", "1")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["quota"]["budget"], "monthly_executions");
+    assert_eq!(body["quota"]["resetsAt"], "2026-11-01T00:00:00Z");
+
+    // Until November
+    app.quotas().set_clock(|| at(17 * SECONDS_PER_DAY + 1));
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_usage_of_the_key_or_of_every_key_to_admins() {
+    let app = TestApp::with_config(|config| {
+        config.quota_daily_fetched_bytes = 1000;
+        config.admin_api_keys = vec!["admin-key".to_string()];
+    })
+    .await;
+    app.upstream.mock("GET", "/big", MockResponse::text(200, &"x".repeat(800)));
+    let code = format!(
+        "let x = 0; for (let i = 0; i < 300000; i++) x += i; (await httpRequest('{}')).data.length",
+        app.upstream.url("/big")
+    );
+    let mut cpu_ms = 0;
+    for _ in 0..2 {
+        let (status, body) = app.send(execute_as("key-a", &code)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["meta"]["usage"]["fetchedBytes"], 800);
+        cpu_ms += body["meta"]["usage"]["cpuMs"].as_u64().unwrap();
+    }
+    // The second went over the budget, so the third is refused
+    let (status, body) = app.send(execute_as("key-a", &code)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!((&body["quota"]["budget"], &body["quota"]["used"]), (&json!("daily_fetched_bytes"), &json!(1600)));
+    assert_eq!(app.send(execute_as("key-b", "1")).await.0, StatusCode::OK);
+
+    let (status, usage) = app.send(usage_as("key-a")).await;
+    assert_eq!(status, StatusCode::OK, "{}", usage);
+    let keys = usage["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    let daily = &keys[0]["daily"];
+    assert_eq!((&daily["executions"], &daily["fetchedBytes"]), (&json!(2), &json!(1600)));
+    // Summed in microseconds, so at least the sum of the rounded down figures
+    let used_cpu_ms = daily["cpuMs"].as_u64().unwrap();
+    assert!(used_cpu_ms >= cpu_ms && used_cpu_ms <= cpu_ms + 2, "{} {}", used_cpu_ms, cpu_ms);
+    assert_eq!(daily["limits"], json!({ "executions": null, "cpuMs": null, "fetchedBytes": 1000 }));
+    assert_eq!(keys[0]["monthly"]["fetchedBytes"], 1600);
+    assert_eq!(keys[0]["apiKeyId"].as_str().unwrap().len(), 32);
+
+    let (_, usage) = app.send(usage_as("admin-key")).await;
+    assert_eq!(usage["keys"].as_array().unwrap().len(), 2, "{}", usage);
+    let (_, usage) = app.send(usage_as("key-c")).await;
+    assert_eq!(usage["keys"][0]["daily"]["executions"], 0);
+
+    let (status, body) = app.get("/usage").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "API key required");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_counts_across_restarts() {
+    let path = std::env::temp_dir().join(format!("sandbox-quotas-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let quota_path = path.to_string_lossy().into_owned();
+    let configure = |config: &mut sandbox_core::Config| {
+        config.quota_monthly_executions = 2;
+        config.quota_path = quota_path.clone();
+    };
+
+    let app = TestApp::with_config(configure).await;
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+    drop(app);
+
+    let app = TestApp::with_config(configure).await;
+    let (_, usage): (_, Value) = app.send(usage_as("key-a")).await;
+    assert_eq!(usage["keys"][0]["monthly"]["executions"], 1);
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::OK);
+    assert_eq!(app.send(execute_as("key-a", "1")).await.0, StatusCode::TOO_MANY_REQUESTS);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,38 @@
+// `random_seed`: Math.random() sequences that can be replayed.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+const SEQUENCE: &str = "Array.from({ length: 5 }, () => Math.random())";
+
+async fn seeded(app: &TestApp, seed: Option<u32>) -> Value {
+    let (_, body) = app.post("/execute", json!({ "code": SEQUENCE, "random_seed": seed, "include_meta": true })).await;
+    body
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repeats_the_sequence_of_a_seed() {
+    let app = TestApp::start().await;
+    let first = seeded(&app, Some(42)).await;
+    let again = seeded(&app, Some(42)).await;
+    assert_eq!(first["result"], again["result"]);
+    assert_eq!(first["meta"]["randomSeed"], 42);
+    let values = first["result"].as_array().unwrap();
+    assert!(values.iter().all(|v| (0.0..1.0).contains(&v.as_f64().unwrap())), "{}", first);
+    assert_ne!(values[0], values[1]);
+
+    assert_ne!(seeded(&app, Some(43)).await["result"], first["result"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_an_execution_from_the_reported_seed() {
+    let app = TestApp::start().await;
+    let first = seeded(&app, None).await;
+    let seed = first["meta"]["randomSeed"].as_u64().unwrap();
+    let replayed = seeded(&app, Some(seed as u32)).await;
+    assert_eq!(replayed["result"], first["result"]);
+    assert_ne!(seeded(&app, None).await["result"], first["result"]);
+}
@@ -0,0 +1,130 @@
+// RATE_LIMIT_ENABLED: token buckets per API key and per client address.
+
+mod support;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use sandbox_core::Config;
+use support::TestApp;
+
+async fn limited(configure: impl FnOnce(&mut Config)) -> TestApp {
+    TestApp::with_config(|config| {
+        config.rate_limit_enabled = true;
+        configure(config);
+    })
+    .await
+}
+
+async fn execute(app: &TestApp, headers: &[(&str, &str)]) -> Response {
+    let mut request = Request::post("/execute").header("content-type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    app.response(request.body(Body::from(json!({ "code": "1" }).to_string())).unwrap()).await
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn limits_an_api_key_to_its_burst() {
+    let app = limited(|config| {
+        config.rate_limit_api_key_per_second = 0.5;
+        config.rate_limit_api_key_burst = 2.0;
+    })
+    .await;
+    let key = [("x-api-key", "key-a")];
+
+    let first = execute(&app, &key).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(header(&first, "x-ratelimit-limit"), "2");
+    assert_eq!(header(&first, "x-ratelimit-remaining"), "1");
+    assert_eq!(header(&first, "x-ratelimit-reset"), "2");
+    let second = execute(&app, &key).await;
+    assert_eq!(header(&second, "x-ratelimit-remaining"), "0");
+
+    let refused = execute(&app, &key).await;
+    assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&refused, "retry-after"), "2");
+    assert_eq!(header(&refused, "x-ratelimit-remaining"), "0");
+    let body: Value = serde_json::from_slice(&to_bytes(refused.into_body(), usize::MAX).await.unwrap()).unwrap();
+    assert_eq!(body["error"], "Rate limit exceeded");
+    assert_eq!(body["message"], "At most 0.5 requests per second with bursts of 2 are allowed");
+
+    // Other keys have buckets of their own, and health checks aren't limited
+    assert_eq!(execute(&app, &[("x-api-key", "key-b")]).await.status(), StatusCode::OK);
+    assert_eq!(app.get("/livez").await.0, StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_clients_by_the_address_the_proxy_saw() {
+    let app = limited(|config| {
+        config.rate_limit_ip_per_second = 1.0;
+        config.rate_limit_ip_burst = 1.0;
+        config.trust_proxy = true;
+    })
+    .await;
+    let client = [("x-forwarded-for", "203.0.113.9, 198.51.100.1")];
+    assert_eq!(execute(&app, &client).await.status(), StatusCode::OK);
+    // The first entry is whatever the client claimed
+    let spoofed = [("x-forwarded-for", "203.0.113.10, 198.51.100.1")];
+    assert_eq!(execute(&app, &spoofed).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(execute(&app, &[("x-forwarded-for", "198.51.100.2")]).await.status(), StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_clients_by_their_socket_address() {
+    let app = limited(|config| {
+        config.rate_limit_ip_burst = 1.0;
+        config.rate_limit_ip_per_second = 1.0;
+    })
+    .await;
+    let address = app.serve().await;
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET /functions HTTP/1.1\r\nHost: {}\r\nX-Forwarded-For: 192.0.2.{}\r\nConnection: close\r\n\r\n", address, statuses.len());
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        statuses.push(response.split(' ').nth(1).unwrap().to_string());
+    }
+    // Without TRUST_PROXY, X-Forwarded-For changes nothing
+    assert_eq!(statuses, ["200", "429"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_wait_of_a_very_slow_rate() {
+    let app = limited(|config| {
+        config.rate_limit_api_key_per_second = 1e-300;
+        config.rate_limit_api_key_burst = 1.0;
+    })
+    .await;
+    let key = [("x-api-key", "slow")];
+    assert_eq!(header(&execute(&app, &key).await, "x-ratelimit-reset"), "86400");
+    let refused = execute(&app, &key).await;
+    assert_eq!(refused.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&refused, "retry-after"), "86400");
+}
+
+#[test]
+fn refuses_rates_that_arent_positive() {
+    for (line, message) in [
+        ("rate_limit_ip_per_second = 0.0", "Invalid RATE_LIMIT_IP_PER_SECOND: expected a positive number, got 0"),
+        ("rate_limit_api_key_per_second = -1.0", "Invalid RATE_LIMIT_API_KEY_PER_SECOND: expected a positive number, got -1"),
+        ("rate_limit_ip_burst = nan", "Invalid RATE_LIMIT_IP_BURST: expected a positive number, got NaN"),
+    ] {
+        let path = std::env::temp_dir().join(format!("sandbox-rate-limit-{}.toml", std::process::id()));
+        std::fs::write(&path, line).unwrap();
+        let config = Config::load(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.err().as_deref(), Some(message));
+    }
+}
@@ -0,0 +1,95 @@
+// /readyz: not ready while warming up, once shutdown started draining, and when
+// executions have been waiting for a slot for longer than READINESS_SATURATION_WINDOW_MS.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_ready_once_warmed_up() {
+    let app = TestApp::with_config(|config| config.js_runtime_pool_size = 2).await;
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["condition"], json!("warming_up"));
+    let (_, version) = app.get("/version").await;
+    assert_eq!(version["warmUpMs"], Value::Null);
+
+    app.warm_up(TIMEOUT).await;
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["condition"], Value::Null);
+    let (_, version) = app.get("/version").await;
+    assert!(version["warmUpMs"].is_u64(), "{}", version);
+    let (_, metrics) = app.get("/metrics").await;
+    assert!(metrics.as_str().unwrap().contains("\njsexec_warm_up_seconds "), "{}", metrics);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stays_not_ready_when_warming_up_fails() {
+    // Too little heap to evaluate the prelude
+    let app = TestApp::with_config(|config| config.js_max_memory_bytes = 64 * 1024).await;
+    app.warm_up(TIMEOUT).await;
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["condition"], json!("warm_up_failed"));
+    assert!(body["message"].as_str().unwrap().starts_with("Warming up failed: "), "{}", body);
+    let (_, version) = app.get("/version").await;
+    assert_eq!(version["warmUpMs"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_draining_once_shutdown_started() {
+    let app = TestApp::start().await;
+    app.warm_up(TIMEOUT).await;
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    app.start_draining();
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["condition"], json!("draining"));
+    assert_eq!(body["message"], json!("The server is shutting down"));
+    // Executions that are still sent get to run
+    let (status, body) = app.exec("1 + 1", json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_saturated_once_executions_waited_for_the_window() {
+    let app = TestApp::with_config(|config| {
+        config.max_concurrent_executions = 1;
+        config.readiness_saturation_window_ms = 300;
+    })
+    .await;
+    app.warm_up(TIMEOUT).await;
+
+    let probes = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // One execution runs and one waits, but not for long yet
+        let (status, body) = app.get("/readyz").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["executions"], json!({ "inFlight": 1, "queued": 1, "maxConcurrent": 1 }));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (status, body) = app.get("/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        assert_eq!(body["condition"], json!("saturated"));
+        let message = body["message"].as_str().unwrap();
+        assert!(message.starts_with("All execution slots have been busy with executions waiting for "), "{}", body);
+    };
+    let slow = "await sleep(1500); 'done'";
+    let (first, second, ()) = tokio::join!(app.exec(slow, json!({})), app.exec(slow, json!({})), probes);
+    assert_eq!(first.0, StatusCode::OK, "{}", first.1);
+    assert_eq!(second.0, StatusCode::OK, "{}", second.1);
+
+    // Ready again once nothing waits
+    let (status, body) = app.get("/readyz").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["condition"], Value::Null);
+}
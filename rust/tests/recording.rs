@@ -0,0 +1,157 @@
+// record_http and replay_http: an execution's HTTP responses, kept and played back.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_a_recorded_execution() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/users/1", MockResponse::json(200, json!({ "name": "Ada" })));
+    app.upstream.mock("POST", "/audit", MockResponse::json(201, json!({ "logged": true })));
+    let code = format!(
+        "const user = await httpRequest('{0}/users/1');
+        const audit = await httpRequest('{0}/audit', {{ method: 'POST', body: JSON.stringify({{ name: user.data.name }}) }});
+        const again = await httpRequest('{0}/users/1');
+        [user.data.name, audit.status, again.data.name]",
+        app.upstream.url("")
+    );
+    let (status, recorded) = app.post("/execute", json!({ "code": code, "record_http": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", recorded);
+    let trace = recorded["httpTrace"].as_object().unwrap();
+    assert_eq!(trace.len(), 3, "{}", recorded);
+    assert!(trace.contains_key(&format!("GET {}", app.upstream.url("/users/1"))));
+    assert!(trace.contains_key(&format!("GET {} #2", app.upstream.url("/users/1"))));
+    assert_eq!(app.upstream.requests().len(), 3);
+
+    let fresh = TestApp::start().await;
+    let (status, replayed) = fresh.post("/execute", json!({ "code": code, "replay_http": recorded["httpTrace"] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", replayed);
+    assert_eq!(replayed["result"], recorded["result"]);
+    assert_eq!(replayed["result"], json!(["Ada", 201, "Ada"]));
+    assert_eq!(app.upstream.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_requests_missing_from_the_replay() {
+    let app = TestApp::start().await;
+    let code = format!("try {{ await httpRequest('{}'); }} catch (e) {{ 'caught' }}", app.upstream.url("/other"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "replay_http": {} })).await;
+    assert_eq!(status, StatusCode::FAILED_DEPENDENCY, "{}", body);
+    assert_eq!(body["error"], "Unmatched request");
+    assert_eq!(body["message"], format!("replay_http has no response for GET {}", app.upstream.url("/other")));
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn masks_sensitive_headers_in_the_trace() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/login",
+        MockResponse::json(200, json!({ "ok": true })).with_header("set-cookie", "session=abc").with_header("x-request-id", "42"),
+    );
+    let code = format!("(await httpRequest('{}')).headers['set-cookie']", app.upstream.url("/login"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "record_http": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], "session=abc");
+    let response = &body["httpTrace"][format!("GET {}", app.upstream.url("/login"))];
+    assert_eq!(response["headers"]["set-cookie"], "***MASKED***");
+    assert_eq!(response["headers"]["x-request-id"], "42");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn returns_the_trace_of_failed_executions() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/items", MockResponse::json(200, json!({})));
+    let code = format!("(await httpRequest('{}')).data.items.map(item => item.id)", app.upstream.url("/items"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "record_http": true })).await;
+    assert_ne!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["httpTrace"].as_object().unwrap().len(), 1, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_the_recorded_responses_for_a_missed_request() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/token", MockResponse::json(200, json!({ "token": "t" })));
+    let url = app.upstream.url("/token");
+    let (_, recorded) = app.post("/execute", json!({ "code": format!("await httpRequest('{}')", url), "record_http": true })).await;
+
+    let code = format!("await httpRequest('{0}'); await httpRequest('{0}')", url);
+    let (status, body) = app.post("/execute", json!({ "code": code, "replay_http": recorded["httpTrace"] })).await;
+    assert_eq!(status, StatusCode::FAILED_DEPENDENCY, "{}", body);
+    assert_eq!(body["message"], format!("replay_http has no response for GET {0} #2; recorded: GET {0}", url));
+    assert_eq!(app.upstream.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_repeated_requests_in_call_order() {
+    let app = TestApp::start().await;
+    let counts = (1..=3).map(|count| MockResponse::json(200, json!({ "count": count }))).collect();
+    app.upstream.mock_sequence("GET", "/counter", counts);
+    let code = format!(
+        "const counts = [];
+        for (const _ of INPUTS.polls) counts.push((await httpRequest('{}')).data.count);
+        counts",
+        app.upstream.url("/counter")
+    );
+    let inputs = json!({ "polls": [1, 2, 3] });
+    let (status, recorded) = app.post("/execute", json!({ "code": code, "inputs": inputs, "record_http": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", recorded);
+    assert_eq!(recorded["result"], json!([1, 2, 3]));
+
+    let (status, replayed) =
+        app.post("/execute", json!({ "code": code, "inputs": inputs, "replay_http": recorded["httpTrace"] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", replayed);
+    assert_eq!(replayed["result"], json!([1, 2, 3]));
+
+    let code = format!(
+        "(await Promise.all(INPUTS.polls.map(() => httpRequest('{}')))).map(r => r.data.count)",
+        app.upstream.url("/counter")
+    );
+    let (_, replayed) = app.post("/execute", json!({ "code": code, "inputs": inputs, "replay_http": recorded["httpTrace"] })).await;
+    assert_eq!(replayed["result"], json!([1, 2, 3]));
+    assert_eq!(app.upstream.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_requests_however_their_options_are_written() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/items", MockResponse::json(200, json!(["a"])));
+    app.upstream.mock("POST", "/items", MockResponse::json(201, json!({ "id": 1 })));
+    app.upstream.mock("GET", "/search", MockResponse::json(200, json!({ "hits": 2 })));
+    let written = |calls: [&str; 3]| {
+        let url = app.upstream.url("");
+        format!(
+            "const a = await httpRequest('{0}/items'{1});
+            const b = await httpRequest('{0}/items', {2});
+            const c = await httpRequest('{0}/search', {3});
+            [a.data, b.data, c.data]",
+            url, calls[0], calls[1], calls[2]
+        )
+    };
+    let one = written([
+        "",
+        "{ method: 'POST', headers: { 'X-B': '2', 'X-A': '1' }, body: { order: { id: 1, lines: [{ sku: 'é', qty: 2.5 }] }, note: 'naïve ☃' } }",
+        "{ query: { q: 'grüße ☃', page: 1 } }",
+    ]);
+    let other = written([
+        ", {}",
+        "{ body: { note: 'naïve ☃', order: { lines: [{ qty: 2.5, sku: 'é' }], id: 1 } }, headers: { 'X-A': '1', 'X-B': '2' }, method: 'post' }",
+        "{ query: { page: 1, q: 'grüße ☃' } }",
+    ]);
+
+    let (_, first) = app.post("/execute", json!({ "code": one, "record_http": true })).await;
+    let (_, second) = app.post("/execute", json!({ "code": other, "record_http": true })).await;
+    let keys = |body: &serde_json::Value| body["httpTrace"].as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+    assert_eq!(keys(&first).len(), 3, "{}", first);
+    assert_eq!(keys(&first), keys(&second));
+
+    let (status, replayed) = app.post("/execute", json!({ "code": other, "replay_http": first["httpTrace"] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", replayed);
+    assert_eq!(replayed["result"], json!([["a"], { "id": 1 }, { "hits": 2 }]));
+    assert_eq!(app.upstream.requests().len(), 6);
+}
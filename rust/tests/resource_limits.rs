@@ -0,0 +1,135 @@
+// Per-request `limits`, which can tighten but not raise the server's.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::{MockResponse, TestApp};
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.max_requests_per_execution = 5;
+        config.max_result_bytes = 1000;
+        config.fetch_max_body_bytes = 1000;
+        config.js_max_memory_bytes = 64 * 1024 * 1024;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_limits_in_meta() {
+    let app = app().await;
+    let (status, body) = app.post("/execute", json!({ "code": "1", "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["meta"]["limits"],
+        json!({
+            "maxRequests": 5,
+            "maxResultBytes": 1000,
+            "maxFetchBodyBytes": 1000,
+            "maxFetchTotalBytes": 50 * 1024 * 1024,
+            "memoryBytes": 64 * 1024 * 1024,
+            "cpuMs": 30000,
+        })
+    );
+
+    let limits = json!({
+        "max_http_requests": 2, "max_result_bytes": 100, "max_fetch_body_bytes": 10, "memory_bytes": 1 << 24, "cpu_ms": 500,
+    });
+    let (status, body) = app.post("/execute", json!({ "code": "1", "limits": limits, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["meta"]["limits"],
+        json!({
+            "maxRequests": 2,
+            "maxResultBytes": 100,
+            "maxFetchBodyBytes": 10,
+            "maxFetchTotalBytes": 50 * 1024 * 1024,
+            "memoryBytes": 1 << 24,
+            "cpuMs": 500,
+        })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_limits_above_the_server_maximum() {
+    let app = app().await;
+    let cases = [
+        ("max_http_requests", json!(6), "limits.max_requests can't exceed the server's MAX_REQUESTS_PER_EXECUTION of 5, got 6"),
+        ("max_result_bytes", json!(1001), "limits.max_result_bytes can't exceed the server's MAX_RESULT_BYTES of 1000, got 1001"),
+        (
+            "max_fetch_body_bytes",
+            json!(2000),
+            "limits.max_fetch_body_bytes can't exceed the server's FETCH_MAX_BODY_BYTES of 1000, got 2000",
+        ),
+        (
+            "memory_bytes",
+            json!(128 * 1024 * 1024),
+            "limits.memory_bytes can't exceed the server's JS_MAX_MEMORY_BYTES of 67108864, got 134217728",
+        ),
+        ("cpu_ms", json!(30001), "limits.cpu_ms can't exceed the server's EXEC_CPU_MS of 30000, got 30001"),
+    ];
+    for (field, value, message) in cases {
+        let (status, body) = app.post("/execute", json!({ "code": "1", "limits": { field: value } })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid limits");
+        assert_eq!(body["message"], message);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_at_a_tighter_request_limit() {
+    let app = app().await;
+    app.upstream.mock("GET", "/ping", MockResponse::text(200, "pong"));
+    let code = format!("for (let i = 0; i < 3; i++) await httpRequest('{}'); 'done'", app.upstream.url("/ping"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "limits": { "max_http_requests": 2 } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Request limit exceeded");
+    assert_eq!(body["message"], "Execution attempted 3 outbound requests, exceeding the max_requests limit of 2");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_a_result_over_a_tighter_limit() {
+    let app = app().await;
+    let (status, body) = app.post("/execute", json!({ "code": "'x'.repeat(200)", "limits": { "max_result_bytes": 100 } })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+    assert_eq!(body["error"], "Result too large");
+    assert_eq!(body["message"], "Result is 202 bytes, exceeding the max_result_bytes limit of 100 bytes");
+
+    let (status, body) = app.post("/execute", json!({ "code": "'x'.repeat(2000)" })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+    assert_eq!(body["message"], "Result is 2002 bytes, exceeding the MAX_RESULT_BYTES limit of 1000 bytes");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_a_fetched_body_over_a_tighter_limit() {
+    let app = app().await;
+    app.upstream.mock("GET", "/big", MockResponse::text(200, &"x".repeat(500)));
+    let code = format!("const r = await httpRequest('{}'); [r.ok, r.errorCode, r.data]", app.upstream.url("/big"));
+    let (status, body) = app.post("/execute", json!({ "code": code, "limits": { "max_fetch_body_bytes": 100 } })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let message = format!(
+        "Fetch failed: response body from {} exceeds the max_fetch_body_bytes limit of 100 bytes",
+        app.upstream.url("/big")
+    );
+    assert_eq!(body["result"], json!([false, "body_too_large", message]));
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"][0], true, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_code_over_a_tighter_memory_limit() {
+    let app = app().await;
+    let code = "const chunks = []; for (let i = 0; i < 30; i++) chunks.push(new Array(100000).fill(i)); chunks.length";
+    let (status, body) = app.post("/execute", json!({ "code": code, "limits": { "memory_bytes": 8 * 1024 * 1024 } })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Memory limit exceeded");
+    assert_eq!(body["message"], "Execution exceeded the memory_bytes limit of 8388608 bytes");
+
+    // The runtime goes back to the pool with the server's limit
+    let result = app.result(code, json!({})).await;
+    assert_eq!(result, 30);
+}
@@ -0,0 +1,154 @@
+// `cache = { ttlSeconds }` on /execute, the cross-request result cache.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+async fn exec(app: &TestApp, code: &str, inputs: Value, ttl_seconds: u64) -> (StatusCode, Value) {
+    let body = json!({ "code": code, "inputs": inputs, "cache": { "ttlSeconds": ttl_seconds }, "include_meta": true });
+    app.post("/execute", body).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_an_identical_request_from_the_cache() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/rates", MockResponse::json(200, json!({ "eur": 0.92 })));
+    let code = format!("[Math.random(), (await httpRequest('{}')).data.eur * INPUTS.amount]", app.upstream.url("/rates"));
+
+    let (status, first) = exec(&app, &code, json!({ "amount": 100 }), 60).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    assert_eq!(first["meta"]["cacheHit"], json!(false));
+    let (status, second) = exec(&app, &code, json!({ "amount": 100 }), 60).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+    assert_eq!(second["result"], first["result"]);
+    assert_eq!(second["meta"]["cacheHit"], json!(true));
+    assert_eq!(second["meta"]["httpRequestCount"], json!(0));
+    assert_eq!(app.upstream.requests().len(), 1);
+
+    // Without `cache` the code runs as always
+    let (_, body) = app.post("/execute", json!({ "code": code, "inputs": { "amount": 100 }, "include_meta": true })).await;
+    assert_eq!(body["meta"]["cacheHit"], json!(false));
+    assert_ne!(body["result"][0], first["result"][0]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_the_code_again_once_the_entry_expired() {
+    let app = TestApp::start().await;
+    let (_, first) = exec(&app, "Math.random()", json!({}), 1).await;
+    let (_, cached) = exec(&app, "Math.random()", json!({}), 1).await;
+    assert_eq!(cached["result"], first["result"]);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let (status, body) = exec(&app, "Math.random()", json!({}), 1).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["cacheHit"], json!(false));
+    assert_ne!(body["result"], first["result"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_entries_by_the_inputs() {
+    let app = TestApp::start().await;
+    let code = "[Math.random(), INPUTS.a + INPUTS.b]";
+    let (_, first) = exec(&app, code, json!({ "a": 1, "b": 2 }), 60).await;
+
+    // The same inputs in another key order
+    let (_, body) = exec(&app, code, json!({ "b": 2, "a": 1 }), 60).await;
+    assert_eq!(body["meta"]["cacheHit"], json!(true));
+    assert_eq!(body["result"], first["result"]);
+
+    // Nested objects and unicode, in another key order too
+    let code = "[Math.random(), INPUTS.user.name]";
+    let (_, first) = exec(&app, code, json!({ "user": { "name": "Zoë ☃", "tags": { "x": 1, "y": [2.5] } } }), 60).await;
+    let (_, body) = exec(&app, code, json!({ "user": { "tags": { "y": [2.5], "x": 1 }, "name": "Zoë ☃" } }), 60).await;
+    assert_eq!(body["meta"]["cacheHit"], json!(true));
+    assert_eq!(body["result"], first["result"]);
+
+    let code = "[Math.random(), INPUTS.a + INPUTS.b]";
+    let (_, body) = exec(&app, code, json!({ "a": 1, "b": 3 }), 60).await;
+    assert_eq!(body["meta"]["cacheHit"], json!(false));
+    assert_eq!(body["result"][1], json!(4));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn never_caches_side_effects_or_errors() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/orders", MockResponse::json(201, json!({ "id": 7 })));
+    app.upstream.mock("GET", "/orders", MockResponse::json(200, json!([])));
+    let orders = app.upstream.url("/orders");
+    let cases = [
+        format!("(await httpRequest('{}', {{ method: 'POST', body: '{{}}' }})).data.id", orders),
+        format!("await httpRequest('{}'); throw new Error('no orders')", orders),
+        "state.set('ran', Date.now()); 1".to_string(),
+    ];
+    for code in &cases {
+        for _ in 0..2 {
+            let (_, body) = exec(&app, code, json!({}), 60).await;
+            assert_eq!(body["meta"]["cacheHit"], json!(false), "{}: {}", code, body);
+        }
+    }
+    let requests = app.upstream.requests();
+    assert_eq!(requests.iter().filter(|request| request.method == "POST").count(), 2);
+    assert_eq!(requests.iter().filter(|request| request.method == "GET").count(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_ttls_over_the_maximum() {
+    let app = TestApp::with_config(|config| config.result_cache_max_ttl_seconds = 3600).await;
+    for ttl_seconds in [3601, u64::MAX] {
+        let (status, body) = exec(&app, "1", json!({}), ttl_seconds).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid cache");
+        assert_eq!(body["message"], format!("cache.ttlSeconds can be at most 3600, got {}", ttl_seconds));
+    }
+    let (status, body) = exec(&app, "1", json!({}), 3600).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_state_namespaces_apart() {
+    let app = TestApp::start().await;
+    let with_key = |key: &str, code: &str| {
+        let body = json!({ "code": code, "state_namespace": "wallet", "cache": { "ttlSeconds": 60 }, "include_meta": true });
+        Request::post("/execute")
+            .header("content-type", "application/json")
+            .header("x-api-key", key)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    app.send(with_key("key-1", "state.set('balance', 100); 'saved'")).await;
+    let (_, first) = app.send(with_key("key-1", "state.get('balance') ?? null")).await;
+    assert_eq!(first["result"], json!(100), "{}", first);
+    let (_, again) = app.send(with_key("key-1", "state.get('balance') ?? null")).await;
+    assert_eq!(again["meta"]["cacheHit"], json!(true));
+
+    // Reading state changes nothing, but another key's read is another entry
+    let (_, other) = app.send(with_key("key-2", "state.get('balance') ?? null")).await;
+    assert_eq!(other["meta"]["cacheHit"], json!(false));
+    assert_eq!(other["result"], json!(null));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_entries_by_the_seed_and_the_clock() {
+    let app = &TestApp::start().await;
+    let run = |settings: Value| {
+        let mut body = json!({ "code": "[Math.random(), Date.now()]", "cache": { "ttlSeconds": 60 }, "include_meta": true });
+        body.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+        async move { app.post("/execute", body).await.1 }
+    };
+    let seeded = run(json!({ "random_seed": 1 })).await;
+    assert_eq!(run(json!({ "random_seed": 1 })).await["meta"]["cacheHit"], json!(true));
+    let other_seed = run(json!({ "random_seed": 2 })).await;
+    assert_eq!(other_seed["meta"]["cacheHit"], json!(false));
+    assert_ne!(other_seed["result"][0], seeded["result"][0]);
+
+    let frozen = run(json!({ "random_seed": 1, "freeze_time": true })).await;
+    assert_eq!(frozen["meta"]["cacheHit"], json!(false));
+    assert_eq!(frozen["result"][0], seeded["result"][0]);
+    assert_eq!(run(json!({ "random_seed": 1, "freeze_time": true })).await["meta"]["cacheHit"], json!(true));
+}
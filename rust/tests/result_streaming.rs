@@ -0,0 +1,121 @@
+// Results streamed as NDJSON: asked for with `stream_result` or above
+// STREAM_RESULT_BYTES, in chunks, with a final record for the rest.
+
+mod support;
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, Request, StatusCode};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+// The response's chunks, its content type and whether it declared a length
+async fn stream(app: &TestApp, body: Value, accept: &str) -> (StatusCode, Option<String>, bool, Vec<Bytes>) {
+    let request = Request::post("/execute")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT, accept)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.response(request).await;
+    let status = response.status();
+    let content_type = response.headers().get(header::CONTENT_TYPE).map(|value| value.to_str().unwrap().to_string());
+    let sized = response.headers().contains_key(header::CONTENT_LENGTH);
+    let chunks = response.into_body().into_data_stream().map(|chunk| chunk.unwrap()).collect().await;
+    (status, content_type, sized, chunks)
+}
+
+fn records(chunks: &[Bytes]) -> Vec<Value> {
+    let body: Vec<u8> = chunks.concat();
+    body.split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_a_large_result_in_chunks() {
+    let app = TestApp::with_config(|config| config.max_result_bytes = 32 * 1024 * 1024).await;
+    let code = "Array.from({ length: 22000 }, (_, i) => ({ id: i, name: 'record ' + i, pad: String(i).repeat(1000).slice(0, 1000) }))";
+    let request = json!({ "code": code, "stream_result": true, "include_meta": true });
+    let (status, content_type, sized, chunks) = stream(&app, request, "application/x-ndjson").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/x-ndjson"));
+    assert!(!sized);
+    let bytes: usize = chunks.iter().map(Bytes::len).sum();
+    assert!(bytes > 20 * 1024 * 1024, "{}", bytes);
+    assert!(chunks.len() > 300, "{}", chunks.len());
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 64 * 1024));
+
+    let records = records(&chunks);
+    assert_eq!(records.len(), 2);
+    let result = records[0]["result"].as_array().unwrap();
+    assert_eq!(result.len(), 22_000);
+    assert_eq!(result[21_999]["name"], "record 21999");
+    let end = &records[1];
+    assert_eq!((&end["done"], &end["ok"], &end["status"]), (&json!(true), &json!(true), &json!(200)));
+    let result_bytes = end["meta"]["resultBytes"].as_u64().unwrap();
+    assert!(result_bytes > 20 * 1024 * 1024, "{}", end);
+    assert_eq!(end["meta"]["usage"]["resultBytes"], result_bytes);
+    // The engine's heap holds the result and its JSON text, and nothing on the way
+    // out makes it grow further
+    let heap_peak = end["meta"]["usage"]["heapPeakBytes"].as_u64().unwrap();
+    assert!(heap_peak < 3 * result_bytes, "{}", end);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ends_the_stream_with_an_error_record() {
+    let app = TestApp::start().await;
+    let request = json!({ "code": "throw new RangeError('too far')", "stream_result": true });
+    let (status, content_type, _, chunks) = stream(&app, request, "application/x-ndjson").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/x-ndjson"));
+    let records = records(&chunks);
+    assert_eq!(records.len(), 1, "{:?}", records);
+    let end = &records[0];
+    assert_eq!((&end["done"], &end["ok"], &end["status"]), (&json!(true), &json!(false), &json!(400)));
+    assert_eq!(end["error"], "RuntimeError");
+    assert_eq!(end["jsError"]["name"], "RangeError");
+
+    // Without the Accept header the request is answered as usual
+    let (status, content_type, _, chunks) = stream(&app, json!({ "code": "[1, 2]", "stream_result": true }), "*/*").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/json"));
+    assert_eq!(serde_json::from_slice::<Value>(&chunks.concat()).unwrap()["result"], json!([1, 2]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_results_above_the_threshold() {
+    let app = TestApp::with_config(|config| config.stream_result_bytes = 1024 * 1024).await;
+    let (status, body) = app.exec("({ small: true })", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["result"], json!({ "small": true }));
+
+    // Secrets are redacted from the JSON text too, as JSON escapes them
+    let request = json!({
+        "code": "({ token: SECRETS.token, quoted: SECRETS.quoted, pad: 'x'.repeat(2 * 1024 * 1024), n: 12 })",
+        "secrets": { "token": "s3cr3t", "quoted": "a\"b\\c", "number": "12" },
+    });
+    let (status, content_type, _, chunks) = stream(&app, request, "application/json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/x-ndjson"));
+    let redacted = records(&chunks);
+    assert_eq!(redacted[0]["result"]["token"], "***REDACTED***");
+    assert_eq!(redacted[0]["result"]["quoted"], "***REDACTED***");
+    assert_eq!(redacted[0]["result"]["n"], 12);
+    assert_eq!(redacted[1]["ok"], true);
+
+    // The output schema needs the parsed result, which is only streamed when asked to
+    let request = json!({ "code": "'x'.repeat(2 * 1024 * 1024)", "output_schema": { "type": "string" } });
+    let (_, content_type, _, _) = stream(&app, request.clone(), "application/json").await;
+    assert_eq!(content_type.as_deref(), Some("application/json"));
+    let mut request = request;
+    request["stream_result"] = json!(true);
+    let (_, content_type, _, chunks) = stream(&app, request, "application/x-ndjson").await;
+    assert_eq!(content_type.as_deref(), Some("application/x-ndjson"));
+    assert_eq!(records(&chunks)[0]["result"].as_str().unwrap().len(), 2 * 1024 * 1024);
+
+    let (status, body) = app.exec("throw new Error('nope')", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "RuntimeError");
+}
@@ -0,0 +1,50 @@
+// `options.retry`: failed attempts sent again with backoff, up to FETCH_MAX_ATTEMPTS.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+// `[status, data]` of a GET with the retry option
+async fn get(app: &TestApp, retry: &str) -> Value {
+    let code = format!("const r = await httpRequest('{}', {{ retry: {} }}); [r.status, r.data]", app.upstream.url("/flaky"), retry);
+    app.result(&code, json!({})).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_a_server_error_until_the_attempts_run_out() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/flaky", MockResponse::json(503, json!("busy")));
+    assert_eq!(get(&app, "{ attempts: 3, backoffMs: 1 }").await, json!([503, "busy"]));
+    assert_eq!(app.upstream.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_retrying_once_an_attempt_succeeds() {
+    let app = TestApp::start().await;
+    let responses = vec![MockResponse::json(502, json!("bad gateway")), MockResponse::json(200, json!("ok"))];
+    app.upstream.mock_sequence("GET", "/flaky", responses);
+    assert_eq!(get(&app, "{ attempts: 4, backoffMs: 1 }").await, json!([200, "ok"]));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_only_the_listed_statuses() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/flaky", MockResponse::json(500, json!("boom")));
+    assert_eq!(get(&app, "{ attempts: 3, backoffMs: 1 }").await, json!([500, "boom"]));
+    assert_eq!(app.upstream.requests().len(), 1);
+
+    app.upstream.mock("GET", "/flaky", MockResponse::json(429, json!("slow down")));
+    assert_eq!(get(&app, "{ attempts: 2, backoffMs: 1, retryOn: [429] }").await, json!([429, "slow down"]));
+    assert_eq!(app.upstream.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_attempts_at_the_server_maximum() {
+    let app = TestApp::with_config(|config| config.fetch_max_attempts = 2).await;
+    app.upstream.mock("GET", "/flaky", MockResponse::json(504, json!("timeout")));
+    assert_eq!(get(&app, "{ attempts: 10, backoffMs: 1 }").await, json!([504, "timeout"]));
+    assert_eq!(app.upstream.requests().len(), 2);
+}
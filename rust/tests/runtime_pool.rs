@@ -0,0 +1,84 @@
+// JS_RUNTIME_POOL_SIZE: runtimes reused across executions, and when they are recycled.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::sync::Arc;
+
+use support::TestApp;
+
+async fn pooled(size: usize, max_uses: u32) -> TestApp {
+    TestApp::with_config(|config| {
+        config.js_runtime_pool_size = size;
+        config.js_runtime_max_uses = max_uses;
+        config.execution_timeout_ms = 200;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shares_no_globals_between_executions_on_a_runtime() {
+    let app = pooled(1, 100).await;
+    assert_eq!(app.result("globalThis.leak = 1; Object.prototype.polluted = true; typeof leak", json!({})).await, "number");
+    assert_eq!(app.result("[typeof leak, ({}).polluted ?? null]", json!({})).await, json!(["undefined", null]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reuses_a_released_runtime_until_its_max_uses() {
+    let app = pooled(1, 2).await;
+    let runtimes = app.executor().runtimes();
+    let first = runtimes.acquire().await.unwrap();
+    let heap = first.heap().clone();
+    first.release().await;
+
+    let second = runtimes.acquire().await.unwrap();
+    assert!(Arc::ptr_eq(second.heap(), &heap));
+    second.release().await;
+
+    // Released after its second execution, so the next one gets a new runtime
+    let third = runtimes.acquire().await.unwrap();
+    assert!(!Arc::ptr_eq(third.heap(), &heap));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discards_a_runtime_whose_lease_was_dropped() {
+    let app = pooled(1, 100).await;
+    let runtimes = app.executor().runtimes();
+    let lease = runtimes.acquire().await.unwrap();
+    let heap = lease.heap().clone();
+    drop(lease);
+    assert!(!Arc::ptr_eq(runtimes.acquire().await.unwrap().heap(), &heap));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn creates_a_runtime_of_its_own_when_the_pool_stays_busy() {
+    let app = pooled(1, 100).await;
+    let runtimes = app.executor().runtimes();
+    let held = runtimes.acquire().await.unwrap();
+    let heap = held.heap().clone();
+
+    let extra = runtimes.acquire().await.unwrap();
+    assert!(!Arc::ptr_eq(extra.heap(), &heap));
+    // The extra runtime doesn't join the pool
+    extra.release().await;
+    held.release().await;
+    assert!(Arc::ptr_eq(runtimes.acquire().await.unwrap().heap(), &heap));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_executing_after_an_interrupted_execution() {
+    let app = pooled(1, 100).await;
+    let (status, body) = app.exec("globalThis.leak = 1; while (true) {}", json!({})).await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT, "{}", body);
+    assert_eq!(app.result("typeof leak", json!({})).await, "undefined");
+    assert_eq!(app.result("new Promise((resolve) => setTimeout(() => resolve(2), 10))", json!({})).await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_more_concurrent_executions_than_it_pools() {
+    let app = pooled(2, 3).await;
+    let executions = (0..8).map(|i| app.result("await sleep(20); INPUTS.i * 2", json!({ "i": i })));
+    let results = futures::future::join_all(executions).await;
+    assert_eq!(results, (0..8).map(|i| json!(i * 2)).collect::<Vec<_>>());
+}
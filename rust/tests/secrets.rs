@@ -0,0 +1,76 @@
+// SECRETS, which the code can use but which never come back out of the sandbox.
+
+mod support;
+
+use axum::http::StatusCode;
+use sandbox_core::secrets::Secrets;
+use sandbox_core::{Config, Executor, Options};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use support::{MockResponse, TestApp};
+
+const TOKEN: &str = "tok-3f9a1c";
+
+async fn exec(app: &TestApp, code: &str) -> (StatusCode, serde_json::Value) {
+    app.post("/execute", json!({ "code": code, "secrets": { "token": TOKEN } })).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacts_a_returned_secret() {
+    let app = TestApp::start().await;
+    let (status, body) = exec(&app, "({ token: SECRETS.token, header: 'Bearer ' + SECRETS.token, [SECRETS.token]: 1 })").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!({ "token": "***REDACTED***", "header": "Bearer ***REDACTED***", "***REDACTED***": 1 })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_the_secret_to_the_upstream() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/me", MockResponse::json(200, json!({ "ok": true })));
+    let code = format!(
+        "const r = await httpRequest('{}', {{ headers: {{ Authorization: 'Bearer ' + SECRETS.token }} }}); r.status",
+        app.upstream.url("/me")
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], 200);
+    assert_eq!(app.upstream.requests()[0].headers["authorization"], format!("Bearer {}", TOKEN));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacts_a_secret_from_errors() {
+    let app = TestApp::start().await;
+    let (status, body) = exec(&app, "throw new Error('bad token ' + SECRETS.token)").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!body.to_string().contains(TOKEN), "{}", body);
+    assert_eq!(body["jsError"]["message"], "bad token ***REDACTED***");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_secrets_out_of_inputs_and_frozen() {
+    let app = TestApp::start().await;
+    let code = "'use strict'; const changed = []; \
+        try { SECRETS.token = 'x'; changed.push('token') } catch (e) {} \
+        try { SECRETS = {}; changed.push('SECRETS') } catch (e) {} \
+        [changed, typeof INPUTS.token, SECRETS.token.length]";
+    let (status, body) = exec(&app, code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([[], "undefined", TOKEN.len()]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacts_a_logged_secret() {
+    let executor = Executor::new(&Config::default()).unwrap();
+    let secrets = BTreeMap::from([("token".to_string(), TOKEN.to_string())]);
+    let options = Options {
+        secrets: Arc::new(Secrets::new(secrets)),
+        ..Options::default()
+    };
+    let execution = executor.run("console.log('using', SECRETS.token); 1", &json!({}), options).await.unwrap();
+    assert_eq!(execution.report.logs[0].message, "using ***REDACTED***");
+}
@@ -0,0 +1,115 @@
+// GET /session: evaluations over a WebSocket that share one context.
+
+mod support;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use support::TestApp;
+
+struct Client(WebSocketStream<MaybeTlsStream<TcpStream>>);
+
+impl Client {
+    async fn connect(address: SocketAddr) -> Self {
+        let (socket, _) = connect_async(format!("ws://{}/session", address)).await.unwrap();
+        Client(socket)
+    }
+
+    async fn send(&mut self, message: Value) {
+        self.0.send(Message::Text(message.to_string())).await.unwrap();
+    }
+
+    // The next message from the server, None once it closed the socket
+    async fn receive(&mut self) -> Option<Value> {
+        let next = tokio::time::timeout(Duration::from_secs(10), self.0.next()).await.expect("a message from the server");
+        match next? {
+            Ok(Message::Text(text)) => Some(serde_json::from_str(&text).unwrap()),
+            Ok(Message::Close(_)) | Err(_) => None,
+            Ok(other) => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    async fn eval(&mut self, id: u32, code: &str, inputs: Value) -> Value {
+        self.send(json!({ "type": "eval", "id": id, "code": code, "inputs": inputs })).await;
+        self.receive().await.unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_globals_between_evaluations() {
+    let app = TestApp::start().await;
+    let mut client = Client::connect(app.serve().await).await;
+    let reply = client.eval(1, "var total = INPUTS.start; total", json!({ "start": 40 })).await;
+    assert_eq!(reply, json!({ "type": "result", "id": 1, "result": 40 }));
+
+    client.send(json!({ "type": "eval", "id": 2, "code": "console.log('adding'); total += 2" })).await;
+    assert_eq!(client.receive().await.unwrap(), json!({ "type": "log", "level": "log", "message": "adding" }));
+    assert_eq!(client.receive().await.unwrap(), json!({ "type": "result", "id": 2, "result": 42 }));
+
+    // Every evaluation gets its own INPUTS
+    assert_eq!(client.eval(3, "INPUTS", json!(null)).await["result"], json!(null));
+    assert_eq!(client.eval(4, "[INPUTS, total]", json!({})).await["result"], json!([{}, 42]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_failures_and_stays_open() {
+    let app = TestApp::with_config(|config| config.execution_timeout_ms = 200).await;
+    let mut client = Client::connect(app.serve().await).await;
+    client.eval(1, "var kept = 'yes'", json!({})).await;
+
+    let reply = client.eval(2, "throw new TypeError('nope')", json!({})).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["id"], 2);
+    assert_eq!(reply["error"], "RuntimeError");
+    assert_eq!(reply["jsError"]["name"], "TypeError");
+    assert_eq!(reply["jsError"]["message"], "nope");
+
+    let reply = client.eval(3, "while (true) {}", json!({})).await;
+    assert_eq!(reply["message"], "Execution exceeded the timeout of 200 ms", "{}", reply);
+
+    client.send(json!({ "type": "run" })).await;
+    assert_eq!(client.receive().await.unwrap()["error"], "Invalid message");
+
+    assert_eq!(client.eval(4, "kept", json!({})).await["result"], "yes");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_sessions_past_max_sessions() {
+    let app = TestApp::with_config(|config| config.max_sessions = 1).await;
+    let address = app.serve().await;
+    let mut first = Client::connect(address).await;
+
+    let Err(tokio_tungstenite::tungstenite::Error::Http(response)) = connect_async(format!("ws://{}/session", address)).await else {
+        panic!("the second session was accepted");
+    };
+    assert_eq!(response.status(), 503);
+
+    // Closing a session frees its slot
+    first.0.close(None).await.unwrap();
+    while first.receive().await.is_some() {}
+    let mut again = None;
+    for _ in 0..50 {
+        if let Ok((socket, _)) = connect_async(format!("ws://{}/session", address)).await {
+            again = Some(Client(socket));
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(again.expect("a session once the first closed").eval(1, "1 + 1", json!({})).await["result"], 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn closes_idle_sessions() {
+    let app = TestApp::with_config(|config| config.session_idle_timeout_ms = 100).await;
+    let mut client = Client::connect(app.serve().await).await;
+    assert_eq!(client.eval(1, "1", json!({})).await["result"], 1);
+
+    let reply = client.receive().await.unwrap();
+    assert_eq!(reply, json!({ "type": "error", "error": "Session idle", "message": "Session closed after 100 ms without messages" }));
+    assert_eq!(client.receive().await, None);
+}
@@ -0,0 +1,67 @@
+// SIGTERM: draining running executions within SHUTDOWN_GRACE_SECONDS, then exiting.
+
+mod support;
+
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+use support::{free_port, Server};
+
+async fn execute(url: String, code: &str) -> (u16, Value) {
+    let response = reqwest::Client::new().post(url).json(&json!({ "code": code })).send().await.unwrap();
+    (response.status().as_u16(), response.json().await.unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn finishes_running_executions_before_exiting() {
+    let address = format!("127.0.0.1:{}", free_port());
+    let mut server = Server::start(&[("LISTEN", &format!("tcp://{}", address)), ("SHUTDOWN_GRACE_SECONDS", "10")]).await;
+    let running = tokio::spawn(execute(format!("http://{}/execute", address), "await sleep(1000); 'done'"));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    server.terminate();
+    let line = server.logged("Shutting down").await;
+    assert_eq!(line["message"], "Shutting down, waiting up to 10 s for executions to finish");
+    // No new connections meanwhile
+    assert!(reqwest::get(format!("http://{}/livez", address)).await.is_err());
+
+    assert_eq!(running.await.unwrap(), (200, json!({ "result": "done" })));
+    let summary = server.logged("Shutdown complete").await;
+    assert_eq!((&summary["finished"], &summary["aborted"]), (&json!(1), &json!(0)));
+    assert!(server.exited().await.success());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupts_executions_still_running_after_the_grace_period() {
+    let address = format!("127.0.0.1:{}", free_port());
+    let listen = format!("tcp://{}", address);
+    let mut server = Server::start(&[("LISTEN", &listen), ("SHUTDOWN_GRACE_SECONDS", "1"), ("EXECUTION_TIMEOUT_MS", "60000")]).await;
+    // Busy, but awaiting now and then, so the signal is seen even on a single CPU
+    let code = "for (;;) { const end = Date.now() + 50; while (Date.now() < end) {} await sleep(1); }";
+    let running = tokio::spawn(execute(format!("http://{}/execute", address), code));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let terminated = Instant::now();
+    server.terminate();
+    let (status, body) = running.await.unwrap();
+    assert_eq!(status, 408, "{}", body);
+    assert_eq!(body["message"], "Execution was interrupted because the server is shutting down");
+    let summary = server.logged("Shutdown complete").await;
+    assert_eq!((&summary["finished"], &summary["aborted"]), (&json!(0), &json!(1)));
+    assert!(server.exited().await.success());
+    assert!(terminated.elapsed() < Duration::from_secs(5), "{:?}", terminated.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exits_right_away_when_idle() {
+    let address = format!("127.0.0.1:{}", free_port());
+    let mut server = Server::start(&[("LISTEN", &format!("tcp://{}", address))]).await;
+    assert_eq!(reqwest::get(format!("http://{}/livez", address)).await.unwrap().status(), 200);
+
+    let terminated = Instant::now();
+    server.terminate();
+    let summary = server.logged("Shutdown complete").await;
+    assert_eq!((&summary["finished"], &summary["aborted"]), (&json!(0), &json!(0)));
+    assert!(server.exited().await.success());
+    assert!(terminated.elapsed() < Duration::from_secs(3), "{:?}", terminated.elapsed());
+}
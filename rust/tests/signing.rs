@@ -0,0 +1,173 @@
+// `options.sign`: HMAC and AWS SigV4 signatures over the request as it is sent.
+
+mod support;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+use support::{MockResponse, TestApp};
+
+// The test suite's credentials, see
+// https://docs.aws.amazon.com/general/latest/gr/signature-v4-test-suite.html
+const AWS_SECRETS: [(&str, &str); 2] = [
+    ("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE"),
+    ("AWS_SECRET_ACCESS_KEY", "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+];
+
+async fn run(app: &TestApp, code: &str, secrets: Value) -> Value {
+    app.result_of(json!({ "code": code, "secrets": secrets })).await
+}
+
+fn header<'a>(request: &'a support::RecordedRequest, name: &str) -> &'a str {
+    request.headers.get(name).map_or("", |value| value.to_str().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signs_the_body_with_hmac_sha256() {
+    let app = TestApp::with_config(|config| {
+        config.signing_secrets = BTreeMap::from([("SERVER_KEY".to_string(), "server-side-key".to_string())]);
+    })
+    .await;
+    app.upstream.mock("POST", "/hook", MockResponse::json(200, json!({ "ok": true })));
+
+    // RFC 4231, test case 2
+    let code = format!(
+        r#"const sign = {{ type: "hmac-sha256", secretRef: "webhook", header: "X-Hub-Signature-256", prefix: "sha256=" }};
+        (await httpRequest("{}", {{ method: "POST", body: "what do ya want for nothing?", sign }})).status"#,
+        app.upstream.url("/hook")
+    );
+    let result = run(&app, &code, json!({ "webhook": "Jefe" })).await;
+    assert_eq!(result, 200);
+    let requests = app.upstream.requests();
+    let expected = "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+    assert_eq!(header(&requests[0], "x-hub-signature-256"), expected);
+
+    // Over the method, path and a timestamp too, with a secret only the server has
+    let code = format!(
+        r#"const sign = {{ type: "hmac-sha256", secretRef: "SERVER_KEY", payloadFields: ["timestamp", "method", "path", "body"],
+            separator: ":", encoding: "base64" }};
+        const response = await httpRequest("{}", {{ method: "POST", query: {{ page: 2 }}, body: '{{"event":"push"}}', sign }});
+        [response.status, typeof SECRETS.SERVER_KEY]"#,
+        app.upstream.url("/hook")
+    );
+    let result = run(&app, &code, json!({})).await;
+    assert_eq!(result, json!([200, "undefined"]));
+    let request = &app.upstream.requests()[1];
+    let timestamp = header(request, "x-signature-timestamp");
+    assert!(timestamp.parse::<u64>().unwrap() > 1_700_000_000, "{}", timestamp);
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"server-side-key").unwrap();
+    mac.update(format!("{}:POST:/hook?page=2:{{\"event\":\"push\"}}", timestamp).as_bytes());
+    let expected = BASE64_STANDARD.encode(mac.finalize().into_bytes());
+    assert_eq!(header(request, "x-signature"), expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn matches_the_sigv4_test_suite() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/", MockResponse::json(200, json!({})));
+    app.upstream.mock("POST", "/", MockResponse::json(200, json!({})));
+    let secrets = Value::Object(AWS_SECRETS.iter().map(|(name, value)| (name.to_string(), json!(value))).collect());
+
+    // get-vanilla-query-order-key-case
+    let code = format!(
+        r#"const sign = {{ type: "aws-sigv4", region: "us-east-1", service: "service", credentialsRef: "AWS" }};
+        const headers = {{ Host: "example.amazonaws.com", "X-Amz-Date": "20150830T123600Z" }};
+        (await httpRequest("{}?Param2=value2&Param1=value1", {{ headers, sign }})).status"#,
+        app.upstream.url("/")
+    );
+    assert_eq!(run(&app, &code, secrets.clone()).await, 200);
+    let get = &app.upstream.requests()[0];
+    assert_eq!(
+        header(get, "authorization"),
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, \
+         Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+    );
+
+    // post-x-www-form-urlencoded
+    let code = format!(
+        r#"const sign = {{ type: "aws-sigv4", region: "us-east-1", service: "service", credentialsRef: "AWS" }};
+        const headers = {{ Host: "example.amazonaws.com", "X-Amz-Date": "20150830T123600Z",
+            "Content-Type": "application/x-www-form-urlencoded" }};
+        (await httpRequest("{}", {{ method: "POST", headers, body: "Param1=value1", sign }})).status"#,
+        app.upstream.url("/")
+    );
+    assert_eq!(run(&app, &code, secrets).await, 200);
+    let post = &app.upstream.requests()[1];
+    assert_eq!(
+        header(post, "authorization"),
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+         SignedHeaders=content-type;host;x-amz-date, Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn adds_the_date_token_and_payload_hash() {
+    let app = TestApp::start().await;
+    app.upstream.mock("PUT", "/bucket/my%20file.txt", MockResponse::json(200, json!({})));
+    let code = format!(
+        r#"const sign = {{ type: "aws-sigv4", region: "eu-west-1", service: "s3", credentialsRef: "TEMP" }};
+        (await httpRequest("{}", {{ method: "PUT", body: "hello", sign }})).status"#,
+        app.upstream.url("/bucket/my file.txt")
+    );
+    let secrets = json!({ "TEMP_ACCESS_KEY_ID": "ASIAEXAMPLE", "TEMP_SECRET_ACCESS_KEY": "secret", "TEMP_SESSION_TOKEN": "token-1" });
+    assert_eq!(run(&app, &code, secrets).await, 200);
+    let request = &app.upstream.requests()[0];
+    let date = header(request, "x-amz-date");
+    assert_eq!((date.len(), &date[8..9], &date[15..]), (16, "T", "Z"), "{}", date);
+    assert_eq!(header(request, "x-amz-security-token"), "token-1");
+    // SHA-256 of "hello"
+    let payload_hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    assert_eq!(header(request, "x-amz-content-sha256"), payload_hash);
+    let authorization = header(request, "authorization");
+    let credential = format!("Credential=ASIAEXAMPLE/{}/eu-west-1/s3/aws4_request", &date[..8]);
+    assert!(authorization.contains(&credential), "{}", authorization);
+    assert!(
+        authorization.contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature="),
+        "{}",
+        authorization
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_to_send_what_it_cannot_sign() {
+    let app = TestApp::start().await;
+    app.upstream.mock("POST", "/hook", MockResponse::json(200, json!({})));
+    let url = app.upstream.url("/hook");
+    let call = |sign: &str, extra: &str| {
+        format!(
+            r#"const r = await httpRequest("{}", {{ method: "POST", body: "x", sign: {}{} }}); [r.errorCode, r.data]"#,
+            url, sign, extra
+        )
+    };
+
+    let result = run(&app, &call(r#"{ type: "hmac-sha256", secretRef: "missing" }"#, ""), json!({ "other": "hunter2" })).await;
+    assert_eq!(result, json!(["invalid_request", "Invalid sign option: there is no secret named missing"]));
+
+    let result = run(&app, &call(r#"{ type: "rsa" }"#, ""), json!({})).await;
+    assert_eq!(result[0], "invalid_request");
+    assert!(result[1].as_str().unwrap().contains("unknown variant `rsa`"), "{}", result);
+
+    let aws = r#"{ type: "aws-sigv4", region: "us-east-1", service: "sts", credentialsRef: "AWS" }"#;
+    let secrets = json!({ "AWS_ACCESS_KEY_ID": "AKID", "AWS_SECRET_ACCESS_KEY": "hunter2" });
+    let result = run(&app, &call(aws, r#", auth: { type: "bearer", token: "t" }"#), secrets).await;
+    assert_eq!(result[0], "invalid_request");
+    assert!(result[1].as_str().unwrap().contains("the signature goes in authorization, which the request already sets"), "{}", result);
+    assert!(!result.to_string().contains("hunter2"));
+    assert!(app.upstream.requests().is_empty());
+}
+
+#[test]
+fn reads_signing_secrets_from_the_environment_and_masks_them() {
+    std::env::set_var("SIGNING_SECRET_SIGNING_TEST_KEY", "s3cr3t-value");
+    let config = sandbox_core::Config::load(None);
+    std::env::remove_var("SIGNING_SECRET_SIGNING_TEST_KEY");
+
+    let config = config.unwrap();
+    assert_eq!(config.signing_secrets["SIGNING_TEST_KEY"], "s3cr3t-value");
+    let logged = config.redacted();
+    assert!(logged.contains("\"SIGNING_TEST_KEY\":\"redacted\""), "{}", logged);
+    assert!(!logged.contains("s3cr3t-value"));
+}
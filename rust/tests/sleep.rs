@@ -0,0 +1,96 @@
+// sleep(), setTimeout and clearTimeout, and the per-execution sleep budget.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delays_the_result_by_the_sleep() {
+    let app = TestApp::start().await;
+    let started = Instant::now();
+    let (status, body) = app
+        .exec("const before = Date.now(); await sleep(200); Date.now() - before >= 190", json!({}))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(true));
+    assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_a_sleep_past_the_budget() {
+    let app = TestApp::with_config(|config| config.sleep_budget_ms = 300).await;
+    let (status, body) = app.exec("await sleep(200); await sleep(200); 'woke'", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "RuntimeError");
+    assert!(
+        body["message"].as_str().unwrap().contains("Sleep budget exceeded: waiting 200 ms would pass SLEEP_BUDGET_MS (300 ms, 200 ms used)"),
+        "{}",
+        body
+    );
+
+    // The wait never starts, so code can catch the error and carry on
+    let started = Instant::now();
+    let (status, body) = app
+        .exec(
+            "try { setTimeout(() => {}, 500); } catch (e) { [e.name, await sleep(250).then(() => 'slept')] }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["RangeError", "slept"]));
+    assert!(started.elapsed() < Duration::from_millis(500), "{:?}", started.elapsed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn polls_an_upstream_between_sleeps() {
+    let app = TestApp::start().await;
+    app.upstream.mock_sequence(
+        "GET",
+        "/job",
+        vec![
+            MockResponse::json(200, json!({ "state": "running" })),
+            MockResponse::json(200, json!({ "state": "running" })),
+            MockResponse::json(200, json!({ "state": "done" })),
+        ],
+    );
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!({ "ok": true })).with_delay(Duration::from_millis(150)));
+    let code = format!(
+        "let polls = 0;
+        let job;
+        do {{
+            if (polls++) await sleep(50);
+            job = (await httpRequest('{0}/job')).data;
+        }} while (job.state !== 'done');
+        const [slow] = await Promise.all([httpRequest('{0}/slow'), sleep(100)]);
+        [polls, job.state, slow.data.ok]",
+        app.upstream.url("")
+    );
+
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!([3, "done", true]));
+    assert_eq!(app.upstream.requests().len(), 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_timeouts_in_order_unless_cleared() {
+    let app = TestApp::start().await;
+    let (status, body) = app
+        .exec(
+            "const order = [];
+            setTimeout((label) => order.push(label), 60, 'late');
+            setTimeout(() => order.push('early'), 20);
+            const cleared = setTimeout(() => order.push('cleared'), 40);
+            clearTimeout(cleared);
+            setTimeout(() => order.push('soon'));
+            await sleep(100);
+            order",
+            json!({}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["soon", "early", "late"]));
+}
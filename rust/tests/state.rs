@@ -0,0 +1,123 @@
+// The `state` global, kept between executions per namespace.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use support::TestApp;
+
+const COUNTER: &str = "const count = (state.get('count') ?? 0) + 1; state.set('count', count); count";
+
+async fn run(app: &TestApp, code: &str, namespace: Option<&str>) -> Value {
+    app.result_of(json!({ "code": code, "state_namespace": namespace })).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shares_a_counter_between_executions() {
+    let app = TestApp::start().await;
+    assert_eq!(run(&app, COUNTER, Some("poller")).await, 1);
+    assert_eq!(run(&app, COUNTER, Some("poller")).await, 2);
+    assert_eq!(run(&app, "state.get('count')", Some("poller")).await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_namespaces_apart() {
+    let app = TestApp::start().await;
+    assert_eq!(run(&app, COUNTER, Some("a")).await, 1);
+    assert_eq!(run(&app, COUNTER, Some("b")).await, 1);
+    assert_eq!(run(&app, COUNTER, None).await, 1);
+    assert_eq!(run(&app, "state.list()", Some("a")).await, json!(["count"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_api_keys_apart() {
+    let app = TestApp::start().await;
+    let with_key = |key: &str| {
+        Request::post("/execute")
+            .header("content-type", "application/json")
+            .header("x-api-key", key)
+            .body(Body::from(json!({ "code": COUNTER, "state_namespace": "shared" }).to_string()))
+            .unwrap()
+    };
+    assert_eq!(app.send(with_key("tenant-1")).await.1["result"], 1);
+    assert_eq!(app.send(with_key("tenant-1")).await.1["result"], 2);
+    assert_eq!(app.send(with_key("tenant-2")).await.1["result"], 1);
+    // Without a key, the namespace is a different one again
+    assert_eq!(run(&app, COUNTER, Some("shared")).await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn discards_the_writes_of_a_failed_execution() {
+    let app = TestApp::start().await;
+    let (status, _) = app.post("/execute", json!({ "code": "state.set('x', 1); throw new Error('no')" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(run(&app, "[state.get('x'), typeof state.get('x')]", None).await, json!([null, "undefined"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_its_own_writes_and_deletes() {
+    let app = TestApp::start().await;
+    run(&app, "state.set('item:1', { id: 1 }); state.set('item:2', [2]); state.set('other', true)", None).await;
+    let code = "const deleted = state.delete('item:1'); state.set('item:3', 'three'); \
+        [deleted, state.delete('missing'), state.list('item:'), state.get('item:1') === undefined, state.get('item:3')]";
+    assert_eq!(run(&app, code, None).await, json!([true, false, ["item:2", "item:3"], true, "three"]));
+    assert_eq!(run(&app, "state.list()", None).await, json!(["item:2", "item:3", "other"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expires_entries_after_their_ttl() {
+    let app = TestApp::start().await;
+    run(&app, "state.set('token', 'abc', { ttlSeconds: 0.2 }); state.set('kept', 1)", None).await;
+    assert_eq!(run(&app, "state.get('token')", None).await, "abc");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(run(&app, "[state.get('token') === undefined, state.list()]", None).await, json!([true, ["kept"]]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_entries_whose_ttl_is_too_long_to_represent() {
+    let app = TestApp::start().await;
+    // Too long for a Duration, and for milliseconds since the epoch
+    let code = "state.set('forever', 1, { ttlSeconds: 1e300 }); state.set('long', 2, { ttlSeconds: 18446744073709551 }); 'set'";
+    assert_eq!(run(&app, code, None).await, "set");
+    assert_eq!(run(&app, "[state.get('forever'), state.get('long')]", None).await, json!([1, 2]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuses_writes_over_the_quota() {
+    let app = TestApp::with_config(|config| config.state_max_namespace_bytes = 100).await;
+    let code = "state.set('small', 1); try { state.set('big', 'x'.repeat(200)); 'stored' } catch (e) { [e.message, state.get('big') === undefined] }";
+    let result = run(&app, code, None).await;
+    assert_eq!(
+        result,
+        json!([
+            "state.set('big') would grow the namespace to 211 bytes, exceeding the STATE_MAX_NAMESPACE_BYTES limit of 100",
+            true
+        ])
+    );
+    assert_eq!(run(&app, "state.list()", None).await, json!(["small"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_the_state_in_the_state_file() {
+    let path = std::env::temp_dir().join(format!("sandbox-state-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = |config: &mut sandbox_core::Config| config.state_path = path.to_string_lossy().into_owned();
+    assert_eq!(run(&TestApp::with_config(config).await, COUNTER, None).await, 1);
+    // A new server reads it back
+    assert_eq!(run(&TestApp::with_config(config).await, COUNTER, None).await, 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_namespaces() {
+    let app = TestApp::start().await;
+    for namespace in ["", "key:abc/x", "a b"] {
+        let (status, body) = app.post("/execute", json!({ "code": "1", "state_namespace": namespace })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid state_namespace");
+    }
+}
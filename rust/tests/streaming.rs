@@ -0,0 +1,137 @@
+// httpGetStream: response bodies handed to the script a chunk at a time.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+async fn exec(app: &TestApp, code: &str) -> (StatusCode, Value) {
+    app.post("/execute", json!({ "code": code, "include_meta": true })).await
+}
+
+fn ndjson(lines: usize) -> String {
+    (0..lines).map(|i| format!("{{\"id\":{},\"name\":\"record {}\"}}\n", i, i)).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_the_lines_of_a_large_ndjson_body() {
+    let app = TestApp::with_config(|config| config.fetch_max_body_bytes = 1024 * 1024).await;
+    let body = ndjson(100_000);
+    assert!(body.len() > 3 * 1024 * 1024);
+    app.upstream.mock(
+        "GET",
+        "/export",
+        MockResponse::text(200, &body).with_header("content-type", "application/x-ndjson").streamed(),
+    );
+    let code = format!(
+        "let lines = 0, chunks = 0, largest = 0, rest = '', last;
+        const response = await httpGetStream('{}', async (chunk) => {{
+            chunks++;
+            largest = Math.max(largest, chunk.length);
+            const parts = (rest + chunk).split('\\n');
+            rest = parts.pop();
+            lines += parts.length;
+            last = parts.at(-1) ?? last;
+            // Awaited before the next chunk is read
+            await null;
+        }});
+        ({{ status: response.status, ok: response.ok, bytesRead: response.bytesRead, type: response.headers['content-type'],
+            text: response.text, lines, rest, last: JSON.parse(last).id, chunked: chunks > 1, largest }})",
+        app.upstream.url("/export")
+    );
+    let (status, result) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", result);
+    let streamed = &result["result"];
+    assert_eq!(streamed["status"], 200);
+    assert_eq!(streamed["ok"], true);
+    assert_eq!(streamed["bytesRead"], body.len());
+    assert_eq!(streamed["type"], "application/x-ndjson");
+    assert_eq!(streamed["text"], Value::Null);
+    assert_eq!(streamed["lines"], 100_000);
+    assert_eq!(streamed["rest"], "");
+    assert_eq!(streamed["last"], 99_999);
+    assert_eq!(streamed["chunked"], true);
+    // Far more than FETCH_MAX_BODY_BYTES in total, but never more than a chunk at once
+    assert!(streamed["largest"].as_u64().unwrap() < 1024 * 1024, "{}", streamed);
+    assert_eq!(result["meta"]["usage"]["fetchedBytes"], body.len());
+    assert_eq!(result["meta"]["httpRequestCount"], 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hands_over_binary_bodies_as_bytes() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/blob",
+        MockResponse::text(200, &"\u{1}\u{2}\u{3}".repeat(20_000))
+            .with_header("content-type", "application/octet-stream")
+            .streamed(),
+    );
+    app.upstream.mock(
+        "GET",
+        "/text",
+        MockResponse::text(200, &"€".repeat(20_000)).with_header("content-type", "text/plain; charset=utf-8").streamed(),
+    );
+    let code = format!(
+        "let bytes = 0, sum = 0, types = new Set();
+        const blob = await httpGetStream('{}', (chunk) => {{
+            types.add(chunk instanceof Uint8Array);
+            bytes += chunk.length;
+            for (const byte of chunk) sum += byte;
+        }});
+        // Multi-byte characters cut by a chunk boundary arrive whole
+        let text = '';
+        await httpGetStream('{}', {{ method: 'GET' }}, (chunk) => {{ text += chunk; }});
+        [blob.bytesRead, bytes, sum, [...types], text.length, text === '€'.repeat(20000)]",
+        app.upstream.url("/blob"),
+        app.upstream.url("/text")
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!([60_000, 60_000, 120_000, [true], 20_000, true]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cuts_the_body_off_at_the_bandwidth_quota() {
+    let app = TestApp::with_config(|config| config.max_fetch_total_bytes = 100_000).await;
+    app.upstream.mock("GET", "/export", MockResponse::text(200, &ndjson(10_000)).streamed());
+    let url = app.upstream.url("/export");
+    let code = format!(
+        "let seen = 0;
+        const response = await httpGetStream('{0}', (chunk) => {{ seen += chunk.length; }});
+        const next = await httpGetStream('{0}', () => {{ throw new Error('never called'); }});
+        [response.ok, response.status, response.errorCode, response.bytesRead > 100000, seen <= 100000, next.errorCode, next.bytesRead]",
+        url
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!([false, 200, "bandwidth_quota_exceeded", true, true, "bandwidth_quota_exceeded", 0])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_reading_when_the_callback_throws() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/export", MockResponse::text(200, &ndjson(10_000)).streamed());
+    let code = format!(
+        "let calls = 0;
+        try {{
+            await httpGetStream('{}', () => {{ calls++; throw new RangeError('enough'); }});
+        }} catch (e) {{
+            [e.name, e.message, calls]
+        }}",
+        app.upstream.url("/export")
+    );
+    let (status, body) = exec(&app, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(["RangeError", "enough", 1]));
+    assert!(body["meta"]["usage"]["fetchedBytes"].as_u64().unwrap() < 10_000 * 30, "{}", body);
+
+    let (status, body) = app.exec("await httpGetStream('https://example.com/')", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["jsError"]["message"], "httpGetStream: onChunk must be a function", "{}", body);
+}
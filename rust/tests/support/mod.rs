@@ -0,0 +1,508 @@
+// Test harness: the router, served in-process, and a mock upstream that scripts
+// send their httpRequest calls to.
+//
+// `TestApp::start()` builds the same router the server runs, with the default
+// configuration except that private networks are allowed, so scripts can reach a
+// `MockUpstream` on 127.0.0.1. Requests go to the router without a socket; the mock
+// upstream is a real HTTP server on a free port, so the whole outbound request path
+// is exercised. What only the binary sets up, its listeners, TLS and shutdown, is
+// tested on a `Server` process.
+//
+//     let app = TestApp::start().await;
+//     app.upstream.mock("GET", "/users/1", MockResponse::json(200, json!({"id": 1})));
+//     let (status, body) = app.exec(&format!("(await httpRequest('{}')).status", app.upstream.url("/users/1")), json!({})).await;
+
+#![allow(dead_code)]
+
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::Request;
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+use js_execution_service::quotas::Quotas;
+use js_execution_service::{app_state, router, warm_up, AppState};
+use sandbox_core::{Config, Executor};
+
+// Bodies larger than this aren't read back in tests
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+// A file in tests/fixtures
+pub fn fixture(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+pub struct TestApp {
+    router: Router,
+    state: AppState,
+    pub upstream: MockUpstream,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        TestApp::with_config(|_| {}).await
+    }
+
+    // The test configuration, adjusted by `configure` before anything is built
+    pub async fn with_config(configure: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Config {
+            allow_private_networks: true,
+            ..Config::default()
+        };
+        configure(&mut config);
+        // As the binary does
+        config.name_service(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        let executor = Executor::new(&config).expect("test configuration is valid");
+        let state = app_state(&config, executor);
+        TestApp {
+            router: router(&config, state.clone()),
+            state,
+            upstream: MockUpstream::start().await,
+        }
+    }
+
+    pub fn executor(&self) -> &Executor {
+        self.state.executor()
+    }
+
+    // The usage quotas, for tests that move their clock
+    pub fn quotas(&self) -> &Quotas {
+        self.state.quotas()
+    }
+
+    // What the server does on SIGTERM before it waits for the executions
+    pub fn start_draining(&self) {
+        self.state.start_draining()
+    }
+
+    // What the server does at startup before it reports ready
+    pub async fn warm_up(&self, timeout: Duration) {
+        warm_up(self.state.clone(), timeout).await
+    }
+
+    // POST /execute with the code and inputs, answering with the status and JSON body
+    pub async fn exec(&self, code: &str, inputs: Value) -> (StatusCode, Value) {
+        self.post("/execute", serde_json::json!({ "code": code, "inputs": inputs })).await
+    }
+
+    // The result of an execution that has to succeed
+    pub async fn result(&self, code: &str, inputs: Value) -> Value {
+        self.result_of(serde_json::json!({ "code": code, "inputs": inputs })).await
+    }
+
+    // Like `result`, for a request with more than code and inputs
+    pub async fn result_of(&self, request: Value) -> Value {
+        let (status, body) = self.post("/execute", request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["result"].clone()
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    pub async fn get(&self, path: &str) -> (StatusCode, Value) {
+        self.send(Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    // Any request, for tests that need other methods or headers. Bodies that aren't
+    // JSON come back as a string.
+    pub async fn send(&self, request: Request) -> (StatusCode, Value) {
+        let response = self.response(request).await;
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), MAX_BODY_BYTES).await.unwrap();
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        (status, body)
+    }
+
+    // The whole response, for tests of its headers
+    pub async fn response(&self, request: Request) -> Response {
+        self.router.clone().oneshot(request).await.unwrap()
+    }
+
+    // The router on a free port, for tests that need a socket such as WebSocket
+    // upgrades or the client's address
+    pub async fn serve(&self) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = self.router.clone().into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        address
+    }
+}
+
+// What the mock upstream answers to one route
+#[derive(Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+    // Waited before the response is sent
+    delay: Duration,
+    // Fail the connection after sending the headers
+    abort: bool,
+    // Send the body as a stream of small chunks, without Content-Length
+    streamed: bool,
+    // Answer 304 to a request whose If-None-Match or If-Modified-Since matches the
+    // response's ETag or Last-Modified
+    conditional: bool,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: Value) -> Self {
+        MockResponse::text(status, &body.to_string()).with_header("content-type", "application/json")
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: Bytes::from(body.to_string()),
+            delay: Duration::ZERO,
+            abort: false,
+            streamed: false,
+            conditional: false,
+        }
+    }
+
+    // The response headers are sent, then the connection fails instead of the body
+    pub fn abort() -> Self {
+        MockResponse {
+            abort: true,
+            ..MockResponse::text(200, "")
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn streamed(mut self) -> Self {
+        self.streamed = true;
+        self
+    }
+
+    pub fn conditional(mut self) -> Self {
+        self.conditional = true;
+        self
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    // Whether the request's validators say the client already has this response
+    fn not_modified(&self, request: &HeaderMap) -> bool {
+        let matches = |validator: &str, header: &str| {
+            let sent = request.get(validator).and_then(|value| value.to_str().ok());
+            sent.is_some() && sent == self.header(header)
+        };
+        self.conditional && (matches("if-none-match", "etag") || matches("if-modified-since", "last-modified"))
+    }
+}
+
+// A request the mock upstream received
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: Method,
+    // Path and query
+    pub uri: String,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("request body is JSON")
+    }
+}
+
+#[derive(Default)]
+struct Routes {
+    // Answered in turn, the last one to every later request
+    responses: HashMap<(Method, String), Vec<MockResponse>>,
+    requests: Vec<RecordedRequest>,
+    in_flight: usize,
+    max_in_flight: usize,
+    // Requests whose client hung up before the response was sent
+    cancelled: usize,
+}
+
+// Leaves the in-flight count once the response is ready, or counts the request as
+// cancelled when hyper drops the handler because the client went away
+struct Answering {
+    routes: Arc<Mutex<Routes>>,
+    answered: bool,
+}
+
+impl Drop for Answering {
+    fn drop(&mut self) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.in_flight -= 1;
+        if !self.answered {
+            routes.cancelled += 1;
+        }
+    }
+}
+
+// An HTTP server on 127.0.0.1 that answers with the mocked responses, by method and
+// path, and 404 to anything else. It runs until the test's runtime shuts down.
+#[derive(Clone)]
+pub struct MockUpstream {
+    address: SocketAddr,
+    scheme: &'static str,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = MockUpstream {
+            address: listener.local_addr().unwrap(),
+            scheme: "http",
+            routes: Arc::default(),
+        };
+        let app = upstream.router();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        upstream
+    }
+
+    // The same server over HTTPS, with the certificate in tests/fixtures/tls that
+    // its `ca.pem` issued for localhost and 127.0.0.1
+    pub async fn start_tls() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = MockUpstream {
+            address: listener.local_addr().unwrap(),
+            scheme: "https",
+            routes: Arc::default(),
+        };
+        let certs = CertificateDer::pem_file_iter(fixture("tls/cert.pem")).unwrap().map(Result::unwrap).collect();
+        let key = PrivateKeyDer::from_pem_file(fixture("tls/key.pem")).unwrap();
+        let config = rustls::ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key).unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let app = upstream.router();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (acceptor, app) = (acceptor.clone(), app.clone());
+                tokio::spawn(async move {
+                    // Clients that refused the certificate hang up during the handshake
+                    let Ok(stream) = acceptor.accept(stream).await else { return };
+                    let service = TowerToHyperService::new(app);
+                    let _ = auto::Builder::new(TokioExecutor::new()).serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        upstream
+    }
+
+    fn router(&self) -> Router {
+        let routes = self.routes.clone();
+        Router::new().fallback(move |request: Request| answer(routes.clone(), request))
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}://{}{}", self.scheme, self.address, path)
+    }
+
+    // Replaces any earlier response for the same method and path
+    pub fn mock(&self, method: &str, path: &str, response: MockResponse) {
+        self.mock_sequence(method, path, vec![response]);
+    }
+
+    // One response per request in turn, then the last one to every later request
+    pub fn mock_sequence(&self, method: &str, path: &str, responses: Vec<MockResponse>) {
+        assert!(!responses.is_empty());
+        let method = Method::from_bytes(method.as_bytes()).unwrap();
+        self.routes.lock().unwrap().responses.insert((method, path.to_string()), responses);
+    }
+
+    // Every request received so far, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.routes.lock().unwrap().requests.clone()
+    }
+
+    // Most requests answered at the same time so far, counted until the headers are sent
+    pub fn max_in_flight(&self) -> usize {
+        self.routes.lock().unwrap().max_in_flight
+    }
+
+    // Requests dropped by their client before the response headers were sent
+    pub fn cancelled(&self) -> usize {
+        self.routes.lock().unwrap().cancelled
+    }
+}
+
+async fn answer(routes: Arc<Mutex<Routes>>, request: Request) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY_BYTES).await.unwrap_or_default();
+    let request_headers = parts.headers.clone();
+    let response = {
+        let mut routes = routes.lock().unwrap();
+        routes.in_flight += 1;
+        routes.max_in_flight = routes.max_in_flight.max(routes.in_flight);
+        routes.requests.push(RecordedRequest {
+            method: parts.method.clone(),
+            uri: parts.uri.to_string(),
+            headers: parts.headers,
+            body,
+        });
+        routes.responses.get_mut(&(parts.method, parts.uri.path().to_string())).map(|responses| match responses.len() {
+            1 => responses[0].clone(),
+            _ => responses.remove(0),
+        })
+    };
+    let mut answering = Answering { routes, answered: false };
+    let Some(response) = response else {
+        answering.answered = true;
+        return Response::builder().status(404).body(Body::from("no mock for this route")).unwrap();
+    };
+
+    tokio::time::sleep(response.delay).await;
+    answering.answered = true;
+    drop(answering);
+    if response.not_modified(&request_headers) {
+        let mut builder = Response::builder().status(304);
+        for name in ["etag", "last-modified"] {
+            if let Some(value) = response.header(name) {
+                builder = builder.header(name, value);
+            }
+        }
+        return builder.body(Body::empty()).unwrap();
+    }
+    let mut builder = Response::builder().status(response.status);
+    for (name, value) in &response.headers {
+        builder = builder.header(name, value);
+    }
+    let body = match (response.abort, response.streamed) {
+        (true, _) => Body::from_stream(futures::stream::once(async {
+            Err::<Bytes, _>(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "aborted by the mock"))
+        })),
+        (false, true) => {
+            let chunks: Vec<Bytes> = response.body.chunks(16 * 1024).map(Bytes::copy_from_slice).collect();
+            Body::from_stream(futures::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>)))
+        }
+        (false, false) => Body::from(response.body),
+    };
+    builder.body(body).unwrap()
+}
+
+// A port nothing listens on, for a server process to bind
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// The server binary in a process of its own, logging JSON lines that tests can
+// wait for
+pub struct Server {
+    child: Child,
+    logs: Arc<Mutex<Vec<Value>>>,
+    // How many of the logs `logged` went past
+    seen: usize,
+    // Done once the process closed its output
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    // Started with `env` on top of this process's environment, which has to set
+    // LISTEN. Returns once all of its listeners are up.
+    pub async fn start(env: &[(&str, &str)]) -> Self {
+        let (_, listen) = env.iter().find(|(name, _)| *name == "LISTEN").expect("LISTEN is set");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_js-execution-service"))
+            .envs(env.iter().copied())
+            .env("LOG_FORMAT", "json")
+            .env("RUST_LOG", "info")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let logs = Arc::default();
+        let readers = vec![
+            collect_lines(child.stdout.take().unwrap(), &logs),
+            collect_lines(child.stderr.take().unwrap(), &logs),
+        ];
+        let mut server = Server { child, logs, seen: 0, readers };
+        for _ in listen.split(',') {
+            server.logged("Server listening on").await;
+        }
+        server
+    }
+
+    // The first line logged since the last call whose message starts with `message`
+    pub async fn logged(&mut self, message: &str) -> Value {
+        for _ in 0..500 {
+            let exited = self.readers.iter().all(|reader| reader.is_finished());
+            {
+                let logs = self.logs.lock().unwrap();
+                let found = logs[self.seen..].iter().position(|line| line["message"].as_str().is_some_and(|m| m.starts_with(message)));
+                if let Some(index) = found {
+                    self.seen += index + 1;
+                    return logs[self.seen - 1].clone();
+                }
+                if exited {
+                    panic!("the server exited before logging {:?}: {:?}", message, logs);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the server didn't log {:?}: {:?}", message, self.logs.lock().unwrap())
+    }
+
+    // Every line logged so far
+    pub fn lines(&self) -> Vec<Value> {
+        self.logs.lock().unwrap().clone()
+    }
+
+    pub fn terminate(&self) {
+        self.signal(libc::SIGTERM);
+    }
+
+    pub fn signal(&self, signal: libc::c_int) {
+        let pid = self.child.id().expect("the server is running");
+        // Only signals the child this test spawned
+        assert_eq!(unsafe { libc::kill(pid as i32, signal) }, 0);
+    }
+
+    pub async fn exited(&mut self) -> ExitStatus {
+        tokio::time::timeout(Duration::from_secs(15), self.child.wait()).await.expect("the server exits").unwrap()
+    }
+}
+
+// Lines that aren't JSON, such as a panic message, are kept as `message`
+fn collect_lines(output: impl AsyncRead + Unpin + Send + 'static, logs: &Arc<Mutex<Vec<Value>>>) -> JoinHandle<()> {
+    let logs = logs.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = serde_json::from_str(&line).unwrap_or_else(|_| serde_json::json!({ "message": line }));
+            logs.lock().unwrap().push(line);
+        }
+    })
+}
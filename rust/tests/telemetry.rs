@@ -0,0 +1,89 @@
+// Trace spans: request > execution > fetch, continuing the caller's `traceparent`
+// and passing each fetch span's context on to the upstream.
+//
+// The spans go to an in-memory exporter instead of OTLP, through a layer set up
+// like the server's: INFO spans only, with the W3C propagator.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use opentelemetry::global;
+use opentelemetry::trace::{SpanId, TraceId, TracerProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use serde_json::json;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use support::{MockResponse, TestApp};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const CALLER_SPAN_ID: &str = "00f067aa0ba902b7";
+
+fn attribute(span: &SpanData, key: &str) -> Option<String> {
+    span.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.as_str().into_owned())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_the_spans_of_an_execution_in_the_callers_trace() {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")).with_filter(LevelFilter::INFO))
+        .init();
+
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/a", MockResponse::json(200, json!(1)));
+    app.upstream.mock("GET", "/b", MockResponse::json(200, json!(2)));
+    let code = "(await httpRequest(INPUTS.a)).data + (await httpRequest(INPUTS.b)).data";
+    let request = Request::post("/execute")
+        .header("content-type", "application/json")
+        .header("traceparent", format!("00-{}-{}-01", TRACE_ID, CALLER_SPAN_ID))
+        .body(Body::from(
+            json!({ "code": code, "inputs": { "a": app.upstream.url("/a"), "b": app.upstream.url("/b") } }).to_string(),
+        ))
+        .unwrap();
+    let (status, body) = app.send(request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], json!(3));
+    provider.force_flush().unwrap();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let named = |name: &str| spans.iter().filter(|span| span.name == name).collect::<Vec<_>>();
+    let (requests, executions, fetches) = (named("request"), named("execution"), named("fetch"));
+    assert_eq!((requests.len(), executions.len(), fetches.len()), (1, 1, 2), "{:?}", spans);
+    let trace_id = TraceId::from_hex(TRACE_ID).unwrap();
+    assert!(spans.iter().all(|span| span.span_context.trace_id() == trace_id), "{:?}", spans);
+
+    // The caller's span is the parent of the request span
+    let (request, execution) = (requests[0], executions[0]);
+    assert_eq!(request.parent_span_id, SpanId::from_hex(CALLER_SPAN_ID).unwrap());
+    assert_eq!(attribute(request, "route").as_deref(), Some("/execute"));
+    assert_eq!(execution.parent_span_id, request.span_context.span_id());
+    assert_eq!(attribute(execution, "http_request_count").as_deref(), Some("2"));
+    for fetch in &fetches {
+        assert_eq!(fetch.parent_span_id, execution.span_context.span_id());
+        assert_eq!(attribute(fetch, "method").as_deref(), Some("GET"));
+        assert_eq!(attribute(fetch, "status").as_deref(), Some("200"));
+    }
+
+    // Each upstream request carries the context of its own fetch span
+    let mut sent: Vec<String> = app
+        .upstream
+        .requests()
+        .iter()
+        .map(|request| request.headers["traceparent"].to_str().unwrap().to_string())
+        .collect();
+    let mut expected: Vec<String> = fetches
+        .iter()
+        .map(|fetch| format!("00-{}-{}-01", TRACE_ID, fetch.span_context.span_id()))
+        .collect();
+    sent.sort();
+    expected.sort();
+    assert_eq!(sent, expected);
+}
@@ -0,0 +1,151 @@
+// Tenants from the config file: limits of their own, usage counters, and state and
+// caches kept apart.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use sandbox_core::config::TenantConfig;
+use support::{MockResponse, TestApp};
+
+async fn with_tenants(tenants: impl IntoIterator<Item = (&'static str, TenantConfig)>) -> TestApp {
+    let tenants: BTreeMap<String, TenantConfig> = tenants.into_iter().map(|(id, tenant)| (id.to_string(), tenant)).collect();
+    TestApp::with_config(|config| config.tenants = tenants).await
+}
+
+fn keys(key: &str) -> TenantConfig {
+    TenantConfig {
+        api_keys: vec![key.to_string()],
+        ..Default::default()
+    }
+}
+
+// An /execute request with the header, e.g. ("x-api-key", "key-a")
+fn execute_as(header: (&str, &str), body: Value) -> Request {
+    Request::post("/execute")
+        .header("content-type", "application/json")
+        .header(header.0, header.1)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn usage_of(tenant: &str, header: (&str, &str)) -> Request {
+    Request::get(format!("/tenants/{}/usage", tenant)).header(header.0, header.1).body(Body::empty()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn one_tenants_quota_and_rate_limit_leave_the_others_alone() {
+    let app = with_tenants([
+        ("a", TenantConfig { monthly_executions: 2, ..keys("key-a") }),
+        ("b", keys("key-b")),
+        ("c", TenantConfig { rate_limit_per_second: 0.001, rate_limit_burst: 1.0, ..Default::default() }),
+        ("d", TenantConfig::default()),
+    ])
+    .await;
+    let one = json!({ "code": "1" });
+    for _ in 0..2 {
+        assert_eq!(app.send(execute_as(("x-api-key", "key-a"), one.clone())).await.0, StatusCode::OK);
+    }
+    let (status, body) = app.send(execute_as(("x-api-key", "key-a"), one.clone())).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+    assert_eq!(body["error"], "Quota exceeded");
+    assert!(body["message"].as_str().unwrap().contains("monthly_executions of 2"), "{}", body);
+    for _ in 0..3 {
+        assert_eq!(app.send(execute_as(("x-api-key", "key-b"), one.clone())).await.0, StatusCode::OK);
+    }
+
+    // Tenants without API keys are named by X-Tenant
+    assert_eq!(app.send(execute_as(("x-tenant", "c"), one.clone())).await.0, StatusCode::OK);
+    let (status, body) = app.send(execute_as(("x-tenant", "c"), one.clone())).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+    assert_eq!(body["error"], "Rate limit exceeded");
+    for _ in 0..3 {
+        assert_eq!(app.send(execute_as(("x-tenant", "d"), one.clone())).await.0, StatusCode::OK);
+    }
+
+    let (status, body) = app.send(execute_as(("x-tenant", "nobody"), one.clone())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Unknown tenant");
+    // Requests of no tenant only get the server's limits
+    assert_eq!(app.post("/execute", one).await.0, StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_usage_across_requests() {
+    let app = with_tenants([("a", keys("key-a")), ("b", keys("key-b"))]).await;
+    app.upstream.mock("GET", "/data", MockResponse::text(200, "0123456789"));
+    let code = format!("(await httpRequest('{}')).text.length", app.upstream.url("/data"));
+    for _ in 0..3 {
+        let (status, body) = app.send(execute_as(("x-api-key", "key-a"), json!({ "code": code }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    // A batch counts each of its jobs
+    let batch = json!({ "jobs": [{ "id": 1, "code": "1" }, { "id": 2, "code": "2" }] });
+    let request = Request::post("/execute/batch")
+        .header("content-type", "application/json")
+        .header("x-api-key", "key-a")
+        .body(Body::from(batch.to_string()))
+        .unwrap();
+    assert_eq!(app.send(request).await.0, StatusCode::OK);
+
+    let (status, usage) = app.send(usage_of("a", ("x-api-key", "key-a"))).await;
+    assert_eq!(status, StatusCode::OK, "{}", usage);
+    assert_eq!(usage["tenant"], "a");
+    assert_eq!(usage["executions"], 5);
+    assert_eq!(usage["fetchedBytes"], 30);
+    assert_eq!(usage["inFlight"], 0);
+    assert_eq!(usage["limits"]["monthlyExecutions"], Value::Null);
+    assert_eq!(usage["period"].as_str().unwrap().len(), "2026-10".len());
+
+    let (_, usage) = app.send(usage_of("b", ("x-api-key", "key-b"))).await;
+    assert_eq!(usage["executions"], 0);
+    // Only a tenant's own usage is visible
+    let (status, _) = app.send(usage_of("a", ("x-api-key", "key-b"))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, metrics) = app.get("/metrics").await;
+    let metrics = metrics.as_str().unwrap();
+    assert!(metrics.contains("jsexec_tenant_executions_total{tenant=\"a\"} 5"), "{}", metrics);
+    assert!(metrics.contains("jsexec_tenant_executions_total{tenant=\"b\"} 0"), "{}", metrics);
+    assert!(metrics.contains("jsexec_tenant_fetched_bytes_total{tenant=\"a\"} 30"), "{}", metrics);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_state_and_cached_results_apart() {
+    let app = with_tenants([("a", TenantConfig::default()), ("b", TenantConfig::default())]).await;
+    let counter = json!({
+        "code": "const count = (state.get('count') ?? 0) + 1; state.set('count', count); count",
+        "state_namespace": "shared",
+    });
+    assert_eq!(app.send(execute_as(("x-tenant", "a"), counter.clone())).await.1["result"], 1);
+    assert_eq!(app.send(execute_as(("x-tenant", "a"), counter.clone())).await.1["result"], 2);
+    assert_eq!(app.send(execute_as(("x-tenant", "b"), counter.clone())).await.1["result"], 1);
+    assert_eq!(app.post("/execute", counter).await.1["result"], 1);
+
+    let cached = json!({ "code": "Math.random()", "cache": { "ttlSeconds": 60 }, "include_meta": true });
+    let (_, first) = app.send(execute_as(("x-tenant", "a"), cached.clone())).await;
+    let (_, again) = app.send(execute_as(("x-tenant", "a"), cached.clone())).await;
+    assert_eq!(again["meta"]["cacheHit"], true, "{}", again);
+    assert_eq!(again["result"], first["result"]);
+    let (_, other) = app.send(execute_as(("x-tenant", "b"), cached)).await;
+    assert_eq!(other["meta"]["cacheHit"], false, "{}", other);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applies_a_tenants_fetch_allowlist() {
+    let app = with_tenants([
+        ("a", TenantConfig { fetch_allowlist: vec!["https://api.example.com/".to_string()], ..Default::default() }),
+        ("b", TenantConfig::default()),
+    ])
+    .await;
+    app.upstream.mock("GET", "/data", MockResponse::text(200, "ok"));
+    let code = format!("const r = await httpRequest('{}'); [r.status, r.errorCode ?? null]", app.upstream.url("/data"));
+    let (_, body) = app.send(execute_as(("x-tenant", "a"), json!({ "code": code }))).await;
+    assert_eq!(body["result"], json!([0, "blocked_by_policy"]), "{}", body);
+    let (_, body) = app.send(execute_as(("x-tenant", "b"), json!({ "code": code }))).await;
+    assert_eq!(body["result"], json!([200, null]), "{}", body);
+}
@@ -0,0 +1,85 @@
+// The per-request timeout_ms and its ceiling.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+// Keeps the script busy for about `ms` milliseconds
+fn busy(ms: u64) -> String {
+    format!("const end = Date.now() + {}; while (Date.now() < end) {{}} 'done'", ms)
+}
+
+async fn app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.execution_timeout_ms = 300;
+        config.max_exec_timeout_ms = 2000;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_timeout_in_meta() {
+    let app = app().await;
+    let (status, body) = app.post("/execute", json!({ "code": "1", "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["timeoutMs"], 300);
+
+    let (status, body) = app.post("/execute", json!({ "code": "1", "timeout_ms": 100, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["timeoutMs"], 100);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn extends_the_default_up_to_the_ceiling() {
+    let app = app().await;
+    let result = app.result_of(json!({ "code": busy(600), "timeout_ms": 1500 })).await;
+    assert_eq!(result, "done");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_the_code_at_a_shorter_timeout() {
+    let app = app().await;
+    let (status, body) = app.post("/execute", json!({ "code": busy(5000), "timeout_ms": 100 })).await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(body["message"], "Execution exceeded the timeout of 100 ms");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_timeouts_out_of_range() {
+    let app = app().await;
+    for timeout_ms in [3000, 0, -5] {
+        let (status, body) = app.post("/execute", json!({ "code": "1", "timeout_ms": timeout_ms })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["error"], "Invalid timeout_ms");
+        assert_eq!(body["message"], format!("timeout_ms must be between 1 and 2000 ms, got {}", timeout_ms));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_limits_from_extending_the_timeout() {
+    let app = app().await;
+    let (status, body) = app
+        .post("/execute", json!({ "code": "1", "limits": { "timeout_ms": 1000 }, "include_meta": true }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["timeoutMs"], 300);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_the_code_at_a_shorter_timeout_in_limits() {
+    let app = app().await;
+    let (status, body) = app
+        .post("/execute", json!({ "code": busy(5000), "limits": { "timeout_ms": 100 }, "include_meta": true }))
+        .await;
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT, "{}", body);
+    assert_eq!(body["message"], "Execution exceeded the timeout of 100 ms");
+
+    // The shorter of timeout_ms and limits.timeout_ms applies
+    let request = json!({ "code": "1", "timeout_ms": 1500, "limits": { "timeout_ms": 200 }, "include_meta": true });
+    let (status, body) = app.post("/execute", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["meta"]["timeoutMs"], 200);
+}
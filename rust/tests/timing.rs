@@ -0,0 +1,65 @@
+// How long httpRequest calls took, per result and per host in the metadata.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+use support::{MockResponse, TestApp};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_each_request() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(300)));
+    let code = format!("const r = await httpRequest('{}'); r.timing", app.upstream.url("/slow"));
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let timing = &body["result"];
+    assert!(timing["totalMs"].as_u64().unwrap() >= 300, "{}", body);
+    assert!(timing["ttfbMs"].as_u64().unwrap() >= 300, "{}", body);
+    assert!(timing["ttfbMs"].as_u64() <= timing["totalMs"].as_u64(), "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_retried_requests_across_their_attempts() {
+    let app = TestApp::start().await;
+    let responses = vec![
+        MockResponse::text(503, "busy").with_delay(Duration::from_millis(200)),
+        MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(200)),
+    ];
+    app.upstream.mock_sequence("GET", "/flaky", responses);
+    let code = format!(
+        "const r = await httpRequest('{}', {{ retry: {{ attempts: 2, backoffMs: 10, retryOn: [503] }} }}); [r.status, r.attempts, r.timing.totalMs]",
+        app.upstream.url("/flaky")
+    );
+
+    let (status, body) = app.exec(&code, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"][0], 200);
+    assert_eq!(body["result"][1], 2);
+    assert!(body["result"][2].as_u64().unwrap() >= 400, "{}", body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sums_the_requests_to_each_host_in_the_metadata() {
+    let app = TestApp::with_config(|config| config.dns_overrides = vec!["other.test=127.0.0.1".to_string()]).await;
+    app.upstream.mock("GET", "/slow", MockResponse::json(200, json!(1)).with_delay(Duration::from_millis(150)));
+    app.upstream.mock("GET", "/fast", MockResponse::json(200, json!(1)));
+    let other = app.upstream.url("/fast").replace("127.0.0.1", "other.test");
+    let code = format!(
+        "await httpRequest('{0}'); await httpRequest('{0}'); await httpRequest('{1}'); 'done'",
+        app.upstream.url("/slow"),
+        other
+    );
+
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let hosts = &body["meta"]["fetchByHost"];
+    assert_eq!(hosts.as_object().unwrap().len(), 2, "{}", body);
+    assert_eq!(hosts["127.0.0.1"]["requests"], 2);
+    assert_eq!(hosts["127.0.0.1"]["attempts"], 2);
+    assert!(hosts["127.0.0.1"]["totalMs"].as_u64().unwrap() >= 300, "{}", body);
+    assert_eq!(hosts["other.test"]["requests"], 1);
+}
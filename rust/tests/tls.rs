@@ -0,0 +1,98 @@
+// TLS_CERT_PATH and TLS_KEY_PATH: HTTPS on the TCP listeners of the server process.
+
+mod support;
+
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+use support::{fixture, free_port, Server};
+
+// Trusts the CA in tests/fixtures/tls, which issued the certificate for localhost
+fn client() -> reqwest::Client {
+    let ca = std::fs::read(fixture("tls/ca.pem")).unwrap();
+    reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap())
+        .build()
+        .unwrap()
+}
+
+async fn start(port: u16, cert: &Path, key: &Path) -> Server {
+    let listen = format!("tcp://127.0.0.1:{}", port);
+    let (cert, key) = (cert.display().to_string(), key.display().to_string());
+    Server::start(&[("LISTEN", &listen), ("TLS_CERT_PATH", &cert), ("TLS_KEY_PATH", &key)]).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_https_with_the_certificate() {
+    let port = free_port();
+    let mut server = start(port, &fixture("tls/cert.pem"), &fixture("tls/key.pem")).await;
+
+    let response = client()
+        .post(format!("https://localhost:{}/execute", port))
+        .json(&json!({ "code": "INPUTS.n + 1", "inputs": { "n": 1 } }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.json::<Value>().await.unwrap(), json!({ "result": 2 }));
+
+    // Only TLS is accepted, and only by clients that trust the issuer
+    assert!(reqwest::get(format!("http://localhost:{}/livez", port)).await.is_err());
+    let error = reqwest::get(format!("https://localhost:{}/livez", port)).await.unwrap_err();
+    assert!(format!("{:?}", error).to_lowercase().contains("certificate"), "{:?}", error);
+
+    server.terminate();
+    assert!(server.exited().await.success());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reloads_the_certificate_on_sighup() {
+    let dir = std::env::temp_dir().join(format!("sandbox-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (cert, key): (PathBuf, PathBuf) = (dir.join("cert.pem"), dir.join("key.pem"));
+    std::fs::copy(fixture("tls/cert.pem"), &cert).unwrap();
+    std::fs::copy(fixture("tls/key.pem"), &key).unwrap();
+    let port = free_port();
+    let mut server = start(port, &cert, &key).await;
+    let url = format!("https://localhost:{}/livez", port);
+    assert_eq!(client().get(&url).send().await.unwrap().status(), 200);
+
+    server.signal(libc::SIGHUP);
+    server.logged("Reloaded the TLS certificate").await;
+
+    // A broken certificate isn't taken, the one in use stays
+    std::fs::write(&cert, "not a certificate").unwrap();
+    server.signal(libc::SIGHUP);
+    let line = server.logged("Keeping the current TLS certificate").await;
+    assert_eq!(line["level"], "ERROR");
+    assert_eq!(client().get(&url).send().await.unwrap().status(), 200);
+
+    server.terminate();
+    assert!(server.exited().await.success());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_to_start_with_half_of_the_settings() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_js-execution-service"))
+        .env("LISTEN", format!("tcp://127.0.0.1:{}", free_port()))
+        .env("TLS_CERT_PATH", fixture("tls/cert.pem"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("TLS_CERT_PATH and TLS_KEY_PATH must be set together"), "{}", stderr);
+}
+
+#[test]
+fn refuses_to_start_with_a_key_of_another_certificate() {
+    // The CA's certificate with the leaf's key
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_js-execution-service"))
+        .env("LISTEN", format!("tcp://127.0.0.1:{}", free_port()))
+        .env("TLS_CERT_PATH", fixture("tls/ca.pem"))
+        .env("TLS_KEY_PATH", fixture("tls/key.pem"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("TLS_CERT_PATH and TLS_KEY_PATH don't form a usable pair"), "{}", stderr);
+}
@@ -0,0 +1,106 @@
+// TypeScript with `language: "typescript"`, stripped of its types before it runs.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+fn typescript(code: &str, inputs: Value) -> Value {
+    json!({ "code": code, "inputs": inputs, "language": "typescript" })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_interfaces_and_generics() {
+    let app = TestApp::start().await;
+    let code = "interface Item { name: string; price: number; tags?: string[] }
+        type Priced<T extends Item = Item> = T & { total: number };
+        function withTotal<T extends Item>(items: T[], quantity: number = 1): Priced<T>[] {
+            return items.map((item: T): Priced<T> => ({ ...item, total: item.price * quantity }));
+        }
+        const first = <T,>(values: readonly T[]): T | undefined => values[0];
+        const items = INPUTS.items as Item[];
+        first(withTotal<Item>(items, 3))!.total satisfies number";
+    let (status, body) = app.post("/execute", typescript(code, json!({ "items": [{ "name": "pen", "price": 2 }] }))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["result"], 6);
+
+    let code = "abstract class Shape {
+            abstract area(): number;
+            describe(this: Shape): string { return `${this.constructor.name}: ${this.area()}`; }
+        }
+        class Square extends Shape implements Iterable<number> {
+            private readonly side!: number;
+            static count: number = 0;
+            constructor(side: number) { super(); this.side = side; Square.count++; }
+            area(): number { return this.side ** 2; }
+            *[Symbol.iterator](): Iterator<number> { yield this.side; }
+        }
+        new Square(3).describe()";
+    let result = app.result_of(typescript(code, json!({}))).await;
+    assert_eq!(result, "Square: 9");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn turns_enums_into_objects() {
+    let app = TestApp::start().await;
+    let code = "const enum Level { Low = 1, Medium, High = Medium * 2 }
+        enum Status { Active = 'ACTIVE', Archived = 'ARCHIVED' }
+        ({ high: Level.High, name: Level[2], status: Status.Active, keys: Object.keys(Status) })";
+    let (status, body) = app.post("/execute", typescript(code, json!({}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!({ "high": 4, "name": "Medium", "status": "ACTIVE", "keys": ["Active", "Archived"] })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_an_async_function_with_typed_params() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/users/7", MockResponse::json(200, json!({ "name": "Ada" })));
+    let code = format!(
+        "type User = {{ name: string }};
+        async function load(id: number, base: string): Promise<User> {{
+            const response: Response = await fetch(`${{base}}/users/${{id}}`);
+            return (await response.json()) as User;
+        }}
+        export async function main(inputs: {{ id: number }}): Promise<string> {{
+            const user: User = await load(inputs.id, '{}');
+            return user.name.toUpperCase();
+        }}",
+        app.upstream.url("")
+    );
+    let request = json!({
+        "code": code, "inputs": { "id": 7 }, "language": "typescript", "module": true, "entrypoint": "main",
+    });
+    let result = app.result_of(request).await;
+    assert_eq!(result, "ADA");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_typescript_errors_at_their_position() {
+    let app = TestApp::start().await;
+    let (status, body) = app.post("/execute", typescript("const total = 1;\nlet price: = 5;", json!({}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"], "SyntaxError");
+    assert_eq!(body["message"], "TypeScript error: Type expected");
+    assert_eq!(body["jsError"]["line"], 2);
+    assert_eq!(body["jsError"]["column"], 12);
+
+    let (status, body) = app.post("/execute", typescript("namespace Tools {\n  export const x = 1;\n}", json!({}))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert!(body["message"].as_str().unwrap().contains("Namespaces aren't supported"), "{}", body);
+
+    // Lines are kept, so errors of the JavaScript left point at the TypeScript
+    let code = "interface Shape {\n  sides: number;\n}\nconst shape: Shape = { sides: 3 };\n\
+        throw new Error(`${shape.sides}`);";
+    let (status, body) = app.post("/execute", typescript(code, json!({}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["jsError"]["line"], 5);
+
+    // Plain JavaScript is the default, where annotations don't parse
+    let (status, body) = app.exec("let price: number = 5; price", json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+}
@@ -0,0 +1,143 @@
+// URL and URLSearchParams.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+async fn run(code: &str) -> Value {
+    TestApp::start().await.result(code, json!({})).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolves_relative_urls_against_a_base() {
+    let result = run(
+        "const base = 'https://api.example.com/v1/users/7?full=1';
+        const url = new URL('../orders?page=2#top', base);
+        [url.href, url.origin, url.pathname, url.search, url.hash, url.searchParams.get('page'),
+         new URL('/health', base).href, new URL('//cdn.example.com/a.js', base).href, new URL('https://other.test:8443', base).port]",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            "https://api.example.com/v1/orders?page=2#top",
+            "https://api.example.com",
+            "/v1/orders",
+            "?page=2",
+            "#top",
+            "2",
+            "https://api.example.com/health",
+            "https://cdn.example.com/a.js",
+            "8443",
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn encodes_unicode_hosts_and_paths() {
+    let result = run(
+        "const url = new URL('https://münchen.de/straße/café?q=süß');
+        url.searchParams.append('name', 'Zoë & co');
+        [url.hostname, url.pathname, url.href, url.searchParams.get('q')]",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            "xn--mnchen-3ya.de",
+            "/stra%C3%9Fe/caf%C3%A9",
+            "https://xn--mnchen-3ya.de/stra%C3%9Fe/caf%C3%A9?q=s%C3%BC%C3%9F&name=Zo%C3%AB+%26+co",
+            "süß",
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_trips_query_strings_with_repeated_keys() {
+    let result = run(
+        "const params = new URLSearchParams('?tag=a&tag=b+c&x=%2F&empty=');
+        const before = [params.getAll('tag'), params.get('x'), params.get('empty'), params.get('missing'), params.toString()];
+        params.set('tag', 'z');
+        params.delete('x');
+        params.append('tag', 'y');
+        const fromPairs = new URLSearchParams([['k', '1'], ['k', '2']]).toString();
+        const fromObject = new URLSearchParams({ a: 1, b: 'two words' }).toString();
+        [before, params.toString(), [...params.keys()], fromPairs, fromObject]",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            [["a", "b c"], "/", "", null, "tag=a&tag=b+c&x=%2F&empty="],
+            "tag=z&empty=&tag=y",
+            ["tag", "empty", "tag"],
+            "k=1&k=2",
+            "a=1&b=two+words",
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_search_params_and_the_url_in_step() {
+    let result = run(
+        "const url = new URL('https://example.com/search?q=1');
+        const params = url.searchParams;
+        params.set('q', 'rust lang');
+        const afterSet = url.href;
+        url.search = '?page=3';
+        url.hash = 'results';
+        [afterSet, params.get('q'), params.get('page'), url.href, JSON.stringify({ url })]",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            "https://example.com/search?q=rust+lang",
+            null,
+            "3",
+            "https://example.com/search?page=3#results",
+            "{\"url\":\"https://example.com/search?page=3#results\"}",
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_a_type_error_for_invalid_urls() {
+    let result = run(
+        "const errors = [];
+        for (const [input, base] of [['/relative'], ['http://[::1'], ['x', 'not a base']]) {
+            try { new URL(input, base); } catch (e) { errors.push([e.name, e.message]); }
+        }
+        [errors, URL.canParse('/relative'), URL.canParse('/relative', 'https://example.com')]",
+    )
+    .await;
+    assert_eq!(
+        result,
+        json!([
+            [
+                ["TypeError", "Invalid URL: '/relative'"],
+                ["TypeError", "Invalid URL: 'http://[::1'"],
+                ["TypeError", "Invalid URL: 'not a base'"],
+            ],
+            false,
+            true,
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fetches_a_url_instance() {
+    let app = TestApp::start().await;
+    app.upstream.mock("GET", "/items", MockResponse::json(200, json!(["a"])));
+    let code = format!(
+        "const url = new URL('/items', '{}');
+        url.searchParams.append('tag', 'x y');
+        (await httpRequest(url)).data",
+        app.upstream.url("")
+    );
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["a"]));
+    assert_eq!(app.upstream.requests()[0].uri, "/items?tag=x+y");
+}
@@ -0,0 +1,70 @@
+// Resource usage of executions in meta.usage, and the histograms it feeds.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+async fn usage(app: &TestApp, code: &str) -> Value {
+    let (status, body) = app.post("/execute", json!({ "code": code, "include_meta": true })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["meta"]["usage"].clone()
+}
+
+// The sum of a histogram in the /metrics text
+async fn histogram_sum(app: &TestApp, name: &str) -> f64 {
+    let (_, metrics) = app.get("/metrics").await;
+    let line = format!("{}_sum ", name);
+    metrics
+        .as_str()
+        .unwrap()
+        .lines()
+        .find_map(|l| l.strip_prefix(line.as_str()))
+        .unwrap_or_else(|| panic!("no {} in {}", line, metrics))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_more_heap_for_code_that_allocates() {
+    let app = TestApp::start().await;
+    let trivial = usage(&app, "1 + 1").await;
+    let heavy = usage(
+        &app,
+        "const items = [];
+        for (let i = 0; i < 200000; i++) items.push({ i, label: 'item ' + i });
+        items.length",
+    )
+    .await;
+
+    let trivial_peak = trivial["heapPeakBytes"].as_u64().unwrap();
+    let heavy_peak = heavy["heapPeakBytes"].as_u64().unwrap();
+    assert!(trivial_peak > 0, "{}", trivial);
+    assert!(heavy_peak > trivial_peak + 5_000_000, "{} vs {}", heavy, trivial);
+    // The loop ran long enough to pass checkpoints
+    assert!(heavy["interrupts"].as_u64().unwrap() > 0, "{}", heavy);
+    assert_eq!(heavy["resultBytes"], json!(6));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts_the_bytes_fetched_from_upstreams() {
+    let app = TestApp::start().await;
+    let body = "x".repeat(5000);
+    app.upstream.mock("GET", "/data", MockResponse::text(200, &body));
+    let code = format!(
+        "const url = '{}';
+        const [first, second] = await Promise.all([httpRequest(url), fetch(url).then((r) => r.text())]);
+        first.text.length + second.length",
+        app.upstream.url("/data")
+    );
+
+    let fetched_before = histogram_sum(&app, "jsexec_execution_fetched_bytes").await;
+    let usage = usage(&app, &code).await;
+    assert_eq!(usage["fetchedBytes"], json!(10_000), "{}", usage);
+    assert_eq!(usage["resultBytes"], json!(5));
+    // The histograms get the same figures
+    let fetched_after = histogram_sum(&app, "jsexec_execution_fetched_bytes").await;
+    assert!(fetched_after - fetched_before >= 10_000.0, "{} -> {}", fetched_before, fetched_after);
+}
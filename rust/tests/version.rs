@@ -0,0 +1,44 @@
+// Build metadata on GET /version and in the X-Sandbox-Version header.
+
+mod support;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use serde_json::json;
+
+use support::TestApp;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_the_build() {
+    let (status, body) = TestApp::start().await.get("/version").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    assert_eq!(body["version"], json!(env!("CARGO_PKG_VERSION")));
+    for field in ["gitCommit", "buildTimestamp", "rustcVersion", "rquickjsVersion", "quickjsVersion", "full"] {
+        assert!(!body[field].as_str().unwrap().is_empty(), "{} is empty in {}", field, body);
+    }
+    assert!(body["gitDirty"].is_boolean());
+    assert!(body["rustcVersion"].as_str().unwrap().starts_with("rustc "), "{}", body);
+    assert_eq!(body["rquickjsVersion"], json!("0.10.0"));
+    assert!(body["full"].as_str().unwrap().starts_with(env!("CARGO_PKG_VERSION")));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn names_the_version_on_every_response() {
+    let app = TestApp::start().await;
+    let (_, version) = app.get("/version").await;
+
+    let execute = Request::post("/execute")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "code": "1 + 1" }).to_string()))
+        .unwrap();
+    let response = app.response(execute).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-sandbox-version"], version["full"].as_str().unwrap());
+
+    // Errors and unknown routes too
+    let missing = app.response(Request::get("/nowhere").body(Body::empty()).unwrap()).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    assert!(missing.headers().contains_key("x-sandbox-version"));
+}
@@ -0,0 +1,31 @@
+// Latency of a trivial execution before and after the startup warm-up. The prelude
+// bytecode is shared by the whole process, so this binary holds one test only.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use support::TestApp;
+
+async fn timed_exec(app: &TestApp) -> Duration {
+    let started = Instant::now();
+    let (status, body) = app.exec("1 + 1", json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    started.elapsed()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warmed_up_executions_beat_the_cold_path() {
+    let cold_app = TestApp::with_config(|config| config.js_runtime_pool_size = 1).await;
+    let cold = timed_exec(&cold_app).await;
+
+    let app = TestApp::with_config(|config| config.js_runtime_pool_size = 1).await;
+    app.warm_up(Duration::from_secs(30)).await;
+    let mut warm = Duration::MAX;
+    for _ in 0..5 {
+        warm = warm.min(timed_exec(&app).await);
+    }
+    assert!(warm < cold, "warm {:?}, cold {:?}", warm, cold);
+}
@@ -0,0 +1,106 @@
+// parseXml(): XML documents as plain objects.
+
+mod support;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use support::{MockResponse, TestApp};
+
+async fn parse(xml: &str, options: Value) -> Value {
+    let (status, body) = TestApp::start()
+        .await
+        .exec("parseXml(INPUTS.xml, INPUTS.options)", json!({ "xml": xml, "options": options }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["result"].clone()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn converts_elements_attributes_and_text() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <!DOCTYPE catalog>
+        <catalog updated="2024-05-01">
+            <!-- two books -->
+            <book id="1" lang="en"><title>Dune</title><price currency="EUR">9.90</price></book>
+            <book id="2"><title>Emma &amp; Co</title><price>4.50</price></book>
+            <note>Prices &lt;incl. VAT&gt;</note>
+            <empty/>
+        </catalog>"#;
+    let result = parse(xml, json!(null)).await;
+    assert_eq!(
+        result,
+        json!({
+            "catalog": {
+                "@attr": { "updated": "2024-05-01" },
+                "book": [
+                    { "@attr": { "id": "1", "lang": "en" }, "title": "Dune", "price": { "@attr": { "currency": "EUR" }, "#text": "9.90" } },
+                    { "@attr": { "id": "2" }, "title": "Emma & Co", "price": "4.50" },
+                ],
+                "note": "Prices <incl. VAT>",
+                "empty": "",
+            }
+        })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_cdata_as_text() {
+    let xml = "<script lang=\"js\"><![CDATA[if (a < b && c) { run(); }]]></script>";
+    let result = parse(xml, json!({ "attributeKey": "_attrs", "textKey": "_text" })).await;
+    assert_eq!(result, json!({ "script": { "_attrs": { "lang": "js" }, "_text": "if (a < b && c) { run(); }" } }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn preserves_or_strips_namespace_prefixes() {
+    let xml = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:m="urn:orders">
+        <soap:Body><m:GetOrderResponse m:version="2"><m:Id>7</m:Id></m:GetOrderResponse></soap:Body>
+    </soap:Envelope>"#;
+    let preserved = parse(xml, json!({})).await;
+    assert_eq!(preserved["soap:Envelope"]["@attr"]["xmlns:m"], "urn:orders");
+    assert_eq!(preserved["soap:Envelope"]["soap:Body"]["m:GetOrderResponse"]["m:Id"], "7");
+
+    let stripped = parse(xml, json!({ "namespaces": "strip" })).await;
+    assert_eq!(
+        stripped,
+        json!({ "Envelope": { "Body": { "GetOrderResponse": { "@attr": { "version": "2" }, "Id": "7" } } } })
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parses_fetched_xml() {
+    let app = TestApp::start().await;
+    app.upstream.mock(
+        "GET",
+        "/feed",
+        MockResponse::text(200, "<rss><channel><item><title>One</title></item><item><title>Two</title></item></channel></rss>")
+            .with_header("content-type", "application/rss+xml"),
+    );
+    let code = format!(
+        "const feed = parseXml((await httpRequest('{}')).text); feed.rss.channel.item.map(item => item.title)",
+        app.upstream.url("/feed")
+    );
+    let result = app.result(&code, json!({})).await;
+    assert_eq!(result, json!(["One", "Two"]));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throws_a_catchable_error_with_the_position() {
+    let (status, body) = TestApp::start()
+        .await
+        .exec(
+            "INPUTS.documents.map(xml => { try { parseXml(xml); } catch (e) { return [e.name, e.message]; } })",
+            json!({ "documents": ["<a>\n  <b>text</c>\n</a>", "<a x=\"1\" x=\"2\"/>", "<a>&nbsp;</a>", "<a>"] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["result"],
+        json!([
+            ["SyntaxError", "Invalid XML at line 2, column 10: expected '</b>', found '</c>'"],
+            ["SyntaxError", "Invalid XML at line 1, column 10: duplicate attribute 'x'"],
+            ["SyntaxError", "Invalid XML at line 1, column 4: unknown entity '&nbsp;'"],
+            ["SyntaxError", "Invalid XML at line 1, column 4: expected '</a>'"],
+        ])
+    );
+}
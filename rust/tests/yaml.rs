@@ -0,0 +1,194 @@
+// parseYaml(), parseYamlAll() and toYaml(): YAML documents as plain values and back.
+
+mod support;
+
+use serde_json::{json, Value};
+
+use support::TestApp;
+
+async fn run(code: &str, yaml: &str) -> Value {
+    TestApp::start().await.result(code, json!({ "yaml": yaml })).await
+}
+
+// `[name, message, line, column]` of what the code threw
+async fn thrown(code: &str, yaml: &str) -> Value {
+    let code = format!("try {{ {}; null }} catch (e) {{ [e.name, e.message, e.line, e.column] }}", code);
+    run(&code, yaml).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parses_nested_mappings_and_scalars() {
+    let yaml = r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: web
+  labels: { app: web, tier: "frontend" }
+spec:
+  replicas: 3
+  paused: false
+  ratio: 0.5
+  mask: 0o755
+  color: 0xFF
+  limit: .inf
+  empty:
+  tilde: ~
+  version: "1.10"
+  zip: 01234
+  script: |
+    echo one
+    echo two
+  containers:
+    - name: app
+      image: nginx:1.25
+      ports: [80, 443]
+"#;
+    let result = run("parseYaml(INPUTS.yaml)", yaml).await;
+    assert_eq!(
+        result,
+        json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "web", "labels": { "app": "web", "tier": "frontend" } },
+            "spec": {
+                "replicas": 3, "paused": false, "ratio": 0.5, "mask": 493, "color": 255, "limit": null,
+                "empty": null, "tilde": null, "version": "1.10", "zip": 1234,
+                "script": "echo one\necho two\n",
+                "containers": [{ "name": "app", "image": "nginx:1.25", "ports": [80, 443] }],
+            },
+        })
+    );
+
+    // Document order is kept, infinities are numbers, and other tags give the text
+    let code = r#"const doc = parseYaml(INPUTS.yaml);
+        [Object.keys(doc), doc.inf === Infinity, doc.when, doc.ref, doc.n, doc.s, doc.keys]"#;
+    let yaml = "z: 1\na: 2\ninf: .inf\nwhen: !!timestamp 2024-05-01\nref: !Ref MyBucket\nn: !!int '12'\ns: !!str 12\nkeys: { 1: one, true: yes, null: nothing }\n";
+    assert_eq!(
+        run(code, yaml).await,
+        json!([
+            ["z", "a", "inf", "when", "ref", "n", "s", "keys"],
+            true,
+            "2024-05-01",
+            "MyBucket",
+            12,
+            "12",
+            { "1": "one", "true": "yes", "null": "nothing" },
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolves_anchors_aliases_and_merge_keys() {
+    let yaml = r#"
+defaults: &defaults
+  image: node:20
+  retry: 2
+  tags: [linux]
+build:
+  <<: *defaults
+  script: npm run build
+  retry: 0
+deploy:
+  <<: [*defaults, { when: manual, retry: 5 }]
+  tags: *a
+same: &a [x]
+"#;
+    // An alias before its anchor is an error
+    let error = thrown("parseYaml(INPUTS.yaml)", yaml).await;
+    assert!(error[1].as_str().unwrap().contains("unknown anchor"), "{}", error);
+    let yaml = yaml.replace("  tags: *a\nsame: &a [x]\n", "  tags: [x]\n");
+    let code = r#"const doc = parseYaml(INPUTS.yaml);
+        doc.build.tags.push("changed");
+        [doc.build, doc.deploy, doc.defaults.tags]"#;
+    assert_eq!(
+        run(code, &yaml).await,
+        json!([
+            { "script": "npm run build", "retry": 0, "image": "node:20", "tags": ["linux", "changed"] },
+            { "tags": ["x"], "image": "node:20", "retry": 2, "when": "manual" },
+            // Aliases are copies
+            ["linux"],
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parses_multi_document_streams() {
+    let yaml = "kind: Service\n---\nkind: Deployment\n---\n# empty\n...\n--- [1, 2]\n";
+    assert_eq!(
+        run("parseYamlAll(INPUTS.yaml)", yaml).await,
+        json!([{ "kind": "Service" }, { "kind": "Deployment" }, null, [1, 2]])
+    );
+    assert_eq!(run("[parseYamlAll(INPUTS.yaml), parseYaml(INPUTS.yaml)]", "").await, json!([[], null]));
+
+    let error = thrown("parseYaml(INPUTS.yaml)", yaml).await;
+    assert_eq!(error[0], "SyntaxError");
+    assert!(error[1].as_str().unwrap().contains("parseYamlAll"), "{}", error);
+    assert_eq!(error[2], 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_alias_bombs() {
+    let yaml = r#"
+a: &a ["lol", "lol", "lol", "lol", "lol", "lol", "lol", "lol", "lol"]
+b: &b [*a, *a, *a, *a, *a, *a, *a, *a, *a]
+c: &c [*b, *b, *b, *b, *b, *b, *b, *b, *b]
+d: &d [*c, *c, *c, *c, *c, *c, *c, *c, *c]
+e: &e [*d, *d, *d, *d, *d, *d, *d, *d, *d]
+f: &f [*e, *e, *e, *e, *e, *e, *e, *e, *e]
+g: &g [*f, *f, *f, *f, *f, *f, *f, *f, *f]
+h: &h [*g, *g, *g, *g, *g, *g, *g, *g, *g]
+i: &i [*h, *h, *h, *h, *h, *h, *h, *h, *h]
+"#;
+    let error = thrown("parseYaml(INPUTS.yaml)", yaml).await;
+    assert_eq!(error[0], "RangeError");
+    assert!(error[1].as_str().unwrap().starts_with("YAML aliases expand to more than 1000000 nodes at line 8"), "{}", error);
+    assert_eq!(error[2], 8);
+
+    let deep: String = (0..300).map(|level| format!("{:level$}- \n", "", level = level)).collect();
+    let error = thrown("parseYaml(INPUTS.yaml)", &deep).await;
+    assert_eq!(error[0], "RangeError");
+    assert!(error[1].as_str().unwrap().contains("deeper than 256"), "{}", error);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_malformed_yaml_with_its_position() {
+    let error = thrown("parseYaml(INPUTS.yaml)", "name: web\nspec:\n  - replicas\n bad: 3\n").await;
+    assert_eq!(error[0], "SyntaxError");
+    assert!(error[1].as_str().unwrap().starts_with("Invalid YAML at line 4, column 5: "), "{}", error);
+    assert_eq!((&error[2], &error[3]), (&json!(4), &json!(5)));
+
+    let error = thrown("parseYaml(INPUTS.yaml)", "a: [1, 2\nb: 3\n").await;
+    assert_eq!(error[0], "SyntaxError");
+
+    let error = thrown("parseYaml(INPUTS.yaml)", "port: !!int eighty\n").await;
+    assert_eq!(error, json!(["SyntaxError", "Invalid YAML at line 1, column 13: \"eighty\" isn't a valid !!int", 1, 13]));
+
+    let error = thrown("toYaml(undefined)", "").await;
+    assert_eq!(error[0], "TypeError");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_trips_through_to_yaml() {
+    let code = r##"
+        const value = {
+            name: "web",
+            replicas: 3,
+            ratio: -0.25,
+            big: 1e300,
+            enabled: true,
+            nothing: null,
+            tricky: ["true", "123", "", " padded ", "a: b", "# not a comment", "- item", "~", "null", "0x1F",
+                "1e3", "multi\nline", "tab\there", "quote\"s", "*star", "é", "...", "---"],
+            nested: { list: [{ a: 1, b: [1, [2, 3]] }, [], {}], "key with: colon": "v", "123": "numeric key" },
+            when: new Date(Date.UTC(2024, 4, 1)),
+            skipped: undefined,
+        };
+        const text = toYaml(value);
+        [text, parseYaml(text), JSON.parse(JSON.stringify(value)), toYaml("plain"), toYaml([]), toYaml(42)]
+    "##;
+    let result = run(code, "").await;
+    assert_eq!(result[1], result[2], "{}", result[0].as_str().unwrap());
+    assert!(result[0].as_str().unwrap().starts_with("name: web\nreplicas: 3\nratio: -0.25\n"), "{}", result[0]);
+    assert!(result[0].as_str().unwrap().contains("  list:\n    - a: 1\n      b:\n        - 1\n        - - 2\n          - 3\n"), "{}", result[0]);
+    assert_eq!((&result[3], &result[4], &result[5]), (&json!("plain\n"), &json!("[]\n"), &json!("42\n")));
+}